@@ -1,5 +1,233 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
 fn main() {
     println!("cargo:rerun-if-changed=Cargo.toml");
+    println!("cargo:rerun-if-changed=Cargo.lock");
     println!("cargo:rerun-if-changed=patches/");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    // HEAD only names the branch, commits move the ref it points to. Refs can also be packed.
+    let head_ref = fs::read_to_string(".git/HEAD")
+        .ok()
+        .and_then(|head| Some(head.strip_prefix("ref:")?.trim().to_owned()));
+    for path in head_ref
+        .map(|head_ref| format!(".git/{}", head_ref))
+        .into_iter()
+        .chain([".git/packed-refs".to_owned()])
+    {
+        // Paths that don't exist would rerun the build script every time
+        if Path::new(&path).exists() {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
     cargo_patch::patch().expect("Failed while patching");
+
+    println!("cargo:rustc-env=CA3D_GIT_HASH={}", git_hash());
+    println!("cargo:rustc-env=CA3D_BUILD_DATE={}", build_date());
+
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    fs::write(out_dir.join("licenses.rs"), licenses_source()).expect("Failed to write licenses.rs");
+}
+
+fn git_hash() -> String {
+    Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|hash| hash.trim().to_owned())
+        .unwrap_or_else(|| "unknown".to_owned())
+}
+
+/// UTC date of the build, or of `SOURCE_DATE_EPOCH` for reproducible builds
+fn build_date() -> String {
+    let secs = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse::<i64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs() as i64)
+        });
+    // Days since the epoch to a civil date, after Howard Hinnant's days_from_civil inverse
+    let z = secs.div_euclid(86400) + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Name and version of every package in the lock file
+fn locked_packages(lock: &str) -> Vec<(String, String)> {
+    let mut packages = Vec::new();
+    let mut name = None;
+    for line in lock.lines() {
+        let value = |line: &str, key: &str| {
+            line.strip_prefix(key)
+                .map(|rest| rest.trim().trim_start_matches('=').trim().trim_matches('"'))
+                .map(str::to_owned)
+        };
+        if line.starts_with("[[package]]") {
+            name = None;
+        } else if let Some(n) = value(line, "name ") {
+            name = Some(n);
+        } else if let Some(version) = value(line, "version ") {
+            if let Some(name) = name.take() {
+                packages.push((name, version));
+            }
+        }
+    }
+    packages
+}
+
+/// Name and version of every package the target links, through normal dependencies only. Build
+/// and dev dependencies and other platforms' dependencies aren't distributed with the binary.
+fn runtime_packages(manifest_dir: &Path) -> Option<Vec<(String, String)>> {
+    let cargo = env::var_os("CARGO")?;
+    let target = env::var("TARGET").ok()?;
+    let output = Command::new(cargo)
+        .arg("tree")
+        .arg("--manifest-path")
+        .arg(manifest_dir.join("Cargo.toml"))
+        .args([
+            "--offline",
+            "--edges",
+            "normal",
+            "--prefix",
+            "none",
+            "--format",
+            "{p}",
+        ])
+        .args(["--target", &target])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    let mut packages = String::from_utf8(output.stdout)
+        .ok()?
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            let name = parts.next()?;
+            let version = parts.next()?.strip_prefix('v')?;
+            Some((name.to_owned(), version.to_owned()))
+        })
+        .collect::<Vec<_>>();
+    packages.sort();
+    packages.dedup();
+    Some(packages)
+}
+
+/// Unpacked crate sources in the cargo registry
+fn registry_dirs() -> Vec<PathBuf> {
+    let cargo_home = env::var_os("CARGO_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cargo")));
+    let Some(cargo_home) = cargo_home else {
+        return Vec::new();
+    };
+    fs::read_dir(cargo_home.join("registry").join("src"))
+        .map(|entries| entries.filter_map(|e| e.ok()).map(|e| e.path()).collect())
+        .unwrap_or_default()
+}
+
+/// The license expression from a crate's manifest and the text of its license files
+fn crate_license(dir: &Path) -> (String, String) {
+    let expression = fs::read_to_string(dir.join("Cargo.toml"))
+        .ok()
+        .and_then(|manifest| {
+            manifest.lines().find_map(|line| {
+                let rest = line.strip_prefix("license")?.trim_start();
+                let value = rest.strip_prefix('=')?.trim().trim_matches('"');
+                Some(value.to_owned())
+            })
+        })
+        .unwrap_or_default();
+
+    let mut files = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|e| e.ok())
+                .filter(|e| e.file_type().is_ok_and(|t| t.is_file()))
+                .filter_map(|e| e.file_name().into_string().ok())
+                .filter(|name| {
+                    let upper = name.to_uppercase();
+                    ["LICENSE", "LICENCE", "COPYING"]
+                        .iter()
+                        .any(|prefix| upper.starts_with(prefix))
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    files.sort();
+    let text = files
+        .iter()
+        .filter_map(|name| {
+            let text = fs::read_to_string(dir.join(name)).ok()?;
+            Some(format!("{}\n\n{}", name, text.trim()))
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    (expression, text)
+}
+
+/// Rust source of the dependency licenses, see `about.rs`. Crates that ship the same license text
+/// share a copy of it.
+fn licenses_source() -> String {
+    let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    // Every locked package when cargo can't tell which ones the target links
+    let packages = runtime_packages(&manifest_dir).unwrap_or_else(|| {
+        let lock = fs::read_to_string(manifest_dir.join("Cargo.lock")).unwrap_or_default();
+        locked_packages(&lock)
+    });
+    let package_name = env::var("CARGO_PKG_NAME").unwrap();
+    let registries = registry_dirs();
+
+    let mut texts: BTreeMap<String, usize> = BTreeMap::new();
+    let mut entries = Vec::new();
+    for (name, version) in packages {
+        if name == package_name {
+            continue;
+        }
+        let dir = registries
+            .iter()
+            .map(|registry| registry.join(format!("{}-{}", name, version)))
+            .find(|dir| dir.is_dir());
+        let (expression, text) = dir.map(|dir| crate_license(&dir)).unwrap_or_default();
+        let next = texts.len();
+        let text_index = if text.is_empty() {
+            None
+        } else {
+            Some(*texts.entry(text).or_insert(next))
+        };
+        entries.push((name, version, expression, text_index));
+    }
+
+    let mut ordered_texts = vec![String::new(); texts.len()];
+    for (text, index) in texts {
+        ordered_texts[index] = text;
+    }
+    let mut source = String::from("pub static LICENSE_TEXTS: &[&str] = &[\n");
+    for text in &ordered_texts {
+        source += &format!("    {:?},\n", text);
+    }
+    source += "];\n\npub static DEPENDENCIES: &[Dependency] = &[\n";
+    for (name, version, expression, text_index) in &entries {
+        source += &format!(
+            "    Dependency {{ name: {:?}, version: {:?}, license: {:?}, text: {:?} }},\n",
+            name, version, expression, text_index
+        );
+    }
+    source += "];\n";
+    source
 }
@@ -0,0 +1,101 @@
+/// A crate the app is built with, from the lock file at build time
+pub struct Dependency {
+    name: &'static str,
+    version: &'static str,
+    // SPDX expression from the crate's manifest, empty when it has none
+    license: &'static str,
+    // Index into LICENSE_TEXTS, None when the crate's sources had no license files
+    text: Option<usize>,
+}
+
+// LICENSE_TEXTS and DEPENDENCIES, generated by build.rs
+include!(concat!(env!("OUT_DIR"), "/licenses.rs"));
+
+const FEATURES: [(&str, bool); 6] = [
+    ("bloom", cfg!(feature = "bloom")),
+    ("picker", cfg!(feature = "picker")),
+    ("overlay", cfg!(feature = "overlay")),
+    ("profiler-gpu", cfg!(feature = "profiler-gpu")),
+    ("recording", cfg!(feature = "recording")),
+    ("stats-server", cfg!(feature = "stats-server")),
+];
+
+/// Version and build information, and the licenses of every dependency that has to ship with
+/// the app
+pub struct About {
+    filter: String,
+}
+
+impl About {
+    pub fn new() -> Self {
+        Self {
+            filter: String::new(),
+        }
+    }
+
+    fn build_info() -> String {
+        let features = FEATURES
+            .iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>();
+        format!(
+            "{} {}\nCommit {}, built {}\nFeatures: {}",
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION"),
+            env!("CA3D_GIT_HASH"),
+            env!("CA3D_BUILD_DATE"),
+            if features.is_empty() {
+                "none".to_owned()
+            } else {
+                features.join(", ")
+            }
+        )
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.heading("CellularAutomata3d");
+        ui.label(Self::build_info());
+        if ui.button("Copy build info").clicked() {
+            ui.output_mut(|o| o.copied_text = Self::build_info());
+        }
+        ui.separator();
+
+        ui.label(format!(
+            "Built with {} crates, under these licenses",
+            DEPENDENCIES.len()
+        ));
+        ui.horizontal(|ui| {
+            ui.label("Filter");
+            ui.text_edit_singleline(&mut self.filter);
+        });
+        let filter = self.filter.to_lowercase();
+        egui::ScrollArea::vertical()
+            .max_height(400.0)
+            .show(ui, |ui| {
+                for dependency in DEPENDENCIES.iter().filter(|d| {
+                    d.name.contains(&filter) || d.license.to_lowercase().contains(&filter)
+                }) {
+                    let title = format!(
+                        "{} {}  {}",
+                        dependency.name,
+                        dependency.version,
+                        match dependency.license {
+                            "" => "unknown license",
+                            license => license,
+                        }
+                    );
+                    egui::CollapsingHeader::new(title)
+                        .id_source((dependency.name, dependency.version))
+                        .show(ui, |ui| match dependency.text {
+                            Some(index) => {
+                                ui.label(egui::RichText::new(LICENSE_TEXTS[index]).monospace());
+                            }
+                            None => {
+                                ui.weak("No license files were found in the crate's sources");
+                            }
+                        });
+                }
+            });
+    }
+}
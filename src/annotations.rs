@@ -0,0 +1,211 @@
+use std::collections::BTreeMap;
+
+use nalgebra_glm as glm;
+
+use crate::camera::Camera;
+use crate::gpu_stage::overlay::Overlay;
+
+const META_PREFIX: &str = "annotation.";
+// How far in front of the camera "Here" places a new annotation
+const PLACE_DISTANCE: f32 = 8.0;
+
+/// A colored, optionally labeled box of voxels, both corners inclusive
+#[derive(Clone, Debug)]
+pub struct Annotation {
+    pub min: glm::IVec3,
+    pub max: glm::IVec3,
+    pub color: [f32; 3],
+    pub note: String,
+}
+
+impl Annotation {
+    // `x0 y0 z0 x1 y1 z1 rrggbb note`, the note runs to the end of the line
+    fn encode(&self) -> String {
+        let [r, g, b] = self
+            .color
+            .map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+        format!(
+            "{} {} {} {} {} {} {:02x}{:02x}{:02x} {}",
+            self.min.x,
+            self.min.y,
+            self.min.z,
+            self.max.x,
+            self.max.y,
+            self.max.z,
+            r,
+            g,
+            b,
+            self.note.replace(['\n', '\r'], " ")
+        )
+    }
+
+    fn decode(value: &str) -> Result<Self, String> {
+        let mut parts = value.splitn(8, ' ');
+        let mut coords = [0; 6];
+        for coord in &mut coords {
+            let part = parts.next().ok_or("missing coordinate")?;
+            *coord = part
+                .parse()
+                .map_err(|e| format!("bad coordinate {:?}: {}", part, e))?;
+        }
+        let color = parts.next().ok_or("missing color")?;
+        let rgb = u32::from_str_radix(color, 16)
+            .ok()
+            .filter(|_| color.len() == 6)
+            .ok_or_else(|| format!("bad color {:?}", color))?;
+        let [_, r, g, b] = rgb.to_be_bytes();
+        let min = glm::vec3(coords[0], coords[1], coords[2]);
+        let max = glm::vec3(coords[3], coords[4], coords[5]);
+        Ok(Self {
+            min: glm::min2(&min, &max),
+            max: glm::max2(&min, &max),
+            color: [r, g, b].map(|c| c as f32 / 255.0),
+            note: parts.next().unwrap_or("").to_owned(),
+        })
+    }
+}
+
+/// User markings on the world, stored with world saves and drawn by the overlay. They live on the
+/// CPU only and never touch the simulation.
+pub struct Annotations {
+    pub visible: bool,
+    items: Vec<Annotation>,
+    editing: Annotation,
+}
+
+impl Annotations {
+    pub fn new() -> Self {
+        Self {
+            visible: true,
+            items: Vec::new(),
+            editing: Annotation {
+                min: glm::IVec3::zeros(),
+                max: glm::IVec3::zeros(),
+                color: [1.0, 0.3, 0.3],
+                note: String::new(),
+            },
+        }
+    }
+
+    pub fn draw(&self, overlay: &Overlay) {
+        if !self.visible {
+            return;
+        }
+        for annotation in &self.items {
+            let [r, g, b] = annotation.color;
+            overlay.cuboid(
+                glm::vec4(r, g, b, 1.0),
+                annotation.min.cast::<f32>(),
+                (annotation.max + glm::vec3(1, 1, 1)).cast::<f32>(),
+            );
+        }
+    }
+
+    pub fn write_meta(&self, meta: &mut BTreeMap<String, String>) {
+        for (i, annotation) in self.items.iter().enumerate() {
+            // Padded so the keys sort in order
+            meta.insert(format!("{}{:04}", META_PREFIX, i), annotation.encode());
+        }
+    }
+
+    /// Replaces the annotations with the ones written by `write_meta`, skipping invalid entries
+    pub fn read_meta(&mut self, meta: &BTreeMap<String, String>) {
+        self.items = meta
+            .iter()
+            .filter(|(key, _)| key.starts_with(META_PREFIX))
+            .filter_map(|(key, value)| match Annotation::decode(value) {
+                Ok(annotation) => Some(annotation),
+                Err(e) => {
+                    log::warn!("Skipping {}: {}", key, e);
+                    None
+                }
+            })
+            .collect();
+    }
+
+    /// Keeps the boxes on the same voxels after the world is doubled or halved about the origin
+    pub fn rescale(&mut self, up: bool) {
+        for annotation in &mut self.items {
+            if up {
+                annotation.min *= 2;
+                annotation.max = annotation.max * 2 + glm::vec3(1, 1, 1);
+            } else {
+                annotation.min = annotation.min.map(|c| c.div_euclid(2));
+                annotation.max = annotation.max.map(|c| c.div_euclid(2));
+            }
+        }
+    }
+
+    /// Moves the boxes along with the voxels they mark, for mirrored or rotated worlds
+    pub fn transform(&mut self, map: impl Fn(&glm::IVec3) -> glm::IVec3) {
+        for annotation in &mut self.items {
+            let (a, b) = (map(&annotation.min), map(&annotation.max));
+            annotation.min = glm::min2(&a, &b);
+            annotation.max = glm::max2(&a, &b);
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui, camera: &Camera) {
+        ui.collapsing("Annotations", |ui| {
+            ui.checkbox(&mut self.visible, "Show annotations");
+
+            let mut remove = None;
+            egui::Grid::new("annotations").show(ui, |ui| {
+                for (i, annotation) in self.items.iter_mut().enumerate() {
+                    ui.color_edit_button_rgb(&mut annotation.color);
+                    ui.label(format!(
+                        "{:?} to {:?}",
+                        annotation.min.as_slice(),
+                        annotation.max.as_slice()
+                    ));
+                    ui.text_edit_singleline(&mut annotation.note);
+                    if ui.small_button("Remove").clicked() {
+                        remove = Some(i);
+                    }
+                    ui.end_row();
+                }
+            });
+            if let Some(i) = remove {
+                self.items.remove(i);
+            }
+
+            ui.separator();
+            let editing = &mut self.editing;
+            for (label, corner) in [("From", &mut editing.min), ("To", &mut editing.max)] {
+                ui.horizontal(|ui| {
+                    ui.label(label);
+                    for c in corner.iter_mut() {
+                        ui.add(egui::DragValue::new(c));
+                    }
+                });
+            }
+            ui.horizontal(|ui| {
+                ui.color_edit_button_rgb(&mut editing.color);
+                ui.text_edit_singleline(&mut editing.note);
+            });
+            ui.horizontal(|ui| {
+                if ui
+                    .button("Here")
+                    .on_hover_text("Set both corners to the voxel in front of the camera")
+                    .clicked()
+                {
+                    let target = camera.position + camera.forward() * PLACE_DISTANCE;
+                    editing.min = glm::floor(&target).map(|c| c as i32);
+                    editing.max = editing.min;
+                }
+                if ui.button("Add").clicked() {
+                    let annotation = Annotation {
+                        min: glm::min2(&editing.min, &editing.max),
+                        max: glm::max2(&editing.min, &editing.max),
+                        ..editing.clone()
+                    };
+                    editing.note.clear();
+                    self.items.push(annotation);
+                }
+                if ui.button("Clear").clicked() {
+                    self.items.clear();
+                }
+            });
+        });
+    }
+}
@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+
+use crate::assets::{self, AssetEntry, AssetKind};
+use crate::thumbnail::THUMBNAIL_SIZE;
+
+pub enum AssetAction {
+    Save(AssetKind, String, Vec<String>),
+    Load(AssetKind, String),
+    // Holds a pattern for placing at the cursor instead of loading it where it was saved
+    Stamp(String),
+}
+
+struct RenameState {
+    name: String,
+    new_name: String,
+    tags: String,
+}
+
+/// Lists saved worlds, patterns and presets from storage
+pub struct AssetBrowser {
+    kind: AssetKind,
+    entries: Vec<AssetEntry>,
+    thumbnails: HashMap<String, egui::TextureHandle>,
+    needs_refresh: bool,
+    filter: String,
+    save_name: String,
+    save_tags: String,
+    renaming: Option<RenameState>,
+}
+
+impl AssetBrowser {
+    pub fn new() -> Self {
+        Self {
+            kind: AssetKind::World,
+            entries: Vec::new(),
+            thumbnails: HashMap::new(),
+            needs_refresh: true,
+            filter: String::new(),
+            save_name: String::new(),
+            save_tags: String::new(),
+            renaming: None,
+        }
+    }
+
+    /// Rereads the listing from storage the next time the browser is shown
+    pub fn refresh(&mut self) {
+        self.needs_refresh = true;
+    }
+
+    fn matches_filter(&self, entry: &AssetEntry) -> bool {
+        let filter = self.filter.trim().to_lowercase();
+        filter.is_empty()
+            || entry.name.to_lowercase().contains(&filter)
+            || entry
+                .tags
+                .iter()
+                .any(|tag| tag.to_lowercase().contains(&filter))
+    }
+
+    fn load_thumbnails(&mut self, ctx: &egui::Context) {
+        let size = THUMBNAIL_SIZE as usize;
+        self.thumbnails = self
+            .entries
+            .iter()
+            .filter_map(|entry| {
+                let pixels = assets::load_thumbnail(self.kind, &entry.name)?;
+                if pixels.len() != size * size * 4 {
+                    log::warn!("Ignoring malformed thumbnail of {}", entry.name);
+                    return None;
+                }
+                let image = egui::ColorImage::from_rgba_unmultiplied([size, size], &pixels);
+                let texture = ctx.load_texture(
+                    format!("thumbnail {}", entry.name),
+                    image,
+                    egui::TextureOptions::LINEAR,
+                );
+                Some((entry.name.clone(), texture))
+            })
+            .collect();
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) -> Option<AssetAction> {
+        if self.needs_refresh {
+            self.entries = assets::list(self.kind);
+            self.load_thumbnails(ui.ctx());
+            self.needs_refresh = false;
+        }
+
+        let mut action = None;
+
+        ui.horizontal(|ui| {
+            for kind in AssetKind::ALL {
+                if ui
+                    .selectable_value(&mut self.kind, kind, kind.label())
+                    .changed()
+                {
+                    self.renaming = None;
+                    self.refresh();
+                }
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Name");
+            ui.text_edit_singleline(&mut self.save_name);
+        });
+        ui.horizontal(|ui| {
+            ui.label("Tags");
+            ui.text_edit_singleline(&mut self.save_tags)
+                .on_hover_text("Comma separated");
+        });
+        if ui.button("Save current").clicked() {
+            action = Some(AssetAction::Save(
+                self.kind,
+                self.save_name.trim().to_owned(),
+                assets::parse_tags(&self.save_tags),
+            ));
+        }
+
+        ui.separator();
+
+        ui.horizontal(|ui| {
+            ui.label("Filter");
+            ui.text_edit_singleline(&mut self.filter);
+            if ui.button("Refresh").clicked() {
+                self.refresh();
+            }
+        });
+
+        let visible = (0..self.entries.len())
+            .filter(|&i| self.matches_filter(&self.entries[i]))
+            .collect::<Vec<_>>();
+        let mut changed = false;
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            for i in visible {
+                let entry = &self.entries[i];
+                ui.horizontal(|ui| {
+                    let thumbnail_size = egui::Vec2::splat(THUMBNAIL_SIZE as f32);
+                    match self.thumbnails.get(&entry.name) {
+                        Some(texture) => {
+                            ui.add(egui::Image::new((texture.id(), thumbnail_size)).rounding(2.0));
+                        }
+                        None => {
+                            let (rect, _) =
+                                ui.allocate_exact_size(thumbnail_size, egui::Sense::hover());
+                            ui.painter()
+                                .rect_filled(rect, 2.0, ui.visuals().extreme_bg_color);
+                        }
+                    }
+
+                    ui.vertical(|ui| {
+                        let renaming_this =
+                            self.renaming.as_ref().is_some_and(|r| r.name == entry.name);
+                        if renaming_this {
+                            let rename = self.renaming.as_mut().unwrap();
+                            ui.text_edit_singleline(&mut rename.new_name);
+                            ui.text_edit_singleline(&mut rename.tags);
+                            ui.horizontal(|ui| {
+                                if ui.button("Apply").clicked() {
+                                    if let Err(e) = assets::rename(
+                                        self.kind,
+                                        &rename.name,
+                                        rename.new_name.trim(),
+                                        &assets::parse_tags(&rename.tags),
+                                    ) {
+                                        log::warn!("Failed to rename {}: {}", rename.name, e);
+                                    }
+                                    changed = true;
+                                }
+                                if ui.button("Cancel").clicked() {
+                                    changed = true;
+                                }
+                            });
+                            return;
+                        }
+
+                        if ui
+                            .selectable_label(false, &entry.name)
+                            .on_hover_text("Double-click to load")
+                            .double_clicked()
+                        {
+                            action = Some(AssetAction::Load(self.kind, entry.name.clone()));
+                        }
+                        if !entry.tags.is_empty() {
+                            ui.weak(entry.tags.join(", "));
+                        }
+                        ui.horizontal(|ui| {
+                            if ui.small_button("Rename").clicked() {
+                                self.renaming = Some(RenameState {
+                                    name: entry.name.clone(),
+                                    new_name: entry.name.clone(),
+                                    tags: entry.tags.join(", "),
+                                });
+                            }
+                            if self.kind == AssetKind::Pattern
+                                && ui
+                                    .small_button("Stamp")
+                                    .on_hover_text("Place copies of it with the mouse")
+                                    .clicked()
+                            {
+                                action = Some(AssetAction::Stamp(entry.name.clone()));
+                            }
+                            if ui.small_button("Delete").clicked() {
+                                if let Err(e) = assets::remove(self.kind, &entry.name) {
+                                    log::warn!("Failed to delete {}: {}", entry.name, e);
+                                }
+                                changed = true;
+                            }
+                        });
+                    });
+                });
+            }
+        });
+        if changed {
+            self.renaming = None;
+            self.refresh();
+        }
+
+        action
+    }
+}
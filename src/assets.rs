@@ -0,0 +1,190 @@
+use std::collections::BTreeMap;
+
+use crate::storage;
+
+const FILE_EXTENSION: &str = ".txt";
+const THUMBNAIL_EXTENSION: &str = ".thumb";
+const BODY_SEPARATOR: &str = "---\n";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AssetKind {
+    World,
+    Pattern,
+    Preset,
+}
+
+impl AssetKind {
+    pub const ALL: [AssetKind; 3] = [AssetKind::World, AssetKind::Pattern, AssetKind::Preset];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            AssetKind::World => "Worlds",
+            AssetKind::Pattern => "Patterns",
+            AssetKind::Preset => "Presets",
+        }
+    }
+
+    fn dir(&self) -> &'static str {
+        match self {
+            AssetKind::World => "worlds",
+            AssetKind::Pattern => "patterns",
+            AssetKind::Preset => "presets",
+        }
+    }
+
+    fn key(&self, name: &str) -> String {
+        format!("{}/{}{}", self.dir(), name, FILE_EXTENSION)
+    }
+
+    fn thumbnail_key(&self, name: &str) -> String {
+        format!("{}/{}{}", self.dir(), name, THUMBNAIL_EXTENSION)
+    }
+}
+
+/// A saved artifact: `key=value` metadata lines, a `---` line, then a kind specific body
+#[derive(Default)]
+pub struct Asset {
+    pub meta: BTreeMap<String, String>,
+    pub body: String,
+}
+
+impl Asset {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn parse(contents: &str) -> Self {
+        let (header, body) = contents
+            .split_once(BODY_SEPARATOR)
+            .unwrap_or((contents, ""));
+        let meta = header
+            .lines()
+            .filter_map(|line| line.split_once('='))
+            .map(|(key, value)| (key.trim().to_owned(), value.trim().to_owned()))
+            .collect();
+        Self {
+            meta,
+            body: body.to_owned(),
+        }
+    }
+
+    fn serialize(&self) -> String {
+        let mut contents = self
+            .meta
+            .iter()
+            .map(|(key, value)| format!("{}={}\n", key, value))
+            .collect::<String>();
+        contents.push_str(BODY_SEPARATOR);
+        contents.push_str(&self.body);
+        contents
+    }
+
+    pub fn tags(&self) -> Vec<String> {
+        self.meta
+            .get("tags")
+            .map(|tags| parse_tags(tags))
+            .unwrap_or_default()
+    }
+
+    pub fn set_tags(&mut self, tags: &[String]) {
+        self.meta.insert("tags".to_owned(), tags.join(","));
+    }
+}
+
+pub fn parse_tags(tags: &str) -> Vec<String> {
+    tags.split(',')
+        .map(|tag| tag.trim())
+        .filter(|tag| !tag.is_empty())
+        .map(|tag| tag.to_owned())
+        .collect()
+}
+
+pub struct AssetEntry {
+    pub name: String,
+    pub tags: Vec<String>,
+}
+
+fn validate_name(name: &str) -> Result<(), String> {
+    if name.trim().is_empty() {
+        return Err("name is empty".to_owned());
+    }
+    if name.contains(['/', '\\']) || name.starts_with('.') {
+        return Err(format!("invalid name {:?}", name));
+    }
+    Ok(())
+}
+
+pub fn list(kind: AssetKind) -> Vec<AssetEntry> {
+    storage::list(kind.dir())
+        .into_iter()
+        .filter_map(|file_name| {
+            let name = file_name.strip_suffix(FILE_EXTENSION)?.to_owned();
+            let tags = load(kind, &name).map(|asset| asset.tags())?;
+            Some(AssetEntry { name, tags })
+        })
+        .collect()
+}
+
+pub fn load(kind: AssetKind, name: &str) -> Option<Asset> {
+    storage::read_string(&kind.key(name)).map(|contents| Asset::parse(&contents))
+}
+
+pub fn save(kind: AssetKind, name: &str, asset: &Asset) -> Result<(), String> {
+    validate_name(name)?;
+    storage::write_string(&kind.key(name), &asset.serialize())
+}
+
+pub fn exists(kind: AssetKind, name: &str) -> bool {
+    storage::list(kind.dir()).contains(&format!("{}{}", name, FILE_EXTENSION))
+}
+
+pub fn remove(kind: AssetKind, name: &str) -> Result<(), String> {
+    remove_thumbnail(kind, name)?;
+    storage::remove(&kind.key(name))
+}
+
+fn remove_thumbnail(kind: AssetKind, name: &str) -> Result<(), String> {
+    if storage::read_string(&kind.thumbnail_key(name)).is_some() {
+        storage::remove(&kind.thumbnail_key(name))?;
+    }
+    Ok(())
+}
+
+/// Thumbnails are stored as hex in a sidecar next to the asset so listing stays cheap
+pub fn save_thumbnail(kind: AssetKind, name: &str, pixels: &[u8]) -> Result<(), String> {
+    validate_name(name)?;
+    let hex = pixels
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>();
+    storage::write_string(&kind.thumbnail_key(name), &hex)
+}
+
+pub fn load_thumbnail(kind: AssetKind, name: &str) -> Option<Vec<u8>> {
+    let hex = storage::read_string(&kind.thumbnail_key(name))?;
+    let hex = hex.trim();
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Renames `from` to `to` and replaces its tags, fails when another asset is already named `to`
+pub fn rename(kind: AssetKind, from: &str, to: &str, tags: &[String]) -> Result<(), String> {
+    validate_name(to)?;
+    if from != to && exists(kind, to) {
+        return Err(format!("{} already exists", to));
+    }
+    let mut asset = load(kind, from).ok_or_else(|| format!("{} not found", from))?;
+    asset.set_tags(tags);
+    save(kind, to, &asset)?;
+    if from != to {
+        // A thumbnail left under the new name would otherwise show for this asset
+        match storage::read_string(&kind.thumbnail_key(from)) {
+            Some(hex) => storage::write_string(&kind.thumbnail_key(to), &hex)?,
+            None => remove_thumbnail(kind, to)?,
+        }
+        remove(kind, from)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,137 @@
+use crate::chunk_manager::ChunkManager;
+use crate::gpu_stage::meshing_render::{self, Meshing};
+use crate::gpu_stage::simulate::{self, Simulate};
+use crate::settings::Settings;
+use crate::wgpu_context::WgpuContext;
+
+#[cfg(not(target_arch = "wasm32"))]
+const BENCHMARK_ITERATIONS: u32 = 8;
+
+fn format_size(size: [u32; 3]) -> String {
+    format!("{}x{}x{}", size[0], size[1], size[2])
+}
+
+fn parse_size(value: &str) -> Option<[u32; 3]> {
+    let mut parts = value.split('x').map(|part| part.parse::<u32>().ok());
+    let size = [parts.next()??, parts.next()??, parts.next()??];
+    parts.next().is_none().then_some(size)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn time_submissions(
+    ctx: &WgpuContext,
+    mut record: impl FnMut(&mut wgpu::CommandEncoder),
+) -> std::time::Duration {
+    let new_encoder = || {
+        ctx.device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("autotune encoder"),
+            })
+    };
+
+    // Warm up first so lazily created resources don't count towards the measurement
+    let mut encoder = new_encoder();
+    record(&mut encoder);
+    ctx.submit([encoder.finish()]);
+    ctx.device.poll(wgpu::Maintain::Wait);
+
+    let mut encoder = new_encoder();
+    for _ in 0..BENCHMARK_ITERATIONS {
+        record(&mut encoder);
+    }
+    let start = std::time::Instant::now();
+    ctx.submit([encoder.finish()]);
+    ctx.device.poll(wgpu::Maintain::Wait);
+    start.elapsed()
+}
+
+/// Picks the fastest workgroup sizes for the compute kernels on the current adapter. Results are
+/// cached in the settings, so the benchmark only runs the first time an adapter is seen.
+pub fn autotune(
+    ctx: &WgpuContext,
+    settings: &mut Settings,
+    chunk_manager: &ChunkManager,
+    simulate: &mut Simulate,
+    meshing: &mut Meshing,
+) {
+    let adapter_info = ctx.adapter.get_info();
+    let prefix = format!(
+        "autotune.{}.{:?}",
+        adapter_info.name.replace('=', "_"),
+        adapter_info.backend
+    );
+    let simulate_key = format!("{}.simulate", prefix);
+    let meshing_key = format!("{}.meshing", prefix);
+
+    let cached_simulate = settings
+        .get::<u32>(&simulate_key)
+        .filter(|size| simulate::WORKGROUP_SIZE_CANDIDATES.contains(size));
+    let cached_meshing = settings
+        .get::<String>(&meshing_key)
+        .and_then(|value| parse_size(&value))
+        .filter(|size| meshing_render::WORKGROUP_SIZE_CANDIDATES.contains(size));
+
+    if let (Some(simulate_size), Some(meshing_size)) = (cached_simulate, cached_meshing) {
+        simulate.set_workgroup_size(ctx, chunk_manager, simulate_size);
+        meshing.set_workgroup_size(ctx, chunk_manager, meshing_size);
+        log::info!(
+            "Using cached workgroup sizes: simulate {}, meshing {}",
+            simulate_size,
+            format_size(meshing_size)
+        );
+        return;
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    {
+        // There is no blocking wait on the web, so timings can't be taken synchronously
+        log::info!("Skipping workgroup autotuning on the web, using defaults");
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let simulate_size = simulate::WORKGROUP_SIZE_CANDIDATES
+            .iter()
+            .map(|&size| {
+                simulate.set_workgroup_size(ctx, chunk_manager, size);
+                let elapsed = time_submissions(ctx, |encoder| {
+                    simulate.encode_benchmark(ctx, encoder, chunk_manager);
+                });
+                log::info!("Simulate workgroup size {}: {:?}", size, elapsed);
+                (size, elapsed)
+            })
+            .min_by_key(|(_, elapsed)| *elapsed)
+            .map(|(size, _)| size)
+            .unwrap();
+        simulate.set_workgroup_size(ctx, chunk_manager, simulate_size);
+
+        let meshing_size = meshing_render::WORKGROUP_SIZE_CANDIDATES
+            .iter()
+            .map(|&size| {
+                meshing.set_workgroup_size(ctx, chunk_manager, size);
+                let elapsed = time_submissions(ctx, |encoder| {
+                    meshing.invalidate_all();
+                    meshing.update(ctx, encoder, chunk_manager);
+                });
+                log::info!(
+                    "Meshing workgroup size {}: {:?}",
+                    format_size(size),
+                    elapsed
+                );
+                (size, elapsed)
+            })
+            .min_by_key(|(_, elapsed)| *elapsed)
+            .map(|(size, _)| size)
+            .unwrap();
+        meshing.set_workgroup_size(ctx, chunk_manager, meshing_size);
+
+        log::info!(
+            "Autotuned workgroup sizes: simulate {}, meshing {}",
+            simulate_size,
+            format_size(meshing_size)
+        );
+        settings.set(&simulate_key, simulate_size);
+        settings.set(&meshing_key, format_size(meshing_size));
+        settings.save();
+    }
+}
@@ -0,0 +1,344 @@
+use std::collections::VecDeque;
+
+use crate::chunk_manager::ChunkSnapshot;
+use crate::thumbnail::{ThumbnailCapture, THUMBNAIL_SIZE};
+
+// Population samples kept for the timeline plot
+const HISTORY_LENGTH: usize = 512;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Trend {
+    Steady,
+    Growing,
+    Shrinking,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MomentKind {
+    // The population started growing quickly
+    Burst,
+    // The population started dying off quickly
+    Collapse,
+    // The last cell died
+    Extinction,
+}
+
+impl MomentKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            MomentKind::Burst => "Burst",
+            MomentKind::Collapse => "Collapse",
+            MomentKind::Extinction => "Extinction",
+        }
+    }
+}
+
+/// A moment worth reviewing: the world as it was, and how it looked from the camera
+pub struct Bookmark {
+    // Tick of the snapshot, the population sample that triggered it was read back a few ticks
+    // earlier
+    pub tick: u64,
+    pub kind: MomentKind,
+    pub population: u64,
+    // Relative population change per tick that triggered it
+    pub rate: f64,
+    snapshot: ChunkSnapshot,
+    capture: Option<ThumbnailCapture>,
+    pixels: Option<Vec<u8>>,
+    texture: Option<egui::TextureHandle>,
+}
+
+impl Bookmark {
+    pub fn snapshot(&self) -> &ChunkSnapshot {
+        &self.snapshot
+    }
+}
+
+/// Watches the population from the live bounds reduction and bookmarks the moments where its
+/// trend changes, so long unattended runs can be reviewed afterwards
+pub struct Bookmarks {
+    pub enabled: bool,
+    // Relative change per tick above which the population counts as growing or shrinking
+    pub threshold: f64,
+    // Fewest ticks between two bookmarks
+    pub min_gap: u64,
+    pub capacity: usize,
+    bookmarks: VecDeque<Bookmark>,
+    // Tick and population of the last sample
+    last_sample: Option<(u64, u64)>,
+    smoothed_rate: f64,
+    trend: Trend,
+    history: VecDeque<(u64, u64)>,
+}
+
+impl Bookmarks {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            threshold: 0.01,
+            min_gap: 64,
+            capacity: 16,
+            bookmarks: VecDeque::new(),
+            last_sample: None,
+            smoothed_rate: 0.0,
+            trend: Trend::Steady,
+            history: VecDeque::new(),
+        }
+    }
+
+    /// Feeds the tick and population of the latest reduction result, see
+    /// `LiveBoundsReduction::population`. Returns what to bookmark when the trend just changed or
+    /// the population died out.
+    pub fn observe(&mut self, sample: Option<(u64, u64)>) -> Option<(MomentKind, f64)> {
+        if !self.enabled {
+            return None;
+        }
+        let (tick, population) = sample?;
+        let Some((last_tick, last_population)) = self.last_sample else {
+            self.last_sample = Some((tick, population));
+            return None;
+        };
+        // Results of edits between ticks don't make a rate
+        if tick == last_tick {
+            return None;
+        }
+        self.last_sample = Some((tick, population));
+        // Stepping back or loading a world starts over
+        if tick < last_tick {
+            self.smoothed_rate = 0.0;
+            self.trend = Trend::Steady;
+            self.history.clear();
+            return None;
+        }
+
+        self.history.push_back((tick, population));
+        while self.history.len() > HISTORY_LENGTH {
+            self.history.pop_front();
+        }
+
+        let rate = (population as f64 - last_population as f64)
+            / last_population.max(1) as f64
+            / (tick - last_tick) as f64;
+        if population == 0 && last_population > 0 {
+            // Always worth a bookmark, however recent the last one
+            self.smoothed_rate = 0.0;
+            self.trend = Trend::Steady;
+            return Some((MomentKind::Extinction, rate));
+        }
+        // Single noisy samples shouldn't flip the trend
+        self.smoothed_rate = self.smoothed_rate * 0.5 + rate * 0.5;
+        let trend = if self.smoothed_rate > self.threshold {
+            Trend::Growing
+        } else if self.smoothed_rate < -self.threshold {
+            Trend::Shrinking
+        } else {
+            Trend::Steady
+        };
+        if trend == self.trend {
+            return None;
+        }
+        self.trend = trend;
+
+        let kind = match trend {
+            Trend::Growing => MomentKind::Burst,
+            Trend::Shrinking => MomentKind::Collapse,
+            Trend::Steady => return None,
+        };
+        if self
+            .bookmarks
+            .back()
+            .is_some_and(|last| tick < last.tick + self.min_gap)
+        {
+            return None;
+        }
+        Some((kind, self.smoothed_rate))
+    }
+
+    pub fn add(
+        &mut self,
+        tick: u64,
+        kind: MomentKind,
+        rate: f64,
+        snapshot: ChunkSnapshot,
+        capture: ThumbnailCapture,
+    ) {
+        log::info!(
+            "Bookmarked a {} at tick {}",
+            kind.name().to_lowercase(),
+            tick
+        );
+        let population = self.last_sample.map_or(0, |(_, population)| population);
+        self.bookmarks.push_back(Bookmark {
+            tick,
+            kind,
+            population,
+            rate,
+            snapshot,
+            capture: Some(capture),
+            pixels: None,
+            texture: None,
+        });
+        while self.bookmarks.len() > self.capacity.max(1) {
+            self.bookmarks.pop_front();
+        }
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Bookmark> {
+        self.bookmarks.get(index)
+    }
+
+    /// Textures belong to the egui context they were loaded in
+    pub fn reload_textures(&mut self) {
+        for bookmark in &mut self.bookmarks {
+            bookmark.texture = None;
+        }
+    }
+
+    fn load_textures(&mut self, ctx: &egui::Context) {
+        let size = THUMBNAIL_SIZE as usize;
+        for bookmark in &mut self.bookmarks {
+            if let Some(pixels) = bookmark.capture.as_ref().and_then(|c| c.try_take()) {
+                match pixels {
+                    Ok(pixels) => bookmark.pixels = Some(pixels),
+                    Err(e) => log::warn!("No thumbnail for bookmark {}: {}", bookmark.tick, e),
+                }
+                bookmark.capture = None;
+            }
+            if bookmark.texture.is_some() {
+                continue;
+            }
+            if let Some(pixels) = &bookmark.pixels {
+                let image = egui::ColorImage::from_rgba_unmultiplied([size, size], pixels);
+                bookmark.texture = Some(ctx.load_texture(
+                    format!("bookmark {}", bookmark.tick),
+                    image,
+                    egui::TextureOptions::LINEAR,
+                ));
+            }
+        }
+    }
+
+    fn history_ui(&self, ui: &mut egui::Ui) {
+        let (rect, response) = ui.allocate_exact_size(
+            egui::vec2(ui.available_width().max(120.0), 80.0),
+            egui::Sense::hover(),
+        );
+        let painter = ui.painter_at(rect);
+        painter.rect_filled(rect, 2.0, ui.visuals().extreme_bg_color);
+        let (Some(first), Some(last)) = (self.history.front(), self.history.back()) else {
+            return;
+        };
+        let max_population = self
+            .history
+            .iter()
+            .map(|(_, p)| *p)
+            .max()
+            .unwrap_or(1)
+            .max(1);
+        let span = (last.0 - first.0).max(1) as f32;
+        let to_screen = |tick: u64, population: u64| {
+            egui::pos2(
+                rect.left() + rect.width() * tick.saturating_sub(first.0) as f32 / span,
+                rect.bottom()
+                    - 2.0
+                    - (rect.height() - 4.0) * population as f32 / max_population as f32,
+            )
+        };
+        for bookmark in &self.bookmarks {
+            if bookmark.tick < first.0 {
+                continue;
+            }
+            let x = to_screen(bookmark.tick, 0).x;
+            let color = match bookmark.kind {
+                MomentKind::Burst => egui::Color32::from_rgb(90, 200, 90),
+                MomentKind::Collapse => egui::Color32::from_rgb(220, 90, 90),
+                MomentKind::Extinction => egui::Color32::from_rgb(160, 160, 160),
+            };
+            painter.vline(x, rect.y_range(), egui::Stroke::new(1.0, color));
+        }
+        painter.add(egui::Shape::line(
+            self.history
+                .iter()
+                .map(|(tick, population)| to_screen(*tick, *population))
+                .collect(),
+            egui::Stroke::new(1.5, ui.visuals().widgets.active.fg_stroke.color),
+        ));
+        response.on_hover_text(format!(
+            "Population from tick {} to {}, up to {} cells",
+            first.0, last.0, max_population
+        ));
+    }
+
+    /// Returns the index of a bookmark to go back to
+    pub fn ui(&mut self, ui: &mut egui::Ui) -> Option<usize> {
+        self.load_textures(ui.ctx());
+        ui.checkbox(&mut self.enabled, "Bookmark interesting moments")
+            .on_hover_text("Needs the live bounds reduction, which samples the population");
+        ui.add(
+            egui::Slider::new(&mut self.threshold, 0.0001..=0.5)
+                .logarithmic(true)
+                .text("Change per tick"),
+        );
+        ui.add(
+            egui::Slider::new(&mut self.min_gap, 1..=4096)
+                .logarithmic(true)
+                .text("Min ticks apart"),
+        );
+        ui.add(egui::Slider::new(&mut self.capacity, 1..=64).text("Keep"));
+        self.history_ui(ui);
+
+        let memory = self
+            .bookmarks
+            .iter()
+            .map(|b| b.snapshot.size_bytes())
+            .sum::<u64>();
+        ui.label(format!(
+            "{} bookmarks, {:.1} MiB of snapshots",
+            self.bookmarks.len(),
+            memory as f64 / (1024.0 * 1024.0)
+        ));
+
+        let mut restore = None;
+        let mut remove = None;
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            for (i, bookmark) in self.bookmarks.iter().enumerate().rev() {
+                ui.horizontal(|ui| {
+                    let size = egui::vec2(THUMBNAIL_SIZE as f32, THUMBNAIL_SIZE as f32);
+                    match &bookmark.texture {
+                        Some(texture) => {
+                            ui.image((texture.id(), size));
+                        }
+                        None => {
+                            ui.allocate_exact_size(size, egui::Sense::hover());
+                        }
+                    }
+                    ui.vertical(|ui| {
+                        ui.label(format!(
+                            "{} at tick {}",
+                            bookmark.kind.name(),
+                            bookmark.tick
+                        ));
+                        ui.label(format!(
+                            "{} cells, {:+.2}% per tick",
+                            bookmark.population,
+                            bookmark.rate * 100.0
+                        ));
+                        ui.horizontal(|ui| {
+                            if ui.button("Go to").clicked() {
+                                restore = Some(i);
+                            }
+                            if ui.button("Remove").clicked() {
+                                remove = Some(i);
+                            }
+                        });
+                    });
+                });
+            }
+        });
+        if let Some(i) = remove {
+            self.bookmarks.remove(i);
+            return None;
+        }
+        restore
+    }
+}
@@ -0,0 +1,432 @@
+use nalgebra_glm as glm;
+
+use crate::settings::Settings;
+
+// Fraction of the remaining zoom or dolly covered per second is 1 - exp(-ANIMATION_RATE)
+const ANIMATION_RATE: f32 = 12.0;
+// Each scroll line zooms or dollies by this factor
+const SCROLL_ZOOM_FACTOR: f32 = 0.9;
+const MIN_FOV: f32 = 10.0;
+const MAX_FOV: f32 = 170.0;
+// Closest a dolly gets to its target point
+const MIN_DOLLY_DISTANCE: f32 = 1.0;
+const MAX_DOLLY_DISTANCE: f32 = 16384.0;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ProjectionType {
+    Perspective,
+    Orthographic,
+}
+
+/// How moving the mouse turns the camera
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LookMode {
+    // Clicking the view locks and hides the cursor until Escape
+    Lock,
+    // Dragging with the right mouse button held turns the camera, the cursor stays visible
+    Drag,
+    Both,
+}
+
+impl LookMode {
+    pub const ALL: [LookMode; 3] = [LookMode::Lock, LookMode::Drag, LookMode::Both];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            LookMode::Lock => "Click to lock cursor",
+            LookMode::Drag => "Right drag",
+            LookMode::Both => "Both",
+        }
+    }
+
+    // Stable name used in the settings
+    pub fn key(self) -> &'static str {
+        match self {
+            LookMode::Lock => "lock",
+            LookMode::Drag => "drag",
+            LookMode::Both => "both",
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|mode| mode.key() == key)
+    }
+
+    pub fn locks_on_click(self) -> bool {
+        self != LookMode::Drag
+    }
+
+    pub fn drags(self) -> bool {
+        self != LookMode::Lock
+    }
+}
+
+/// How mouse speed maps to turning speed
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SensitivityCurve {
+    Linear,
+    // Faster movements turn more per count, up to a cap
+    Accelerated,
+}
+
+impl SensitivityCurve {
+    fn key(self) -> &'static str {
+        match self {
+            SensitivityCurve::Linear => "linear",
+            SensitivityCurve::Accelerated => "accelerated",
+        }
+    }
+
+    fn from_key(key: &str) -> Option<Self> {
+        [SensitivityCurve::Linear, SensitivityCurve::Accelerated]
+            .into_iter()
+            .find(|curve| curve.key() == key)
+    }
+}
+
+/// Turns raw mouse counts into degrees to turn the camera by. Kept apart from the camera, which
+/// gets replaced when a world is generated, and saved in the settings.
+pub struct MouseLook {
+    // Degrees per count at the slowest
+    pub sensitivity: f32,
+    pub curve: SensitivityCurve,
+    // Extra gain per count of speed in a single motion event
+    pub acceleration: f32,
+    // Largest gain acceleration reaches
+    pub max_gain: f32,
+    pub scale_x: f32,
+    pub scale_y: f32,
+    pub invert_y: bool,
+}
+
+impl MouseLook {
+    pub fn new(settings: &Settings) -> Self {
+        let get = |key: &str| settings.get::<String>(&format!("mouse.{}", key));
+        let parse = |key: &str, default: f32| {
+            get(key)
+                .and_then(|v| v.parse::<f32>().ok())
+                .filter(|v| v.is_finite())
+                .unwrap_or(default)
+        };
+        Self {
+            sensitivity: parse("sensitivity", 0.1),
+            curve: get("curve")
+                .and_then(|v| SensitivityCurve::from_key(&v))
+                .unwrap_or(SensitivityCurve::Linear),
+            acceleration: parse("acceleration", 0.02),
+            max_gain: parse("max_gain", 4.0),
+            scale_x: parse("scale_x", 1.0),
+            scale_y: parse("scale_y", 1.0),
+            invert_y: get("invert_y").is_some_and(|v| v == "true"),
+        }
+    }
+
+    fn save(&self, settings: &mut Settings) {
+        let mut set = |key: &str, value: String| settings.set(&format!("mouse.{}", key), value);
+        set("sensitivity", self.sensitivity.to_string());
+        set("curve", self.curve.key().to_owned());
+        set("acceleration", self.acceleration.to_string());
+        set("max_gain", self.max_gain.to_string());
+        set("scale_x", self.scale_x.to_string());
+        set("scale_y", self.scale_y.to_string());
+        set("invert_y", self.invert_y.to_string());
+        settings.save();
+    }
+
+    /// Degrees to turn right and up for a motion of `dx`, `dy` counts
+    pub fn apply(&self, dx: f64, dy: f64) -> (f32, f32) {
+        let (dx, dy) = (dx as f32, dy as f32);
+        let gain = match self.curve {
+            SensitivityCurve::Linear => 1.0,
+            SensitivityCurve::Accelerated => {
+                let speed = (dx * dx + dy * dy).sqrt();
+                (1.0 + speed * self.acceleration).min(self.max_gain.max(1.0))
+            }
+        };
+        let invert = if self.invert_y { -1.0 } else { 1.0 };
+        let degrees = self.sensitivity * gain;
+        (
+            dx * degrees * self.scale_x,
+            -dy * degrees * self.scale_y * invert,
+        )
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui, settings: &mut Settings) {
+        ui.collapsing("Mouse look", |ui| {
+            let mut changed = false;
+            let mut commit = |response: egui::Response| {
+                // Sliders are saved once let go instead of on every step of the drag
+                changed |= response.drag_released() || (response.changed() && !response.dragged());
+            };
+            commit(
+                ui.add(
+                    egui::Slider::new(&mut self.sensitivity, 0.005..=1.0)
+                        .logarithmic(true)
+                        .text("Sensitivity")
+                        .suffix("°/count"),
+                ),
+            );
+            ui.horizontal(|ui| {
+                commit(ui.radio_value(&mut self.curve, SensitivityCurve::Linear, "Linear"));
+                commit(ui.radio_value(
+                    &mut self.curve,
+                    SensitivityCurve::Accelerated,
+                    "Accelerated",
+                ));
+            });
+            ui.add_enabled_ui(self.curve == SensitivityCurve::Accelerated, |ui| {
+                commit(
+                    ui.add(
+                        egui::Slider::new(&mut self.acceleration, 0.0..=0.5)
+                            .logarithmic(true)
+                            .text("Acceleration"),
+                    )
+                    .on_hover_text("Extra gain per count moved in one event"),
+                );
+                commit(ui.add(egui::Slider::new(&mut self.max_gain, 1.0..=16.0).text("Max gain")));
+            });
+            commit(
+                ui.add(egui::Slider::new(&mut self.scale_x, 0.1..=4.0).text("Horizontal scale")),
+            );
+            commit(ui.add(egui::Slider::new(&mut self.scale_y, 0.1..=4.0).text("Vertical scale")));
+            commit(ui.checkbox(&mut self.invert_y, "Invert vertical"));
+            if changed {
+                self.save(settings);
+            }
+        });
+    }
+}
+
+pub struct Camera {
+    pub position: glm::Vec3,
+    pub look: glm::Vec2,
+    pub speed: f32,
+    pub fov: f32,
+    pub projection_type: ProjectionType,
+    // Height of the view volume in world units when using orthographic projection
+    pub ortho_height: f32,
+    // Where the animated zoom is heading, fov or ortho_height depending on the projection
+    zoom_target: Option<f32>,
+    // Movement still to be done by the animated dolly
+    dolly_remaining: glm::Vec3,
+    last_animate: Option<f64>,
+}
+
+impl Camera {
+    pub fn new() -> Self {
+        Self {
+            position: glm::vec3(80.0, 80.0, 80.0),
+            look: glm::vec2(-45.0, 45.0),
+            speed: 0.1,
+            fov: 90.0,
+            projection_type: ProjectionType::Perspective,
+            ortho_height: 128.0,
+            zoom_target: None,
+            dolly_remaining: glm::Vec3::zeros(),
+            last_animate: None,
+        }
+    }
+
+    pub fn move_relative(&mut self, rel_movement: glm::Vec3) {
+        let abs_movement = glm::rotate_y_vec3(
+            &glm::vec3(rel_movement.x, 0.0, rel_movement.z),
+            self.look.y.to_radians(),
+        ) + glm::vec3(0.0, rel_movement.y, 0.0);
+
+        self.position += abs_movement * self.speed;
+    }
+
+    /// Turns right by `yaw` and up by `pitch`, in degrees
+    pub fn turn(&mut self, yaw: f32, pitch: f32) {
+        self.look.y -= yaw;
+        self.look.x += pitch;
+        if self.look.x > 90.0 {
+            self.look.x = 90.0;
+        }
+        if self.look.x < -90.0 {
+            self.look.x = -90.0;
+        }
+    }
+
+    pub fn scroll(&mut self, y: f32) {
+        self.speed *= 1.0 + y / 100.0;
+        self.speed = self.speed.clamp(0.0001, 10000.0);
+    }
+
+    fn zoom_value(&mut self) -> &mut f32 {
+        match self.projection_type {
+            ProjectionType::Perspective => &mut self.fov,
+            ProjectionType::Orthographic => &mut self.ortho_height,
+        }
+    }
+
+    /// Narrows the view when scrolling up, animated by `animate`
+    pub fn scroll_zoom(&mut self, y: f32) {
+        let current = self.zoom_target.unwrap_or(*self.zoom_value());
+        let target = current * SCROLL_ZOOM_FACTOR.powf(y);
+        self.zoom_target = Some(match self.projection_type {
+            ProjectionType::Perspective => target.clamp(MIN_FOV, MAX_FOV),
+            ProjectionType::Orthographic => target.clamp(1.0, 4096.0),
+        });
+    }
+
+    /// Moves toward `target` when scrolling up and away from it when scrolling down, animated by
+    /// `animate`. Never gets closer than `MIN_DOLLY_DISTANCE` to the target.
+    pub fn scroll_dolly(&mut self, y: f32, target: &glm::Vec3) {
+        let goal = self.position + self.dolly_remaining;
+        let offset = goal - target;
+        let distance = glm::length(&offset);
+        if distance < f32::EPSILON {
+            return;
+        }
+        // Already being closer or further than the limits is fine, scrolling just doesn't go on
+        let new_distance = (distance * SCROLL_ZOOM_FACTOR.powf(y)).clamp(
+            distance.min(MIN_DOLLY_DISTANCE),
+            distance.max(MAX_DOLLY_DISTANCE),
+        );
+        self.dolly_remaining += offset * (new_distance / distance - 1.0);
+    }
+
+    pub fn is_animating(&self) -> bool {
+        self.zoom_target.is_some() || self.dolly_remaining != glm::Vec3::zeros()
+    }
+
+    /// Advances the zoom and dolly animations to `now`, in seconds
+    pub fn animate(&mut self, now: f64) {
+        let dt = self
+            .last_animate
+            .map_or(0.0, |last| (now - last).max(0.0) as f32);
+        self.last_animate = Some(now);
+        if !self.is_animating() {
+            return;
+        }
+        let t = 1.0 - (-ANIMATION_RATE * dt).exp();
+
+        if let Some(target) = self.zoom_target {
+            let value = self.zoom_value();
+            *value += (target - *value) * t;
+            if (target - *value).abs() < target * 1e-3 {
+                *value = target;
+                self.zoom_target = None;
+            }
+        }
+
+        let step = self.dolly_remaining * t;
+        self.position += step;
+        self.dolly_remaining -= step;
+        if glm::length(&self.dolly_remaining) < 1e-3 {
+            self.position += self.dolly_remaining;
+            self.dolly_remaining = glm::Vec3::zeros();
+        }
+    }
+
+    /// Drops any zoom or dolly still in progress, for when something else takes over the camera
+    pub fn stop_animation(&mut self) {
+        self.zoom_target = None;
+        self.dolly_remaining = glm::Vec3::zeros();
+    }
+
+    pub fn projection(&self, aspect: f32) -> glm::Mat4 {
+        match self.projection_type {
+            ProjectionType::Perspective => {
+                glm::reversed_infinite_perspective_rh_zo(aspect, self.fov.to_radians(), 0.1)
+            }
+            ProjectionType::Orthographic => {
+                let half_height = self.ortho_height * 0.5;
+                let half_width = half_height * aspect;
+                // Near and far are swapped to keep the reversed depth convention
+                glm::ortho_rh_zo(
+                    -half_width,
+                    half_width,
+                    -half_height,
+                    half_height,
+                    10000.0,
+                    -10000.0,
+                )
+            }
+        }
+    }
+
+    pub fn view(&self) -> glm::Mat4 {
+        let view: glm::Mat4 = glm::identity();
+        let view = glm::rotate_x(&view, -self.look.x.to_radians());
+        let view = glm::rotate_y(&view, -self.look.y.to_radians());
+        glm::translate(&view, &-self.position)
+    }
+
+    pub fn forward(&self) -> glm::Vec3 {
+        let (pitch, yaw) = (self.look.x.to_radians(), self.look.y.to_radians());
+        glm::vec3(
+            -yaw.sin() * pitch.cos(),
+            pitch.sin(),
+            -yaw.cos() * pitch.cos(),
+        )
+    }
+
+    /// Horizontal direction to the right of the view
+    pub fn right(&self) -> glm::Vec3 {
+        let yaw = self.look.y.to_radians();
+        glm::vec3(yaw.cos(), 0.0, -yaw.sin())
+    }
+
+    /// Keeps the view direction and moves back until the box from `min` to `max` fits in view
+    pub fn frame(&mut self, min: &glm::Vec3, max: &glm::Vec3) {
+        let center = (min + max) * 0.5;
+        let radius = (glm::length(&(max - min)) * 0.5).max(1.0);
+        let distance = match self.projection_type {
+            ProjectionType::Perspective => radius / (self.fov * 0.5).to_radians().sin(),
+            ProjectionType::Orthographic => {
+                self.ortho_height = radius * 2.0;
+                radius * 2.0
+            }
+        };
+        self.position = center - self.forward() * distance;
+    }
+
+    /// Turns to face `target` from the current position
+    pub fn look_at(&mut self, target: &glm::Vec3) {
+        let dir = glm::normalize(&(target - self.position));
+        if !dir.x.is_finite() {
+            return;
+        }
+        self.look.x = dir.y.asin().to_degrees();
+        self.look.y = (-dir.x).atan2(-dir.z).to_degrees();
+    }
+
+    /// Looks straight down at `center` with an orthographic projection covering `extent` units
+    pub fn top_down_preset(&mut self, center: glm::Vec3, extent: f32) {
+        self.position = center + glm::vec3(0.0, extent, 0.0);
+        self.look = glm::vec2(-90.0, 0.0);
+        self.projection_type = ProjectionType::Orthographic;
+        self.ortho_height = extent;
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.collapsing("Camera", |ui| {
+            ui.horizontal(|ui| {
+                ui.radio_value(
+                    &mut self.projection_type,
+                    ProjectionType::Perspective,
+                    "Perspective",
+                );
+                ui.radio_value(
+                    &mut self.projection_type,
+                    ProjectionType::Orthographic,
+                    "Orthographic",
+                );
+            });
+            match self.projection_type {
+                ProjectionType::Perspective => {
+                    ui.add(egui::Slider::new(&mut self.fov, MIN_FOV..=MAX_FOV).text("FOV"));
+                }
+                ProjectionType::Orthographic => {
+                    ui.add(
+                        egui::Slider::new(&mut self.ortho_height, 1.0..=4096.0)
+                            .logarithmic(true)
+                            .text("View height"),
+                    );
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,359 @@
+use std::ops::{Add, Mul, Sub};
+
+use nalgebra_glm as glm;
+
+use crate::camera::Camera;
+use crate::gpu_stage::overlay::Overlay;
+use crate::theme;
+
+const PATH_SEGMENT_STEPS: usize = 24;
+
+#[derive(Copy, Clone, Debug)]
+pub struct Keyframe {
+    // Seconds from the start of the path
+    pub time: f32,
+    pub position: glm::Vec3,
+    pub look: glm::Vec2,
+    pub fov: f32,
+    // 0 moves through the segment after this keyframe at constant speed, 1 eases in and out
+    pub ease: f32,
+}
+
+fn catmull_rom<T>(p: [T; 4], t: f32) -> T
+where
+    T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f32, Output = T>,
+{
+    let [p0, p1, p2, p3] = p;
+    let t2 = t * t;
+    let t3 = t2 * t;
+    (p1 * 2.0
+        + (p2 - p0) * t
+        + (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * t2
+        + (p1 * 3.0 - p0 - p2 * 3.0 + p3) * t3)
+        * 0.5
+}
+
+/// Camera keyframes on a timeline, played back along Catmull-Rom splines. Playback can run in
+/// real time or step a fixed amount per frame while the frames are recorded.
+pub struct CameraPath {
+    keyframes: Vec<Keyframe>,
+    pub show_path: bool,
+    pub looping: bool,
+    pub playback_speed: f32,
+
+    pub record: bool,
+    pub record_fps: u32,
+    pub record_size: (u32, u32),
+    pub record_name: String,
+
+    playing: bool,
+    time: f32,
+    last_now: f64,
+    record_frame: u32,
+}
+
+impl CameraPath {
+    pub fn new() -> Self {
+        Self {
+            keyframes: Vec::new(),
+            show_path: true,
+            looping: false,
+            playback_speed: 1.0,
+
+            record: false,
+            record_fps: 30,
+            record_size: (1280, 720),
+            record_name: "path".to_owned(),
+
+            playing: false,
+            time: 0.0,
+            last_now: 0.0,
+            record_frame: 0,
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.playing && self.record
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |k| k.time)
+    }
+
+    /// Adds a keyframe for the current camera, a few seconds after the last one
+    pub fn add_keyframe(&mut self, camera: &Camera) {
+        let time = if self.keyframes.is_empty() {
+            0.0
+        } else {
+            self.duration() + 2.0
+        };
+        self.keyframes.push(Keyframe {
+            time,
+            position: camera.position,
+            look: camera.look,
+            fov: camera.fov,
+            ease: 0.0,
+        });
+    }
+
+    fn sort(&mut self) {
+        self.keyframes
+            .sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap());
+    }
+
+    pub fn play(&mut self) {
+        if self.keyframes.len() < 2 {
+            log::warn!("A camera path needs at least two keyframes");
+            return;
+        }
+        self.sort();
+        self.playing = true;
+        self.time = 0.0;
+        self.record_frame = 0;
+    }
+
+    pub fn stop(&mut self) {
+        self.playing = false;
+    }
+
+    /// Position, look angles and FOV at `time` seconds
+    fn sample(&self, time: f32) -> Option<(glm::Vec3, glm::Vec2, f32)> {
+        let keyframes = &self.keyframes;
+        let last = keyframes.len().checked_sub(1)?;
+        let i = keyframes[..last]
+            .iter()
+            .rposition(|k| k.time <= time)
+            .unwrap_or(0);
+        let j = (i + 1).min(last);
+        let span = keyframes[j].time - keyframes[i].time;
+        let t = if span > 0.0 {
+            ((time - keyframes[i].time) / span).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let smooth = t * t * (3.0 - 2.0 * t);
+        let t = t + (smooth - t) * keyframes[i].ease;
+
+        let indices = [i.saturating_sub(1), i, j, (j + 1).min(last)];
+        let points = indices.map(|k| keyframes[k]);
+
+        // Yaw takes the short way around between neighbouring keyframes
+        let mut yaws = points.map(|k| k.look.y);
+        for k in 1..4 {
+            yaws[k] = yaws[k - 1] + (yaws[k] - yaws[k - 1] + 180.0).rem_euclid(360.0) - 180.0;
+        }
+        let looks = [0, 1, 2, 3].map(|k| glm::vec2(points[k].look.x, yaws[k]));
+
+        let position = catmull_rom(points.map(|k| k.position), t);
+        let look = catmull_rom(looks, t);
+        let fov = catmull_rom(points.map(|k| k.fov), t);
+        Some((
+            position,
+            glm::vec2(look.x.clamp(-90.0, 90.0), look.y.rem_euclid(360.0)),
+            fov.clamp(10.0, 170.0),
+        ))
+    }
+
+    fn apply(&self, camera: &mut Camera) {
+        if let Some((position, look, fov)) = self.sample(self.time) {
+            camera.position = position;
+            camera.look = look;
+            camera.fov = fov;
+        }
+    }
+
+    /// Advances playback to time `now` in seconds and moves the camera. While recording, time
+    /// instead steps by one frame per call unless the recorder is `busy`, and the index of the
+    /// frame to capture is returned.
+    pub fn update(&mut self, now: f64, camera: &mut Camera, busy: bool) -> Option<u32> {
+        let dt = (now - self.last_now).max(0.0) as f32;
+        self.last_now = now;
+        if !self.playing {
+            return None;
+        }
+
+        if self.record {
+            if busy {
+                return None;
+            }
+            if self.time > self.duration() {
+                log::info!("Recorded {} frames", self.record_frame);
+                self.playing = false;
+                return None;
+            }
+            self.apply(camera);
+            let frame = self.record_frame;
+            self.record_frame += 1;
+            self.time = self.record_frame as f32 / self.record_fps.max(1) as f32;
+            return Some(frame);
+        }
+
+        self.time += dt * self.playback_speed;
+        if self.time > self.duration() {
+            if self.looping {
+                self.time %= self.duration().max(f32::EPSILON);
+            } else {
+                self.time = self.duration();
+                self.playing = false;
+            }
+        }
+        self.apply(camera);
+        None
+    }
+
+    /// Draws the path and each keyframe's view direction
+    pub fn draw(&self, overlay: &Overlay) {
+        if !self.show_path || self.playing || self.keyframes.len() < 2 {
+            return;
+        }
+        let color = theme::rgba(overlay.theme.camera_path);
+        for pair in self.keyframes.windows(2) {
+            let (start, end) = (pair[0].time, pair[1].time);
+            let mut previous = pair[0].position;
+            for step in 1..=PATH_SEGMENT_STEPS {
+                let time = start + (end - start) * step as f32 / PATH_SEGMENT_STEPS as f32;
+                let Some((position, _, _)) = self.sample(time) else {
+                    return;
+                };
+                overlay.line(color, (previous, position));
+                previous = position;
+            }
+        }
+        for keyframe in &self.keyframes {
+            let camera = Camera {
+                position: keyframe.position,
+                look: keyframe.look,
+                ..Camera::new()
+            };
+            overlay.line(
+                theme::rgba(overlay.theme.keyframe),
+                (camera.position, camera.position + camera.forward() * 4.0),
+            );
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui, camera: &mut Camera) {
+        ui.collapsing("Camera path", |ui| {
+            ui.horizontal(|ui| {
+                if ui.button("Add keyframe").clicked() {
+                    self.add_keyframe(camera);
+                }
+                if self.playing {
+                    if ui.button("Stop").clicked() {
+                        self.stop();
+                    }
+                } else if ui.button("Play").clicked() {
+                    self.play();
+                }
+                if ui.button("Clear").clicked() {
+                    self.keyframes.clear();
+                    self.playing = false;
+                }
+            });
+
+            let duration = self.duration();
+            let scrub = ui.add_enabled(
+                !self.playing && self.keyframes.len() >= 2,
+                egui::Slider::new(&mut self.time, 0.0..=duration)
+                    .suffix(" s")
+                    .text("Time"),
+            );
+            if scrub.changed() {
+                self.sort();
+                self.apply(camera);
+            }
+
+            let mut remove = None;
+            let mut reorder = false;
+            egui::Grid::new("camera path keyframes").show(ui, |ui| {
+                for (i, keyframe) in self.keyframes.iter_mut().enumerate() {
+                    let response = ui.add(
+                        egui::DragValue::new(&mut keyframe.time)
+                            .speed(0.05)
+                            .clamp_range(0.0..=f32::MAX)
+                            .suffix(" s"),
+                    );
+                    reorder |=
+                        response.drag_released() || (response.changed() && !response.dragged());
+                    ui.add(
+                        egui::DragValue::new(&mut keyframe.ease)
+                            .speed(0.01)
+                            .clamp_range(0.0..=1.0)
+                            .prefix("ease "),
+                    );
+                    ui.add(
+                        egui::DragValue::new(&mut keyframe.fov)
+                            .clamp_range(10.0..=170.0)
+                            .prefix("fov "),
+                    );
+                    if ui.small_button("Go").clicked() {
+                        camera.position = keyframe.position;
+                        camera.look = keyframe.look;
+                        camera.fov = keyframe.fov;
+                    }
+                    if ui.small_button("Set").clicked() {
+                        keyframe.position = camera.position;
+                        keyframe.look = camera.look;
+                        keyframe.fov = camera.fov;
+                    }
+                    if ui.small_button("Remove").clicked() {
+                        remove = Some(i);
+                    }
+                    ui.end_row();
+                }
+            });
+            if let Some(i) = remove {
+                self.keyframes.remove(i);
+            }
+            if reorder {
+                self.sort();
+            }
+
+            ui.checkbox(&mut self.show_path, "Show path");
+            ui.checkbox(&mut self.looping, "Loop");
+            ui.add(
+                egui::Slider::new(&mut self.playback_speed, 0.1..=10.0)
+                    .logarithmic(true)
+                    .text("Playback speed"),
+            );
+
+            // Builds without the `recording` feature can't save the frames
+            if cfg!(feature = "recording") {
+                ui.separator();
+                ui.add_enabled_ui(!self.playing && !cfg!(target_arch = "wasm32"), |ui| {
+                    ui.checkbox(&mut self.record, "Record frames")
+                        .on_hover_text("Steps the path one frame at a time and saves every frame");
+                    ui.horizontal(|ui| {
+                        ui.add(
+                            egui::DragValue::new(&mut self.record_size.0).clamp_range(16..=7680),
+                        );
+                        ui.label("x");
+                        ui.add(
+                            egui::DragValue::new(&mut self.record_size.1).clamp_range(16..=4320),
+                        );
+                        ui.add(
+                            egui::DragValue::new(&mut self.record_fps)
+                                .clamp_range(1..=240)
+                                .suffix(" fps"),
+                        );
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Name");
+                        ui.text_edit_singleline(&mut self.record_name);
+                    });
+                });
+            }
+            if self.is_recording() {
+                ui.label(format!(
+                    "Recording frame {} of {}",
+                    self.record_frame,
+                    (self.duration() * self.record_fps as f32) as u32 + 1
+                ));
+            }
+        });
+    }
+}
@@ -1,5 +1,8 @@
 use nalgebra_glm as glm;
 
+pub const CHUNK_SIZE: u32 = 64;
+pub const CHUNK_VOLUME: usize = (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize;
+
 pub struct ResidencyOffset {
     pub index: u64,  // used by the offset tracker
     pub offset: u32, // offset into shared buffers
@@ -15,6 +18,8 @@ pub struct Chunk {
     pub pos: glm::I32Vec3,
     pub neighbors: u32,
     pub residency: Option<ResidencyOffset>,
+    // Bumped whenever the voxels are written from the CPU
+    pub version: u64,
 }
 
 impl Chunk {
@@ -23,6 +28,7 @@ impl Chunk {
             pos,
             residency: None,
             neighbors: 0,
+            version: 0,
         }
     }
 
@@ -33,3 +39,13 @@ impl Chunk {
             .offset
     }
 }
+
+/// The chunk containing the voxel at world position `pos`, and the voxel's position inside it
+pub fn world_to_chunk(pos: &glm::Vec3) -> (glm::IVec3, glm::UVec3) {
+    let voxel = pos.map(|c| c.floor() as i32);
+    let size = CHUNK_SIZE as i32;
+    (
+        voxel.map(|c| c.div_euclid(size)),
+        voxel.map(|c| c.rem_euclid(size) as u32),
+    )
+}
@@ -1,3 +1,4 @@
+use crate::chunk::CHUNK_SIZE;
 use crate::util::TextureAndView;
 use crate::wgpu_context::WgpuContext;
 use nalgebra_glm as glm;
@@ -5,6 +6,19 @@ use std::mem::size_of;
 use std::num::NonZeroU32;
 use wgpu::*;
 
+pub const CHUNK_FORMAT: TextureFormat = TextureFormat::R32Uint;
+pub const ATLAS_SIZE: u32 = 64;
+// Chunk positions are offset by this much when indexing the atlas
+pub const ATLAS_OFFSET: i32 = ATLAS_SIZE as i32 / 2;
+// Size of the binding array the grid groups are bound as
+pub const MAX_GRID_GROUPS: u32 = 8;
+
+/// Whether a chunk at `pos` can be addressed through the atlas
+pub fn in_atlas(pos: &glm::IVec3) -> bool {
+    pos.iter()
+        .all(|c| (-ATLAS_OFFSET..ATLAS_SIZE as i32 - ATLAS_OFFSET).contains(c))
+}
+
 pub struct ChunkDatastore {
     chunks_per_group: u32,
     grid_groups: Vec<TextureAndView>,
@@ -28,7 +42,7 @@ impl ChunkDatastore {
             mip_level_count: 1,
             sample_count: 1,
             dimension: TextureDimension::D3,
-            format: TextureFormat::R32Uint,
+            format: CHUNK_FORMAT,
             usage: TextureUsages::STORAGE_BINDING
                 | TextureUsages::TEXTURE_BINDING
                 | TextureUsages::COPY_SRC
@@ -48,7 +62,7 @@ impl ChunkDatastore {
         dummy_views: &[TextureView],
     ) -> BindGroup {
         let mut grid_views = grid_groups.iter().map(|v| &v.view).collect::<Vec<_>>();
-        for dummy in dummy_views[grid_views.len()..8].iter() {
+        for dummy in dummy_views[grid_views.len()..MAX_GRID_GROUPS as usize].iter() {
             grid_views.push(dummy);
         }
         ctx.device.create_bind_group(&BindGroupDescriptor {
@@ -71,14 +85,14 @@ impl ChunkDatastore {
         let texture = ctx.device.create_texture(&TextureDescriptor {
             label: Some("chunk_datastore grid_group_texture"),
             size: Extent3d {
-                width: 64 * chunks_per_group,
-                height: 64,
-                depth_or_array_layers: 64 * 2,
+                width: CHUNK_SIZE * chunks_per_group,
+                height: CHUNK_SIZE,
+                depth_or_array_layers: CHUNK_SIZE * 2,
             },
             mip_level_count: 1,
             sample_count: 1,
             dimension: TextureDimension::D3,
-            format: TextureFormat::R32Uint,
+            format: CHUNK_FORMAT,
             usage: TextureUsages::STORAGE_BINDING
                 | TextureUsages::COPY_SRC
                 | TextureUsages::COPY_DST,
@@ -106,7 +120,7 @@ impl ChunkDatastore {
                                 visibility: ShaderStages::COMPUTE,
                                 ty: BindingType::StorageTexture {
                                     access: StorageTextureAccess::ReadOnly,
-                                    format: TextureFormat::R32Uint,
+                                    format: CHUNK_FORMAT,
                                     view_dimension: TextureViewDimension::D3,
                                 },
                                 count: None,
@@ -119,10 +133,10 @@ impl ChunkDatastore {
                                         StorageTextureAccess::ReadWrite,
                                         StorageTextureAccess::ReadOnly,
                                     ][i],
-                                    format: TextureFormat::R32Uint,
+                                    format: CHUNK_FORMAT,
                                     view_dimension: TextureViewDimension::D3,
                                 },
-                                count: NonZeroU32::new(8),
+                                count: NonZeroU32::new(MAX_GRID_GROUPS),
                             },
                         ],
                     })
@@ -134,14 +148,14 @@ impl ChunkDatastore {
         let atlas_texture = ctx.device.create_texture(&TextureDescriptor {
             label: Some("chunk_datastore atlas_texture"),
             size: Extent3d {
-                width: 64,
-                height: 64,
-                depth_or_array_layers: 64,
+                width: ATLAS_SIZE,
+                height: ATLAS_SIZE,
+                depth_or_array_layers: ATLAS_SIZE,
             },
             mip_level_count: 1,
             sample_count: 1,
             dimension: TextureDimension::D3,
-            format: TextureFormat::R32Uint,
+            format: CHUNK_FORMAT,
             usage: TextureUsages::STORAGE_BINDING
                 | TextureUsages::TEXTURE_BINDING
                 | TextureUsages::COPY_SRC
@@ -157,7 +171,7 @@ impl ChunkDatastore {
             view: atlas_view,
         };
 
-        let dummy_views = (0..8)
+        let dummy_views = (0..MAX_GRID_GROUPS)
             .map(|_| Self::new_dummy_texture(ctx))
             .collect::<Vec<_>>();
 
@@ -198,9 +212,9 @@ impl ChunkDatastore {
         }
         let group = offset_and_which.0 / self.chunks_per_group;
         let origin = glm::UVec3::new(
-            (offset_and_which.0 % self.chunks_per_group) * 64,
+            (offset_and_which.0 % self.chunks_per_group) * CHUNK_SIZE,
             0,
-            offset_and_which.1 * 64,
+            offset_and_which.1 * CHUNK_SIZE,
         );
         (group, origin)
     }
@@ -221,13 +235,13 @@ impl ChunkDatastore {
             bytemuck::cast_slice(data),
             ImageDataLayout {
                 offset: 0,
-                bytes_per_row: Some(64 * size_of::<u32>() as u32),
-                rows_per_image: Some(64),
+                bytes_per_row: Some(CHUNK_SIZE * size_of::<u32>() as u32),
+                rows_per_image: Some(CHUNK_SIZE),
             },
             Extent3d {
-                width: 64,
-                height: 64,
-                depth_or_array_layers: 64,
+                width: CHUNK_SIZE,
+                height: CHUNK_SIZE,
+                depth_or_array_layers: CHUNK_SIZE,
             },
         );
     }
@@ -257,15 +271,15 @@ impl ChunkDatastore {
                 aspect: TextureAspect::All,
             },
             Extent3d {
-                width: 64,
-                height: 64,
-                depth_or_array_layers: 64,
+                width: CHUNK_SIZE,
+                height: CHUNK_SIZE,
+                depth_or_array_layers: CHUNK_SIZE,
             },
         );
     }
 
     pub fn update_atlas(&self, ctx: &WgpuContext, pos: glm::IVec3, data: u32) {
-        let pos = pos + glm::vec3(32, 32, 32);
+        let pos = pos + glm::vec3(ATLAS_OFFSET, ATLAS_OFFSET, ATLAS_OFFSET);
         ctx.queue.write_texture(
             ImageCopyTexture {
                 texture: &self.atlas.texture,
@@ -280,8 +294,82 @@ impl ChunkDatastore {
             bytemuck::cast_slice(&[data]),
             ImageDataLayout {
                 offset: 0,
-                bytes_per_row: Some(64 * size_of::<u32>() as u32),
-                rows_per_image: Some(64),
+                bytes_per_row: Some(ATLAS_SIZE * size_of::<u32>() as u32),
+                rows_per_image: Some(ATLAS_SIZE),
+            },
+            Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+
+    /// Copies a chunk into `buffer` at `buffer_offset`, tightly packed in x, y, z order
+    pub fn download(
+        &self,
+        encoder: &mut CommandEncoder,
+        offset_and_which: (u32, u32),
+        buffer: &Buffer,
+        buffer_offset: u64,
+    ) {
+        let (group, origin) = self.offset_and_which_to_group_and_origin(offset_and_which);
+        encoder.copy_texture_to_buffer(
+            ImageCopyTexture {
+                texture: &self.grid_groups[group as usize].texture,
+                mip_level: 0,
+                origin: Origin3d {
+                    x: origin.x,
+                    y: origin.y,
+                    z: origin.z,
+                },
+                aspect: TextureAspect::All,
+            },
+            ImageCopyBuffer {
+                buffer,
+                layout: ImageDataLayout {
+                    offset: buffer_offset,
+                    bytes_per_row: Some(CHUNK_SIZE * size_of::<u32>() as u32),
+                    rows_per_image: Some(CHUNK_SIZE),
+                },
+            },
+            Extent3d {
+                width: CHUNK_SIZE,
+                height: CHUNK_SIZE,
+                depth_or_array_layers: CHUNK_SIZE,
+            },
+        );
+    }
+
+    /// Copies the voxel at `local` inside a chunk into `buffer` at `buffer_offset`
+    pub fn download_voxel(
+        &self,
+        encoder: &mut CommandEncoder,
+        offset_and_which: (u32, u32),
+        local: glm::UVec3,
+        buffer: &Buffer,
+        buffer_offset: u64,
+    ) {
+        let (group, origin) = self.offset_and_which_to_group_and_origin(offset_and_which);
+        let origin = origin + local;
+        encoder.copy_texture_to_buffer(
+            ImageCopyTexture {
+                texture: &self.grid_groups[group as usize].texture,
+                mip_level: 0,
+                origin: Origin3d {
+                    x: origin.x,
+                    y: origin.y,
+                    z: origin.z,
+                },
+                aspect: TextureAspect::All,
+            },
+            ImageCopyBuffer {
+                buffer,
+                layout: ImageDataLayout {
+                    offset: buffer_offset,
+                    bytes_per_row: None,
+                    rows_per_image: None,
+                },
             },
             Extent3d {
                 width: 1,
@@ -291,37 +379,166 @@ impl ChunkDatastore {
         );
     }
 
-    // pub fn download(&self, _ctx: &WgpuContext, _data: &mut [u32; 64 * 64 * 64]) {
-    //     todo!("implement Chunk::download");
-    // }
+    pub fn restore(
+        &self,
+        encoder: &mut CommandEncoder,
+        offset_and_which: (u32, u32),
+        buffer: &Buffer,
+        buffer_offset: u64,
+    ) {
+        let (group, origin) = self.offset_and_which_to_group_and_origin(offset_and_which);
+        encoder.copy_buffer_to_texture(
+            ImageCopyBuffer {
+                buffer,
+                layout: ImageDataLayout {
+                    offset: buffer_offset,
+                    bytes_per_row: Some(CHUNK_SIZE * size_of::<u32>() as u32),
+                    rows_per_image: Some(CHUNK_SIZE),
+                },
+            },
+            ImageCopyTexture {
+                texture: &self.grid_groups[group as usize].texture,
+                mip_level: 0,
+                origin: Origin3d {
+                    x: origin.x,
+                    y: origin.y,
+                    z: origin.z,
+                },
+                aspect: TextureAspect::All,
+            },
+            Extent3d {
+                width: CHUNK_SIZE,
+                height: CHUNK_SIZE,
+                depth_or_array_layers: CHUNK_SIZE,
+            },
+        );
+    }
+
+    fn rebuild_bind_groups(&mut self, ctx: &WgpuContext) {
+        self.bind_group_rw = Self::new_bind_group_from_grid_groups(
+            ctx,
+            &self.atlas,
+            &self.grid_groups,
+            &self.bind_group_layout_rw,
+            &self.dummy_views,
+        );
+        self.bind_group_ro = Self::new_bind_group_from_grid_groups(
+            ctx,
+            &self.atlas,
+            &self.grid_groups,
+            &self.bind_group_layout_ro,
+            &self.dummy_views,
+        );
+    }
 
     pub fn ensure_size(&mut self, ctx: &WgpuContext, size: u32) {
         let required_groups = size.div_ceil(self.chunks_per_group);
+        assert!(
+            required_groups <= MAX_GRID_GROUPS,
+            "{} chunks need {} grid groups, the bind group has {}",
+            size,
+            required_groups,
+            MAX_GRID_GROUPS
+        );
         if required_groups > self.grid_groups.len() as u32 {
             self.grid_groups.resize_with(required_groups as usize, || {
                 Self::new_grid_group(ctx, self.chunks_per_group)
             });
-            self.bind_group_rw = Self::new_bind_group_from_grid_groups(
-                ctx,
-                &self.atlas,
-                &self.grid_groups,
-                &self.bind_group_layout_rw,
-                &self.dummy_views,
-            );
-            self.bind_group_ro = Self::new_bind_group_from_grid_groups(
-                ctx,
-                &self.atlas,
-                &self.grid_groups,
-                &self.bind_group_layout_ro,
-                &self.dummy_views,
+            self.rebuild_bind_groups(ctx);
+        }
+    }
+
+    /// Frees the grid groups past the ones the first `size` offsets need, returns how many were
+    /// freed. Groups are only ever added otherwise, so they stay allocated after chunks are
+    /// removed.
+    pub fn shrink(&mut self, ctx: &WgpuContext, size: u32) -> usize {
+        let required_groups = size.div_ceil(self.chunks_per_group).max(1) as usize;
+        let freed = self.grid_groups.len().saturating_sub(required_groups);
+        if freed > 0 {
+            self.grid_groups.truncate(required_groups);
+            self.rebuild_bind_groups(ctx);
+        }
+        freed
+    }
+
+    /// Largest group size the 3D texture limit allows, a power of two like every group size
+    pub fn max_chunks_per_group(ctx: &WgpuContext) -> u32 {
+        let max = ctx.device.limits().max_texture_dimension_3d / CHUNK_SIZE;
+        1 << max.max(1).ilog2()
+    }
+
+    /// Moves the first `size` offsets into new grid groups of `chunks_per_group` chunks each. Both
+    /// buffers of every chunk are copied, so offsets stay valid. Offsets past the current groups
+    /// are only made room for.
+    pub fn set_chunks_per_group(&mut self, ctx: &WgpuContext, chunks_per_group: u32, size: u32) {
+        assert!(
+            chunks_per_group.is_power_of_two(),
+            "chunks per group must be a power of two"
+        );
+        let required_groups = size.div_ceil(chunks_per_group).max(1);
+        assert!(
+            required_groups <= MAX_GRID_GROUPS,
+            "{} chunks don't fit {} grid groups of {}",
+            size,
+            MAX_GRID_GROUPS,
+            chunks_per_group
+        );
+        // Offsets past the current groups were just handed out and have nothing to copy yet
+        let copied = size.min(self.grid_groups.len() as u32 * self.chunks_per_group);
+        let grid_groups = (0..required_groups)
+            .map(|_| Self::new_grid_group(ctx, chunks_per_group))
+            .collect::<Vec<_>>();
+
+        let mut encoder = ctx
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("chunk_datastore set_chunks_per_group"),
+            });
+        for offset in 0..copied {
+            let (from, to) = (offset / self.chunks_per_group, offset / chunks_per_group);
+            encoder.copy_texture_to_texture(
+                ImageCopyTexture {
+                    texture: &self.grid_groups[from as usize].texture,
+                    mip_level: 0,
+                    origin: Origin3d {
+                        x: offset % self.chunks_per_group * CHUNK_SIZE,
+                        y: 0,
+                        z: 0,
+                    },
+                    aspect: TextureAspect::All,
+                },
+                ImageCopyTexture {
+                    texture: &grid_groups[to as usize].texture,
+                    mip_level: 0,
+                    origin: Origin3d {
+                        x: offset % chunks_per_group * CHUNK_SIZE,
+                        y: 0,
+                        z: 0,
+                    },
+                    aspect: TextureAspect::All,
+                },
+                Extent3d {
+                    width: CHUNK_SIZE,
+                    height: CHUNK_SIZE,
+                    depth_or_array_layers: CHUNK_SIZE * 2,
+                },
             );
         }
+        ctx.queue.submit([encoder.finish()]);
+
+        self.grid_groups = grid_groups;
+        self.chunks_per_group = chunks_per_group;
+        self.rebuild_bind_groups(ctx);
     }
 
     pub fn chunks_per_group(&self) -> u32 {
         self.chunks_per_group
     }
 
+    pub fn num_grid_groups(&self) -> usize {
+        self.grid_groups.len()
+    }
+
     pub fn bind_group_layout(&self, read_write: bool) -> &BindGroupLayout {
         if read_write {
             &self.bind_group_layout_rw
@@ -1,11 +1,24 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::mem::size_of;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
 
 use nalgebra_glm as glm;
+use rayon::prelude::*;
 
-use crate::chunk::{Chunk, ResidencyOffset};
-use crate::chunk_datastore::ChunkDatastore;
+use crate::chunk::{world_to_chunk, Chunk, ResidencyOffset, CHUNK_VOLUME};
+use crate::chunk_datastore::{in_atlas, ChunkDatastore, MAX_GRID_GROUPS};
+use crate::chunk_priority::ChunkPriority;
+use crate::gpu_stage::live_bounds::LiveBounds;
 use crate::wgpu_context::WgpuContext;
 
+// Chunks side by side in one grid group texture, unless the 3D texture limit is lower
+const DEFAULT_CHUNKS_PER_GROUP: u32 = 32;
+// Queued chunk data written to the GPU per frame, at least one chunk goes through regardless
+const DEFAULT_UPLOAD_BUDGET_MIB: f32 = 16.0;
+// Chunks per staging buffer when downloading, a whole world wouldn't fit in one buffer
+const DOWNLOAD_BATCH_CHUNKS: usize = 64;
+
 #[derive(Default)]
 struct SharedBufferOffsetTracker {
     index_to_offset: HashMap<u64, u32>,
@@ -48,13 +61,147 @@ impl SharedBufferOffsetTracker {
     }
 }
 
+/// Voxel data of every chunk copied back from the GPU, ready once the buffer is mapped
+pub struct ChunkDownload {
+    // Staging buffers and the chunks copied into them, in copy order
+    batches: Vec<(wgpu::Buffer, Vec<glm::IVec3>)>,
+    // Empty chunks without a slot, they come out as all zeros
+    empty: Vec<glm::IVec3>,
+    // Batches mapped so far
+    mapped: Arc<AtomicUsize>,
+    // Set when mapping any of the batches failed, the download never completes then
+    failed: Arc<AtomicBool>,
+}
+
+impl ChunkDownload {
+    /// `None` until every batch is mapped, an error if mapping one of them failed
+    pub fn try_take(&self) -> Option<Result<Vec<(glm::IVec3, Vec<u32>)>, String>> {
+        if self.failed.load(Ordering::Acquire) {
+            return Some(Err("Failed to read the chunks back from the GPU".to_owned()));
+        }
+        if self.mapped.load(Ordering::Acquire) < self.batches.len() {
+            return None;
+        }
+        let mut chunks = Vec::new();
+        for (buffer, positions) in &self.batches {
+            {
+                let mapped_range = buffer.slice(..).get_mapped_range();
+                let data: &[u32] = bytemuck::cast_slice(&mapped_range);
+                chunks.extend(
+                    positions
+                        .iter()
+                        .zip(data.chunks_exact(CHUNK_VOLUME))
+                        .map(|(pos, data)| (*pos, data.to_vec())),
+                );
+            }
+            buffer.unmap();
+        }
+        chunks.extend(self.empty.iter().map(|pos| (*pos, vec![0; CHUNK_VOLUME])));
+        self.mapped.store(0, Ordering::Release);
+        Some(Ok(chunks))
+    }
+}
+
+/// A single voxel copied back from the GPU, ready once the buffer is mapped
+pub struct VoxelReadback {
+    buffer: wgpu::Buffer,
+    mapped: Arc<AtomicBool>,
+    failed: Arc<AtomicBool>,
+}
+
+impl VoxelReadback {
+    /// Whether mapping failed, the value never arrives then
+    pub fn failed(&self) -> bool {
+        self.failed.load(Ordering::Acquire)
+    }
+
+    pub fn try_take(&self) -> Option<u32> {
+        if !self.mapped.load(Ordering::Acquire) {
+            return None;
+        }
+        let value = {
+            let mapped_range = self.buffer.slice(..).get_mapped_range();
+            bytemuck::cast_slice::<u8, u32>(&mapped_range)[0]
+        };
+        self.buffer.unmap();
+        self.mapped.store(false, Ordering::Release);
+        Some(value)
+    }
+}
+
+/// Voxel data of every chunk at one point in time, kept on the GPU
+pub struct ChunkSnapshot {
+    buffer: wgpu::Buffer,
+    positions: Vec<glm::IVec3>,
+}
+
+impl ChunkSnapshot {
+    pub fn size_bytes(&self) -> u64 {
+        self.buffer.size()
+    }
+
+    pub fn positions(&self) -> &[glm::IVec3] {
+        &self.positions
+    }
+
+    /// Copies the chunk at `index` into `buffer` at `buffer_offset`, tightly packed
+    pub fn copy_chunk_to_buffer(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        index: usize,
+        buffer: &wgpu::Buffer,
+        buffer_offset: u64,
+    ) {
+        let chunk_bytes = (CHUNK_VOLUME * size_of::<u32>()) as u64;
+        encoder.copy_buffer_to_buffer(
+            &self.buffer,
+            index as u64 * chunk_bytes,
+            buffer,
+            buffer_offset,
+            chunk_bytes,
+        );
+    }
+}
+
 pub struct ChunkManager {
     chunks: HashMap<glm::IVec3, Chunk>,
     shared_buffer_offset_tracker: SharedBufferOffsetTracker,
     atlas_updates: HashSet<glm::IVec3>,
+    // Chunk position for every shared buffer offset, rebuilt only when chunks change
+    offset_positions: Vec<glm::IVec3>,
     datastore: ChunkDatastore,
     modified_this_frame: bool,
     which: u32,
+    // Bumped whenever the simulation advances, which may change any chunk
+    sim_version: u64,
+    // Bumped whenever chunks are added, removed or move to another offset
+    layout_version: u64,
+    // Simulation tick as of the last update, and the tick every chunk last changed at. Changes
+    // from the simulation come from the GPU a few frames late, see `ChunkActivity`.
+    tick: u64,
+    last_modified: HashMap<glm::IVec3, u64>,
+    live_bounds: Option<LiveBounds>,
+    // Group size picked in the UI, applied at the start of the next frame
+    requested_chunks_per_group: Option<u32>,
+    // Set from the UI, the unused grid groups are freed at the end of the next finalize
+    compaction_requested: bool,
+    // Chunk removals since the last compaction, each moved the chunk at the last offset into the
+    // hole
+    relocations: usize,
+    dropped_chunks: usize,
+    capacity_error: Option<String>,
+    // Chunk data waiting to be uploaded, in priority order when processed, and how many made up
+    // the current batch
+    pending_uploads: VecDeque<(glm::IVec3, Vec<u32>)>,
+    upload_batch: usize,
+    upload_budget_mib: f32,
+    // Chunks of the world that hold no cells and were dropped from the grid groups, they read as
+    // outside the world to their neighbors until something needs them again
+    empty_chunks: HashSet<glm::IVec3>,
+    // Chunks that got a slot back and are cleared once it's assigned
+    pending_clears: HashSet<glm::IVec3>,
+    // Order chunks are generated, uploaded and meshed in
+    pub priority: ChunkPriority,
 }
 impl ChunkManager {
     pub fn new(ctx: &WgpuContext) -> Self {
@@ -62,9 +209,29 @@ impl ChunkManager {
             chunks: HashMap::new(),
             shared_buffer_offset_tracker: SharedBufferOffsetTracker::new(),
             atlas_updates: HashSet::new(),
-            datastore: ChunkDatastore::new(ctx, 32),
+            offset_positions: Vec::new(),
+            datastore: ChunkDatastore::new(
+                ctx,
+                DEFAULT_CHUNKS_PER_GROUP.min(ChunkDatastore::max_chunks_per_group(ctx)),
+            ),
             modified_this_frame: false,
             which: 0,
+            sim_version: 0,
+            layout_version: 0,
+            tick: 0,
+            last_modified: HashMap::new(),
+            live_bounds: None,
+            requested_chunks_per_group: None,
+            compaction_requested: false,
+            relocations: 0,
+            dropped_chunks: 0,
+            capacity_error: None,
+            pending_uploads: VecDeque::new(),
+            upload_batch: 0,
+            upload_budget_mib: DEFAULT_UPLOAD_BUDGET_MIB,
+            empty_chunks: HashSet::new(),
+            pending_clears: HashSet::new(),
+            priority: ChunkPriority::new(),
         }
     }
 
@@ -90,6 +257,7 @@ impl ChunkManager {
             }
         }
         self.atlas_updates.insert(chunk.pos);
+        self.last_modified.insert(chunk.pos, self.tick);
         chunk.neighbors = neighbors;
         self.chunks.insert(chunk.pos, chunk);
     }
@@ -100,13 +268,11 @@ impl ChunkManager {
             .chunks
             .remove(pos)
             .unwrap_or_else(|| panic!("chunk {:?} not found", pos));
-        self.shared_buffer_offset_tracker.remove_index(
-            chunk
-                .residency
-                .as_ref()
-                .unwrap_or_else(|| panic!("chunk {:?} offset not tracked", pos))
-                .index,
-        );
+        // Chunks added since the last finalize don't have an offset yet
+        if let Some(residency) = &chunk.residency {
+            self.shared_buffer_offset_tracker
+                .remove_index(residency.index);
+        }
         for dx in -1..=1 {
             for dy in -1..=1 {
                 for dz in -1..=1 {
@@ -122,18 +288,231 @@ impl ChunkManager {
             }
         }
         self.atlas_updates.insert(*pos);
+        self.last_modified.remove(pos);
         chunk.neighbors = 0;
         chunk
     }
 
+    /// Most chunks the grid groups can hold at once
+    pub fn max_chunks(&self) -> usize {
+        (MAX_GRID_GROUPS * self.datastore.chunks_per_group()) as usize
+    }
+
+    /// Most chunks the grid groups could hold if they were grown as far as the device allows
+    pub fn chunk_capacity(&self, ctx: &WgpuContext) -> usize {
+        (MAX_GRID_GROUPS * ChunkDatastore::max_chunks_per_group(ctx)) as usize
+    }
+
+    /// Why chunks were dropped since the last time the world was replaced, if any were
+    pub fn capacity_error(&self) -> Option<&str> {
+        self.capacity_error.as_deref()
+    }
+
+    // Chunks added past what the device can hold are removed again before they get an offset
+    fn drop_chunks_over_capacity(&mut self, ctx: &WgpuContext) {
+        let capacity = self.chunk_capacity(ctx);
+        if self.chunks.len() <= capacity {
+            return;
+        }
+        let excess = self
+            .chunks
+            .values()
+            .filter(|chunk| chunk.residency.is_none())
+            .map(|chunk| chunk.pos)
+            .take(self.chunks.len() - capacity)
+            .collect::<Vec<_>>();
+        for pos in &excess {
+            self.remove_chunk(pos);
+        }
+        self.dropped_chunks += excess.len();
+        let error = format!(
+            "{} chunks were dropped, the device holds at most {}",
+            self.dropped_chunks, capacity
+        );
+        log::error!("{}", error);
+        self.capacity_error = Some(error);
+    }
+
+    /// Whether a world made of the chunks at `positions` fits the atlas and the grid groups, grown
+    /// if needed
+    pub fn check_chunk_positions(
+        &self,
+        ctx: &WgpuContext,
+        positions: &HashSet<glm::IVec3>,
+    ) -> Result<(), String> {
+        if positions.len() > self.chunk_capacity(ctx) {
+            return Err(format!(
+                "{} chunks needed, at most {} fit",
+                positions.len(),
+                self.chunk_capacity(ctx)
+            ));
+        }
+        if let Some(pos) = positions.iter().find(|pos| !in_atlas(pos)) {
+            return Err(format!(
+                "chunk {:?} would be outside the world limits",
+                pos.as_slice()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Removes the chunks that aren't in `positions` and adds the missing ones, chunks in both keep
+    /// their voxels
+    pub fn set_chunk_positions(&mut self, positions: &HashSet<glm::IVec3>) {
+        let removed = self
+            .chunks
+            .keys()
+            .filter(|pos| !positions.contains(*pos))
+            .copied()
+            .collect::<Vec<_>>();
+        for pos in removed {
+            self.remove_chunk(&pos);
+        }
+        self.empty_chunks.retain(|pos| positions.contains(pos));
+        self.dropped_chunks = 0;
+        self.capacity_error = None;
+        // Whatever was still on its way belonged to the old world
+        self.pending_uploads.clear();
+        self.upload_batch = 0;
+        for pos in positions {
+            if !self.contains(pos) {
+                self.add_chunk(Chunk::new(*pos));
+            }
+        }
+    }
+
+    /// Chunks that have a slot in the grid groups, empty chunks that were dropped aren't included
     pub fn chunks(&self) -> &HashMap<glm::IVec3, Chunk> {
         &self.chunks
     }
 
+    pub fn empty_chunks(&self) -> &HashSet<glm::IVec3> {
+        &self.empty_chunks
+    }
+
+    /// Whether `pos` is part of the world, with a slot or empty
+    pub fn contains(&self, pos: &glm::IVec3) -> bool {
+        self.chunks.contains_key(pos) || self.empty_chunks.contains(pos)
+    }
+
+    /// Every chunk position of the world, including the empty chunks
+    pub fn world_positions(&self) -> impl Iterator<Item = glm::IVec3> + '_ {
+        self.chunks.keys().chain(&self.empty_chunks).copied()
+    }
+
+    /// Drops a chunk that holds no cells from the grid groups, it's no longer simulated or meshed
+    /// and reads as empty to its neighbors
+    pub fn evict_empty(&mut self, pos: &glm::IVec3) {
+        self.remove_chunk(pos);
+        self.pending_clears.remove(pos);
+        self.empty_chunks.insert(*pos);
+    }
+
+    /// Gives an empty chunk its slot back, cleared on the next finalize. Returns whether `pos` was
+    /// an empty chunk.
+    pub fn materialize(&mut self, pos: &glm::IVec3) -> bool {
+        if !self.empty_chunks.remove(pos) {
+            return false;
+        }
+        self.add_chunk(Chunk::new(*pos));
+        self.pending_clears.insert(*pos);
+        true
+    }
+
+    /// Brings back every empty chunk, for passes that need the whole world in the grid groups
+    pub fn materialize_all(&mut self) {
+        let positions = self.empty_chunks.iter().copied().collect::<Vec<_>>();
+        for pos in &positions {
+            self.materialize(pos);
+        }
+    }
+
+    /// Materializes the empty chunks overlapping the box from `min` to `max` in world space
+    pub fn materialize_in_aabb(&mut self, min: &glm::Vec3, max: &glm::Vec3) {
+        let min = world_to_chunk(min).0;
+        let max = world_to_chunk(max).0;
+        let positions = self
+            .empty_chunks
+            .iter()
+            .filter(|pos| (0..3).all(|axis| (min[axis]..=max[axis]).contains(&pos[axis])))
+            .copied()
+            .collect::<Vec<_>>();
+        for pos in &positions {
+            self.materialize(pos);
+        }
+    }
+
     pub fn chunks_mut(&mut self) -> &mut HashMap<glm::IVec3, Chunk> {
         &mut self.chunks
     }
 
+    /// The chunk containing world position `pos`, if there is one
+    pub fn chunk_at_world(&self, pos: &glm::Vec3) -> Option<&Chunk> {
+        self.chunks.get(&world_to_chunk(pos).0)
+    }
+
+    /// Chunks overlapping the box from `min` to `max` in world space, in no particular order
+    pub fn chunks_in_aabb(
+        &self,
+        min: &glm::Vec3,
+        max: &glm::Vec3,
+    ) -> impl Iterator<Item = &Chunk> + '_ {
+        let min = world_to_chunk(min).0;
+        let max = world_to_chunk(max).0;
+        self.chunks.values().filter(move |chunk| {
+            (0..3).all(|axis| (min[axis]..=max[axis]).contains(&chunk.pos[axis]))
+        })
+    }
+
+    /// Reads back the voxel at world position `pos`, `None` when no chunk contains it. The value is
+    /// from the current buffer as of the next submission
+    pub fn voxel_at_world(&self, ctx: &WgpuContext, pos: &glm::Vec3) -> Option<VoxelReadback> {
+        if self.modified_this_frame {
+            panic!("voxel_at_world called before finalize_changes_and_start_frame");
+        }
+        let (chunk_pos, local) = world_to_chunk(pos);
+        let chunk = self.chunks.get(&chunk_pos)?;
+        let buffer = ctx.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("chunk_manager voxel_buffer"),
+            size: size_of::<u32>() as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let mut encoder = ctx
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("chunk_manager voxel_at_world"),
+            });
+        self.datastore.download_voxel(
+            &mut encoder,
+            (chunk.offset(), self.which),
+            local,
+            &buffer,
+            0,
+        );
+        ctx.queue.submit([encoder.finish()]);
+
+        let mapped = Arc::new(AtomicBool::new(false));
+        let failed = Arc::new(AtomicBool::new(false));
+        let mapped_clone = mapped.clone();
+        let failed_clone = failed.clone();
+        buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| match result {
+                Ok(_) => mapped_clone.store(true, Ordering::Release),
+                Err(e) => {
+                    log::error!("Failed to map voxel buffer: {:?}", e);
+                    failed_clone.store(true, Ordering::Release);
+                }
+            });
+
+        Some(VoxelReadback {
+            buffer,
+            mapped,
+            failed,
+        })
+    }
+
     pub fn num_offsets(&self) -> u32 {
         if self.modified_this_frame {
             panic!("total_offsets called before finalize_changes_and_start_frame");
@@ -141,35 +520,300 @@ impl ChunkManager {
         self.shared_buffer_offset_tracker.offset_to_index.len() as u32
     }
 
-    pub fn upload_chunk_data(&self, ctx: &WgpuContext, pos: glm::IVec3, data: &[u32]) {
+    pub fn offset_positions(&self) -> &[glm::IVec3] {
+        if self.modified_this_frame {
+            panic!("offset_positions called before finalize_changes_and_start_frame");
+        }
+        &self.offset_positions
+    }
+
+    pub fn download_chunks(&self, ctx: &WgpuContext) -> ChunkDownload {
+        if self.modified_this_frame {
+            panic!("download_chunks called before finalize_changes_and_start_frame");
+        }
+        let chunk_bytes = (CHUNK_VOLUME * size_of::<u32>()) as u64;
+        let batch_chunks = DOWNLOAD_BATCH_CHUNKS
+            .min((ctx.device.limits().max_buffer_size / chunk_bytes) as usize)
+            .max(1);
+        let mut encoder = ctx
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("chunk_manager download_chunks"),
+            });
+        let chunks = self.chunks.values().collect::<Vec<_>>();
+        let batches = chunks
+            .chunks(batch_chunks)
+            .map(|batch| {
+                let buffer = ctx.device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("chunk_manager download_buffer"),
+                    size: chunk_bytes * batch.len() as u64,
+                    usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                    mapped_at_creation: false,
+                });
+                for (i, chunk) in batch.iter().enumerate() {
+                    self.datastore.download(
+                        &mut encoder,
+                        (chunk.offset(), self.which),
+                        &buffer,
+                        i as u64 * chunk_bytes,
+                    );
+                }
+                (buffer, batch.iter().map(|chunk| chunk.pos).collect())
+            })
+            .collect::<Vec<(wgpu::Buffer, Vec<_>)>>();
+        ctx.queue.submit([encoder.finish()]);
+
+        let mapped = Arc::new(AtomicUsize::new(0));
+        let failed = Arc::new(AtomicBool::new(false));
+        for (buffer, _) in &batches {
+            let mapped = mapped.clone();
+            let failed = failed.clone();
+            buffer
+                .slice(..)
+                .map_async(wgpu::MapMode::Read, move |result| match result {
+                    Ok(_) => {
+                        mapped.fetch_add(1, Ordering::AcqRel);
+                    }
+                    Err(e) => {
+                        log::error!("Failed to map chunk download buffer: {:?}", e);
+                        failed.store(true, Ordering::Release);
+                    }
+                });
+        }
+
+        ChunkDownload {
+            batches,
+            empty: self.empty_chunks.iter().copied().collect(),
+            mapped,
+            failed,
+        }
+    }
+
+    pub fn snapshot_chunks(
+        &self,
+        ctx: &WgpuContext,
+        encoder: &mut wgpu::CommandEncoder,
+    ) -> ChunkSnapshot {
+        if self.modified_this_frame {
+            panic!("snapshot_chunks called before finalize_changes_and_start_frame");
+        }
+        let chunk_bytes = (CHUNK_VOLUME * size_of::<u32>()) as u64;
+        let buffer = ctx.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("chunk_manager snapshot_buffer"),
+            size: chunk_bytes * self.chunks.len().max(1) as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let mut positions = Vec::with_capacity(self.chunks.len());
+        for (i, chunk) in self.chunks.values().enumerate() {
+            self.datastore.download(
+                encoder,
+                (chunk.offset(), self.which),
+                &buffer,
+                i as u64 * chunk_bytes,
+            );
+            positions.push(chunk.pos);
+        }
+        ChunkSnapshot { buffer, positions }
+    }
+
+    /// Copies one chunk of the current buffer into `buffer` at `buffer_offset`, tightly packed
+    pub fn copy_chunk_to_buffer(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        chunk: &Chunk,
+        buffer: &wgpu::Buffer,
+        buffer_offset: u64,
+    ) {
+        if self.modified_this_frame {
+            panic!("copy_chunk_to_buffer called before finalize_changes_and_start_frame");
+        }
+        self.datastore
+            .download(encoder, (chunk.offset(), self.which), buffer, buffer_offset);
+    }
+
+    /// Copies a snapshot back into the current buffer, chunks that no longer exist are skipped
+    pub fn restore_snapshot(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        snapshot: &ChunkSnapshot,
+    ) {
+        if self.modified_this_frame {
+            panic!("restore_snapshot called before finalize_changes_and_start_frame");
+        }
+        let chunk_bytes = (CHUNK_VOLUME * size_of::<u32>()) as u64;
+        for (i, pos) in snapshot.positions.iter().enumerate() {
+            if let Some(chunk) = self.chunks.get(pos) {
+                self.datastore.restore(
+                    encoder,
+                    (chunk.offset(), self.which),
+                    &snapshot.buffer,
+                    i as u64 * chunk_bytes,
+                );
+            }
+        }
+        self.sim_version += 1;
+        self.mark_all_modified();
+    }
+
+    /// For passes that write a chunk on the GPU, drops what's cached from its voxels like an upload
+    pub fn mark_chunk_written(&mut self, pos: &glm::IVec3) {
+        if let Some(chunk) = self.chunks.get_mut(pos) {
+            chunk.version += 1;
+            self.last_modified.insert(*pos, self.tick);
+        }
+    }
+
+    /// For passes that rewrite every chunk on the GPU, so anything cached from the old voxels is
+    /// dropped like after an upload
+    pub fn mark_all_written(&mut self) {
+        for chunk in self.chunks.values_mut() {
+            chunk.version += 1;
+        }
+        self.sim_version += 1;
+        self.mark_all_modified();
+    }
+
+    /// Counts every chunk as changed at the current tick, for GPU passes that write cells without
+    /// reporting which chunks they touched
+    pub fn mark_all_modified(&mut self) {
+        let tick = self.tick;
+        self.last_modified
+            .extend(self.chunks.keys().map(|pos| (*pos, tick)));
+    }
+
+    /// Records that the simulation changed the chunk at `pos` by `tick`
+    pub fn record_modified(&mut self, pos: &glm::IVec3, tick: u64) {
+        if let Some(last_modified) = self.last_modified.get_mut(pos) {
+            // Reports from before stepping back can be past the current tick
+            *last_modified = (*last_modified).max(tick.min(self.tick));
+        }
+    }
+
+    /// Tick the chunk at `pos` last changed at, `None` for positions without a chunk
+    pub fn last_modified(&self, pos: &glm::IVec3) -> Option<u64> {
+        self.last_modified.get(pos).copied()
+    }
+
+    /// Chunks that changed after `tick`
+    pub fn modified_since(&self, tick: u64) -> impl Iterator<Item = (&glm::IVec3, u64)> + '_ {
+        self.last_modified
+            .iter()
+            .filter(move |(_, modified)| **modified > tick)
+            .map(|(pos, modified)| (pos, *modified))
+    }
+
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    /// Follows the simulation tick, changes recorded after it are moved back to it when the
+    /// simulation went back in time
+    pub fn set_tick(&mut self, tick: u64) {
+        if tick < self.tick {
+            for modified in self.last_modified.values_mut() {
+                *modified = (*modified).min(tick);
+            }
+        }
+        self.tick = tick;
+    }
+
+    pub fn upload_chunk_data(&mut self, ctx: &WgpuContext, pos: glm::IVec3, data: &[u32]) {
         if self.modified_this_frame {
             panic!("upload_chunk_data called before finalize_changes_and_start_frame");
         }
         let chunk = self
             .chunks
-            .get(&pos)
+            .get_mut(&pos)
             .unwrap_or_else(|| panic!("chunk {:?} not found", pos));
+        chunk.version += 1;
+        self.last_modified.insert(pos, self.tick);
         self.datastore
             .upload_chunk_data(ctx, (chunk.offset(), self.which), data);
     }
 
+    /// Uploads `data` over the next frames along with the rest of the queue, so loading many
+    /// chunks at once doesn't stall a single frame. Chunks removed in the meantime are skipped.
+    pub fn queue_chunk_upload(&mut self, pos: glm::IVec3, data: Vec<u32>) {
+        // The upload overwrites the whole chunk, clearing it first would be wasted
+        if self.materialize(&pos) {
+            self.pending_clears.remove(&pos);
+        }
+        self.pending_uploads.push_back((pos, data));
+        self.upload_batch += 1;
+    }
+
+    /// Chunks waiting in the upload queue
+    pub fn queued_uploads(&self) -> usize {
+        self.pending_uploads.len()
+    }
+
+    /// Writes queued chunks until the per frame budget is used up, highest priority first
+    pub fn process_uploads(&mut self, ctx: &WgpuContext) {
+        self.priority
+            .sort(self.pending_uploads.make_contiguous(), |(pos, _)| *pos);
+        let chunk_bytes = (CHUNK_VOLUME * size_of::<u32>()) as f32;
+        let budget = (self.upload_budget_mib * 1024.0 * 1024.0 / chunk_bytes).max(1.0) as usize;
+        for _ in 0..budget {
+            let Some((pos, data)) = self.pending_uploads.pop_front() else {
+                break;
+            };
+            if self.chunks.contains_key(&pos) {
+                self.upload_chunk_data(ctx, pos, &data);
+            }
+        }
+        if self.pending_uploads.is_empty() {
+            self.upload_batch = 0;
+        }
+    }
+
+    /// Chunks uploaded and queued in total since the queue was last empty, `None` when it is
+    pub fn upload_progress(&self) -> Option<(usize, usize)> {
+        (!self.pending_uploads.is_empty()).then(|| {
+            (
+                self.upload_batch - self.pending_uploads.len(),
+                self.upload_batch,
+            )
+        })
+    }
+
     pub fn finalize_changes_and_start_frame(&mut self, ctx: &WgpuContext) {
+        if let Some(chunks_per_group) = self.requested_chunks_per_group.take() {
+            if let Err(e) = self.set_chunks_per_group(ctx, chunks_per_group) {
+                log::error!("Could not resize the grid groups: {}", e);
+            }
+        }
+        if self.compaction_requested {
+            // The removed chunks' data has to be moved before the groups past it go away
+            self.modified_this_frame = true;
+        }
         if !self.modified_this_frame {
             return;
         }
 
         // Process the copies incurred by chunk removals first
-        let mut copies = Vec::new();
-        for chunk in self.chunks.values_mut() {
-            if let Some(residency) = &mut chunk.residency {
-                let offset = self
-                    .shared_buffer_offset_tracker
-                    .get_offset(residency.index);
-                copies.push((0, offset));
+        let tracker = &self.shared_buffer_offset_tracker;
+        let copies = self
+            .chunks
+            .par_iter_mut()
+            .filter_map(|(pos, chunk)| {
+                let residency = chunk.residency.as_mut()?;
+                let offset = tracker.get_offset(residency.index);
+                if offset == residency.offset {
+                    return None;
+                }
+                let old_offset = residency.offset;
                 residency.offset = offset;
-                self.atlas_updates.insert(chunk.pos);
-            }
-        }
+                Some((*pos, old_offset, offset))
+            })
+            .collect::<Vec<_>>();
+        self.atlas_updates
+            .extend(copies.iter().map(|(pos, _, _)| *pos));
+        // Changes the simulation reported at the old offset that weren't read back yet are lost
+        let tick = self.tick;
+        self.last_modified
+            .extend(copies.iter().map(|(pos, _, _)| (*pos, tick)));
+        self.relocations += copies.len();
 
         if !copies.is_empty() {
             let mut encoder = ctx
@@ -177,13 +821,14 @@ impl ChunkManager {
                 .create_command_encoder(&wgpu::CommandEncoderDescriptor {
                     label: Some("chunk_manager finalize_changes_and_start_frame"),
                 });
-            for (old_offset, offset) in copies {
+            for (_, old_offset, offset) in copies {
                 self.datastore
                     .copy(&mut encoder, (old_offset, self.which), (offset, self.which));
             }
             ctx.queue.submit([encoder.finish()]);
         }
 
+        self.drop_chunks_over_capacity(ctx);
         for chunk in self.chunks.values_mut() {
             if chunk.residency.is_none() {
                 let index = self.shared_buffer_offset_tracker.add_and_get_index();
@@ -192,10 +837,37 @@ impl ChunkManager {
             }
         }
 
-        self.datastore.ensure_size(
-            ctx,
-            self.shared_buffer_offset_tracker.offset_to_index.len() as u32,
-        );
+        let num_offsets = self.shared_buffer_offset_tracker.offset_to_index.len() as u32;
+        if num_offsets as usize > self.max_chunks() {
+            // Bigger groups instead of more of them, the bind group only has room for so many
+            let chunks_per_group = num_offsets.div_ceil(MAX_GRID_GROUPS).next_power_of_two();
+            log::warn!(
+                "{} chunks don't fit {} grid groups, growing them to {} chunks each",
+                num_offsets,
+                MAX_GRID_GROUPS,
+                chunks_per_group
+            );
+            self.datastore
+                .set_chunks_per_group(ctx, chunks_per_group, num_offsets);
+        }
+        self.datastore.ensure_size(ctx, num_offsets);
+
+        self.offset_positions.clear();
+        self.offset_positions
+            .resize(num_offsets as usize, glm::IVec3::zeros());
+        for chunk in self.chunks.values() {
+            self.offset_positions[chunk.offset() as usize] = chunk.pos;
+        }
+
+        if !self.pending_clears.is_empty() {
+            let zeros = vec![0u32; CHUNK_VOLUME];
+            for pos in self.pending_clears.drain() {
+                if let Some(chunk) = self.chunks.get(&pos) {
+                    self.datastore
+                        .upload_chunk_data(ctx, (chunk.offset(), self.which), &zeros);
+                }
+            }
+        }
 
         for pos in self.atlas_updates.drain() {
             match self.chunks.get(&pos) {
@@ -208,9 +880,26 @@ impl ChunkManager {
             }
         }
 
+        if std::mem::take(&mut self.compaction_requested) {
+            let freed = self.datastore.shrink(ctx, num_offsets);
+            log::info!(
+                "Compacted the chunk storage, freed {} grid groups after {} relocations",
+                freed,
+                self.relocations
+            );
+            self.relocations = 0;
+        }
+
+        self.layout_version += 1;
         self.modified_this_frame = false;
     }
 
+    /// Changes whenever the chunk at any offset changes, for caching things built from
+    /// [`Self::offset_positions`]
+    pub fn layout_version(&self) -> u64 {
+        self.layout_version
+    }
+
     pub fn offset_to_group_and_origin_x(&self, offset: u32) -> (u32, u32) {
         (
             offset / self.datastore.chunks_per_group(),
@@ -230,11 +919,139 @@ impl ChunkManager {
         self.datastore.chunks_per_group()
     }
 
+    /// Rebuilds the grid groups with `chunks_per_group` chunks each, keeping every chunk's voxels.
+    /// Fails when the chunks wouldn't fit or the size isn't a power of two within the texture
+    /// limit.
+    pub fn set_chunks_per_group(
+        &mut self,
+        ctx: &WgpuContext,
+        chunks_per_group: u32,
+    ) -> Result<(), String> {
+        let max = ChunkDatastore::max_chunks_per_group(ctx);
+        if !chunks_per_group.is_power_of_two() || chunks_per_group > max {
+            return Err(format!(
+                "{} chunks per group isn't a power of two up to {}",
+                chunks_per_group, max
+            ));
+        }
+        self.finalize_changes_and_start_frame(ctx);
+        let num_offsets = self.num_offsets();
+        if num_offsets > MAX_GRID_GROUPS * chunks_per_group {
+            return Err(format!(
+                "{} chunks need groups of at least {}",
+                num_offsets,
+                num_offsets.div_ceil(MAX_GRID_GROUPS).next_power_of_two()
+            ));
+        }
+        if chunks_per_group != self.chunks_per_group() {
+            self.datastore
+                .set_chunks_per_group(ctx, chunks_per_group, num_offsets);
+        }
+        Ok(())
+    }
+
+    /// Grid group size picker, takes effect on the next frame
+    pub fn ui(&mut self, ui: &mut egui::Ui, ctx: &WgpuContext) {
+        ui.collapsing("Chunk storage", |ui| {
+            let current = self.chunks_per_group();
+            let max = ChunkDatastore::max_chunks_per_group(ctx);
+            let group_bytes = (CHUNK_VOLUME * 2 * size_of::<u32>()) as u64;
+            let mut selected = current;
+            egui::ComboBox::from_label("Chunks per group")
+                .selected_text(current.to_string())
+                .show_ui(ui, |ui| {
+                    for size in (0..=max.ilog2()).map(|shift| 1u32 << shift) {
+                        ui.selectable_value(&mut selected, size, size.to_string());
+                    }
+                });
+            if selected != current {
+                // This frame's commands are already recorded against the old groups
+                self.requested_chunks_per_group = Some(selected);
+            }
+            ui.label(format!(
+                "{} groups of {:.1} MiB, room for {} chunks",
+                self.datastore.num_grid_groups(),
+                (group_bytes * self.chunks_per_group() as u64) as f64 / (1024.0 * 1024.0),
+                self.max_chunks()
+            ));
+            ui.add(
+                egui::Slider::new(&mut self.upload_budget_mib, 1.0..=256.0)
+                    .logarithmic(true)
+                    .suffix(" MiB")
+                    .text("Upload budget per frame"),
+            )
+            .on_hover_text("Loading a world spreads its chunks over frames to stay below this");
+            if let Some(error) = &self.capacity_error {
+                ui.colored_label(ui.visuals().error_fg_color, error);
+            }
+            ui.separator();
+            self.compaction_ui(ui, group_bytes);
+        });
+    }
+
+    // Counts taken from the tracker rather than `num_offsets`, chunks may have changed this frame
+    fn compaction_ui(&mut self, ui: &mut egui::Ui, group_bytes: u64) {
+        let offsets = self.shared_buffer_offset_tracker.offset_to_index.len() as u32;
+        let groups = self.datastore.num_grid_groups();
+        let slots = groups as u32 * self.chunks_per_group();
+        let needed_groups = offsets.div_ceil(self.chunks_per_group()).max(1) as usize;
+        let unused_groups = groups.saturating_sub(needed_groups);
+        egui::Grid::new("chunk_storage_stats").show(ui, |ui| {
+            ui.label("Resident chunks");
+            ui.label(self.chunks.len().to_string());
+            ui.end_row();
+            ui.label("Offsets in use");
+            ui.label(format!("{} of {} slots", offsets, slots));
+            ui.end_row();
+            ui.label("Empty chunks")
+                .on_hover_text("Chunks without cells that were dropped from the grid groups");
+            ui.label(self.empty_chunks.len().to_string());
+            ui.end_row();
+            ui.label("Relocations").on_hover_text(
+                "Chunks moved to fill the offsets of removed ones since the last compaction",
+            );
+            ui.label(self.relocations.to_string());
+            ui.end_row();
+            ui.label("Unused groups");
+            ui.label(format!(
+                "{} of {}, {:.1} MiB",
+                unused_groups,
+                groups,
+                (group_bytes * self.chunks_per_group() as u64 * unused_groups as u64) as f64
+                    / (1024.0 * 1024.0)
+            ));
+            ui.end_row();
+        });
+        ui.add_enabled_ui(!self.compaction_requested, |ui| {
+            if ui
+                .button("Compact now")
+                .on_hover_text("Frees the grid groups no chunk is in anymore, on the next frame")
+                .clicked()
+            {
+                self.compaction_requested = true;
+            }
+        });
+    }
+
     pub fn which(&self) -> u32 {
         self.which
     }
 
     pub fn advance_which(&mut self, amount: u32) {
         self.which = (self.which + amount) % 2;
+        self.sim_version += 1;
+    }
+
+    pub fn sim_version(&self) -> u64 {
+        self.sim_version
+    }
+
+    /// Bounds of the live cells as of the last reduction, `None` when the world is empty
+    pub fn live_bounds(&self) -> Option<&LiveBounds> {
+        self.live_bounds.as_ref()
+    }
+
+    pub fn set_live_bounds(&mut self, live_bounds: Option<LiveBounds>) {
+        self.live_bounds = live_bounds;
     }
 }
@@ -0,0 +1,112 @@
+use std::cmp::Reverse;
+
+use nalgebra_glm as glm;
+
+use crate::chunk::CHUNK_SIZE;
+
+/// Decides which chunks are generated, uploaded and meshed first when there are more than fit in a
+/// frame. Chunks score lower the further they are from the camera and get a bonus while they
+/// overlap the view frustum, the highest score goes first.
+pub struct ChunkPriority {
+    pub enabled: bool,
+    // Score lost per chunk of distance from the camera
+    pub distance_weight: f32,
+    // Score gained by chunks that are at least partly in view
+    pub frustum_bonus: f32,
+    // Chunks meshed per frame at most, 0 meshes every stale chunk. Chunks over the budget wait
+    // for a later frame, while the simulation runs the ones out of view may not update at all.
+    pub mesh_budget: u32,
+    eye: glm::Vec3,
+    // Left, right, bottom and top planes of the view frustum, pointing inwards
+    planes: [glm::Vec4; 4],
+}
+
+impl ChunkPriority {
+    pub fn new() -> Self {
+        Self {
+            enabled: true,
+            distance_weight: 1.0,
+            frustum_bonus: 8.0,
+            mesh_budget: 0,
+            eye: glm::Vec3::zeros(),
+            planes: [glm::Vec4::zeros(); 4],
+        }
+    }
+
+    /// Follows the camera, `view_proj` is the matrix the frame is drawn with
+    pub fn update(&mut self, eye: &glm::Vec3, view_proj: &glm::Mat4) {
+        self.eye = *eye;
+        let row = |i: usize| {
+            glm::vec4(
+                view_proj[(i, 0)],
+                view_proj[(i, 1)],
+                view_proj[(i, 2)],
+                view_proj[(i, 3)],
+            )
+        };
+        // Near and far are left out, the far plane is at infinity anyway
+        self.planes = [
+            row(3) + row(0),
+            row(3) - row(0),
+            row(3) + row(1),
+            row(3) - row(1),
+        ];
+    }
+
+    pub fn in_frustum(&self, pos: &glm::IVec3) -> bool {
+        let min = pos.cast::<f32>() * CHUNK_SIZE as f32;
+        let max = min.add_scalar(CHUNK_SIZE as f32);
+        self.planes.iter().all(|plane| {
+            // The corner furthest along the plane normal
+            let corner = glm::vec3(
+                if plane.x >= 0.0 { max.x } else { min.x },
+                if plane.y >= 0.0 { max.y } else { min.y },
+                if plane.z >= 0.0 { max.z } else { min.z },
+            );
+            plane.xyz().dot(&corner) + plane.w >= 0.0
+        })
+    }
+
+    pub fn score(&self, pos: &glm::IVec3) -> f32 {
+        let center = (pos.cast::<f32>() + glm::vec3(0.5, 0.5, 0.5)) * CHUNK_SIZE as f32;
+        let distance = glm::distance(&center, &self.eye) / CHUNK_SIZE as f32;
+        let bonus = if self.in_frustum(pos) {
+            self.frustum_bonus
+        } else {
+            0.0
+        };
+        bonus - distance * self.distance_weight
+    }
+
+    /// Orders `items` highest priority first, leaves them as they are when disabled
+    pub fn sort<T>(&self, items: &mut [T], pos: impl Fn(&T) -> glm::IVec3) {
+        if !self.enabled {
+            return;
+        }
+        // Scores in 1/1024ths are plenty to tell chunks apart
+        items.sort_by_cached_key(|item| Reverse((self.score(&pos(item)) * 1024.0) as i64));
+    }
+
+    /// How many of the stale chunks to mesh this frame
+    pub fn meshes_this_frame(&self, stale: usize) -> usize {
+        match self.mesh_budget {
+            0 => stale,
+            budget => stale.min(budget as usize),
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.checkbox(&mut self.enabled, "Chunks in front of the camera first")
+            .on_hover_text("Orders world generation, uploads and meshing");
+        ui.add_enabled_ui(self.enabled, |ui| {
+            ui.add(
+                egui::Slider::new(&mut self.distance_weight, 0.0..=4.0)
+                    .text("Distance weight")
+                    .suffix(" per chunk"),
+            );
+            ui.add(egui::Slider::new(&mut self.frustum_bonus, 0.0..=64.0).text("In view bonus"));
+        });
+        ui.add(egui::Slider::new(&mut self.mesh_budget, 0..=512).text("Chunks meshed per frame"))
+            .on_hover_text("0 meshes every chunk that changed");
+    }
+}
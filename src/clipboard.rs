@@ -0,0 +1,96 @@
+use std::collections::HashSet;
+
+use nalgebra_glm as glm;
+
+use crate::chunk::CHUNK_SIZE;
+
+// Patterns are whole saved worlds, stamping more cells than this at once is refused
+const MAX_CELLS: usize = 1 << 20;
+
+/// The live cells of a pattern, held on the CPU for stamping. Positions are relative to the
+/// corner of the pattern's bounding box.
+pub struct Clipboard {
+    pub name: String,
+    cells: Vec<(glm::IVec3, u32)>,
+    size: glm::IVec3,
+}
+
+impl Clipboard {
+    /// Gathers the non-empty cells of `chunks` as saved in a pattern
+    pub fn from_chunks(name: &str, chunks: &[(glm::IVec3, Vec<u32>)]) -> Result<Self, String> {
+        let size = CHUNK_SIZE as usize;
+        let mut cells = Vec::new();
+        for (pos, data) in chunks {
+            let origin = *pos * CHUNK_SIZE as i32;
+            for (i, &value) in data.iter().enumerate() {
+                if value == 0 {
+                    continue;
+                }
+                if cells.len() == MAX_CELLS {
+                    return Err(format!("more than {} cells", MAX_CELLS));
+                }
+                let local = glm::vec3(i % size, (i / size) % size, i / (size * size));
+                cells.push((origin + local.cast::<i32>(), value));
+            }
+        }
+        if cells.is_empty() {
+            return Err("the pattern is empty".to_owned());
+        }
+        let min = cells
+            .iter()
+            .fold(cells[0].0, |min, (pos, _)| glm::min2(&min, pos));
+        let max = cells
+            .iter()
+            .fold(cells[0].0, |max, (pos, _)| glm::max2(&max, pos));
+        for (pos, _) in &mut cells {
+            *pos -= min;
+        }
+        Ok(Self {
+            name: name.to_owned(),
+            cells,
+            size: max - min + glm::vec3(1, 1, 1),
+        })
+    }
+
+    pub fn cells(&self) -> &[(glm::IVec3, u32)] {
+        &self.cells
+    }
+
+    pub fn size(&self) -> glm::IVec3 {
+        self.size
+    }
+
+    /// Quarter turn about `axis`, the cells stay inside the bounding box starting at the origin
+    pub fn rotate(&mut self, axis: usize) {
+        let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+        for (pos, _) in &mut self.cells {
+            let (pu, pv) = (pos[u], pos[v]);
+            pos[u] = self.size[v] - 1 - pv;
+            pos[v] = pu;
+        }
+        let size = self.size;
+        self.size[u] = size[v];
+        self.size[v] = size[u];
+    }
+
+    /// Faces of the cells that aren't covered by another cell, as (cell, face, value). Face
+    /// `2 * axis` looks down the axis, `2 * axis + 1` up it.
+    pub fn exposed_faces(&self) -> Vec<(glm::IVec3, u32, u32)> {
+        let occupied = self
+            .cells
+            .iter()
+            .map(|(pos, _)| *pos)
+            .collect::<HashSet<_>>();
+        let mut faces = Vec::new();
+        for &(pos, value) in &self.cells {
+            for face in 0..6u32 {
+                let mut neighbor = pos;
+                neighbor[face as usize / 2] += if face & 1 == 1 { 1 } else { -1 };
+                if !occupied.contains(&neighbor) {
+                    faces.push((pos, face, value));
+                }
+            }
+        }
+        faces
+    }
+}
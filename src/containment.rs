@@ -0,0 +1,171 @@
+use nalgebra_glm as glm;
+use rand::{thread_rng, Rng};
+
+use crate::chunk::{CHUNK_SIZE, CHUNK_VOLUME};
+use crate::chunk_manager::ChunkManager;
+use crate::gpu_stage::overlay::Overlay;
+use crate::theme;
+
+// Segments per circle when drawing the sphere
+const CIRCLE_SEGMENTS: usize = 48;
+
+/// Values match the CONTAIN_ constants in simulate.wgsl
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ContainmentShape {
+    Off = 0,
+    Sphere = 1,
+    Box = 2,
+}
+
+/// Kills every cell outside of a sphere or box each tick, so growing patterns stay put instead of
+/// spreading into ever more chunks. The test runs on cell centers in world voxel coordinates.
+pub struct Containment {
+    pub shape: ContainmentShape,
+    pub center: glm::Vec3,
+    pub radius: f32,
+    pub half_extents: glm::Vec3,
+    pub visible: bool,
+    // Chance of a cell inside the boundary being alive after seeding
+    seed_density: f32,
+}
+
+impl Containment {
+    pub fn new() -> Self {
+        Self {
+            shape: ContainmentShape::Off,
+            center: glm::vec3(1.0, 1.0, 1.0) * CHUNK_SIZE as f32,
+            radius: CHUNK_SIZE as f32 * 0.75,
+            half_extents: glm::vec3(1.0, 1.0, 1.0) * CHUNK_SIZE as f32 * 0.75,
+            visible: true,
+            seed_density: 0.1,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.shape != ContainmentShape::Off
+    }
+
+    pub fn contains(&self, pos: &glm::Vec3) -> bool {
+        let d = pos - self.center;
+        match self.shape {
+            ContainmentShape::Off => true,
+            ContainmentShape::Sphere => d.norm_squared() <= self.radius * self.radius,
+            ContainmentShape::Box => (0..3).all(|axis| d[axis].abs() <= self.half_extents[axis]),
+        }
+    }
+
+    /// Box around the boundary, in world voxel coordinates
+    pub fn bounds(&self) -> (glm::Vec3, glm::Vec3) {
+        let extents = match self.shape {
+            ContainmentShape::Box => self.half_extents,
+            _ => glm::vec3(1.0, 1.0, 1.0) * self.radius,
+        };
+        (self.center - extents, self.center + extents)
+    }
+
+    /// Fills the inside of the boundary with random cells, returns how many chunks were queued.
+    /// The chunks it touches are replaced as a whole, so cells outside the boundary in them are
+    /// cleared as well.
+    pub fn seed(&self, chunk_manager: &mut ChunkManager) -> usize {
+        let (min, max) = self.bounds();
+        chunk_manager.materialize_in_aabb(&min, &max);
+        let positions = chunk_manager
+            .chunks_in_aabb(&min, &max)
+            .map(|chunk| chunk.pos)
+            .collect::<Vec<_>>();
+        let mut rng = thread_rng();
+        let size = CHUNK_SIZE as usize;
+        for &pos in &positions {
+            let origin = pos.cast::<f32>() * CHUNK_SIZE as f32;
+            let mut data = vec![0u32; CHUNK_VOLUME];
+            for (i, cell) in data.iter_mut().enumerate() {
+                let local = glm::vec3(i % size, (i / size) % size, i / (size * size));
+                let center = origin + local.cast::<f32>() + glm::vec3(0.5, 0.5, 0.5);
+                if self.contains(&center) && rng.gen::<f32>() < self.seed_density {
+                    *cell = rng.gen::<u32>() | 0xFF000000;
+                }
+            }
+            chunk_manager.queue_chunk_upload(pos, data);
+        }
+        positions.len()
+    }
+
+    pub fn draw(&self, overlay: &Overlay) {
+        if !self.visible {
+            return;
+        }
+        let color = theme::rgba(overlay.theme.containment);
+        match self.shape {
+            ContainmentShape::Off => {}
+            ContainmentShape::Box => {
+                let (min, max) = self.bounds();
+                overlay.cuboid(color, min, max);
+            }
+            ContainmentShape::Sphere => {
+                // A circle around every axis
+                for axis in 0..3 {
+                    let point = |i: usize| {
+                        let angle = i as f32 / CIRCLE_SEGMENTS as f32 * std::f32::consts::TAU;
+                        let mut offset = glm::Vec3::zeros();
+                        offset[(axis + 1) % 3] = angle.cos() * self.radius;
+                        offset[(axis + 2) % 3] = angle.sin() * self.radius;
+                        self.center + offset
+                    };
+                    for i in 0..CIRCLE_SEGMENTS {
+                        overlay.line(color, (point(i), point(i + 1)));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns whether seeding the inside was requested
+    pub fn ui(&mut self, ui: &mut egui::Ui) -> bool {
+        let mut seed = false;
+        ui.collapsing("Containment", |ui| {
+            ui.horizontal(|ui| {
+                ui.radio_value(&mut self.shape, ContainmentShape::Off, "Off");
+                ui.radio_value(&mut self.shape, ContainmentShape::Sphere, "Sphere");
+                ui.radio_value(&mut self.shape, ContainmentShape::Box, "Box");
+            });
+            ui.horizontal(|ui| {
+                ui.label("Center");
+                for axis in 0..3 {
+                    ui.add(egui::DragValue::new(&mut self.center[axis]).speed(0.5));
+                }
+            });
+            match self.shape {
+                ContainmentShape::Box => {
+                    ui.horizontal(|ui| {
+                        ui.label("Half extents");
+                        for axis in 0..3 {
+                            ui.add(
+                                egui::DragValue::new(&mut self.half_extents[axis])
+                                    .speed(0.5)
+                                    .clamp_range(0.5..=4096.0),
+                            );
+                        }
+                    });
+                }
+                _ => {
+                    ui.add(
+                        egui::Slider::new(&mut self.radius, 0.5..=1024.0)
+                            .logarithmic(true)
+                            .text("Radius"),
+                    );
+                }
+            }
+            ui.checkbox(&mut self.visible, "Show in overlay");
+            ui.horizontal(|ui| {
+                ui.add(egui::Slider::new(&mut self.seed_density, 0.0..=1.0).text("Density"));
+                seed = ui
+                    .add_enabled(self.enabled(), egui::Button::new("Seed inside"))
+                    .on_hover_text(
+                        "Fills the boundary with random cells, replacing the chunks it touches",
+                    )
+                    .clicked();
+            });
+        });
+        seed
+    }
+}
@@ -0,0 +1,170 @@
+use nalgebra_glm as glm;
+
+use crate::camera::Camera;
+use crate::settings::Settings;
+
+const IDLE_TIMEOUT_KEY: &str = "demo.idle_timeout";
+
+/// What the game should do after a demo update
+pub enum DemoEvent {
+    Started,
+    Stopped,
+    NextPreset,
+}
+
+struct SavedState {
+    position: glm::Vec3,
+    look: glm::Vec2,
+    paused: bool,
+}
+
+/// Slowly orbits the camera around the world while the simulation runs, either started from the
+/// menu or after the user has been idle for a while
+pub struct DemoMode {
+    // Seconds without input before the demo starts, 0 disables starting automatically
+    pub idle_timeout: f32,
+    // Degrees per second
+    pub orbit_speed: f32,
+    // Seconds between switching rule presets, 0 keeps the current rules
+    pub preset_interval: f32,
+
+    active: bool,
+    started_automatically: bool,
+    start_requested: bool,
+    now: f64,
+    last_activity: f64,
+    next_preset_at: f64,
+    angle: f32,
+    saved: Option<SavedState>,
+}
+
+impl DemoMode {
+    pub fn new(settings: &Settings) -> Self {
+        Self {
+            idle_timeout: settings.get(IDLE_TIMEOUT_KEY).unwrap_or(0.0),
+            orbit_speed: 6.0,
+            preset_interval: 60.0,
+
+            active: false,
+            started_automatically: false,
+            start_requested: false,
+            now: 0.0,
+            last_activity: 0.0,
+            next_preset_at: 0.0,
+            angle: 0.0,
+            saved: None,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    pub fn start(&mut self) {
+        self.start_requested = true;
+    }
+
+    /// Records user input. `explicit` input such as key presses and clicks always ends the demo,
+    /// pointer movement only ends one that started by itself.
+    pub fn notify_input(&mut self, explicit: bool) {
+        self.last_activity = self.now;
+        if self.active && (explicit || self.started_automatically) {
+            self.active = false;
+        }
+    }
+
+    /// Seconds until the idle timeout starts the demo, if it's going to
+    pub fn time_until_start(&self) -> Option<f64> {
+        if self.active || self.idle_timeout <= 0.0 {
+            return None;
+        }
+        Some((self.last_activity + self.idle_timeout as f64 - self.now).max(0.0))
+    }
+
+    /// Advances the orbit to time `now` in seconds. `bounds` is the region to circle around.
+    pub fn update(
+        &mut self,
+        now: f64,
+        camera: &mut Camera,
+        paused: &mut bool,
+        bounds: (glm::Vec3, glm::Vec3),
+    ) -> Option<DemoEvent> {
+        let dt = (now - self.now).max(0.0) as f32;
+        self.now = now;
+
+        if !self.active {
+            if let Some(saved) = self.saved.take() {
+                camera.position = saved.position;
+                camera.look = saved.look;
+                *paused = saved.paused;
+                self.last_activity = now;
+                return Some(DemoEvent::Stopped);
+            }
+
+            let idle = self.time_until_start() == Some(0.0);
+            if !self.start_requested && !idle {
+                return None;
+            }
+            self.started_automatically = !self.start_requested;
+            self.start_requested = false;
+            self.active = true;
+            self.saved = Some(SavedState {
+                position: camera.position,
+                look: camera.look,
+                paused: *paused,
+            });
+            *paused = false;
+            let offset = camera.position - (bounds.0 + bounds.1) * 0.5;
+            self.angle = offset.z.atan2(offset.x).to_degrees();
+            self.next_preset_at = now + self.preset_interval as f64;
+            self.orbit(camera, bounds);
+            return Some(DemoEvent::Started);
+        }
+
+        self.angle = (self.angle + self.orbit_speed * dt) % 360.0;
+        self.orbit(camera, bounds);
+
+        if self.preset_interval > 0.0 && now >= self.next_preset_at {
+            self.next_preset_at = now + self.preset_interval as f64;
+            return Some(DemoEvent::NextPreset);
+        }
+        None
+    }
+
+    fn orbit(&self, camera: &mut Camera, (min, max): (glm::Vec3, glm::Vec3)) {
+        let center = (min + max) * 0.5;
+        let radius = glm::length(&(max - min)).max(1.0);
+        let angle = self.angle.to_radians();
+        camera.position = center + glm::vec3(angle.cos(), 0.4, angle.sin()) * radius;
+        camera.look_at(&center);
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui, settings: &mut Settings) {
+        ui.collapsing("Demo mode", |ui| {
+            let response = ui
+                .add(
+                    egui::Slider::new(&mut self.idle_timeout, 0.0..=3600.0)
+                        .logarithmic(true)
+                        .suffix(" s")
+                        .text("Start after idle"),
+                )
+                .on_hover_text("0 only starts the demo from the menu");
+            if response.drag_released() || (response.changed() && !response.dragged()) {
+                settings.set(IDLE_TIMEOUT_KEY, self.idle_timeout);
+                settings.save();
+            }
+            ui.add(
+                egui::Slider::new(&mut self.orbit_speed, 0.5..=90.0)
+                    .logarithmic(true)
+                    .suffix("°/s")
+                    .text("Orbit speed"),
+            );
+            ui.add(
+                egui::Slider::new(&mut self.preset_interval, 0.0..=600.0)
+                    .suffix(" s")
+                    .text("Preset interval"),
+            )
+            .on_hover_text("Cycles through the saved rule presets, 0 keeps the current rules");
+        });
+    }
+}
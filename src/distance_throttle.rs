@@ -0,0 +1,132 @@
+use nalgebra_glm as glm;
+
+use crate::chunk::CHUNK_SIZE;
+
+// Slowest rate a chunk can be throttled to is every 2^MAX_TICK_SHIFT-th tick
+const MAX_TICK_SHIFT: u32 = 2;
+
+/// Slows down the simulation of chunks far from the camera, to keep huge worlds interactive
+/// without freezing them. Chunks near the focus run every tick, further out the rate halves every
+/// `halving_distance` chunks until it reaches `1 / 2^max_tick_shift`. Chunks that skip a tick keep
+/// their cells, so cells crossing between chunks of different rates may look like they stall.
+pub struct DistanceThrottle {
+    pub enabled: bool,
+    // World position the distances are measured from, follows the camera
+    pub focus: glm::Vec3,
+    // In chunks, from the focus to the chunk center
+    pub full_rate_distance: f32,
+    pub halving_distance: f32,
+    pub max_tick_shift: u32,
+    // Set while the rule updates 2x2x2 blocks. Blocks straddle chunk borders every other tick, a
+    // chunk skipping ticks would step only its half of them and duplicate or lose cells.
+    pub suspended: bool,
+    throttled_chunks: usize,
+}
+
+impl DistanceThrottle {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            focus: glm::Vec3::zeros(),
+            full_rate_distance: 4.0,
+            halving_distance: 4.0,
+            max_tick_shift: MAX_TICK_SHIFT,
+            suspended: false,
+            throttled_chunks: 0,
+        }
+    }
+
+    fn tick_shift_at(&self, distance: f32) -> u32 {
+        if distance <= self.full_rate_distance {
+            return 0;
+        }
+        let halvings = ((distance - self.full_rate_distance) / self.halving_distance).ceil();
+        (halvings as u32).min(self.max_tick_shift)
+    }
+
+    /// A chunk only runs the ticks that are a multiple of `1 << tick_shift`
+    pub fn tick_shift(&self, chunk_pos: &glm::IVec3) -> u32 {
+        if !self.enabled || self.suspended {
+            return 0;
+        }
+        let center = (chunk_pos.cast::<f32>() + glm::vec3(0.5, 0.5, 0.5)) * CHUNK_SIZE as f32;
+        self.tick_shift_at(glm::distance(&center, &self.focus) / CHUNK_SIZE as f32)
+    }
+
+    /// Everything the tick shifts depend on, `None` while no chunk is throttled
+    pub fn shift_inputs(&self) -> Option<(glm::Vec3, f32, f32, u32)> {
+        (self.enabled && !self.suspended).then_some((
+            self.focus,
+            self.full_rate_distance,
+            self.halving_distance,
+            self.max_tick_shift,
+        ))
+    }
+
+    pub fn set_throttled_chunks(&mut self, throttled_chunks: usize) {
+        self.throttled_chunks = throttled_chunks;
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.collapsing("Distance throttling", |ui| {
+            ui.checkbox(&mut self.enabled, "Slow down distant chunks");
+            ui.add(
+                egui::Slider::new(&mut self.full_rate_distance, 0.0..=64.0)
+                    .text("Full rate distance"),
+            );
+            ui.add(
+                egui::Slider::new(&mut self.halving_distance, 0.5..=64.0).text("Halving distance"),
+            );
+            ui.horizontal(|ui| {
+                ui.label("Slowest");
+                for shift in 1..=MAX_TICK_SHIFT {
+                    ui.radio_value(
+                        &mut self.max_tick_shift,
+                        shift,
+                        format!("every {}", 1 << shift),
+                    );
+                }
+            });
+            self.falloff_ui(ui);
+            if self.enabled && self.suspended {
+                ui.label("Off for block rules, every chunk runs every tick");
+            } else if self.enabled {
+                ui.label(format!("{} chunks throttled", self.throttled_chunks));
+            }
+        });
+    }
+
+    // Plot of the tick rate over the distance in chunks
+    fn falloff_ui(&self, ui: &mut egui::Ui) {
+        let max_distance =
+            self.full_rate_distance + self.halving_distance * (self.max_tick_shift as f32 + 1.0);
+        let (rect, response) = ui.allocate_exact_size(
+            egui::vec2(ui.available_width().min(240.0), 60.0),
+            egui::Sense::hover(),
+        );
+        let painter = ui.painter_at(rect);
+        painter.rect_filled(rect, 2.0, ui.visuals().extreme_bg_color);
+
+        let to_screen = |distance: f32, rate: f32| {
+            egui::pos2(
+                rect.left() + rect.width() * distance / max_distance,
+                rect.bottom() - (rect.height() - 4.0) * rate - 2.0,
+            )
+        };
+        let steps = 128;
+        let points = (0..=steps)
+            .map(|i| {
+                let distance = max_distance * i as f32 / steps as f32;
+                to_screen(distance, 1.0 / (1 << self.tick_shift_at(distance)) as f32)
+            })
+            .collect::<Vec<_>>();
+        painter.add(egui::Shape::line(
+            points,
+            egui::Stroke::new(1.5, ui.visuals().widgets.active.fg_stroke.color),
+        ));
+        response.on_hover_text(format!(
+            "Tick rate over the distance from the camera, up to {:.0} chunks",
+            max_distance
+        ));
+    }
+}
@@ -0,0 +1,197 @@
+use crate::game::StageToggles;
+use crate::gpu_stage::meshing_render::Translucency;
+use crate::gpu_stage::simulate::SimulationMode;
+use crate::settings::Settings;
+
+/// The rule the simulation starts with
+#[derive(Copy, Clone)]
+pub struct RuleConfig {
+    pub mode: SimulationMode,
+    // Bit n set when n live neighbors give birth or let a cell survive, for the life modes
+    pub birth_mask: u32,
+    pub survival_mask: u32,
+}
+
+impl Default for RuleConfig {
+    fn default() -> Self {
+        Self {
+            mode: SimulationMode::Spread3d,
+            birth_mask: 1 << 3,
+            survival_mask: (1 << 2) | (1 << 3),
+        }
+    }
+}
+
+/// How the world is drawn at startup
+#[derive(Copy, Clone)]
+pub struct RenderConfig {
+    pub translucency: Translucency,
+    pub sort_faces: bool,
+}
+
+impl Default for RenderConfig {
+    fn default() -> Self {
+        Self {
+            translucency: Translucency::Off,
+            sort_faces: true,
+        }
+    }
+}
+
+/// Everything the engine is set up with before the first frame. Saved in the settings, embedders
+/// and the command line override parts of it through [`EngineConfigBuilder`].
+#[derive(Copy, Clone)]
+pub struct EngineConfig {
+    // Chunks along each axis of the initial world
+    pub world_size: i32,
+    // Chunks side by side in one grid group texture, `None` picks what the device allows
+    pub chunks_per_group: Option<u32>,
+    pub stages: StageToggles,
+    pub rule: RuleConfig,
+    pub render: RenderConfig,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        Self {
+            world_size: 2,
+            chunks_per_group: None,
+            stages: StageToggles::default(),
+            rule: RuleConfig::default(),
+            render: RenderConfig::default(),
+        }
+    }
+}
+
+impl EngineConfig {
+    pub fn builder() -> EngineConfigBuilder {
+        EngineConfigBuilder::default()
+    }
+
+    /// The saved config, with defaults for anything that was never saved
+    pub fn load(settings: &Settings) -> Self {
+        let mut config = Self::default();
+        let get = |key: &str| settings.get::<String>(&format!("engine.{}", key));
+        let get_bool = |key: &str, value: &mut bool| {
+            if let Some(saved) = get(key).and_then(|v| v.parse().ok()) {
+                *value = saved;
+            }
+        };
+
+        if let Some(world_size) = get("world_size").and_then(|v| v.parse().ok()) {
+            config.world_size = world_size;
+        }
+        config.chunks_per_group = get("chunks_per_group").and_then(|v| v.parse().ok());
+
+        let stages = &mut config.stages;
+        get_bool("stages.simulate", &mut stages.simulate);
+        get_bool("stages.meshing", &mut stages.meshing);
+        get_bool("stages.render", &mut stages.render);
+        get_bool("stages.picker", &mut stages.picker);
+        get_bool("stages.overlay", &mut stages.overlay);
+        get_bool("stages.bloom", &mut stages.bloom);
+        get_bool("stages.tonemap", &mut stages.tonemap);
+
+        if let Some(mode) = get("rule.mode") {
+            match SimulationMode::from_key(&mode) {
+                Some(mode) => config.rule.mode = mode,
+                None => log::warn!("Unknown simulation mode {:?}", mode),
+            }
+        }
+        if let Some(mask) = get("rule.birth_mask").and_then(|v| v.parse().ok()) {
+            config.rule.birth_mask = mask;
+        }
+        if let Some(mask) = get("rule.survival_mask").and_then(|v| v.parse().ok()) {
+            config.rule.survival_mask = mask;
+        }
+
+        if let Some(translucency) = get("render.translucency") {
+            match Translucency::from_key(&translucency) {
+                Some(translucency) => config.render.translucency = translucency,
+                None => log::warn!("Unknown translucency {:?}", translucency),
+            }
+        }
+        get_bool("render.sort_faces", &mut config.render.sort_faces);
+        config
+    }
+
+    pub fn save(&self, settings: &mut Settings) {
+        let mut set = |key: &str, value: String| settings.set(&format!("engine.{}", key), value);
+        set("world_size", self.world_size.to_string());
+        // Leaving it empty goes back to picking one for the device
+        set(
+            "chunks_per_group",
+            self.chunks_per_group
+                .map(|n| n.to_string())
+                .unwrap_or_default(),
+        );
+        set("stages.simulate", self.stages.simulate.to_string());
+        set("stages.meshing", self.stages.meshing.to_string());
+        set("stages.render", self.stages.render.to_string());
+        set("stages.picker", self.stages.picker.to_string());
+        set("stages.overlay", self.stages.overlay.to_string());
+        set("stages.bloom", self.stages.bloom.to_string());
+        set("stages.tonemap", self.stages.tonemap.to_string());
+        set("rule.mode", self.rule.mode.key().to_owned());
+        set("rule.birth_mask", self.rule.birth_mask.to_string());
+        set("rule.survival_mask", self.rule.survival_mask.to_string());
+        set(
+            "render.translucency",
+            self.render.translucency.key().to_owned(),
+        );
+        set("render.sort_faces", self.render.sort_faces.to_string());
+        settings.save();
+    }
+}
+
+/// Parts of an [`EngineConfig`] to override, anything left unset comes from the base config
+#[derive(Clone, Default)]
+pub struct EngineConfigBuilder {
+    world_size: Option<i32>,
+    chunks_per_group: Option<u32>,
+    stages: Option<StageToggles>,
+    rule: Option<RuleConfig>,
+    render: Option<RenderConfig>,
+}
+
+impl EngineConfigBuilder {
+    pub fn world_size(mut self, world_size: i32) -> Self {
+        self.world_size = Some(world_size);
+        self
+    }
+
+    pub fn chunks_per_group(mut self, chunks_per_group: u32) -> Self {
+        self.chunks_per_group = Some(chunks_per_group);
+        self
+    }
+
+    pub fn stages(mut self, stages: StageToggles) -> Self {
+        self.stages = Some(stages);
+        self
+    }
+
+    pub fn rule(mut self, rule: RuleConfig) -> Self {
+        self.rule = Some(rule);
+        self
+    }
+
+    pub fn render(mut self, render: RenderConfig) -> Self {
+        self.render = Some(render);
+        self
+    }
+
+    pub fn build(&self) -> EngineConfig {
+        self.build_on(EngineConfig::default())
+    }
+
+    /// `base` with the overrides applied, used to layer them over the saved config
+    pub fn build_on(&self, base: EngineConfig) -> EngineConfig {
+        EngineConfig {
+            world_size: self.world_size.unwrap_or(base.world_size).max(1),
+            chunks_per_group: self.chunks_per_group.or(base.chunks_per_group),
+            stages: self.stages.unwrap_or(base.stages),
+            rule: self.rule.unwrap_or(base.rule),
+            render: self.render.unwrap_or(base.render),
+        }
+    }
+}
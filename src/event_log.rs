@@ -0,0 +1,279 @@
+use std::collections::BTreeMap;
+
+use nalgebra_glm as glm;
+
+use crate::gpu_stage::simulate::Simulate;
+
+const META_PREFIX: &str = "event.";
+
+/// Something that changed the world or how it evolves
+#[derive(Clone, Debug, PartialEq)]
+pub enum EventKind {
+    Seed(u32),
+    // One rule setting as written by `Simulate::write_meta`, without its prefix
+    Rule {
+        key: String,
+        value: String,
+    },
+    Pause,
+    Resume,
+    // A brush sphere, every symmetric copy is its own event
+    Paint {
+        center: glm::Vec3,
+        radius: f32,
+        value: u32,
+    },
+    Pattern(String),
+    Agent(glm::Vec3),
+    // Anything else that rewrote cells, with a description since it can't be replayed
+    Edit(String),
+}
+
+/// An event that happened before the tick `tick` ran
+#[derive(Clone, Debug, PartialEq)]
+pub struct Event {
+    pub tick: u64,
+    pub kind: EventKind,
+}
+
+impl Event {
+    // `tick kind args`, free text runs to the end of the line
+    fn encode(&self) -> String {
+        let args = match &self.kind {
+            EventKind::Seed(seed) => format!("seed {}", seed),
+            EventKind::Rule { key, value } => format!("rule {} {}", key, value),
+            EventKind::Pause => "pause".to_owned(),
+            EventKind::Resume => "resume".to_owned(),
+            EventKind::Paint {
+                center,
+                radius,
+                value,
+            } => format!(
+                "paint {} {} {} {} {:x}",
+                center.x, center.y, center.z, radius, value
+            ),
+            EventKind::Pattern(name) => format!("pattern {}", name),
+            EventKind::Agent(pos) => format!("agent {} {} {}", pos.x, pos.y, pos.z),
+            EventKind::Edit(what) => format!("edit {}", what),
+        };
+        format!("{} {}", self.tick, args.replace(['\n', '\r'], " "))
+    }
+
+    fn decode(value: &str) -> Result<Self, String> {
+        let (tick, rest) = value.split_once(' ').ok_or("missing event")?;
+        let tick = tick
+            .parse()
+            .map_err(|e| format!("bad tick {:?}: {}", tick, e))?;
+        let (kind, args) = rest.split_once(' ').unwrap_or((rest, ""));
+        let floats = |n: usize| {
+            let values = args
+                .split_whitespace()
+                .take(n)
+                .map(|v| v.parse::<f32>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| format!("bad {} {:?}: {}", kind, args, e))?;
+            if values.len() < n {
+                return Err(format!("bad {} {:?}", kind, args));
+            }
+            Ok(values)
+        };
+        let kind = match kind {
+            "seed" => EventKind::Seed(
+                args.parse()
+                    .map_err(|e| format!("bad seed {:?}: {}", args, e))?,
+            ),
+            "rule" => {
+                let (key, value) = args.split_once(' ').unwrap_or((args, ""));
+                EventKind::Rule {
+                    key: key.to_owned(),
+                    value: value.to_owned(),
+                }
+            }
+            "pause" => EventKind::Pause,
+            "resume" => EventKind::Resume,
+            "paint" => {
+                let v = floats(4)?;
+                let value = args.split_whitespace().nth(4).unwrap_or("");
+                EventKind::Paint {
+                    center: glm::vec3(v[0], v[1], v[2]),
+                    radius: v[3],
+                    value: u32::from_str_radix(value, 16)
+                        .map_err(|e| format!("bad paint value {:?}: {}", value, e))?,
+                }
+            }
+            "pattern" => EventKind::Pattern(args.to_owned()),
+            "agent" => {
+                let v = floats(3)?;
+                EventKind::Agent(glm::vec3(v[0], v[1], v[2]))
+            }
+            "edit" => EventKind::Edit(args.to_owned()),
+            _ => return Err(format!("unknown event {:?}", kind)),
+        };
+        Ok(Self { tick, kind })
+    }
+
+    fn describe(&self) -> String {
+        match &self.kind {
+            EventKind::Seed(seed) => format!("Seed {}", seed),
+            EventKind::Rule { key, value } => format!("{} = {}", key, value),
+            EventKind::Pause => "Paused".to_owned(),
+            EventKind::Resume => "Resumed".to_owned(),
+            EventKind::Paint {
+                center,
+                radius,
+                value,
+            } => format!(
+                "Painted {:08x} at {:.1}, {:.1}, {:.1} r {}",
+                value, center.x, center.y, center.z, radius
+            ),
+            EventKind::Pattern(name) => format!("Inserted pattern {}", name),
+            EventKind::Agent(pos) => {
+                format!("Spawned agent at {:.1}, {:.1}, {:.1}", pos.x, pos.y, pos.z)
+            }
+            EventKind::Edit(what) => what.clone(),
+        }
+    }
+}
+
+/// Every edit, rule change and pause with the tick it happened at, stored with world saves. The
+/// first events of a log are the seed and every rule setting, so a run can be reconstructed from
+/// the log as long as it only holds events that can be replayed, which `Edit` ones can't.
+pub struct EventLog {
+    pub recording: bool,
+    events: Vec<Event>,
+    // What the simulation looked like last frame, changes since are recorded
+    last_rules: Option<BTreeMap<String, String>>,
+    last_paused: Option<bool>,
+    // Set until the settings have been recorded once, a loaded log has them already
+    needs_baseline: bool,
+    // Latest seed in the log
+    logged_seed: Option<u32>,
+}
+
+impl EventLog {
+    pub fn new() -> Self {
+        Self {
+            recording: true,
+            events: Vec::new(),
+            last_rules: None,
+            last_paused: None,
+            needs_baseline: true,
+            logged_seed: None,
+        }
+    }
+
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    pub fn record(&mut self, tick: u64, kind: EventKind) {
+        if !self.recording {
+            return;
+        }
+        if let EventKind::Seed(seed) = kind {
+            self.logged_seed = Some(seed);
+        }
+        self.events.push(Event { tick, kind });
+    }
+
+    /// Records what changed about the simulation since the last call, before the frame's ticks
+    /// run. A new log starts with everything.
+    pub fn observe(&mut self, simulate: &Simulate) {
+        let tick = simulate.tick();
+        let baseline = std::mem::take(&mut self.needs_baseline);
+
+        let mut meta = BTreeMap::new();
+        simulate.write_meta(&mut meta);
+        let rules = meta
+            .into_iter()
+            .filter_map(|(key, value)| Some((key.strip_prefix("simulate.")?.to_owned(), value)))
+            .collect::<BTreeMap<_, _>>();
+
+        if self.logged_seed != Some(simulate.seed()) {
+            self.record(tick, EventKind::Seed(simulate.seed()));
+        }
+        for (key, value) in &rules {
+            let changed = match &self.last_rules {
+                Some(last) => last.get(key) != Some(value),
+                None => baseline,
+            };
+            if changed {
+                self.record(
+                    tick,
+                    EventKind::Rule {
+                        key: key.clone(),
+                        value: value.clone(),
+                    },
+                );
+            }
+        }
+        if self.last_paused.is_some_and(|p| p != simulate.paused) {
+            let kind = if simulate.paused {
+                EventKind::Pause
+            } else {
+                EventKind::Resume
+            };
+            self.record(tick, kind);
+        }
+
+        self.last_rules = Some(rules);
+        self.last_paused = Some(simulate.paused);
+    }
+
+    pub fn clear(&mut self) {
+        self.events.clear();
+        self.last_rules = None;
+        self.needs_baseline = true;
+        self.logged_seed = None;
+    }
+
+    pub fn write_meta(&self, meta: &mut BTreeMap<String, String>) {
+        for (i, event) in self.events.iter().enumerate() {
+            // Padded so the keys sort in order
+            meta.insert(format!("{}{:06}", META_PREFIX, i), event.encode());
+        }
+    }
+
+    /// Replaces the log with the one written by `write_meta`, skipping invalid entries. The
+    /// settings the world loads with are taken as they are rather than as changes.
+    pub fn read_meta(&mut self, meta: &BTreeMap<String, String>) {
+        self.events = meta
+            .iter()
+            .filter(|(key, _)| key.starts_with(META_PREFIX))
+            .filter_map(|(key, value)| match Event::decode(value) {
+                Ok(event) => Some(event),
+                Err(e) => {
+                    log::warn!("Skipping {}: {}", key, e);
+                    None
+                }
+            })
+            .collect();
+        self.last_rules = None;
+        self.needs_baseline = false;
+        self.logged_seed = self.events.iter().rev().find_map(|e| match e.kind {
+            EventKind::Seed(seed) => Some(seed),
+            _ => None,
+        });
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.collapsing("Event log", |ui| {
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut self.recording, "Record");
+                if ui.button("Clear").clicked() {
+                    self.clear();
+                }
+                ui.label(format!("{} events", self.events.len()));
+            });
+            let row_height = ui.text_style_height(&egui::TextStyle::Body);
+            egui::ScrollArea::vertical()
+                .max_height(240.0)
+                .stick_to_bottom(true)
+                .show_rows(ui, row_height, self.events.len(), |ui, rows| {
+                    for event in &self.events[rows] {
+                        ui.label(format!("{:>8}  {}", event.tick, event.describe()));
+                    }
+                });
+        });
+    }
+}
@@ -0,0 +1,231 @@
+use std::collections::VecDeque;
+
+use wgpu::*;
+
+use crate::gpu_stage::tonemap::Tonemap;
+use crate::settings::Settings;
+use crate::storage;
+use crate::thumbnail::ThumbnailCapture;
+use crate::util::TextureAndView;
+use crate::wgpu_context::WgpuContext;
+
+const SCREENSHOT_DIR: &str = "screenshots";
+const WITHOUT_GUI_KEY: &str = "capture.without_gui";
+
+/// Screenshots of the main view, written as binary PPM images. Without the GUI the tonemapped
+/// scene is drawn once more into a texture of its own, which is the image the GUI is composited
+/// over, so menus and windows stay visible to whoever is operating while the capture is clean.
+/// With the GUI the surface texture is copied once the GUI has been drawn, which needs a surface
+/// that can be copied from.
+pub struct FrameCapture {
+    pub without_gui: bool,
+    requested: bool,
+    // Surface sized, in the surface format the tonemap pipeline draws in
+    target: Option<TextureAndView>,
+    // Copies recorded this frame, mapped after submit, with their size. The flag is set for BGRA
+    // pixels.
+    recorded: Vec<(String, (u32, u32), bool, ThumbnailCapture)>,
+    pending: VecDeque<(String, (u32, u32), bool, ThumbnailCapture)>,
+    next_index: u32,
+}
+
+impl FrameCapture {
+    pub fn new(settings: &Settings) -> Self {
+        // Carries on after the screenshots of earlier sessions instead of overwriting them
+        let next_index = storage::list(SCREENSHOT_DIR)
+            .iter()
+            .filter_map(|name| {
+                name.strip_prefix("screenshot_")?
+                    .strip_suffix(".ppm")?
+                    .parse::<u32>()
+                    .ok()
+            })
+            .max()
+            .map_or(0, |index| index + 1);
+        Self {
+            without_gui: settings.get(WITHOUT_GUI_KEY).unwrap_or(true),
+            requested: false,
+            target: None,
+            recorded: Vec::new(),
+            pending: VecDeque::new(),
+            next_index,
+        }
+    }
+
+    /// Captures the next frame
+    pub fn request(&mut self) {
+        self.requested = true;
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.requested || !self.pending.is_empty()
+    }
+
+    fn surface_copyable(ctx: &WgpuContext) -> bool {
+        ctx.surface_config.usage.contains(TextureUsages::COPY_SRC)
+    }
+
+    /// Whether pixels of `format` are BGRA, None for formats that can't be written as a PPM
+    fn is_bgra(format: TextureFormat) -> Option<bool> {
+        match format {
+            TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => Some(false),
+            TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb => Some(true),
+            _ => {
+                log::warn!("Can't capture {:?} pixels as a screenshot", format);
+                None
+            }
+        }
+    }
+
+    fn next_key(&mut self) -> String {
+        let key = format!("{}/screenshot_{:04}.ppm", SCREENSHOT_DIR, self.next_index);
+        self.next_index += 1;
+        key
+    }
+
+    /// Draws the tonemapped scene into the capture target and copies it out, after tonemap has
+    /// run this frame. Also used with the GUI when the surface can't be copied from.
+    pub fn capture_scene(
+        &mut self,
+        ctx: &WgpuContext,
+        encoder: &mut CommandEncoder,
+        tonemap: &Tonemap,
+    ) {
+        if !self.requested || (!self.without_gui && Self::surface_copyable(ctx)) {
+            return;
+        }
+        if !self.without_gui {
+            log::warn!("The surface can't be copied from, capturing without the GUI");
+        }
+        self.requested = false;
+
+        let (width, height, format) = (
+            ctx.surface_config.width,
+            ctx.surface_config.height,
+            ctx.surface_format,
+        );
+        let Some(bgra) = Self::is_bgra(format) else {
+            return;
+        };
+        let stale = self.target.as_ref().map_or(true, |target| {
+            (
+                target.texture.width(),
+                target.texture.height(),
+                target.texture.format(),
+            ) != (width, height, format)
+        });
+        if stale {
+            let texture = ctx.device.create_texture(&TextureDescriptor {
+                label: Some("frame_capture target"),
+                size: Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format,
+                usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_SRC,
+                view_formats: &[],
+            });
+            let view = texture.create_view(&TextureViewDescriptor::default());
+            self.target = Some(TextureAndView { texture, view });
+        }
+        let target = self.target.as_ref().unwrap();
+
+        {
+            let final_draw_resources = tonemap.final_draw_resources();
+            let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+                label: Some("frame_capture render_pass"),
+                color_attachments: &[Some(RenderPassColorAttachment {
+                    view: &target.view,
+                    resolve_target: None,
+                    ops: Operations {
+                        load: LoadOp::Clear(Color::BLACK),
+                        store: StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            render_pass.set_pipeline(&final_draw_resources.pipeline);
+            render_pass.set_bind_group(0, &final_draw_resources.bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+        let capture = ThumbnailCapture::copy_from(ctx, encoder, &target.texture);
+        let key = self.next_key();
+        self.recorded.push((key, (width, height), bgra, capture));
+    }
+
+    /// Copies the finished surface texture, after the GUI has been drawn into it
+    pub fn capture_surface(
+        &mut self,
+        ctx: &WgpuContext,
+        encoder: &mut CommandEncoder,
+        surface_texture: &Texture,
+    ) {
+        if !self.requested || self.without_gui || !Self::surface_copyable(ctx) {
+            return;
+        }
+        self.requested = false;
+        let Some(bgra) = Self::is_bgra(surface_texture.format()) else {
+            return;
+        };
+        let capture = ThumbnailCapture::copy_from(ctx, encoder, surface_texture);
+        let key = self.next_key();
+        let size = (surface_texture.width(), surface_texture.height());
+        self.recorded.push((key, size, bgra, capture));
+    }
+
+    pub fn after_submit(&mut self) {
+        for (key, size, bgra, capture) in self.recorded.drain(..) {
+            capture.start_map();
+            self.pending.push_back((key, size, bgra, capture));
+        }
+    }
+
+    /// Writes the captures that have been read back, in order
+    pub fn finish(&mut self) {
+        while let Some((key, (width, height), bgra, capture)) = self.pending.front() {
+            let Some(pixels) = capture.try_take() else {
+                return;
+            };
+            let pixels = match pixels {
+                Ok(pixels) => pixels,
+                Err(e) => {
+                    log::warn!("Failed to save screenshot {}: {}", key, e);
+                    self.pending.pop_front();
+                    continue;
+                }
+            };
+            let mut ppm = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+            if *bgra {
+                ppm.extend(pixels.chunks(4).flat_map(|p| [p[2], p[1], p[0]]));
+            } else {
+                ppm.extend(pixels.chunks(4).flat_map(|p| &p[..3]));
+            }
+            match storage::write_bytes(key, &ppm) {
+                Ok(()) => log::info!("Saved screenshot {}", key),
+                Err(e) => log::warn!("Failed to write {}: {}", key, e),
+            }
+            self.pending.pop_front();
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui, settings: &mut Settings) {
+        if ui.button("Screenshot (F12)").clicked() {
+            self.request();
+            ui.close_menu();
+        }
+        if ui
+            .checkbox(&mut self.without_gui, "Screenshots without GUI")
+            .on_hover_text("Capture the rendered scene the menus and windows are drawn over")
+            .changed()
+        {
+            settings.set(WITHOUT_GUI_KEY, self.without_gui);
+            settings.save();
+        }
+    }
+}
@@ -1,96 +1,497 @@
+use std::collections::HashSet;
+#[cfg(feature = "recording")]
+use std::collections::VecDeque;
 use std::rc::Rc;
 use std::sync::Arc;
 
 use egui::Widget;
 use nalgebra_glm as glm;
-use rand::{thread_rng, Rng};
-use winit::event::{ElementState, WindowEvent};
 use winit::event_loop::EventLoopProxy;
-use winit::keyboard::{KeyCode, PhysicalKey};
+use winit::keyboard::KeyCode;
 
-use crate::chunk::Chunk;
-use crate::chunk_manager::ChunkManager;
+use crate::about::About;
+use crate::annotations::Annotations;
+use crate::asset_browser::{AssetAction, AssetBrowser};
+use crate::assets::{self, Asset, AssetKind};
+use crate::autotune::autotune;
+use crate::bookmarks::Bookmarks;
+use crate::camera::{Camera, LookMode, MouseLook};
+use crate::camera_path::CameraPath;
+use crate::chunk::{Chunk, CHUNK_SIZE};
+use crate::chunk_manager::{ChunkDownload, ChunkManager};
+use crate::clipboard::Clipboard;
+use crate::demo_mode::{DemoEvent, DemoMode};
+use crate::engine_config::{EngineConfig, EngineConfigBuilder, RenderConfig, RuleConfig};
+use crate::event_log::{EventKind, EventLog};
+#[cfg(feature = "recording")]
+use crate::frame_capture::FrameCapture;
+#[cfg(feature = "bloom")]
 use crate::gpu_stage::bloom::Bloom;
-use crate::gpu_stage::meshing_render::{Meshing, Render};
+use crate::gpu_stage::brush::{Brush, PlacementKeys};
+use crate::gpu_stage::frame_graph::{FrameGraph, TargetStage};
+use crate::gpu_stage::ground::Ground;
+use crate::gpu_stage::legend::{Legend, SHORTCUT_ROWS};
+use crate::gpu_stage::live_bounds::LiveBoundsReduction;
+use crate::gpu_stage::meshing_render::{Meshing, Render, Translucency};
+use crate::gpu_stage::occupancy::Occupancy;
 use crate::gpu_stage::overlay::Overlay;
+use crate::gpu_stage::pick::Pick;
+#[cfg(feature = "picker")]
 use crate::gpu_stage::picker::Picker;
-use crate::gpu_stage::simulate::Simulate;
+use crate::gpu_stage::resample::{ResampleDirection, WorldResample};
+use crate::gpu_stage::seam_check::SeamCheck;
+use crate::gpu_stage::simulate::{Simulate, SimulationMode};
+use crate::gpu_stage::stamp::Stamp;
 use crate::gpu_stage::tonemap::Tonemap;
+use crate::gpu_stage::world_transform::{Axis, Transform, WorldTransform};
+use crate::importer::Importer;
+use crate::input_event::{InputEvent, MouseButton};
 use crate::key_tracker::KeyTracker;
+use crate::profiler::log_duration;
+use crate::resource_tracker::LeakCheck;
+use crate::rng::{RngStreams, Stream};
+use crate::settings::Settings;
+use crate::startup_screen::StartupScreen;
+use crate::storage;
+use crate::theme::{self, Theme};
+use crate::thumbnail::{ThumbnailCapture, ThumbnailRenderer};
+use crate::tool_window::ToolWindow;
 use crate::user_event::UserEvent;
 use crate::util::RenderTargetInfo;
+use crate::validation;
 use crate::wgpu_context::WgpuContext;
+use crate::workspace::Workspace;
+use crate::world_file;
+use crate::worldgen::WorldGen;
 use crate::FinalDrawResources;
 
+// Recorded frames waiting for readback before the camera path stops advancing
+#[cfg(feature = "recording")]
+const MAX_RECORDED_FRAMES_IN_FLIGHT: usize = 3;
+const LOOK_MODE_KEY: &str = "camera.look_mode";
+const DISTANCE_HUD_KEY: &str = "hud.distance";
+
+/// Which stages run each frame, disabled stages are skipped or bypassed in the target chain
+#[derive(Copy, Clone)]
+pub struct StageToggles {
+    pub simulate: bool,
+    pub meshing: bool,
+    pub render: bool,
+    pub picker: bool,
+    pub overlay: bool,
+    pub bloom: bool,
+    pub tonemap: bool,
+}
+
+impl Default for StageToggles {
+    fn default() -> Self {
+        Self {
+            simulate: true,
+            meshing: true,
+            render: true,
+            picker: true,
+            overlay: true,
+            bloom: true,
+            tonemap: true,
+        }
+    }
+}
+
+impl StageToggles {
+    fn ui(&mut self, ui: &mut egui::Ui, event_loop_proxy: &EventLoopProxy<UserEvent>) {
+        ui.collapsing("Pipeline", |ui| {
+            ui.checkbox(&mut self.simulate, "Simulate");
+            ui.checkbox(&mut self.meshing, "Meshing");
+            ui.checkbox(&mut self.render, "Render");
+            // Stages left out of the build can't be turned on
+            // The picker is only created once first enabled, on the resize this requests
+            if cfg!(feature = "picker") && ui.checkbox(&mut self.picker, "Picker").changed() {
+                let _ = event_loop_proxy.send_event(UserEvent::RequestResize);
+            }
+            if cfg!(feature = "overlay") {
+                ui.checkbox(&mut self.overlay, "Overlay");
+            }
+            // Bloom owns an intermediate target, so the chain has to be rebuilt around it
+            if cfg!(feature = "bloom") && ui.checkbox(&mut self.bloom, "Bloom").changed() {
+                let _ = event_loop_proxy.send_event(UserEvent::RequestResize);
+            }
+            ui.checkbox(&mut self.tonemap, "Tonemap");
+        });
+    }
+}
+
+// A world or pattern save waiting for its chunk data to come back from the GPU
+struct PendingSave {
+    kind: AssetKind,
+    name: String,
+    asset: Asset,
+    download: ChunkDownload,
+}
+
 pub struct Game {
-    position: glm::Vec3,
+    camera: Camera,
+    mouse_look: MouseLook,
     projection: glm::Mat4,
-    look: glm::Vec2,
-    look_sensitivity: f32,
-    speed: f32,
-    fov: f32,
 
     key_tracker: KeyTracker,
     show_debug_window: bool,
     show_render_options: bool,
     show_profiler: bool,
+    show_stats: bool,
+    power_saving: bool,
+    // Holds the picture, a new frame is only produced when the frame step key is pressed while
+    // the UI keeps running
+    frame_step: bool,
+    frame_advance: bool,
+    look_mode: LookMode,
+    // Distance to the surface under the crosshair, shown below it
+    show_distance: bool,
+    show_asset_browser: bool,
+    show_timeline: bool,
+    show_legend: bool,
+    show_about: bool,
+    // Tool windows shown in their own OS window instead
+    detached: HashSet<ToolWindow>,
+    workspace: Workspace,
+    leak_check: LeakCheck,
+
+    about: About,
+    asset_browser: AssetBrowser,
+    importer: Importer,
+    pending_save: Option<PendingSave>,
+    thumbnail_renderer: ThumbnailRenderer,
+    pending_thumbnail: Option<(AssetKind, String, ThumbnailCapture)>,
+
+    demo: DemoMode,
+    demo_preset: usize,
+
+    camera_path: CameraPath,
+    annotations: Annotations,
+    bookmarks: Bookmarks,
+    event_log: EventLog,
+    #[cfg(feature = "recording")]
+    path_recorder: Option<ThumbnailRenderer>,
+    #[cfg(feature = "recording")]
+    recorded_frames: VecDeque<(u32, (u32, u32), ThumbnailCapture)>,
+    #[cfg(feature = "recording")]
+    frame_capture: FrameCapture,
 
     chunk_manager: ChunkManager,
+    worldgen: WorldGen,
+    // Seeds of the world generation, the simulation and the visual effects, saved with worlds
+    rng: RngStreams,
+    settings: Settings,
+    // What the engine was started with, edited in the UI for the next start
+    config: EngineConfig,
+
+    pub stages: StageToggles,
+    // Stages that ran in the last update, used after submit
+    frame_stages: StageToggles,
 
     pub simulate: Simulate,
+    pub live_bounds: LiveBoundsReduction,
+    pub occupancy: Occupancy,
+    legend: Legend,
     pub meshing: Meshing,
+    pub seam_check: SeamCheck,
+    resample: WorldResample,
+    world_transform: WorldTransform,
+    brush: Brush,
+    stamp: Stamp,
     pub render: Render,
-    pub picker: Picker,
     pub overlay: Overlay,
-    pub bloom: Bloom,
     pub tonemap: Tonemap,
+    // Optional stages, left out in safe mode. The picker and bloom can also be left out of the
+    // build with their cargo features, and aren't created before they're first enabled.
+    pub ground: Option<Ground>,
+    #[cfg(feature = "picker")]
+    pub picker: Option<Picker>,
+    #[cfg(feature = "bloom")]
+    pub bloom: Option<Bloom>,
+    safe_mode: bool,
+}
+
+// Number keys 1 to 9 stand for the first rows of the legend
+fn legend_row(key: KeyCode) -> Option<usize> {
+    const DIGITS: [KeyCode; SHORTCUT_ROWS] = [
+        KeyCode::Digit1,
+        KeyCode::Digit2,
+        KeyCode::Digit3,
+        KeyCode::Digit4,
+        KeyCode::Digit5,
+        KeyCode::Digit6,
+        KeyCode::Digit7,
+        KeyCode::Digit8,
+        KeyCode::Digit9,
+    ];
+    DIGITS.iter().position(|&digit| digit == key)
+}
+
+/// Creates an optional stage on the startup screen, or returns None if that raised a GPU error
+fn create_optional_stage<T>(
+    ctx: &WgpuContext,
+    startup: &mut StartupScreen,
+    name: &str,
+    create: impl FnOnce() -> T,
+) -> Option<T> {
+    // Presented outside the error scopes, errors of the startup screen aren't the stage's
+    startup.present_if_due(ctx, name);
+    catch_stage_errors(ctx, name, || startup.run(name, create))
+}
+
+/// Runs `create` in error scopes, returns None if that raised a GPU error. WebGPU reports errors
+/// asynchronously, so there they're only logged and the stage is kept.
+fn catch_stage_errors<T>(ctx: &WgpuContext, name: &str, create: impl FnOnce() -> T) -> Option<T> {
+    ctx.device.push_error_scope(wgpu::ErrorFilter::OutOfMemory);
+    ctx.device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let stage = create();
+    let scopes = [ctx.device.pop_error_scope(), ctx.device.pop_error_scope()];
+
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(error) = scopes.into_iter().find_map(pollster::block_on) {
+        log::error!("{} failed: {}", name, error);
+        return None;
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    for scope in scopes {
+        let name = name.to_owned();
+        wasm_bindgen_futures::spawn_local(async move {
+            if let Some(error) = scope.await {
+                log::error!("{} failed: {}", name, error);
+            }
+        });
+    }
+
+    Some(stage)
 }
 
 impl Game {
-    pub fn new(ctx: &WgpuContext) -> Self {
-        let chunk_manager = ChunkManager::new(ctx);
+    /// In safe mode, or once an optional stage fails to initialize, only the core stages run:
+    /// simulate, meshing, render and tonemap. `overrides` are applied over the saved config. The
+    /// config is checked against the device limits first, in strict mode any problem is fatal.
+    /// `startup` shows which stage is being created meanwhile.
+    pub fn new(
+        ctx: &WgpuContext,
+        startup: &mut StartupScreen,
+        safe_mode: bool,
+        strict: bool,
+        overrides: &EngineConfigBuilder,
+        seed: Option<u64>,
+    ) -> Self {
+        let settings = Settings::load();
+        let config = overrides.build_on(EngineConfig::load(&settings));
+
+        let problems = validation::check_limits(&ctx.device.limits(), &config);
+        for problem in &problems {
+            log::error!("Config doesn't fit the device: {}", problem);
+        }
+        if strict && !problems.is_empty() {
+            panic!(
+                "The config doesn't fit the device limits:\n{}",
+                problems.join("\n")
+            );
+        }
+
+        let mut chunk_manager = ChunkManager::new(ctx);
+        if let Some(chunks_per_group) = config.chunks_per_group {
+            if let Err(e) = chunk_manager.set_chunks_per_group(ctx, chunks_per_group) {
+                log::warn!("Ignoring the configured group size: {}", e);
+            }
+        }
+        let mut failed = false;
+
+        let tonemap = startup.stage(ctx, "Tonemap::new", || {
+            Tonemap::new(ctx, Rc::new(RenderTargetInfo::from(ctx)))
+        });
+        // Bloom and the picker are only created once they're first enabled when they start out
+        // disabled, see `create_enabled_stages`
+        #[cfg(feature = "bloom")]
+        let mut bloom = (!safe_mode && config.stages.bloom)
+            .then(|| {
+                create_optional_stage(ctx, startup, "Bloom::new", || {
+                    Bloom::new(ctx, tonemap.input_target())
+                })
+            })
+            .flatten();
+        #[cfg(feature = "bloom")]
+        let overlay_target = {
+            failed |= !safe_mode && config.stages.bloom && bloom.is_none();
+            match &bloom {
+                Some(bloom) => bloom.input_target(),
+                None => tonemap.input_target(),
+            }
+        };
+        #[cfg(not(feature = "bloom"))]
+        let overlay_target = tonemap.input_target();
+        let mut overlay = startup.stage(ctx, "Overlay::new", || Overlay::new(ctx, overlay_target));
+        overlay.theme = Theme::load(&settings);
+        #[cfg(feature = "picker")]
+        let mut picker = (!safe_mode && config.stages.picker)
+            .then(|| {
+                create_optional_stage(ctx, startup, "Picker::new", || {
+                    Picker::new(ctx, overlay.input_target())
+                })
+            })
+            .flatten();
+        #[cfg(feature = "picker")]
+        let scene_target = {
+            failed |= !safe_mode && config.stages.picker && picker.is_none();
+            match &picker {
+                Some(picker) => picker.input_target(),
+                None => overlay.input_target(),
+            }
+        };
+        #[cfg(not(feature = "picker"))]
+        let scene_target = overlay.input_target();
+        let stamp = startup.stage(ctx, "Stamp::new", || {
+            Stamp::new(ctx, &chunk_manager, overlay.input_target())
+        });
+        let render = startup.stage(ctx, "Render::new", || {
+            Render::new(ctx, scene_target.clone())
+        });
+        let mut ground = (!safe_mode)
+            .then(|| {
+                create_optional_stage(ctx, startup, "Ground::new", || {
+                    Ground::new(ctx, &chunk_manager, scene_target)
+                })
+            })
+            .flatten();
+        failed |= !safe_mode && ground.is_none();
+
+        if failed {
+            log::warn!("An optional stage failed to initialize, falling back to safe mode");
+            #[cfg(feature = "bloom")]
+            {
+                bloom = None;
+            }
+            #[cfg(feature = "picker")]
+            {
+                picker = None;
+            }
+            ground = None;
+        }
+        let safe_mode = safe_mode || failed;
+        let meshing = startup.stage(ctx, "Meshing::new", || Meshing::new(ctx, &chunk_manager));
+        let seam_check = startup.stage(ctx, "SeamCheck::new", || {
+            SeamCheck::new(ctx, &chunk_manager)
+        });
+        // Resampling and transforming the world are rare, their pipelines are created on first use
+        let resample = WorldResample::new();
+        let world_transform = WorldTransform::new();
+        let brush = startup.stage(ctx, "Brush::new", || Brush::new(ctx, &chunk_manager));
+        let simulate = startup.stage(ctx, "Simulate::new", || Simulate::new(ctx, &chunk_manager));
+        let live_bounds = startup.stage(ctx, "LiveBoundsReduction::new", || {
+            LiveBoundsReduction::new(ctx, &chunk_manager)
+        });
+        let occupancy = startup.stage(ctx, "Occupancy::new", || {
+            Occupancy::new(ctx, &chunk_manager)
+        });
+        let legend = startup.stage(ctx, "Legend::new", || Legend::new(ctx, &chunk_manager));
+        let thumbnail_renderer = startup.stage(ctx, "ThumbnailRenderer::new", || {
+            ThumbnailRenderer::new(ctx)
+        });
 
-        let tonemap = Tonemap::new(ctx, Rc::new(RenderTargetInfo::from(ctx)));
-        let bloom = Bloom::new(ctx, tonemap.input_target());
-        let overlay = Overlay::new(ctx, bloom.input_target());
-        let picker = Picker::new(ctx, overlay.input_target());
-        let render = Render::new(ctx, picker.input_target());
-        let meshing = Meshing::new(ctx, &chunk_manager);
-        let simulate = Simulate::new(ctx, &chunk_manager);
+        let demo = DemoMode::new(&settings);
+        let mouse_look = MouseLook::new(&settings);
 
         let mut game = Self {
-            position: glm::vec3(80.0, 80.0, 80.0),
+            camera: Camera::new(),
+            mouse_look,
             projection: glm::identity(),
-            look: glm::vec2(-45.0, 45.0),
-            look_sensitivity: 0.1,
-            speed: 0.1,
-            fov: 90.0,
 
             key_tracker: KeyTracker::new(),
             show_debug_window: false,
             show_render_options: false,
             show_profiler: false,
+            show_stats: false,
+            power_saving: false,
+            frame_step: false,
+            frame_advance: false,
+            look_mode: settings
+                .get::<String>(LOOK_MODE_KEY)
+                .and_then(|key| LookMode::from_key(&key))
+                .unwrap_or(LookMode::Lock),
+            show_distance: settings.get::<bool>(DISTANCE_HUD_KEY).unwrap_or(false),
+            show_asset_browser: false,
+            show_timeline: false,
+            show_legend: false,
+            show_about: false,
+            detached: HashSet::new(),
+            workspace: Workspace::load(&settings),
+            leak_check: LeakCheck::new(),
+
+            about: About::new(),
+            asset_browser: AssetBrowser::new(),
+            importer: Importer::new(),
+            pending_save: None,
+            thumbnail_renderer,
+            pending_thumbnail: None,
+
+            demo,
+            demo_preset: 0,
+
+            camera_path: CameraPath::new(),
+            annotations: Annotations::new(),
+            bookmarks: Bookmarks::new(),
+            event_log: EventLog::new(),
+            #[cfg(feature = "recording")]
+            path_recorder: None,
+            #[cfg(feature = "recording")]
+            recorded_frames: VecDeque::new(),
+            #[cfg(feature = "recording")]
+            frame_capture: FrameCapture::new(&settings),
 
             chunk_manager,
+            worldgen: WorldGen::new(),
+            rng: RngStreams::new(seed),
+            settings,
+            config,
+
+            stages: config.stages,
+            frame_stages: config.stages,
 
             simulate,
+            live_bounds,
+            occupancy,
+            legend,
             meshing,
+            seam_check,
+            resample,
+            world_transform,
+            brush,
+            stamp,
             render,
-            picker,
             overlay,
-            bloom,
             tonemap,
+            ground,
+            #[cfg(feature = "picker")]
+            picker,
+            #[cfg(feature = "bloom")]
+            bloom,
+            safe_mode,
         };
 
-        let mut rng = thread_rng();
-
-        let mut blocks = vec![0u32; 64 * 64 * 64];
+        game.simulate.mode = config.rule.mode;
+        game.simulate.birth_mask = config.rule.birth_mask;
+        game.simulate.survival_mask = config.rule.survival_mask;
+        game.render.translucency = config.render.translucency;
+        game.render.sort_faces = config.render.sort_faces;
 
-        let init_size = 2;
+        if safe_mode {
+            log::info!("Running in safe mode");
+            game.stages.picker = false;
+            game.stages.overlay = false;
+            game.stages.bloom = false;
+            game.render.translucency = Translucency::Off;
+            game.render.sort_faces = false;
+            // Relinks the targets past the stages that were dropped
+            game.resize(ctx);
+        }
 
-        for cx in 0..init_size {
-            for cy in 0..init_size {
-                for cz in 0..init_size {
+        for cx in 0..config.world_size {
+            for cy in 0..config.world_size {
+                for cz in 0..config.world_size {
                     let pos = glm::vec3(cx, cy, cz);
 
                     let chunk = Chunk::new(pos);
@@ -99,29 +500,552 @@ impl Game {
             }
         }
         game.chunk_manager.finalize_changes_and_start_frame(ctx);
-        for x in 0..64 {
-            for z in 0..64 {
-                for y in 0..64 {
-                    if rng.gen_range(0..10000) == 0 {
-                        blocks[x + y * 64 + z * 64 * 64] = rng.gen();
-                    } else {
-                        blocks[x + y * 64 + z * 64 * 64] = 0;
-                    }
-                }
+        game.apply_seeds(&Stream::ALL);
+        if config.rule.mode == SimulationMode::Life2d {
+            game.set_plane_mode(ctx, true);
+        } else {
+            game.seed_world(false);
+        }
+
+        game.show_debug_window = game.workspace.is_open("debug");
+        for tool in ToolWindow::ALL {
+            *game.tool_window_open(tool) = game.workspace.is_open(tool.key());
+        }
+        if let Some(name) = game.workspace.last_world().map(str::to_owned) {
+            if !safe_mode {
+                game.load_asset(ctx, AssetKind::World, &name);
             }
         }
 
-        for cx in 0..init_size {
-            for cy in 0..init_size {
-                for cz in 0..init_size {
-                    let pos = glm::vec3(cx, cy, cz);
+        startup.stage(ctx, "autotune", || {
+            autotune(
+                ctx,
+                &mut game.settings,
+                &game.chunk_manager,
+                &mut game.simulate,
+                &mut game.meshing,
+            )
+        });
 
-                    game.chunk_manager.upload_chunk_data(ctx, pos, &blocks);
-                }
+        game
+    }
+
+    /// Fills every chunk with random cells, generated in the background over the next frames
+    fn seed_world(&mut self, plane: bool) {
+        // Random cells can't be replayed, so the history starts over. The ticks do too, the
+        // simulation draws from its stream by tick, so the same run seed gives the same run.
+        self.simulate.set_tick(0);
+        self.event_log.clear();
+        self.event_log.record(
+            self.simulate.tick(),
+            EventKind::Edit("Seeded a random world".to_owned()),
+        );
+
+        let mut positions = self.chunk_manager.world_positions().collect::<Vec<_>>();
+        self.chunk_manager.priority.sort(&mut positions, |pos| *pos);
+        let seed = self.rng.seed(Stream::Worldgen);
+        log::info!("Seeding a world with run seed {}", self.rng.run_seed());
+        self.worldgen.start(positions, seed as u64, plane);
+    }
+
+    /// Hands the seeds of `streams` to the stages drawing from them, the world generation seed is
+    /// only read when the world is seeded
+    fn apply_seeds(&mut self, streams: &[Stream]) {
+        for stream in streams {
+            match stream {
+                Stream::Worldgen => {}
+                Stream::Simulation => self.simulate.set_seed(self.rng.seed(*stream)),
+                Stream::Visual => self.tonemap.set_seed(self.rng.seed(*stream)),
             }
         }
+    }
 
-        game
+    /// Replaces the cells of the current world with random ones, from a new run seed
+    pub fn reseed_world(&mut self, ctx: &WgpuContext) {
+        self.rng.reroll_run();
+        self.apply_seeds(&Stream::ALL);
+        self.chunk_manager.finalize_changes_and_start_frame(ctx);
+        self.seed_world(self.simulate.mode == SimulationMode::Life2d);
+    }
+
+    /// Switches between the regular 3d world and a single voxel thick slab at y = 0
+    pub fn set_plane_mode(&mut self, ctx: &WgpuContext, plane: bool) {
+        if plane {
+            self.simulate.mode = SimulationMode::Life2d;
+        } else if self.simulate.mode == SimulationMode::Life2d {
+            self.simulate.mode = SimulationMode::Spread3d;
+        }
+        self.chunk_manager.finalize_changes_and_start_frame(ctx);
+        self.seed_world(plane);
+        self.reset_camera(plane);
+    }
+
+    /// Looks down on the whole plane, or starts over from the default camera
+    fn reset_camera(&mut self, plane: bool) {
+        if plane {
+            let extent = (self.config.world_size * CHUNK_SIZE as i32) as f32;
+            self.camera
+                .top_down_preset(glm::vec3(extent * 0.5, 0.0, extent * 0.5), extent);
+        } else {
+            self.camera = Camera::new();
+        }
+    }
+
+    fn save_asset(&mut self, ctx: &WgpuContext, kind: AssetKind, name: String, tags: Vec<String>) {
+        let mut asset = Asset::new();
+        asset.set_tags(&tags);
+        self.simulate.write_meta(&mut asset.meta);
+        if kind == AssetKind::World {
+            self.annotations.write_meta(&mut asset.meta);
+            self.event_log.write_meta(&mut asset.meta);
+            self.rng.write_meta(&mut asset.meta);
+            self.render.palettes.write_meta(&mut asset.meta);
+        }
+        self.capture_thumbnail(ctx, kind, &name);
+        if kind == AssetKind::Preset {
+            self.store_asset(kind, &name, &asset);
+            return;
+        }
+
+        if self.pending_save.is_some() {
+            log::warn!("A save is already in progress");
+            return;
+        }
+        asset
+            .meta
+            .insert("simulate.tick".to_owned(), self.simulate.tick().to_string());
+        if kind == AssetKind::World {
+            self.workspace.set_last_world(&name);
+        }
+        self.chunk_manager.finalize_changes_and_start_frame(ctx);
+        let download = self.chunk_manager.download_chunks(ctx);
+        self.pending_save = Some(PendingSave {
+            kind,
+            name,
+            asset,
+            download,
+        });
+    }
+
+    /// Renders the current view into a small image that's stored next to the asset once read back
+    fn capture_thumbnail(&mut self, ctx: &WgpuContext, kind: AssetKind, name: &str) {
+        if self.pending_thumbnail.is_some() {
+            log::warn!("A thumbnail is already being captured");
+            return;
+        }
+        let view_proj = self.camera.projection(1.0) * self.camera.view();
+        let capture = self.thumbnail_renderer.capture(
+            ctx,
+            &self.tonemap,
+            &self.chunk_manager,
+            self.meshing.per_chunk_resources(),
+            &view_proj,
+        );
+        self.pending_thumbnail = Some((kind, name.to_owned(), capture));
+    }
+
+    fn finish_pending_thumbnail(&mut self) {
+        let Some((kind, name, capture)) = &self.pending_thumbnail else {
+            return;
+        };
+        let Some(pixels) = capture.try_take() else {
+            return;
+        };
+        let pixels = match pixels {
+            Ok(pixels) => pixels,
+            Err(e) => {
+                log::warn!("No thumbnail for {:?} {}: {}", kind, name, e);
+                self.pending_thumbnail = None;
+                return;
+            }
+        };
+        // Worlds and patterns are only stored once their chunks are back. An asset that was
+        // deleted or renamed meanwhile doesn't get a thumbnail without it.
+        let saving = self
+            .pending_save
+            .as_ref()
+            .is_some_and(|save| save.kind == *kind && save.name == *name);
+        if saving || assets::exists(*kind, name) {
+            if let Err(e) = assets::save_thumbnail(*kind, name, &pixels) {
+                log::warn!("Failed to save thumbnail of {:?} {}: {}", kind, name, e);
+            }
+        }
+        self.pending_thumbnail = None;
+        self.asset_browser.refresh();
+    }
+
+    fn finish_pending_save(&mut self) {
+        let Some(pending_save) = &self.pending_save else {
+            return;
+        };
+        let Some(chunks) = pending_save.download.try_take() else {
+            return;
+        };
+        let PendingSave {
+            kind,
+            name,
+            mut asset,
+            ..
+        } = self.pending_save.take().unwrap();
+        let chunks = match chunks {
+            Ok(chunks) => chunks,
+            Err(e) => {
+                log::warn!("Failed to save {:?} {}: {}", kind, name, e);
+                return;
+            }
+        };
+        asset.body = world_file::encode_chunks(&chunks);
+        self.store_asset(kind, &name, &asset);
+    }
+
+    fn store_asset(&mut self, kind: AssetKind, name: &str, asset: &Asset) {
+        match assets::save(kind, name, asset) {
+            Ok(()) => {
+                log::info!("Saved {:?} {}", kind, name);
+                self.asset_browser.refresh();
+            }
+            Err(e) => log::warn!("Failed to save {:?} {}: {}", kind, name, e),
+        }
+    }
+
+    /// Presets only change the rule settings. Worlds replace every chunk, patterns only overwrite
+    /// the chunks they contain that also exist in the current world.
+    fn load_asset(&mut self, ctx: &WgpuContext, kind: AssetKind, name: &str) {
+        let Some(asset) = assets::load(kind, name) else {
+            log::warn!("{:?} {} not found", kind, name);
+            return;
+        };
+        let was_plane = self.simulate.mode == SimulationMode::Life2d;
+        if kind == AssetKind::Preset {
+            self.simulate.read_meta(&asset.meta);
+            // Switching to or from the plane seeds a new world like picking the mode does
+            let plane = self.simulate.mode == SimulationMode::Life2d;
+            if plane != was_plane {
+                self.set_plane_mode(ctx, plane);
+            }
+            return;
+        }
+
+        let chunks = match world_file::decode_chunks(&asset.body) {
+            Ok(chunks) => chunks,
+            Err(e) => {
+                log::warn!("Failed to load {:?} {}: {}", kind, name, e);
+                return;
+            }
+        };
+        if kind == AssetKind::World {
+            // Checked before anything changes, so a world that doesn't fit leaves the current one
+            let positions = chunks.iter().map(|(pos, _)| *pos).collect::<HashSet<_>>();
+            if let Err(e) = self.chunk_manager.check_chunk_positions(ctx, &positions) {
+                log::warn!("Could not load {:?} {}: {}", kind, name, e);
+                return;
+            }
+        }
+        self.simulate.read_meta(&asset.meta);
+        let plane = self.simulate.mode == SimulationMode::Life2d;
+        if plane != was_plane {
+            if kind == AssetKind::World {
+                // The saved chunks replace the world, only the camera changes along with the mode
+                self.reset_camera(plane);
+            } else {
+                // The pattern then lands in a freshly seeded world of the new mode
+                self.set_plane_mode(ctx, plane);
+            }
+        }
+
+        if kind == AssetKind::World {
+            self.worldgen.cancel();
+            self.workspace.set_last_world(name);
+            self.annotations.read_meta(&asset.meta);
+            self.event_log.read_meta(&asset.meta);
+            let streams = self.rng.read_meta(&asset.meta);
+            self.apply_seeds(&streams);
+            self.render.palettes.read_meta(&asset.meta);
+            if let Some(tick) = asset.meta.get("simulate.tick").and_then(|t| t.parse().ok()) {
+                self.simulate.set_tick(tick);
+            }
+            let positions = chunks.iter().map(|(pos, _)| *pos).collect::<HashSet<_>>();
+            self.chunk_manager.set_chunk_positions(&positions);
+        } else {
+            self.event_log
+                .record(self.simulate.tick(), EventKind::Pattern(name.to_owned()));
+        }
+        self.chunk_manager.finalize_changes_and_start_frame(ctx);
+
+        let mut skipped = 0;
+        for (pos, data) in chunks {
+            if self.chunk_manager.contains(&pos) {
+                self.chunk_manager.queue_chunk_upload(pos, data);
+            } else {
+                skipped += 1;
+            }
+        }
+        // Empty chunks the pattern lands in got their slots back
+        self.chunk_manager.finalize_changes_and_start_frame(ctx);
+        if skipped > 0 {
+            log::warn!("Skipped {} chunks outside the current world", skipped);
+        }
+    }
+
+    /// Picks up a saved pattern for stamping, its cells are placed with the mouse
+    fn hold_pattern(&mut self, name: &str) {
+        let Some(asset) = assets::load(AssetKind::Pattern, name) else {
+            log::warn!("Pattern {} not found", name);
+            return;
+        };
+        match world_file::decode_chunks(&asset.body)
+            .and_then(|chunks| Clipboard::from_chunks(name, &chunks))
+        {
+            Ok(clipboard) => self.stamp.hold(clipboard),
+            Err(e) => log::warn!("Can't stamp {}: {}", name, e),
+        }
+    }
+
+    /// Replaces every chunk with imported ones
+    fn import_world(&mut self, ctx: &WgpuContext, chunks: Vec<(glm::IVec3, Vec<u32>)>) {
+        let positions = chunks.iter().map(|(pos, _)| *pos).collect::<HashSet<_>>();
+        if let Err(e) = self.chunk_manager.check_chunk_positions(ctx, &positions) {
+            log::warn!("Could not import: {}", e);
+            return;
+        }
+        self.chunk_manager.set_chunk_positions(&positions);
+        self.worldgen.cancel();
+        for (pos, data) in chunks {
+            self.chunk_manager.queue_chunk_upload(pos, data);
+        }
+        self.chunk_manager.set_live_bounds(None);
+        self.event_log.clear();
+        self.event_log.record(
+            self.simulate.tick(),
+            EventKind::Edit("Imported a world".to_owned()),
+        );
+    }
+
+    fn transform_world(&mut self, ctx: &WgpuContext, transform: Transform) {
+        self.chunk_manager.materialize_all();
+        self.chunk_manager.finalize_changes_and_start_frame(ctx);
+        match self
+            .world_transform
+            .apply(ctx, &mut self.chunk_manager, transform)
+        {
+            Ok(map) => {
+                self.annotations.transform(|voxel| map.apply(voxel));
+                self.chunk_manager.set_live_bounds(None);
+                self.event_log.record(
+                    self.simulate.tick(),
+                    EventKind::Edit(format!("Transformed the world: {:?}", transform)),
+                );
+            }
+            Err(e) => log::warn!("Could not apply {:?}: {}", transform, e),
+        }
+    }
+
+    /// Doubles or halves the world about the origin, the camera and annotations follow along
+    fn resample_world(&mut self, ctx: &WgpuContext, direction: ResampleDirection) {
+        self.chunk_manager.materialize_all();
+        self.chunk_manager.finalize_changes_and_start_frame(ctx);
+        if !self
+            .resample
+            .resample(ctx, &mut self.chunk_manager, direction)
+        {
+            return;
+        }
+        let scale = match direction {
+            ResampleDirection::Up => 2.0,
+            ResampleDirection::Down => 0.5,
+        };
+        self.camera.position *= scale;
+        self.camera.ortho_height *= scale;
+        self.annotations.rescale(direction == ResampleDirection::Up);
+        self.chunk_manager.set_live_bounds(None);
+        self.event_log.record(
+            self.simulate.tick(),
+            EventKind::Edit(format!("Resampled the world: {:?}", direction)),
+        );
+    }
+
+    /// Puts the world back to a bookmarked moment and pauses there
+    fn restore_bookmark(&mut self, ctx: &WgpuContext, index: usize) {
+        let Some(bookmark) = self.bookmarks.get(index) else {
+            return;
+        };
+        // The bookmark may have cells in chunks that were dropped since
+        self.chunk_manager.materialize_all();
+        self.chunk_manager.finalize_changes_and_start_frame(ctx);
+        let mut encoder = ctx
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("encoder restore_bookmark"),
+            });
+        self.chunk_manager
+            .restore_snapshot(&mut encoder, bookmark.snapshot());
+        ctx.queue.submit([encoder.finish()]);
+        self.event_log.record(
+            self.simulate.tick(),
+            EventKind::Edit(format!("Restored the bookmark at tick {}", bookmark.tick)),
+        );
+        self.simulate.set_tick(bookmark.tick);
+        self.simulate.paused = true;
+    }
+
+    /// World space box around the live cells, or every loaded chunk before the first reduction
+    fn world_bounds(&self) -> (glm::Vec3, glm::Vec3) {
+        if let Some(live_bounds) = self.chunk_manager.live_bounds() {
+            return (
+                live_bounds.min.cast::<f32>(),
+                (live_bounds.max + glm::vec3(1, 1, 1)).cast::<f32>(),
+            );
+        }
+
+        self.chunk_bounds()
+    }
+
+    /// World space box around every loaded chunk
+    fn chunk_bounds(&self) -> (glm::Vec3, glm::Vec3) {
+        let mut min = glm::vec3(i32::MAX, i32::MAX, i32::MAX);
+        let mut max = glm::vec3(i32::MIN, i32::MIN, i32::MIN);
+        for pos in self.chunk_manager.world_positions() {
+            min = glm::min2(&min, &pos);
+            max = glm::max2(&max, &(pos + glm::vec3(1, 1, 1)));
+        }
+        if min.x > max.x {
+            return (glm::Vec3::zeros(), glm::Vec3::zeros());
+        }
+        (
+            min.cast::<f32>() * CHUNK_SIZE as f32,
+            max.cast::<f32>() * CHUNK_SIZE as f32,
+        )
+    }
+
+    /// Where the brush symmetry planes meet, the center of the chunks so it doesn't drift while
+    /// painting
+    fn symmetry_origin(&self) -> glm::Vec3 {
+        let (min, max) = self.chunk_bounds();
+        (min + max) * 0.5
+    }
+
+    /// Point under the crosshair that Alt+scroll dollies toward: where the view ray enters the
+    /// world bounds, or a point ahead at the distance of the world when the ray misses them
+    fn dolly_target(&self) -> glm::Vec3 {
+        let (min, max) = self.world_bounds();
+        let origin = self.camera.position;
+        let dir = self.camera.forward();
+        let (mut near, mut far) = (0.0f32, f32::MAX);
+        for axis in 0..3 {
+            let inv = 1.0 / dir[axis];
+            let t0 = (min[axis] - origin[axis]) * inv;
+            let t1 = (max[axis] - origin[axis]) * inv;
+            near = near.max(t0.min(t1));
+            far = far.min(t0.max(t1));
+        }
+        let distance = if near <= far && near > 0.0 {
+            near
+        } else {
+            glm::distance(&origin, &((min + max) * 0.5)).max(1.0)
+        };
+        origin + dir * distance
+    }
+
+    /// Switches to the next saved rule preset, in listing order
+    fn next_demo_preset(&mut self, ctx: &WgpuContext) {
+        let presets = assets::list(AssetKind::Preset);
+        if presets.is_empty() {
+            return;
+        }
+        self.demo_preset = (self.demo_preset + 1) % presets.len();
+        let name = presets[self.demo_preset].name.clone();
+        log::info!("Demo mode switching to preset {}", name);
+        self.load_asset(ctx, AssetKind::Preset, &name);
+    }
+
+    fn update_demo(&mut self, ctx: &egui::Context, wgpu_ctx: &WgpuContext) {
+        let (now, explicit_input, pointer_moved) = ctx.input(|i| {
+            let explicit_input = i.events.iter().any(|e| {
+                matches!(
+                    e,
+                    egui::Event::Key { pressed: true, .. }
+                        | egui::Event::PointerButton { pressed: true, .. }
+                )
+            });
+            (i.time, explicit_input, i.pointer.is_moving())
+        });
+        if explicit_input || pointer_moved {
+            self.demo.notify_input(explicit_input);
+        }
+
+        let bounds = self.world_bounds();
+        match self
+            .demo
+            .update(now, &mut self.camera, &mut self.simulate.paused, bounds)
+        {
+            Some(DemoEvent::Started) => log::info!("Demo mode started"),
+            Some(DemoEvent::Stopped) => log::info!("Demo mode stopped"),
+            Some(DemoEvent::NextPreset) => self.next_demo_preset(wgpu_ctx),
+            None => {}
+        }
+        // Wake up in time for the idle timeout even when power saving stops redraws
+        if let Some(remaining) = self.demo.time_until_start() {
+            ctx.request_repaint_after(std::time::Duration::from_secs_f64(remaining));
+        }
+    }
+
+    #[cfg(not(feature = "recording"))]
+    fn update_camera_path(&mut self, ctx: &egui::Context, _wgpu_ctx: &WgpuContext) {
+        let now = ctx.input(|i| i.time);
+        self.camera_path.update(now, &mut self.camera, false);
+    }
+
+    #[cfg(feature = "recording")]
+    fn update_camera_path(&mut self, ctx: &egui::Context, wgpu_ctx: &WgpuContext) {
+        self.finish_recorded_frames();
+
+        let now = ctx.input(|i| i.time);
+        let busy = self.recorded_frames.len() >= MAX_RECORDED_FRAMES_IN_FLIGHT;
+        let Some(frame) = self.camera_path.update(now, &mut self.camera, busy) else {
+            return;
+        };
+
+        let size = self.camera_path.record_size;
+        if self.path_recorder.as_ref().map(|r| r.size()) != Some(size) {
+            self.path_recorder = Some(ThumbnailRenderer::with_size(wgpu_ctx, size.0, size.1));
+        }
+        let view_proj = self.camera.projection(size.0 as f32 / size.1 as f32) * self.camera.view();
+        let capture = self.path_recorder.as_mut().unwrap().capture(
+            wgpu_ctx,
+            &self.tonemap,
+            &self.chunk_manager,
+            self.meshing.per_chunk_resources(),
+            &view_proj,
+        );
+        self.recorded_frames.push_back((frame, size, capture));
+    }
+
+    /// Writes recorded frames that have been read back as binary PPM images, in order
+    #[cfg(feature = "recording")]
+    fn finish_recorded_frames(&mut self) {
+        while let Some((frame, (width, height), capture)) = self.recorded_frames.front() {
+            let Some(pixels) = capture.try_take() else {
+                return;
+            };
+            let key = format!(
+                "recordings/{}/frame_{:05}.ppm",
+                self.camera_path.record_name, frame
+            );
+            match pixels {
+                Ok(pixels) => {
+                    let mut ppm = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+                    ppm.extend(pixels.chunks(4).flat_map(|p| &p[..3]));
+                    if let Err(e) = storage::write_bytes(&key, &ppm) {
+                        log::warn!("Failed to write {}: {}", key, e);
+                    }
+                }
+                Err(e) => log::warn!("Failed to record {}: {}", key, e),
+            }
+            self.recorded_frames.pop_front();
+        }
+        if !self.camera_path.is_recording() {
+            self.path_recorder = None;
+        }
     }
 
     pub fn update(
@@ -129,6 +1053,15 @@ impl Game {
         ctx: &WgpuContext,
         encoder: &mut wgpu::CommandEncoder,
     ) -> Vec<wgpu::CommandBuffer> {
+        self.finish_pending_save();
+        self.finish_pending_thumbnail();
+        #[cfg(feature = "recording")]
+        self.frame_capture.finish();
+        // The last frame stays in the targets and keeps being shown under the UI
+        if self.frame_step && !std::mem::take(&mut self.frame_advance) {
+            return Vec::new();
+        }
+
         let mut rel_movement = glm::vec3(0.0, 0.0, 0.0);
         if self.key_tracker.is_key_pressed(KeyCode::KeyW) {
             rel_movement.z -= 1.0;
@@ -149,96 +1082,393 @@ impl Game {
             rel_movement.y -= 1.0;
         }
 
-        let abs_movement = glm::rotate_y_vec3(
-            &glm::vec3(rel_movement.x, 0.0, rel_movement.z),
-            self.look.y.to_radians(),
-        ) + glm::vec3(0.0, rel_movement.y, 0.0);
+        self.camera.move_relative(rel_movement);
 
-        self.position += abs_movement * self.speed;
-
-        self.projection = glm::reversed_infinite_perspective_rh_zo(
-            ctx.surface_config.width as f32 / ctx.surface_config.height as f32,
-            self.fov.to_radians(),
-            0.1,
-        );
-        let view: glm::Mat4 = glm::identity();
-        let view = glm::rotate_x(&view, -self.look.x.to_radians());
-        let view = glm::rotate_y(&view, -self.look.y.to_radians());
-        let view = glm::translate(&view, &-self.position);
+        self.projection = self
+            .camera
+            .projection(ctx.surface_config.width as f32 / ctx.surface_config.height as f32);
+        let view = self.camera.view();
 
         let mvp = self.projection * view;
+        self.chunk_manager
+            .priority
+            .update(&self.camera.position, &mvp);
 
+        let can_evict = self.simulate.empty_stays_empty()
+            && self.chunk_manager.upload_progress().is_none()
+            && self.worldgen.progress().is_none();
+        self.occupancy
+            .apply(&mut self.chunk_manager, self.simulate.tick(), can_evict);
+        if self.brush.enabled {
+            let pressed =
+                |keys: [KeyCode; 2]| keys.iter().any(|&key| self.key_tracker.is_key_pressed(key));
+            // Shift is taken by moving down
+            let ctrl = pressed([KeyCode::ControlLeft, KeyCode::ControlRight]);
+            let alt = pressed([KeyCode::AltLeft, KeyCode::AltRight]);
+            let keys = PlacementKeys {
+                plane: ctrl && !alt,
+                axis: ctrl && alt,
+                extrude: alt && !ctrl,
+            };
+            let pick = self.pick(&self.stages).and_then(|pick| pick.hit);
+            self.brush.prepare(&self.camera, keys, pick);
+            let origin = self.symmetry_origin();
+            for (min, max) in self.brush.stroke_bounds(&self.camera, &origin) {
+                self.chunk_manager.materialize_in_aabb(&min, &max);
+            }
+        }
+        if self.stamp.active() {
+            let pick = self.pick(&self.stages).and_then(|pick| pick.hit);
+            self.stamp.prepare(&self.camera, pick);
+            if let Some((min, max)) = self.stamp.pending_bounds() {
+                self.chunk_manager.materialize_in_aabb(&min, &max);
+            }
+        }
+        self.worldgen.poll(&mut self.chunk_manager);
         self.chunk_manager.finalize_changes_and_start_frame(ctx);
-        ctx.profiler.profile(encoder, "simulate", |encoder| {
-            self.simulate.update(ctx, encoder, &mut self.chunk_manager);
+        self.chunk_manager.process_uploads(ctx);
+        self.simulate.throttle.focus = self.camera.position;
+        self.event_log.observe(&self.simulate);
+        self.frame_stages = self.stages;
+        if !self.stages.simulate {
+            // Simulation is off, the world stays as is
+        } else if self.chunk_manager.upload_progress().is_some()
+            || self.worldgen.progress().is_some()
+        {
+            // The world is still being generated or uploaded, ticking now would run on part of it
+        } else if self.simulate.separate_submission {
+            // Skip this frame's simulation if the previous burst hasn't finished on the GPU yet,
+            // rendering keeps going with the last completed state
+            if !self.simulate.submission_in_flight() {
+                let mut simulate_encoder =
+                    ctx.device
+                        .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                            label: Some("encoder simulate"),
+                        });
+                ctx.profiler
+                    .profile(&mut simulate_encoder, "simulate", |encoder| {
+                        self.simulate.update(ctx, encoder, &mut self.chunk_manager);
+                    });
+                self.simulate.submit(ctx, simulate_encoder);
+            }
+        } else {
+            ctx.profiler.profile(encoder, "simulate", |encoder| {
+                self.simulate.update(ctx, encoder, &mut self.chunk_manager);
+            });
+        }
+
+        if self.brush.enabled {
+            let origin = self.symmetry_origin();
+            let strokes = ctx.profiler.profile(encoder, "brush", |encoder| {
+                self.brush
+                    .update(ctx, encoder, &mut self.chunk_manager, &self.camera, &origin)
+            });
+            for stroke in strokes {
+                self.event_log.record(
+                    self.simulate.tick(),
+                    EventKind::Paint {
+                        center: stroke.center,
+                        radius: stroke.radius,
+                        value: stroke.value,
+                    },
+                );
+            }
+        }
+
+        let stamped = ctx.profiler.profile(encoder, "stamp", |encoder| {
+            self.stamp.update(ctx, encoder, &mut self.chunk_manager)
         });
+        if let Some((name, corner)) = stamped {
+            self.event_log.record(
+                self.simulate.tick(),
+                EventKind::Edit(format!(
+                    "Stamped {} at {} {} {}",
+                    name, corner.x, corner.y, corner.z
+                )),
+            );
+        }
 
-        let meshing_result = ctx.profiler.profile(encoder, "meshing", |encoder| {
-            self.meshing.update(ctx, encoder, &self.chunk_manager)
+        ctx.profiler.profile(encoder, "live_bounds", |encoder| {
+            self.live_bounds
+                .update(encoder, &mut self.chunk_manager, self.simulate.tick());
+        });
+        ctx.profiler.profile(encoder, "occupancy", |encoder| {
+            self.occupancy
+                .update(ctx, encoder, &self.chunk_manager, self.simulate.tick());
         });
 
+        // Counting every cell is only worth it while someone looks at the counts
+        if self.show_legend || self.detached.contains(&ToolWindow::Legend) {
+            ctx.profiler.profile(encoder, "legend", |encoder| {
+                self.legend.update(
+                    encoder,
+                    &self.chunk_manager,
+                    self.simulate.generation_states(),
+                    self.simulate.mode == SimulationMode::Powder,
+                );
+            });
+        }
+
+        let tick = self.simulate.tick();
+        if let Some((kind, rate)) = self.bookmarks.observe(self.live_bounds.population()) {
+            let snapshot = self.chunk_manager.snapshot_chunks(ctx, encoder);
+            let capture = self.thumbnail_renderer.capture(
+                ctx,
+                &self.tonemap,
+                &self.chunk_manager,
+                self.meshing.per_chunk_resources(),
+                &(self.camera.projection(1.0) * view),
+            );
+            self.bookmarks.add(tick, kind, rate, snapshot, capture);
+        }
+
+        if self.stages.meshing {
+            ctx.profiler.profile(encoder, "meshing", |encoder| {
+                self.meshing.set_state_filter(
+                    ctx,
+                    encoder,
+                    self.legend
+                        .filter()
+                        .with_iso_level(self.simulate.iso_level())
+                        .with_states(self.simulate.generation_states())
+                        .with_materials(self.simulate.mode == SimulationMode::Powder),
+                );
+                self.meshing.update(ctx, encoder, &self.chunk_manager);
+            });
+            ctx.profiler.profile(encoder, "seam_check", |encoder| {
+                self.seam_check.update(
+                    ctx,
+                    encoder,
+                    &self.chunk_manager,
+                    self.meshing.per_chunk_resources(),
+                );
+            });
+        }
+
         ctx.profiler.profile(encoder, "render", |encoder| {
-            self.render
-                .update(ctx, encoder, &self.chunk_manager, meshing_result, &mvp);
+            if self.stages.render {
+                self.render.update(
+                    ctx,
+                    encoder,
+                    &self.chunk_manager,
+                    self.meshing.per_chunk_resources(),
+                    &mvp,
+                    self.meshing.frame(),
+                );
+            } else {
+                self.render.clear(encoder);
+            }
         });
 
-        ctx.profiler.profile(encoder, "picker", |encoder| {
-            self.picker.update(ctx, encoder);
-        });
+        if let Some(ground) = self
+            .ground
+            .as_mut()
+            .filter(|g| self.stages.render && g.enabled)
+        {
+            ctx.profiler.profile(encoder, "ground", |encoder| {
+                ground.update(
+                    ctx,
+                    encoder,
+                    &self.chunk_manager,
+                    &mvp,
+                    &self.camera.position,
+                );
+            });
+        }
 
-        ctx.profiler.profile(encoder, "overlay", |encoder| {
-            self.overlay.update(ctx, encoder, &self.projection, &view);
-        });
+        #[cfg(feature = "picker")]
+        if let Some(picker) = self.picker.as_mut().filter(|_| self.stages.picker) {
+            let trail_color = theme::rgba(self.overlay.theme.pick_trail);
+            ctx.profiler.profile(encoder, "picker", |encoder| {
+                picker.update(ctx, encoder, &mvp, trail_color);
+            });
+        }
 
-        ctx.profiler.profile(encoder, "bloom", |encoder| {
-            self.bloom.update(ctx, encoder);
-        });
+        // After the picker, so the ghost isn't picked and the pattern doesn't stack up on itself
+        if self.stamp.active() {
+            ctx.profiler.profile(encoder, "stamp ghost", |encoder| {
+                self.stamp.draw(ctx, encoder, &mvp);
+            });
+        }
+
+        if self.stages.overlay {
+            self.camera_path.draw(&self.overlay);
+            self.annotations.draw(&self.overlay);
+            self.brush
+                .draw(&self.overlay, &self.camera, &self.symmetry_origin());
+            self.seam_check.draw(&self.overlay);
+            self.simulate.containment.draw(&self.overlay);
+            self.simulate.selection.draw(&self.overlay);
+            self.simulate.heat.draw(&self.overlay);
+            self.simulate
+                .activity
+                .draw(&self.overlay, &self.chunk_manager);
+            self.simulate.agents.draw(&self.overlay);
+            #[cfg(feature = "picker")]
+            if let Some((buffer, count)) = self
+                .picker
+                .as_ref()
+                .filter(|_| self.stages.picker)
+                .and_then(Picker::trail_lines)
+            {
+                self.overlay.line_buffer(buffer, count);
+            }
+            ctx.profiler.profile(encoder, "overlay", |encoder| {
+                self.overlay.update(ctx, encoder, &self.projection, &view);
+            });
+        }
 
-        ctx.profiler.profile(encoder, "tonemap", |_encoder| {
-            self.tonemap.update(ctx);
+        #[cfg(feature = "bloom")]
+        if let Some(bloom) = self.bloom.as_mut().filter(|_| self.stages.bloom) {
+            ctx.profiler.profile(encoder, "bloom", |encoder| {
+                bloom.update(ctx, encoder);
+            });
+        }
+
+        ctx.profiler.profile(encoder, "tonemap", |encoder| {
+            self.tonemap.update(ctx, encoder, !self.stages.tonemap);
         });
+        #[cfg(feature = "recording")]
+        self.frame_capture
+            .capture_scene(ctx, encoder, &self.tonemap);
 
         vec![]
     }
 
+    /// Whether frames should be drawn back to back rather than only on input
+    pub fn wants_continuous_redraw(&self) -> bool {
+        if self.frame_step {
+            return self.frame_advance || self.capture_busy();
+        }
+        !self.power_saving
+            || self.demo.is_active()
+            || self.camera_path.is_playing()
+            || self.capture_busy()
+            || self.chunk_manager.upload_progress().is_some()
+            || self.worldgen.progress().is_some()
+            || (self.stages.simulate && self.simulate.is_running())
+            || self.key_tracker.any_pressed()
+    }
+
+    /// Whether recorded frames or screenshots are still being read back
+    fn capture_busy(&self) -> bool {
+        #[cfg(feature = "recording")]
+        return !self.recorded_frames.is_empty() || self.frame_capture.is_busy();
+        #[cfg(not(feature = "recording"))]
+        return false;
+    }
+
+    /// The last pick that came back, if the picker is built and `stages` has it enabled
+    #[cfg_attr(not(feature = "picker"), allow(unused_variables))]
+    fn pick(&self, stages: &StageToggles) -> Option<Pick> {
+        #[cfg(feature = "picker")]
+        return self
+            .picker
+            .as_ref()
+            .filter(|_| stages.picker)
+            .and_then(|picker| picker.result().copied());
+        #[cfg(not(feature = "picker"))]
+        return None;
+    }
+
+    /// Copies the surface texture for a screenshot with the GUI, once the GUI has been drawn
+    #[cfg(feature = "recording")]
+    pub fn capture_surface(
+        &mut self,
+        ctx: &WgpuContext,
+        encoder: &mut wgpu::CommandEncoder,
+        surface_texture: &wgpu::Texture,
+    ) {
+        self.frame_capture
+            .capture_surface(ctx, encoder, surface_texture);
+    }
+
     pub fn final_draw_resources(&self) -> Arc<FinalDrawResources> {
         self.tonemap.final_draw_resources()
     }
 
-    pub fn mouse_motion(&mut self, dx: f64, dy: f64) {
-        self.look.y -= dx as f32 * self.look_sensitivity;
-        self.look.x -= dy as f32 * self.look_sensitivity;
-        if self.look.x > 90.0 {
-            self.look.x = 90.0;
+    /// Creates the optional stages that were disabled at startup once they're enabled. One that
+    /// fails to initialize is disabled again.
+    fn create_enabled_stages(&mut self, ctx: &WgpuContext) {
+        if self.safe_mode {
+            return;
+        }
+        #[cfg(feature = "picker")]
+        if self.stages.picker && self.picker.is_none() {
+            let target = self.overlay.input_target();
+            self.picker = catch_stage_errors(ctx, "Picker::new", || {
+                log_duration("Picker::new", || Picker::new(ctx, target))
+            });
+            self.stages.picker = self.picker.is_some();
         }
-        if self.look.x < -90.0 {
-            self.look.x = -90.0;
+        #[cfg(feature = "bloom")]
+        if self.stages.bloom && self.bloom.is_none() {
+            let target = self.tonemap.input_target();
+            self.bloom = catch_stage_errors(ctx, "Bloom::new", || {
+                log_duration("Bloom::new", || Bloom::new(ctx, target))
+            });
+            self.stages.bloom = self.bloom.is_some();
         }
     }
 
     pub fn resize(&mut self, ctx: &WgpuContext) {
+        self.create_enabled_stages(ctx);
         self.tonemap
             .resize(ctx, Rc::new(RenderTargetInfo::from(ctx)));
-        self.bloom.resize(ctx, self.tonemap.input_target());
-        self.overlay.resize(ctx, self.bloom.input_target());
-        self.picker.resize(ctx, self.overlay.input_target());
-        self.render.resize(ctx, self.picker.input_target());
-    }
-
-    pub fn input(&mut self, event: &WindowEvent, event_loop_proxy: &EventLoopProxy<UserEvent>) {
-        match event {
-            WindowEvent::KeyboardInput {
-                event:
-                    winit::event::KeyEvent {
-                        physical_key: PhysicalKey::Code(key_code),
-                        state,
-                        ..
-                    },
-                ..
-            } => {
-                if *state == ElementState::Pressed {
-                    self.key_tracker.key_down(*key_code);
-                    match *key_code {
+        let graph = FrameGraph::new();
+        #[cfg(feature = "picker")]
+        let graph = graph.optional_stage(self.picker.as_mut(), true);
+        // Overlay owns the depth buffer render draws with, so it's never linked past
+        let graph = graph.stage(&mut self.overlay, true);
+        #[cfg(feature = "bloom")]
+        let graph = graph.optional_stage(self.bloom.as_mut(), self.stages.bloom);
+        let scene_target = graph.link(ctx, self.tonemap.input_target());
+        self.stamp.resize(ctx, self.overlay.input_target());
+        if let Some(ground) = &mut self.ground {
+            ground.resize(ctx, scene_target.clone());
+        }
+        self.render.resize(ctx, scene_target);
+        self.leak_check.after_resize();
+    }
+
+    /// Uploads the egui textures the game owns again, after the GUI renderer was recreated
+    pub fn reload_gui_textures(&mut self) {
+        self.asset_browser.refresh();
+        self.bookmarks.reload_textures();
+    }
+
+    /// Called when `tool` moves into its own OS window or back into the main window
+    pub fn set_detached(&mut self, tool: ToolWindow, detached: bool) {
+        if detached {
+            self.detached.insert(tool);
+        } else {
+            self.detached.remove(&tool);
+            *self.tool_window_open(tool) = false;
+        }
+        // Textures belong to the egui context they were loaded in
+        if tool == ToolWindow::Assets || tool == ToolWindow::Timeline {
+            self.reload_gui_textures();
+        }
+    }
+
+    fn tool_window_open(&mut self, tool: ToolWindow) -> &mut bool {
+        match tool {
+            ToolWindow::RenderOptions => &mut self.show_render_options,
+            ToolWindow::Stats => &mut self.show_stats,
+            ToolWindow::Profiler => &mut self.show_profiler,
+            ToolWindow::Assets => &mut self.show_asset_browser,
+            ToolWindow::Timeline => &mut self.show_timeline,
+            ToolWindow::Legend => &mut self.show_legend,
+        }
+    }
+
+    pub fn input(&mut self, event: &InputEvent, event_loop_proxy: &EventLoopProxy<UserEvent>) {
+        match *event {
+            InputEvent::Key { key, pressed } => {
+                if pressed {
+                    self.demo.notify_input(true);
+                    self.key_tracker.key_down(key);
+                    match key {
                         KeyCode::Escape => {
                             let _ =
                                 event_loop_proxy.send_event(UserEvent::RequestCursorLock(false));
@@ -246,29 +1476,124 @@ impl Game {
                         KeyCode::KeyI => {
                             self.simulate.step = 1;
                         }
+                        KeyCode::Comma => {
+                            self.simulate.step_back = true;
+                        }
+                        KeyCode::Period if self.frame_step => {
+                            self.frame_advance = true;
+                        }
                         KeyCode::KeyP => {
                             self.simulate.paused = !self.simulate.paused;
                         }
-                        _ => {}
+                        KeyCode::KeyF => {
+                            let (min, max) = self.world_bounds();
+                            self.camera.frame(&min, &max);
+                        }
+                        KeyCode::Digit0 => {
+                            self.legend.show_all();
+                        }
+                        KeyCode::KeyT => self.stamp.rotate(0),
+                        KeyCode::KeyR => self.stamp.rotate(1),
+                        KeyCode::KeyG => self.stamp.rotate(2),
+                        KeyCode::Backspace => self.stamp.cancel(),
+                        #[cfg(feature = "recording")]
+                        KeyCode::F12 => {
+                            self.frame_capture.request();
+                        }
+                        _ => {
+                            if let Some(row) = legend_row(key) {
+                                if self.key_tracker.is_key_pressed(KeyCode::ShiftLeft)
+                                    || self.key_tracker.is_key_pressed(KeyCode::ShiftRight)
+                                {
+                                    self.legend.isolate_row(row);
+                                } else {
+                                    self.legend.toggle_row(row);
+                                }
+                            }
+                        }
                     }
                 } else {
-                    self.key_tracker.key_up(*key_code);
+                    self.key_tracker.key_up(key);
+                }
+            }
+            InputEvent::Wheel { y, .. } => {
+                self.demo.notify_input(true);
+                let pressed = |keys: [KeyCode; 2]| {
+                    keys.iter().any(|&key| self.key_tracker.is_key_pressed(key))
+                };
+                if pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) {
+                    self.camera.scroll_zoom(y);
+                } else if pressed([KeyCode::AltLeft, KeyCode::AltRight]) {
+                    let target = self.dolly_target();
+                    self.camera.scroll_dolly(y, &target);
+                } else {
+                    self.camera.scroll(y);
                 }
             }
-            WindowEvent::MouseWheel {
-                delta: winit::event::MouseScrollDelta::LineDelta(_, y),
-                ..
-            } => {
-                self.speed *= 1.0 + y / 100.0;
-                self.speed = self.speed.clamp(0.0001, 10000.0);
+            InputEvent::MouseMotion { dx, dy } => {
+                self.demo.notify_input(false);
+                self.mouse_motion(dx, dy);
+            }
+            InputEvent::MouseButton { button, pressed } => {
+                if pressed {
+                    self.demo.notify_input(true);
+                }
+                // A held pattern takes the click from the brush
+                if button == MouseButton::Left && !(pressed && self.stamp.request()) {
+                    self.brush.set_painting(pressed);
+                }
+                if button == MouseButton::Middle && pressed {
+                    let target = self.brush.target(&self.camera);
+                    self.simulate.agents.spawn(&target);
+                    self.event_log
+                        .record(self.simulate.tick(), EventKind::Agent(target));
+                }
             }
-            _ => {}
         }
     }
 
+    fn mouse_motion(&mut self, dx: f64, dy: f64) {
+        let (yaw, pitch) = self.mouse_look.apply(dx, dy);
+        self.camera.turn(yaw, pitch);
+    }
+
     pub fn cursor_lock_update(&mut self, locked: bool) {
-        if !locked {
+        if locked {
+            // The crosshair is in the center
+            self.pointer_update(None);
+        } else {
             self.key_tracker.reset();
+            self.brush.set_painting(false);
+        }
+    }
+
+    /// Cursor position in physical pixels, what's under it is picked. `None` picks at the center
+    /// of the screen.
+    #[cfg_attr(not(feature = "picker"), allow(unused_variables))]
+    pub fn pointer_update(&mut self, position: Option<(f64, f64)>) {
+        #[cfg(feature = "picker")]
+        if let Some(picker) = &mut self.picker {
+            let pixel = position.map(|(x, y)| glm::vec2(x.max(0.0) as u32, y.max(0.0) as u32));
+            picker.set_pixel(pixel);
+        }
+    }
+
+    /// Refresh rate of the monitor the window is on, when it's known
+    pub fn set_refresh_rate(&mut self, millihertz: Option<u32>) {
+        self.simulate
+            .schedule
+            .set_refresh_rate(millihertz.map(|mhz| mhz as f32 / 1000.0));
+    }
+
+    pub fn look_mode(&self) -> LookMode {
+        self.look_mode
+    }
+
+    /// Keys held while dragging to look are released with the button
+    pub fn drag_look_update(&mut self, dragging: bool) {
+        if !dragging {
+            self.key_tracker.reset();
+            self.brush.set_painting(false);
         }
     }
 
@@ -282,12 +1607,39 @@ impl Game {
             egui::menu::bar(ui, |ui| {
                 let is_web = cfg!(target_arch = "wasm32");
                 ui.menu_button("File", |ui| {
+                    #[cfg(feature = "recording")]
+                    self.frame_capture.ui(ui, &mut self.settings);
                     if !is_web {
+                        ui.separator();
                         if ui.button("Quit").clicked() {
                             ctx.send_viewport_cmd(egui::ViewportCommand::Close);
                         }
                     }
                 });
+                ui.menu_button("Edit", |ui| {
+                    let mut transform = None;
+                    ui.menu_button("Mirror", |ui| {
+                        for axis in Axis::ALL {
+                            if ui.button(format!("Along {}", axis.name())).clicked() {
+                                transform = Some(Transform::Mirror(axis));
+                            }
+                        }
+                    });
+                    ui.menu_button("Rotate 90°", |ui| {
+                        for axis in Axis::ALL {
+                            if ui.button(format!("About {}", axis.name())).clicked() {
+                                transform = Some(Transform::Rotate(axis));
+                            }
+                        }
+                    });
+                    if let Some(transform) = transform {
+                        self.transform_world(wgpu_ctx, transform);
+                        ui.close_menu();
+                    }
+                    ui.separator();
+                    ui.checkbox(&mut self.brush.enabled, "Paint brush")
+                        .on_hover_text("Settings are under Render options");
+                });
                 ui.menu_button("View", |ui| {
                     egui::widgets::global_dark_light_mode_buttons(ui);
                     egui::widgets::Checkbox::new(&mut self.show_debug_window, "Debug window")
@@ -295,40 +1647,446 @@ impl Game {
                     egui::widgets::Checkbox::new(&mut self.show_render_options, "Render options")
                         .ui(ui);
                     egui::widgets::Checkbox::new(&mut self.show_profiler, "Profiler").ui(ui);
+                    egui::widgets::Checkbox::new(&mut self.show_stats, "Stats").ui(ui);
+                    egui::widgets::Checkbox::new(&mut self.show_asset_browser, "Assets").ui(ui);
+                    egui::widgets::Checkbox::new(&mut self.show_timeline, "Timeline").ui(ui);
+                    egui::widgets::Checkbox::new(&mut self.show_legend, "Legend").ui(ui);
+                    egui::widgets::Checkbox::new(&mut self.power_saving, "Power saving")
+                        .ui(ui)
+                        .on_hover_text("Only redraw on input or while the simulation runs");
+                    egui::widgets::Checkbox::new(&mut self.frame_step, "Frame step")
+                        .ui(ui)
+                        .on_hover_text(
+                            "Holds the picture until . is pressed, to look at temporal effects one \
+                             frame at a time",
+                        );
+                    if ui
+                        .checkbox(&mut self.show_distance, "Distance to surface")
+                        .on_hover_text("How far the surface under the crosshair is, to judge scale")
+                        .changed()
+                    {
+                        self.settings.set(DISTANCE_HUD_KEY, self.show_distance);
+                        self.settings.save();
+                    }
+                    ui.menu_button("Mouse look", |ui| {
+                        for mode in LookMode::ALL {
+                            if ui
+                                .radio_value(&mut self.look_mode, mode, mode.name())
+                                .changed()
+                            {
+                                self.settings.set(LOOK_MODE_KEY, mode.key());
+                                self.settings.save();
+                            }
+                        }
+                    })
+                    .response
+                    .on_hover_text("Right drag turns the camera without hiding the cursor");
+                    ui.separator();
+                    if ui
+                        .button("Demo mode")
+                        .on_hover_text("Orbit the world until the next key press or click")
+                        .clicked()
+                    {
+                        self.demo.start();
+                        ui.close_menu();
+                    }
+                });
+                ui.menu_button("Help", |ui| {
+                    if ui.button("About").clicked() {
+                        self.show_about = true;
+                        ui.close_menu();
+                    }
                 });
+                if self.safe_mode {
+                    ui.separator();
+                    ui.colored_label(ui.visuals().warn_fg_color, "Safe mode")
+                        .on_hover_text("Only the core stages are running");
+                }
             });
         });
 
-        egui::Window::new("Debug")
-            .open(&mut self.show_debug_window)
-            .show(ctx, |ui| {
-                egui::collapsing_header::CollapsingHeader::new("Settings").show(ui, |ui| {
-                    ctx.settings_ui(ui);
-                });
-                egui::collapsing_header::CollapsingHeader::new("Inspection").show(ui, |ui| {
-                    ctx.inspection_ui(ui);
-                });
-                egui::collapsing_header::CollapsingHeader::new("Memory").show(ui, |ui| {
-                    ctx.memory_ui(ui);
+        self.update_demo(ctx, wgpu_ctx);
+        self.update_camera_path(ctx, wgpu_ctx);
+        self.camera.animate(ctx.input(|i| i.time));
+        self.simulate.schedule.set_now(ctx.input(|i| i.time));
+        if self.camera.is_animating() {
+            ctx.request_repaint();
+        }
+
+        egui::TopBottomPanel::bottom("statusbar").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                if self.brush.enabled {
+                    self.brush.toolbar_ui(ui);
+                    ui.separator();
+                }
+                self.stats_ui(ui);
+                if self.frame_step {
+                    ui.separator();
+                    ui.colored_label(ui.visuals().warn_fg_color, "Frame step")
+                        .on_hover_text("Press . for the next frame");
+                }
+                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                    wgpu_ctx.profiler.frame_times_ui(ui, true);
                 });
             });
+        });
+        if self.show_distance {
+            self.distance_hud(ctx);
+        }
 
-        egui::Window::new("Render options")
-            .open(&mut self.show_render_options)
-            .show(ctx, |ui| {
+        egui::Window::new("About")
+            .open(&mut self.show_about)
+            .collapsible(false)
+            .show(ctx, |ui| self.about.ui(ui));
+
+        #[cfg(not(target_arch = "wasm32"))]
+        self.simulate.shader_reload.error_window(ctx);
+
+        let mut debug_window = egui::Window::new("Debug").open(&mut self.show_debug_window);
+        if let Some(rect) = self.workspace.rect("debug") {
+            debug_window = debug_window.default_rect(rect);
+        }
+        let debug_response = debug_window.show(ctx, |ui| {
+            egui::collapsing_header::CollapsingHeader::new("Settings").show(ui, |ui| {
+                ctx.settings_ui(ui);
+            });
+            egui::collapsing_header::CollapsingHeader::new("Inspection").show(ui, |ui| {
+                ctx.inspection_ui(ui);
+            });
+            egui::collapsing_header::CollapsingHeader::new("Memory").show(ui, |ui| {
+                ctx.memory_ui(ui);
+            });
+            egui::collapsing_header::CollapsingHeader::new("GPU resources").show(ui, |ui| {
+                self.leak_check.ui(ui);
+            });
+            egui::collapsing_header::CollapsingHeader::new("Chunk priority").show(ui, |ui| {
+                self.chunk_manager.priority.ui(ui);
+            });
+        });
+        if let Some(response) = debug_response {
+            self.workspace.set_rect("debug", response.response.rect);
+        }
+        self.workspace.set_open("debug", self.show_debug_window);
+
+        // Tool windows that were detached when the app was last closed
+        for tool in self.workspace.take_pending_detach() {
+            let _ = event_loop_proxy.send_event(UserEvent::DetachToolWindow(tool));
+        }
+
+        for tool in ToolWindow::ALL {
+            if self.detached.contains(&tool) {
+                continue;
+            }
+            let mut open = *self.tool_window_open(tool);
+            let mut window = egui::Window::new(tool.title()).open(&mut open);
+            if let Some(rect) = self.workspace.rect(tool.key()) {
+                window = window.default_rect(rect);
+            }
+            let response = window.show(ctx, |ui| {
+                if !cfg!(target_arch = "wasm32")
+                    && ui
+                        .small_button("Detach")
+                        .on_hover_text("Move into a separate window")
+                        .clicked()
+                {
+                    let _ = event_loop_proxy.send_event(UserEvent::DetachToolWindow(tool));
+                }
+                self.tool_window_ui(tool, ui, wgpu_ctx, event_loop_proxy);
+            });
+            if let Some(response) = response {
+                self.workspace.set_rect(tool.key(), response.response.rect);
+            }
+            *self.tool_window_open(tool) = open;
+        }
+
+        for tool in ToolWindow::ALL {
+            let open = *self.tool_window_open(tool);
+            self.workspace.set_open(tool.key(), open);
+        }
+        self.workspace.set_detached(self.detached.iter().copied());
+        let dragging = ctx.input(|i| i.pointer.any_down());
+        self.workspace.save(&mut self.settings, dragging);
+    }
+
+    /// Contents of a tool window, whether it's inside the main window or detached
+    pub fn tool_window_ui(
+        &mut self,
+        tool: ToolWindow,
+        ui: &mut egui::Ui,
+        wgpu_ctx: &WgpuContext,
+        event_loop_proxy: &EventLoopProxy<UserEvent>,
+    ) {
+        match tool {
+            ToolWindow::RenderOptions => {
+                self.stages.ui(ui, event_loop_proxy);
+                self.camera.ui(ui);
+                self.mouse_look.ui(ui, &mut self.settings);
+                self.camera_path.ui(ui, &mut self.camera);
+                self.annotations.ui(ui, &self.camera);
+                self.brush
+                    .ui(ui, wgpu_ctx, &self.chunk_manager, &self.camera);
+                self.stamp.ui(ui);
+                #[cfg(feature = "picker")]
+                if let Some(picker) = &mut self.picker {
+                    picker.ui(ui);
+                }
+                self.render.ui(ui, wgpu_ctx);
+                self.render.palettes.ui(ui, &self.simulate.selection);
+                if let Some(ground) = &mut self.ground {
+                    ground.ui(ui);
+                }
                 self.simulate.ui(ui, event_loop_proxy);
-                self.bloom.ui(ui, event_loop_proxy);
+                let (rerolled, reseed) = self.rng.ui(ui);
+                self.apply_seeds(&rerolled);
+                if reseed {
+                    self.chunk_manager
+                        .finalize_changes_and_start_frame(wgpu_ctx);
+                    self.seed_world(self.simulate.mode == SimulationMode::Life2d);
+                }
+                self.simulate.portals.ui(ui, &self.chunk_manager);
+                self.simulate.activity.ui(ui, &self.chunk_manager);
+                if self.simulate.containment.ui(ui) {
+                    let chunks = self.simulate.containment.seed(&mut self.chunk_manager);
+                    self.chunk_manager
+                        .finalize_changes_and_start_frame(wgpu_ctx);
+                    log::info!("Seeding {} chunks inside the containment", chunks);
+                    self.event_log.record(
+                        self.simulate.tick(),
+                        EventKind::Edit("Seeded the containment".to_owned()),
+                    );
+                }
+                let hit = self.pick(&self.stages).and_then(|pick| pick.hit);
+                self.simulate
+                    .selection
+                    .ui(ui, self.chunk_manager.live_bounds(), hit);
+                self.simulate.probe.ui(ui, hit);
+                self.chunk_manager.ui(ui, wgpu_ctx);
+                self.live_bounds.ui(ui);
+                self.occupancy.ui(ui, &self.chunk_manager);
+                self.seam_check.ui(ui);
+                if let Some(direction) = self.resample.ui(ui) {
+                    self.resample_world(wgpu_ctx, direction);
+                }
+                self.demo.ui(ui, &mut self.settings);
+                self.config_ui(ui);
+                #[cfg(feature = "bloom")]
+                if let Some(bloom) = &mut self.bloom {
+                    bloom.ui(ui, event_loop_proxy);
+                }
                 self.tonemap.ui(ui, event_loop_proxy);
+                if cfg!(feature = "overlay") {
+                    self.overlay.theme.ui(ui, &mut self.settings);
+                }
+            }
+            ToolWindow::Stats => {
+                self.stats_ui(ui);
+            }
+            ToolWindow::Profiler => {
+                wgpu_ctx.profiler.ui(ui);
+                ui.separator();
+                ui.push_id("staging", |ui| wgpu_ctx.staging.ui(ui));
+            }
+            ToolWindow::Assets => {
+                match self.asset_browser.ui(ui) {
+                    Some(AssetAction::Save(kind, name, tags)) => {
+                        self.save_asset(wgpu_ctx, kind, name, tags)
+                    }
+                    Some(AssetAction::Load(kind, name)) => self.load_asset(wgpu_ctx, kind, &name),
+                    Some(AssetAction::Stamp(name)) => self.hold_pattern(&name),
+                    None => {}
+                }
+                if let Some(chunks) = self
+                    .importer
+                    .ui(ui, self.chunk_manager.chunk_capacity(wgpu_ctx))
+                {
+                    self.import_world(wgpu_ctx, chunks);
+                }
+            }
+            ToolWindow::Timeline => {
+                if let Some(index) = self.bookmarks.ui(ui) {
+                    self.restore_bookmark(wgpu_ctx, index);
+                }
+                self.event_log.ui(ui);
+            }
+            ToolWindow::Legend => {
+                self.legend.ui(ui, self.simulate.mode);
+            }
+        }
+    }
+
+    /// Everything the stats server reports, from the previous frame's profile
+    #[cfg(all(feature = "stats-server", not(target_arch = "wasm32")))]
+    pub fn engine_stats(&self, ctx: &WgpuContext) -> crate::stats_server::EngineStats {
+        let stats = self.meshing.stats();
+        crate::stats_server::EngineStats {
+            stages: ctx
+                .profiler
+                .prev_frame_info()
+                .iter()
+                .map(|(name, info)| crate::stats_server::StageTiming {
+                    name: name.clone(),
+                    cpu: info.cpu.1,
+                    gpu: info.gpu.map(|gpu| gpu.1),
+                })
+                .collect(),
+            tick: self.simulate.tick(),
+            ticks_per_second: 0.0,
+            paused: !self.simulate.is_running(),
+            population: self.chunk_manager.live_bounds().map(|b| b.live_cells),
+            chunks: self.chunk_manager.chunks().len() as u32,
+            faces: stats.faces,
+            truncated_chunks: stats.truncated_chunks,
+        }
+    }
+
+    /// The config for the next start, most of it can't change while running
+    fn config_ui(&mut self, ui: &mut egui::Ui) {
+        ui.collapsing("Startup", |ui| {
+            ui.add(egui::Slider::new(&mut self.config.world_size, 1..=8).text("World size"))
+                .on_hover_text("Chunks along each axis of the initial world");
+            ui.horizontal(|ui| {
+                if ui
+                    .button("Save current setup")
+                    .on_hover_text(
+                        "Stages, rules, render options and chunk storage as they are now",
+                    )
+                    .clicked()
+                {
+                    self.config.chunks_per_group = Some(self.chunk_manager.chunks_per_group());
+                    self.config.stages = self.stages;
+                    self.config.rule = RuleConfig {
+                        mode: self.simulate.mode,
+                        birth_mask: self.simulate.birth_mask,
+                        survival_mask: self.simulate.survival_mask,
+                    };
+                    self.config.render = RenderConfig {
+                        translucency: self.render.translucency,
+                        sort_faces: self.render.sort_faces,
+                    };
+                    self.config.save(&mut self.settings);
+                }
+                if ui.button("Reset").clicked() {
+                    self.config = EngineConfig::default();
+                    self.config.save(&mut self.settings);
+                }
             });
+        });
+    }
 
-        egui::Window::new("Profiler")
-            .open(&mut self.show_profiler)
+    /// Readout just below the center of the screen, nothing while the pick misses the world
+    fn distance_hud(&self, ctx: &egui::Context) {
+        let Some(hit) = self.pick(&self.frame_stages).and_then(|pick| pick.hit) else {
+            return;
+        };
+        let distance = glm::distance(&self.camera.position, &hit.position);
+        let text = if distance >= CHUNK_SIZE as f32 {
+            format!(
+                "{:.1} voxels ({:.1} chunks)",
+                distance,
+                distance / CHUNK_SIZE as f32
+            )
+        } else {
+            format!("{:.2} voxels", distance)
+        };
+        egui::Area::new(egui::Id::new("distance_hud"))
+            .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 32.0))
+            .interactable(false)
             .show(ctx, |ui| {
-                wgpu_ctx.profiler.ui(ui);
+                egui::Frame::popup(ui.style()).show(ui, |ui| {
+                    ui.label(text);
+                });
             });
     }
 
-    pub fn after_submit(&self) {
-        self.picker.after_submit();
+    /// Simulation and meshing stats, laid out along the current layout direction
+    fn stats_ui(&self, ui: &mut egui::Ui) {
+        let stats = self.meshing.stats();
+        match self.simulate.pause_at() {
+            Some(pause_at) => ui.label(format!("Tick: {} / {}", self.simulate.tick(), pause_at)),
+            None => ui.label(format!("Tick: {}", self.simulate.tick())),
+        };
+        ui.separator();
+        ui.label(format!("Chunks: {}", stats.chunks));
+        ui.separator();
+        ui.label(format!("Faces rendered: {}", stats.faces));
+        ui.separator();
+        ui.label(format!(
+            "Face buffers: {:.1} MiB",
+            stats.allocated_faces as f64 * 8.0 / (1024.0 * 1024.0)
+        ));
+        if stats.truncated_chunks > 0 {
+            ui.separator();
+            ui.label(format!("Truncated chunks: {}", stats.truncated_chunks));
+        }
+        if let Some((generated, total)) = self.worldgen.progress() {
+            ui.separator();
+            ui.add(
+                egui::ProgressBar::new(generated as f32 / total.max(1) as f32)
+                    .desired_width(160.0)
+                    .text(format!("Generating chunks {}/{}", generated, total)),
+            );
+        }
+        if let Some((uploaded, total)) = self.chunk_manager.upload_progress() {
+            ui.separator();
+            ui.add(
+                egui::ProgressBar::new(uploaded as f32 / total as f32)
+                    .desired_width(160.0)
+                    .text(format!("Uploading chunks {}/{}", uploaded, total)),
+            );
+        }
+        if let Some(hit) = self
+            .pick(&self.frame_stages)
+            .and_then(|pick| pick.hit.map(|hit| (pick.color, hit)))
+        {
+            let (color, hit) = hit;
+            ui.separator();
+            ui.label(format!(
+                "Voxel: {} {} {} ({})",
+                hit.voxel.x,
+                hit.voxel.y,
+                hit.voxel.z,
+                hit.face_name()
+            ))
+            .on_hover_text(format!(
+                "Surface at {:.2} {:.2} {:.2}\nColor {:.2} {:.2} {:.2}",
+                hit.position.x, hit.position.y, hit.position.z, color.x, color.y, color.z
+            ));
+        }
+        if let Some(error) = self.chunk_manager.capacity_error() {
+            ui.separator();
+            ui.colored_label(ui.visuals().error_fg_color, error);
+        }
+        if let Some(live_bounds) = self.chunk_manager.live_bounds() {
+            let growth = self.live_bounds.stats();
+            let size = live_bounds.size();
+            ui.separator();
+            ui.label(format!(
+                "Live cells: {} ({:+.1}/tick)",
+                live_bounds.live_cells, growth.cell_growth
+            ));
+            ui.separator();
+            ui.label(format!("Bounds: {}x{}x{}", size.x, size.y, size.z))
+                .on_hover_text(format!(
+                    "{:?} to {:?}\nVolume {:+.1}/tick",
+                    live_bounds.min.as_slice(),
+                    live_bounds.max.as_slice(),
+                    growth.volume_growth
+                ));
+        }
+    }
+
+    pub fn after_submit(&mut self) {
+        #[cfg(feature = "picker")]
+        if let Some(picker) = self.picker.as_mut().filter(|_| self.frame_stages.picker) {
+            picker.after_submit();
+        }
+        self.meshing.after_submit();
+        self.seam_check.after_submit();
+        self.live_bounds.after_submit();
+        self.occupancy.after_submit();
+        self.legend.after_submit();
+        self.simulate.after_submit();
+        #[cfg(feature = "recording")]
+        self.frame_capture.after_submit();
     }
 }
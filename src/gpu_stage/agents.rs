@@ -0,0 +1,407 @@
+use std::collections::VecDeque;
+use std::mem::size_of;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use bytemuck::{Pod, Zeroable};
+use nalgebra_glm as glm;
+use rand::{thread_rng, Rng};
+use wgpu::*;
+
+use crate::chunk_manager::ChunkManager;
+use crate::gpu_stage::overlay::Overlay;
+use crate::shader_prep::ShaderPrep;
+use crate::wgpu_context::WgpuContext;
+
+/// Agents the buffers have room for
+pub const MAX_AGENTS: u32 = 256;
+// Positions remembered per agent for the trail in the overlay
+const TRAIL_LENGTH: usize = 64;
+const WORKGROUP_SIZE: u32 = 64;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable, Default)]
+struct Agent {
+    pos: [i32; 3],
+    dir: u32,
+    up: u32,
+    color: u32,
+    steps: u32,
+    _pad0: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable, Default)]
+struct AgentPushConstants {
+    num_agents: u32,
+    chunks_per_buffer_shift: u32,
+    which: u32,
+    on_empty: u32,
+    on_filled: u32,
+    _pad0: [u32; 3],
+}
+
+/// What an agent does on a cell, relative to where it heads and which way is up for it
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Turn {
+    None = 0,
+    Right = 1,
+    Left = 2,
+    Up = 3,
+    Down = 4,
+    Back = 5,
+}
+
+impl Turn {
+    pub const ALL: [Turn; 6] = [
+        Turn::None,
+        Turn::Right,
+        Turn::Left,
+        Turn::Up,
+        Turn::Down,
+        Turn::Back,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Turn::None => "Straight",
+            Turn::Right => "Right",
+            Turn::Left => "Left",
+            Turn::Up => "Up",
+            Turn::Down => "Down",
+            Turn::Back => "Back",
+        }
+    }
+}
+
+/// Turmites walking the grid, one cell per tick: each flips the cell beneath it between empty and
+/// its own color, turns depending on what it found and moves ahead. They run between the ticks
+/// of the regular simulation, so the cells they leave behind are simulated as well. Like the heat
+/// field, tick t steps from buffer t & 1 into the other one so running a tick again gives the same
+/// agents. Agents aren't part of snapshots or saved worlds.
+pub struct Agents {
+    pub on_empty: Turn,
+    pub on_filled: Turn,
+    pub show_trails: bool,
+    pipeline: ComputePipeline,
+    buffers: [Buffer; 2],
+    // Indexed by the buffer the step reads, it writes the other one
+    bind_groups: [BindGroup; 2],
+    count: u32,
+    pending: Vec<Agent>,
+    readback_buffer: Buffer,
+    readback_count: u32,
+    // Bumped by clearing, so a readback from before doesn't bring the old trails back
+    generation: u64,
+    readback_generation: u64,
+    copied: bool,
+    map_requested: bool,
+    mapped: Arc<AtomicBool>,
+    trails: Vec<VecDeque<glm::IVec3>>,
+    colors: Vec<u32>,
+    steps: u32,
+}
+
+impl Agents {
+    pub fn new(ctx: &WgpuContext, chunk_manager: &ChunkManager) -> Self {
+        let source = ShaderPrep::new().process(include_str!("./agents.wgsl"));
+        let shader = ctx.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("agents shader"),
+            source: ShaderSource::Wgsl(source.into()),
+        });
+
+        let storage_entry = |binding, read_only| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = ctx
+            .device
+            .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("agents bind_group_layout"),
+                entries: &[storage_entry(0, true), storage_entry(1, false)],
+            });
+        let pipeline_layout = ctx
+            .device
+            .create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some("agents pipeline_layout"),
+                bind_group_layouts: &[&bind_group_layout, chunk_manager.bind_group_layout(true)],
+                push_constant_ranges: &[PushConstantRange {
+                    stages: ShaderStages::COMPUTE,
+                    range: 0..size_of::<AgentPushConstants>() as u32,
+                }],
+            });
+        let pipeline = ctx
+            .device
+            .create_compute_pipeline(&ComputePipelineDescriptor {
+                label: Some("agents pipeline"),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point: "cs_step",
+            });
+
+        let buffer_size = MAX_AGENTS as u64 * size_of::<Agent>() as u64;
+        let buffers = [0, 1].map(|_| {
+            ctx.device.create_buffer(&BufferDescriptor {
+                label: Some("agents agent_buffer"),
+                size: buffer_size,
+                usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            })
+        });
+        let bind_groups = [0, 1].map(|src| {
+            ctx.device.create_bind_group(&BindGroupDescriptor {
+                label: Some("agents bind_group"),
+                layout: &bind_group_layout,
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: buffers[src].as_entire_binding(),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: buffers[src ^ 1].as_entire_binding(),
+                    },
+                ],
+            })
+        });
+        let readback_buffer = ctx.device.create_buffer(&BufferDescriptor {
+            label: Some("agents readback_buffer"),
+            size: buffer_size,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            on_empty: Turn::Right,
+            on_filled: Turn::Left,
+            show_trails: true,
+            pipeline,
+            buffers,
+            bind_groups,
+            count: 0,
+            pending: Vec::new(),
+            readback_buffer,
+            readback_count: 0,
+            generation: 0,
+            readback_generation: 0,
+            copied: false,
+            map_requested: false,
+            mapped: Arc::new(AtomicBool::new(false)),
+            trails: Vec::new(),
+            colors: Vec::new(),
+            steps: 0,
+        }
+    }
+
+    /// Whether there are agents to step every tick
+    pub fn active(&self) -> bool {
+        self.count > 0
+    }
+
+    fn total(&self) -> u32 {
+        self.count + self.pending.len() as u32
+    }
+
+    /// Queues an agent at `pos` heading in a random direction, uploaded with the next update
+    pub fn spawn(&mut self, pos: &glm::Vec3) {
+        if self.total() >= MAX_AGENTS {
+            log::warn!("Can't have more than {} agents", MAX_AGENTS);
+            return;
+        }
+        let mut rng = thread_rng();
+        let dir = rng.gen_range(0..6u32);
+        // Any direction on another axis is perpendicular
+        let up = (dir / 2 + rng.gen_range(1..3u32)) % 3 * 2 + rng.gen_range(0..2u32);
+        let color = egui::Color32::from(egui::ecolor::Hsva::new(
+            rng.gen_range(0.0..1.0),
+            0.8,
+            1.0,
+            1.0,
+        ));
+        let pos = pos.map(|c| c.floor() as i32);
+        self.pending.push(Agent {
+            pos: [pos.x, pos.y, pos.z],
+            dir,
+            up,
+            color: u32::from_le_bytes(color.to_array()),
+            ..Default::default()
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.count = 0;
+        self.pending.clear();
+        self.trails.clear();
+        self.colors.clear();
+        self.steps = 0;
+        self.generation += 1;
+    }
+
+    /// Takes in the last readback, uploads the agents spawned since and copies them out again for
+    /// the trails. `tick` is the next one to run.
+    pub fn update(&mut self, ctx: &WgpuContext, encoder: &mut CommandEncoder, tick: u64) {
+        if self.mapped.load(Ordering::Acquire) {
+            self.process_readback();
+        }
+        let current = &self.buffers[(tick & 1) as usize];
+        if !self.pending.is_empty() {
+            ctx.staging.write(
+                &ctx.device,
+                &ctx.queue,
+                encoder,
+                "agents spawn",
+                current,
+                self.count as u64 * size_of::<Agent>() as u64,
+                bytemuck::cast_slice(&self.pending),
+            );
+            self.count += self.pending.len() as u32;
+            self.pending.clear();
+        }
+        if self.count == 0 || !self.show_trails || self.map_requested || self.copied {
+            return;
+        }
+        encoder.copy_buffer_to_buffer(
+            current,
+            0,
+            &self.readback_buffer,
+            0,
+            self.count as u64 * size_of::<Agent>() as u64,
+        );
+        self.readback_count = self.count;
+        self.readback_generation = self.generation;
+        self.copied = true;
+    }
+
+    /// Steps the agents for `tick`, with `which` the buffer the tick wrote its cells into. The
+    /// caller has to set its own pipeline and bind groups again afterwards.
+    pub fn encode_step<'a>(
+        &'a self,
+        compute_pass: &mut ComputePass<'a>,
+        chunk_manager: &'a ChunkManager,
+        tick: u64,
+        which: u32,
+    ) {
+        compute_pass.set_pipeline(&self.pipeline);
+        compute_pass.set_bind_group(0, &self.bind_groups[(tick & 1) as usize], &[]);
+        compute_pass.set_bind_group(1, chunk_manager.bind_group(true), &[]);
+        compute_pass.set_push_constants(
+            0,
+            bytemuck::bytes_of(&AgentPushConstants {
+                num_agents: self.count,
+                chunks_per_buffer_shift: chunk_manager.chunks_per_group().ilog2(),
+                which,
+                on_empty: self.on_empty as u32,
+                on_filled: self.on_filled as u32,
+                ..Default::default()
+            }),
+        );
+        compute_pass.dispatch_workgroups(self.count.div_ceil(WORKGROUP_SIZE), 1, 1);
+    }
+
+    fn process_readback(&mut self) {
+        let size = self.readback_count as u64 * size_of::<Agent>() as u64;
+        if self.readback_generation == self.generation {
+            let range = self.readback_buffer.slice(..size).get_mapped_range();
+            let agents: &[Agent] = bytemuck::cast_slice(&range);
+            self.trails.resize_with(agents.len(), VecDeque::new);
+            self.colors.clear();
+            for (agent, trail) in agents.iter().zip(&mut self.trails) {
+                let pos = glm::IVec3::from(agent.pos);
+                if trail.back() != Some(&pos) {
+                    trail.push_back(pos);
+                }
+                if trail.len() > TRAIL_LENGTH {
+                    trail.pop_front();
+                }
+                self.colors.push(agent.color);
+            }
+            self.steps = agents.iter().map(|a| a.steps).max().unwrap_or(0);
+        }
+        self.readback_buffer.unmap();
+        self.mapped.store(false, Ordering::Release);
+        self.map_requested = false;
+    }
+
+    pub fn after_submit(&mut self) {
+        if !self.copied {
+            return;
+        }
+        self.copied = false;
+        self.map_requested = true;
+        let mapped = self.mapped.clone();
+        let size = self.readback_count as u64 * size_of::<Agent>() as u64;
+        self.readback_buffer
+            .slice(..size)
+            .map_async(MapMode::Read, move |result| match result {
+                Ok(_) => mapped.store(true, Ordering::Release),
+                Err(e) => log::error!("Failed to map agents readback buffer: {:?}", e),
+            });
+    }
+
+    pub fn draw(&self, overlay: &Overlay) {
+        if !self.show_trails {
+            return;
+        }
+        let center = |p: &glm::IVec3| p.cast::<f32>() + glm::vec3(0.5, 0.5, 0.5);
+        for (trail, color) in self.trails.iter().zip(&self.colors) {
+            let color = glm::Vec4::from(color.to_le_bytes().map(|c| c as f32 / 255.0));
+            for (a, b) in trail.iter().zip(trail.iter().skip(1)) {
+                overlay.line(color, (center(a), center(b)));
+            }
+            if let Some(head) = trail.back() {
+                let min = head.cast::<f32>();
+                overlay.cuboid(color, min, min + glm::vec3(1.0, 1.0, 1.0));
+            }
+        }
+    }
+
+    fn turn_ui(ui: &mut egui::Ui, label: &str, turn: &mut Turn) {
+        egui::ComboBox::from_label(label)
+            .selected_text(turn.name())
+            .show_ui(ui, |ui| {
+                for t in Turn::ALL {
+                    ui.selectable_value(turn, t, t.name());
+                }
+            });
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.collapsing("Agents", |ui| {
+            ui.label("Middle click spawns an agent where the brush paints");
+            Self::turn_ui(ui, "On empty cells", &mut self.on_empty);
+            Self::turn_ui(ui, "On filled cells", &mut self.on_filled);
+            ui.horizontal(|ui| {
+                if ui.button("Langton's ant").clicked() {
+                    self.on_empty = Turn::Right;
+                    self.on_filled = Turn::Left;
+                }
+                if ui
+                    .button("Climber")
+                    .on_hover_text("Pitches up on empty cells, so it leaves the plane")
+                    .clicked()
+                {
+                    self.on_empty = Turn::Up;
+                    self.on_filled = Turn::Left;
+                }
+            });
+            ui.checkbox(&mut self.show_trails, "Show trails");
+            ui.horizontal(|ui| {
+                ui.label(format!(
+                    "{} of {} agents, {} steps",
+                    self.total(),
+                    MAX_AGENTS,
+                    self.steps
+                ));
+                if ui.button("Clear").clicked() {
+                    self.clear();
+                }
+            });
+        });
+    }
+}
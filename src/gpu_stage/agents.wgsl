@@ -0,0 +1,109 @@
+#include "common.wgsl"
+
+struct Agent {
+    pos: vec3<i32>,
+    // Indices into the six axis directions, see `direction`
+    dir: u32,
+    up: u32,
+    color: u32,
+    steps: u32,
+    _pad0: u32,
+};
+
+struct PushConstants {
+    @size(4) num_agents: u32,
+    @size(4) chunks_per_buffer_shift: u32,
+    @size(4) which: u32,
+    // Turn taken on an empty and on a filled cell
+    @size(4) on_empty: u32,
+    @size(4) on_filled: u32,
+    @size(12) _pad0: u32,
+};
+
+const TURN_RIGHT: u32 = 1u;
+const TURN_LEFT: u32 = 2u;
+const TURN_UP: u32 = 3u;
+const TURN_DOWN: u32 = 4u;
+const TURN_BACK: u32 = 5u;
+
+var<push_constant> consts: PushConstants;
+
+@group(0) @binding(0)
+var<storage, read> src: array<Agent>;
+
+@group(0) @binding(1)
+var<storage, read_write> dst: array<Agent>;
+
+@group(1) @binding(0)
+var atlas: texture_storage_3d<{{CHUNK_FORMAT}}, read>;
+
+@group(1) @binding(1)
+var grids: binding_array<texture_storage_3d<{{CHUNK_FORMAT}}, read_write>, 8>;
+
+// +x, -x, +y, -y, +z, -z
+fn direction(i: u32) -> vec3<i32> {
+    var v = vec3<i32>(0);
+    v[i / 2u] = select(1, -1, (i & 1u) != 0u);
+    return v;
+}
+
+fn direction_index(v: vec3<i32>) -> u32 {
+    let axis = select(select(2u, 1u, v.y != 0), 0u, v.x != 0);
+    return axis * 2u + select(0u, 1u, v[axis] < 0);
+}
+
+fn turn(agent: ptr<function, Agent>, kind: u32) {
+    let forward = vec3<f32>(direction((*agent).dir));
+    let up = vec3<f32>(direction((*agent).up));
+    switch kind {
+        case TURN_RIGHT: {
+            (*agent).dir = direction_index(vec3<i32>(cross(forward, up)));
+        }
+        case TURN_LEFT: {
+            (*agent).dir = direction_index(vec3<i32>(cross(up, forward)));
+        }
+        case TURN_UP: {
+            (*agent).up = (*agent).dir ^ 1u;
+            (*agent).dir = direction_index(vec3<i32>(up));
+        }
+        case TURN_DOWN: {
+            (*agent).up = (*agent).dir;
+            (*agent).dir = direction_index(vec3<i32>(up)) ^ 1u;
+        }
+        case TURN_BACK: {
+            (*agent).dir ^= 1u;
+        }
+        default: {}
+    }
+}
+
+// Flips the cell beneath every agent between empty and the agent's color, turns depending on
+// what was there and moves one cell ahead. Agents outside of the loaded chunks only turn and move.
+@compute
+@workgroup_size(64)
+fn cs_step(@builtin(global_invocation_id) gid: vec3<u32>) {
+    if(gid.x >= consts.num_agents) {
+        return;
+    }
+    var agent = src[gid.x];
+
+    let atlas_pos = (agent.pos >> vec3<u32>(CHUNK_SHIFT)) + vec3<i32>(ATLAS_OFFSET);
+    var chunk = 0u;
+    if(all(atlas_pos >= vec3<i32>(0)) && all(atlas_pos < vec3<i32>(textureDimensions(atlas)))) {
+        chunk = textureLoad(atlas, atlas_pos).r;
+    }
+    var filled = false;
+    if(chunk != 0u) {
+        let chunk_idx = chunk - 1u;
+        let group = chunk_idx >> consts.chunks_per_buffer_shift;
+        let origin_x = chunk_idx & ((1u << consts.chunks_per_buffer_shift) - 1u);
+        let texel = vec3<u32>(agent.pos & vec3<i32>(CHUNK_SIZE - 1)) + vec3<u32>(origin_x, 0u, consts.which) * CHUNK_SIZE_U;
+        filled = textureLoad(grids[group], texel).r != 0u;
+        textureStore(grids[group], texel, vec4<u32>(select(agent.color, 0u, filled), 0u, 0u, 0u));
+    }
+
+    turn(&agent, select(consts.on_empty, consts.on_filled, filled));
+    agent.pos += direction(agent.dir);
+    agent.steps += 1u;
+    dst[gid.x] = agent;
+}
@@ -6,6 +6,8 @@ use nalgebra_glm as glm;
 use wgpu::*;
 use winit::event_loop::EventLoopProxy;
 
+use crate::gpu_stage::frame_graph::TargetStage;
+use crate::resource_tracker::{self, Tracked};
 use crate::user_event::UserEvent;
 use crate::util::*;
 use crate::wgpu_context::WgpuContext;
@@ -33,9 +35,9 @@ struct DynamicResources {
 
     downsample_pipeline: ComputePipeline,
     upsample_pipeline: ComputePipeline,
-    per_pass_bind_group_downsample: Vec<BindGroup>,
-    per_pass_bind_group_upsample: Vec<BindGroup>,
-    upsample_uniforms: Vec<Buffer>,
+    per_pass_bind_group_downsample: Vec<Tracked<BindGroup>>,
+    per_pass_bind_group_upsample: Vec<Tracked<BindGroup>>,
+    upsample_uniforms: Vec<Tracked<Buffer>>,
 }
 
 pub struct Bloom {
@@ -245,40 +247,50 @@ impl DynamicResources {
         let (_upsample_buffer, upsample_view, upsample_pipeline) =
             create_resources_for_shader("cs_upsample", &res.upsample_pipeline_layout);
 
-        let renderbuffer_view = downsample_buffer.create_view(&TextureViewDescriptor {
-            base_mip_level: 0,
-            mip_level_count: Some(1),
-            ..Default::default()
-        });
+        let renderbuffer_view = resource_tracker::view(
+            &downsample_buffer,
+            &TextureViewDescriptor {
+                label: Some("bloom renderbuffer_view"),
+                base_mip_level: 0,
+                mip_level_count: Some(1),
+                ..Default::default()
+            },
+        );
 
         let per_pass_bind_group_downsample = (0..(mips - 1) as usize)
             .map(|i| {
-                ctx.device.create_bind_group(&BindGroupDescriptor {
-                    label: Some("bloom per_pass_bind_group_downsample"),
-                    layout: &res.downsample_group_layout,
-                    entries: &[
-                        BindGroupEntry {
-                            binding: 0,
-                            resource: BindingResource::TextureView(&downsample_view[i]),
-                        },
-                        BindGroupEntry {
-                            binding: 1,
-                            resource: BindingResource::TextureView(&downsample_view[i + 1]),
-                        },
-                    ],
-                })
+                resource_tracker::bind_group(
+                    ctx,
+                    &BindGroupDescriptor {
+                        label: Some("bloom per_pass_bind_group_downsample"),
+                        layout: &res.downsample_group_layout,
+                        entries: &[
+                            BindGroupEntry {
+                                binding: 0,
+                                resource: BindingResource::TextureView(&downsample_view[i]),
+                            },
+                            BindGroupEntry {
+                                binding: 1,
+                                resource: BindingResource::TextureView(&downsample_view[i + 1]),
+                            },
+                        ],
+                    },
+                )
             })
             .collect::<Vec<_>>();
 
-        let (per_pass_bind_group_upsample, upsample_uniforms): (Vec<BindGroup>, Vec<Buffer>) = (0
-            ..(mips - 1) as usize)
+        let (per_pass_bind_group_upsample, upsample_uniforms): (Vec<_>, Vec<_>) = (0..(mips - 1)
+            as usize)
             .map(|i| {
-                let uniform_buffer = ctx.device.create_buffer(&BufferDescriptor {
-                    label: Some("bloom uniform_buffer"),
-                    size: size_of::<Uniforms>() as u64,
-                    usage: BufferUsages::COPY_DST | BufferUsages::UNIFORM,
-                    mapped_at_creation: false,
-                });
+                let uniform_buffer = resource_tracker::buffer(
+                    ctx,
+                    &BufferDescriptor {
+                        label: Some("bloom uniform_buffer"),
+                        size: size_of::<Uniforms>() as u64,
+                        usage: BufferUsages::COPY_DST | BufferUsages::UNIFORM,
+                        mapped_at_creation: false,
+                    },
+                );
 
                 ctx.queue.write_buffer(
                     &uniform_buffer,
@@ -296,36 +308,41 @@ impl DynamicResources {
                     }),
                 );
 
-                let bind_group = ctx.device.create_bind_group(&BindGroupDescriptor {
-                    label: Some("bloom bind_group"),
-                    layout: &res.upsample_group_layout,
-                    entries: &[
-                        BindGroupEntry {
-                            binding: 0,
-                            resource: BindingResource::TextureView(if i == (mips - 2) as usize {
-                                &downsample_view[i + 1]
-                            } else {
-                                &upsample_view[i + 1]
-                            }),
-                        },
-                        BindGroupEntry {
-                            binding: 1,
-                            resource: BindingResource::TextureView(if i == 0 {
-                                &output_target.render_target
-                            } else {
-                                &upsample_view[i]
-                            }),
-                        },
-                        BindGroupEntry {
-                            binding: 2,
-                            resource: uniform_buffer.as_entire_binding(),
-                        },
-                        BindGroupEntry {
-                            binding: 3,
-                            resource: BindingResource::TextureView(&downsample_view[i]),
-                        },
-                    ],
-                });
+                let bind_group = resource_tracker::bind_group(
+                    ctx,
+                    &BindGroupDescriptor {
+                        label: Some("bloom bind_group"),
+                        layout: &res.upsample_group_layout,
+                        entries: &[
+                            BindGroupEntry {
+                                binding: 0,
+                                resource: BindingResource::TextureView(
+                                    if i == (mips - 2) as usize {
+                                        &downsample_view[i + 1]
+                                    } else {
+                                        &upsample_view[i + 1]
+                                    },
+                                ),
+                            },
+                            BindGroupEntry {
+                                binding: 1,
+                                resource: BindingResource::TextureView(if i == 0 {
+                                    &output_target.render_target
+                                } else {
+                                    &upsample_view[i]
+                                }),
+                            },
+                            BindGroupEntry {
+                                binding: 2,
+                                resource: uniform_buffer.as_entire_binding(),
+                            },
+                            BindGroupEntry {
+                                binding: 3,
+                                resource: BindingResource::TextureView(&downsample_view[i]),
+                            },
+                        ],
+                    },
+                );
                 (bind_group, uniform_buffer)
             })
             .unzip();
@@ -365,9 +382,6 @@ impl Bloom {
             mip_limit: 12,
         }
     }
-    pub fn resize(&mut self, ctx: &WgpuContext, output_target: Rc<RenderTarget>) {
-        self.dynamic = DynamicResources::new(ctx, &mut self.res, self.mip_limit, output_target);
-    }
 
     pub fn update(&mut self, ctx: &WgpuContext, command_encoder: &mut CommandEncoder) {
         if self.res.texture_desc.mip_level_count == 1 {
@@ -384,7 +398,11 @@ impl Bloom {
             scale_fact: glm::mix(&no_bloom, &full_bloom, self.bloom_factor),
             ..Default::default()
         };
-        ctx.queue.write_buffer(
+        ctx.staging.write(
+            &ctx.device,
+            &ctx.queue,
+            command_encoder,
+            "bloom upsample_uniforms",
             self.dynamic.upsample_uniforms.first().unwrap(),
             0,
             bytemuck::bytes_of(&uniforms),
@@ -424,15 +442,6 @@ impl Bloom {
         }
     }
 
-    pub fn input_target(&self) -> Rc<RenderTarget> {
-        // If mip level is 1, bypass bloom altogether
-        if self.res.texture_desc.mip_level_count == 1 {
-            self.dynamic.output_target.clone()
-        } else {
-            self.dynamic.input_target.clone()
-        }
-    }
-
     pub fn ui(&mut self, ui: &mut egui::Ui, elp: &EventLoopProxy<UserEvent>) {
         ui.collapsing("Bloom", |ui| {
             ui.add(egui::Slider::new(&mut self.bloom_factor, 0.0..=1.0).text("Bloom Factor"));
@@ -444,3 +453,18 @@ impl Bloom {
         });
     }
 }
+
+impl TargetStage for Bloom {
+    fn resize(&mut self, ctx: &WgpuContext, output_target: Rc<RenderTarget>) {
+        self.dynamic = DynamicResources::new(ctx, &mut self.res, self.mip_limit, output_target);
+    }
+
+    fn input_target(&self) -> Rc<RenderTarget> {
+        // If mip level is 1, bypass bloom altogether
+        if self.res.texture_desc.mip_level_count == 1 {
+            self.dynamic.output_target.clone()
+        } else {
+            self.dynamic.input_target.clone()
+        }
+    }
+}
@@ -0,0 +1,546 @@
+use std::mem::size_of;
+
+use bytemuck::{Pod, Zeroable};
+use nalgebra_glm as glm;
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::*;
+
+use crate::camera::Camera;
+use crate::chunk::CHUNK_SIZE;
+use crate::chunk_manager::{ChunkManager, VoxelReadback};
+use crate::gpu_stage::overlay::Overlay;
+use crate::gpu_stage::pick::PickHit;
+use crate::materials::Material;
+use crate::shader_prep::ShaderPrep;
+use crate::theme;
+use crate::wgpu_context::WgpuContext;
+
+const WORKGROUP_SIZE: u32 = 4;
+
+/// Planes and rotations every stroke is replicated across, all through a common origin
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Symmetry {
+    pub mirror: [bool; 3],
+    // 4-fold about the vertical axis
+    pub rotational: bool,
+}
+
+impl Symmetry {
+    fn rotations(&self) -> u32 {
+        if self.rotational {
+            4
+        } else {
+            1
+        }
+    }
+
+    /// Every copy of `center`, including itself. Same as brush_image in the shader.
+    pub fn images(&self, center: &glm::Vec3, origin: &glm::Vec3) -> Vec<glm::Vec3> {
+        (0..self.rotations() * 8)
+            .filter(|i| (0..3).all(|axis| i >> axis & 1 == 0 || self.mirror[axis]))
+            .map(|i| {
+                let mut p = center - origin;
+                for axis in 0..3 {
+                    if i >> axis & 1 != 0 {
+                        p[axis] = -p[axis];
+                    }
+                }
+                for _ in 0..i >> 3 {
+                    p = glm::vec3(p.z, p.y, -p.x);
+                }
+                p + origin
+            })
+            .collect()
+    }
+}
+
+/// Keeps strokes on a plane or a line through where the stroke started
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Lock {
+    #[default]
+    Off,
+    // The plane perpendicular to this axis
+    Plane(usize),
+    Axis(usize),
+}
+
+/// Aids for placing strokes precisely. The toolbar settings hold all the time, the modifier keys
+/// only while held: Ctrl locks to the plane facing the camera, Ctrl+Alt to the axis going across
+/// the screen and Alt extrudes from the picked face.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Placement {
+    pub lock: Lock,
+    // Stroke centers go to the middle of cells of a grid this many voxels wide, 0 doesn't snap
+    pub snap: u32,
+    // Strokes start on the face under the crosshair and grow out along its normal
+    pub extrude: bool,
+}
+
+/// Modifier keys held this frame
+#[derive(Copy, Clone, Debug, Default)]
+pub struct PlacementKeys {
+    pub plane: bool,
+    pub axis: bool,
+    pub extrude: bool,
+}
+
+// Index of the largest component
+fn dominant_axis(v: &glm::Vec3) -> usize {
+    let a = v.abs();
+    if a.x >= a.y && a.x >= a.z {
+        0
+    } else if a.y >= a.z {
+        1
+    } else {
+        2
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable, Default)]
+struct BrushUniformPod {
+    center: [f32; 3],
+    radius: f32,
+    origin: [f32; 3],
+    value: u32,
+    mirror: [u32; 3],
+    rotations: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable, Default)]
+struct BrushPushConstants {
+    group: u32,
+    origin_x: u32,
+    which: u32,
+    _pad0: u32,
+    chunk_pos: [i32; 4],
+}
+
+/// A sphere of cells the brush painted
+pub struct Stroke {
+    pub center: glm::Vec3,
+    pub radius: f32,
+    pub value: u32,
+}
+
+/// Paints spheres of cells at a fixed distance in front of the camera while the left mouse button
+/// is held, replicated across the symmetry planes
+pub struct Brush {
+    pub enabled: bool,
+    pub radius: f32,
+    pub distance: f32,
+    pub color: [f32; 3],
+    // Paints this powder material instead of the color when set
+    pub material: Option<Material>,
+    pub erase: bool,
+    pub symmetry: Symmetry,
+    pub placement: Placement,
+    // Lock and extrusion in effect this frame, the toolbar settings with the held keys on top
+    lock: Lock,
+    extrude: bool,
+    // Where the stroke was when the lock took effect
+    anchor: Option<glm::Vec3>,
+    pick: Option<PickHit>,
+    painting: bool,
+    // Pending read of the voxel at the target, its color becomes the brush color
+    eyedropper: Option<VoxelReadback>,
+    last_stroke: Option<glm::Vec3>,
+    pipeline: ComputePipeline,
+    uniform_buffer: Buffer,
+    bind_group: BindGroup,
+}
+
+impl Brush {
+    pub fn new(ctx: &WgpuContext, chunk_manager: &ChunkManager) -> Self {
+        let source = ShaderPrep::new().process(include_str!("./brush.wgsl"));
+        let shader = ctx.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("brush shader"),
+            source: ShaderSource::Wgsl(source.into()),
+        });
+
+        let bind_group_layout = ctx
+            .device
+            .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("brush bind_group_layout"),
+                entries: &[BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+            });
+
+        let pipeline_layout = ctx
+            .device
+            .create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some("brush pipeline_layout"),
+                bind_group_layouts: &[&bind_group_layout, chunk_manager.bind_group_layout(true)],
+                push_constant_ranges: &[PushConstantRange {
+                    stages: ShaderStages::COMPUTE,
+                    range: 0..size_of::<BrushPushConstants>() as u32,
+                }],
+            });
+
+        let pipeline = ctx
+            .device
+            .create_compute_pipeline(&ComputePipelineDescriptor {
+                label: Some("brush pipeline"),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point: "cs_paint",
+            });
+
+        let uniform_buffer = ctx.device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("brush uniform_buffer"),
+            contents: bytemuck::bytes_of(&BrushUniformPod::default()),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+        let bind_group = ctx.device.create_bind_group(&BindGroupDescriptor {
+            label: Some("brush bind_group"),
+            layout: &bind_group_layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+        });
+
+        Self {
+            enabled: false,
+            radius: 3.0,
+            distance: 16.0,
+            color: [1.0, 0.8, 0.2],
+            material: None,
+            erase: false,
+            symmetry: Symmetry::default(),
+            placement: Placement::default(),
+            lock: Lock::Off,
+            extrude: false,
+            anchor: None,
+            pick: None,
+            painting: false,
+            last_stroke: None,
+            eyedropper: None,
+            pipeline,
+            uniform_buffer,
+            bind_group,
+        }
+    }
+
+    pub fn set_painting(&mut self, painting: bool) {
+        self.painting = painting && self.enabled;
+        if !self.painting {
+            self.last_stroke = None;
+            self.anchor = None;
+        }
+    }
+
+    /// Takes in the held modifier keys and what's under the crosshair, before the frame's
+    /// strokes are placed
+    pub fn prepare(&mut self, camera: &Camera, keys: PlacementKeys, pick: Option<PickHit>) {
+        self.pick = pick;
+        self.extrude = self.placement.extrude || keys.extrude;
+        let lock = if keys.plane {
+            Lock::Plane(dominant_axis(&camera.forward()))
+        } else if keys.axis {
+            Lock::Axis(dominant_axis(&camera.right()))
+        } else {
+            self.placement.lock
+        };
+        if lock != self.lock {
+            self.anchor = None;
+        }
+        self.lock = lock;
+        if self.lock == Lock::Off {
+            self.anchor = None;
+        } else if self.anchor.is_none() {
+            self.anchor = Some(self.free_target(camera));
+        }
+    }
+
+    // Where the stroke goes before locking and snapping
+    fn free_target(&self, camera: &Camera) -> glm::Vec3 {
+        match self.pick.filter(|_| self.extrude) {
+            Some(hit) => {
+                let normal = hit.normal.cast::<f32>();
+                // The sphere's edge touches the cell in front of the face
+                hit.adjacent().cast::<f32>().add_scalar(0.5) + normal * (self.radius - 0.5).max(0.0)
+            }
+            None => camera.position + camera.forward() * self.distance,
+        }
+    }
+
+    pub fn target(&self, camera: &Camera) -> glm::Vec3 {
+        let free = self.free_target(camera);
+        let mut target = match (self.lock, self.anchor) {
+            (Lock::Plane(axis), Some(anchor)) => {
+                // Where the view ray crosses the plane, when it does in front of the camera
+                let forward = camera.forward();
+                let t = (anchor[axis] - camera.position[axis]) / forward[axis];
+                if t.is_finite() && t > 0.0 && !self.extrude {
+                    camera.position + forward * t
+                } else {
+                    let mut target = free;
+                    target[axis] = anchor[axis];
+                    target
+                }
+            }
+            (Lock::Axis(axis), Some(anchor)) => {
+                // The point on the line closest to the view ray
+                let mut direction = glm::Vec3::zeros();
+                direction[axis] = 1.0;
+                let forward = if self.extrude {
+                    glm::normalize(&(free - camera.position))
+                } else {
+                    camera.forward()
+                };
+                let w = anchor - camera.position;
+                let b = direction.dot(&forward);
+                let denom = 1.0 - b * b;
+                let s = if denom > 1e-4 {
+                    (b * forward.dot(&w) - direction.dot(&w)) / denom
+                } else {
+                    0.0
+                };
+                anchor + direction * s
+            }
+            _ => free,
+        };
+        if self.placement.snap > 0 {
+            let size = self.placement.snap as f32;
+            target = (target / size).map(|c| c.floor()) * size;
+            target = target.add_scalar(0.5);
+        }
+        target
+    }
+
+    /// Boxes the next stroke will paint, one per symmetric copy, empty while not painting
+    pub fn stroke_bounds(
+        &self,
+        camera: &Camera,
+        origin: &glm::Vec3,
+    ) -> Vec<(glm::Vec3, glm::Vec3)> {
+        if !self.painting {
+            return Vec::new();
+        }
+        let extent = glm::vec3(1.0, 1.0, 1.0) * self.radius;
+        self.symmetry
+            .images(&self.target(camera), origin)
+            .into_iter()
+            .map(|image| (image - extent, image + extent))
+            .collect()
+    }
+
+    fn value(&self) -> u32 {
+        if self.erase {
+            return 0;
+        }
+        if let Some(material) = self.material {
+            return material.value();
+        }
+        let [r, g, b] = self
+            .color
+            .map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u32);
+        0xFF000000 | b << 16 | g << 8 | r
+    }
+
+    /// Paints one stroke at the brush target while painting, `origin` is where the symmetry
+    /// planes meet. Returns the spheres painted, one per symmetric copy.
+    pub fn update(
+        &mut self,
+        ctx: &WgpuContext,
+        encoder: &mut CommandEncoder,
+        chunk_manager: &mut ChunkManager,
+        camera: &Camera,
+        origin: &glm::Vec3,
+    ) -> Vec<Stroke> {
+        if !self.painting {
+            return Vec::new();
+        }
+        let center = self.target(camera);
+        // Holding still doesn't repaint the same spot every frame
+        if self
+            .last_stroke
+            .is_some_and(|last| glm::distance(&last, &center) < self.radius * 0.25)
+        {
+            return Vec::new();
+        }
+        self.last_stroke = Some(center);
+
+        ctx.queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::bytes_of(&BrushUniformPod {
+                center: center.into(),
+                radius: self.radius,
+                origin: (*origin).into(),
+                value: self.value(),
+                mirror: self.symmetry.mirror.map(|m| m as u32),
+                rotations: self.symmetry.rotations(),
+            }),
+        );
+
+        let extent = glm::vec3(1.0, 1.0, 1.0) * self.radius;
+        let images = self.symmetry.images(&center, origin);
+        let mut touched = images
+            .iter()
+            .flat_map(|image| {
+                chunk_manager
+                    .chunks_in_aabb(&(image - extent), &(image + extent))
+                    .map(|chunk| chunk.pos)
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        touched.sort_by_key(|pos| (pos.x, pos.y, pos.z));
+        touched.dedup();
+
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+                label: Some("brush compute_pass"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_pipeline(&self.pipeline);
+            compute_pass.set_bind_group(0, &self.bind_group, &[]);
+            compute_pass.set_bind_group(1, chunk_manager.bind_group(true), &[]);
+            for pos in &touched {
+                let chunk = &chunk_manager.chunks()[pos];
+                let (group, origin_x) = chunk_manager.offset_to_group_and_origin_x(chunk.offset());
+                compute_pass.set_push_constants(
+                    0,
+                    bytemuck::bytes_of(&BrushPushConstants {
+                        group,
+                        origin_x,
+                        which: chunk_manager.which(),
+                        _pad0: 0,
+                        chunk_pos: [pos.x, pos.y, pos.z, 0],
+                    }),
+                );
+                let workgroups = CHUNK_SIZE / WORKGROUP_SIZE;
+                compute_pass.dispatch_workgroups(workgroups, workgroups, workgroups);
+            }
+        }
+        for pos in &touched {
+            chunk_manager.mark_chunk_written(pos);
+        }
+        images
+            .into_iter()
+            .map(|center| Stroke {
+                center,
+                radius: self.radius,
+                value: self.value(),
+            })
+            .collect()
+    }
+
+    /// Outlines where the next stroke and its copies will land
+    pub fn draw(&self, overlay: &Overlay, camera: &Camera, origin: &glm::Vec3) {
+        if !self.enabled {
+            return;
+        }
+        let [r, g, b] = self.color;
+        let color = if self.erase {
+            theme::rgba(overlay.theme.erase_brush)
+        } else if let Some(material) = self.material {
+            let value = material.value();
+            glm::vec4(value, value >> 8, value >> 16, 0xFF).map(|c| (c & 0xFF) as f32 / 255.0)
+        } else {
+            glm::vec4(r, g, b, 1.0)
+        };
+        let extent = glm::vec3(1.0, 1.0, 1.0) * self.radius;
+        for image in self.symmetry.images(&self.target(camera), origin) {
+            overlay.cuboid(color, image - extent, image + extent);
+        }
+    }
+
+    /// Placement settings in a single row, for the status bar
+    pub fn toolbar_ui(&mut self, ui: &mut egui::Ui) {
+        let lock = &mut self.placement.lock;
+        ui.label("Lock");
+        ui.selectable_value(lock, Lock::Off, "Off");
+        for (axis, name) in ["X", "Y", "Z"].iter().enumerate() {
+            ui.selectable_value(lock, Lock::Plane(axis), format!("{} plane", name))
+                .on_hover_text(format!("Keeps strokes at the {} they started at", name));
+        }
+        for (axis, name) in ["X", "Y", "Z"].iter().enumerate() {
+            ui.selectable_value(lock, Lock::Axis(axis), format!("{} axis", name))
+                .on_hover_text(format!("Strokes only move along {}", name));
+        }
+        ui.separator();
+        ui.add(
+            egui::DragValue::new(&mut self.placement.snap)
+                .clamp_range(0..=64)
+                .prefix("Snap "),
+        )
+        .on_hover_text("Grid size stroke centers snap to, 0 doesn't snap");
+        ui.checkbox(&mut self.placement.extrude, "Extrude")
+            .on_hover_text("Paints out from the face under the crosshair");
+        ui.label("Hold Ctrl, Ctrl+Alt or Alt")
+            .on_hover_text("Plane lock, axis lock and extrude while held");
+    }
+
+    pub fn ui(
+        &mut self,
+        ui: &mut egui::Ui,
+        ctx: &WgpuContext,
+        chunk_manager: &ChunkManager,
+        camera: &Camera,
+    ) {
+        if self.eyedropper.as_ref().is_some_and(VoxelReadback::failed) {
+            self.eyedropper = None;
+        }
+        if let Some(value) = self.eyedropper.as_ref().and_then(|e| e.try_take()) {
+            self.eyedropper = None;
+            if value != 0 {
+                self.color = [value, value >> 8, value >> 16].map(|c| (c & 0xFF) as f32 / 255.0);
+                self.material = Material::from_value(value);
+                self.erase = false;
+            }
+        }
+        ui.collapsing("Brush", |ui| {
+            ui.checkbox(&mut self.enabled, "Paint with the left mouse button")
+                .on_hover_text("Paints in front of the camera while the cursor is captured");
+            ui.add(egui::Slider::new(&mut self.radius, 0.5..=32.0).text("Radius"));
+            ui.add(egui::Slider::new(&mut self.distance, 1.0..=256.0).text("Distance"));
+            ui.horizontal(|ui| {
+                ui.label("Material");
+                ui.selectable_value(&mut self.material, None, "Color");
+                for material in Material::ALL {
+                    ui.selectable_value(&mut self.material, Some(material), material.name());
+                }
+            })
+            .response
+            .on_hover_text("Materials move in the powder simulation mode");
+            ui.horizontal(|ui| {
+                if ui.color_edit_button_rgb(&mut self.color).changed() {
+                    self.material = None;
+                }
+                ui.checkbox(&mut self.erase, "Erase");
+                let target = self.target(camera);
+                if ui
+                    .add_enabled(
+                        chunk_manager.chunk_at_world(&target).is_some(),
+                        egui::Button::new("Pick"),
+                    )
+                    .on_hover_text("Takes the color of the voxel at the brush target")
+                    .clicked()
+                {
+                    self.eyedropper = chunk_manager.voxel_at_world(ctx, &target);
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("Mirror");
+                for (axis, name) in ["X", "Y", "Z"].iter().enumerate() {
+                    ui.checkbox(&mut self.symmetry.mirror[axis], *name);
+                }
+            });
+            ui.checkbox(
+                &mut self.symmetry.rotational,
+                "4-fold rotation about the vertical axis",
+            );
+        });
+    }
+}
@@ -0,0 +1,66 @@
+#include "common.wgsl"
+
+struct BrushUniform {
+    center: vec3<f32>,
+    radius: f32,
+    // Point the symmetry planes and the rotation axis go through
+    origin: vec3<f32>,
+    value: u32,
+    // Nonzero to mirror across the plane through the origin perpendicular to that axis
+    mirror: vec3<u32>,
+    // 1 or 4, copies rotated about the vertical axis through the origin
+    rotations: u32,
+};
+
+struct PushConstants {
+    @size(4) group: u32,
+    @size(4) origin_x: u32,
+    @size(4) which: u32,
+    @size(4) _pad0: u32,
+    @size(16) chunk_pos: vec3<i32>,
+};
+
+var<push_constant> consts: PushConstants;
+
+@group(0) @binding(0)
+var<uniform> brush: BrushUniform;
+
+@group(1) @binding(0)
+var atlas: texture_storage_3d<{{CHUNK_FORMAT}}, read>;
+
+@group(1) @binding(1)
+var grids: binding_array<texture_storage_3d<{{CHUNK_FORMAT}}, read_write>, 8>;
+
+// Same as Symmetry::images on the Rust side
+fn brush_image(i: u32) -> vec3<f32> {
+    var p = brush.center - brush.origin;
+    let flips = vec3<u32>(i & 1u, (i >> 1u) & 1u, (i >> 2u) & 1u);
+    p = select(p, -p, flips != vec3<u32>(0u));
+    for(var r = 0u; r < i >> 3u; r++) {
+        p = vec3<f32>(p.z, p.y, -p.x);
+    }
+    return p + brush.origin;
+}
+
+@compute
+@workgroup_size(4, 4, 4)
+fn cs_paint(@builtin(global_invocation_id) gid: vec3<u32>) {
+    let voxel_center = vec3<f32>(consts.chunk_pos * CHUNK_SIZE + vec3<i32>(gid)) + 0.5;
+    var inside = false;
+    for(var i = 0u; i < brush.rotations * 8u; i++) {
+        let flips = vec3<u32>(i & 1u, (i >> 1u) & 1u, (i >> 2u) & 1u);
+        // Only flips along mirrored axes
+        if(any(flips > brush.mirror)) {
+            continue;
+        }
+        if(distance(voxel_center, brush_image(i)) <= brush.radius) {
+            inside = true;
+            break;
+        }
+    }
+    if(!inside) {
+        return;
+    }
+    let origin = vec3<u32>(consts.origin_x * CHUNK_SIZE_U, 0u, consts.which * CHUNK_SIZE_U);
+    textureStore(grids[consts.group], origin + gid, vec4<u32>(brush.value, 0u, 0u, 0u));
+}
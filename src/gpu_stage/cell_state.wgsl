@@ -0,0 +1,24 @@
+// Powder cells holding exactly one of these colors are that material, see materials.rs
+const MATERIAL_SAND: u32 = {{MATERIAL_SAND}}u;
+const MATERIAL_WATER: u32 = {{MATERIAL_WATER}}u;
+const MATERIAL_FIRE: u32 = {{MATERIAL_FIRE}}u;
+const MATERIAL_SMOKE: u32 = {{MATERIAL_SMOKE}}u;
+
+// Keys of the states the legend lists, ALIVE_KEY and WALL_KEY in legend.rs. Decaying cells are
+// keyed by their decay step and powder materials by their value.
+const STATE_ALIVE: u32 = 0xFFu;
+const STATE_WALL: u32 = 0x100u;
+
+// State a non-empty cell is in. Generations rules with more than 2 states keep the decay step in
+// the alpha byte, which is 0xFF while the cell is alive.
+fn state_key(value: u32, states: u32, materials: bool) -> u32 {
+    if(materials) {
+        let material = value == MATERIAL_SAND || value == MATERIAL_WATER
+            || value == MATERIAL_FIRE || value == MATERIAL_SMOKE;
+        return select(STATE_WALL, value, material);
+    }
+    if(states > 2u) {
+        return value >> 24u;
+    }
+    return STATE_ALIVE;
+}
@@ -0,0 +1,193 @@
+use std::mem::size_of;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use nalgebra_glm as glm;
+use wgpu::*;
+
+use crate::chunk::CHUNK_SIZE;
+use crate::chunk_manager::ChunkManager;
+use crate::gpu_stage::overlay::Overlay;
+use crate::gpu_stage::simulate::CHANGED_FLAGS_OFFSET;
+use crate::theme;
+use crate::wgpu_context::WgpuContext;
+
+/// Reads back which chunks the simulation changed. Every workgroup of the simulation shader that
+/// changed a cell sets the flag of its chunk, the flags are copied out and cleared after the ticks
+/// of a frame, and whenever the last copy came back the chunks it flagged are recorded in the
+/// chunk manager as changed at the tick of the copy. Flags keep adding up while a copy is in
+/// flight and are copied on a later frame, paused or not, so no change is missed, it only shows
+/// up a few frames late.
+pub struct ChunkActivity {
+    readback_buffer: Buffer,
+    // Chunks the readback buffer has room for
+    capacity: u32,
+    // Chunk at every offset when the flags were copied, and the tick they were copied at
+    positions: Vec<glm::IVec3>,
+    tick: u64,
+    copied: bool,
+    in_flight: bool,
+    mapped: Arc<AtomicBool>,
+    // Set when mapping failed, the flags of that copy are lost
+    map_failed: Arc<AtomicBool>,
+    // Set when flags were thrown away without being read back
+    lost: bool,
+    pub visualize: bool,
+    // Chunks that changed this many ticks ago or longer aren't drawn
+    pub fade_ticks: u64,
+}
+
+impl ChunkActivity {
+    pub fn new(ctx: &WgpuContext) -> Self {
+        let capacity = 64;
+        Self {
+            readback_buffer: Self::create_readback_buffer(ctx, capacity),
+            capacity,
+            positions: Vec::new(),
+            tick: 0,
+            copied: false,
+            in_flight: false,
+            mapped: Arc::new(AtomicBool::new(false)),
+            map_failed: Arc::new(AtomicBool::new(false)),
+            lost: false,
+            visualize: false,
+            fade_ticks: 64,
+        }
+    }
+
+    fn create_readback_buffer(ctx: &WgpuContext, capacity: u32) -> Buffer {
+        ctx.device.create_buffer(&BufferDescriptor {
+            label: Some("chunk_activity readback_buffer"),
+            size: capacity as u64 * size_of::<u32>() as u64,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// For when the flag buffer was replaced, every chunk counts as changed on the next update
+    pub fn invalidate(&mut self) {
+        self.lost = true;
+    }
+
+    pub fn update(&mut self, chunk_manager: &mut ChunkManager) {
+        if self.map_failed.swap(false, Ordering::AcqRel) {
+            self.in_flight = false;
+            self.lost = true;
+        }
+        if std::mem::take(&mut self.lost) {
+            chunk_manager.mark_all_modified();
+        }
+        if !self.mapped.load(Ordering::Acquire) {
+            return;
+        }
+        {
+            let size = self.positions.len() as u64 * size_of::<u32>() as u64;
+            let range = self.readback_buffer.slice(..size).get_mapped_range();
+            let flags: &[u32] = bytemuck::cast_slice(&range);
+            for (pos, _) in self
+                .positions
+                .iter()
+                .zip(flags)
+                .filter(|(_, flag)| **flag != 0)
+            {
+                chunk_manager.record_modified(pos, self.tick);
+            }
+        }
+        self.readback_buffer.unmap();
+        self.mapped.store(false, Ordering::Release);
+        self.in_flight = false;
+    }
+
+    /// Copies the flags out of the simulation's `feedback` buffer and clears them, after the ticks
+    /// of the frame. `tick` is the tick the simulation is at after them, nothing is copied when it
+    /// hasn't moved since the last copy.
+    pub fn encode_readback(
+        &mut self,
+        ctx: &WgpuContext,
+        encoder: &mut CommandEncoder,
+        feedback: &Buffer,
+        chunk_manager: &ChunkManager,
+        tick: u64,
+    ) {
+        let num_offsets = chunk_manager.num_offsets();
+        if self.in_flight || num_offsets == 0 || tick == self.tick {
+            return;
+        }
+        if num_offsets > self.capacity {
+            self.capacity = num_offsets.next_power_of_two();
+            self.readback_buffer = Self::create_readback_buffer(ctx, self.capacity);
+        }
+        let size = num_offsets as u64 * size_of::<u32>() as u64;
+        encoder.copy_buffer_to_buffer(
+            feedback,
+            CHANGED_FLAGS_OFFSET,
+            &self.readback_buffer,
+            0,
+            size,
+        );
+        encoder.clear_buffer(feedback, CHANGED_FLAGS_OFFSET, Some(size));
+        self.positions = chunk_manager.offset_positions().to_vec();
+        self.tick = tick;
+        self.copied = true;
+        self.in_flight = true;
+    }
+
+    pub fn after_submit(&mut self) {
+        if !self.copied {
+            return;
+        }
+        self.copied = false;
+        let size = self.positions.len() as u64 * size_of::<u32>() as u64;
+        let mapped = self.mapped.clone();
+        let map_failed = self.map_failed.clone();
+        self.readback_buffer
+            .slice(..size)
+            .map_async(MapMode::Read, move |result| match result {
+                Ok(_) => mapped.store(true, Ordering::Release),
+                Err(e) => {
+                    log::error!("Failed to map chunk_activity buffer: {:?}", e);
+                    map_failed.store(true, Ordering::Release);
+                }
+            });
+    }
+
+    /// Outlines the chunks that changed recently, brighter the more recent they changed
+    pub fn draw(&self, overlay: &Overlay, chunk_manager: &ChunkManager) {
+        if !self.visualize {
+            return;
+        }
+        let now = chunk_manager.tick();
+        let fade_ticks = self.fade_ticks.max(1);
+        let size = glm::vec3(1.0, 1.0, 1.0) * CHUNK_SIZE as f32;
+        for (pos, modified) in chunk_manager.modified_since(now.saturating_sub(fade_ticks)) {
+            let brightness = 1.0 - 0.8 * (now - modified) as f32 / fade_ticks as f32;
+            let min = pos.cast::<f32>() * CHUNK_SIZE as f32;
+            overlay.cuboid(
+                theme::rgba(overlay.theme.chunk_activity.map(|c| c * brightness)),
+                min,
+                min + size,
+            );
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui, chunk_manager: &ChunkManager) {
+        ui.collapsing("Chunk activity", |ui| {
+            ui.checkbox(&mut self.visualize, "Show changed chunks");
+            ui.add(
+                egui::Slider::new(&mut self.fade_ticks, 1..=4096)
+                    .logarithmic(true)
+                    .text("Fade over ticks"),
+            );
+            let now = chunk_manager.tick();
+            let changed = chunk_manager
+                .modified_since(now.saturating_sub(self.fade_ticks.max(1)))
+                .count();
+            ui.label(format!(
+                "{} of {} chunks changed in the last {} ticks",
+                changed,
+                chunk_manager.chunks().len(),
+                self.fade_ticks.max(1)
+            ));
+        });
+    }
+}
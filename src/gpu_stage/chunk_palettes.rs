@@ -0,0 +1,319 @@
+use std::collections::{BTreeMap, HashMap};
+
+use nalgebra_glm as glm;
+use wgpu::*;
+
+use crate::chunk::CHUNK_SIZE;
+use crate::resource_tracker::{self, Tracked};
+use crate::selection::Selection;
+use crate::wgpu_context::WgpuContext;
+
+// Entries per palette, a state picks one by its brightness
+const PALETTE_SIZE: u32 = 256;
+// Layers of the palette texture
+const MAX_PALETTES: usize = 4;
+const META_PREFIX: &str = "palette.";
+
+/// Colors a palette runs through, evenly spread from the first entry to the last
+#[derive(Clone, PartialEq)]
+struct Palette {
+    name: &'static str,
+    stops: [[f32; 3]; 3],
+}
+
+const DEFAULT_PALETTES: [Palette; MAX_PALETTES] = [
+    Palette {
+        name: "Fire",
+        stops: [[0.15, 0.0, 0.0], [0.9, 0.3, 0.0], [1.0, 0.95, 0.5]],
+    },
+    Palette {
+        name: "Ice",
+        stops: [[0.0, 0.05, 0.2], [0.2, 0.5, 0.9], [0.9, 1.0, 1.0]],
+    },
+    Palette {
+        name: "Moss",
+        stops: [[0.05, 0.15, 0.05], [0.3, 0.6, 0.1], [0.85, 0.9, 0.5]],
+    },
+    Palette {
+        name: "Dusk",
+        stops: [[0.1, 0.0, 0.2], [0.7, 0.2, 0.5], [1.0, 0.7, 0.4]],
+    },
+];
+
+impl Palette {
+    fn colors(&self) -> impl Iterator<Item = [u8; 4]> + '_ {
+        (0..PALETTE_SIZE).map(|i| {
+            let t = i as f32 / (PALETTE_SIZE - 1) as f32 * 2.0;
+            let (from, to) = if t < 1.0 {
+                (self.stops[0], self.stops[1])
+            } else {
+                (self.stops[1], self.stops[2])
+            };
+            let t = if t < 1.0 { t } else { t - 1.0 };
+            let [r, g, b] = [0, 1, 2]
+                .map(|c| ((from[c] + (to[c] - from[c]) * t).clamp(0.0, 1.0) * 255.0).round() as u8);
+            [r, g, b, 0xFF]
+        })
+    }
+}
+
+/// Recolors the voxels of chosen chunks with a palette instead of their own color, so worlds or
+/// rules sharing a scene can be told apart without touching the cells. The palettes are layers
+/// of a small texture array, every chunk is drawn with the layer it was given.
+pub struct ChunkPalettes {
+    pub enabled: bool,
+    palettes: [Palette; MAX_PALETTES],
+    // 1 based palette of every recolored chunk
+    chunks: HashMap<glm::IVec3, u32>,
+    bind_group_layout: BindGroupLayout,
+    texture: Tracked<Texture>,
+    bind_group: BindGroup,
+    // Set when the palettes changed since they were last uploaded
+    dirty: bool,
+    selected: usize,
+}
+
+impl ChunkPalettes {
+    pub fn new(ctx: &WgpuContext) -> Self {
+        let bind_group_layout = ctx
+            .device
+            .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("chunk_palettes bind_group_layout"),
+                entries: &[BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::VERTEX,
+                    ty: BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: TextureViewDimension::D2Array,
+                        sample_type: TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                }],
+            });
+        let texture = resource_tracker::texture(
+            ctx,
+            &TextureDescriptor {
+                label: Some("chunk_palettes texture"),
+                size: Extent3d {
+                    width: PALETTE_SIZE,
+                    height: 1,
+                    depth_or_array_layers: MAX_PALETTES as u32,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: TextureFormat::Rgba8Unorm,
+                usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+                view_formats: &[],
+            },
+        );
+        let view = texture.create_view(&TextureViewDescriptor {
+            dimension: Some(TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        let bind_group = ctx.device.create_bind_group(&BindGroupDescriptor {
+            label: Some("chunk_palettes bind_group"),
+            layout: &bind_group_layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&view),
+            }],
+        });
+        Self {
+            enabled: true,
+            palettes: DEFAULT_PALETTES,
+            chunks: HashMap::new(),
+            bind_group_layout,
+            texture,
+            bind_group,
+            dirty: true,
+            selected: 0,
+        }
+    }
+
+    pub fn bind_group_layout(&self) -> &BindGroupLayout {
+        &self.bind_group_layout
+    }
+
+    pub fn bind_group(&self) -> &BindGroup {
+        &self.bind_group
+    }
+
+    /// Layer the chunk at `pos` is drawn with plus one, 0 to keep the colors of its cells
+    pub fn palette(&self, pos: &glm::IVec3) -> u32 {
+        if !self.enabled {
+            return 0;
+        }
+        self.chunks.get(pos).copied().unwrap_or(0)
+    }
+
+    pub fn upload(&mut self, ctx: &WgpuContext) {
+        if !std::mem::take(&mut self.dirty) {
+            return;
+        }
+        let pixels = self
+            .palettes
+            .iter()
+            .flat_map(|palette| palette.colors())
+            .flatten()
+            .collect::<Vec<u8>>();
+        ctx.queue.write_texture(
+            ImageCopyTexture {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: Origin3d::ZERO,
+                aspect: TextureAspect::All,
+            },
+            &pixels,
+            ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(PALETTE_SIZE * 4),
+                rows_per_image: Some(1),
+            },
+            Extent3d {
+                width: PALETTE_SIZE,
+                height: 1,
+                depth_or_array_layers: MAX_PALETTES as u32,
+            },
+        );
+    }
+
+    /// Chunks touched by the selection box, in chunk coordinates
+    fn selected_chunks(selection: &Selection) -> impl Iterator<Item = glm::IVec3> {
+        let min = selection.min.map(|c| c.div_euclid(CHUNK_SIZE as i32));
+        let max = selection.max.map(|c| c.div_euclid(CHUNK_SIZE as i32));
+        (min.z..=max.z).flat_map(move |z| {
+            (min.y..=max.y).flat_map(move |y| (min.x..=max.x).map(move |x| glm::vec3(x, y, z)))
+        })
+    }
+
+    /// Saves the palettes and which chunks use them with a world
+    pub fn write_meta(&self, meta: &mut BTreeMap<String, String>) {
+        for (i, palette) in self.palettes.iter().enumerate() {
+            let stops = palette
+                .stops
+                .iter()
+                .map(|[r, g, b]| format!("{},{},{}", r, g, b))
+                .collect::<Vec<_>>()
+                .join(" ");
+            meta.insert(format!("{}{}", META_PREFIX, i), stops);
+        }
+        let mut chunks = self.chunks.iter().collect::<Vec<_>>();
+        chunks.sort_by_key(|(pos, _)| (pos.z, pos.y, pos.x));
+        let chunks = chunks
+            .iter()
+            .map(|(pos, palette)| format!("{},{},{}={}", pos.x, pos.y, pos.z, palette))
+            .collect::<Vec<_>>()
+            .join("; ");
+        meta.insert(format!("{}chunks", META_PREFIX), chunks);
+    }
+
+    /// Replaces the palettes and chunk assignments with the ones written by `write_meta`, worlds
+    /// saved without them get the default palettes and no recolored chunks
+    pub fn read_meta(&mut self, meta: &BTreeMap<String, String>) {
+        self.palettes = DEFAULT_PALETTES;
+        for (i, palette) in self.palettes.iter_mut().enumerate() {
+            let Some(value) = meta.get(&format!("{}{}", META_PREFIX, i)) else {
+                continue;
+            };
+            let stops = value
+                .split_whitespace()
+                .map(|stop| {
+                    let rgb = stop
+                        .split(',')
+                        .map(|c| c.parse::<f32>().ok())
+                        .collect::<Option<Vec<_>>>()?;
+                    <[f32; 3]>::try_from(rgb).ok()
+                })
+                .collect::<Option<Vec<_>>>();
+            match stops.and_then(|stops| <[[f32; 3]; 3]>::try_from(stops).ok()) {
+                Some(stops) => palette.stops = stops,
+                None => log::warn!("Invalid palette {:?}", value),
+            }
+        }
+        self.chunks.clear();
+        let chunks = meta
+            .get(&format!("{}chunks", META_PREFIX))
+            .map_or("", |chunks| chunks.as_str());
+        for entry in chunks.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+            let parsed = entry.split_once('=').and_then(|(pos, palette)| {
+                let pos = pos
+                    .split(',')
+                    .map(|c| c.trim().parse::<i32>().ok())
+                    .collect::<Option<Vec<_>>>()?;
+                let [x, y, z] = pos[..] else {
+                    return None;
+                };
+                let palette = palette
+                    .trim()
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|p| (1..=MAX_PALETTES as u32).contains(p))?;
+                Some((glm::vec3(x, y, z), palette))
+            });
+            match parsed {
+                Some((pos, palette)) => {
+                    self.chunks.insert(pos, palette);
+                }
+                None => log::warn!("Skipping chunk palette {:?}", entry),
+            }
+        }
+        self.dirty = true;
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui, selection: &Selection) {
+        ui.collapsing("Chunk palettes", |ui| {
+            ui.checkbox(&mut self.enabled, "Recolor chunks")
+                .on_hover_text(
+                    "Chunks given a palette are drawn in its colors, picked by the brightness of \
+                     every cell",
+                );
+            ui.horizontal(|ui| {
+                for (i, palette) in self.palettes.iter().enumerate() {
+                    ui.selectable_value(&mut self.selected, i, palette.name);
+                }
+            });
+            let palette = &mut self.palettes[self.selected];
+            ui.horizontal(|ui| {
+                for stop in &mut palette.stops {
+                    self.dirty |= ui.color_edit_button_rgb(stop).changed();
+                }
+                if ui
+                    .add_enabled(
+                        *palette != DEFAULT_PALETTES[self.selected],
+                        egui::Button::new("Reset"),
+                    )
+                    .clicked()
+                {
+                    *palette = DEFAULT_PALETTES[self.selected].clone();
+                    self.dirty = true;
+                }
+            });
+            ui.horizontal(|ui| {
+                let assign = ui
+                    .add_enabled(selection.active, egui::Button::new("Assign to selection"))
+                    .on_hover_text("Every chunk the selection box touches");
+                if assign.clicked() {
+                    for pos in Self::selected_chunks(selection) {
+                        self.chunks.insert(pos, self.selected as u32 + 1);
+                    }
+                }
+                if ui
+                    .add_enabled(selection.active, egui::Button::new("Clear selection"))
+                    .clicked()
+                {
+                    for pos in Self::selected_chunks(selection) {
+                        self.chunks.remove(&pos);
+                    }
+                }
+                if ui
+                    .add_enabled(!self.chunks.is_empty(), egui::Button::new("Clear all"))
+                    .clicked()
+                {
+                    self.chunks.clear();
+                }
+            });
+            ui.label(format!("{} recolored chunks", self.chunks.len()));
+        });
+    }
+}
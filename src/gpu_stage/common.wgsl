@@ -0,0 +1,13 @@
+// Definitions shared between shaders, the values come from ShaderPrep on the Rust side
+
+const CHUNK_SIZE: i32 = {{CHUNK_SIZE}};
+const CHUNK_SIZE_U: u32 = {{CHUNK_SIZE}}u;
+const CHUNK_SHIFT: u32 = {{CHUNK_SHIFT}}u;
+const CHUNK_MASK: u32 = CHUNK_SIZE_U - 1u;
+
+// Chunk positions are offset by this much when indexing the atlas
+const ATLAS_OFFSET: i32 = {{ATLAS_OFFSET}};
+
+// FaceInstance.info layout: voxel position, then side, then per vertex ambient occlusion
+const FACE_SIDE_SHIFT: u32 = CHUNK_SHIFT * 3u;
+const FACE_AO_SHIFT: u32 = FACE_SIDE_SHIFT + 3u;
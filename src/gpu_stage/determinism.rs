@@ -0,0 +1,358 @@
+use std::mem::size_of;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use bytemuck::{Pod, Zeroable};
+use nalgebra_glm as glm;
+use wgpu::*;
+
+use crate::chunk::{Chunk, CHUNK_SIZE, CHUNK_VOLUME};
+use crate::chunk_manager::ChunkManager;
+use crate::shader_prep::ShaderPrep;
+use crate::wgpu_context::WgpuContext;
+
+const WORKGROUP_SIZE: u32 = 4;
+// Chunks compared per check, larger worlds are covered over several checks
+const MAX_CHUNKS_PER_CHECK: u64 = 32;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable, Default)]
+struct DeterminismPushConstants {
+    group: u32,
+    origin_x: u32,
+    which: u32,
+    slot: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct CompareResultPod {
+    mismatches: u32,
+    first: u32,
+}
+
+const COMPARE_RESULT_INIT: CompareResultPod = CompareResultPod {
+    mismatches: 0,
+    first: u32::MAX,
+};
+
+#[derive(Copy, Clone, Debug)]
+pub struct Divergence {
+    pub tick: u64,
+    pub mismatches: u32,
+    // First differing voxel, in world voxel coordinates
+    pub voxel: glm::IVec3,
+}
+
+struct Scratch {
+    reference_buffer: Buffer,
+    bind_group: BindGroup,
+    chunks: u64,
+}
+
+/// Verification mode for the simulation: a tick is run twice from the same input, the first
+/// result is kept in a scratch buffer and the second one is compared against it on the GPU. Any
+/// difference comes from races or atomics in the simulation shader.
+pub struct DeterminismCheck {
+    pub enabled: bool,
+    bind_group_layout: BindGroupLayout,
+    pipeline: ComputePipeline,
+    result_buffer: Buffer,
+    readback_buffer: Buffer,
+    scratch: Option<Scratch>,
+    max_chunks: u64,
+    // Index into the chunks sorted by offset where the next check starts
+    cursor: usize,
+    // Tick and chunk positions, in slot order, of the check that's being read back
+    pending: Option<(u64, Vec<glm::IVec3>)>,
+    copied: bool,
+    mapped: Arc<AtomicBool>,
+    ticks_checked: u64,
+    ticks_diverged: u64,
+    last_divergence: Option<Divergence>,
+}
+
+impl DeterminismCheck {
+    pub fn new(ctx: &WgpuContext, chunk_manager: &ChunkManager) -> Self {
+        let source = ShaderPrep::new().process(include_str!("./determinism.wgsl"));
+        let shader = ctx.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("determinism shader"),
+            source: ShaderSource::Wgsl(source.into()),
+        });
+
+        let storage_entry = |binding, read_only| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = ctx
+            .device
+            .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("determinism bind_group_layout"),
+                entries: &[storage_entry(0, true), storage_entry(1, false)],
+            });
+
+        let pipeline_layout = ctx
+            .device
+            .create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some("determinism pipeline_layout"),
+                bind_group_layouts: &[&bind_group_layout, chunk_manager.bind_group_layout(false)],
+                push_constant_ranges: &[PushConstantRange {
+                    stages: ShaderStages::COMPUTE,
+                    range: 0..size_of::<DeterminismPushConstants>() as u32,
+                }],
+            });
+
+        let pipeline = ctx
+            .device
+            .create_compute_pipeline(&ComputePipelineDescriptor {
+                label: Some("determinism pipeline"),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point: "cs_compare",
+            });
+
+        let result_buffer = ctx.device.create_buffer(&BufferDescriptor {
+            label: Some("determinism result_buffer"),
+            size: size_of::<CompareResultPod>() as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback_buffer = ctx.device.create_buffer(&BufferDescriptor {
+            label: Some("determinism readback_buffer"),
+            size: size_of::<CompareResultPod>() as u64,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let chunk_bytes = (CHUNK_VOLUME * size_of::<u32>()) as u64;
+        let max_chunks = (ctx.device.limits().max_storage_buffer_binding_size as u64 / chunk_bytes)
+            .clamp(1, MAX_CHUNKS_PER_CHECK);
+
+        Self {
+            enabled: false,
+            bind_group_layout,
+            pipeline,
+            result_buffer,
+            readback_buffer,
+            scratch: None,
+            max_chunks,
+            cursor: 0,
+            pending: None,
+            copied: false,
+            mapped: Arc::new(AtomicBool::new(false)),
+            ticks_checked: 0,
+            ticks_diverged: 0,
+            last_divergence: None,
+        }
+    }
+
+    /// Whether the next tick should be verified, one check is read back at a time
+    pub fn ready(&self) -> bool {
+        self.enabled && self.pending.is_none()
+    }
+
+    pub fn update(&mut self) {
+        if self.mapped.load(Ordering::Acquire) {
+            self.process_readback();
+        }
+    }
+
+    fn ensure_scratch(&mut self, ctx: &WgpuContext, chunks: u64) {
+        if self.scratch.as_ref().is_some_and(|s| s.chunks >= chunks) {
+            return;
+        }
+        let reference_buffer = ctx.device.create_buffer(&BufferDescriptor {
+            label: Some("determinism reference_buffer"),
+            size: chunks * (CHUNK_VOLUME * size_of::<u32>()) as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = ctx.device.create_bind_group(&BindGroupDescriptor {
+            label: Some("determinism bind_group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: reference_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: self.result_buffer.as_entire_binding(),
+                },
+            ],
+        });
+        self.scratch = Some(Scratch {
+            reference_buffer,
+            bind_group,
+            chunks,
+        });
+    }
+
+    /// Copies the chunks to check out of the current buffer, right after the first run of `tick`
+    pub fn capture_reference(
+        &mut self,
+        ctx: &WgpuContext,
+        encoder: &mut CommandEncoder,
+        chunk_manager: &ChunkManager,
+        tick: u64,
+    ) {
+        let mut chunks = chunk_manager.chunks().values().collect::<Vec<_>>();
+        if chunks.is_empty() {
+            return;
+        }
+        chunks.sort_by_key(|chunk| chunk.offset());
+        let count = (chunks.len() as u64).min(self.max_chunks) as usize;
+        let start = self.cursor % chunks.len();
+        self.cursor = start + count;
+        let selected = (0..count)
+            .map(|i| chunks[(start + i) % chunks.len()])
+            .collect::<Vec<&Chunk>>();
+
+        self.ensure_scratch(ctx, count as u64);
+        let scratch = self.scratch.as_ref().unwrap();
+        let chunk_bytes = (CHUNK_VOLUME * size_of::<u32>()) as u64;
+        for (slot, chunk) in selected.iter().enumerate() {
+            chunk_manager.copy_chunk_to_buffer(
+                encoder,
+                chunk,
+                &scratch.reference_buffer,
+                slot as u64 * chunk_bytes,
+            );
+        }
+        self.pending = Some((tick, selected.iter().map(|chunk| chunk.pos).collect()));
+    }
+
+    /// Compares the current buffer, right after the second run of the same tick, against the
+    /// reference
+    pub fn compare(
+        &mut self,
+        ctx: &WgpuContext,
+        encoder: &mut CommandEncoder,
+        chunk_manager: &ChunkManager,
+    ) {
+        let (Some((_, positions)), Some(scratch)) = (&self.pending, &self.scratch) else {
+            return;
+        };
+        ctx.queue.write_buffer(
+            &self.result_buffer,
+            0,
+            bytemuck::bytes_of(&COMPARE_RESULT_INIT),
+        );
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+                label: Some("determinism compute_pass"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_pipeline(&self.pipeline);
+            compute_pass.set_bind_group(0, &scratch.bind_group, &[]);
+            compute_pass.set_bind_group(1, chunk_manager.bind_group(false), &[]);
+            for (slot, pos) in positions.iter().enumerate() {
+                let chunk = &chunk_manager.chunks()[pos];
+                let (group, origin_x) = chunk_manager.offset_to_group_and_origin_x(chunk.offset());
+                compute_pass.set_push_constants(
+                    0,
+                    bytemuck::bytes_of(&DeterminismPushConstants {
+                        group,
+                        origin_x,
+                        which: chunk_manager.which(),
+                        slot: slot as u32,
+                    }),
+                );
+                let workgroups = CHUNK_SIZE / WORKGROUP_SIZE;
+                compute_pass.dispatch_workgroups(workgroups, workgroups, workgroups);
+            }
+        }
+        encoder.copy_buffer_to_buffer(
+            &self.result_buffer,
+            0,
+            &self.readback_buffer,
+            0,
+            size_of::<CompareResultPod>() as u64,
+        );
+        self.copied = true;
+    }
+
+    fn process_readback(&mut self) {
+        let result = *bytemuck::from_bytes::<CompareResultPod>(
+            &self.readback_buffer.slice(..).get_mapped_range(),
+        );
+        self.readback_buffer.unmap();
+        self.mapped.store(false, Ordering::Release);
+
+        let Some((tick, positions)) = self.pending.take() else {
+            return;
+        };
+        self.ticks_checked += 1;
+        if result.mismatches == 0 {
+            return;
+        }
+        self.ticks_diverged += 1;
+
+        let slot = result.first as usize / CHUNK_VOLUME;
+        let local = result.first as usize % CHUNK_VOLUME;
+        let size = CHUNK_SIZE as usize;
+        let local = glm::vec3(local % size, local / size % size, local / (size * size));
+        let voxel = positions[slot] * CHUNK_SIZE as i32 + local.map(|c| c as i32);
+        log::warn!(
+            "Tick {} diverged between two runs: {} voxels differ, the first at {:?}",
+            tick,
+            result.mismatches,
+            voxel.as_slice()
+        );
+        self.last_divergence = Some(Divergence {
+            tick,
+            mismatches: result.mismatches,
+            voxel,
+        });
+    }
+
+    pub fn after_submit(&mut self) {
+        if !self.copied {
+            return;
+        }
+        self.copied = false;
+        let mapped = self.mapped.clone();
+        self.readback_buffer
+            .slice(..)
+            .map_async(MapMode::Read, move |result| match result {
+                Ok(_) => mapped.store(true, Ordering::Release),
+                Err(e) => log::error!("Failed to map determinism buffer: {:?}", e),
+            });
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.collapsing("Determinism check", |ui| {
+            ui.checkbox(&mut self.enabled, "Verify ticks")
+                .on_hover_text(format!(
+                    "Runs ticks twice and compares up to {} chunks of the results",
+                    self.max_chunks
+                ));
+            ui.label(format!(
+                "{} ticks checked, {} diverged",
+                self.ticks_checked, self.ticks_diverged
+            ));
+            if let Some(divergence) = &self.last_divergence {
+                ui.colored_label(
+                    ui.visuals().warn_fg_color,
+                    format!(
+                        "Last at tick {}: {} voxels, first at {:?}",
+                        divergence.tick,
+                        divergence.mismatches,
+                        divergence.voxel.as_slice()
+                    ),
+                );
+            }
+            if ui.button("Reset").clicked() {
+                self.ticks_checked = 0;
+                self.ticks_diverged = 0;
+                self.last_divergence = None;
+            }
+        });
+    }
+}
@@ -0,0 +1,41 @@
+#include "common.wgsl"
+
+struct CompareResult {
+    mismatches: atomic<u32>,
+    // Lowest differing index into `reference`
+    first: atomic<u32>,
+};
+
+struct PushConstants {
+    @size(4) group: u32,
+    @size(4) origin_x: u32,
+    @size(4) which: u32,
+    @size(4) slot: u32,
+};
+
+var<push_constant> consts: PushConstants;
+
+// Chunks from the first run, tightly packed in x, y, z order
+@group(0) @binding(0)
+var<storage, read> reference: array<u32>;
+
+@group(0) @binding(1)
+var<storage, read_write> result: CompareResult;
+
+@group(1) @binding(0)
+var atlas: texture_storage_3d<{{CHUNK_FORMAT}}, read>;
+
+@group(1) @binding(1)
+var chunk_groups: binding_array<texture_storage_3d<{{CHUNK_FORMAT}}, read>, 8>;
+
+@compute
+@workgroup_size(4, 4, 4)
+fn cs_compare(@builtin(global_invocation_id) gid: vec3<u32>) {
+    let pos = vec3<i32>(gid);
+    let cur = textureLoad(chunk_groups[consts.group], pos + vec3<i32>(vec3<u32>(consts.origin_x, 0u, consts.which)) * CHUNK_SIZE).r;
+    let index = ((consts.slot * CHUNK_SIZE_U + gid.z) * CHUNK_SIZE_U + gid.y) * CHUNK_SIZE_U + gid.x;
+    if(cur != reference[index]) {
+        atomicAdd(&result.mismatches, 1u);
+        atomicMin(&result.first, index);
+    }
+}
@@ -0,0 +1,13 @@
+// Ordered dithering shared between shaders
+
+// Threshold from 0 to 1 of a 2^levels by 2^levels Bayer matrix at the pixel, built up from the
+// bits of the pixel position
+fn bayer_threshold(pixel: vec2<u32>, levels: u32) -> f32 {
+    var rank = 0u;
+    for(var bit = 0u; bit < levels; bit++) {
+        let x = (pixel.x >> bit) & 1u;
+        let y = (pixel.y >> bit) & 1u;
+        rank |= (((x ^ y) << 1u) | y) << ((levels - 1u - bit) * 2u);
+    }
+    return (f32(rank) + 0.5) / f32(1u << (levels * 2u));
+}
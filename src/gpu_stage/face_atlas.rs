@@ -0,0 +1,269 @@
+use wgpu::*;
+
+use crate::resource_tracker::{self, Tracked};
+use crate::wgpu_context::WgpuContext;
+
+// Every material gets a row of this many tile variants
+pub const VARIANTS: u32 = 4;
+const DEFAULT_ATLAS: &[u8] = include_bytes!("./face_atlas.png");
+
+/// RGBA8 pixels of a decoded atlas
+struct AtlasImage {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+fn decode_png(bytes: &[u8]) -> Result<AtlasImage, String> {
+    let mut decoder = png::Decoder::new(bytes);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(|e| e.to_string())?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).map_err(|e| e.to_string())?;
+    let buffer = &buffer[..info.buffer_size()];
+    let pixels = match info.color_type {
+        png::ColorType::Rgba => buffer.to_vec(),
+        png::ColorType::Rgb => buffer
+            .chunks_exact(3)
+            .flat_map(|p| [p[0], p[1], p[2], 0xFF])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => buffer
+            .chunks_exact(2)
+            .flat_map(|p| [p[0], p[0], p[0], p[1]])
+            .collect(),
+        png::ColorType::Grayscale => buffer.iter().flat_map(|&v| [v, v, v, 0xFF]).collect(),
+        png::ColorType::Indexed => return Err("indexed colors weren't expanded".to_owned()),
+    };
+
+    let tile_size = info.width / VARIANTS;
+    if tile_size == 0 || info.width % VARIANTS != 0 || info.height % tile_size != 0 {
+        return Err(format!(
+            "a {}x{} atlas doesn't split into square tiles, {} per row",
+            info.width, info.height, VARIANTS
+        ));
+    }
+    Ok(AtlasImage {
+        width: info.width,
+        height: info.height,
+        pixels,
+    })
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read_file(path: &str) -> Result<Vec<u8>, String> {
+    std::fs::read(path).map_err(|e| e.to_string())
+}
+
+#[cfg(target_arch = "wasm32")]
+fn read_file(_path: &str) -> Result<Vec<u8>, String> {
+    Err("the web build can't read files from disk".to_owned())
+}
+
+/// Textures voxel faces are drawn with when texturing is on. The atlas is a grid of square
+/// tiles, a row per material with `VARIANTS` tiles each. Every state is assigned a material row
+/// by the key meshing gives its faces, and every voxel one of the row's variants by its position,
+/// so large areas of one state don't repeat the same tile.
+pub struct FaceAtlas {
+    pub enabled: bool,
+    bind_group_layout: BindGroupLayout,
+    sampler: Sampler,
+    _texture: Tracked<Texture>,
+    bind_group: BindGroup,
+    rows: u32,
+    // Where the current atlas came from, empty for the built in one
+    source: String,
+    path: String,
+    error: Option<String>,
+}
+
+impl FaceAtlas {
+    pub fn new(ctx: &WgpuContext) -> Self {
+        let bind_group_layout = ctx
+            .device
+            .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("face_atlas bind_group_layout"),
+                entries: &[
+                    BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: TextureViewDimension::D2,
+                            sample_type: TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                ],
+            });
+        // Nearest keeps the pixel look of small tiles
+        let sampler = ctx.device.create_sampler(&SamplerDescriptor {
+            label: Some("face_atlas sampler"),
+            min_filter: FilterMode::Nearest,
+            mag_filter: FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let image = decode_png(DEFAULT_ATLAS).expect("Built in face atlas is invalid");
+        let (texture, bind_group, rows) =
+            Self::create_texture(ctx, &bind_group_layout, &sampler, &image);
+        Self {
+            enabled: false,
+            bind_group_layout,
+            sampler,
+            _texture: texture,
+            bind_group,
+            rows,
+            source: String::new(),
+            path: String::new(),
+            error: None,
+        }
+    }
+
+    fn create_texture(
+        ctx: &WgpuContext,
+        bind_group_layout: &BindGroupLayout,
+        sampler: &Sampler,
+        image: &AtlasImage,
+    ) -> (Tracked<Texture>, BindGroup, u32) {
+        let size = Extent3d {
+            width: image.width,
+            height: image.height,
+            depth_or_array_layers: 1,
+        };
+        let texture = resource_tracker::texture(
+            ctx,
+            &TextureDescriptor {
+                label: Some("face_atlas texture"),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: TextureFormat::Rgba8Unorm,
+                usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+                view_formats: &[],
+            },
+        );
+        ctx.queue.write_texture(
+            ImageCopyTexture {
+                texture: &texture,
+                mip_level: 0,
+                origin: Origin3d::ZERO,
+                aspect: TextureAspect::All,
+            },
+            &image.pixels,
+            ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(image.width * 4),
+                rows_per_image: Some(image.height),
+            },
+            size,
+        );
+        let view = texture.create_view(&TextureViewDescriptor::default());
+        let bind_group = ctx.device.create_bind_group(&BindGroupDescriptor {
+            label: Some("face_atlas bind_group"),
+            layout: bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(&view),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Sampler(sampler),
+                },
+            ],
+        });
+        let rows = image.height / (image.width / VARIANTS);
+        (texture, bind_group, rows)
+    }
+
+    fn replace(&mut self, ctx: &WgpuContext, image: &AtlasImage, source: String) {
+        let max = ctx.device.limits().max_texture_dimension_2d;
+        if image.width > max || image.height > max {
+            self.error = Some(format!(
+                "{}x{} is larger than the device allows, {} at most",
+                image.width, image.height, max
+            ));
+            return;
+        }
+        let (texture, bind_group, rows) =
+            Self::create_texture(ctx, &self.bind_group_layout, &self.sampler, image);
+        self._texture = texture;
+        self.bind_group = bind_group;
+        self.rows = rows;
+        self.source = source;
+        self.error = None;
+    }
+
+    /// Swaps in the PNG atlas at `path`, keeps the current one when it can't be used
+    pub fn load(&mut self, ctx: &WgpuContext, path: &str) {
+        match read_file(path).and_then(|bytes| decode_png(&bytes)) {
+            Ok(image) => {
+                log::info!("Loaded face atlas {}", path);
+                self.replace(ctx, &image, path.to_owned());
+            }
+            Err(e) => {
+                log::warn!("Failed to load face atlas {}: {}", path, e);
+                self.error = Some(e);
+            }
+        }
+    }
+
+    pub fn reset(&mut self, ctx: &WgpuContext) {
+        let image = decode_png(DEFAULT_ATLAS).expect("Built in face atlas is invalid");
+        self.replace(ctx, &image, String::new());
+    }
+
+    pub fn bind_group_layout(&self) -> &BindGroupLayout {
+        &self.bind_group_layout
+    }
+
+    pub fn bind_group(&self) -> &BindGroup {
+        &self.bind_group
+    }
+
+    /// Material rows in the atlas
+    pub fn rows(&self) -> u32 {
+        self.rows
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui, ctx: &WgpuContext) {
+        ui.collapsing("Textures", |ui| {
+            ui.checkbox(&mut self.enabled, "Texture voxel faces")
+                .on_hover_text("Every state is drawn with a tile of the atlas tinted in its color");
+            ui.label(match self.source.as_str() {
+                "" => format!("Built in atlas, {} materials", self.rows),
+                source => format!("{}, {} materials", source, self.rows),
+            });
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut self.path)
+                    .on_hover_text(format!(
+                        "PNG of square tiles, {} per row and a row per material",
+                        VARIANTS
+                    ));
+                if ui
+                    .add_enabled(!self.path.is_empty(), egui::Button::new("Load"))
+                    .clicked()
+                {
+                    let path = self.path.clone();
+                    self.load(ctx, &path);
+                }
+                if ui
+                    .add_enabled(!self.source.is_empty(), egui::Button::new("Built in"))
+                    .clicked()
+                {
+                    self.reset(ctx);
+                }
+            });
+            if let Some(error) = &self.error {
+                ui.colored_label(ui.visuals().error_fg_color, error);
+            }
+        });
+    }
+}
@@ -0,0 +1,123 @@
+use std::collections::HashMap;
+use std::mem::size_of;
+
+use bytemuck::{Pod, Zeroable};
+use nalgebra_glm as glm;
+use wgpu::*;
+
+use crate::chunk::CHUNK_SIZE;
+use crate::chunk_manager::ChunkManager;
+use crate::gpu_stage::meshing_render::PerChunkResource;
+use crate::shader_prep::ShaderPrep;
+use crate::wgpu_context::WgpuContext;
+
+const WORKGROUP_SIZE: u32 = 256;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable, Default)]
+struct FaceSortPushConstants {
+    view_proj: glm::Mat4x4,
+    translate: glm::Vec3,
+    block: u32,
+    flip: u32,
+    _pad0: [u32; 3],
+}
+
+/// Bitonic sort of every chunk's face instances by view depth, back to front, so blended faces
+/// are drawn in order. Runs log2(n)^2 / 2 dispatches per chunk.
+pub struct FaceSort {
+    pipeline: ComputePipeline,
+}
+
+impl FaceSort {
+    pub fn new(ctx: &WgpuContext) -> Self {
+        let source = ShaderPrep::new().process(include_str!("./face_sort.wgsl"));
+        let shader = ctx.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("face_sort shader"),
+            source: ShaderSource::Wgsl(source.into()),
+        });
+
+        // Matches the meshing layout, so the per chunk bind groups can be reused
+        let storage_entry = |binding| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = ctx
+            .device
+            .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("face_sort bind_group_layout"),
+                entries: &[storage_entry(0), storage_entry(1)],
+            });
+
+        let pipeline_layout = ctx
+            .device
+            .create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some("face_sort pipeline_layout"),
+                bind_group_layouts: &[&bind_group_layout],
+                push_constant_ranges: &[PushConstantRange {
+                    stages: ShaderStages::COMPUTE,
+                    range: 0..size_of::<FaceSortPushConstants>() as u32,
+                }],
+            });
+
+        let pipeline = ctx
+            .device
+            .create_compute_pipeline(&ComputePipelineDescriptor {
+                label: Some("face_sort pipeline"),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point: "cs_sort",
+            });
+
+        Self { pipeline }
+    }
+
+    pub fn update(
+        &self,
+        command_encoder: &mut CommandEncoder,
+        chunk_manager: &ChunkManager,
+        per_chunk_resources: &HashMap<glm::IVec3, PerChunkResource>,
+        view_proj: &glm::Mat4x4,
+    ) {
+        let mut compute_pass = command_encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("face_sort compute_pass"),
+            timestamp_writes: None,
+        });
+        compute_pass.set_pipeline(&self.pipeline);
+
+        for pos in chunk_manager.chunks().keys() {
+            let Some(per_chunk_resource) = per_chunk_resources.get(pos) else {
+                continue;
+            };
+            compute_pass.set_bind_group(0, per_chunk_resource.bind_group(), &[]);
+
+            // The face count is only known on the GPU, so the sort covers the whole buffer
+            let size = per_chunk_resource.max_faces().next_power_of_two();
+            let workgroups = (size / 2).div_ceil(WORKGROUP_SIZE);
+            let mut push_constants = FaceSortPushConstants {
+                view_proj: *view_proj,
+                translate: pos.cast::<f32>() * CHUNK_SIZE as f32,
+                ..Default::default()
+            };
+            let mut stage = 2;
+            while stage <= size {
+                let mut block = stage;
+                push_constants.flip = 1;
+                while block >= 2 {
+                    push_constants.block = block;
+                    compute_pass.set_push_constants(0, bytemuck::bytes_of(&push_constants));
+                    compute_pass.dispatch_workgroups(workgroups, 1, 1);
+                    push_constants.flip = 0;
+                    block /= 2;
+                }
+                stage *= 2;
+            }
+        }
+    }
+}
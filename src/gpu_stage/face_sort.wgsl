@@ -0,0 +1,82 @@
+#include "common.wgsl"
+
+struct DrawIndirect {
+    @size(4) vertex_count: u32,
+    @size(4) instance_count: u32,
+    @size(4) first_vertex: u32,
+    @size(4) first_instance: u32,
+};
+
+struct FaceInstance {
+    @size(4) color: u32,
+    @size(4) info: u32,
+    @size(4) born: u32,
+    @size(4) state: u32,
+}
+
+struct PushConstants {
+    @size(64) view_proj: mat4x4<f32>,
+    translate: vec3<f32>,
+    // Size of the blocks compared in this step
+    block: u32,
+    // 1 for the first step of every stage, which compares mirrored elements
+    @size(16) flip: u32,
+};
+
+var<push_constant> consts: PushConstants;
+
+@group(0) @binding(0)
+var<storage, read_write> indirect: DrawIndirect;
+
+@group(0) @binding(1)
+var<storage, read_write> faces: array<FaceInstance>;
+
+// Same side order as render.wgsl
+var<private> normal: array<vec3<f32>, 6> = array<vec3<f32>, 6>(
+    vec3<f32>(-1.0,  0.0,  0.0),
+    vec3<f32>( 1.0,  0.0,  0.0),
+    vec3<f32>( 0.0, -1.0,  0.0),
+    vec3<f32>( 0.0,  1.0,  0.0),
+    vec3<f32>( 0.0,  0.0, -1.0),
+    vec3<f32>( 0.0,  0.0,  1.0),
+);
+
+// Depth of the face center, smaller is further away with the reversed depth
+fn face_depth(face: FaceInstance) -> f32 {
+    let info = face.info;
+    let offset = vec3<u32>(info & CHUNK_MASK, (info >> CHUNK_SHIFT) & CHUNK_MASK, (info >> (CHUNK_SHIFT * 2u)) & CHUNK_MASK);
+    let side = (info >> FACE_SIDE_SHIFT) & 0x7u;
+    let center = vec3<f32>(offset) + 0.5 + normal[side] * 0.5 + consts.translate;
+    let clip = consts.view_proj * vec4<f32>(center, 1.0);
+    if(clip.w <= 0.0) {
+        // Behind the camera, never visible
+        return 3.0e38;
+    }
+    return clip.z / clip.w;
+}
+
+// One step of a bitonic sort that only ever moves the smaller element to the lower index. Indices
+// past the face count act as infinitely large and stay where they are, so the count doesn't need
+// to be a power of two.
+@compute
+@workgroup_size(256)
+fn cs_sort(@builtin(global_invocation_id) gid: vec3<u32>) {
+    let count = min(indirect.instance_count, arrayLength(&faces));
+    let half_block = consts.block / 2u;
+    let start = (gid.x / half_block) * consts.block;
+    let offset = gid.x % half_block;
+    let a = start + offset;
+    var b = a + half_block;
+    if(consts.flip == 1u) {
+        b = start + consts.block - 1u - offset;
+    }
+    if(b >= count) {
+        return;
+    }
+    let face_a = faces[a];
+    let face_b = faces[b];
+    if(face_depth(face_a) > face_depth(face_b)) {
+        faces[a] = face_b;
+        faces[b] = face_a;
+    }
+}
@@ -0,0 +1,56 @@
+use std::rc::Rc;
+
+use crate::util::RenderTarget;
+use crate::wgpu_context::WgpuContext;
+
+/// A stage that draws into a target owned further down the frame and exposes the target the
+/// stage before it should draw into
+pub trait TargetStage {
+    fn resize(&mut self, ctx: &WgpuContext, output_target: Rc<RenderTarget>);
+    fn input_target(&self) -> Rc<RenderTarget>;
+}
+
+struct Node<'a> {
+    stage: &'a mut dyn TargetStage,
+    enabled: bool,
+}
+
+/// Links stages listed in the order they run. Each stage draws into the input target of the next
+/// enabled stage, so inserting a stage is a single `stage` call.
+#[derive(Default)]
+pub struct FrameGraph<'a> {
+    nodes: Vec<Node<'a>>,
+}
+
+impl<'a> FrameGraph<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Disabled stages still get resized, but the stages around them are linked past them
+    pub fn stage(mut self, stage: &'a mut dyn TargetStage, enabled: bool) -> Self {
+        self.nodes.push(Node { stage, enabled });
+        self
+    }
+
+    /// Like `stage`, for stages that may not exist, e.g. in safe mode
+    pub fn optional_stage<T: TargetStage>(self, stage: Option<&'a mut T>, enabled: bool) -> Self {
+        match stage {
+            Some(stage) => self.stage(stage, enabled),
+            None => self,
+        }
+    }
+
+    /// (Re)creates every stage's targets back to front, ending in `sink`. Returns the target the
+    /// producer in front of the first stage should draw into.
+    pub fn link(self, ctx: &WgpuContext, sink: Rc<RenderTarget>) -> Rc<RenderTarget> {
+        let mut target = sink;
+        for node in self.nodes.into_iter().rev() {
+            node.stage.resize(ctx, target.clone());
+            if node.enabled {
+                target = node.stage.input_target();
+            }
+        }
+        target
+    }
+}
@@ -0,0 +1,63 @@
+struct PushConstants {
+    view_proj: mat4x4<f32>,
+    // World position of the corner of the pattern
+    offset: vec3<i32>,
+    opacity: f32,
+};
+
+var<push_constant> consts: PushConstants;
+
+struct FaceInput {
+    @location(0) cell: vec3<i32>,
+    // Twice the axis the face looks along, plus one when it looks up the axis
+    @location(1) face: u32,
+    @location(2) value: u32,
+}
+
+struct VertexOutput {
+    @location(0) color: vec4<f32>,
+    @builtin(position) pos: vec4<f32>,
+};
+
+@vertex
+fn vs_ghost(@builtin(vertex_index) vertex: u32, in: FaceInput) -> VertexOutput {
+    // Counter-clockwise seen from the positive side of the axis
+    var quad = array<vec2<f32>, 6>(
+        vec2<f32>(0.0, 0.0),
+        vec2<f32>(1.0, 0.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(0.0, 0.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(0.0, 1.0),
+    );
+    // Shades the axes apart, the ghost isn't lit
+    var shade = array<f32, 3>(0.8, 1.0, 0.9);
+
+    let axis = in.face / 2u;
+    let positive = (in.face & 1u) == 1u;
+    var corner = quad[vertex];
+    if(!positive) {
+        // Mirroring the quad winds it the other way around
+        corner = corner.yx;
+    }
+    var local = vec3<f32>(0.0);
+    local[axis] = select(0.0, 1.0, positive);
+    local[(axis + 1u) % 3u] = corner.x;
+    local[(axis + 2u) % 3u] = corner.y;
+
+    let rgb = vec3<f32>(
+        f32(in.value & 0xFFu),
+        f32((in.value >> 8u) & 0xFFu),
+        f32((in.value >> 16u) & 0xFFu),
+    ) / 255.0;
+
+    var out: VertexOutput;
+    out.color = vec4<f32>(rgb * shade[axis], consts.opacity);
+    out.pos = consts.view_proj * vec4<f32>(vec3<f32>(consts.offset + in.cell) + local, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_ghost(in: VertexOutput) -> @location(0) vec4<f32> {
+    return in.color;
+}
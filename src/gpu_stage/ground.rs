@@ -0,0 +1,464 @@
+use std::mem::size_of;
+use std::rc::Rc;
+
+use bytemuck::{Pod, Zeroable};
+use nalgebra_glm as glm;
+use wgpu::*;
+
+use crate::chunk::CHUNK_SIZE;
+use crate::chunk_manager::ChunkManager;
+use crate::shader_prep::ShaderPrep;
+use crate::util::RenderTarget;
+use crate::wgpu_context::WgpuContext;
+
+// Shadows are only computed for this many voxels along x and z
+const MAX_REGION_SIZE: u32 = 2048;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable, Default)]
+struct HeightsPushConstants {
+    group: u32,
+    origin_x: u32,
+    which: u32,
+    plane_y: i32,
+    chunk_pos: glm::IVec3,
+    region_width: u32,
+    region_min: glm::IVec2,
+    region_depth: u32,
+    _pad0: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable, Default)]
+struct PlanePushConstants {
+    view_proj: glm::Mat4x4,
+    camera_pos: glm::Vec3,
+    plane_y: f32,
+    region_min: glm::IVec2,
+    region_size: glm::UVec2,
+    color: glm::Vec4,
+    shadow_strength: f32,
+    extent: f32,
+    _pad0: [f32; 2],
+}
+
+// Columns of the world the shadows are computed for, in voxels
+#[derive(Copy, Clone, PartialEq, Eq, Default)]
+struct Region {
+    min: glm::IVec2,
+    size: glm::UVec2,
+}
+
+// Everything the heights depend on, they're only recomputed when this changes
+#[derive(Copy, Clone, PartialEq, Eq)]
+struct HeightsKey {
+    sim_version: u64,
+    chunks: usize,
+    version_sum: u64,
+    plane_y: i32,
+    region: Region,
+}
+
+struct Heights {
+    buffer: Buffer,
+    compute_bind_group: BindGroup,
+    render_bind_group: BindGroup,
+}
+
+struct Resources {
+    plane_shader: ShaderModule,
+    heights_pipeline: ComputePipeline,
+    plane_pipeline_layout: PipelineLayout,
+    heights_bind_group_layout: BindGroupLayout,
+    plane_bind_group_layout: BindGroupLayout,
+    heights: Heights,
+}
+
+struct DynamicResources {
+    output_target: Rc<RenderTarget>,
+    pipeline: RenderPipeline,
+}
+
+/// Optional grid plane at a fixed height, drawn into the scene after the voxels. It darkens
+/// under and around the columns of voxels above it.
+pub struct Ground {
+    res: Resources,
+    dynamic: DynamicResources,
+    pub enabled: bool,
+    pub plane_y: i32,
+    pub color: glm::Vec3,
+    pub shadow_strength: f32,
+    region: Region,
+    heights_key: Option<HeightsKey>,
+}
+
+impl Heights {
+    fn new(
+        ctx: &WgpuContext,
+        heights_layout: &BindGroupLayout,
+        plane_layout: &BindGroupLayout,
+        len: u32,
+    ) -> Self {
+        let buffer = ctx.device.create_buffer(&BufferDescriptor {
+            label: Some("ground heights buffer"),
+            size: len.max(1) as u64 * size_of::<u32>() as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let compute_bind_group = ctx.device.create_bind_group(&BindGroupDescriptor {
+            label: Some("ground heights compute_bind_group"),
+            layout: heights_layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+        });
+        let render_bind_group = ctx.device.create_bind_group(&BindGroupDescriptor {
+            label: Some("ground heights render_bind_group"),
+            layout: plane_layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+        });
+        Self {
+            buffer,
+            compute_bind_group,
+            render_bind_group,
+        }
+    }
+
+    fn len(&self) -> u32 {
+        (self.buffer.size() / size_of::<u32>() as u64) as u32
+    }
+}
+
+fn storage_layout(
+    ctx: &WgpuContext,
+    label: &str,
+    stage: ShaderStages,
+    read_only: bool,
+) -> BindGroupLayout {
+    ctx.device
+        .create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some(label),
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                visibility: stage,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        })
+}
+
+impl Resources {
+    fn new(ctx: &WgpuContext, chunk_manager: &ChunkManager) -> Self {
+        let heights_shader = ctx.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("ground heights_shader"),
+            source: ShaderSource::Wgsl(
+                ShaderPrep::new()
+                    .process(include_str!("./ground_heights.wgsl"))
+                    .into(),
+            ),
+        });
+        let plane_shader = ctx.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("ground plane_shader"),
+            source: ShaderSource::Wgsl(
+                ShaderPrep::new()
+                    .process(include_str!("./ground.wgsl"))
+                    .into(),
+            ),
+        });
+
+        let heights_bind_group_layout = storage_layout(
+            ctx,
+            "ground heights_bind_group_layout",
+            ShaderStages::COMPUTE,
+            false,
+        );
+        let plane_bind_group_layout = storage_layout(
+            ctx,
+            "ground plane_bind_group_layout",
+            ShaderStages::FRAGMENT,
+            true,
+        );
+
+        let heights_pipeline_layout =
+            ctx.device
+                .create_pipeline_layout(&PipelineLayoutDescriptor {
+                    label: Some("ground heights_pipeline_layout"),
+                    bind_group_layouts: &[
+                        &heights_bind_group_layout,
+                        chunk_manager.bind_group_layout(false),
+                    ],
+                    push_constant_ranges: &[PushConstantRange {
+                        stages: ShaderStages::COMPUTE,
+                        range: 0..size_of::<HeightsPushConstants>() as u32,
+                    }],
+                });
+        let heights_pipeline = ctx
+            .device
+            .create_compute_pipeline(&ComputePipelineDescriptor {
+                label: Some("ground heights_pipeline"),
+                layout: Some(&heights_pipeline_layout),
+                module: &heights_shader,
+                entry_point: "cs_heights",
+            });
+
+        let plane_pipeline_layout = ctx
+            .device
+            .create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some("ground plane_pipeline_layout"),
+                bind_group_layouts: &[&plane_bind_group_layout],
+                push_constant_ranges: &[PushConstantRange {
+                    stages: ShaderStages::VERTEX_FRAGMENT,
+                    range: 0..size_of::<PlanePushConstants>() as u32,
+                }],
+            });
+
+        let heights = Heights::new(ctx, &heights_bind_group_layout, &plane_bind_group_layout, 1);
+
+        Self {
+            plane_shader,
+            heights_pipeline,
+            plane_pipeline_layout,
+            heights_bind_group_layout,
+            plane_bind_group_layout,
+            heights,
+        }
+    }
+}
+
+impl DynamicResources {
+    fn new(ctx: &WgpuContext, res: &mut Resources, output_target: Rc<RenderTarget>) -> Self {
+        let pipeline = ctx
+            .device
+            .create_render_pipeline(&RenderPipelineDescriptor {
+                label: Some("ground pipeline"),
+                layout: Some(&res.plane_pipeline_layout),
+                vertex: VertexState {
+                    module: &res.plane_shader,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(FragmentState {
+                    module: &res.plane_shader,
+                    entry_point: "fs_main",
+                    targets: &[Some(ColorTargetState {
+                        format: output_target.info.format,
+                        blend: None,
+                        write_mask: ColorWrites::ALL,
+                    })],
+                }),
+                primitive: PrimitiveState {
+                    topology: PrimitiveTopology::TriangleList,
+                    strip_index_format: None,
+                    front_face: FrontFace::Ccw,
+                    cull_mode: None,
+                    unclipped_depth: false,
+                    polygon_mode: PolygonMode::Fill,
+                    conservative: false,
+                },
+                depth_stencil: Some(DepthStencilState {
+                    format: TextureFormat::Depth32Float,
+                    depth_write_enabled: true,
+                    depth_compare: CompareFunction::Greater,
+                    stencil: Default::default(),
+                    bias: Default::default(),
+                }),
+                multisample: MultisampleState::default(),
+                multiview: None,
+            });
+        Self {
+            output_target,
+            pipeline,
+        }
+    }
+}
+
+impl Ground {
+    pub fn new(
+        ctx: &WgpuContext,
+        chunk_manager: &ChunkManager,
+        output_target: Rc<RenderTarget>,
+    ) -> Self {
+        let mut res = Resources::new(ctx, chunk_manager);
+        let dynamic = DynamicResources::new(ctx, &mut res, output_target);
+        Self {
+            res,
+            dynamic,
+            enabled: false,
+            plane_y: 0,
+            color: glm::vec3(0.35, 0.35, 0.38),
+            shadow_strength: 0.7,
+            region: Region::default(),
+            heights_key: None,
+        }
+    }
+
+    /// Draws into the same target as the voxel render stage
+    pub fn resize(&mut self, ctx: &WgpuContext, output_target: Rc<RenderTarget>) {
+        self.dynamic = DynamicResources::new(ctx, &mut self.res, output_target);
+    }
+
+    fn region_of(chunk_manager: &ChunkManager) -> Region {
+        let mut min = glm::vec2(i32::MAX, i32::MAX);
+        let mut max = glm::vec2(i32::MIN, i32::MIN);
+        for pos in chunk_manager.chunks().keys() {
+            min = glm::min2(&min, &pos.xz());
+            max = glm::max2(&max, &pos.xz());
+        }
+        if min.x > max.x {
+            return Region::default();
+        }
+        let size = (max - min + glm::vec2(1, 1)).map(|c| c as u32 * CHUNK_SIZE);
+        if size.x > MAX_REGION_SIZE || size.y > MAX_REGION_SIZE {
+            return Region::default();
+        }
+        Region {
+            min: min * CHUNK_SIZE as i32,
+            size,
+        }
+    }
+
+    fn update_heights(
+        &mut self,
+        ctx: &WgpuContext,
+        command_encoder: &mut CommandEncoder,
+        chunk_manager: &ChunkManager,
+    ) {
+        let region = Self::region_of(chunk_manager);
+        let key = HeightsKey {
+            sim_version: chunk_manager.sim_version(),
+            chunks: chunk_manager.chunks().len(),
+            version_sum: chunk_manager.chunks().values().map(|c| c.version).sum(),
+            plane_y: self.plane_y,
+            region,
+        };
+        if self.heights_key == Some(key) {
+            return;
+        }
+        self.heights_key = Some(key);
+        self.region = region;
+
+        let len = region.size.x * region.size.y;
+        if len > self.res.heights.len() {
+            self.res.heights = Heights::new(
+                ctx,
+                &self.res.heights_bind_group_layout,
+                &self.res.plane_bind_group_layout,
+                len.next_power_of_two(),
+            );
+        }
+        command_encoder.clear_buffer(&self.res.heights.buffer, 0, None);
+        if len == 0 {
+            return;
+        }
+
+        let mut compute_pass = command_encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("ground heights compute_pass"),
+            timestamp_writes: None,
+        });
+        compute_pass.set_pipeline(&self.res.heights_pipeline);
+        compute_pass.set_bind_group(0, &self.res.heights.compute_bind_group, &[]);
+        compute_pass.set_bind_group(1, chunk_manager.bind_group(false), &[]);
+        for chunk in chunk_manager.chunks().values() {
+            // Chunks entirely below the plane can't cast anything onto it
+            if (chunk.pos.y + 1) * CHUNK_SIZE as i32 <= self.plane_y {
+                continue;
+            }
+            let (group, origin_x) = chunk_manager.offset_to_group_and_origin_x(chunk.offset());
+            compute_pass.set_push_constants(
+                0,
+                bytemuck::cast_slice(&[HeightsPushConstants {
+                    group,
+                    origin_x,
+                    which: chunk_manager.which(),
+                    plane_y: self.plane_y,
+                    chunk_pos: chunk.pos,
+                    region_width: region.size.x,
+                    region_min: region.min,
+                    region_depth: region.size.y,
+                    ..Default::default()
+                }]),
+            );
+            let workgroups = CHUNK_SIZE / 4;
+            compute_pass.dispatch_workgroups(workgroups, workgroups, workgroups);
+        }
+    }
+
+    pub fn update(
+        &mut self,
+        ctx: &WgpuContext,
+        command_encoder: &mut CommandEncoder,
+        chunk_manager: &ChunkManager,
+        view_proj: &glm::Mat4x4,
+        camera_pos: &glm::Vec3,
+    ) {
+        self.update_heights(ctx, command_encoder, chunk_manager);
+
+        let mut render_pass = command_encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("ground render_pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: &self.dynamic.output_target.render_target,
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Load,
+                    store: StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                view: self
+                    .dynamic
+                    .output_target
+                    .depth_target
+                    .as_ref()
+                    .expect("no depth target"),
+                depth_ops: Some(Operations {
+                    load: LoadOp::Load,
+                    store: StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        render_pass.set_pipeline(&self.dynamic.pipeline);
+        render_pass.set_bind_group(0, &self.res.heights.render_bind_group, &[]);
+        render_pass.set_push_constants(
+            ShaderStages::VERTEX_FRAGMENT,
+            0,
+            bytemuck::cast_slice(&[PlanePushConstants {
+                view_proj: *view_proj,
+                camera_pos: *camera_pos,
+                plane_y: self.plane_y as f32,
+                region_min: self.region.min,
+                region_size: self.region.size,
+                color: glm::vec4(self.color.x, self.color.y, self.color.z, 1.0),
+                shadow_strength: self.shadow_strength,
+                extent: 10000.0,
+                ..Default::default()
+            }]),
+        );
+        render_pass.draw(0..6, 0..1);
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.collapsing("Ground plane", |ui| {
+            ui.checkbox(&mut self.enabled, "Enabled");
+            ui.add(egui::DragValue::new(&mut self.plane_y).prefix("Height "));
+            ui.horizontal(|ui| {
+                ui.label("Color");
+                let mut color: [f32; 3] = self.color.into();
+                if ui.color_edit_button_rgb(&mut color).changed() {
+                    self.color = color.into();
+                }
+            });
+            ui.add(egui::Slider::new(&mut self.shadow_strength, 0.0..=1.0).text("Shadow strength"));
+        });
+    }
+}
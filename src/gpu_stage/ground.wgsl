@@ -0,0 +1,96 @@
+#include "common.wgsl"
+
+struct PushConstants {
+    @size(64) view_proj: mat4x4<f32>,
+    @size(12) camera_pos: vec3<f32>,
+    @size(4) plane_y: f32,
+    @size(8) region_min: vec2<i32>,
+    @size(8) region_size: vec2<u32>,
+    @size(16) color: vec4<f32>,
+    @size(4) shadow_strength: f32,
+    @size(12) extent: f32,
+};
+
+var<push_constant> consts: PushConstants;
+
+@group(0) @binding(0)
+var<storage, read> heights: array<u32>;
+
+struct VertexOut {
+    @builtin(position) position: vec4<f32>,
+    @location(0) world_pos: vec3<f32>,
+};
+
+var<private> corners: array<vec2<f32>, 6> = array<vec2<f32>, 6>(
+    vec2<f32>(-1.0, -1.0),
+    vec2<f32>(-1.0,  1.0),
+    vec2<f32>( 1.0, -1.0),
+    vec2<f32>( 1.0, -1.0),
+    vec2<f32>(-1.0,  1.0),
+    vec2<f32>( 1.0,  1.0),
+);
+
+// A large quad that follows the camera, so the plane looks infinite
+@vertex
+fn vs_main(@builtin(vertex_index) v_idx: u32) -> VertexOut {
+    let corner = corners[v_idx] * consts.extent + consts.camera_pos.xz;
+    let world_pos = vec3<f32>(corner.x, consts.plane_y, corner.y);
+
+    var out: VertexOut;
+    out.position = consts.view_proj * vec4<f32>(world_pos, 1.0);
+    out.world_pos = world_pos;
+    return out;
+}
+
+// Height of the lowest voxel above the plane in a column, negative if there is none
+fn column_height(column: vec2<i32>) -> f32 {
+    let c = column - consts.region_min;
+    if(any(c < vec2<i32>(0)) || any(vec2<u32>(c) >= consts.region_size)) {
+        return -1.0;
+    }
+    let value = heights[u32(c.y) * consts.region_size.x + u32(c.x)];
+    if(value == 0u) {
+        return -1.0;
+    }
+    return f32(0xFFFFFFFFu - value);
+}
+
+fn grid_line(p: vec2<f32>, spacing: f32) -> f32 {
+    let scaled = p / spacing;
+    let dist = abs(fract(scaled - 0.5) - 0.5) / fwidth(scaled);
+    return 1.0 - min(min(dist.x, dist.y), 1.0);
+}
+
+@fragment
+fn fs_main(in: VertexOut) -> @location(0) vec4<f32> {
+    let p = in.world_pos.xz;
+    let fine = grid_line(p, 1.0);
+    let coarse = grid_line(p, f32(CHUNK_SIZE));
+
+    // Straight down shadow from the column above, plus ambient occlusion from nearby columns that
+    // fades with their height and distance
+    let center = vec2<i32>(floor(p));
+    var occlusion = 0.0;
+    let direct = column_height(center);
+    if(direct >= 0.0) {
+        occlusion = exp(-direct * 0.02);
+    }
+    for(var dz = -3; dz <= 3; dz += 1) {
+        for(var dx = -3; dx <= 3; dx += 1) {
+            let h = column_height(center + vec2<i32>(dx, dz));
+            if(h >= 0.0) {
+                let d = length(vec2<f32>(f32(dx), f32(dz)) + vec2<f32>(0.5) - fract(p));
+                occlusion += exp(-h * 0.15) * max(0.0, 1.0 - d / 3.5) * 0.1;
+            }
+        }
+    }
+    occlusion = clamp(occlusion, 0.0, 1.0);
+
+    // Thin lines fade out with distance before they alias
+    let distance = length(in.world_pos - consts.camera_pos);
+    let fine_fade = clamp(1.0 - distance / 200.0, 0.0, 1.0);
+    let line = max(fine * fine_fade * 0.15, coarse * 0.4);
+
+    let color = mix(consts.color.rgb, vec3<f32>(1.0), line) * (1.0 - consts.shadow_strength * occlusion);
+    return vec4<f32>(color, 1.0);
+}
@@ -0,0 +1,44 @@
+#include "common.wgsl"
+
+struct PushConstants {
+    @size(4) group: u32,
+    @size(4) origin_x: u32,
+    @size(4) which: u32,
+    @size(4) plane_y: i32,
+    @size(12) chunk_pos: vec3<i32>,
+    @size(4) region_width: u32,
+    @size(8) region_min: vec2<i32>,
+    @size(8) region_depth: u32,
+};
+
+var<push_constant> consts: PushConstants;
+
+// Per column of the region: 0 when nothing is above the plane, otherwise 0xFFFFFFFF minus the
+// height of the lowest voxel above it, so atomicMax keeps the lowest one
+@group(0) @binding(0)
+var<storage, read_write> heights: array<atomic<u32>>;
+
+@group(1) @binding(0)
+var atlas: texture_storage_3d<{{CHUNK_FORMAT}}, read>;
+
+@group(1) @binding(1)
+var chunk_groups: binding_array<texture_storage_3d<{{CHUNK_FORMAT}}, read>, 8>;
+
+@compute
+@workgroup_size(4, 4, 4)
+fn cs_heights(@builtin(global_invocation_id) gid: vec3<u32>) {
+    let pos = vec3<i32>(gid);
+    let world_pos = consts.chunk_pos * CHUNK_SIZE + pos;
+    if(world_pos.y < consts.plane_y) {
+        return;
+    }
+    let column = world_pos.xz - consts.region_min;
+    if(any(column < vec2<i32>(0)) || u32(column.x) >= consts.region_width || u32(column.y) >= consts.region_depth) {
+        return;
+    }
+    let cur = textureLoad(chunk_groups[consts.group], pos + vec3<i32>(vec3<u32>(consts.origin_x, 0u, consts.which)) * CHUNK_SIZE).r;
+    if(cur == 0u) {
+        return;
+    }
+    atomicMax(&heights[u32(column.y) * consts.region_width + u32(column.x)], 0xFFFFFFFFu - u32(world_pos.y - consts.plane_y));
+}
@@ -0,0 +1,623 @@
+use std::collections::HashMap;
+use std::mem::size_of;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use bytemuck::{Pod, Zeroable};
+use nalgebra_glm as glm;
+use wgpu::*;
+
+use crate::chunk::CHUNK_SIZE;
+use crate::chunk_manager::ChunkManager;
+use crate::gpu_stage::overlay::Overlay;
+use crate::shader_prep::ShaderPrep;
+use crate::wgpu_context::WgpuContext;
+
+/// Voxels per heat cell along every axis
+pub const HEAT_CELL_SIZE: u32 = 8;
+const HEAT_PER_AXIS: u32 = CHUNK_SIZE / HEAT_CELL_SIZE;
+const HEAT_PER_CHUNK: u32 = HEAT_PER_AXIS * HEAT_PER_AXIS * HEAT_PER_AXIS;
+// Chunks the buffers have room for at first, they double when outgrown
+const MIN_HEAT_CAPACITY: u32 = 64;
+// Heat cells the visualization draws, the hottest ones
+const MAX_DRAWN: usize = 4096;
+
+fn heat_bytes(chunks: u32) -> u64 {
+    chunks as u64 * HEAT_PER_CHUNK as u64 * size_of::<f32>() as u64
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable, Default, PartialEq)]
+struct HeatParams {
+    emission: f32,
+    cooling: f32,
+    diffusion: f32,
+    enabled: u32,
+    birth_max: f32,
+    survival_max: f32,
+    _pad0: [u32; 2],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable, Default)]
+struct HeatPushConstants {
+    num_chunks: u32,
+    chunks_per_buffer_shift: u32,
+    which: u32,
+    _pad0: u32,
+}
+
+// Two copies of the field, ticks diffuse from one into the other
+struct HeatBuffers {
+    chunks: u32,
+    temperature: [Buffer; 2],
+    positions: Buffer,
+    // Indexed by the buffer diffusion reads, it writes the other one
+    diffuse_bind_groups: [BindGroup; 2],
+    // Indexed by the buffer the simulation reads
+    read_bind_groups: [BindGroup; 2],
+}
+
+/// Temperature per 8x8x8 block of cells that filled cells heat up, diffusing and cooling off
+/// every tick before the simulation runs. Rules can keep cells from being born or surviving where
+/// it is too hot. Tick t diffuses from buffer t & 1 into the other one, so running a tick again
+/// gives the same field. The field isn't part of snapshots.
+pub struct HeatField {
+    pub enabled: bool,
+    pub emission: f32,
+    pub cooling: f32,
+    pub diffusion: f32,
+    pub birth_max: f32,
+    pub survival_max: f32,
+    // Draws the hotter heat cells in false color
+    pub visualize: bool,
+    pub visualize_min: f32,
+    pub readback_interval: u32,
+    bind_group_layout: BindGroupLayout,
+    diffuse_bind_group_layout: BindGroupLayout,
+    pipeline: ComputePipeline,
+    params_buffer: Buffer,
+    uploaded_params: Option<HeatParams>,
+    buffers: HeatBuffers,
+    // Chunk at every offset the field is laid out for, and the layout version it came from
+    positions: Vec<glm::IVec3>,
+    layout_version: Option<u64>,
+    readback_buffer: Option<Buffer>,
+    readback_positions: Vec<glm::IVec3>,
+    last_readback: Option<u64>,
+    copied: bool,
+    map_requested: bool,
+    mapped: Arc<AtomicBool>,
+    // World position of the corner and temperature of the cells to draw
+    hot_cells: Vec<(glm::Vec3, f32)>,
+    max_temperature: f32,
+}
+
+impl HeatBuffers {
+    fn new(
+        ctx: &WgpuContext,
+        bind_group_layout: &BindGroupLayout,
+        diffuse_bind_group_layout: &BindGroupLayout,
+        params_buffer: &Buffer,
+        chunks: u32,
+    ) -> Self {
+        let temperature = [0, 1].map(|_| {
+            ctx.device.create_buffer(&BufferDescriptor {
+                label: Some("heat temperature_buffer"),
+                size: heat_bytes(chunks),
+                usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            })
+        });
+        let positions = ctx.device.create_buffer(&BufferDescriptor {
+            label: Some("heat positions_buffer"),
+            size: chunks as u64 * size_of::<glm::IVec4>() as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let diffuse_bind_groups = [0, 1].map(|src| {
+            ctx.device.create_bind_group(&BindGroupDescriptor {
+                label: Some("heat diffuse_bind_group"),
+                layout: diffuse_bind_group_layout,
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: temperature[src].as_entire_binding(),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: temperature[src ^ 1].as_entire_binding(),
+                    },
+                    BindGroupEntry {
+                        binding: 2,
+                        resource: params_buffer.as_entire_binding(),
+                    },
+                    BindGroupEntry {
+                        binding: 3,
+                        resource: positions.as_entire_binding(),
+                    },
+                ],
+            })
+        });
+        let read_bind_groups = [0, 1].map(|i| {
+            ctx.device.create_bind_group(&BindGroupDescriptor {
+                label: Some("heat read_bind_group"),
+                layout: bind_group_layout,
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: temperature[i].as_entire_binding(),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: params_buffer.as_entire_binding(),
+                    },
+                ],
+            })
+        });
+        Self {
+            chunks,
+            temperature,
+            positions,
+            diffuse_bind_groups,
+            read_bind_groups,
+        }
+    }
+}
+
+impl HeatField {
+    pub fn new(ctx: &WgpuContext, chunk_manager: &ChunkManager) -> Self {
+        let source = ShaderPrep::new()
+            .define("HEAT_CELL_SIZE", HEAT_CELL_SIZE)
+            .process(include_str!("./heat.wgsl"));
+        let shader = ctx.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("heat shader"),
+            source: ShaderSource::Wgsl(source.into()),
+        });
+
+        let storage_entry = |binding, read_only| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let uniform_entry = |binding| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: BufferSize::new(size_of::<HeatParams>() as u64),
+            },
+            count: None,
+        };
+        // Temperature and parameters, bound by the simulation shader
+        let bind_group_layout = ctx
+            .device
+            .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("heat bind_group_layout"),
+                entries: &[storage_entry(0, true), uniform_entry(1)],
+            });
+        let diffuse_bind_group_layout =
+            ctx.device
+                .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                    label: Some("heat diffuse_bind_group_layout"),
+                    entries: &[
+                        storage_entry(0, true),
+                        storage_entry(1, false),
+                        uniform_entry(2),
+                        storage_entry(3, true),
+                    ],
+                });
+
+        let pipeline_layout = ctx
+            .device
+            .create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some("heat pipeline_layout"),
+                bind_group_layouts: &[
+                    &diffuse_bind_group_layout,
+                    chunk_manager.bind_group_layout(false),
+                ],
+                push_constant_ranges: &[PushConstantRange {
+                    stages: ShaderStages::COMPUTE,
+                    range: 0..size_of::<HeatPushConstants>() as u32,
+                }],
+            });
+        let pipeline = ctx
+            .device
+            .create_compute_pipeline(&ComputePipelineDescriptor {
+                label: Some("heat pipeline"),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point: "cs_diffuse",
+            });
+
+        let params_buffer = ctx.device.create_buffer(&BufferDescriptor {
+            label: Some("heat params_buffer"),
+            size: size_of::<HeatParams>() as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let buffers = HeatBuffers::new(
+            ctx,
+            &bind_group_layout,
+            &diffuse_bind_group_layout,
+            &params_buffer,
+            MIN_HEAT_CAPACITY,
+        );
+
+        Self {
+            enabled: false,
+            emission: 0.05,
+            cooling: 0.02,
+            diffusion: 0.1,
+            birth_max: 1.0,
+            survival_max: 2.0,
+            visualize: false,
+            visualize_min: 0.25,
+            readback_interval: 8,
+            bind_group_layout,
+            diffuse_bind_group_layout,
+            pipeline,
+            params_buffer,
+            uploaded_params: None,
+            buffers,
+            positions: Vec::new(),
+            layout_version: None,
+            readback_buffer: None,
+            readback_positions: Vec::new(),
+            last_readback: None,
+            copied: false,
+            map_requested: false,
+            mapped: Arc::new(AtomicBool::new(false)),
+            hot_cells: Vec::new(),
+            max_temperature: 0.0,
+        }
+    }
+
+    /// Layout of the bind group the simulation shader reads the field from
+    pub fn bind_group_layout(&self) -> &BindGroupLayout {
+        &self.bind_group_layout
+    }
+
+    /// The field as the simulation sees it at `tick`, after that tick's diffusion
+    pub fn bind_group(&self, tick: u64) -> &BindGroup {
+        &self.buffers.read_bind_groups[((tick + 1) & 1) as usize]
+    }
+
+    fn params(&self) -> HeatParams {
+        HeatParams {
+            emission: self.emission,
+            cooling: self.cooling,
+            diffusion: self.diffusion,
+            enabled: self.enabled as u32,
+            birth_max: self.birth_max,
+            survival_max: self.survival_max,
+            ..Default::default()
+        }
+    }
+
+    pub fn update(&mut self) {
+        if self.mapped.load(Ordering::Acquire) {
+            self.process_readback();
+        }
+    }
+
+    /// Uploads the parameters and moves the field along with chunks that changed offsets, before
+    /// the ticks starting at `tick` are dispatched
+    pub fn prepare(
+        &mut self,
+        ctx: &WgpuContext,
+        encoder: &mut CommandEncoder,
+        chunk_manager: &ChunkManager,
+        tick: u64,
+    ) {
+        let params = self.params();
+        if self.uploaded_params != Some(params) {
+            ctx.staging.write(
+                &ctx.device,
+                &ctx.queue,
+                encoder,
+                "heat params",
+                &self.params_buffer,
+                0,
+                bytemuck::bytes_of(&params),
+            );
+            self.uploaded_params = Some(params);
+        }
+        if !self.enabled {
+            // Starts cold when enabled again
+            self.positions.clear();
+            self.layout_version = None;
+            return;
+        }
+        if self.layout_version != Some(chunk_manager.layout_version())
+            || self.buffers.chunks < chunk_manager.num_offsets()
+        {
+            self.relayout(ctx, encoder, chunk_manager, tick);
+        }
+    }
+
+    // Copies the heat of every chunk from its old offset to its new one, chunks that are new
+    // start cold
+    fn relayout(
+        &mut self,
+        ctx: &WgpuContext,
+        encoder: &mut CommandEncoder,
+        chunk_manager: &ChunkManager,
+        tick: u64,
+    ) {
+        let chunks = chunk_manager.num_offsets();
+        let current = (tick & 1) as usize;
+        let old_buffers = (self.buffers.chunks < chunks).then(|| {
+            let capacity = chunks.max(MIN_HEAT_CAPACITY).next_power_of_two();
+            std::mem::replace(
+                &mut self.buffers,
+                HeatBuffers::new(
+                    ctx,
+                    &self.bind_group_layout,
+                    &self.diffuse_bind_group_layout,
+                    &self.params_buffer,
+                    capacity,
+                ),
+            )
+        });
+        // Without new buffers the result goes into the other buffer and is copied back
+        let (src, dst) = match &old_buffers {
+            Some(old) => (
+                &old.temperature[current],
+                &self.buffers.temperature[current],
+            ),
+            None => (
+                &self.buffers.temperature[current],
+                &self.buffers.temperature[current ^ 1],
+            ),
+        };
+
+        let old_offsets = self
+            .positions
+            .iter()
+            .enumerate()
+            .map(|(offset, pos)| (*pos, offset as u64))
+            .collect::<HashMap<_, _>>();
+        let chunk_bytes = heat_bytes(1);
+        encoder.clear_buffer(dst, 0, None);
+        for (offset, pos) in chunk_manager.offset_positions().iter().enumerate() {
+            if let Some(old_offset) = old_offsets.get(pos) {
+                encoder.copy_buffer_to_buffer(
+                    src,
+                    old_offset * chunk_bytes,
+                    dst,
+                    offset as u64 * chunk_bytes,
+                    chunk_bytes,
+                );
+            }
+        }
+        if old_buffers.is_none() && chunks > 0 {
+            encoder.copy_buffer_to_buffer(dst, 0, src, 0, heat_bytes(chunks));
+        }
+
+        let positions = chunk_manager
+            .offset_positions()
+            .iter()
+            .map(|p| glm::vec4(p.x, p.y, p.z, 0))
+            .collect::<Vec<_>>();
+        ctx.staging.write(
+            &ctx.device,
+            &ctx.queue,
+            encoder,
+            "heat positions",
+            &self.buffers.positions,
+            0,
+            bytemuck::cast_slice(&positions),
+        );
+        self.positions = chunk_manager.offset_positions().to_vec();
+        self.layout_version = Some(chunk_manager.layout_version());
+    }
+
+    /// Diffuses the field for `tick`, with `which` the buffer that holds the cells at the start of
+    /// it. The caller has to set its own pipeline and bind groups again afterwards.
+    pub fn encode_diffuse<'a>(
+        &'a self,
+        compute_pass: &mut ComputePass<'a>,
+        chunk_manager: &'a ChunkManager,
+        tick: u64,
+        which: u32,
+    ) {
+        compute_pass.set_pipeline(&self.pipeline);
+        compute_pass.set_bind_group(
+            0,
+            &self.buffers.diffuse_bind_groups[(tick & 1) as usize],
+            &[],
+        );
+        compute_pass.set_bind_group(1, chunk_manager.bind_group(false), &[]);
+        compute_pass.set_push_constants(
+            0,
+            bytemuck::bytes_of(&HeatPushConstants {
+                num_chunks: chunk_manager.num_offsets(),
+                chunks_per_buffer_shift: chunk_manager.chunks_per_group().ilog2(),
+                which,
+                ..Default::default()
+            }),
+        );
+        let blocks = HEAT_PER_AXIS / 4;
+        compute_pass.dispatch_workgroups(chunk_manager.num_offsets(), blocks * blocks * blocks, 1);
+    }
+
+    /// Copies the field out for the visualization every few ticks, `tick` is the next one to run
+    pub fn encode_readback(
+        &mut self,
+        ctx: &WgpuContext,
+        encoder: &mut CommandEncoder,
+        chunk_manager: &ChunkManager,
+        tick: u64,
+    ) {
+        if !self.enabled || !self.visualize || self.map_requested || self.copied {
+            return;
+        }
+        if self
+            .last_readback
+            .is_some_and(|last| tick < last + self.readback_interval.max(1) as u64 && tick >= last)
+        {
+            return;
+        }
+        let size = heat_bytes(chunk_manager.num_offsets());
+        if size == 0 {
+            return;
+        }
+        if self
+            .readback_buffer
+            .as_ref()
+            .map_or(true, |b| b.size() < size)
+        {
+            self.readback_buffer = Some(ctx.device.create_buffer(&BufferDescriptor {
+                label: Some("heat readback_buffer"),
+                size: size.next_power_of_two(),
+                usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+                mapped_at_creation: false,
+            }));
+        }
+        let readback_buffer = self.readback_buffer.as_ref().unwrap();
+        encoder.copy_buffer_to_buffer(
+            &self.buffers.temperature[(tick & 1) as usize],
+            0,
+            readback_buffer,
+            0,
+            size,
+        );
+        self.readback_positions = self.positions.clone();
+        self.last_readback = Some(tick);
+        self.copied = true;
+    }
+
+    fn process_readback(&mut self) {
+        let readback_buffer = self.readback_buffer.as_ref().unwrap();
+        self.hot_cells.clear();
+        self.max_temperature = 0.0;
+        {
+            let range = readback_buffer.slice(..).get_mapped_range();
+            let temperatures: &[f32] = bytemuck::cast_slice(&range);
+            for (pos, chunk) in self
+                .readback_positions
+                .iter()
+                .zip(temperatures.chunks_exact(HEAT_PER_CHUNK as usize))
+            {
+                let origin = pos.cast::<f32>() * CHUNK_SIZE as f32;
+                for (i, &t) in chunk.iter().enumerate() {
+                    self.max_temperature = self.max_temperature.max(t);
+                    if t < self.visualize_min {
+                        continue;
+                    }
+                    let n = HEAT_PER_AXIS as usize;
+                    let local = glm::vec3(i % n, (i / n) % n, i / (n * n)).cast::<f32>();
+                    self.hot_cells
+                        .push((origin + local * HEAT_CELL_SIZE as f32, t));
+                }
+            }
+        }
+        readback_buffer.unmap();
+        self.mapped.store(false, Ordering::Release);
+        self.map_requested = false;
+
+        self.hot_cells.sort_by(|a, b| b.1.total_cmp(&a.1));
+        self.hot_cells.truncate(MAX_DRAWN);
+    }
+
+    pub fn after_submit(&mut self) {
+        if !self.copied {
+            return;
+        }
+        self.copied = false;
+        self.map_requested = true;
+        let mapped = self.mapped.clone();
+        self.readback_buffer
+            .as_ref()
+            .unwrap()
+            .slice(..)
+            .map_async(MapMode::Read, move |result| match result {
+                Ok(_) => mapped.store(true, Ordering::Release),
+                Err(e) => log::error!("Failed to map heat readback buffer: {:?}", e),
+            });
+    }
+
+    // Blue through red, relative to the hottest cell
+    fn false_color(t: f32) -> glm::Vec4 {
+        let stops = [
+            glm::vec3(0.1, 0.2, 1.0),
+            glm::vec3(0.0, 0.9, 0.9),
+            glm::vec3(1.0, 0.9, 0.1),
+            glm::vec3(1.0, 0.1, 0.0),
+        ];
+        let x = t.clamp(0.0, 1.0) * (stops.len() - 1) as f32;
+        let i = (x as usize).min(stops.len() - 2);
+        let c = glm::lerp(&stops[i], &stops[i + 1], x - i as f32);
+        glm::vec4(c.x, c.y, c.z, 1.0)
+    }
+
+    pub fn draw(&self, overlay: &Overlay) {
+        if !self.enabled || !self.visualize {
+            return;
+        }
+        let range = (self.max_temperature - self.visualize_min).max(f32::EPSILON);
+        let size = glm::vec3(1.0, 1.0, 1.0) * HEAT_CELL_SIZE as f32;
+        for (min, t) in &self.hot_cells {
+            let color = Self::false_color((t - self.visualize_min) / range);
+            overlay.cuboid(color, *min, min + size);
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.collapsing("Heat", |ui| {
+            ui.checkbox(&mut self.enabled, "Enabled")
+                .on_hover_text(format!(
+                    "Filled cells heat up their {0}x{0}x{0} block, the heat spreads and cools off \
+                 every tick",
+                    HEAT_CELL_SIZE
+                ));
+            ui.add(
+                egui::Slider::new(&mut self.emission, 0.0..=1.0)
+                    .logarithmic(true)
+                    .text("Emission"),
+            );
+            ui.add(
+                egui::Slider::new(&mut self.cooling, 0.0..=0.5)
+                    .logarithmic(true)
+                    .text("Cooling"),
+            );
+            ui.add(egui::Slider::new(&mut self.diffusion, 0.0..=1.0 / 6.0).text("Diffusion"));
+            ui.add(
+                egui::Slider::new(&mut self.birth_max, 0.0..=100.0)
+                    .logarithmic(true)
+                    .text("No births above"),
+            )
+            .on_hover_text("Applies to the 2d and larger than life rules");
+            ui.add(
+                egui::Slider::new(&mut self.survival_max, 0.0..=100.0)
+                    .logarithmic(true)
+                    .text("No survival above"),
+            );
+            ui.checkbox(&mut self.visualize, "Show in overlay");
+            if self.visualize {
+                ui.add(
+                    egui::Slider::new(&mut self.visualize_min, 0.0..=100.0)
+                        .logarithmic(true)
+                        .text("Hide below"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut self.readback_interval, 1..=256)
+                        .logarithmic(true)
+                        .text("Update every n ticks"),
+                );
+                ui.label(format!(
+                    "Hottest {:.3}, showing {} cells",
+                    self.max_temperature,
+                    self.hot_cells.len()
+                ));
+            }
+        });
+    }
+}
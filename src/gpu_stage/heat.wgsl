@@ -0,0 +1,108 @@
+#include "common.wgsl"
+
+struct PushConstants {
+    @size(4) num_chunks: u32,
+    @size(4) chunks_per_buffer_shift: u32,
+    @size(4) which: u32,
+    @size(4) _pad0: u32,
+};
+
+struct HeatParams {
+    // Added per tick by a heat cell that is completely filled
+    emission: f32,
+    // Fraction of the heat lost per tick
+    cooling: f32,
+    diffusion: f32,
+    enabled: u32,
+    birth_max: f32,
+    survival_max: f32,
+};
+
+const HEAT_CELL_SIZE: i32 = {{HEAT_CELL_SIZE}};
+const HEAT_PER_AXIS: i32 = CHUNK_SIZE / HEAT_CELL_SIZE;
+const HEAT_PER_CHUNK: u32 = u32(HEAT_PER_AXIS * HEAT_PER_AXIS * HEAT_PER_AXIS);
+
+var<push_constant> consts: PushConstants;
+
+@group(0) @binding(0)
+var<storage, read> src: array<f32>;
+
+@group(0) @binding(1)
+var<storage, read_write> dst: array<f32>;
+
+@group(0) @binding(2)
+var<uniform> params: HeatParams;
+
+// Chunk position of every offset
+@group(0) @binding(3)
+var<storage, read> positions: array<vec4<i32>>;
+
+@group(1) @binding(0)
+var atlas: texture_storage_3d<{{CHUNK_FORMAT}}, read>;
+
+@group(1) @binding(1)
+var chunk_groups: binding_array<texture_storage_3d<{{CHUNK_FORMAT}}, read>, 8>;
+
+fn heat_index(chunk_idx: u32, pos: vec3<i32>) -> u32 {
+    return chunk_idx * HEAT_PER_CHUNK + u32(dot(pos, vec3<i32>(1, HEAT_PER_AXIS, HEAT_PER_AXIS * HEAT_PER_AXIS)));
+}
+
+// Heat at `pos` in heat cells of the chunk at `chunk_idx`, up to one cell outside of it. Missing
+// chunks are at the ambient temperature of 0.
+fn temperature(chunk_idx: u32, pos: vec3<i32>) -> f32 {
+    let outside = select(vec3<i32>(0), vec3<i32>(1), pos >= vec3<i32>(HEAT_PER_AXIS)) - select(vec3<i32>(0), vec3<i32>(1), pos < vec3<i32>(0));
+    if(all(outside == vec3<i32>(0))) {
+        return src[heat_index(chunk_idx, pos)];
+    }
+    let neighbor = textureLoad(atlas, positions[chunk_idx].xyz + outside + vec3<i32>(ATLAS_OFFSET)).r;
+    if(neighbor == 0u) {
+        return 0.0;
+    }
+    return src[heat_index(neighbor - 1u, pos - outside * HEAT_PER_AXIS)];
+}
+
+// Fraction of the voxels in the heat cell that are filled
+fn fill(chunk_idx: u32, pos: vec3<i32>) -> f32 {
+    let group = chunk_idx >> consts.chunks_per_buffer_shift;
+    let origin_x = chunk_idx & ((1u << consts.chunks_per_buffer_shift) - 1u);
+    let origin = vec3<i32>(vec3<u32>(origin_x, 0u, consts.which)) * CHUNK_SIZE + pos * HEAT_CELL_SIZE;
+    var filled = 0u;
+    for(var z = 0; z < HEAT_CELL_SIZE; z++) {
+        for(var y = 0; y < HEAT_CELL_SIZE; y++) {
+            for(var x = 0; x < HEAT_CELL_SIZE; x++) {
+                if(textureLoad(chunk_groups[group], origin + vec3<i32>(x, y, z)).r != 0u) {
+                    filled += 1u;
+                }
+            }
+        }
+    }
+    return f32(filled) / f32(HEAT_CELL_SIZE * HEAT_CELL_SIZE * HEAT_CELL_SIZE);
+}
+
+// One heat cell per invocation, the y workgroup picks the 4x4x4 block of heat cells in the chunk
+@compute
+@workgroup_size(4, 4, 4)
+fn cs_diffuse(
+    @builtin(local_invocation_id) lid: vec3<u32>,
+    @builtin(workgroup_id) wid: vec3<u32>,
+    ) {
+    let chunk_idx = wid.x;
+    if(chunk_idx >= consts.num_chunks) {
+        return;
+    }
+    let blocks = u32(HEAT_PER_AXIS) / 4u;
+    let block = vec3<u32>(wid.y % blocks, (wid.y / blocks) % blocks, wid.y / (blocks * blocks));
+    let pos = vec3<i32>(block * 4u + lid);
+
+    let cur = src[heat_index(chunk_idx, pos)];
+    var laplacian = -6.0 * cur;
+    laplacian += temperature(chunk_idx, pos + vec3<i32>(1, 0, 0));
+    laplacian += temperature(chunk_idx, pos + vec3<i32>(-1, 0, 0));
+    laplacian += temperature(chunk_idx, pos + vec3<i32>(0, 1, 0));
+    laplacian += temperature(chunk_idx, pos + vec3<i32>(0, -1, 0));
+    laplacian += temperature(chunk_idx, pos + vec3<i32>(0, 0, 1));
+    laplacian += temperature(chunk_idx, pos + vec3<i32>(0, 0, -1));
+
+    let next = cur + params.diffusion * laplacian + params.emission * fill(chunk_idx, pos);
+    dst[heat_index(chunk_idx, pos)] = max(next * (1.0 - params.cooling), 0.0);
+}
@@ -0,0 +1,481 @@
+use std::mem::size_of;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use bytemuck::{Pod, Zeroable};
+use wgpu::*;
+
+use crate::chunk::CHUNK_SIZE;
+use crate::chunk_manager::ChunkManager;
+use crate::gpu_stage::live_bounds::WorldKey;
+use crate::gpu_stage::simulate::SimulationMode;
+use crate::materials::Material;
+use crate::shader_prep::ShaderPrep;
+use crate::wgpu_context::WgpuContext;
+
+const WORKGROUP_SIZE: u32 = 4;
+// Distinct states the census tells apart, anything past that is counted as other
+const CENSUS_SLOTS: usize = 64;
+const CENSUS_WORDS: usize = CENSUS_SLOTS * 3 + 1;
+// States the legend lists, the last filter bit stands for everything that isn't listed
+pub const MAX_STATES: usize = 31;
+// Rows that can be toggled with the number keys
+pub const SHORTCUT_ROWS: usize = 9;
+const OTHER_BIT: u32 = 1 << MAX_STATES;
+// State keys of live cells and of powder cells that aren't a material, STATE_ALIVE and STATE_WALL
+// in cell_state.wgsl. Decaying cells are keyed by their decay step, materials by their value.
+const ALIVE_KEY: u32 = 0xFF;
+const WALL_KEY: u32 = 0x100;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable, Default)]
+struct CensusPushConstants {
+    group: u32,
+    origin_x: u32,
+    which: u32,
+    states: u32,
+    materials: u32,
+}
+
+/// Cell states meshing leaves out, matched by state key. Laid out as a uniform.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable, Default, PartialEq)]
+pub struct StateFilter {
+    // Bit n hides the n-th listed state, the last bit every state that isn't listed
+    hidden: u32,
+    count: u32,
+    // Reaction-diffusion cells with less v than this are empty, 0 when cells are colors
+    iso_level: f32,
+    // Generations state count when cells decay, their alpha byte is then the decay step
+    states: u32,
+    // Nonzero when cells are powder materials, which are states of their own
+    materials: u32,
+    _pad0: [u32; 3],
+    keys: [u32; MAX_STATES + 1],
+}
+
+impl StateFilter {
+    pub fn with_iso_level(mut self, iso_level: f32) -> Self {
+        self.iso_level = iso_level;
+        self
+    }
+
+    pub fn with_states(mut self, states: u32) -> Self {
+        self.states = states;
+        self
+    }
+
+    pub fn with_materials(mut self, materials: bool) -> Self {
+        self.materials = materials as u32;
+        self
+    }
+}
+
+pub struct LegendState {
+    pub key: u32,
+    // Some cell value of the state, colors vary within most states
+    pub value: u32,
+    pub count: u64,
+    pub hidden: bool,
+}
+
+impl LegendState {
+    pub fn color(&self) -> egui::Color32 {
+        let [r, g, b, _] = self.value.to_le_bytes();
+        egui::Color32::from_rgb(r, g, b)
+    }
+
+    pub fn name(&self, mode: SimulationMode) -> String {
+        match self.key {
+            ALIVE_KEY => mode.live_state_name().to_owned(),
+            WALL_KEY => "Wall".to_owned(),
+            step if step < ALIVE_KEY => format!("Decaying, step {}", step),
+            value => Material::from_value(value)
+                .map_or("Material", |material| material.name())
+                .to_owned(),
+        }
+    }
+}
+
+struct Resources {
+    pipeline: ComputePipeline,
+    census_buffer: Buffer,
+    readback_buffer: Buffer,
+    bind_group: BindGroup,
+}
+
+/// Lists the cell states in the world with their live counts, counted on the GPU every
+/// `interval` ticks while the legend is shown, and which of them meshing hides
+pub struct Legend {
+    res: Resources,
+    pub interval: u32,
+    last_run: Option<(u64, WorldKey)>,
+    copied: bool,
+    map_requested: bool,
+    mapped: Arc<AtomicBool>,
+    // Set when mapping failed, the next update requests another census
+    map_failed: Arc<AtomicBool>,
+    // In the order they were first seen, so hiding one doesn't move the others around
+    states: Vec<LegendState>,
+    other_count: u64,
+    other_hidden: bool,
+}
+
+impl Resources {
+    fn new(ctx: &WgpuContext, chunk_manager: &ChunkManager) -> Self {
+        let source = Material::define_all(ShaderPrep::new())
+            .define("CENSUS_SLOTS", CENSUS_SLOTS)
+            .process(include_str!("./legend.wgsl"));
+        let shader = ctx.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("legend shader"),
+            source: ShaderSource::Wgsl(source.into()),
+        });
+
+        let bind_group_layout = ctx
+            .device
+            .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("legend bind_group_layout"),
+                entries: &[BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+            });
+
+        let pipeline_layout = ctx
+            .device
+            .create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some("legend pipeline_layout"),
+                bind_group_layouts: &[&bind_group_layout, chunk_manager.bind_group_layout(false)],
+                push_constant_ranges: &[PushConstantRange {
+                    stages: ShaderStages::COMPUTE,
+                    range: 0..size_of::<CensusPushConstants>() as u32,
+                }],
+            });
+
+        let pipeline = ctx
+            .device
+            .create_compute_pipeline(&ComputePipelineDescriptor {
+                label: Some("legend pipeline"),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point: "cs_census",
+            });
+
+        let census_buffer = ctx.device.create_buffer(&BufferDescriptor {
+            label: Some("legend census_buffer"),
+            size: (CENSUS_WORDS * size_of::<u32>()) as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback_buffer = ctx.device.create_buffer(&BufferDescriptor {
+            label: Some("legend readback_buffer"),
+            size: (CENSUS_WORDS * size_of::<u32>()) as u64,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = ctx.device.create_bind_group(&BindGroupDescriptor {
+            label: Some("legend bind_group"),
+            layout: &bind_group_layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: census_buffer.as_entire_binding(),
+            }],
+        });
+
+        Self {
+            pipeline,
+            census_buffer,
+            readback_buffer,
+            bind_group,
+        }
+    }
+}
+
+impl Legend {
+    pub fn new(ctx: &WgpuContext, chunk_manager: &ChunkManager) -> Self {
+        Self {
+            res: Resources::new(ctx, chunk_manager),
+            interval: 16,
+            last_run: None,
+            copied: false,
+            map_requested: false,
+            mapped: Arc::new(AtomicBool::new(false)),
+            map_failed: Arc::new(AtomicBool::new(false)),
+            states: Vec::new(),
+            other_count: 0,
+            other_hidden: false,
+        }
+    }
+
+    /// What meshing should leave out
+    pub fn filter(&self) -> StateFilter {
+        let mut filter = StateFilter {
+            count: self.states.len() as u32,
+            ..Default::default()
+        };
+        for (i, state) in self.states.iter().enumerate() {
+            filter.keys[i] = state.key;
+            if state.hidden {
+                filter.hidden |= 1 << i;
+            }
+        }
+        if self.other_hidden {
+            filter.hidden |= OTHER_BIT;
+        }
+        filter
+    }
+
+    pub fn show_all(&mut self) {
+        for state in &mut self.states {
+            state.hidden = false;
+        }
+        self.other_hidden = false;
+    }
+
+    // States in the order the legend lists them, most common first. The filter keeps the order
+    // they were seen in.
+    fn rows(&self) -> Vec<usize> {
+        let mut rows = (0..self.states.len()).collect::<Vec<_>>();
+        rows.sort_by_key(|&i| std::cmp::Reverse(self.states[i].count));
+        rows
+    }
+
+    /// Hides or shows the state in the `row`-th row of the legend
+    pub fn toggle_row(&mut self, row: usize) {
+        if let Some(&i) = self.rows().get(row) {
+            self.states[i].hidden = !self.states[i].hidden;
+        }
+    }
+
+    pub fn isolate_row(&mut self, row: usize) {
+        if let Some(&i) = self.rows().get(row) {
+            self.isolate(i);
+        }
+    }
+
+    /// Hides everything but the `index`-th state, or shows everything again if that's already
+    /// the case
+    fn isolate(&mut self, index: usize) {
+        let already_isolated = self.other_hidden
+            && self
+                .states
+                .iter()
+                .enumerate()
+                .all(|(i, state)| state.hidden == (i != index));
+        for (i, state) in self.states.iter_mut().enumerate() {
+            state.hidden = !already_isolated && i != index;
+        }
+        self.other_hidden = !already_isolated;
+    }
+
+    fn due(&self, chunk_manager: &ChunkManager, key: WorldKey) -> bool {
+        let Some((last_sim_version, last_key)) = self.last_run else {
+            return true;
+        };
+        key != last_key
+            || chunk_manager.sim_version() < last_sim_version
+            || chunk_manager.sim_version() >= last_sim_version + self.interval.max(1) as u64
+    }
+
+    /// Counts the cells again if the world changed enough, only needed while the counts are shown.
+    /// `states` and `materials` tell the states apart like the meshing state filter does.
+    pub fn update(
+        &mut self,
+        command_encoder: &mut CommandEncoder,
+        chunk_manager: &ChunkManager,
+        states: u32,
+        materials: bool,
+    ) {
+        if self.mapped.load(Ordering::Acquire) {
+            self.process_readback();
+        }
+        if self.map_failed.swap(false, Ordering::AcqRel) {
+            self.map_requested = false;
+        }
+
+        let key = WorldKey::of(chunk_manager);
+        if self.map_requested || !self.due(chunk_manager, key) {
+            return;
+        }
+        self.last_run = Some((chunk_manager.sim_version(), key));
+
+        command_encoder.clear_buffer(&self.res.census_buffer, 0, None);
+        {
+            let mut compute_pass = command_encoder.begin_compute_pass(&ComputePassDescriptor {
+                label: Some("legend compute_pass"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_pipeline(&self.res.pipeline);
+            compute_pass.set_bind_group(0, &self.res.bind_group, &[]);
+            compute_pass.set_bind_group(1, chunk_manager.bind_group(false), &[]);
+            for chunk in chunk_manager.chunks().values() {
+                let (group, origin_x) = chunk_manager.offset_to_group_and_origin_x(chunk.offset());
+                compute_pass.set_push_constants(
+                    0,
+                    bytemuck::cast_slice(&[CensusPushConstants {
+                        group,
+                        origin_x,
+                        which: chunk_manager.which(),
+                        states,
+                        materials: materials as u32,
+                    }]),
+                );
+                let workgroups = CHUNK_SIZE / WORKGROUP_SIZE;
+                compute_pass.dispatch_workgroups(workgroups, workgroups, workgroups);
+            }
+        }
+        command_encoder.copy_buffer_to_buffer(
+            &self.res.census_buffer,
+            0,
+            &self.res.readback_buffer,
+            0,
+            (CENSUS_WORDS * size_of::<u32>()) as u64,
+        );
+        self.copied = true;
+    }
+
+    fn process_readback(&mut self) {
+        let words =
+            bytemuck::cast_slice::<u8, u32>(&self.res.readback_buffer.slice(..).get_mapped_range())
+                .to_vec();
+        self.res.readback_buffer.unmap();
+        self.mapped.store(false, Ordering::Release);
+        self.map_requested = false;
+
+        for state in &mut self.states {
+            state.count = 0;
+        }
+        self.other_count = words[CENSUS_SLOTS * 3] as u64;
+        // States that died out make room for new ones, unless they're hidden
+        let mut seen = words[..CENSUS_SLOTS * 3]
+            .chunks_exact(3)
+            .filter(|slot| slot[0] != 0)
+            .map(|slot| (slot[0], slot[1] as u64, slot[2]))
+            .collect::<Vec<_>>();
+        seen.sort_by_key(|(_, count, _)| std::cmp::Reverse(*count));
+        for (key, count, value) in seen.iter().copied() {
+            if let Some(state) = self.states.iter_mut().find(|state| state.key == key) {
+                state.count = count;
+                state.value = value;
+            }
+        }
+        self.states.retain(|state| state.count > 0 || state.hidden);
+        for (key, count, value) in seen {
+            if self.states.iter().any(|state| state.key == key) {
+                continue;
+            }
+            if self.states.len() < MAX_STATES {
+                self.states.push(LegendState {
+                    key,
+                    value,
+                    count,
+                    hidden: self.other_hidden,
+                });
+            } else {
+                self.other_count += count;
+            }
+        }
+    }
+
+    pub fn after_submit(&mut self) {
+        if !self.copied {
+            return;
+        }
+        self.copied = false;
+        self.map_requested = true;
+        let mapped = self.mapped.clone();
+        let map_failed = self.map_failed.clone();
+        self.res
+            .readback_buffer
+            .slice(..)
+            .map_async(MapMode::Read, move |result| match result {
+                Ok(_) => mapped.store(true, Ordering::Release),
+                Err(e) => {
+                    log::error!("Failed to map legend census buffer: {:?}", e);
+                    map_failed.store(true, Ordering::Release);
+                }
+            });
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui, mode: SimulationMode) {
+        ui.horizontal(|ui| {
+            if ui.button("Show all").on_hover_text("0").clicked() {
+                self.show_all();
+            }
+            ui.add(
+                egui::Slider::new(&mut self.interval, 1..=256)
+                    .logarithmic(true)
+                    .text("Count every n ticks"),
+            );
+        });
+
+        let mut isolate = None;
+        egui::Grid::new("legend")
+            .num_columns(6)
+            .striped(true)
+            .show(ui, |ui| {
+                for (row, i) in self.rows().into_iter().enumerate() {
+                    let state = &mut self.states[i];
+                    if row < SHORTCUT_ROWS {
+                        ui.weak((row + 1).to_string());
+                    } else {
+                        ui.label("");
+                    }
+                    let (rect, _) =
+                        ui.allocate_exact_size(egui::vec2(16.0, 16.0), egui::Sense::hover());
+                    ui.painter().rect_filled(rect, 2.0, state.color());
+                    ui.label(state.name(mode));
+                    ui.label(state.count.to_string());
+                    let mut visible = !state.hidden;
+                    if ui
+                        .checkbox(&mut visible, "Visible")
+                        .on_hover_text("The number key in front")
+                        .changed()
+                    {
+                        state.hidden = !visible;
+                    }
+                    if ui
+                        .small_button("Isolate")
+                        .on_hover_text(
+                            "Only draw this state, again to draw everything. Shift and the number \
+                             key in front.",
+                        )
+                        .clicked()
+                    {
+                        isolate = Some(i);
+                    }
+                    ui.end_row();
+                }
+
+                ui.label("");
+                let (rect, _) =
+                    ui.allocate_exact_size(egui::vec2(16.0, 16.0), egui::Sense::hover());
+                ui.painter()
+                    .rect_stroke(rect, 2.0, ui.visuals().widgets.inactive.fg_stroke);
+                ui.label("Other");
+                ui.label(self.other_count.to_string());
+                let mut visible = !self.other_hidden;
+                if ui
+                    .checkbox(&mut visible, "Visible")
+                    .on_hover_text(format!(
+                        "States past the first {} the legend lists",
+                        MAX_STATES
+                    ))
+                    .changed()
+                {
+                    self.other_hidden = !visible;
+                }
+                ui.end_row();
+            });
+        if let Some(i) = isolate {
+            self.isolate(i);
+        }
+    }
+}
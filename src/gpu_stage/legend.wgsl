@@ -0,0 +1,118 @@
+#include "common.wgsl"
+#include "cell_state.wgsl"
+#include "rng.wgsl"
+
+const SLOTS: u32 = {{CENSUS_SLOTS}}u;
+const WG_VOLUME: u32 = 64u;
+
+struct Slot {
+    // State key, 0 marks an unused slot and empty cells aren't counted
+    key: atomic<u32>,
+    count: atomic<u32>,
+    // Highest cell value in the state, shown as its color
+    value: atomic<u32>,
+}
+
+struct Census {
+    slots: array<Slot, SLOTS>,
+    // Cells whose state found no free slot
+    other: atomic<u32>,
+}
+
+struct PushConstants {
+    @size(4) group: u32,
+    @size(4) origin_x: u32,
+    @size(4) which: u32,
+    // How cell values map to states, as in the meshing state filter
+    @size(4) states: u32,
+    @size(4) materials: u32,
+};
+
+var<push_constant> consts: PushConstants;
+
+@group(0) @binding(0)
+var<storage, read_write> census: Census;
+
+@group(1) @binding(0)
+var atlas: texture_storage_3d<{{CHUNK_FORMAT}}, read>;
+
+@group(1) @binding(1)
+var chunk_groups: binding_array<texture_storage_3d<{{CHUNK_FORMAT}}, read>, 8>;
+
+// Counted within the workgroup first, most workgroups only see a few states
+var<workgroup> wg_keys: array<atomic<u32>, SLOTS>;
+var<workgroup> wg_counts: array<atomic<u32>, SLOTS>;
+var<workgroup> wg_values: array<atomic<u32>, SLOTS>;
+var<workgroup> wg_other: atomic<u32>;
+
+// Open addressing, a slot keeps the first key that claims it
+fn add_local(key: u32, value: u32) {
+    var slot = hash(key) % SLOTS;
+    for(var probe = 0u; probe < SLOTS; probe++) {
+        var claimed = atomicLoad(&wg_keys[slot]);
+        while(claimed == 0u) {
+            // Weak exchanges may fail spuriously, so only stop once the slot is taken
+            let result = atomicCompareExchangeWeak(&wg_keys[slot], 0u, key);
+            claimed = select(result.old_value, key, result.exchanged);
+        }
+        if(claimed == key) {
+            atomicAdd(&wg_counts[slot], 1u);
+            atomicMax(&wg_values[slot], value);
+            return;
+        }
+        slot = (slot + 1u) % SLOTS;
+    }
+    atomicAdd(&wg_other, 1u);
+}
+
+fn add_global(key: u32, count: u32, value: u32) {
+    var slot = hash(key) % SLOTS;
+    for(var probe = 0u; probe < SLOTS; probe++) {
+        var claimed = atomicLoad(&census.slots[slot].key);
+        while(claimed == 0u) {
+            let result = atomicCompareExchangeWeak(&census.slots[slot].key, 0u, key);
+            claimed = select(result.old_value, key, result.exchanged);
+        }
+        if(claimed == key) {
+            atomicAdd(&census.slots[slot].count, count);
+            atomicMax(&census.slots[slot].value, value);
+            return;
+        }
+        slot = (slot + 1u) % SLOTS;
+    }
+    atomicAdd(&census.other, count);
+}
+
+@compute
+@workgroup_size(4, 4, 4)
+fn cs_census(@builtin(global_invocation_id) gid: vec3<u32>, @builtin(local_invocation_index) lid: u32) {
+    for(var i = lid; i < SLOTS; i += WG_VOLUME) {
+        atomicStore(&wg_keys[i], 0u);
+        atomicStore(&wg_counts[i], 0u);
+        atomicStore(&wg_values[i], 0u);
+    }
+    if(lid == 0u) {
+        atomicStore(&wg_other, 0u);
+    }
+    workgroupBarrier();
+
+    let pos = vec3<i32>(gid);
+    let cur = textureLoad(chunk_groups[consts.group], pos + vec3<i32>(vec3<u32>(consts.origin_x, 0u, consts.which)) * CHUNK_SIZE).r;
+    if(cur != 0u) {
+        add_local(state_key(cur, consts.states, consts.materials != 0u), cur);
+    }
+    workgroupBarrier();
+
+    for(var i = lid; i < SLOTS; i += WG_VOLUME) {
+        let key = atomicLoad(&wg_keys[i]);
+        if(key != 0u) {
+            add_global(key, atomicLoad(&wg_counts[i]), atomicLoad(&wg_values[i]));
+        }
+    }
+    if(lid == 0u) {
+        let other = atomicLoad(&wg_other);
+        if(other > 0u) {
+            atomicAdd(&census.other, other);
+        }
+    }
+}
@@ -0,0 +1,375 @@
+use std::mem::size_of;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use wgpu::*;
+
+use crate::chunk::CHUNK_VOLUME;
+use crate::shader_prep::ShaderPrep;
+use crate::wgpu_context::WgpuContext;
+
+// Ages saturate at 16 bits, bin n holds lifetimes from 2^n to 2^(n+1) - 1 ticks
+pub const AGE_BINS: usize = 16;
+// Deaths a single tick can append, the rest are only counted
+pub const DEATH_CAPACITY: u32 = 1 << 20;
+const REDUCE_WG_SIZE: u32 = 256;
+const REDUCE_WORKGROUPS: u32 = 64;
+// Simulation updates between histogram readbacks
+const READBACK_INTERVAL: u32 = 30;
+// Bins plus the dropped counter
+const HISTOGRAM_BYTES: u64 = ((AGE_BINS + 1) * size_of::<u32>()) as u64;
+
+// Two ages share a word
+fn ages_bytes(chunks: u32) -> u64 {
+    (chunks as u64 * CHUNK_VOLUME as u64 * 2).max(size_of::<u32>() as u64)
+}
+
+struct Ages {
+    buffer: Buffer,
+    bind_group: BindGroup,
+    chunks: u32,
+}
+
+/// Histogram of how many ticks cells live. While recording, the simulation shader keeps an age
+/// per cell and appends the age of every cell that dies to a buffer, which is binned after every
+/// tick and read back every few updates. Cells that were already alive when recording started
+/// count their age from then.
+pub struct LifetimeHistogram {
+    pub enabled: bool,
+    bind_group_layout: BindGroupLayout,
+    reduce_pipeline: ComputePipeline,
+    reset_pipeline: ComputePipeline,
+    reduce_bind_group: BindGroup,
+    deaths_buffer: Buffer,
+    histogram_buffer: Buffer,
+    readback_buffer: Buffer,
+    ages: Ages,
+    // Ages and the GPU histogram are cleared before the next recorded tick
+    clear_pending: bool,
+    updates_since_readback: u32,
+    copied: bool,
+    in_flight: bool,
+    // A reset happened while a readback was in flight, its counts are from before
+    discard_readback: bool,
+    mapped: Arc<AtomicBool>,
+    counts: [u64; AGE_BINS],
+    dropped: u64,
+}
+
+impl LifetimeHistogram {
+    pub fn new(ctx: &WgpuContext) -> Self {
+        let source = ShaderPrep::new()
+            .define("AGE_BINS", AGE_BINS)
+            .define("DEATH_CAPACITY", DEATH_CAPACITY)
+            .define("REDUCE_WG_SIZE", REDUCE_WG_SIZE)
+            .process(include_str!("./lifetimes.wgsl"));
+        let shader = ctx.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("lifetimes shader"),
+            source: ShaderSource::Wgsl(source.into()),
+        });
+
+        let storage_entry = |binding| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        // Ages and deaths, bound by the simulation shader
+        let bind_group_layout = ctx
+            .device
+            .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("lifetimes bind_group_layout"),
+                entries: &[storage_entry(0), storage_entry(1)],
+            });
+        // Deaths and the histogram
+        let reduce_bind_group_layout =
+            ctx.device
+                .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                    label: Some("lifetimes reduce_bind_group_layout"),
+                    entries: &[storage_entry(0), storage_entry(1)],
+                });
+
+        let pipeline_layout = ctx
+            .device
+            .create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some("lifetimes pipeline_layout"),
+                bind_group_layouts: &[&reduce_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let create_pipeline = |entry_point: &'static str| {
+            ctx.device
+                .create_compute_pipeline(&ComputePipelineDescriptor {
+                    label: Some("lifetimes pipeline"),
+                    layout: Some(&pipeline_layout),
+                    module: &shader,
+                    entry_point,
+                })
+        };
+
+        let deaths_buffer = ctx.device.create_buffer(&BufferDescriptor {
+            label: Some("lifetimes deaths_buffer"),
+            size: (DEATH_CAPACITY as u64 + 1) * size_of::<u32>() as u64,
+            usage: BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let histogram_buffer = ctx.device.create_buffer(&BufferDescriptor {
+            label: Some("lifetimes histogram_buffer"),
+            size: HISTOGRAM_BYTES,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback_buffer = ctx.device.create_buffer(&BufferDescriptor {
+            label: Some("lifetimes readback_buffer"),
+            size: HISTOGRAM_BYTES,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let reduce_bind_group = ctx.device.create_bind_group(&BindGroupDescriptor {
+            label: Some("lifetimes reduce_bind_group"),
+            layout: &reduce_bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: deaths_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: histogram_buffer.as_entire_binding(),
+                },
+            ],
+        });
+        let ages = Self::create_ages(ctx, &bind_group_layout, &deaths_buffer, 0);
+
+        Self {
+            enabled: false,
+            bind_group_layout,
+            reduce_pipeline: create_pipeline("cs_reduce"),
+            reset_pipeline: create_pipeline("cs_reset"),
+            reduce_bind_group,
+            deaths_buffer,
+            histogram_buffer,
+            readback_buffer,
+            ages,
+            clear_pending: false,
+            updates_since_readback: 0,
+            copied: false,
+            in_flight: false,
+            discard_readback: false,
+            mapped: Arc::new(AtomicBool::new(false)),
+            counts: [0; AGE_BINS],
+            dropped: 0,
+        }
+    }
+
+    fn create_ages(
+        ctx: &WgpuContext,
+        bind_group_layout: &BindGroupLayout,
+        deaths_buffer: &Buffer,
+        chunks: u32,
+    ) -> Ages {
+        let buffer = ctx.device.create_buffer(&BufferDescriptor {
+            label: Some("lifetimes ages_buffer"),
+            size: ages_bytes(chunks),
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = ctx.device.create_bind_group(&BindGroupDescriptor {
+            label: Some("lifetimes bind_group"),
+            layout: bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: deaths_buffer.as_entire_binding(),
+                },
+            ],
+        });
+        Ages {
+            buffer,
+            bind_group,
+            chunks,
+        }
+    }
+
+    /// Layout of the bind group the simulation shader records into
+    pub fn bind_group_layout(&self) -> &BindGroupLayout {
+        &self.bind_group_layout
+    }
+
+    pub fn bind_group(&self) -> &BindGroup {
+        &self.ages.bind_group
+    }
+
+    pub fn update(&mut self) {
+        if self.mapped.load(Ordering::Acquire) {
+            self.process_readback();
+        }
+    }
+
+    /// Makes room for the ages of `chunks` chunks, returns whether the next ticks should record
+    pub fn prepare(
+        &mut self,
+        ctx: &WgpuContext,
+        encoder: &mut CommandEncoder,
+        chunks: u32,
+    ) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        if self.ages.chunks < chunks {
+            let chunks = chunks.next_power_of_two();
+            if ages_bytes(chunks) > ctx.device.limits().max_storage_buffer_binding_size as u64 {
+                log::warn!(
+                    "The ages of {} chunks don't fit into a buffer, not recording lifetimes",
+                    chunks
+                );
+                self.enabled = false;
+                return false;
+            }
+            self.ages =
+                Self::create_ages(ctx, &self.bind_group_layout, &self.deaths_buffer, chunks);
+        }
+        if self.clear_pending {
+            encoder.clear_buffer(&self.ages.buffer, 0, None);
+            encoder.clear_buffer(&self.histogram_buffer, 0, None);
+            self.clear_pending = false;
+        }
+        true
+    }
+
+    /// Bins the deaths of the tick that was just dispatched and empties the append buffer. The
+    /// caller has to set its own pipeline and bind groups again afterwards.
+    pub fn encode_reduce<'a>(&'a self, compute_pass: &mut ComputePass<'a>) {
+        compute_pass.set_bind_group(0, &self.reduce_bind_group, &[]);
+        compute_pass.set_pipeline(&self.reduce_pipeline);
+        compute_pass.dispatch_workgroups(REDUCE_WORKGROUPS, 1, 1);
+        compute_pass.set_pipeline(&self.reset_pipeline);
+        compute_pass.dispatch_workgroups(1, 1, 1);
+    }
+
+    /// Moves the GPU histogram into the readback buffer every few updates
+    pub fn encode_readback(&mut self, encoder: &mut CommandEncoder) {
+        self.updates_since_readback += 1;
+        if self.updates_since_readback < READBACK_INTERVAL || self.in_flight {
+            return;
+        }
+        self.updates_since_readback = 0;
+        encoder.copy_buffer_to_buffer(
+            &self.histogram_buffer,
+            0,
+            &self.readback_buffer,
+            0,
+            HISTOGRAM_BYTES,
+        );
+        encoder.clear_buffer(&self.histogram_buffer, 0, None);
+        self.copied = true;
+        self.in_flight = true;
+    }
+
+    fn process_readback(&mut self) {
+        {
+            let range = self.readback_buffer.slice(..).get_mapped_range();
+            let counts: &[u32] = bytemuck::cast_slice(&range);
+            if !self.discard_readback {
+                for (total, count) in self.counts.iter_mut().zip(counts) {
+                    *total += *count as u64;
+                }
+                self.dropped += counts[AGE_BINS] as u64;
+            }
+        }
+        self.readback_buffer.unmap();
+        self.mapped.store(false, Ordering::Release);
+        self.in_flight = false;
+        self.discard_readback = false;
+    }
+
+    pub fn after_submit(&mut self) {
+        if !self.copied {
+            return;
+        }
+        self.copied = false;
+        let mapped = self.mapped.clone();
+        self.readback_buffer
+            .slice(..)
+            .map_async(MapMode::Read, move |result| match result {
+                Ok(_) => mapped.store(true, Ordering::Release),
+                Err(e) => log::error!("Failed to map lifetimes buffer: {:?}", e),
+            });
+    }
+
+    fn reset(&mut self) {
+        self.counts = [0; AGE_BINS];
+        self.dropped = 0;
+        self.clear_pending = true;
+        self.discard_readback = self.in_flight;
+    }
+
+    fn plot_ui(&self, ui: &mut egui::Ui) {
+        let (rect, response) = ui.allocate_exact_size(
+            egui::vec2(ui.available_width().max(120.0), 80.0),
+            egui::Sense::hover(),
+        );
+        let painter = ui.painter_at(rect);
+        painter.rect_filled(rect, 2.0, ui.visuals().extreme_bg_color);
+        let max_count = self.counts.iter().copied().max().unwrap_or(0).max(1);
+        let bar_width = rect.width() / AGE_BINS as f32;
+        for (bin, count) in self.counts.iter().enumerate() {
+            let height = (rect.height() - 4.0) * *count as f32 / max_count as f32;
+            let left = rect.left() + bar_width * bin as f32;
+            painter.rect_filled(
+                egui::Rect::from_min_max(
+                    egui::pos2(left + 1.0, rect.bottom() - 2.0 - height),
+                    egui::pos2(left + bar_width - 1.0, rect.bottom() - 2.0),
+                ),
+                0.0,
+                ui.visuals().widgets.active.fg_stroke.color,
+            );
+        }
+        if let Some(pos) = response.hover_pos() {
+            let bin = (((pos.x - rect.left()) / bar_width) as usize).min(AGE_BINS - 1);
+            response.on_hover_text(format!(
+                "{} to {} ticks: {} cells",
+                1u32 << bin,
+                (1u32 << (bin + 1)) - 1,
+                self.counts[bin]
+            ));
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.collapsing("Cell lifetimes", |ui| {
+            let response = ui
+                .checkbox(&mut self.enabled, "Record lifetimes")
+                .on_hover_text(
+                "Keeps an age per cell and bins the age of every cell that dies. Not recorded by \
+                 the packed kernel.",
+            );
+            // Ages left over from an earlier recording are stale
+            if response.changed() && self.enabled {
+                self.clear_pending = true;
+            }
+            self.plot_ui(ui);
+            let deaths = self.counts.iter().sum::<u64>();
+            ui.label(format!("{} deaths, bins double in width", deaths));
+            if self.dropped > 0 {
+                ui.colored_label(
+                    ui.visuals().warn_fg_color,
+                    format!(
+                        "{} deaths didn't fit into the buffer of {} per tick",
+                        self.dropped, DEATH_CAPACITY
+                    ),
+                );
+            }
+            if ui.button("Reset").clicked() {
+                self.reset();
+            }
+        });
+    }
+}
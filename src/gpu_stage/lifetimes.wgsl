@@ -0,0 +1,64 @@
+const AGE_BINS: u32 = {{AGE_BINS}}u;
+const DEATH_CAPACITY: u32 = {{DEATH_CAPACITY}}u;
+const REDUCE_WG_SIZE: u32 = {{REDUCE_WG_SIZE}}u;
+
+// Appended to by the simulation shader, `count` keeps going past the capacity
+struct Deaths {
+    count: u32,
+    ages: array<u32>,
+}
+
+// One entry per bin, then the deaths that didn't fit into the append buffer
+struct Histogram {
+    bins: array<atomic<u32>, {{AGE_BINS}}>,
+    dropped: atomic<u32>,
+}
+
+@group(0) @binding(0)
+var<storage, read_write> deaths: Deaths;
+
+@group(0) @binding(1)
+var<storage, read_write> histogram: Histogram;
+
+var<workgroup> local_bins: array<atomic<u32>, {{AGE_BINS}}>;
+
+// Bin n holds ages from 2^n to 2^(n+1) - 1
+fn age_bin(age: u32) -> u32 {
+    return min(firstLeadingBit(max(age, 1u)), AGE_BINS - 1u);
+}
+
+@compute
+@workgroup_size({{REDUCE_WG_SIZE}})
+fn cs_reduce(
+    @builtin(global_invocation_id) gid: vec3<u32>,
+    @builtin(local_invocation_index) lidx: u32,
+    @builtin(num_workgroups) num_wg: vec3<u32>,
+    ) {
+    if(lidx < AGE_BINS) {
+        atomicStore(&local_bins[lidx], 0u);
+    }
+    workgroupBarrier();
+
+    let count = min(deaths.count, DEATH_CAPACITY);
+    for(var i = gid.x; i < count; i += num_wg.x * REDUCE_WG_SIZE) {
+        atomicAdd(&local_bins[age_bin(deaths.ages[i])], 1u);
+    }
+    workgroupBarrier();
+
+    if(lidx < AGE_BINS) {
+        let n = atomicLoad(&local_bins[lidx]);
+        if(n != 0u) {
+            atomicAdd(&histogram.bins[lidx], n);
+        }
+    }
+}
+
+// Runs after cs_reduce so the next tick appends from the start again
+@compute
+@workgroup_size(1)
+fn cs_reset() {
+    if(deaths.count > DEATH_CAPACITY) {
+        atomicAdd(&histogram.dropped, deaths.count - DEATH_CAPACITY);
+    }
+    deaths.count = 0u;
+}
@@ -0,0 +1,334 @@
+use std::mem::size_of;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use bytemuck::{Pod, Zeroable};
+use nalgebra_glm as glm;
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::*;
+
+use crate::chunk::CHUNK_SIZE;
+use crate::chunk_manager::ChunkManager;
+use crate::shader_prep::ShaderPrep;
+use crate::wgpu_context::WgpuContext;
+
+const WORKGROUP_SIZE: u32 = 4;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable, Default)]
+struct LiveBoundsPushConstants {
+    group: u32,
+    origin_x: u32,
+    which: u32,
+    _pad0: u32,
+    chunk_pos: glm::IVec3,
+    _pad1: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct BoundsPod {
+    min: [i32; 3],
+    max: [i32; 3],
+    live_cells: u32,
+}
+
+/// Box around every non-empty voxel, in world voxel coordinates with `max` inclusive
+#[derive(Copy, Clone, Debug)]
+pub struct LiveBounds {
+    pub min: glm::IVec3,
+    pub max: glm::IVec3,
+    pub live_cells: u64,
+    // Simulation tick the bounds were computed at
+    pub tick: u64,
+}
+
+impl LiveBounds {
+    pub fn size(&self) -> glm::IVec3 {
+        self.max - self.min + glm::vec3(1, 1, 1)
+    }
+}
+
+#[derive(Default)]
+pub struct LiveBoundsStats {
+    // Change per simulation tick between the last two results
+    pub cell_growth: f64,
+    pub volume_growth: f64,
+}
+
+// What the last reduction saw, a new one only runs when this changes
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct WorldKey {
+    chunks: usize,
+    version_sum: u64,
+}
+
+impl WorldKey {
+    pub fn of(chunk_manager: &ChunkManager) -> Self {
+        Self {
+            chunks: chunk_manager.chunks().len(),
+            version_sum: chunk_manager.chunks().values().map(|c| c.version).sum(),
+        }
+    }
+}
+
+struct Resources {
+    pipeline: ComputePipeline,
+    bounds_buffer: Buffer,
+    bounds_buffer_init: Buffer,
+    readback_buffer: Buffer,
+    bind_group: BindGroup,
+}
+
+/// GPU reduction of the live cell bounding box, rerun every `interval` simulation ticks
+pub struct LiveBoundsReduction {
+    res: Resources,
+    pub interval: u32,
+    // Tick and world of the last reduction
+    last_run: Option<(u64, WorldKey)>,
+    pending_tick: u64,
+    copied: bool,
+    map_requested: bool,
+    mapped: Arc<AtomicBool>,
+    previous: Option<LiveBounds>,
+    // Tick and live cells of the last result, kept for empty worlds too
+    population: Option<(u64, u64)>,
+    stats: LiveBoundsStats,
+}
+
+impl Resources {
+    fn new(ctx: &WgpuContext, chunk_manager: &ChunkManager) -> Self {
+        let source = ShaderPrep::new().process(include_str!("./live_bounds.wgsl"));
+        let shader = ctx.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("live_bounds shader"),
+            source: ShaderSource::Wgsl(source.into()),
+        });
+
+        let bind_group_layout = ctx
+            .device
+            .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("live_bounds bind_group_layout"),
+                entries: &[BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+            });
+
+        let pipeline_layout = ctx
+            .device
+            .create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some("live_bounds pipeline_layout"),
+                bind_group_layouts: &[&bind_group_layout, chunk_manager.bind_group_layout(false)],
+                push_constant_ranges: &[PushConstantRange {
+                    stages: ShaderStages::COMPUTE,
+                    range: 0..size_of::<LiveBoundsPushConstants>() as u32,
+                }],
+            });
+
+        let pipeline = ctx
+            .device
+            .create_compute_pipeline(&ComputePipelineDescriptor {
+                label: Some("live_bounds pipeline"),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point: "cs_bounds",
+            });
+
+        let bounds_buffer = ctx.device.create_buffer(&BufferDescriptor {
+            label: Some("live_bounds bounds_buffer"),
+            size: size_of::<BoundsPod>() as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let bounds_buffer_init = ctx.device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("live_bounds bounds_buffer_init"),
+            contents: bytemuck::bytes_of(&BoundsPod {
+                min: [i32::MAX; 3],
+                max: [i32::MIN; 3],
+                live_cells: 0,
+            }),
+            usage: BufferUsages::COPY_SRC,
+        });
+        let readback_buffer = ctx.device.create_buffer(&BufferDescriptor {
+            label: Some("live_bounds readback_buffer"),
+            size: size_of::<BoundsPod>() as u64,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = ctx.device.create_bind_group(&BindGroupDescriptor {
+            label: Some("live_bounds bind_group"),
+            layout: &bind_group_layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: bounds_buffer.as_entire_binding(),
+            }],
+        });
+
+        Self {
+            pipeline,
+            bounds_buffer,
+            bounds_buffer_init,
+            readback_buffer,
+            bind_group,
+        }
+    }
+}
+
+impl LiveBoundsReduction {
+    pub fn new(ctx: &WgpuContext, chunk_manager: &ChunkManager) -> Self {
+        Self {
+            res: Resources::new(ctx, chunk_manager),
+            interval: 16,
+            last_run: None,
+            pending_tick: 0,
+            copied: false,
+            map_requested: false,
+            mapped: Arc::new(AtomicBool::new(false)),
+            previous: None,
+            population: None,
+            stats: LiveBoundsStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> &LiveBoundsStats {
+        &self.stats
+    }
+
+    /// Tick of the last result and the live cells counted at it, also when there were none
+    pub fn population(&self) -> Option<(u64, u64)> {
+        self.population
+    }
+
+    fn due(&self, tick: u64, key: WorldKey) -> bool {
+        let Some((last_tick, last_key)) = self.last_run else {
+            return true;
+        };
+        // Edits from the CPU show up right away, simulation progress every `interval` ticks
+        key != last_key || tick < last_tick || tick >= last_tick + self.interval.max(1) as u64
+    }
+
+    /// `tick` is the simulation tick after the ticks of this frame
+    pub fn update(
+        &mut self,
+        command_encoder: &mut CommandEncoder,
+        chunk_manager: &mut ChunkManager,
+        tick: u64,
+    ) {
+        if self.mapped.load(Ordering::Acquire) {
+            self.process_readback(chunk_manager);
+        }
+
+        let key = WorldKey::of(chunk_manager);
+        // Also wait while the previous readback is still in flight
+        if self.map_requested || !self.due(tick, key) {
+            return;
+        }
+        self.last_run = Some((tick, key));
+        self.pending_tick = tick;
+
+        command_encoder.copy_buffer_to_buffer(
+            &self.res.bounds_buffer_init,
+            0,
+            &self.res.bounds_buffer,
+            0,
+            size_of::<BoundsPod>() as u64,
+        );
+        {
+            let mut compute_pass = command_encoder.begin_compute_pass(&ComputePassDescriptor {
+                label: Some("live_bounds compute_pass"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_pipeline(&self.res.pipeline);
+            compute_pass.set_bind_group(0, &self.res.bind_group, &[]);
+            compute_pass.set_bind_group(1, chunk_manager.bind_group(false), &[]);
+            for chunk in chunk_manager.chunks().values() {
+                let (group, origin_x) = chunk_manager.offset_to_group_and_origin_x(chunk.offset());
+                compute_pass.set_push_constants(
+                    0,
+                    bytemuck::cast_slice(&[LiveBoundsPushConstants {
+                        group,
+                        origin_x,
+                        which: chunk_manager.which(),
+                        chunk_pos: chunk.pos,
+                        ..Default::default()
+                    }]),
+                );
+                let workgroups = CHUNK_SIZE / WORKGROUP_SIZE;
+                compute_pass.dispatch_workgroups(workgroups, workgroups, workgroups);
+            }
+        }
+        command_encoder.copy_buffer_to_buffer(
+            &self.res.bounds_buffer,
+            0,
+            &self.res.readback_buffer,
+            0,
+            size_of::<BoundsPod>() as u64,
+        );
+        self.copied = true;
+    }
+
+    fn process_readback(&mut self, chunk_manager: &mut ChunkManager) {
+        let pod = *bytemuck::from_bytes::<BoundsPod>(
+            &self.res.readback_buffer.slice(..).get_mapped_range(),
+        );
+        self.res.readback_buffer.unmap();
+        self.mapped.store(false, Ordering::Release);
+        self.map_requested = false;
+
+        let bounds = (pod.live_cells > 0).then(|| LiveBounds {
+            min: glm::make_vec3(&pod.min),
+            max: glm::make_vec3(&pod.max),
+            live_cells: pod.live_cells as u64,
+            tick: self.pending_tick,
+        });
+
+        if let (Some(previous), Some(current)) = (self.previous, bounds) {
+            let ticks = current.tick.saturating_sub(previous.tick);
+            if ticks > 0 {
+                let volume = |b: &LiveBounds| {
+                    let size = b.size().cast::<f64>();
+                    size.x * size.y * size.z
+                };
+                self.stats.cell_growth =
+                    (current.live_cells as f64 - previous.live_cells as f64) / ticks as f64;
+                self.stats.volume_growth = (volume(&current) - volume(&previous)) / ticks as f64;
+            }
+        }
+        self.previous = bounds;
+        self.population = Some((self.pending_tick, pod.live_cells as u64));
+        chunk_manager.set_live_bounds(bounds);
+    }
+
+    pub fn after_submit(&mut self) {
+        if !self.copied {
+            return;
+        }
+        self.copied = false;
+        self.map_requested = true;
+        let mapped = self.mapped.clone();
+        self.res
+            .readback_buffer
+            .slice(..)
+            .map_async(MapMode::Read, move |result| match result {
+                Ok(_) => mapped.store(true, Ordering::Release),
+                Err(e) => log::error!("Failed to map live bounds buffer: {:?}", e),
+            });
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.collapsing("Live bounds", |ui| {
+            ui.add(
+                egui::Slider::new(&mut self.interval, 1..=256)
+                    .logarithmic(true)
+                    .text("Update every n ticks"),
+            );
+        });
+    }
+}
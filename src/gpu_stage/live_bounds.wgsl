@@ -0,0 +1,66 @@
+#include "common.wgsl"
+
+struct Bounds {
+    min: array<atomic<i32>, 3>,
+    max: array<atomic<i32>, 3>,
+    live_cells: atomic<u32>,
+};
+
+struct PushConstants {
+    @size(4) group: u32,
+    @size(4) origin_x: u32,
+    @size(4) which: u32,
+    chunk_pos: vec3<i32>,
+};
+
+var<push_constant> consts: PushConstants;
+
+@group(0) @binding(0)
+var<storage, read_write> bounds: Bounds;
+
+@group(1) @binding(0)
+var atlas: texture_storage_3d<{{CHUNK_FORMAT}}, read>;
+
+@group(1) @binding(1)
+var chunk_groups: binding_array<texture_storage_3d<{{CHUNK_FORMAT}}, read>, 8>;
+
+// Reduced within the workgroup first so only one invocation per workgroup touches the global bounds
+var<workgroup> wg_min: array<atomic<i32>, 3>;
+var<workgroup> wg_max: array<atomic<i32>, 3>;
+var<workgroup> wg_count: atomic<u32>;
+
+@compute
+@workgroup_size(4, 4, 4)
+fn cs_bounds(@builtin(global_invocation_id) gid: vec3<u32>, @builtin(local_invocation_index) lid: u32) {
+    if(lid == 0u) {
+        for(var i = 0; i < 3; i++) {
+            atomicStore(&wg_min[i], 2147483647);
+            atomicStore(&wg_max[i], -2147483647 - 1);
+        }
+        atomicStore(&wg_count, 0u);
+    }
+    workgroupBarrier();
+
+    let pos = vec3<i32>(gid);
+    let cur = textureLoad(chunk_groups[consts.group], pos + vec3<i32>(vec3<u32>(consts.origin_x, 0u, consts.which)) * CHUNK_SIZE).r;
+    if(cur != 0u) {
+        let world_pos = consts.chunk_pos * CHUNK_SIZE + pos;
+        for(var i = 0; i < 3; i++) {
+            atomicMin(&wg_min[i], world_pos[i]);
+            atomicMax(&wg_max[i], world_pos[i]);
+        }
+        atomicAdd(&wg_count, 1u);
+    }
+    workgroupBarrier();
+
+    if(lid == 0u) {
+        let count = atomicLoad(&wg_count);
+        if(count > 0u) {
+            for(var i = 0; i < 3; i++) {
+                atomicMin(&bounds.min[i], atomicLoad(&wg_min[i]));
+                atomicMax(&bounds.max[i], atomicLoad(&wg_max[i]));
+            }
+            atomicAdd(&bounds.live_cells, count);
+        }
+    }
+}
@@ -1,3 +1,6 @@
+#include "common.wgsl"
+#include "cell_state.wgsl"
+
 struct DrawIndirect {
     @size(4) vertex_count: u32,
     @size(4) instance_count: atomic<u32>,
@@ -8,6 +11,9 @@ struct DrawIndirect {
 struct FaceInstance {
     @size(4) color: u32,
     @size(4) info: u32,
+    @size(4) born: u32,
+    // Key of the cell state, picks the atlas material the face is drawn with
+    @size(4) state: u32,
 }
 
 struct PushConstants {
@@ -15,8 +21,26 @@ struct PushConstants {
     @size(4) group: u32,
     @size(4) origin_x: u32,
     @size(4) which: u32,
+    // Frame the chunk was first meshed at
+    @size(4) born: u32,
 };
 
+// Cell states meshing treats as empty, chosen in the legend
+struct StateFilter {
+    // Bit n hides the state keyed keys[n], the last bit every state that isn't listed
+    @size(4) hidden: u32,
+    @size(4) count: u32,
+    // Nonzero when cells hold reaction-diffusion concentrations instead of colors
+    iso_level: f32,
+    // Generations state count, above 2 the alpha byte of a cell that isn't opaque is its decay step
+    states: u32,
+    // Nonzero when cells are powder materials
+    @size(16) materials: u32,
+    keys: array<vec4<u32>, 8>,
+}
+
+const OTHER_STATE: u32 = 31u;
+
 var<push_constant> consts: PushConstants;
 
 @group(0) @binding(0)
@@ -26,22 +50,73 @@ var<storage, read_write> indirect: DrawIndirect;
 var<storage, read_write> faces: array<FaceInstance>;
 
 @group(1) @binding(0)
-var atlas: texture_storage_3d<r32uint, read>;
+var atlas: texture_storage_3d<{{CHUNK_FORMAT}}, read>;
 
 @group(1) @binding(1)
-var chunk_groups: binding_array<texture_storage_3d<r32uint, read>, 8>;
+var chunk_groups: binding_array<texture_storage_3d<{{CHUNK_FORMAT}}, read>, 8>;
 
-fn load(pos: vec3<i32>) -> u32 {
-    if(any(pos >= vec3<i32>(64, 64, 64))) {
+@group(2) @binding(0)
+var<uniform> state_filter: StateFilter;
+
+fn visible(value: u32) -> bool {
+    if(state_filter.hidden == 0u) {
+        return true;
+    }
+    let key = state_key(value, state_filter.states, state_filter.materials != 0u);
+    var state = OTHER_STATE;
+    for(var i = 0u; i < state_filter.count; i++) {
+        if(state_filter.keys[i / 4u][i % 4u] == key) {
+            state = i;
+            break;
+        }
+    }
+    return (state_filter.hidden & (1u << state)) == 0u;
+}
+
+// Reaction-diffusion cells are drawn where v reaches the iso level, colored by how far past it
+// they are
+fn concentration_color(value: u32) -> u32 {
+    let v = unpack2x16unorm(value).y;
+    if(v < state_filter.iso_level) {
+        return 0u;
+    }
+    let t = (v - state_filter.iso_level) / max(1.0 - state_filter.iso_level, 1e-3);
+    return pack4x8unorm(vec4<f32>(mix(vec3<f32>(0.1, 0.35, 0.9), vec3<f32>(1.0, 0.85, 0.3), t), 1.0));
+}
+
+// Decaying cells darken the further along they are, drawn opaque so they don't need translucency
+fn decay_color(value: u32) -> u32 {
+    let step = f32(value >> 24u);
+    let left = 1.0 - step / f32(state_filter.states - 1u);
+    let rgb = unpack4x8unorm(value).rgb * mix(0.1, 0.6, left);
+    return pack4x8unorm(vec4<f32>(rgb, 1.0));
+}
+
+fn load_value(pos: vec3<i32>) -> u32 {
+    if(any(pos >= vec3<i32>(CHUNK_SIZE))) {
         return 0u;
     }
     if(any(pos < vec3<i32>(0, 0, 0))) {
         return 0u;
     }
-    return textureLoad(chunk_groups[consts.group], pos + vec3<i32>(vec3<u32>(consts.origin_x, 0u, consts.which)) * 64).r;
+    return textureLoad(chunk_groups[consts.group], pos + vec3<i32>(vec3<u32>(consts.origin_x, 0u, consts.which)) * CHUNK_SIZE).r;
+}
+
+fn load(pos: vec3<i32>) -> u32 {
+    let value = load_value(pos);
+    if(value == 0u || !visible(value)) {
+        return 0u;
+    }
+    if(state_filter.iso_level > 0.0) {
+        return concentration_color(value);
+    }
+    if(state_filter.states > 2u && (value >> 24u) != 0xFFu) {
+        return decay_color(value);
+    }
+    return value;
 }
 
-fn append_face(color: u32, side: u32, pos: vec3<i32>) {
+fn append_face(color: u32, state: u32, side: u32, pos: vec3<i32>) {
     let index = atomicAdd(&indirect.instance_count, 1u);
     if(index > consts.max_faces) {
         atomicStore(&indirect.instance_count, consts.max_faces);
@@ -50,33 +125,36 @@ fn append_face(color: u32, side: u32, pos: vec3<i32>) {
         return;
     }
     faces[index].color = color;
-    faces[index].info = u32((pos.x << 0u) | (pos.y << 6u) | (pos.z << 12u)) | (side << 18u);
+    faces[index].info = u32((pos.x << 0u) | (pos.y << CHUNK_SHIFT) | (pos.z << (CHUNK_SHIFT * 2u))) | (side << FACE_SIDE_SHIFT);
+    faces[index].born = consts.born;
+    faces[index].state = state;
 }
 
 @compute
-@workgroup_size(4, 4, 4)
+@workgroup_size({{WG_X}}, {{WG_Y}}, {{WG_Z}})
 fn cs_generate(@builtin(global_invocation_id) gid: vec3<u32>) {
     let pos = vec3<i32>(gid);
     let cur = load(pos);
     if(cur == 0u) {
         return;
     }
+    let state = state_key(load_value(pos), state_filter.states, state_filter.materials != 0u);
     if(load(pos + vec3<i32>(-1, 0, 0)) == 0u) {
-        append_face(cur, 0u, pos);
+        append_face(cur, state, 0u, pos);
     }
     if(load(pos + vec3<i32>(1, 0, 0)) == 0u) {
-        append_face(cur, 1u, pos);
+        append_face(cur, state, 1u, pos);
     }
     if(load(pos + vec3<i32>(0, -1, 0)) == 0u) {
-        append_face(cur, 2u, pos);
+        append_face(cur, state, 2u, pos);
     }
     if(load(pos + vec3<i32>(0, 1, 0)) == 0u) {
-        append_face(cur, 3u, pos);
+        append_face(cur, state, 3u, pos);
     }
     if(load(pos + vec3<i32>(0, 0, -1)) == 0u) {
-        append_face(cur, 4u, pos);
+        append_face(cur, state, 4u, pos);
     }
     if(load(pos + vec3<i32>(0, 0, 1)) == 0u) {
-        append_face(cur, 5u, pos);
+        append_face(cur, state, 5u, pos);
     }
 }
\ No newline at end of file
@@ -1,16 +1,33 @@
 use std::collections::HashMap;
 use std::mem::size_of;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 use bytemuck::{offset_of, Pod, Zeroable};
 use nalgebra_glm as glm;
 use wgpu::util::{BufferInitDescriptor, DeviceExt};
 use wgpu::*;
 
+use crate::chunk::{Chunk, CHUNK_SIZE, CHUNK_VOLUME};
 use crate::chunk_manager::ChunkManager;
+use crate::gpu_stage::chunk_palettes::ChunkPalettes;
+use crate::gpu_stage::face_atlas::FaceAtlas;
+use crate::gpu_stage::face_sort::FaceSort;
+use crate::gpu_stage::legend::StateFilter;
+use crate::materials::Material;
+use crate::shader_prep::ShaderPrep;
 use crate::util::*;
 use crate::wgpu_context::WgpuContext;
 
+pub const WORKGROUP_SIZE_CANDIDATES: [[u32; 3]; 5] =
+    [[4, 4, 4], [8, 4, 4], [8, 8, 4], [8, 8, 8], [16, 8, 4]];
+
+// Instance buffers start small and are resized from the face counts read back from the GPU
+const MIN_FACES: u32 = 4096;
+const INITIAL_FACES: u32 = 16384;
+const MAX_FACES: u32 = CHUNK_VOLUME as u32;
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, Pod, Zeroable, Default)]
 struct MeshingPushConstants {
@@ -18,32 +35,52 @@ struct MeshingPushConstants {
     group: u32,
     origin_x: u32,
     which: u32,
+    born: u32,
 }
 
+pub const MESHING_PUSH_CONSTANTS_SIZE: u32 = size_of::<MeshingPushConstants>() as u32;
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, Pod, Zeroable, Default)]
 struct FaceInstance {
     color: u32,
     info: u32,
+    // Meshing frame the chunk of the face was first meshed at, for fading it in
+    born: u32,
+    // Key of the cell state, see cell_state.wgsl
+    state: u32,
+}
+
+// Everything the mesh of a chunk depends on, the mesh is kept while this stays the same. Both
+// buffers of a chunk hold the same cells while it doesn't change, so ticks alone don't count.
+#[derive(Copy, Clone, PartialEq, Eq)]
+struct MeshKey {
+    last_modified: Option<u64>,
+    version: u64,
+    offset: u32,
 }
 
 pub struct PerChunkResource {
     indirect_buffer: Buffer,
     instance_buffer: Buffer,
     bind_group: BindGroup,
+    meshed: Option<MeshKey>,
 }
 
 impl PerChunkResource {
-    fn new(ctx: &WgpuContext, bind_group_layout: &BindGroupLayout) -> Self {
+    fn new(ctx: &WgpuContext, bind_group_layout: &BindGroupLayout, max_faces: u32) -> Self {
         let indirect_buffer = ctx.device.create_buffer(&BufferDescriptor {
             label: Some("meshing per_chunk indirect_buffer"),
             size: size_of::<DrawIndirectPod>() as u64,
-            usage: BufferUsages::INDIRECT | BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            usage: BufferUsages::INDIRECT
+                | BufferUsages::STORAGE
+                | BufferUsages::COPY_DST
+                | BufferUsages::COPY_SRC,
             mapped_at_creation: false,
         });
         let instance_buffer = ctx.device.create_buffer(&BufferDescriptor {
             label: Some("meshing per_chunk instance_buffer"),
-            size: 64 * 64 * 64 * size_of::<FaceInstance>() as u64,
+            size: max_faces as u64 * size_of::<FaceInstance>() as u64,
             usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::VERTEX,
             mapped_at_creation: false,
         });
@@ -65,6 +102,58 @@ impl PerChunkResource {
             indirect_buffer,
             instance_buffer,
             bind_group,
+            meshed: None,
+        }
+    }
+
+    pub fn max_faces(&self) -> u32 {
+        (self.instance_buffer.size() / size_of::<FaceInstance>() as u64) as u32
+    }
+
+    pub fn bind_group(&self) -> &BindGroup {
+        &self.bind_group
+    }
+}
+
+/// Face counts from the indirect buffers, a frame or two behind the meshing dispatch
+#[derive(Default)]
+pub struct MeshStats {
+    pub faces: u64,
+    pub chunks: u32,
+    // Chunks whose faces didn't fit into their instance buffer
+    pub truncated_chunks: u32,
+    pub allocated_faces: u64,
+    pub per_chunk: HashMap<glm::IVec3, u32>,
+}
+
+struct CountReadback {
+    buffer: Buffer,
+    capacity: u32,
+    // Chunk and instance buffer size for every count in the buffer, in copy order
+    entries: Vec<(glm::IVec3, u32)>,
+    copied: bool,
+    map_requested: bool,
+    mapped: Arc<AtomicBool>,
+    // Set when mapping failed, the counts are then copied again
+    map_failed: Arc<AtomicBool>,
+}
+
+impl CountReadback {
+    fn new(ctx: &WgpuContext, capacity: u32) -> Self {
+        let buffer = ctx.device.create_buffer(&BufferDescriptor {
+            label: Some("meshing count_readback buffer"),
+            size: capacity as u64 * size_of::<u32>() as u64,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        Self {
+            buffer,
+            capacity,
+            entries: Vec::new(),
+            copied: false,
+            map_requested: false,
+            mapped: Arc::new(AtomicBool::new(false)),
+            map_failed: Arc::new(AtomicBool::new(false)),
         }
     }
 }
@@ -73,18 +162,39 @@ struct MeshingResources {
     bind_group_layout: BindGroupLayout,
     pipeline: ComputePipeline,
     indirect_buffer_init: Buffer,
+    filter_buffer: Buffer,
+    filter_bind_group: BindGroup,
     per_chunk_resources: HashMap<glm::IVec3, PerChunkResource>,
+    workgroup_size: [u32; 3],
 }
 
 pub struct Meshing {
     res: MeshingResources,
+    readback: CountReadback,
+    stats: MeshStats,
+    state_filter: StateFilter,
+    // Counts updates, the clock chunks fade in by
+    frame: u32,
+    // Frame every chunk was first meshed at, kept when its resources are recreated so growing an
+    // instance buffer doesn't fade the chunk in again
+    born: HashMap<glm::IVec3, u32>,
 }
 
 impl MeshingResources {
-    fn new(ctx: &WgpuContext, chunk_manager: &ChunkManager) -> Self {
+    fn new(
+        ctx: &WgpuContext,
+        chunk_manager: &ChunkManager,
+        workgroup_size: [u32; 3],
+        state_filter: &StateFilter,
+    ) -> Self {
+        let source = Material::define_all(ShaderPrep::new())
+            .define("WG_X", workgroup_size[0])
+            .define("WG_Y", workgroup_size[1])
+            .define("WG_Z", workgroup_size[2])
+            .process(include_str!("./meshing.wgsl"));
         let shader = ctx.device.create_shader_module(ShaderModuleDescriptor {
             label: Some("meshing shader"),
-            source: ShaderSource::Wgsl(include_str!("./meshing.wgsl").into()),
+            source: ShaderSource::Wgsl(source.into()),
         });
 
         let bind_group_layout = ctx
@@ -115,11 +225,31 @@ impl MeshingResources {
                 ],
             });
 
+        let filter_bind_group_layout =
+            ctx.device
+                .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                    label: Some("meshing filter_bind_group_layout"),
+                    entries: &[BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    }],
+                });
+
         let pipeline_layout = ctx
             .device
             .create_pipeline_layout(&PipelineLayoutDescriptor {
                 label: Some("meshing pipeline_layout"),
-                bind_group_layouts: &[&bind_group_layout, chunk_manager.bind_group_layout(false)],
+                bind_group_layouts: &[
+                    &bind_group_layout,
+                    chunk_manager.bind_group_layout(false),
+                    &filter_bind_group_layout,
+                ],
                 push_constant_ranges: &[PushConstantRange {
                     stages: ShaderStages::COMPUTE,
                     range: 0..size_of::<MeshingPushConstants>() as u32,
@@ -146,19 +276,97 @@ impl MeshingResources {
             usage: BufferUsages::INDIRECT | BufferUsages::COPY_SRC,
         });
 
+        let filter_buffer = ctx.device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("meshing filter_buffer"),
+            contents: bytemuck::bytes_of(state_filter),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+        let filter_bind_group = ctx.device.create_bind_group(&BindGroupDescriptor {
+            label: Some("meshing filter_bind_group"),
+            layout: &filter_bind_group_layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: filter_buffer.as_entire_binding(),
+            }],
+        });
+
         Self {
             bind_group_layout,
             pipeline,
             indirect_buffer_init,
+            filter_buffer,
+            filter_bind_group,
             per_chunk_resources: HashMap::new(),
+            workgroup_size,
         }
     }
 }
 
 impl Meshing {
     pub fn new(ctx: &WgpuContext, chunk_manager: &ChunkManager) -> Self {
-        let res = MeshingResources::new(ctx, chunk_manager);
-        Self { res }
+        let state_filter = StateFilter::default();
+        let res = MeshingResources::new(ctx, chunk_manager, [4, 4, 4], &state_filter);
+        let readback = CountReadback::new(ctx, 64);
+        Self {
+            res,
+            readback,
+            stats: MeshStats::default(),
+            state_filter,
+            frame: 0,
+            born: HashMap::new(),
+        }
+    }
+
+    /// Frame the chunks were last meshed at, what the fade of every chunk is measured against
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    pub fn stats(&self) -> &MeshStats {
+        &self.stats
+    }
+
+    /// Forces every chunk to be remeshed on the next update
+    pub fn invalidate_all(&mut self) {
+        for per_chunk_resource in self.res.per_chunk_resources.values_mut() {
+            per_chunk_resource.meshed = None;
+        }
+    }
+
+    pub fn set_workgroup_size(
+        &mut self,
+        ctx: &WgpuContext,
+        chunk_manager: &ChunkManager,
+        workgroup_size: [u32; 3],
+    ) {
+        if workgroup_size != self.res.workgroup_size {
+            // Per chunk bind groups reference the old layout, they get recreated on the next update
+            self.res =
+                MeshingResources::new(ctx, chunk_manager, workgroup_size, &self.state_filter);
+        }
+    }
+
+    /// Leaves the cells the filter hides out of the meshes, remeshing everything when it changes
+    pub fn set_state_filter(
+        &mut self,
+        ctx: &WgpuContext,
+        command_encoder: &mut CommandEncoder,
+        state_filter: StateFilter,
+    ) {
+        if state_filter == self.state_filter {
+            return;
+        }
+        self.state_filter = state_filter;
+        ctx.staging.write(
+            &ctx.device,
+            &ctx.queue,
+            command_encoder,
+            "meshing state_filter",
+            &self.res.filter_buffer,
+            0,
+            bytemuck::bytes_of(&self.state_filter),
+        );
+        self.invalidate_all();
     }
 
     pub fn update(
@@ -166,34 +374,66 @@ impl Meshing {
         ctx: &WgpuContext,
         command_encoder: &mut CommandEncoder,
         chunk_manager: &ChunkManager,
-    ) -> &HashMap<glm::IVec3, PerChunkResource> {
+    ) {
+        self.frame = self.frame.wrapping_add(1);
         self.res
             .per_chunk_resources
             .retain(|chunk, _| chunk_manager.chunks().contains_key(chunk));
+        self.born
+            .retain(|chunk, _| chunk_manager.chunks().contains_key(chunk));
 
+        if self.readback.mapped.load(Ordering::Acquire) {
+            self.process_readback(ctx);
+        }
+        if self.readback.map_failed.swap(false, Ordering::AcqRel) {
+            self.readback.map_requested = false;
+        }
+
+        // Only chunks whose voxels changed since they were last meshed are redone
+        let mesh_key = |chunk: &Chunk| MeshKey {
+            last_modified: chunk_manager.last_modified(&chunk.pos),
+            version: chunk.version,
+            offset: chunk.offset(),
+        };
+        let mut stale_chunks = Vec::new();
         for chunk in chunk_manager.chunks().values() {
-            self.res
+            let per_chunk_resource = self
+                .res
                 .per_chunk_resources
                 .entry(chunk.pos)
-                .or_insert_with(|| PerChunkResource::new(ctx, &self.res.bind_group_layout));
+                .or_insert_with(|| {
+                    PerChunkResource::new(ctx, &self.res.bind_group_layout, INITIAL_FACES)
+                });
+            if per_chunk_resource.meshed != Some(mesh_key(chunk)) {
+                stale_chunks.push(chunk);
+            }
+        }
+        // Chunks over the budget stay stale and are picked up in a later frame
+        let priority = &chunk_manager.priority;
+        priority.sort(&mut stale_chunks, |chunk| chunk.pos);
+        stale_chunks.truncate(priority.meshes_this_frame(stale_chunks.len()));
 
+        for chunk in &stale_chunks {
+            let per_chunk_resource = self.res.per_chunk_resources.get_mut(&chunk.pos).unwrap();
+            per_chunk_resource.meshed = Some(mesh_key(*chunk));
+            self.born.entry(chunk.pos).or_insert(self.frame);
             command_encoder.copy_buffer_to_buffer(
                 &self.res.indirect_buffer_init,
                 0,
-                &self.res.per_chunk_resources[&chunk.pos].indirect_buffer,
+                &per_chunk_resource.indirect_buffer,
                 0,
                 size_of::<DrawIndirectPod>() as u64,
             );
         }
 
-        {
+        if !stale_chunks.is_empty() {
             let mut compute_pass = command_encoder.begin_compute_pass(&ComputePassDescriptor {
                 label: Some("meshing compute_pass"),
                 timestamp_writes: None,
             });
 
             compute_pass.set_pipeline(&self.res.pipeline);
-            for chunk in chunk_manager.chunks().values() {
+            for chunk in stale_chunks {
                 let per_chunk_resource = &self.res.per_chunk_resources[&chunk.pos];
 
                 let (group, origin_x) = chunk_manager.offset_to_group_and_origin_x(chunk.offset());
@@ -201,27 +441,165 @@ impl Meshing {
                 compute_pass.set_push_constants(
                     0,
                     bytemuck::cast_slice(&[MeshingPushConstants {
-                        max_faces: self.res.per_chunk_resources[&chunk.pos]
-                            .instance_buffer
-                            .size() as u32
-                            / size_of::<FaceInstance>() as u32,
+                        max_faces: per_chunk_resource.max_faces(),
                         group,
                         origin_x,
                         which: chunk_manager.which(),
+                        born: self.born[&chunk.pos],
                     }]),
                 );
                 compute_pass.set_bind_group(0, &per_chunk_resource.bind_group, &[]);
                 compute_pass.set_bind_group(1, chunk_manager.bind_group(false), &[]);
+                compute_pass.set_bind_group(2, &self.res.filter_bind_group, &[]);
+                let [wg_x, wg_y, wg_z] = self.res.workgroup_size;
                 compute_pass.dispatch_workgroups(
-                    64u32.div_ceil(4),
-                    64u32.div_ceil(4),
-                    64u32.div_ceil(4),
+                    CHUNK_SIZE.div_ceil(wg_x),
+                    CHUNK_SIZE.div_ceil(wg_y),
+                    CHUNK_SIZE.div_ceil(wg_z),
                 );
             }
         }
 
+        self.copy_counts(ctx, command_encoder, chunk_manager);
+    }
+
+    pub fn per_chunk_resources(&self) -> &HashMap<glm::IVec3, PerChunkResource> {
         &self.res.per_chunk_resources
     }
+
+    fn copy_counts(
+        &mut self,
+        ctx: &WgpuContext,
+        command_encoder: &mut CommandEncoder,
+        chunk_manager: &ChunkManager,
+    ) {
+        // The previous readback is still in flight
+        if self.readback.map_requested {
+            return;
+        }
+
+        let num_chunks = chunk_manager.chunks().len() as u32;
+        if num_chunks > self.readback.capacity {
+            self.readback = CountReadback::new(ctx, num_chunks.next_power_of_two());
+        }
+
+        self.readback.entries.clear();
+        for (i, pos) in chunk_manager.chunks().keys().enumerate() {
+            let per_chunk_resource = &self.res.per_chunk_resources[pos];
+            command_encoder.copy_buffer_to_buffer(
+                &per_chunk_resource.indirect_buffer,
+                offset_of!(DrawIndirectPod, instance_count) as u64,
+                &self.readback.buffer,
+                (i * size_of::<u32>()) as u64,
+                size_of::<u32>() as u64,
+            );
+            self.readback
+                .entries
+                .push((*pos, per_chunk_resource.max_faces()));
+        }
+        self.readback.copied = !self.readback.entries.is_empty();
+    }
+
+    fn process_readback(&mut self, ctx: &WgpuContext) {
+        let mut stats = MeshStats::default();
+        {
+            let mapped_range = self.readback.buffer.slice(..).get_mapped_range();
+            let counts: &[u32] = bytemuck::cast_slice(&mapped_range);
+
+            for (&(pos, max_faces), &count) in self.readback.entries.iter().zip(counts) {
+                let Some(per_chunk_resource) = self.res.per_chunk_resources.get_mut(&pos) else {
+                    continue;
+                };
+                stats.faces += count as u64;
+                stats.chunks += 1;
+                stats.per_chunk.insert(pos, count);
+
+                // The count saturates at the buffer size when faces were dropped
+                let new_max_faces = if count >= max_faces {
+                    stats.truncated_chunks += 1;
+                    (max_faces * 4).min(MAX_FACES)
+                } else if count < max_faces / 4 {
+                    (max_faces / 2).max(MIN_FACES)
+                } else {
+                    max_faces
+                };
+                if new_max_faces != max_faces && per_chunk_resource.max_faces() == max_faces {
+                    *per_chunk_resource =
+                        PerChunkResource::new(ctx, &self.res.bind_group_layout, new_max_faces);
+                }
+            }
+        }
+        self.readback.buffer.unmap();
+        self.readback.mapped.store(false, Ordering::Release);
+        self.readback.map_requested = false;
+
+        stats.allocated_faces = self
+            .res
+            .per_chunk_resources
+            .values()
+            .map(|r| r.max_faces() as u64)
+            .sum();
+        self.stats = stats;
+    }
+
+    pub fn after_submit(&mut self) {
+        if !self.readback.copied {
+            return;
+        }
+        self.readback.copied = false;
+        self.readback.map_requested = true;
+        let mapped = self.readback.mapped.clone();
+        let map_failed = self.readback.map_failed.clone();
+        self.readback
+            .buffer
+            .slice(..)
+            .map_async(MapMode::Read, move |result| match result {
+                Ok(_) => mapped.store(true, Ordering::Release),
+                Err(e) => {
+                    log::error!("Failed to map count readback buffer: {:?}", e);
+                    map_failed.store(true, Ordering::Release);
+                }
+            });
+    }
+}
+
+/// How faces whose color alpha is below 1 are drawn
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Translucency {
+    // Alpha is ignored and every face is opaque
+    Off,
+    Blend,
+    Additive,
+}
+
+impl Translucency {
+    const ALL: [Translucency; 3] = [
+        Translucency::Off,
+        Translucency::Blend,
+        Translucency::Additive,
+    ];
+
+    // Stable name used in the settings
+    pub fn key(self) -> &'static str {
+        match self {
+            Translucency::Off => "off",
+            Translucency::Blend => "blend",
+            Translucency::Additive => "additive",
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|mode| mode.key() == key)
+    }
+}
+
+// Which faces a draw includes, faces that don't belong are collapsed in the vertex shader
+#[repr(u32)]
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum FacePass {
+    All = 0,
+    Opaque = 1,
+    Translucent = 2,
 }
 
 #[repr(C)]
@@ -229,35 +607,123 @@ impl Meshing {
 struct RenderPushConstants {
     view_proj: glm::Mat4x4,
     translate: glm::Vec3,
+    face_pass: u32,
+    chunk_tint: u32,
+    textured: u32,
+    atlas_rows: u32,
+    palette: u32,
+    frame: u32,
+    fade_frames: u32,
+    mirror: u32,
+    _pad0: u32,
+}
+
+pub const RENDER_PUSH_CONSTANTS_SIZE: u32 = size_of::<RenderPushConstants>() as u32;
+
+/// Draws the world again mirrored across a plane per enabled axis, and across every combination
+/// of them, for symmetric pictures out of asymmetric simulations. Only the drawing is affected,
+/// the mirrored copies can't be picked or edited.
+pub struct Kaleidoscope {
+    pub enabled: [bool; 3],
+    // Position of the plane along its axis, in voxels
+    pub planes: [f32; 3],
+}
+
+impl Kaleidoscope {
+    fn new() -> Self {
+        Self {
+            enabled: [false; 3],
+            planes: [0.0; 3],
+        }
+    }
+
+    /// Transform of every copy including the world itself, and a bit per axis it's mirrored
+    /// across, an odd number of them flips the winding
+    fn copies(&self) -> Vec<(glm::Mat4x4, u32)> {
+        (0..8usize)
+            .filter(|&mask| (0..3).all(|axis| mask & (1 << axis) == 0 || self.enabled[axis]))
+            .map(|mask| {
+                let mut transform = glm::Mat4x4::identity();
+                for axis in (0..3).filter(|axis| mask & (1 << *axis) != 0) {
+                    transform[(axis, axis)] = -1.0;
+                    transform[(axis, 3)] = 2.0 * self.planes[axis];
+                }
+                (transform, mask as u32)
+            })
+            .collect()
+    }
+
+    fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.collapsing("Kaleidoscope", |ui| {
+            egui::Grid::new("kaleidoscope_planes")
+                .show(ui, |ui| {
+                    for (axis, label) in
+                        ["Mirror x", "Mirror y", "Mirror z"].into_iter().enumerate()
+                    {
+                        ui.checkbox(&mut self.enabled[axis], label);
+                        ui.add_enabled(
+                            self.enabled[axis],
+                            egui::DragValue::new(&mut self.planes[axis]).speed(0.5),
+                        );
+                        ui.end_row();
+                    }
+                })
+                .response
+                .on_hover_text("Plane position along the axis, in voxels");
+            let copies = self.copies().len();
+            if copies > 1 {
+                ui.label(format!("Drawing the world {} times", copies));
+            }
+        });
+    }
 }
 
 struct RenderResources {
     shader: ShaderModule,
     pipeline_layout: PipelineLayout,
+    face_sort: FaceSort,
 }
 
 struct RenderDynamicResources {
     output_target: Rc<RenderTarget>,
-    pipeline: RenderPipeline,
+    // Indexed by whether the copy is mirrored, mirroring flips the winding of every face
+    pipeline: [RenderPipeline; 2],
+    blend_pipeline: [RenderPipeline; 2],
+    additive_pipeline: [RenderPipeline; 2],
 }
 
 pub struct Render {
     res: RenderResources,
     dynamic: RenderDynamicResources,
+    pub translucency: Translucency,
+    // Sorting is only needed for blending, and costs a few hundred dispatches per chunk
+    pub sort_faces: bool,
+    // Colors every chunk by its position, to spot data that ended up in the wrong chunk
+    pub chunk_tint: bool,
+    pub atlas: FaceAtlas,
+    pub palettes: ChunkPalettes,
+    pub kaleidoscope: Kaleidoscope,
+    // Chunks that just appeared are dithered in over this many frames instead of popping in, 0
+    // draws them whole right away
+    pub fade_frames: u32,
 }
 
 impl RenderResources {
-    fn new(ctx: &WgpuContext) -> Self {
+    fn new(ctx: &WgpuContext, atlas: &FaceAtlas, palettes: &ChunkPalettes) -> Self {
         let shader = ctx.device.create_shader_module(ShaderModuleDescriptor {
             label: Some("render shader"),
-            source: ShaderSource::Wgsl(include_str!("./render.wgsl").into()),
+            source: ShaderSource::Wgsl(
+                ShaderPrep::new()
+                    .process(include_str!("./render.wgsl"))
+                    .into(),
+            ),
         });
 
         let pipeline_layout = ctx
             .device
             .create_pipeline_layout(&PipelineLayoutDescriptor {
                 label: Some("render pipeline_layout"),
-                bind_group_layouts: &[],
+                bind_group_layouts: &[atlas.bind_group_layout(), palettes.bind_group_layout()],
                 push_constant_ranges: &[PushConstantRange {
                     stages: ShaderStages::VERTEX,
                     range: 0..size_of::<RenderPushConstants>() as u32,
@@ -267,132 +733,307 @@ impl RenderResources {
         Self {
             shader,
             pipeline_layout,
+            face_sort: FaceSort::new(ctx),
         }
     }
 }
 
 impl RenderDynamicResources {
+    fn new_pipeline(
+        ctx: &WgpuContext,
+        res: &RenderResources,
+        output_target: &RenderTarget,
+        label: &str,
+        fragment_entry_point: &str,
+        blend: Option<BlendState>,
+    ) -> [RenderPipeline; 2] {
+        [FrontFace::Ccw, FrontFace::Cw].map(|front_face| {
+            let label = match front_face {
+                FrontFace::Ccw => label.to_owned(),
+                FrontFace::Cw => format!("{} mirrored", label),
+            };
+            ctx.device
+                .create_render_pipeline(&RenderPipelineDescriptor {
+                    label: Some(&label),
+                    layout: Some(&res.pipeline_layout),
+                    vertex: VertexState {
+                        module: &res.shader,
+                        entry_point: "vs_main",
+                        buffers: &[VertexBufferLayout {
+                            array_stride: size_of::<FaceInstance>() as u64,
+                            step_mode: VertexStepMode::Instance,
+                            attributes: &[
+                                VertexAttribute {
+                                    format: VertexFormat::Uint32,
+                                    offset: offset_of!(FaceInstance, color) as u64,
+                                    shader_location: 0,
+                                },
+                                VertexAttribute {
+                                    format: VertexFormat::Uint32,
+                                    offset: offset_of!(FaceInstance, info) as u64,
+                                    shader_location: 1,
+                                },
+                                VertexAttribute {
+                                    format: VertexFormat::Uint32,
+                                    offset: offset_of!(FaceInstance, born) as u64,
+                                    shader_location: 2,
+                                },
+                                VertexAttribute {
+                                    format: VertexFormat::Uint32,
+                                    offset: offset_of!(FaceInstance, state) as u64,
+                                    shader_location: 3,
+                                },
+                            ],
+                        }],
+                    },
+                    fragment: Some(FragmentState {
+                        module: &res.shader,
+                        entry_point: fragment_entry_point,
+                        targets: &[Some(ColorTargetState {
+                            format: output_target.info.format,
+                            blend,
+                            write_mask: ColorWrites::ALL,
+                        })],
+                    }),
+                    primitive: PrimitiveState {
+                        topology: PrimitiveTopology::TriangleList,
+                        strip_index_format: None,
+                        front_face,
+                        cull_mode: Some(Face::Back),
+                        unclipped_depth: true,
+                        polygon_mode: PolygonMode::Fill,
+                        conservative: false,
+                    },
+                    depth_stencil: Some(DepthStencilState {
+                        format: TextureFormat::Depth32Float,
+                        // Blended faces are tested against the opaque ones but don't occlude
+                        // each other
+                        depth_write_enabled: blend.is_none(),
+                        depth_compare: CompareFunction::Greater,
+                        stencil: Default::default(),
+                        bias: Default::default(),
+                    }),
+                    multisample: MultisampleState::default(),
+                    multiview: None,
+                })
+        })
+    }
+
     fn new(ctx: &WgpuContext, res: &mut RenderResources, output_target: Rc<RenderTarget>) -> Self {
-        let pipeline = ctx
-            .device
-            .create_render_pipeline(&RenderPipelineDescriptor {
-                label: Some("render pipeline"),
-                layout: Some(&res.pipeline_layout),
-                vertex: VertexState {
-                    module: &res.shader,
-                    entry_point: "vs_main",
-                    buffers: &[VertexBufferLayout {
-                        array_stride: size_of::<FaceInstance>() as u64,
-                        step_mode: VertexStepMode::Instance,
-                        attributes: &[
-                            VertexAttribute {
-                                format: VertexFormat::Uint32,
-                                offset: offset_of!(FaceInstance, color) as u64,
-                                shader_location: 0,
-                            },
-                            VertexAttribute {
-                                format: VertexFormat::Uint32,
-                                offset: offset_of!(FaceInstance, info) as u64,
-                                shader_location: 1,
-                            },
-                        ],
-                    }],
+        let pipeline =
+            Self::new_pipeline(ctx, res, &output_target, "render pipeline", "fs_main", None);
+        let blend_pipeline = Self::new_pipeline(
+            ctx,
+            res,
+            &output_target,
+            "render blend_pipeline",
+            "fs_translucent",
+            Some(BlendState::ALPHA_BLENDING),
+        );
+        let additive_pipeline = Self::new_pipeline(
+            ctx,
+            res,
+            &output_target,
+            "render additive_pipeline",
+            "fs_translucent",
+            Some(BlendState {
+                color: BlendComponent {
+                    src_factor: BlendFactor::SrcAlpha,
+                    dst_factor: BlendFactor::One,
+                    operation: BlendOperation::Add,
                 },
-                fragment: Some(FragmentState {
-                    module: &res.shader,
-                    entry_point: "fs_main",
-                    targets: &[Some(output_target.info.format.into())],
-                }),
-                primitive: PrimitiveState {
-                    topology: PrimitiveTopology::TriangleList,
-                    strip_index_format: None,
-                    front_face: FrontFace::Ccw,
-                    cull_mode: Some(Face::Back),
-                    unclipped_depth: true,
-                    polygon_mode: PolygonMode::Fill,
-                    conservative: false,
-                },
-                depth_stencil: Some(DepthStencilState {
-                    format: TextureFormat::Depth32Float,
-                    depth_write_enabled: true,
-                    depth_compare: CompareFunction::Greater,
-                    stencil: Default::default(),
-                    bias: Default::default(),
-                }),
-                multisample: MultisampleState::default(),
-                multiview: None,
-            });
+                alpha: BlendComponent::OVER,
+            }),
+        );
 
         Self {
             output_target,
             pipeline,
+            blend_pipeline,
+            additive_pipeline,
         }
     }
 }
 
 impl Render {
     pub fn new(ctx: &WgpuContext, output_target: Rc<RenderTarget>) -> Self {
-        let mut res = RenderResources::new(ctx);
+        let atlas = FaceAtlas::new(ctx);
+        let palettes = ChunkPalettes::new(ctx);
+        let mut res = RenderResources::new(ctx, &atlas, &palettes);
         let dynamic = RenderDynamicResources::new(ctx, &mut res, output_target);
-        Self { res, dynamic }
+        Self {
+            res,
+            dynamic,
+            translucency: Translucency::Off,
+            sort_faces: true,
+            chunk_tint: false,
+            atlas,
+            palettes,
+            kaleidoscope: Kaleidoscope::new(),
+            fade_frames: 16,
+        }
     }
     pub fn resize(&mut self, ctx: &WgpuContext, output_target: Rc<RenderTarget>) {
         self.dynamic = RenderDynamicResources::new(ctx, &mut self.res, output_target);
     }
 
+    fn begin_render_pass<'a>(&'a self, command_encoder: &'a mut CommandEncoder) -> RenderPass<'a> {
+        command_encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("render render_pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: &self.dynamic.output_target.render_target,
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Clear(Color::BLACK),
+                    store: StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                view: self
+                    .dynamic
+                    .output_target
+                    .depth_target
+                    .as_ref()
+                    .expect("no depth target"),
+                depth_ops: Some(Operations {
+                    load: LoadOp::Clear(0.0),
+                    store: StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        })
+    }
+
+    /// Clears the color and depth targets without drawing, used when rendering is disabled
+    pub fn clear(&self, command_encoder: &mut CommandEncoder) {
+        self.begin_render_pass(command_encoder);
+    }
+
+    /// `frame` is the meshing frame, see `Meshing::frame`
     pub fn update(
         &mut self,
-        _ctx: &WgpuContext,
+        ctx: &WgpuContext,
         command_encoder: &mut CommandEncoder,
         chunk_manager: &ChunkManager,
         per_chunk_resource: &HashMap<glm::IVec3, PerChunkResource>,
         view_proj: &glm::Mat4x4,
+        frame: u32,
     ) {
-        {
-            let mut render_pass = command_encoder.begin_render_pass(&RenderPassDescriptor {
-                label: Some("render render_pass"),
-                color_attachments: &[Some(RenderPassColorAttachment {
-                    view: &self.dynamic.output_target.render_target,
-                    resolve_target: None,
-                    ops: Operations {
-                        load: LoadOp::Clear(Color::BLACK),
-                        store: StoreOp::Store,
-                    },
-                })],
-                depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
-                    view: self
-                        .dynamic
-                        .output_target
-                        .depth_target
-                        .as_ref()
-                        .expect("no depth target"),
-                    depth_ops: Some(Operations {
-                        load: LoadOp::Clear(0.0),
-                        store: StoreOp::Store,
-                    }),
-                    stencil_ops: None,
-                }),
-                timestamp_writes: None,
-                occlusion_query_set: None,
-            });
+        self.palettes.upload(ctx);
+        let translucent_pipeline = match self.translucency {
+            Translucency::Off => None,
+            Translucency::Blend => Some(&self.dynamic.blend_pipeline),
+            Translucency::Additive => Some(&self.dynamic.additive_pipeline),
+        };
+        if self.translucency == Translucency::Blend && self.sort_faces {
+            self.res.face_sort.update(
+                command_encoder,
+                chunk_manager,
+                per_chunk_resource,
+                view_proj,
+            );
+        }
 
-            render_pass.set_pipeline(&self.dynamic.pipeline);
+        // Chunks with a mesh, back to front for the translucent faces
+        let depth = |view_proj: &glm::Mat4x4, pos: &glm::IVec3| {
+            let center = (pos.cast::<f32>() + glm::vec3(0.5, 0.5, 0.5)) * CHUNK_SIZE as f32;
+            let clip = view_proj * glm::vec4(center.x, center.y, center.z, 1.0);
+            clip.z / clip.w.max(f32::EPSILON)
+        };
+        let chunks = chunk_manager
+            .chunks()
+            .keys()
+            .filter_map(|pos| Some((*pos, per_chunk_resource.get(pos)?)))
+            .collect::<Vec<_>>();
+        // The mirrored copies are sorted by chunk but reuse the faces sorted for the world itself
+        let copies = self
+            .kaleidoscope
+            .copies()
+            .into_iter()
+            .map(|(transform, mirror)| {
+                let view_proj = view_proj * transform;
+                let mut chunks = chunks.clone();
+                if translucent_pipeline.is_some() {
+                    chunks.sort_by(|(a, _), (b, _)| {
+                        depth(&view_proj, a).total_cmp(&depth(&view_proj, b))
+                    });
+                }
+                (view_proj, mirror, chunks)
+            })
+            .collect::<Vec<_>>();
 
-            for (pos, chunk) in chunk_manager.chunks() {
-                let per_chunk_resource = &per_chunk_resource[pos];
+        {
+            let mut render_pass = self.begin_render_pass(command_encoder);
 
-                render_pass.set_push_constants(
-                    ShaderStages::VERTEX,
-                    0,
-                    bytemuck::cast_slice(&[RenderPushConstants {
-                        view_proj: *view_proj,
-                        translate: chunk.pos.cast::<f32>() * 64.0,
-                    }]),
-                );
+            let passes = match translucent_pipeline {
+                None => vec![(&self.dynamic.pipeline, FacePass::All)],
+                Some(translucent_pipeline) => vec![
+                    (&self.dynamic.pipeline, FacePass::Opaque),
+                    (translucent_pipeline, FacePass::Translucent),
+                ],
+            };
+            for (pipeline, face_pass) in passes {
+                for (view_proj, mirror, chunks) in &copies {
+                    render_pass.set_pipeline(&pipeline[mirror.count_ones() as usize % 2]);
+                    render_pass.set_bind_group(0, self.atlas.bind_group(), &[]);
+                    render_pass.set_bind_group(1, self.palettes.bind_group(), &[]);
+
+                    // Chunks added while meshing is disabled have no mesh yet
+                    for (pos, per_chunk_resource) in chunks {
+                        render_pass.set_push_constants(
+                            ShaderStages::VERTEX,
+                            0,
+                            bytemuck::cast_slice(&[RenderPushConstants {
+                                view_proj: *view_proj,
+                                translate: pos.cast::<f32>() * CHUNK_SIZE as f32,
+                                face_pass: face_pass as u32,
+                                chunk_tint: self.chunk_tint as u32,
+                                textured: self.atlas.enabled as u32,
+                                atlas_rows: self.atlas.rows(),
+                                palette: self.palettes.palette(pos),
+                                frame,
+                                fade_frames: self.fade_frames,
+                                mirror: *mirror,
+                                _pad0: 0,
+                            }]),
+                        );
 
-                render_pass.set_vertex_buffer(0, per_chunk_resource.instance_buffer.slice(..));
-                render_pass.draw_indirect(&per_chunk_resource.indirect_buffer, 0);
+                        render_pass
+                            .set_vertex_buffer(0, per_chunk_resource.instance_buffer.slice(..));
+                        render_pass.draw_indirect(&per_chunk_resource.indirect_buffer, 0);
+                    }
+                }
             }
         }
     }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui, ctx: &WgpuContext) {
+        self.atlas.ui(ui, ctx);
+        ui.collapsing("Translucency", |ui| {
+            ui.horizontal(|ui| {
+                ui.radio_value(&mut self.translucency, Translucency::Off, "Off");
+                ui.radio_value(&mut self.translucency, Translucency::Blend, "Blend");
+                ui.radio_value(&mut self.translucency, Translucency::Additive, "Additive");
+            })
+            .response
+            .on_hover_text("Faces with a color alpha below 1 are drawn see-through");
+            ui.add_enabled(
+                self.translucency == Translucency::Blend,
+                egui::Checkbox::new(&mut self.sort_faces, "Sort faces on the GPU"),
+            )
+            .on_hover_text("Draws blended faces back to front, at the cost of a sort per chunk");
+        });
+        ui.checkbox(&mut self.chunk_tint, "Tint chunks")
+            .on_hover_text("Every chunk gets a hue of its own that only depends on its position");
+        ui.add(
+            egui::Slider::new(&mut self.fade_frames, 0..=120).text("Fade in chunks over frames"),
+        )
+        .on_hover_text(
+            "Chunks that were just loaded or generated are dithered in instead of popping in, \
+                 0 to turn it off",
+        );
+        self.kaleidoscope.ui(ui);
+    }
 }
@@ -1,6 +1,31 @@
+pub mod agents;
+#[cfg(feature = "bloom")]
 pub mod bloom;
+pub mod brush;
+pub mod chunk_activity;
+pub mod chunk_palettes;
+pub mod determinism;
+pub mod face_atlas;
+pub mod face_sort;
+pub mod frame_graph;
+pub mod ground;
+pub mod heat;
+pub mod legend;
+pub mod lifetimes;
+pub mod live_bounds;
 pub mod meshing_render;
+pub mod occupancy;
+#[cfg_attr(not(feature = "overlay"), path = "overlay_disabled.rs")]
 pub mod overlay;
+// Without the picker nothing is ever picked, but the brush still takes picks
+#[cfg_attr(not(feature = "picker"), allow(dead_code))]
+pub mod pick;
+#[cfg(feature = "picker")]
 pub mod picker;
+pub mod resample;
+pub mod rule_probe;
+pub mod seam_check;
 pub mod simulate;
+pub mod stamp;
 pub mod tonemap;
+pub mod world_transform;
@@ -0,0 +1,366 @@
+use std::collections::HashMap;
+use std::mem::size_of;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use bytemuck::{Pod, Zeroable};
+use nalgebra_glm as glm;
+use wgpu::*;
+
+use crate::chunk::CHUNK_SIZE;
+use crate::chunk_manager::ChunkManager;
+use crate::neighborhood::MAX_RADIUS;
+use crate::shader_prep::ShaderPrep;
+use crate::wgpu_context::WgpuContext;
+
+const WORKGROUP_SIZE: u32 = 4;
+// Cells this close to a face count as about to reach the chunk behind it
+const MARGIN: i32 = 24;
+// Cells move at most MAX_RADIUS per tick, so the margin is only trusted for this many ticks
+const MAX_STALE_TICKS: u64 = (MARGIN / MAX_RADIUS) as u64;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable, Default)]
+struct OccupancyPushConstants {
+    group: u32,
+    origin_x: u32,
+    which: u32,
+    slot: u32,
+    margin: i32,
+    _pad0: [u32; 3],
+}
+
+// Bit of the mask for the neighbor at `d`, the center bit stands for the chunk itself
+fn direction_bit(d: &glm::IVec3) -> u32 {
+    1 << ((d.x + 1) + (d.y + 1) * 3 + (d.z + 1) * 9)
+}
+
+fn directions() -> impl Iterator<Item = glm::IVec3> {
+    (0..27)
+        .map(|i| glm::vec3(i % 3 - 1, i / 3 % 3 - 1, i / 9 - 1))
+        .filter(|d| *d != glm::IVec3::zeros())
+}
+
+struct MaskReadback {
+    buffer: Buffer,
+    // Chunk and version for every mask in the buffer, in slot order
+    entries: Vec<(glm::IVec3, u64)>,
+    // Simulation tick the masks were computed at, and the most ticks a frame ran before that
+    tick: u64,
+    burst: u64,
+    copied: bool,
+    map_requested: bool,
+    mapped: Arc<AtomicBool>,
+}
+
+/// Finds chunks without live cells on the GPU and drops them from the grid groups, so empty space
+/// isn't simulated or meshed. Every chunk also reports which of its neighbors its cells are close
+/// to, and empty neighbors they're close to are brought back before the cells get there. Chunks
+/// are only dropped while the rule can't create cells out of nothing.
+pub struct Occupancy {
+    pub enabled: bool,
+    pub interval: u32,
+    pipeline: ComputePipeline,
+    bind_group_layout: BindGroupLayout,
+    // Room for this many chunk slots in the mask and readback buffers
+    capacity: u32,
+    mask_buffer: Buffer,
+    bind_group: BindGroup,
+    readback: MaskReadback,
+    last_run: Option<u64>,
+    // Tick of the last update, and the most ticks a frame ran since masks were last computed
+    last_tick: Option<u64>,
+    burst: u64,
+    evicted: usize,
+    materialized: usize,
+}
+
+impl Occupancy {
+    pub fn new(ctx: &WgpuContext, chunk_manager: &ChunkManager) -> Self {
+        let source = ShaderPrep::new().process(include_str!("./occupancy.wgsl"));
+        let shader = ctx.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("occupancy shader"),
+            source: ShaderSource::Wgsl(source.into()),
+        });
+
+        let bind_group_layout = ctx
+            .device
+            .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("occupancy bind_group_layout"),
+                entries: &[BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+            });
+
+        let pipeline_layout = ctx
+            .device
+            .create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some("occupancy pipeline_layout"),
+                bind_group_layouts: &[&bind_group_layout, chunk_manager.bind_group_layout(false)],
+                push_constant_ranges: &[PushConstantRange {
+                    stages: ShaderStages::COMPUTE,
+                    range: 0..size_of::<OccupancyPushConstants>() as u32,
+                }],
+            });
+
+        let pipeline = ctx
+            .device
+            .create_compute_pipeline(&ComputePipelineDescriptor {
+                label: Some("occupancy pipeline"),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point: "cs_occupancy",
+            });
+
+        let capacity = 64;
+        let (mask_buffer, bind_group, readback) =
+            Self::create_buffers(ctx, &bind_group_layout, capacity);
+
+        Self {
+            enabled: true,
+            interval: 8,
+            pipeline,
+            bind_group_layout,
+            capacity,
+            mask_buffer,
+            bind_group,
+            readback,
+            last_run: None,
+            last_tick: None,
+            burst: 0,
+            evicted: 0,
+            materialized: 0,
+        }
+    }
+
+    fn create_buffers(
+        ctx: &WgpuContext,
+        bind_group_layout: &BindGroupLayout,
+        capacity: u32,
+    ) -> (Buffer, BindGroup, MaskReadback) {
+        let size = capacity as u64 * size_of::<u32>() as u64;
+        let mask_buffer = ctx.device.create_buffer(&BufferDescriptor {
+            label: Some("occupancy mask_buffer"),
+            size,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let bind_group = ctx.device.create_bind_group(&BindGroupDescriptor {
+            label: Some("occupancy bind_group"),
+            layout: bind_group_layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: mask_buffer.as_entire_binding(),
+            }],
+        });
+        let readback = MaskReadback {
+            buffer: ctx.device.create_buffer(&BufferDescriptor {
+                label: Some("occupancy readback_buffer"),
+                size,
+                usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+                mapped_at_creation: false,
+            }),
+            entries: Vec::new(),
+            tick: 0,
+            burst: 0,
+            copied: false,
+            map_requested: false,
+            mapped: Arc::new(AtomicBool::new(false)),
+        };
+        (mask_buffer, bind_group, readback)
+    }
+
+    /// Drops and brings back chunks according to the last masks that came back. Runs before the
+    /// frame's chunk changes are finalized. `can_evict` is false while the rule can create cells
+    /// out of nothing or the world is still being loaded.
+    pub fn apply(&mut self, chunk_manager: &mut ChunkManager, tick: u64, can_evict: bool) {
+        if !self.enabled {
+            // Everything comes back when the feature is turned off
+            self.materialized += chunk_manager.empty_chunks().len();
+            chunk_manager.materialize_all();
+        }
+        if !self.readback.mapped.load(Ordering::Acquire) {
+            return;
+        }
+
+        let masks = {
+            let mapped_range = self.readback.buffer.slice(..).get_mapped_range();
+            let values: &[u32] = bytemuck::cast_slice(&mapped_range);
+            self.readback
+                .entries
+                .iter()
+                .zip(values)
+                // Chunks written from the CPU since have to be looked at again
+                .filter(|((pos, version), _)| {
+                    chunk_manager
+                        .chunks()
+                        .get(pos)
+                        .is_some_and(|chunk| chunk.version == *version)
+                })
+                .map(|((pos, _), &mask)| (*pos, mask))
+                .collect::<HashMap<_, _>>()
+        };
+        self.readback.buffer.unmap();
+        self.readback.mapped.store(false, Ordering::Release);
+        self.readback.map_requested = false;
+        if !self.enabled {
+            return;
+        }
+
+        for (pos, mask) in &masks {
+            for d in directions().filter(|d| mask & direction_bit(d) != 0) {
+                if chunk_manager.materialize(&(pos + d)) {
+                    self.materialized += 1;
+                }
+            }
+        }
+
+        // An evicted chunk comes back once later masks see cells approach, which are computed at
+        // least `interval` ticks later and only between frames. Many iterations per frame can
+        // carry cells past the whole margin before that.
+        let burst = self.readback.burst.max(self.burst).max(1);
+        let next_masks = (self.interval.max(1) as u64).div_ceil(burst) * burst;
+        let elapsed = tick.checked_sub(self.readback.tick);
+        if !can_evict || elapsed.map_or(true, |ticks| ticks + next_masks > MAX_STALE_TICKS) {
+            return;
+        }
+        for (pos, _) in masks.iter().filter(|(_, mask)| **mask == 0) {
+            let approached = directions().any(|d| {
+                let neighbor = pos + d;
+                match masks.get(&neighbor) {
+                    Some(mask) => mask & direction_bit(&-d) != 0,
+                    // Chunks without a fresh mask might hold anything
+                    None => chunk_manager.chunks().contains_key(&neighbor),
+                }
+            });
+            if !approached {
+                chunk_manager.evict_empty(pos);
+                self.evicted += 1;
+            }
+        }
+    }
+
+    pub fn update(
+        &mut self,
+        ctx: &WgpuContext,
+        command_encoder: &mut CommandEncoder,
+        chunk_manager: &ChunkManager,
+        tick: u64,
+    ) {
+        if let Some(last_tick) = self.last_tick {
+            self.burst = self.burst.max(tick.saturating_sub(last_tick));
+        }
+        self.last_tick = Some(tick);
+        if !self.enabled || self.readback.map_requested {
+            return;
+        }
+        let due = self.last_run.map_or(true, |last| {
+            tick < last || tick >= last + self.interval.max(1) as u64
+        });
+        if !due {
+            return;
+        }
+        self.last_run = Some(tick);
+
+        let num_offsets = chunk_manager.num_offsets();
+        if num_offsets == 0 {
+            return;
+        }
+        if num_offsets > self.capacity {
+            self.capacity = num_offsets.next_power_of_two();
+            (self.mask_buffer, self.bind_group, self.readback) =
+                Self::create_buffers(ctx, &self.bind_group_layout, self.capacity);
+        }
+
+        let size = num_offsets as u64 * size_of::<u32>() as u64;
+        command_encoder.clear_buffer(&self.mask_buffer, 0, Some(size));
+        {
+            let mut compute_pass = command_encoder.begin_compute_pass(&ComputePassDescriptor {
+                label: Some("occupancy compute_pass"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_pipeline(&self.pipeline);
+            compute_pass.set_bind_group(0, &self.bind_group, &[]);
+            compute_pass.set_bind_group(1, chunk_manager.bind_group(false), &[]);
+            for chunk in chunk_manager.chunks().values() {
+                let (group, origin_x) = chunk_manager.offset_to_group_and_origin_x(chunk.offset());
+                compute_pass.set_push_constants(
+                    0,
+                    bytemuck::bytes_of(&OccupancyPushConstants {
+                        group,
+                        origin_x,
+                        which: chunk_manager.which(),
+                        slot: chunk.offset(),
+                        margin: MARGIN,
+                        ..Default::default()
+                    }),
+                );
+                let workgroups = CHUNK_SIZE / WORKGROUP_SIZE;
+                compute_pass.dispatch_workgroups(workgroups, workgroups, workgroups);
+            }
+        }
+        command_encoder.copy_buffer_to_buffer(&self.mask_buffer, 0, &self.readback.buffer, 0, size);
+
+        self.readback.entries = chunk_manager
+            .offset_positions()
+            .iter()
+            .map(|pos| (*pos, chunk_manager.chunks()[pos].version))
+            .collect();
+        self.readback.tick = tick;
+        self.readback.burst = self.burst;
+        self.readback.copied = true;
+        self.burst = 0;
+    }
+
+    pub fn after_submit(&mut self) {
+        if !self.readback.copied {
+            return;
+        }
+        self.readback.copied = false;
+        self.readback.map_requested = true;
+        let mapped = self.readback.mapped.clone();
+        self.readback
+            .buffer
+            .slice(..)
+            .map_async(MapMode::Read, move |result| match result {
+                Ok(_) => mapped.store(true, Ordering::Release),
+                Err(e) => log::error!("Failed to map occupancy readback buffer: {:?}", e),
+            });
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui, chunk_manager: &ChunkManager) {
+        ui.collapsing("Empty chunks", |ui| {
+            ui.checkbox(&mut self.enabled, "Drop empty chunks")
+                .on_hover_text(
+                    "Chunks without live cells aren't simulated or meshed until cells get close",
+                );
+            ui.add(
+                egui::Slider::new(&mut self.interval, 1..=64)
+                    .logarithmic(true)
+                    .text("Check every n ticks"),
+            )
+            .on_hover_text(format!(
+                "Chunks are only dropped while this and the ticks a frame runs stay within {} \
+                 ticks, cells could get past the margin unseen otherwise",
+                MAX_STALE_TICKS
+            ));
+            let empty = chunk_manager.empty_chunks().len();
+            ui.label(format!(
+                "{} of {} chunks empty",
+                empty,
+                empty + chunk_manager.chunks().len()
+            ));
+            ui.label(format!(
+                "{} dropped, {} brought back",
+                self.evicted, self.materialized
+            ));
+        });
+    }
+}
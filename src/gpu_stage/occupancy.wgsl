@@ -0,0 +1,62 @@
+#include "common.wgsl"
+
+struct PushConstants {
+    @size(4) group: u32,
+    @size(4) origin_x: u32,
+    @size(4) which: u32,
+    @size(4) slot: u32,
+    margin: i32,
+};
+
+var<push_constant> consts: PushConstants;
+
+// Per chunk slot, bit (dx + 1) + (dy + 1) * 3 + (dz + 1) * 9 is set when live cells are within the
+// margin of the neighbor at (dx, dy, dz). The center bit means the chunk isn't empty.
+@group(0) @binding(0)
+var<storage, read_write> masks: array<atomic<u32>>;
+
+@group(1) @binding(0)
+var atlas: texture_storage_3d<{{CHUNK_FORMAT}}, read>;
+
+@group(1) @binding(1)
+var chunk_groups: binding_array<texture_storage_3d<{{CHUNK_FORMAT}}, read>, 8>;
+
+var<workgroup> wg_mask: atomic<u32>;
+
+@compute
+@workgroup_size(4, 4, 4)
+fn cs_occupancy(@builtin(global_invocation_id) gid: vec3<u32>, @builtin(local_invocation_index) lid: u32) {
+    if(lid == 0u) {
+        atomicStore(&wg_mask, 0u);
+    }
+    workgroupBarrier();
+
+    let pos = vec3<i32>(gid);
+    let cur = textureLoad(chunk_groups[consts.group], pos + vec3<i32>(vec3<u32>(consts.origin_x, 0u, consts.which)) * CHUNK_SIZE).r;
+    if(cur != 0u) {
+        // Per axis, -1 or 1 when the cell is within the margin of that face
+        let low = select(vec3<i32>(0), vec3<i32>(-1), pos < vec3<i32>(consts.margin));
+        let high = select(vec3<i32>(0), vec3<i32>(1), pos >= vec3<i32>(CHUNK_SIZE - consts.margin));
+        let side = low + high;
+        var mask = 0u;
+        for(var dz = -1; dz <= 1; dz++) {
+            for(var dy = -1; dy <= 1; dy++) {
+                for(var dx = -1; dx <= 1; dx++) {
+                    let d = vec3<i32>(dx, dy, dz);
+                    if(all((d == vec3<i32>(0)) | (d == side))) {
+                        mask |= 1u << u32((dx + 1) + (dy + 1) * 3 + (dz + 1) * 9);
+                    }
+                }
+            }
+        }
+        atomicOr(&wg_mask, mask);
+    }
+    workgroupBarrier();
+
+    if(lid == 0u) {
+        let mask = atomicLoad(&wg_mask);
+        if(mask != 0u) {
+            atomicOr(&masks[consts.slot], mask);
+        }
+    }
+}
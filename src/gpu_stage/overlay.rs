@@ -1,4 +1,7 @@
+use crate::gpu_stage::frame_graph::TargetStage;
 use crate::resource_size_helper::ResourceSizeHelper;
+use crate::resource_tracker::{self, Tracked};
+use crate::theme::{self, Theme};
 use crate::util::{RenderTarget, RenderTargetInfo};
 use crate::wgpu_context::WgpuContext;
 use bytemuck::{offset_of, Pod, Zeroable};
@@ -38,15 +41,19 @@ struct Resources {
 
 struct DynamicResources {
     output_target: Rc<RenderTarget>,
-    depth_view: Rc<TextureView>,
+    depth_view: Rc<Tracked<TextureView>>,
     pipeline: RenderPipeline,
 }
 
 pub struct Overlay {
+    // Colors the other stages draw their outlines with
+    pub theme: Theme,
     res: Resources,
     dynamic: DynamicResources,
     cylinder_instances: RefCell<Vec<WireframeInstanceInput>>,
     sphere_instances: RefCell<Vec<WireframeInstanceInput>>,
+    // Lines other stages wrote into buffers of their own on the GPU, with how many there are
+    line_buffers: RefCell<Vec<(Rc<Buffer>, u32)>>,
 }
 
 impl Resources {
@@ -125,7 +132,13 @@ impl DynamicResources {
         res.depth_desc.size.width = output_target.info.width;
         res.depth_desc.size.height = output_target.info.height;
         let depth_texture = ctx.device.create_texture(&res.depth_desc);
-        let depth_view = depth_texture.create_view(&TextureViewDescriptor::default());
+        let depth_view = resource_tracker::view(
+            &depth_texture,
+            &TextureViewDescriptor {
+                label: Some("overlay depth_view"),
+                ..Default::default()
+            },
+        );
         let wireframe_pipeline = ctx
             .device
             .create_render_pipeline(&RenderPipelineDescriptor {
@@ -209,10 +222,12 @@ impl Overlay {
         let mut res = Resources::new(ctx);
         let dynamic = DynamicResources::new(ctx, &mut res, output_target);
         Self {
+            theme: Theme::default(),
             res,
             dynamic,
             cylinder_instances: RefCell::new(vec![]),
             sphere_instances: RefCell::new(vec![]),
+            line_buffers: RefCell::new(vec![]),
         }
     }
 
@@ -225,20 +240,28 @@ impl Overlay {
         });
     }
 
-    pub fn resize(&mut self, ctx: &WgpuContext, output_target: Rc<RenderTarget>) {
-        self.dynamic = DynamicResources::new(ctx, &mut self.res, output_target);
+    /// Draws `count` lines from `buffer` this frame, laid out like the lines passed to `line` with
+    /// the color, both ends and a thickness in w
+    pub fn line_buffer(&self, buffer: Rc<Buffer>, count: u32) {
+        self.line_buffers.borrow_mut().push((buffer, count));
     }
 
-    pub fn input_target(&self) -> Rc<RenderTarget> {
-        Rc::new(RenderTarget {
-            render_target: self.dynamic.output_target.render_target.clone(),
-            depth_target: Some(self.dynamic.depth_view.clone()),
-            info: RenderTargetInfo {
-                format: self.dynamic.output_target.info.format,
-                width: self.dynamic.output_target.info.width,
-                height: self.dynamic.output_target.info.height,
-            },
-        })
+    /// The 12 edges of the axis aligned box from `min` to `max`
+    pub fn cuboid(&self, color: glm::Vec4, min: glm::Vec3, max: glm::Vec3) {
+        let corner = |i: usize| {
+            glm::vec3(
+                if i & 1 == 0 { min.x } else { max.x },
+                if i & 2 == 0 { min.y } else { max.y },
+                if i & 4 == 0 { min.z } else { max.z },
+            )
+        };
+        for i in 0..8 {
+            for bit in [1, 2, 4] {
+                if i & bit == 0 {
+                    self.line(color, (corner(i), corner(i | bit)));
+                }
+            }
+        }
     }
 
     pub fn update(
@@ -249,9 +272,33 @@ impl Overlay {
         view: &glm::Mat4x4,
     ) {
         self.line(
-            glm::vec4(1.0, 0.0, 1.0, 1.0),
+            theme::rgba(self.theme.origin),
             (glm::vec3(0.0, 0.0, 0.0), glm::vec3(1.0, 1.0, 0.0)),
         );
+
+        // Copies can't be recorded inside the render pass
+        let mut cylinder_instances = self.cylinder_instances.borrow_mut();
+        let cylinder_instance_buffer = self.res.cylinder_instance_buffer.get_or_recreate(
+            cylinder_instances.len() as u32,
+            |size| {
+                ctx.device.create_buffer(&BufferDescriptor {
+                    label: Some("overlay cylinder_instance_buffer"),
+                    size: size as u64 * size_of::<WireframeInstanceInput>() as u64,
+                    usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                })
+            },
+        );
+        ctx.staging.write(
+            &ctx.device,
+            &ctx.queue,
+            command_encoder,
+            "overlay cylinder_instances",
+            cylinder_instance_buffer,
+            0,
+            bytemuck::cast_slice(&cylinder_instances),
+        );
+
         {
             let mut render_pass = command_encoder.begin_render_pass(&RenderPassDescriptor {
                 label: Some("overlay render_pass"),
@@ -285,25 +332,7 @@ impl Overlay {
                 }]),
             );
 
-            let mut cylinder_instances = self.cylinder_instances.borrow_mut();
-
-            let cylinder_instance_buffer = self.res.cylinder_instance_buffer.get_or_recreate(
-                cylinder_instances.len() as u32,
-                |size| {
-                    ctx.device.create_buffer(&BufferDescriptor {
-                        label: Some("overlay cylinder_instance_buffer"),
-                        size: size as u64 * size_of::<WireframeInstanceInput>() as u64,
-                        usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
-                        mapped_at_creation: false,
-                    })
-                },
-            );
-
-            ctx.queue.write_buffer(
-                cylinder_instance_buffer,
-                0,
-                bytemuck::cast_slice(&cylinder_instances),
-            );
+            let cylinder_instance_buffer = self.res.cylinder_instance_buffer.get_existing();
 
             render_pass.set_vertex_buffer(0, self.res.cylinder_vertex_buffer.slice(..));
             render_pass.set_vertex_buffer(1, cylinder_instance_buffer.slice(..));
@@ -312,7 +341,35 @@ impl Overlay {
                 0..1,
             );
 
-            cylinder_instances.clear();
+            let line_buffers = self.line_buffers.borrow();
+            for (buffer, count) in line_buffers.iter() {
+                render_pass.set_vertex_buffer(1, buffer.slice(..));
+                render_pass.draw(
+                    0..self.res.cylinder_vertex_buffer.size() as u32
+                        / size_of::<glm::Vec4>() as u32,
+                    0..*count,
+                );
+            }
         }
+        cylinder_instances.clear();
+        self.line_buffers.borrow_mut().clear();
+    }
+}
+
+impl TargetStage for Overlay {
+    fn resize(&mut self, ctx: &WgpuContext, output_target: Rc<RenderTarget>) {
+        self.dynamic = DynamicResources::new(ctx, &mut self.res, output_target);
+    }
+
+    fn input_target(&self) -> Rc<RenderTarget> {
+        Rc::new(RenderTarget {
+            render_target: self.dynamic.output_target.render_target.clone(),
+            depth_target: Some(self.dynamic.depth_view.clone()),
+            info: RenderTargetInfo {
+                format: self.dynamic.output_target.info.format,
+                width: self.dynamic.output_target.info.width,
+                height: self.dynamic.output_target.info.height,
+            },
+        })
     }
 }
@@ -0,0 +1,88 @@
+use crate::gpu_stage::frame_graph::TargetStage;
+use crate::resource_tracker::{self, Tracked};
+use crate::theme::Theme;
+use crate::util::{RenderTarget, RenderTargetInfo};
+use crate::wgpu_context::WgpuContext;
+use nalgebra_glm as glm;
+use std::rc::Rc;
+use wgpu::*;
+
+/// Stands in for the overlay in builds without the `overlay` feature. Nothing is drawn, but the
+/// depth buffer render draws with is still owned here, so the frame is linked the same way.
+pub struct Overlay {
+    pub theme: Theme,
+    output_target: Rc<RenderTarget>,
+    depth_view: Rc<Tracked<TextureView>>,
+}
+
+impl Overlay {
+    pub fn new(ctx: &WgpuContext, output_target: Rc<RenderTarget>) -> Self {
+        let depth_view = Self::create_depth_view(ctx, &output_target);
+        Self {
+            theme: Theme::default(),
+            output_target,
+            depth_view,
+        }
+    }
+
+    fn create_depth_view(
+        ctx: &WgpuContext,
+        output_target: &RenderTarget,
+    ) -> Rc<Tracked<TextureView>> {
+        let depth_texture = ctx.device.create_texture(&TextureDescriptor {
+            label: Some("overlay depth_desc"),
+            size: Extent3d {
+                width: output_target.info.width,
+                height: output_target.info.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Depth32Float,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        Rc::new(resource_tracker::view(
+            &depth_texture,
+            &TextureViewDescriptor {
+                label: Some("overlay depth_view"),
+                ..Default::default()
+            },
+        ))
+    }
+
+    pub fn line(&self, _color: glm::Vec4, _line: (glm::Vec3, glm::Vec3)) {}
+
+    pub fn line_buffer(&self, _buffer: Rc<Buffer>, _count: u32) {}
+
+    pub fn cuboid(&self, _color: glm::Vec4, _min: glm::Vec3, _max: glm::Vec3) {}
+
+    pub fn update(
+        &mut self,
+        _ctx: &WgpuContext,
+        _command_encoder: &mut CommandEncoder,
+        _proj: &glm::Mat4x4,
+        _view: &glm::Mat4x4,
+    ) {
+    }
+}
+
+impl TargetStage for Overlay {
+    fn resize(&mut self, ctx: &WgpuContext, output_target: Rc<RenderTarget>) {
+        self.depth_view = Self::create_depth_view(ctx, &output_target);
+        self.output_target = output_target;
+    }
+
+    fn input_target(&self) -> Rc<RenderTarget> {
+        Rc::new(RenderTarget {
+            render_target: self.output_target.render_target.clone(),
+            depth_target: Some(self.depth_view.clone()),
+            info: RenderTargetInfo {
+                format: self.output_target.info.format,
+                width: self.output_target.info.width,
+                height: self.output_target.info.height,
+            },
+        })
+    }
+}
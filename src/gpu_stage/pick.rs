@@ -0,0 +1,36 @@
+use nalgebra_glm as glm;
+
+/// Where the pixel under the pick position lands in the world
+#[derive(Copy, Clone, Debug)]
+pub struct PickHit {
+    pub position: glm::Vec3,
+    // The voxel the surface belongs to
+    pub voxel: glm::IVec3,
+    // Unit axis pointing out of the face that was hit
+    pub normal: glm::IVec3,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Pick {
+    pub color: glm::Vec4,
+    // None where nothing was drawn
+    pub hit: Option<PickHit>,
+}
+
+impl PickHit {
+    /// The cell in front of the face that was hit, where a new voxel would go
+    pub fn adjacent(&self) -> glm::IVec3 {
+        self.voxel + self.normal
+    }
+
+    pub fn face_name(&self) -> &'static str {
+        match (self.normal.x, self.normal.y, self.normal.z) {
+            (-1, _, _) => "-x",
+            (1, _, _) => "+x",
+            (_, -1, _) => "-y",
+            (_, 1, _) => "+y",
+            (_, _, -1) => "-z",
+            _ => "+z",
+        }
+    }
+}
@@ -0,0 +1,54 @@
+// Same layout as the pick in picker.wgsl
+struct Pick {
+    color: vec4<f32>,
+    position: vec4<f32>,
+    voxel: vec4<i32>,
+    normal: vec4<f32>,
+};
+
+// Same layout as the overlay's line instances
+struct Line {
+    color: vec4<f32>,
+    // w is the thickness
+    offset1: vec4<f32>,
+    offset2: vec4<f32>,
+};
+
+struct PushConstants {
+    color: vec4<f32>,
+    // Slot of the newest pick in the history
+    head: u32,
+    // Picks recorded so far, at most the length of the history
+    count: u32,
+};
+
+var<push_constant> consts: PushConstants;
+
+@group(0) @binding(0) var<storage, read> history: array<Pick>;
+@group(0) @binding(1) var<storage, read_write> lines: array<Line>;
+
+// Line `i` joins the pick `i` picks back with the one before it, fading out with age
+@compute @workgroup_size(64)
+fn cs_trail(@builtin(global_invocation_id) id: vec3<u32>) {
+    let len = arrayLength(&history);
+    let age = id.x;
+    if(age + 1u >= len) {
+        return;
+    }
+    let newer = history[(consts.head + len - age) % len];
+    let older = history[(consts.head + len - age - 1u) % len];
+
+    var line: Line;
+    // Slots that weren't written yet and picks that missed the world give an invisible line
+    if(age + 1u < consts.count && newer.position.w > 0.0 && older.position.w > 0.0) {
+        let fade = 1.0 - f32(age) / f32(len - 1u);
+        line.color = vec4<f32>(consts.color.rgb, consts.color.a * fade);
+        line.offset1 = vec4<f32>(newer.position.xyz, 1.0);
+        line.offset2 = vec4<f32>(older.position.xyz, 1.0);
+    } else {
+        line.color = vec4<f32>(0.0);
+        line.offset1 = vec4<f32>(0.0);
+        line.offset2 = vec4<f32>(0.0);
+    }
+    lines[age] = line;
+}
@@ -1,15 +1,72 @@
 use std::mem::size_of;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
+use bytemuck::{Pod, Zeroable};
 use nalgebra_glm as glm;
 use wgpu::*;
 
+use crate::gpu_stage::frame_graph::TargetStage;
+use crate::gpu_stage::pick::{Pick, PickHit};
 use crate::util::RenderTarget;
 use crate::wgpu_context::WgpuContext;
 
+// Picks the trail is drawn through, the newest one included
+const TRAIL_LENGTH: u32 = 64;
+// Size of a line instance of the overlay, see pick_trail.wgsl
+const TRAIL_LINE_SIZE: u64 = 3 * size_of::<[f32; 4]>() as u64;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable, Default)]
+struct PickerPushConstants {
+    inv_view_proj: [[f32; 4]; 4],
+    pixel: [u32; 2],
+    _pad0: [u32; 2],
+}
+
+// Layout of the pick buffer, see picker.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable, Default)]
+struct PickPod {
+    color: [f32; 4],
+    position: [f32; 4],
+    voxel: [i32; 4],
+    normal: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable, Default)]
+struct TrailPushConstants {
+    color: [f32; 4],
+    head: u32,
+    count: u32,
+    _pad0: [u32; 2],
+}
+
+impl From<PickPod> for Pick {
+    fn from(pod: PickPod) -> Self {
+        let hit = (pod.position[3] > 0.0).then(|| PickHit {
+            position: glm::vec3(pod.position[0], pod.position[1], pod.position[2]),
+            voxel: glm::vec3(pod.voxel[0], pod.voxel[1], pod.voxel[2]),
+            normal: glm::vec3(pod.normal[0], pod.normal[1], pod.normal[2]).map(|c| c as i32),
+        });
+        Self {
+            color: glm::Vec4::from(pod.color),
+            hit,
+        }
+    }
+}
+
 struct Resources {
     bind_group_layout: BindGroupLayout,
     pipeline: ComputePipeline,
+    // The last picks stay on the GPU, copied into a ring one per pick, and are turned into lines
+    // for the overlay there too
+    history_buffer: Buffer,
+    trail_buffer: Rc<Buffer>,
+    trail_pipeline: ComputePipeline,
+    trail_bind_group: BindGroup,
 }
 
 struct DynamicResources {
@@ -19,9 +76,22 @@ struct DynamicResources {
     bind_group: BindGroup,
 }
 
+/// Reads back what's under a single pixel of the scene: its color, the world position
+/// reconstructed from the depth buffer, and the voxel and face it belongs to
 pub struct Picker {
     res: Resources,
     dynamic: DynamicResources,
+    // Pixel to pick, the center of the screen when not set
+    pixel: Option<glm::UVec2>,
+    result: Option<Pick>,
+    copied: bool,
+    map_requested: bool,
+    mapped: Arc<AtomicBool>,
+    // Draws a fading line through the last picks
+    pub trail: bool,
+    // Slot the next pick goes into and how many were recorded since the trail was cleared
+    trail_head: u32,
+    trail_count: u32,
 }
 
 impl Resources {
@@ -55,6 +125,16 @@ impl Resources {
                         },
                         count: None,
                     },
+                    BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: TextureViewDimension::D2,
+                            sample_type: TextureSampleType::Depth,
+                        },
+                        count: None,
+                    },
                 ],
             });
         let pipeline_layout = ctx
@@ -62,7 +142,10 @@ impl Resources {
             .create_pipeline_layout(&PipelineLayoutDescriptor {
                 label: Some("picker pipeline_layout"),
                 bind_group_layouts: &[&bind_group_layout],
-                push_constant_ranges: &[],
+                push_constant_ranges: &[PushConstantRange {
+                    stages: ShaderStages::COMPUTE,
+                    range: 0..size_of::<PickerPushConstants>() as u32,
+                }],
             });
         let pipeline = ctx
             .device
@@ -72,9 +155,90 @@ impl Resources {
                 module: &shader,
                 entry_point: "cs_main",
             });
+
+        let history_buffer = ctx.device.create_buffer(&BufferDescriptor {
+            label: Some("picker history_buffer"),
+            size: TRAIL_LENGTH as u64 * size_of::<PickPod>() as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let trail_buffer = ctx.device.create_buffer(&BufferDescriptor {
+            label: Some("picker trail_buffer"),
+            size: (TRAIL_LENGTH - 1) as u64 * TRAIL_LINE_SIZE,
+            usage: BufferUsages::STORAGE | BufferUsages::VERTEX,
+            mapped_at_creation: false,
+        });
+        let trail_shader = ctx.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("picker trail_shader"),
+            source: ShaderSource::Wgsl(include_str!("pick_trail.wgsl").into()),
+        });
+        let trail_bind_group_layout =
+            ctx.device
+                .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                    label: Some("picker trail_bind_group_layout"),
+                    entries: &[
+                        BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::Buffer {
+                                ty: BufferBindingType::Storage { read_only: true },
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        },
+                        BindGroupLayoutEntry {
+                            binding: 1,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::Buffer {
+                                ty: BufferBindingType::Storage { read_only: false },
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        },
+                    ],
+                });
+        let trail_pipeline_layout = ctx
+            .device
+            .create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some("picker trail_pipeline_layout"),
+                bind_group_layouts: &[&trail_bind_group_layout],
+                push_constant_ranges: &[PushConstantRange {
+                    stages: ShaderStages::COMPUTE,
+                    range: 0..size_of::<TrailPushConstants>() as u32,
+                }],
+            });
+        let trail_pipeline = ctx
+            .device
+            .create_compute_pipeline(&ComputePipelineDescriptor {
+                label: Some("picker trail_pipeline"),
+                layout: Some(&trail_pipeline_layout),
+                module: &trail_shader,
+                entry_point: "cs_trail",
+            });
+        let trail_bind_group = ctx.device.create_bind_group(&BindGroupDescriptor {
+            label: Some("picker trail_bind_group"),
+            layout: &trail_bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: history_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: trail_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
         Self {
             bind_group_layout,
             pipeline,
+            history_buffer,
+            trail_buffer: Rc::new(trail_buffer),
+            trail_pipeline,
+            trail_bind_group,
         }
     }
 }
@@ -83,18 +247,20 @@ impl DynamicResources {
     fn new(ctx: &WgpuContext, res: &mut Resources, output_target: Rc<RenderTarget>) -> Self {
         let buffer = ctx.device.create_buffer(&BufferDescriptor {
             label: Some("picker buffer"),
-            size: (output_target.info.width * output_target.info.height) as u64
-                * size_of::<glm::Vec4>() as u64,
-            usage: BufferUsages::COPY_DST | BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+            size: size_of::<PickPod>() as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC,
             mapped_at_creation: false,
         });
         let cpu_buffer = ctx.device.create_buffer(&BufferDescriptor {
             label: Some("picker cpu_buffer"),
-            size: (output_target.info.width * output_target.info.height) as u64
-                * size_of::<glm::Vec4>() as u64,
+            size: size_of::<PickPod>() as u64,
             usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
-            mapped_at_creation: true,
+            mapped_at_creation: false,
         });
+        let depth_target = output_target
+            .depth_target
+            .as_ref()
+            .expect("Picker needs a target with depth");
         let bind_group = ctx.device.create_bind_group(&BindGroupDescriptor {
             label: Some("picker bind_group"),
             layout: &res.bind_group_layout,
@@ -111,6 +277,10 @@ impl DynamicResources {
                         size: None,
                     }),
                 },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::TextureView(depth_target),
+                },
             ],
         });
         Self {
@@ -126,18 +296,76 @@ impl Picker {
     pub fn new(ctx: &WgpuContext, output_target: Rc<RenderTarget>) -> Self {
         let mut res = Resources::new(ctx);
         let dynamic = DynamicResources::new(ctx, &mut res, output_target);
-        Self { res, dynamic }
+        Self {
+            res,
+            dynamic,
+            pixel: None,
+            result: None,
+            copied: false,
+            map_requested: false,
+            mapped: Arc::new(AtomicBool::new(false)),
+            trail: false,
+            trail_head: 0,
+            trail_count: 0,
+        }
     }
 
-    pub fn resize(&mut self, ctx: &WgpuContext, output_target: Rc<RenderTarget>) {
-        self.dynamic = DynamicResources::new(ctx, &mut self.res, output_target);
+    /// Picks at `pixel` from now on, or the center of the screen with `None`
+    pub fn set_pixel(&mut self, pixel: Option<glm::UVec2>) {
+        self.pixel = pixel;
     }
 
-    pub fn input_target(&self) -> Rc<RenderTarget> {
-        self.dynamic.output_target.clone()
+    /// The last pick that came back, a frame or two behind
+    pub fn result(&self) -> Option<&Pick> {
+        self.result.as_ref()
     }
 
-    pub fn update(&mut self, ctx: &WgpuContext, command_encoder: &mut CommandEncoder) {
+    /// Lines through the last picks for the overlay, laid out like its line instances, and how
+    /// many there are
+    pub fn trail_lines(&self) -> Option<(Rc<Buffer>, u32)> {
+        self.trail
+            .then(|| (self.res.trail_buffer.clone(), TRAIL_LENGTH - 1))
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        if ui
+            .checkbox(&mut self.trail, "Pick trail")
+            .on_hover_text("Draws a line through the last picks that fades out")
+            .changed()
+        {
+            self.trail_count = 0;
+        }
+    }
+
+    /// `trail_color` is what the trail starts out as at the newest pick
+    pub fn update(
+        &mut self,
+        _ctx: &WgpuContext,
+        command_encoder: &mut CommandEncoder,
+        view_proj: &glm::Mat4,
+        trail_color: glm::Vec4,
+    ) {
+        if self.mapped.load(Ordering::Acquire) {
+            {
+                let mapped_range = self.dynamic.cpu_buffer.slice(..).get_mapped_range();
+                let pod: PickPod = *bytemuck::from_bytes(&mapped_range);
+                self.result = Some(pod.into());
+            }
+            self.dynamic.cpu_buffer.unmap();
+            self.mapped.store(false, Ordering::Release);
+            self.map_requested = false;
+        }
+        if self.map_requested {
+            return;
+        }
+
+        let info = &self.dynamic.output_target.info;
+        let pixel = self
+            .pixel
+            .unwrap_or_else(|| glm::vec2(info.width / 2, info.height / 2));
+        let Some(inv_view_proj) = view_proj.try_inverse() else {
+            return;
+        };
         {
             let mut compute_pass = command_encoder.begin_compute_pass(&ComputePassDescriptor {
                 label: Some("picker compute_pass"),
@@ -145,42 +373,90 @@ impl Picker {
             });
             compute_pass.set_pipeline(&self.res.pipeline);
             compute_pass.set_bind_group(0, &self.dynamic.bind_group, &[]);
-            compute_pass.dispatch_workgroups(
-                self.dynamic.output_target.info.width.div_ceil(8),
-                self.dynamic.output_target.info.height.div_ceil(8),
-                1,
+            compute_pass.set_push_constants(
+                0,
+                bytemuck::bytes_of(&PickerPushConstants {
+                    inv_view_proj: inv_view_proj.into(),
+                    pixel: [pixel.x, pixel.y],
+                    ..Default::default()
+                }),
             );
+            compute_pass.dispatch_workgroups(1, 1, 1);
         }
         command_encoder.copy_buffer_to_buffer(
             &self.dynamic.buffer,
             0,
             &self.dynamic.cpu_buffer,
             0,
-            (self.dynamic.output_target.info.width * self.dynamic.output_target.info.height) as u64
-                * size_of::<glm::Vec4>() as u64,
+            size_of::<PickPod>() as u64,
         );
+        self.copied = true;
 
-        {
-            let mapped_range = self.dynamic.cpu_buffer.slice(..).get_mapped_range();
+        if self.trail {
+            self.update_trail(command_encoder, trail_color);
+        }
+    }
+
+    fn update_trail(&mut self, command_encoder: &mut CommandEncoder, color: glm::Vec4) {
+        command_encoder.copy_buffer_to_buffer(
+            &self.dynamic.buffer,
+            0,
+            &self.res.history_buffer,
+            self.trail_head as u64 * size_of::<PickPod>() as u64,
+            size_of::<PickPod>() as u64,
+        );
+        self.trail_count = (self.trail_count + 1).min(TRAIL_LENGTH);
 
-            // let in_buf: &[glm::Vec4] = bytemuck::cast_slice(mapped_range.as_ref());
+        {
+            let mut compute_pass = command_encoder.begin_compute_pass(&ComputePassDescriptor {
+                label: Some("picker trail_compute_pass"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_pipeline(&self.res.trail_pipeline);
+            compute_pass.set_bind_group(0, &self.res.trail_bind_group, &[]);
+            compute_pass.set_push_constants(
+                0,
+                bytemuck::bytes_of(&TrailPushConstants {
+                    color: color.into(),
+                    head: self.trail_head,
+                    count: self.trail_count,
+                    ..Default::default()
+                }),
+            );
+            compute_pass.dispatch_workgroups((TRAIL_LENGTH - 1).div_ceil(64), 1, 1);
         }
 
-        self.dynamic.cpu_buffer.unmap();
+        self.trail_head = (self.trail_head + 1) % TRAIL_LENGTH;
     }
 
-    pub fn after_submit(&self) {
+    pub fn after_submit(&mut self) {
+        if !self.copied {
+            return;
+        }
+        self.copied = false;
+        self.map_requested = true;
+        let mapped = self.mapped.clone();
         self.dynamic
             .cpu_buffer
             .slice(..)
-            .map_async(MapMode::Read, |result| {
-                match result {
-                    Ok(_) => {}
-                    Err(e) => {
-                        log::error!("Failed to map buffer: {:?}", e);
-                    }
-                }
-                // result.expect("Failed to map buffer");
+            .map_async(MapMode::Read, move |result| match result {
+                Ok(_) => mapped.store(true, Ordering::Release),
+                Err(e) => log::error!("Failed to map picker buffer: {:?}", e),
             });
     }
 }
+
+impl TargetStage for Picker {
+    fn resize(&mut self, ctx: &WgpuContext, output_target: Rc<RenderTarget>) {
+        // A map in flight belongs to the old buffer
+        self.copied = false;
+        self.map_requested = false;
+        self.mapped = Arc::new(AtomicBool::new(false));
+        self.result = None;
+        self.dynamic = DynamicResources::new(ctx, &mut self.res, output_target);
+    }
+
+    fn input_target(&self) -> Rc<RenderTarget> {
+        self.dynamic.output_target.clone()
+    }
+}
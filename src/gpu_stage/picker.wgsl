@@ -1,16 +1,82 @@
 struct PushConstants {
-    @size(64) view_proj: mat4x4<f32>,
-    @size(16) translate: vec3<f32>,
+    @size(64) inv_view_proj: mat4x4<f32>,
+    pixel: vec2<u32>,
 };
 
+// What's under one pixel of the scene
+struct Pick {
+    color: vec4<f32>,
+    // xyz is the world position of the surface, w is 1 on a hit and 0 where nothing was drawn
+    position: vec4<f32>,
+    // xyz is the voxel that was hit, w the face: -x, +x, -y, +y, -z, +z in that order
+    voxel: vec4<i32>,
+    // Axis aligned normal of that face
+    normal: vec4<f32>,
+};
+
+var<push_constant> consts: PushConstants;
+
 @group(0) @binding(0) var texture: texture_2d<f32>;
-@group(0) @binding(1) var<storage, read_write> data: array<vec4<f32>>;
+@group(0) @binding(1) var<storage, read_write> pick: Pick;
+@group(0) @binding(2) var depth_texture: texture_depth_2d;
+
+fn unproject(pixel: vec2<i32>, depth: f32) -> vec3<f32> {
+    let dimensions = vec2<f32>(textureDimensions(depth_texture, 0));
+    let uv = (vec2<f32>(pixel) + 0.5) / dimensions;
+    let world = consts.inv_view_proj * vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    return world.xyz / world.w;
+}
+
+fn world_at(pixel: vec2<i32>) -> vec3<f32> {
+    let dimensions = vec2<i32>(textureDimensions(depth_texture, 0));
+    let clamped = clamp(pixel, vec2<i32>(0), dimensions - 1);
+    return unproject(clamped, textureLoad(depth_texture, clamped, 0));
+}
+
+// The step to the neighbor on the same surface, whichever side is closer
+fn surface_step(pixel: vec2<i32>, center: vec3<f32>, offset: vec2<i32>) -> vec3<f32> {
+    let forward = world_at(pixel + offset) - center;
+    let backward = center - world_at(pixel - offset);
+    return select(backward, forward, dot(forward, forward) < dot(backward, backward));
+}
 
-@compute @workgroup_size(8, 8)
-fn cs_main(@builtin(global_invocation_id) gid: vec3<u32>) {
-    let dimensions = textureDimensions(texture, 0);
-    if(gid.x >= dimensions.x || gid.y >= dimensions.y) {
+@compute @workgroup_size(1)
+fn cs_main() {
+    let dimensions = vec2<i32>(textureDimensions(texture, 0));
+    let pixel = min(vec2<i32>(consts.pixel), dimensions - 1);
+    pick.color = textureLoad(texture, pixel, 0);
+
+    // Depth is reversed, 0 is where nothing was drawn
+    let depth = textureLoad(depth_texture, pixel, 0);
+    if(depth <= 0.0) {
+        pick.position = vec4<f32>(0.0);
+        pick.voxel = vec4<i32>(0);
+        pick.normal = vec4<f32>(0.0);
         return;
     }
-    data[dimensions.x * gid.y + gid.x] = textureLoad(texture, gid.xy, 0);
-}
\ No newline at end of file
+
+    let center = unproject(pixel, depth);
+    var normal = cross(surface_step(pixel, center, vec2<i32>(1, 0)), surface_step(pixel, center, vec2<i32>(0, 1)));
+    // Facing back along the view ray, which goes from the near plane to the surface
+    let ray = center - unproject(pixel, 1.0);
+    if(dot(normal, ray) > 0.0) {
+        normal = -normal;
+    }
+
+    // Voxel faces are axis aligned, the largest component wins
+    let a = abs(normal);
+    var axis = 0;
+    if(a.y > a.x && a.y >= a.z) {
+        axis = 1;
+    } else if(a.z > a.x && a.z > a.y) {
+        axis = 2;
+    }
+    var face_normal = vec3<f32>(0.0);
+    face_normal[axis] = sign(normal[axis]);
+    let face = axis * 2 + select(0, 1, normal[axis] > 0.0);
+
+    pick.position = vec4<f32>(center, 1.0);
+    // Half a voxel back from the surface is inside the voxel that was hit
+    pick.voxel = vec4<i32>(vec3<i32>(floor(center - face_normal * 0.5)), face);
+    pick.normal = vec4<f32>(face_normal, 0.0);
+}
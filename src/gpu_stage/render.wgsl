@@ -1,6 +1,12 @@
+#include "common.wgsl"
+#include "dither.wgsl"
+#include "rng.wgsl"
+
 struct FaceInstance {
     @location(0) color: u32,
     @location(1) info: u32,
+    @location(2) born: u32,
+    @location(3) state: u32,
 }
 
 struct VertexOut {
@@ -9,15 +15,43 @@ struct VertexOut {
     @location(1) world_normal: vec3<f32>,
     @location(2) color: vec4<f32>,
     @location(3) @interpolate(flat) ao: u32,
+    // Where in the atlas, only used when textured is nonzero
+    @location(4) uv: vec2<f32>,
+    @location(5) @interpolate(flat) textured: u32,
+    // How far the chunk has faded in, from 0 to 1
+    @location(6) @interpolate(flat) fade: f32,
 };
 
 struct PushConstants {
     @size(64) view_proj: mat4x4<f32>,
-    @size(16) translate: vec3<f32>,
+    translate: vec3<f32>,
+    // 0 draws every face, 1 only opaque ones and 2 only translucent ones
+    face_pass: u32,
+    // Nonzero to tint every chunk in a hue of its own
+    chunk_tint: u32,
+    // Nonzero to draw faces with the atlas
+    textured: u32,
+    // Material rows in the atlas
+    atlas_rows: u32,
+    // Layer of the palettes plus one to recolor the chunk with, 0 keeps the cell colors
+    palette: u32,
+    // Current meshing frame, and how many frames after it was first meshed a chunk is drawn whole
+    frame: u32,
+    fade_frames: u32,
+    // A bit per axis the kaleidoscope copy being drawn is mirrored across
+    mirror: u32,
 };
 
 var<push_constant> consts: PushConstants;
 
+@group(0) @binding(0) var atlas: texture_2d<f32>;
+@group(0) @binding(1) var atlas_sampler: sampler;
+
+@group(1) @binding(0) var palettes: texture_2d_array<f32>;
+
+// Tile variants per material row, VARIANTS in face_atlas.rs
+const ATLAS_VARIANTS: u32 = 4u;
+
 var<private> which_vertex: array<u32, 6> = array<u32, 6>(
     0u, 1u, 2u, 2u, 1u, 3u
 );
@@ -51,33 +85,115 @@ var<private> indices: array<u32, 24> = array<u32, 24>(
     3u, 1u, 7u, 5u,
 );
 
+// Hue of the position the chunk is drawn at, which comes from the CPU side. Neighbouring chunks
+// stand apart so data in the wrong chunk shows up as a shape cut off at a hue border, the hue
+// itself can't tell which chunk the data came from
+fn chunk_hue(chunk_pos: vec3<i32>) -> vec3<f32> {
+    let p = bitcast<vec3<u32>>(chunk_pos);
+    let h = f32(hash(p.x ^ hash(p.y ^ hash(p.z))) >> 8u) / f32(1u << 24u);
+    let rgb = clamp(abs(fract(h + vec3<f32>(0.0, 2.0 / 3.0, 1.0 / 3.0)) * 6.0 - 3.0) - 1.0, vec3<f32>(0.0), vec3<f32>(1.0));
+    return rgb;
+}
+
 @vertex
 fn vs_main(@builtin(vertex_index) v_idx: u32, face: FaceInstance) -> VertexOut {
     let info = face.info;
-    let offset = vec3<u32>(info & 0x3Fu, (info >> 6u) & 0x3Fu, (info >> 12u) & 0x3Fu);
-    let side = (info >> 18u) & 0x7u;
+    let offset = vec3<u32>(info & CHUNK_MASK, (info >> CHUNK_SHIFT) & CHUNK_MASK, (info >> (CHUNK_SHIFT * 2u)) & CHUNK_MASK);
+    let side = (info >> FACE_SIDE_SHIFT) & 0x7u;
 
     let back_side = (side & 1u);
 
     let which = which_vertex[v_idx];
-    let ao = (info >> (21u + which * 2u)) & 0x3u;
+    let ao = (info >> (FACE_AO_SHIFT + which * 2u)) & 0x3u;
     let world_pos = vec3<f32>(offset) + pos[indices[side * 4u + which]] + consts.translate;
-    let world_normal = normal[side];
-    let color = unpack4x8unorm(face.color);
+    // The mirrored copies are lit like the faces they show rather than the ones they came from
+    let flip = (vec3<u32>(consts.mirror) >> vec3<u32>(0u, 1u, 2u)) & vec3<u32>(1u);
+    let world_normal = normal[side] * (1.0 - 2.0 * vec3<f32>(flip));
+    var color = unpack4x8unorm(face.color);
+    if(consts.chunk_tint != 0u) {
+        let chunk_pos = vec3<i32>(round(consts.translate / f32(CHUNK_SIZE)));
+        color = vec4<f32>(mix(color.rgb, chunk_hue(chunk_pos), 0.7), color.a);
+    }
+    if(consts.palette != 0u) {
+        // Brighter cells take later entries, the alpha stays so translucency still applies
+        let index = u32(round(dot(color.rgb, vec3<f32>(0.299, 0.587, 0.114)) * 255.0));
+        let entry = textureLoad(palettes, vec2<u32>(index, 0u), consts.palette - 1u, 0);
+        color = vec4<f32>(entry.rgb, color.a);
+    }
 
     var out: VertexOut;
 
     out.position = consts.view_proj * vec4<f32>(world_pos, 1.0);
+    let translucent = color.a < 1.0;
+    if((consts.face_pass == 1u && translucent) || (consts.face_pass == 2u && !translucent)) {
+        // Every vertex of the face ends up in the same spot, so nothing is rasterized
+        out.position = vec4<f32>(0.0, 0.0, 0.0, 1.0);
+    }
     out.world_pos = world_pos;
     out.world_normal = world_normal;
+    if(consts.fade_frames == 0u) {
+        out.fade = 1.0;
+    } else {
+        // Wrapping subtraction, the frame counter runs over eventually
+        let age = consts.frame - face.born;
+        out.fade = min(f32(age) / f32(consts.fade_frames), 1.0);
+    }
     out.color = color;
     out.ao = ao;
+    if(consts.textured != 0u) {
+        // The two axes along the face, in the same directions for both sides
+        let axis = side >> 1u;
+        let corner = pos[indices[side * 4u + which]];
+        let within = select(select(corner.xy, corner.xz, axis == 1u), corner.zy, axis == 0u);
+        // The material comes from the state meshing keyed the face with, a variant only depends
+        // on the voxel. The corners within the tile are filled in here, faces are instanced so
+        // meshing has a single entry for all four.
+        let voxel = bitcast<vec3<u32>>(vec3<i32>(round(consts.translate)) + vec3<i32>(offset));
+        let rows = max(consts.atlas_rows, 1u);
+        let material = hash(face.state) % rows;
+        let variant = hash(voxel.x ^ hash(voxel.y ^ hash(voxel.z))) % ATLAS_VARIANTS;
+        // Kept off the tile edges so nothing bleeds in from the neighbors
+        let inset = clamp(within, vec2<f32>(0.001), vec2<f32>(0.999));
+        out.uv = (vec2<f32>(f32(variant), f32(material)) + inset) / vec2<f32>(f32(ATLAS_VARIANTS), f32(rows));
+        out.textured = 1u;
+    } else {
+        out.uv = vec2<f32>(0.0);
+        out.textured = 0u;
+    }
 
     return out;
 }
 
+fn texel(in: VertexOut) -> vec3<f32> {
+    if(in.textured == 0u) {
+        return vec3<f32>(1.0);
+    }
+    return textureSampleLevel(atlas, atlas_sampler, in.uv, 0.0).rgb;
+}
+
+fn shade(in: VertexOut) -> vec3<f32> {
+    let emission = 1.0; // step(in.color.a, 0.05) * 25.0
+    return in.color.rgb * texel(in) * (dot(in.world_normal, vec3<f32>(0.8, 1.0, 0.2)) * 0.25 + 0.75) * (1.0 + emission);
+}
+
+// 4x4 ordered dither threshold of the pixel, so a chunk fading in covers more pixels every frame
+// in a fixed pattern instead of blending, which would need sorting
+fn dither_threshold(frag_coord: vec4<f32>) -> f32 {
+    return bayer_threshold(vec2<u32>(frag_coord.xy), 2u);
+}
+
 @fragment
 fn fs_main(in: VertexOut) -> @location(0) vec4<f32> {
-    let emission = 1.0; // step(in.color.a, 0.05) * 25.0
-    return vec4<f32>(in.color.rgb * (dot(in.world_normal, vec3<f32>(0.8, 1.0, 0.2)) * 0.25 + 0.75) * (1.0 + emission), 1.0);
-}
\ No newline at end of file
+    if(in.fade < dither_threshold(in.position)) {
+        discard;
+    }
+    return vec4<f32>(shade(in), 1.0);
+}
+
+@fragment
+fn fs_translucent(in: VertexOut) -> @location(0) vec4<f32> {
+    if(in.fade < dither_threshold(in.position)) {
+        discard;
+    }
+    return vec4<f32>(shade(in), in.color.a);
+}
@@ -0,0 +1,311 @@
+use std::collections::{HashMap, HashSet};
+use std::mem::size_of;
+
+use bytemuck::{Pod, Zeroable};
+use nalgebra_glm as glm;
+use wgpu::*;
+
+use crate::chunk::{CHUNK_SIZE, CHUNK_VOLUME};
+use crate::chunk_manager::ChunkManager;
+use crate::profiler::log_duration;
+use crate::shader_prep::ShaderPrep;
+use crate::wgpu_context::WgpuContext;
+
+const WORKGROUP_SIZE: u32 = 4;
+// Source chunks a destination chunk reads from when downscaling
+const SOURCE_SLOTS: u64 = 8;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ResampleDirection {
+    // Every voxel becomes a 2x2x2 block
+    Up,
+    // Every 2x2x2 block becomes a voxel
+    Down,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ResampleFilter {
+    // Keeps the first voxel of each block
+    Nearest,
+    // Keeps the most common value of each block
+    Majority,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable, Default)]
+struct ResamplePushConstants {
+    group: u32,
+    origin_x: u32,
+    which: u32,
+    upscale: u32,
+    majority: u32,
+    octant: [u32; 3],
+}
+
+/// Resamples the whole world by a factor of 2 about the origin, into a new set of chunks. The old
+/// chunks are snapshotted first and copied into a small source buffer one destination chunk at a
+/// time, so the world doesn't need to fit in a single storage binding.
+pub struct WorldResample {
+    pub filter: ResampleFilter,
+    // Created the first time the world is resampled
+    pipeline: Option<ResamplePipeline>,
+    last_error: Option<String>,
+}
+
+struct ResamplePipeline {
+    pipeline: ComputePipeline,
+    source_buffer: Buffer,
+    bind_group: BindGroup,
+}
+
+impl ResamplePipeline {
+    fn new(ctx: &WgpuContext, chunk_manager: &ChunkManager) -> Self {
+        log_duration("ResamplePipeline::new", || Self::create(ctx, chunk_manager))
+    }
+
+    fn create(ctx: &WgpuContext, chunk_manager: &ChunkManager) -> Self {
+        let source = ShaderPrep::new().process(include_str!("./resample.wgsl"));
+        let shader = ctx.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("resample shader"),
+            source: ShaderSource::Wgsl(source.into()),
+        });
+
+        let bind_group_layout = ctx
+            .device
+            .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("resample bind_group_layout"),
+                entries: &[BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+            });
+
+        let pipeline_layout = ctx
+            .device
+            .create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some("resample pipeline_layout"),
+                bind_group_layouts: &[&bind_group_layout, chunk_manager.bind_group_layout(true)],
+                push_constant_ranges: &[PushConstantRange {
+                    stages: ShaderStages::COMPUTE,
+                    range: 0..size_of::<ResamplePushConstants>() as u32,
+                }],
+            });
+
+        let pipeline = ctx
+            .device
+            .create_compute_pipeline(&ComputePipelineDescriptor {
+                label: Some("resample pipeline"),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point: "cs_resample",
+            });
+
+        let source_buffer = ctx.device.create_buffer(&BufferDescriptor {
+            label: Some("resample source_buffer"),
+            size: SOURCE_SLOTS * (CHUNK_VOLUME * size_of::<u32>()) as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = ctx.device.create_bind_group(&BindGroupDescriptor {
+            label: Some("resample bind_group"),
+            layout: &bind_group_layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: source_buffer.as_entire_binding(),
+            }],
+        });
+
+        Self {
+            pipeline,
+            source_buffer,
+            bind_group,
+        }
+    }
+}
+
+impl WorldResample {
+    pub fn new() -> Self {
+        Self {
+            filter: ResampleFilter::Majority,
+            pipeline: None,
+            last_error: None,
+        }
+    }
+
+    /// Chunk positions covering the world after resampling
+    fn resampled_positions(
+        chunk_manager: &ChunkManager,
+        direction: ResampleDirection,
+    ) -> HashSet<glm::IVec3> {
+        let mut positions = HashSet::new();
+        for pos in chunk_manager.chunks().keys() {
+            match direction {
+                ResampleDirection::Up => {
+                    for i in 0..8 {
+                        positions.insert(pos * 2 + glm::vec3(i & 1, (i >> 1) & 1, i >> 2));
+                    }
+                }
+                ResampleDirection::Down => {
+                    positions.insert(pos.map(|c| c.div_euclid(2)));
+                }
+            }
+        }
+        positions
+    }
+
+    /// Replaces the world with its resampled version, unless the result wouldn't fit. Returns
+    /// whether the world changed.
+    pub fn resample(
+        &mut self,
+        ctx: &WgpuContext,
+        chunk_manager: &mut ChunkManager,
+        direction: ResampleDirection,
+    ) -> bool {
+        match self.try_resample(ctx, chunk_manager, direction) {
+            Ok(()) => {
+                self.last_error = None;
+                true
+            }
+            Err(e) => {
+                log::warn!("Could not resample the world: {}", e);
+                self.last_error = Some(e);
+                false
+            }
+        }
+    }
+
+    fn try_resample(
+        &mut self,
+        ctx: &WgpuContext,
+        chunk_manager: &mut ChunkManager,
+        direction: ResampleDirection,
+    ) -> Result<(), String> {
+        chunk_manager.finalize_changes_and_start_frame(ctx);
+        let positions = Self::resampled_positions(chunk_manager, direction);
+        chunk_manager.check_chunk_positions(ctx, &positions)?;
+        let pipeline = self
+            .pipeline
+            .get_or_insert_with(|| ResamplePipeline::new(ctx, chunk_manager));
+
+        // The old voxels are kept aside before chunks are added or moved around
+        let mut encoder = ctx
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("resample snapshot"),
+            });
+        let snapshot = chunk_manager.snapshot_chunks(ctx, &mut encoder);
+        ctx.queue.submit([encoder.finish()]);
+        let snapshot_index = snapshot
+            .positions()
+            .iter()
+            .enumerate()
+            .map(|(i, pos)| (*pos, i))
+            .collect::<HashMap<_, _>>();
+
+        chunk_manager.set_chunk_positions(&positions);
+        chunk_manager.finalize_changes_and_start_frame(ctx);
+
+        let chunk_bytes = (CHUNK_VOLUME * size_of::<u32>()) as u64;
+        let mut encoder = ctx
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("resample"),
+            });
+        for pos in &positions {
+            let octant = pos.map(|c| c.rem_euclid(2) as u32);
+            match direction {
+                ResampleDirection::Up => {
+                    let source = pos.map(|c| c.div_euclid(2));
+                    snapshot.copy_chunk_to_buffer(
+                        &mut encoder,
+                        snapshot_index[&source],
+                        &pipeline.source_buffer,
+                        0,
+                    );
+                }
+                ResampleDirection::Down => {
+                    for slot in 0..SOURCE_SLOTS {
+                        let offset = glm::vec3(slot & 1, (slot >> 1) & 1, slot >> 2);
+                        let source = pos * 2 + offset.map(|c| c as i32);
+                        match snapshot_index.get(&source) {
+                            Some(&index) => snapshot.copy_chunk_to_buffer(
+                                &mut encoder,
+                                index,
+                                &pipeline.source_buffer,
+                                slot * chunk_bytes,
+                            ),
+                            None => encoder.clear_buffer(
+                                &pipeline.source_buffer,
+                                slot * chunk_bytes,
+                                Some(chunk_bytes),
+                            ),
+                        }
+                    }
+                }
+            }
+
+            let chunk = &chunk_manager.chunks()[pos];
+            let (group, origin_x) = chunk_manager.offset_to_group_and_origin_x(chunk.offset());
+            let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+                label: Some("resample compute_pass"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_pipeline(&pipeline.pipeline);
+            compute_pass.set_bind_group(0, &pipeline.bind_group, &[]);
+            compute_pass.set_bind_group(1, chunk_manager.bind_group(true), &[]);
+            compute_pass.set_push_constants(
+                0,
+                bytemuck::bytes_of(&ResamplePushConstants {
+                    group,
+                    origin_x,
+                    which: chunk_manager.which(),
+                    upscale: (direction == ResampleDirection::Up) as u32,
+                    majority: (self.filter == ResampleFilter::Majority) as u32,
+                    octant: [octant.x, octant.y, octant.z],
+                }),
+            );
+            let workgroups = CHUNK_SIZE / WORKGROUP_SIZE;
+            compute_pass.dispatch_workgroups(workgroups, workgroups, workgroups);
+        }
+        ctx.queue.submit([encoder.finish()]);
+        chunk_manager.mark_all_written();
+        Ok(())
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) -> Option<ResampleDirection> {
+        let mut requested = None;
+        ui.collapsing("Resample world", |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Downscale filter");
+                ui.radio_value(&mut self.filter, ResampleFilter::Nearest, "Nearest");
+                ui.radio_value(&mut self.filter, ResampleFilter::Majority, "Majority");
+            });
+            ui.horizontal(|ui| {
+                if ui
+                    .button("Double")
+                    .on_hover_text("Blows every voxel up into a 2x2x2 block")
+                    .clicked()
+                {
+                    requested = Some(ResampleDirection::Up);
+                }
+                if ui
+                    .button("Halve")
+                    .on_hover_text("Condenses every 2x2x2 block into a voxel")
+                    .clicked()
+                {
+                    requested = Some(ResampleDirection::Down);
+                }
+            });
+            if let Some(error) = &self.last_error {
+                ui.colored_label(ui.visuals().warn_fg_color, error);
+            }
+        });
+        requested
+    }
+}
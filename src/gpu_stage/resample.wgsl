@@ -0,0 +1,79 @@
+#include "common.wgsl"
+
+struct PushConstants {
+    @size(4) group: u32,
+    @size(4) origin_x: u32,
+    @size(4) which: u32,
+    // 1 to double the world, 0 to halve it
+    @size(4) upscale: u32,
+    @size(4) majority: u32,
+    // Upscaling: which half of the source chunk along each axis ends up in this chunk
+    @size(4) octant_x: u32,
+    @size(4) octant_y: u32,
+    @size(4) octant_z: u32,
+};
+
+var<push_constant> consts: PushConstants;
+
+// Source chunks tightly packed in x, y, z order. Upscaling only uses the first one, downscaling
+// uses all 8 in x, y, z order of their position relative to the destination
+@group(0) @binding(0)
+var<storage, read> source: array<u32>;
+
+@group(1) @binding(0)
+var atlas: texture_storage_3d<{{CHUNK_FORMAT}}, read>;
+
+@group(1) @binding(1)
+var grids: binding_array<texture_storage_3d<{{CHUNK_FORMAT}}, read_write>, 8>;
+
+fn source_index(slot: u32, pos: vec3<u32>) -> u32 {
+    return ((slot * CHUNK_SIZE_U + pos.z) * CHUNK_SIZE_U + pos.y) * CHUNK_SIZE_U + pos.x;
+}
+
+// Whether most of a 2x2x2 block is alive, votes by liveness since every cell has a color of its
+// own. A live block takes the color of the first live cell, ties count as alive so thin
+// structures don't vanish.
+fn majority(block: array<u32, 8>) -> u32 {
+    // Only variables can be indexed dynamically
+    var values = block;
+    var live = 0u;
+    var color = 0u;
+    for(var i = 0u; i < 8u; i++) {
+        if(values[i] != 0u) {
+            live += 1u;
+            if(color == 0u) {
+                color = values[i];
+            }
+        }
+    }
+    return select(0u, color, live >= 4u);
+}
+
+@compute
+@workgroup_size(4, 4, 4)
+fn cs_resample(@builtin(global_invocation_id) gid: vec3<u32>) {
+    var value: u32;
+    if(consts.upscale != 0u) {
+        let octant = vec3<u32>(consts.octant_x, consts.octant_y, consts.octant_z);
+        value = source[source_index(0u, octant * (CHUNK_SIZE_U / 2u) + gid / 2u)];
+    } else {
+        // The block covering this voxel lies in a single source chunk since it starts at an even
+        // position
+        let base = gid * 2u;
+        let slot_pos = base >> vec3<u32>(CHUNK_SHIFT);
+        let slot = slot_pos.x + slot_pos.y * 2u + slot_pos.z * 4u;
+        let local = base & vec3<u32>(CHUNK_MASK);
+        var values: array<u32, 8>;
+        for(var i = 0u; i < 8u; i++) {
+            let offset = vec3<u32>(i & 1u, (i >> 1u) & 1u, i >> 2u);
+            values[i] = source[source_index(slot, local + offset)];
+        }
+        if(consts.majority != 0u) {
+            value = majority(values);
+        } else {
+            value = values[0];
+        }
+    }
+    let dest = vec3<u32>(consts.origin_x * CHUNK_SIZE_U, 0u, consts.which * CHUNK_SIZE_U) + gid;
+    textureStore(grids[consts.group], dest, vec4<u32>(value, 0u, 0u, 0u));
+}
@@ -0,0 +1,25 @@
+// Counter based random numbers, the same as the functions in rng.rs
+
+fn rng_hash(x: u32) -> u32 {
+    // https://www.pcg-random.org/
+    let state = x * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+// Value number `counter` of the stream seeded with `seed`
+fn rng_stream(seed: u32, counter: u32) -> u32 {
+    return rng_hash(seed ^ rng_hash(counter));
+}
+
+// Cheap integer hash the shaders derive per cell values from, the tick rng and the colors of
+// chunks and atlas tiles. It has no counterpart in rng.rs.
+fn hash(in: u32) -> u32 {
+    var x = in;
+    x += x << 10u;
+    x ^= x >>  6u;
+    x += x <<  3u;
+    x ^= x >> 11u;
+    x += x << 15u;
+    return x;
+}
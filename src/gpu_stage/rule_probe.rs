@@ -0,0 +1,223 @@
+use std::mem::size_of;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use bytemuck::{Pod, Zeroable};
+use nalgebra_glm as glm;
+use wgpu::*;
+
+use crate::gpu_stage::pick::PickHit;
+use crate::wgpu_context::WgpuContext;
+
+// Flags the simulation shader sets in ProbeResult
+const PROBE_SIMULATED: u32 = 1;
+const PROBE_COUNTED: u32 = 2;
+const PROBE_BORN: u32 = 4;
+const PROBE_SURVIVES: u32 = 8;
+
+/// What the simulation shader wrote for the probed cell on the last tick it ran
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable, Default)]
+pub struct ProbeResult {
+    flags: u32,
+    // Low bits of the tick
+    tick: u32,
+    before: u32,
+    after: u32,
+    // Live neighbors, or the sum of their weights for larger than life
+    count: i32,
+    // Value a birth takes over
+    newest: u32,
+}
+
+/// Debug view of a single cell: while a voxel is probed, the simulation shader writes the
+/// neighbor count, the rule inputs and the outcome of that cell to the start of its feedback
+/// buffer, which is read back after every frame that simulated. Modes without a count only report
+/// the outcome.
+pub struct RuleProbe {
+    pub voxel: Option<glm::IVec3>,
+    readback_buffer: Buffer,
+    // Voxel the buffer was last cleared for, a new voxel starts from an empty result
+    cleared_for: Option<glm::IVec3>,
+    // Voxel the copy in the readback buffer is for
+    readback_voxel: Option<glm::IVec3>,
+    copied: bool,
+    in_flight: bool,
+    mapped: Arc<AtomicBool>,
+    result: Option<ProbeResult>,
+}
+
+fn decision(result: &ProbeResult) -> &'static str {
+    match (result.before != 0, result.after != 0) {
+        (false, false) => "stays empty",
+        (false, true) => "born",
+        (true, false) => "dies",
+        (true, true) if result.before == result.after => "survives",
+        (true, true) => "changes",
+    }
+}
+
+impl RuleProbe {
+    pub fn new(ctx: &WgpuContext) -> Self {
+        let readback_buffer = ctx.device.create_buffer(&BufferDescriptor {
+            label: Some("rule_probe readback_buffer"),
+            size: size_of::<ProbeResult>() as u64,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        Self {
+            voxel: None,
+            readback_buffer,
+            cleared_for: None,
+            readback_voxel: None,
+            copied: false,
+            in_flight: false,
+            mapped: Arc::new(AtomicBool::new(false)),
+            result: None,
+        }
+    }
+
+    pub fn update(&mut self) {
+        if self.mapped.load(Ordering::Acquire) {
+            self.process_readback();
+        }
+    }
+
+    /// Empties the result in the simulation's `feedback` buffer when another voxel is probed,
+    /// before the ticks of the frame
+    pub fn prepare(&mut self, encoder: &mut CommandEncoder, feedback: &Buffer) {
+        if self.voxel.is_none() || self.cleared_for == self.voxel {
+            return;
+        }
+        encoder.clear_buffer(feedback, 0, Some(size_of::<ProbeResult>() as u64));
+        self.cleared_for = self.voxel;
+        self.result = None;
+    }
+
+    /// Copies the result into the readback buffer, after the ticks of the frame
+    pub fn encode_readback(&mut self, encoder: &mut CommandEncoder, feedback: &Buffer) {
+        if self.voxel.is_none() || self.in_flight {
+            return;
+        }
+        encoder.copy_buffer_to_buffer(
+            feedback,
+            0,
+            &self.readback_buffer,
+            0,
+            size_of::<ProbeResult>() as u64,
+        );
+        self.readback_voxel = self.voxel;
+        self.copied = true;
+        self.in_flight = true;
+    }
+
+    fn process_readback(&mut self) {
+        {
+            let range = self.readback_buffer.slice(..).get_mapped_range();
+            let result: ProbeResult = *bytemuck::from_bytes(&range);
+            // Results for a voxel that isn't probed anymore are dropped
+            if self.readback_voxel == self.voxel && result.flags & PROBE_SIMULATED != 0 {
+                self.result = Some(result);
+            }
+        }
+        self.readback_buffer.unmap();
+        self.mapped.store(false, Ordering::Release);
+        self.in_flight = false;
+    }
+
+    pub fn after_submit(&mut self) {
+        if !self.copied {
+            return;
+        }
+        self.copied = false;
+        let mapped = self.mapped.clone();
+        self.readback_buffer
+            .slice(..)
+            .map_async(MapMode::Read, move |result| match result {
+                Ok(_) => mapped.store(true, Ordering::Release),
+                Err(e) => log::error!("Failed to map rule_probe buffer: {:?}", e),
+            });
+    }
+
+    fn result_ui(ui: &mut egui::Ui, result: &ProbeResult) {
+        egui::Grid::new("rule_probe_result").show(ui, |ui| {
+            ui.label("Tick");
+            ui.label(result.tick.to_string());
+            ui.end_row();
+            ui.label("Before");
+            ui.monospace(format!("{:08x}", result.before));
+            ui.end_row();
+            if result.flags & PROBE_COUNTED != 0 {
+                ui.label("Count");
+                ui.label(result.count.to_string());
+                ui.end_row();
+                ui.label("Newest neighbor");
+                ui.monospace(format!("{:08x}", result.newest));
+                ui.end_row();
+                ui.label("Rule");
+                ui.label(format!(
+                    "birth {}, survival {}",
+                    if result.flags & PROBE_BORN != 0 {
+                        "yes"
+                    } else {
+                        "no"
+                    },
+                    if result.flags & PROBE_SURVIVES != 0 {
+                        "yes"
+                    } else {
+                        "no"
+                    },
+                ));
+                ui.end_row();
+            }
+            ui.label("After");
+            ui.monospace(format!("{:08x}", result.after));
+            ui.end_row();
+            ui.label("Outcome");
+            ui.label(decision(result));
+            ui.end_row();
+        });
+    }
+
+    /// `hit` is the last pick, the voxel under it can be probed
+    pub fn ui(&mut self, ui: &mut egui::Ui, hit: Option<PickHit>) {
+        ui.collapsing("Rule probe", |ui| {
+            ui.horizontal(|ui| {
+                if ui
+                    .add_enabled(hit.is_some(), egui::Button::new("Probe picked voxel"))
+                    .on_hover_text("Shows how the rule decided on the voxel under the pick")
+                    .clicked()
+                {
+                    self.voxel = hit.map(|hit| hit.voxel);
+                }
+                if ui
+                    .add_enabled(self.voxel.is_some(), egui::Button::new("Stop"))
+                    .clicked()
+                {
+                    self.voxel = None;
+                    self.cleared_for = None;
+                    self.result = None;
+                }
+            });
+            let Some(voxel) = self.voxel else {
+                ui.label("No voxel probed");
+                return;
+            };
+            ui.label(format!("Voxel {}, {}, {}", voxel.x, voxel.y, voxel.z));
+            match &self.result {
+                Some(result) => Self::result_ui(ui, result),
+                None => {
+                    ui.label("Waiting for a tick of its chunk");
+                }
+            }
+        });
+    }
+
+    /// Push constant inputs of the simulation shader, the probed voxel and whether there is one
+    pub fn target(&self) -> (glm::IVec3, u32) {
+        match self.voxel {
+            Some(voxel) => (voxel, 1),
+            None => (glm::IVec3::zeros(), 0),
+        }
+    }
+}
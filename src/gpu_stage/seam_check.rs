@@ -0,0 +1,358 @@
+use std::collections::HashMap;
+use std::mem::size_of;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use bytemuck::{Pod, Zeroable};
+use nalgebra_glm as glm;
+use wgpu::*;
+
+use crate::chunk::CHUNK_SIZE;
+use crate::chunk_manager::ChunkManager;
+use crate::gpu_stage::meshing_render::PerChunkResource;
+use crate::gpu_stage::overlay::Overlay;
+use crate::shader_prep::ShaderPrep;
+use crate::theme;
+use crate::wgpu_context::WgpuContext;
+
+// Mismatches past this are counted but not located
+const MAX_REPORTED: u32 = 1024;
+const BORDER_WORDS: u32 = 6 * CHUNK_SIZE * CHUNK_SIZE / 32;
+// Direction of each face side, in FaceInstance order
+const SIDE_DIRECTIONS: [[i32; 3]; 6] = [
+    [-1, 0, 0],
+    [1, 0, 0],
+    [0, -1, 0],
+    [0, 1, 0],
+    [0, 0, -1],
+    [0, 0, 1],
+];
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable, Default)]
+struct SeamCheckPushConstants {
+    group: u32,
+    origin_x: u32,
+    which: u32,
+    side: u32,
+    neighbor_group: u32,
+    neighbor_origin_x: u32,
+    has_neighbor: u32,
+    max_faces: u32,
+    origin_world: [i32; 3],
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Seam {
+    pub voxel: glm::IVec3,
+    pub side: u32,
+    // Whether the face is missing, otherwise it's there although the neighbor covers it
+    pub missing: bool,
+}
+
+/// Debug pass that compares the faces meshing generated on chunk borders with the voxels of the
+/// neighboring chunks, to catch neighbor lookup bugs when chunks come and go. A border face is
+/// expected exactly where the voxel is filled and the voxel across the border is empty.
+///
+/// Meshing currently treats everything outside a chunk as empty, so every covered border face
+/// shows up as an extra face.
+pub struct SeamCheck {
+    // Check after every meshing update instead of only on request
+    pub continuous: bool,
+    pub visible: bool,
+    requested: bool,
+    clear_pipeline: ComputePipeline,
+    mark_pipeline: ComputePipeline,
+    compare_pipeline: ComputePipeline,
+    bind_group: BindGroup,
+    report_buffer: Buffer,
+    readback_buffer: Buffer,
+    in_flight: bool,
+    copied: bool,
+    mapped: Arc<AtomicBool>,
+    chunks_checked: usize,
+    total: u32,
+    seams: Vec<Seam>,
+}
+
+impl SeamCheck {
+    pub fn new(ctx: &WgpuContext, chunk_manager: &ChunkManager) -> Self {
+        let source = ShaderPrep::new()
+            .define("MAX_REPORTED", MAX_REPORTED)
+            .process(include_str!("./seam_check.wgsl"));
+        let shader = ctx.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("seam_check shader"),
+            source: ShaderSource::Wgsl(source.into()),
+        });
+
+        let storage_entry = |binding| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        // Same layout as the meshing per chunk bind groups, which are bound as is
+        let per_chunk_bind_group_layout =
+            ctx.device
+                .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                    label: Some("seam_check per_chunk bind_group_layout"),
+                    entries: &[storage_entry(0), storage_entry(1)],
+                });
+        let bind_group_layout = ctx
+            .device
+            .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("seam_check bind_group_layout"),
+                entries: &[storage_entry(0), storage_entry(1)],
+            });
+
+        let pipeline_layout = ctx
+            .device
+            .create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some("seam_check pipeline_layout"),
+                bind_group_layouts: &[
+                    &per_chunk_bind_group_layout,
+                    chunk_manager.bind_group_layout(false),
+                    &bind_group_layout,
+                ],
+                push_constant_ranges: &[PushConstantRange {
+                    stages: ShaderStages::COMPUTE,
+                    range: 0..size_of::<SeamCheckPushConstants>() as u32,
+                }],
+            });
+        let create_pipeline = |entry_point: &'static str| {
+            ctx.device
+                .create_compute_pipeline(&ComputePipelineDescriptor {
+                    label: Some("seam_check pipeline"),
+                    layout: Some(&pipeline_layout),
+                    module: &shader,
+                    entry_point,
+                })
+        };
+
+        let border_buffer = ctx.device.create_buffer(&BufferDescriptor {
+            label: Some("seam_check border_buffer"),
+            size: BORDER_WORDS as u64 * size_of::<u32>() as u64,
+            usage: BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        // A 16 byte count, then the reports
+        let report_size = 16 + MAX_REPORTED as u64 * size_of::<[i32; 4]>() as u64;
+        let report_buffer = ctx.device.create_buffer(&BufferDescriptor {
+            label: Some("seam_check report_buffer"),
+            size: report_size,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback_buffer = ctx.device.create_buffer(&BufferDescriptor {
+            label: Some("seam_check readback_buffer"),
+            size: report_size,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let bind_group = ctx.device.create_bind_group(&BindGroupDescriptor {
+            label: Some("seam_check bind_group"),
+            layout: &bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: border_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: report_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        Self {
+            continuous: false,
+            visible: true,
+            requested: false,
+            clear_pipeline: create_pipeline("cs_clear"),
+            mark_pipeline: create_pipeline("cs_mark"),
+            compare_pipeline: create_pipeline("cs_compare"),
+            bind_group,
+            report_buffer,
+            readback_buffer,
+            in_flight: false,
+            copied: false,
+            mapped: Arc::new(AtomicBool::new(false)),
+            chunks_checked: 0,
+            total: 0,
+            seams: Vec::new(),
+        }
+    }
+
+    /// Checks every meshed chunk when a check was requested, right after the meshing update so
+    /// the meshes match the current voxels
+    pub fn update(
+        &mut self,
+        ctx: &WgpuContext,
+        encoder: &mut CommandEncoder,
+        chunk_manager: &ChunkManager,
+        per_chunk_resources: &HashMap<glm::IVec3, PerChunkResource>,
+    ) {
+        if self.mapped.load(Ordering::Acquire) {
+            self.process_readback();
+        }
+        if self.in_flight || !(self.continuous || self.requested) {
+            return;
+        }
+        self.requested = false;
+
+        ctx.queue.write_buffer(&self.report_buffer, 0, &[0; 16]);
+        let mut chunks_checked = 0;
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+                label: Some("seam_check compute_pass"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_bind_group(1, chunk_manager.bind_group(false), &[]);
+            compute_pass.set_bind_group(2, &self.bind_group, &[]);
+            for chunk in chunk_manager.chunks().values() {
+                let Some(per_chunk_resource) = per_chunk_resources.get(&chunk.pos) else {
+                    continue;
+                };
+                chunks_checked += 1;
+                let (group, origin_x) = chunk_manager.offset_to_group_and_origin_x(chunk.offset());
+                let mut consts = SeamCheckPushConstants {
+                    group,
+                    origin_x,
+                    which: chunk_manager.which(),
+                    max_faces: per_chunk_resource.max_faces(),
+                    origin_world: (chunk.pos * CHUNK_SIZE as i32).into(),
+                    ..Default::default()
+                };
+                compute_pass.set_bind_group(0, per_chunk_resource.bind_group(), &[]);
+
+                compute_pass.set_pipeline(&self.clear_pipeline);
+                compute_pass.dispatch_workgroups(BORDER_WORDS.div_ceil(64), 1, 1);
+                compute_pass.set_pipeline(&self.mark_pipeline);
+                compute_pass.set_push_constants(0, bytemuck::bytes_of(&consts));
+                compute_pass.dispatch_workgroups(per_chunk_resource.max_faces().div_ceil(64), 1, 1);
+
+                compute_pass.set_pipeline(&self.compare_pipeline);
+                for (side, direction) in SIDE_DIRECTIONS.iter().enumerate() {
+                    let neighbor = chunk_manager
+                        .chunks()
+                        .get(&(chunk.pos + glm::IVec3::from(*direction)));
+                    let (neighbor_group, neighbor_origin_x) = neighbor
+                        .map(|n| chunk_manager.offset_to_group_and_origin_x(n.offset()))
+                        .unwrap_or((0, 0));
+                    consts.side = side as u32;
+                    consts.neighbor_group = neighbor_group;
+                    consts.neighbor_origin_x = neighbor_origin_x;
+                    consts.has_neighbor = neighbor.is_some() as u32;
+                    compute_pass.set_push_constants(0, bytemuck::bytes_of(&consts));
+                    compute_pass.dispatch_workgroups(CHUNK_SIZE / 8, CHUNK_SIZE / 8, 1);
+                }
+            }
+        }
+        encoder.copy_buffer_to_buffer(
+            &self.report_buffer,
+            0,
+            &self.readback_buffer,
+            0,
+            self.report_buffer.size(),
+        );
+        self.chunks_checked = chunks_checked;
+        self.copied = true;
+        self.in_flight = true;
+    }
+
+    fn process_readback(&mut self) {
+        {
+            let mapped_range = self.readback_buffer.slice(..).get_mapped_range();
+            let words: &[i32] = bytemuck::cast_slice(&mapped_range);
+            self.total = words[0] as u32;
+            self.seams = words[4..]
+                .chunks_exact(4)
+                .take(self.total.min(MAX_REPORTED) as usize)
+                .map(|entry| Seam {
+                    voxel: glm::vec3(entry[0], entry[1], entry[2]),
+                    side: entry[3] as u32 & 7,
+                    missing: entry[3] & 8 != 0,
+                })
+                .collect();
+        }
+        self.readback_buffer.unmap();
+        self.mapped.store(false, Ordering::Release);
+        self.in_flight = false;
+
+        if self.total > 0 && !self.continuous {
+            log::warn!(
+                "Seam check found {} mismatched border faces in {} chunks",
+                self.total,
+                self.chunks_checked
+            );
+        }
+    }
+
+    pub fn after_submit(&mut self) {
+        if !self.copied {
+            return;
+        }
+        self.copied = false;
+        let mapped = self.mapped.clone();
+        self.readback_buffer
+            .slice(..)
+            .map_async(MapMode::Read, move |result| match result {
+                Ok(_) => mapped.store(true, Ordering::Release),
+                Err(e) => log::error!("Failed to map seam check buffer: {:?}", e),
+            });
+    }
+
+    pub fn draw(&self, overlay: &Overlay) {
+        if !self.visible {
+            return;
+        }
+        for seam in &self.seams {
+            let color = theme::rgba(if seam.missing {
+                overlay.theme.seam_missing
+            } else {
+                overlay.theme.seam_mismatch
+            });
+            let min = seam.voxel.cast::<f32>();
+            overlay.cuboid(color, min, min + glm::vec3(1.0, 1.0, 1.0));
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.collapsing("Seam check", |ui| {
+            ui.checkbox(&mut self.continuous, "Check every frame");
+            ui.checkbox(&mut self.visible, "Highlight in overlay")
+                .on_hover_text("Red: face covered by the neighbor, yellow: face missing");
+            ui.horizontal(|ui| {
+                if ui
+                    .add_enabled(!self.in_flight, egui::Button::new("Check now"))
+                    .clicked()
+                {
+                    self.requested = true;
+                }
+                if ui.button("Clear").clicked() {
+                    self.total = 0;
+                    self.seams.clear();
+                }
+            });
+            let missing = self.seams.iter().filter(|s| s.missing).count();
+            ui.label(format!(
+                "{} mismatched faces in {} chunks, {} extra and {} missing listed",
+                self.total,
+                self.chunks_checked,
+                self.seams.len() - missing,
+                missing
+            ));
+            if let Some(seam) = self.seams.first() {
+                ui.label(format!(
+                    "First at {:?} on side {}",
+                    seam.voxel.as_slice(),
+                    seam.side
+                ));
+            }
+        });
+    }
+}
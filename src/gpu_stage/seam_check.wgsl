@@ -0,0 +1,136 @@
+#include "common.wgsl"
+
+struct DrawIndirect {
+    @size(4) vertex_count: u32,
+    @size(4) instance_count: u32,
+    @size(4) first_vertex: u32,
+    @size(4) first_instance: u32,
+};
+
+struct FaceInstance {
+    @size(4) color: u32,
+    @size(4) info: u32,
+    @size(4) born: u32,
+    @size(4) state: u32,
+}
+
+struct PushConstants {
+    @size(4) group: u32,
+    @size(4) origin_x: u32,
+    @size(4) which: u32,
+    @size(4) side: u32,
+    @size(4) neighbor_group: u32,
+    @size(4) neighbor_origin_x: u32,
+    // 0 when there's no chunk on the `side` of this one
+    @size(4) has_neighbor: u32,
+    @size(4) max_faces: u32,
+    // First voxel of the chunk in world coordinates, for the report
+    @size(4) origin_world_x: i32,
+    @size(4) origin_world_y: i32,
+    @size(4) origin_world_z: i32,
+};
+
+// xyz is the voxel in world coordinates, w is the side, plus 8 when the face is missing
+struct SeamReport {
+    @size(16) count: atomic<u32>,
+    entries: array<vec4<i32>, {{MAX_REPORTED}}>,
+};
+
+var<push_constant> consts: PushConstants;
+
+@group(0) @binding(0)
+var<storage, read_write> indirect: DrawIndirect;
+
+@group(0) @binding(1)
+var<storage, read_write> faces: array<FaceInstance>;
+
+@group(1) @binding(0)
+var atlas: texture_storage_3d<{{CHUNK_FORMAT}}, read>;
+
+@group(1) @binding(1)
+var chunk_groups: binding_array<texture_storage_3d<{{CHUNK_FORMAT}}, read>, 8>;
+
+// One bit per voxel on each of the 6 borders of the chunk being checked, set where the mesh has
+// a face pointing out of the chunk
+@group(2) @binding(0)
+var<storage, read_write> border_faces: array<atomic<u32>>;
+
+@group(2) @binding(1)
+var<storage, read_write> report: SeamReport;
+
+fn load(group: u32, origin_x: u32, pos: vec3<i32>) -> u32 {
+    return textureLoad(chunk_groups[group], pos + vec3<i32>(vec3<u32>(origin_x, 0u, consts.which)) * CHUNK_SIZE).r;
+}
+
+// Position of the voxel on the border of `side` at `uv`, with u and v the two other axes
+fn border_pos(side: u32, uv: vec2<u32>) -> vec3<i32> {
+    let axis = side / 2u;
+    let depth = select(0u, CHUNK_SIZE_U - 1u, side % 2u == 1u);
+    var pos = vec3<u32>(0u, 0u, 0u);
+    pos[axis] = depth;
+    pos[(axis + 1u) % 3u] = uv.x;
+    pos[(axis + 2u) % 3u] = uv.y;
+    return vec3<i32>(pos);
+}
+
+fn border_bit(side: u32, pos: vec3<u32>) -> u32 {
+    let axis = side / 2u;
+    return (side * CHUNK_SIZE_U + pos[(axis + 2u) % 3u]) * CHUNK_SIZE_U + pos[(axis + 1u) % 3u];
+}
+
+@compute
+@workgroup_size(64)
+fn cs_clear(@builtin(global_invocation_id) gid: vec3<u32>) {
+    if(gid.x < arrayLength(&border_faces)) {
+        atomicStore(&border_faces[gid.x], 0u);
+    }
+}
+
+@compute
+@workgroup_size(64)
+fn cs_mark(@builtin(global_invocation_id) gid: vec3<u32>) {
+    if(gid.x >= min(indirect.instance_count, consts.max_faces)) {
+        return;
+    }
+    let info = faces[gid.x].info;
+    let pos = vec3<u32>(info, info >> CHUNK_SHIFT, info >> (CHUNK_SHIFT * 2u)) & vec3<u32>(CHUNK_MASK);
+    let side = (info >> FACE_SIDE_SHIFT) & 7u;
+    let depth = select(0u, CHUNK_SIZE_U - 1u, side % 2u == 1u);
+    if(pos[side / 2u] != depth) {
+        return;
+    }
+    let bit = border_bit(side, pos);
+    atomicOr(&border_faces[bit / 32u], 1u << (bit % 32u));
+}
+
+@compute
+@workgroup_size(8, 8)
+fn cs_compare(@builtin(global_invocation_id) gid: vec3<u32>) {
+    let side = consts.side;
+    let pos = border_pos(side, gid.xy);
+    let cur = load(consts.group, consts.origin_x, pos);
+
+    var neighbor = 0u;
+    if(consts.has_neighbor != 0u) {
+        // The voxel across the border, on the opposite border of the neighbor
+        var neighbor_pos = pos;
+        neighbor_pos[side / 2u] = CHUNK_SIZE - 1 - pos[side / 2u];
+        neighbor = load(consts.neighbor_group, consts.neighbor_origin_x, neighbor_pos);
+    }
+
+    let bit = border_bit(side, vec3<u32>(pos));
+    let has_face = (atomicLoad(&border_faces[bit / 32u]) & (1u << (bit % 32u))) != 0u;
+    let expected = cur != 0u && neighbor == 0u;
+    if(has_face == expected) {
+        return;
+    }
+    // Faces past the end of a truncated buffer were dropped, not culled
+    if(!has_face && indirect.instance_count >= consts.max_faces) {
+        return;
+    }
+
+    let index = atomicAdd(&report.count, 1u);
+    if(index < {{MAX_REPORTED}}u) {
+        report.entries[index] = vec4<i32>(vec3<i32>(consts.origin_world_x, consts.origin_world_y, consts.origin_world_z) + pos, i32(side | select(0u, 8u, !has_face)));
+    }
+}
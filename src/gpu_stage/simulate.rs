@@ -1,13 +1,122 @@
 use bytemuck::{Pod, Zeroable};
 use nalgebra_glm as glm;
+use pod_enum::pod_enum;
+use rayon::prelude::*;
+use std::collections::BTreeMap;
 use std::mem::size_of;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use wgpu::*;
 use winit::event_loop::EventLoopProxy;
 
+use crate::chunk::{CHUNK_SIZE, CHUNK_VOLUME};
 use crate::chunk_manager::ChunkManager;
+use crate::containment::Containment;
+use crate::distance_throttle::DistanceThrottle;
+use crate::gpu_stage::agents::Agents;
+use crate::gpu_stage::chunk_activity::ChunkActivity;
+use crate::gpu_stage::determinism::DeterminismCheck;
+use crate::gpu_stage::heat::{HeatField, HEAT_CELL_SIZE};
+use crate::gpu_stage::lifetimes::{LifetimeHistogram, DEATH_CAPACITY};
+use crate::gpu_stage::rule_probe::{ProbeResult, RuleProbe};
+use crate::materials::Material;
+use crate::neighborhood::{Neighborhood, NeighborhoodShape, MAX_OFFSETS};
+use crate::portals::{PortalEntry, Portals};
+use crate::rng;
+use crate::rule_parser::{self, Rule};
+use crate::rules::{RulePreset, PRESETS};
+use crate::selection::Selection;
+use crate::shader_prep::ShaderPrep;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::shader_reload::{ShaderChange, ShaderReload};
+use crate::snapshots::SnapshotRing;
+use crate::tick_schedule::TickSchedule;
 use crate::user_event::UserEvent;
 use crate::wgpu_context::WgpuContext;
 
+pub const WORKGROUP_SIZE_CANDIDATES: [u32; 2] = [4, 8];
+
+// The packed kernel keeps a 2x2x2 block of cells per u32, in 4x4x4 block workgroups
+const PACKED_WORDS_PER_CHUNK: u64 = CHUNK_VOLUME as u64 / 8;
+const PACKED_WORKGROUP_SIZE: u32 = 4;
+
+// Chunks the chunk info and portal buffers have room for at first, they double when outgrown
+const MIN_CHUNK_CAPACITY: u32 = 4096;
+// Unchanged chunk info entries between two changed ones are uploaded along with them when there
+// are at most this many, rather than starting another copy
+const CHUNK_INFO_MERGE_GAP: usize = 16;
+// The changed chunk flags follow the probe result in the feedback buffer
+pub const CHANGED_FLAGS_OFFSET: u64 = size_of::<ProbeResult>() as u64;
+
+#[repr(u32)]
+#[pod_enum]
+pub enum SimulationMode {
+    Spread3d = 0,
+    Life2d = 1,
+    Margolus = 2,
+    LargerThanLife = 3,
+    ReactionDiffusion = 4,
+    Powder = 5,
+}
+
+impl Default for SimulationMode {
+    fn default() -> Self {
+        SimulationMode::Spread3d
+    }
+}
+
+impl SimulationMode {
+    const ALL: [SimulationMode; 6] = [
+        SimulationMode::Spread3d,
+        SimulationMode::Life2d,
+        SimulationMode::Margolus,
+        SimulationMode::LargerThanLife,
+        SimulationMode::ReactionDiffusion,
+        SimulationMode::Powder,
+    ];
+
+    // Stable name used in saved files
+    pub fn key(&self) -> &'static str {
+        if *self == SimulationMode::Life2d {
+            "life2d"
+        } else if *self == SimulationMode::Margolus {
+            "margolus"
+        } else if *self == SimulationMode::LargerThanLife {
+            "ltl3d"
+        } else if *self == SimulationMode::ReactionDiffusion {
+            "gray_scott"
+        } else if *self == SimulationMode::Powder {
+            "powder"
+        } else {
+            "spread3d"
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|mode| mode.key() == key)
+    }
+
+    /// Whether ticks update 2x2x2 blocks, whose offset alternates every tick
+    pub fn uses_blocks(&self) -> bool {
+        *self == SimulationMode::Margolus || *self == SimulationMode::Powder
+    }
+
+    /// What the rule calls a non-empty cell, the color tells cells apart
+    pub fn live_state_name(&self) -> &'static str {
+        if *self == SimulationMode::Life2d || *self == SimulationMode::LargerThanLife {
+            "Alive"
+        } else if *self == SimulationMode::Margolus {
+            "Particle"
+        } else if *self == SimulationMode::ReactionDiffusion {
+            "Reacting"
+        } else if *self == SimulationMode::Powder {
+            "Material"
+        } else {
+            "Spreading"
+        }
+    }
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, Pod, Zeroable, Default)]
 struct PushConstants {
@@ -15,51 +124,508 @@ struct PushConstants {
     chunks_per_buffer_shift: u32,
     starting_which: u32,
     num_chunks: u32,
+    mode: SimulationMode,
+    block_offset: u32,
+    portals_enabled: u32,
+    target_which: u32,
+    tick: u32,
+    record_lifetimes: u32,
+    containment_shape: u32,
+    _pad0: u32,
+    // Cell that reports its rule evaluation when probe_enabled is nonzero, in world voxels
+    probe_voxel: glm::IVec3,
+    probe_enabled: u32,
+    containment_center: glm::Vec3,
+    containment_radius: f32,
+    containment_half_extents: glm::Vec3,
+    reaction_diffusion: GrayScott,
+    _pad1: u32,
 }
 
+/// Transition rule of the life and larger than life modes. Kept in a uniform rather than the push
+/// constants, it's only uploaded again when the rule changes.
 #[repr(C)]
-#[derive(Copy, Clone, Debug, Pod, Zeroable, Default)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable, Default, PartialEq)]
+struct RuleConfig {
+    birth_mask: u32,
+    survival_mask: u32,
+    // Offsets in the neighborhood buffer
+    neighborhood_size: u32,
+    // Inclusive neighbor count ranges of the larger than life rule, min in the low 16 bits
+    birth_range: u32,
+    survival_range: u32,
+    birth_probability: f32,
+    survival_probability: f32,
+    // Nonzero to match the larger than life counts against the masks instead of the ranges
+    count_sets: u32,
+    // Nonzero for the spread mode to read the neighborhood buffer instead of the 6 face neighbors
+    spread_neighborhood: u32,
+    // Generations state count, 2 for cells that die at once
+    states: u32,
+    _pad0: [u32; 2],
+}
+
+// The region only changes with the selection, so it lives in a uniform of its own too
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable, Default, PartialEq)]
+struct RegionParams {
+    min: glm::IVec3,
+    enabled: u32,
+    max: glm::IVec3,
+    _pad0: u32,
+}
+
+// The alpha byte of a decaying cell holds how many steps it took, live cells keep 0xFF
+pub const MAX_STATES: u32 = 0xFF;
+// Most iterations the slider allows per frame
+const MAX_ITERATIONS: u32 = 1024;
+
+pub const PUSH_CONSTANTS_SIZE: u32 = size_of::<PushConstants>() as u32;
+
+/// How the simulation shader gets at the neighbors of a cell
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SimulateKernel {
+    // The workgroup loads the cells it reads into shared memory together first
+    Tiled = 0,
+    // Every cell reads its neighbors from the chunk textures itself
+    Direct = 1,
+}
+
+impl SimulateKernel {
+    const ALL: [SimulateKernel; 2] = [SimulateKernel::Tiled, SimulateKernel::Direct];
+
+    fn name(&self) -> &'static str {
+        match self {
+            SimulateKernel::Tiled => "tiled kernel",
+            SimulateKernel::Direct => "direct kernel",
+        }
+    }
+
+    fn other(&self) -> Self {
+        match self {
+            SimulateKernel::Tiled => SimulateKernel::Direct,
+            SimulateKernel::Direct => SimulateKernel::Tiled,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BlockRulePreset {
+    Identity,
+    Sand,
+    LatticeGas,
+}
+
+impl BlockRulePreset {
+    const ALL: [BlockRulePreset; 3] = [
+        BlockRulePreset::Identity,
+        BlockRulePreset::Sand,
+        BlockRulePreset::LatticeGas,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            BlockRulePreset::Identity => "Identity",
+            BlockRulePreset::Sand => "Sand",
+            BlockRulePreset::LatticeGas => "Lattice gas",
+        }
+    }
+
+    // Bit (x + 2y + 4z) of a pattern is the cell at (x, y, z) within the 2x2x2 block
+    fn table(&self) -> [u32; 256] {
+        let mut table = [0u32; 256];
+        for (pattern, entry) in table.iter_mut().enumerate() {
+            let pattern = pattern as u32;
+            *entry = match self {
+                BlockRulePreset::Identity => pattern,
+                BlockRulePreset::Sand => {
+                    let mut out = pattern;
+                    for column in [0u32, 1, 4, 5] {
+                        let bottom = 1 << column;
+                        let top = 1 << (column + 2);
+                        if out & top != 0 && out & bottom == 0 {
+                            out = (out & !top) | bottom;
+                        }
+                    }
+                    out
+                }
+                BlockRulePreset::LatticeGas => {
+                    // Reflect every cell through the block center so particles travel diagonally
+                    (0..8)
+                        .filter(|bit| pattern & (1 << bit) != 0)
+                        .fold(0, |acc, bit| acc | (1 << (7 - bit)))
+                }
+            };
+        }
+        table
+    }
+}
+
+/// Gray-Scott parameters, rates are per tick
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable, Default, PartialEq)]
+pub struct GrayScott {
+    pub feed: f32,
+    pub kill: f32,
+    pub diffusion_u: f32,
+    pub diffusion_v: f32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GrayScottPreset {
+    Spots,
+    Mitosis,
+    Coral,
+    Worms,
+}
+
+impl GrayScottPreset {
+    const ALL: [GrayScottPreset; 4] = [
+        GrayScottPreset::Spots,
+        GrayScottPreset::Mitosis,
+        GrayScottPreset::Coral,
+        GrayScottPreset::Worms,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            GrayScottPreset::Spots => "Spots",
+            GrayScottPreset::Mitosis => "Mitosis",
+            GrayScottPreset::Coral => "Coral",
+            GrayScottPreset::Worms => "Worms",
+        }
+    }
+
+    // Diffusion stays below 1/6, past that the explicit update on the 6 neighbor laplacian blows
+    // up
+    fn params(&self) -> GrayScott {
+        let (feed, kill) = match self {
+            GrayScottPreset::Spots => (0.03, 0.062),
+            GrayScottPreset::Mitosis => (0.0367, 0.0649),
+            GrayScottPreset::Coral => (0.0545, 0.062),
+            GrayScottPreset::Worms => (0.078, 0.061),
+        };
+        GrayScott {
+            feed,
+            kill,
+            diffusion_u: 0.16,
+            diffusion_v: 0.08,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable, Default, PartialEq)]
 struct ChunkInfoEntry {
     pos: glm::IVec3,
-    _pad0: u32,
+    tick_shift: u32,
+}
+
+struct PackedPipelines {
+    bind_group_layout: BindGroupLayout,
+    pack: ComputePipeline,
+    step: ComputePipeline,
+    unpack: ComputePipeline,
+}
+
+// Two packed copies of every chunk, ticks go back and forth between them
+struct PackedBuffers {
+    chunks: u32,
+    // The first reads buffer 0 and writes buffer 1, the second the other way around
+    bind_groups: [BindGroup; 2],
 }
 
 struct Resources {
+    data_bind_group_layout: BindGroupLayout,
+    // Chunks the chunk info and portal buffers have room for
+    chunk_capacity: u32,
     chunk_info_buffer: Buffer,
+    block_rule_buffer: Buffer,
+    neighborhood_buffer: Buffer,
+    portal_buffer: Buffer,
+    region_buffer: Buffer,
+    rule_buffer: Buffer,
+    // The rule probe result, followed by a flag per chunk offset that the workgroups that changed
+    // a cell set, see `RuleProbe` and `ChunkActivity`
+    feedback_buffer: Buffer,
     data_bind_group: BindGroup,
-    pipeline: ComputePipeline,
+    pipeline_layout: PipelineLayout,
+    // Indexed by SimulateKernel
+    pipelines: [ComputePipeline; 2],
+    packed: PackedPipelines,
+    workgroup_size: u32,
 }
 
 pub struct Simulate {
     res: Resources,
+    // What's in the chunk info buffer, along with the chunk layout version and throttle inputs it
+    // was built for
+    chunk_info: Vec<ChunkInfoEntry>,
+    chunk_info_key: Option<(u64, Option<(glm::Vec3, f32, f32, u32)>)>,
+    next_chunk_info: Vec<ChunkInfoEntry>,
     n_iter: u32,
     pub paused: bool,
+    pub schedule: TickSchedule,
     pub step: u32,
+    // Set to go back a single tick on the next update
+    pub step_back: bool,
+    pub snapshots: SnapshotRing,
+    pub determinism: DeterminismCheck,
+    pub lifetimes: LifetimeHistogram,
+    pub heat: HeatField,
+    pub probe: RuleProbe,
+    pub activity: ChunkActivity,
+    pub agents: Agents,
+    #[cfg(not(target_arch = "wasm32"))]
+    pub shader_reload: ShaderReload,
+    seed: u32,
+    pub mode: SimulationMode,
+    block_rule_preset: BlockRulePreset,
+    block_rule_dirty: bool,
+    tick: u64,
+    // The simulation pauses itself once it reaches this tick
+    pause_at: Option<u64>,
+    run_ticks_input: u64,
+    pause_at_input: u64,
+    // wgpu only exposes a single queue, so "async" simulation means submitting the simulation
+    // work on its own and not queueing more while a previous submission is still executing
+    pub separate_submission: bool,
+    in_flight: Arc<AtomicBool>,
+    // Bit n set means a cell is born/survives with n live neighbors (2d mode only)
+    pub birth_mask: u32,
+    pub survival_mask: u32,
+    // Larger than life mode sums the weights of the live neighbors and checks the sum against
+    // these ranges
+    pub neighborhood: Neighborhood,
+    pub birth_range: (u32, u32),
+    pub survival_range: (u32, u32),
+    // Larger than life checks the count against the birth and survival masks instead, for rules
+    // like the presets that aren't ranges
+    pub count_sets: bool,
+    // The spread mode counts the cells of `neighborhood` instead of the 6 face neighbors
+    pub spread_neighborhood: bool,
+    // Dead and alive plus the states a cell of the 2d or larger than life rule decays through
+    // after it stops surviving, 2 to die at once
+    pub states: u32,
+    // Picking a rule preset starts over from a random world
+    reseed_on_preset: bool,
+    // Rule typed in the rule language, see `rule_parser`
+    rule_input: String,
+    rule_error: Option<String>,
+    // Chance of a birth or survival the 2d or larger than life rule decided on actually happening.
+    // The dice come from the tick's random numbers, so replays and single steps roll the same.
+    pub birth_probability: f32,
+    pub survival_probability: f32,
+    // Reaction-diffusion cells hold the two concentrations instead of a color, see
+    // `simulate_reaction_diffusion`
+    pub reaction_diffusion: GrayScott,
+    // Lowest v that is drawn
+    pub iso_level: f32,
+    pub portals: Portals,
+    pub throttle: DistanceThrottle,
+    pub containment: Containment,
+    pub selection: Selection,
+    // Only cells inside the selection are simulated, the rest keep their state
+    pub only_in_selection: bool,
+    uploaded_region: Option<RegionParams>,
+    uploaded_rule: Option<RuleConfig>,
+    pub kernel: SimulateKernel,
+    // Runs the first tick of every update with both kernels, so they show up next to each other
+    // in the profiler
+    pub compare_kernels: bool,
+    // Margolus ticks run on cells packed into 2x2x2 blocks, see `simulate_packed.wgsl`
+    pub packed: bool,
+    packed_buffers: Option<PackedBuffers>,
 }
 
-impl Resources {
-    fn new(ctx: &WgpuContext, chunk_manager: &ChunkManager) -> Self {
+impl PackedPipelines {
+    fn new(
+        ctx: &WgpuContext,
+        chunk_manager: &ChunkManager,
+        data_bind_group_layout: &BindGroupLayout,
+    ) -> Self {
+        let tile_size = PACKED_WORKGROUP_SIZE + 2;
+        let source = ShaderPrep::new()
+            .define("TILE_VOLUME", tile_size * tile_size * tile_size)
+            .process(include_str!("simulate_packed.wgsl"));
         let shader = ctx.device.create_shader_module(ShaderModuleDescriptor {
-            label: Some("simulate shader"),
-            source: ShaderSource::Wgsl(include_str!("simulate.wgsl").into()),
+            label: Some("simulate packed_shader"),
+            source: ShaderSource::Wgsl(source.into()),
         });
 
+        let storage_entry = |binding, read_only| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = ctx
+            .device
+            .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("simulate packed_bind_group_layout"),
+                entries: &[storage_entry(0, true), storage_entry(1, false)],
+            });
+
+        let pipeline_layout = ctx
+            .device
+            .create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some("simulate packed_pipeline_layout"),
+                bind_group_layouts: &[
+                    data_bind_group_layout,
+                    chunk_manager.bind_group_layout(true),
+                    &bind_group_layout,
+                ],
+                push_constant_ranges: &[PushConstantRange {
+                    stages: ShaderStages::COMPUTE,
+                    range: 0..size_of::<PushConstants>() as u32,
+                }],
+            });
+        let create_pipeline = |entry_point: &'static str| {
+            ctx.device
+                .create_compute_pipeline(&ComputePipelineDescriptor {
+                    label: Some("simulate packed_pipeline"),
+                    layout: Some(&pipeline_layout),
+                    module: &shader,
+                    entry_point,
+                })
+        };
+
+        Self {
+            pack: create_pipeline("cs_pack"),
+            step: create_pipeline("cs_step"),
+            unpack: create_pipeline("cs_unpack"),
+            bind_group_layout,
+        }
+    }
+}
+
+impl PackedBuffers {
+    fn new(ctx: &WgpuContext, bind_group_layout: &BindGroupLayout, chunks: u32) -> Self {
+        let buffers = [0, 1].map(|_| {
+            ctx.device.create_buffer(&BufferDescriptor {
+                label: Some("simulate packed_buffer"),
+                size: chunks as u64 * PACKED_WORDS_PER_CHUNK * size_of::<u32>() as u64,
+                usage: BufferUsages::STORAGE,
+                mapped_at_creation: false,
+            })
+        });
+        let bind_groups = [0, 1].map(|src| {
+            ctx.device.create_bind_group(&BindGroupDescriptor {
+                label: Some("simulate packed_bind_group"),
+                layout: bind_group_layout,
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: buffers[src].as_entire_binding(),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: buffers[src ^ 1].as_entire_binding(),
+                    },
+                ],
+            })
+        });
+        Self {
+            chunks,
+            bind_groups,
+        }
+    }
+}
+
+impl Resources {
+    fn new(
+        ctx: &WgpuContext,
+        chunk_manager: &ChunkManager,
+        lifetimes: &LifetimeHistogram,
+        heat: &HeatField,
+        workgroup_size: u32,
+    ) -> Self {
         let data_bind_group_layout =
             ctx.device
                 .create_bind_group_layout(&BindGroupLayoutDescriptor {
                     label: Some("simulate data_bind_group_layout"),
-                    entries: &[BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: ShaderStages::COMPUTE,
-                        ty: BindingType::Buffer {
-                            ty: BufferBindingType::Storage { read_only: false },
-                            has_dynamic_offset: false,
-                            min_binding_size: BufferSize::new(
-                                (4096 * size_of::<ChunkInfoEntry>()) as u64,
-                            ),
+                    entries: &[
+                        BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::Buffer {
+                                ty: BufferBindingType::Storage { read_only: true },
+                                has_dynamic_offset: false,
+                                min_binding_size: BufferSize::new(
+                                    size_of::<ChunkInfoEntry>() as u64
+                                ),
+                            },
+                            count: None,
+                        },
+                        BindGroupLayoutEntry {
+                            binding: 1,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::Buffer {
+                                ty: BufferBindingType::Storage { read_only: true },
+                                has_dynamic_offset: false,
+                                min_binding_size: BufferSize::new(256 * size_of::<u32>() as u64),
+                            },
+                            count: None,
+                        },
+                        BindGroupLayoutEntry {
+                            binding: 2,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::Buffer {
+                                ty: BufferBindingType::Storage { read_only: true },
+                                has_dynamic_offset: false,
+                                min_binding_size: BufferSize::new(
+                                    (6 * size_of::<PortalEntry>()) as u64,
+                                ),
+                            },
+                            count: None,
                         },
-                        count: None,
-                    }],
+                        BindGroupLayoutEntry {
+                            binding: 3,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::Buffer {
+                                ty: BufferBindingType::Storage { read_only: true },
+                                has_dynamic_offset: false,
+                                min_binding_size: BufferSize::new(size_of::<glm::IVec4>() as u64),
+                            },
+                            count: None,
+                        },
+                        BindGroupLayoutEntry {
+                            binding: 4,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::Buffer {
+                                ty: BufferBindingType::Uniform,
+                                has_dynamic_offset: false,
+                                min_binding_size: BufferSize::new(size_of::<RegionParams>() as u64),
+                            },
+                            count: None,
+                        },
+                        BindGroupLayoutEntry {
+                            binding: 5,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::Buffer {
+                                ty: BufferBindingType::Uniform,
+                                has_dynamic_offset: false,
+                                min_binding_size: BufferSize::new(size_of::<RuleConfig>() as u64),
+                            },
+                            count: None,
+                        },
+                        BindGroupLayoutEntry {
+                            binding: 6,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::Buffer {
+                                ty: BufferBindingType::Storage { read_only: false },
+                                has_dynamic_offset: false,
+                                min_binding_size: BufferSize::new(
+                                    CHANGED_FLAGS_OFFSET + size_of::<u32>() as u64,
+                                ),
+                            },
+                            count: None,
+                        },
+                    ],
                 });
 
         let pipeline_layout = ctx
@@ -69,6 +635,8 @@ impl Resources {
                 bind_group_layouts: &[
                     &data_bind_group_layout,
                     chunk_manager.bind_group_layout(true),
+                    lifetimes.bind_group_layout(),
+                    heat.bind_group_layout(),
                 ],
                 push_constant_ranges: &[PushConstantRange {
                     stages: ShaderStages::COMPUTE,
@@ -76,108 +644,1434 @@ impl Resources {
                 }],
             });
 
-        let pipeline = ctx
-            .device
-            .create_compute_pipeline(&ComputePipelineDescriptor {
-                label: Some("simulate pipeline"),
-                layout: Some(&pipeline_layout),
-                module: &shader,
-                entry_point: "cs_simulate",
+        let pipelines = Self::create_pipelines(
+            ctx,
+            &pipeline_layout,
+            workgroup_size,
+            include_str!("simulate.wgsl"),
+        )
+        .unwrap_or_else(|e| panic!("{}", e));
+
+        let packed = PackedPipelines::new(ctx, chunk_manager, &data_bind_group_layout);
+
+        let block_rule_buffer = ctx.device.create_buffer(&BufferDescriptor {
+            label: Some("simulate block_rule_buffer"),
+            size: 256 * size_of::<u32>() as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let neighborhood_buffer = ctx.device.create_buffer(&BufferDescriptor {
+            label: Some("simulate neighborhood_buffer"),
+            size: (MAX_OFFSETS * size_of::<glm::IVec4>()) as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let region_buffer = ctx.device.create_buffer(&BufferDescriptor {
+            label: Some("simulate region_buffer"),
+            size: size_of::<RegionParams>() as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let rule_buffer = ctx.device.create_buffer(&BufferDescriptor {
+            label: Some("simulate rule_buffer"),
+            size: size_of::<RuleConfig>() as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let chunk_capacity = chunk_manager
+            .num_offsets()
+            .max(MIN_CHUNK_CAPACITY)
+            .next_power_of_two();
+        let (chunk_info_buffer, portal_buffer, feedback_buffer, data_bind_group) =
+            Self::create_chunk_buffers(
+                ctx,
+                &data_bind_group_layout,
+                &block_rule_buffer,
+                &neighborhood_buffer,
+                &region_buffer,
+                &rule_buffer,
+                chunk_capacity,
+            );
+
+        Self {
+            data_bind_group_layout,
+            chunk_capacity,
+            chunk_info_buffer,
+            block_rule_buffer,
+            neighborhood_buffer,
+            portal_buffer,
+            region_buffer,
+            rule_buffer,
+            feedback_buffer,
+            data_bind_group,
+            pipeline_layout,
+            pipelines,
+            packed,
+            workgroup_size,
+        }
+    }
+
+    /// Builds both kernels from `source`, which only fails for sources naming unknown includes
+    /// or constants. Errors in the WGSL itself are GPU errors.
+    fn create_pipelines(
+        ctx: &WgpuContext,
+        pipeline_layout: &PipelineLayout,
+        workgroup_size: u32,
+        source: &str,
+    ) -> Result<[ComputePipeline; 2], String> {
+        let tile_size = workgroup_size + 2;
+        let sources = SimulateKernel::ALL
+            .iter()
+            .map(|&kernel| {
+                let tiled = kernel == SimulateKernel::Tiled;
+                Material::define_all(ShaderPrep::new())
+                    .define("TILED", tiled)
+                    .define("WG_SIZE", workgroup_size)
+                    .define("DEATH_CAPACITY", DEATH_CAPACITY)
+                    .define("HEAT_CELL_SIZE", HEAT_CELL_SIZE)
+                    // The direct kernel doesn't use the tile, so it shouldn't reserve shared memory
+                    .define(
+                        "TILE_VOLUME",
+                        if tiled {
+                            tile_size * tile_size * tile_size
+                        } else {
+                            1
+                        },
+                    )
+                    .try_process(source)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(SimulateKernel::ALL.map(|kernel| {
+            let module = ctx.device.create_shader_module(ShaderModuleDescriptor {
+                label: Some("simulate shader"),
+                source: ShaderSource::Wgsl(sources[kernel as usize].as_str().into()),
             });
+            ctx.device
+                .create_compute_pipeline(&ComputePipelineDescriptor {
+                    label: Some("simulate pipeline"),
+                    layout: Some(pipeline_layout),
+                    module: &module,
+                    entry_point: "cs_simulate",
+                })
+        }))
+    }
 
+    fn create_chunk_buffers(
+        ctx: &WgpuContext,
+        data_bind_group_layout: &BindGroupLayout,
+        block_rule_buffer: &Buffer,
+        neighborhood_buffer: &Buffer,
+        region_buffer: &Buffer,
+        rule_buffer: &Buffer,
+        chunk_capacity: u32,
+    ) -> (Buffer, Buffer, Buffer, BindGroup) {
         let chunk_info_buffer = ctx.device.create_buffer(&BufferDescriptor {
             label: Some("simulate chunk_info_buffer"),
-            size: (4096 * size_of::<ChunkInfoEntry>()) as u64,
+            size: chunk_capacity as u64 * size_of::<ChunkInfoEntry>() as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let portal_buffer = ctx.device.create_buffer(&BufferDescriptor {
+            label: Some("simulate portal_buffer"),
+            size: chunk_capacity as u64 * 6 * size_of::<PortalEntry>() as u64,
             usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
             mapped_at_creation: false,
         });
 
+        let feedback_buffer = ctx.device.create_buffer(&BufferDescriptor {
+            label: Some("simulate feedback_buffer"),
+            size: CHANGED_FLAGS_OFFSET + chunk_capacity as u64 * size_of::<u32>() as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
         let data_bind_group = ctx.device.create_bind_group(&BindGroupDescriptor {
             label: Some("simulate data_bind_group"),
-            layout: &data_bind_group_layout,
-            entries: &[BindGroupEntry {
-                binding: 0,
-                resource: chunk_info_buffer.as_entire_binding(),
-            }],
+            layout: data_bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: chunk_info_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: block_rule_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: portal_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: neighborhood_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 4,
+                    resource: region_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 5,
+                    resource: rule_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 6,
+                    resource: feedback_buffer.as_entire_binding(),
+                },
+            ],
         });
 
-        Self {
+        (
             chunk_info_buffer,
+            portal_buffer,
+            feedback_buffer,
             data_bind_group,
+        )
+    }
 
-            pipeline,
+    /// Makes room for `chunks` chunks, returns whether the buffers were replaced and have to be
+    /// filled again
+    fn ensure_chunk_capacity(&mut self, ctx: &WgpuContext, chunks: u32) -> bool {
+        if chunks <= self.chunk_capacity {
+            return false;
         }
+        self.chunk_capacity = chunks.next_power_of_two();
+        log::info!(
+            "Growing the simulate chunk buffers to {} chunks",
+            self.chunk_capacity
+        );
+        (
+            self.chunk_info_buffer,
+            self.portal_buffer,
+            self.feedback_buffer,
+            self.data_bind_group,
+        ) = Self::create_chunk_buffers(
+            ctx,
+            &self.data_bind_group_layout,
+            &self.block_rule_buffer,
+            &self.neighborhood_buffer,
+            &self.region_buffer,
+            &self.rule_buffer,
+            self.chunk_capacity,
+        );
+        true
+    }
+
+    fn workgroups_per_chunk(&self) -> u32 {
+        (CHUNK_SIZE / self.workgroup_size).pow(3)
     }
 }
 
 impl Simulate {
     pub fn new(ctx: &WgpuContext, chunk_manager: &ChunkManager) -> Self {
-        let res = Resources::new(ctx, chunk_manager);
+        let lifetimes = LifetimeHistogram::new(ctx);
+        let heat = HeatField::new(ctx, chunk_manager);
+        let probe = RuleProbe::new(ctx);
+        let res = Resources::new(ctx, chunk_manager, &lifetimes, &heat, 8);
         Self {
             res,
+            chunk_info: Vec::new(),
+            chunk_info_key: None,
+            next_chunk_info: Vec::new(),
             n_iter: 1,
             paused: true,
+            schedule: TickSchedule::new(),
             step: 0,
+            step_back: false,
+            snapshots: SnapshotRing::new(),
+            determinism: DeterminismCheck::new(ctx, chunk_manager),
+            lifetimes,
+            heat,
+            probe,
+            activity: ChunkActivity::new(ctx),
+            agents: Agents::new(ctx, chunk_manager),
+            #[cfg(not(target_arch = "wasm32"))]
+            shader_reload: ShaderReload::new(),
+            seed: rand::random(),
+            mode: SimulationMode::Spread3d,
+            block_rule_preset: BlockRulePreset::Sand,
+            block_rule_dirty: true,
+            tick: 0,
+            pause_at: None,
+            run_ticks_input: 100,
+            pause_at_input: 1000,
+            separate_submission: true,
+            in_flight: Arc::new(AtomicBool::new(false)),
+            birth_mask: 1 << 3,
+            survival_mask: (1 << 2) | (1 << 3),
+            neighborhood: Neighborhood::new(),
+            birth_range: (34, 45),
+            survival_range: (34, 58),
+            count_sets: false,
+            spread_neighborhood: false,
+            states: 2,
+            reseed_on_preset: true,
+            rule_input: String::new(),
+            rule_error: None,
+            birth_probability: 1.0,
+            survival_probability: 1.0,
+            reaction_diffusion: GrayScottPreset::Mitosis.params(),
+            iso_level: 0.25,
+            portals: Portals::new(),
+            throttle: DistanceThrottle::new(),
+            containment: Containment::new(),
+            selection: Selection::new(),
+            only_in_selection: false,
+            uploaded_region: None,
+            uploaded_rule: None,
+            kernel: SimulateKernel::Tiled,
+            compare_kernels: false,
+            packed: false,
+            packed_buffers: None,
         }
     }
 
+    fn uses_packed_kernel(&self) -> bool {
+        self.packed
+            && self.mode == SimulationMode::Margolus
+            && !self.portals.enabled
+            && !self.containment.enabled()
+            && !self.restricted_to_selection()
+            && !self.agents.active()
+    }
+
+    fn restricted_to_selection(&self) -> bool {
+        self.only_in_selection && self.selection.active
+    }
+
+    fn region_params(&self) -> RegionParams {
+        RegionParams {
+            min: self.selection.min,
+            enabled: self.restricted_to_selection() as u32,
+            max: self.selection.max,
+            ..Default::default()
+        }
+    }
+
+    fn rule_config(&self) -> RuleConfig {
+        RuleConfig {
+            birth_mask: self.birth_mask,
+            survival_mask: self.survival_mask,
+            neighborhood_size: self.neighborhood.size(),
+            birth_range: self.birth_range.0 | (self.birth_range.1 << 16),
+            survival_range: self.survival_range.0 | (self.survival_range.1 << 16),
+            birth_probability: self.birth_probability,
+            survival_probability: self.survival_probability,
+            count_sets: self.count_sets as u32,
+            spread_neighborhood: self.spread_neighborhood as u32,
+            states: self.generation_states().max(2),
+            ..Default::default()
+        }
+    }
+
+    /// Switches to larger than life with the counts and neighborhood of `rule`
+    pub fn apply_rule(&mut self, rule: &Rule) {
+        self.mode = SimulationMode::LargerThanLife;
+        if let Err(e) = self
+            .neighborhood
+            .set_from_key(&rule.neighborhood_key())
+            .and_then(|()| self.neighborhood.set_weights_from_key(""))
+        {
+            log::warn!("{}", e);
+        }
+        self.count_sets = true;
+        self.birth_mask = rule.birth_mask;
+        self.survival_mask = rule.survival_mask;
+        self.states = rule.states;
+        self.birth_probability = 1.0;
+        self.survival_probability = 1.0;
+    }
+
+    /// The larger than life rule as the rule language writes it, if it can be written that way
+    fn current_rule(&self) -> Option<Rule> {
+        if self.mode != SimulationMode::LargerThanLife
+            || !self.count_sets
+            || self.neighborhood.shape == NeighborhoodShape::Custom
+            || !self.neighborhood.weights_key().is_empty()
+        {
+            return None;
+        }
+        Some(Rule {
+            birth_mask: self.birth_mask,
+            survival_mask: self.survival_mask,
+            states: self.states,
+            shape: self.neighborhood.shape,
+            radius: self.neighborhood.radius,
+        })
+    }
+
+    fn current_preset(&self) -> Option<&'static RulePreset> {
+        let rule = self.current_rule()?;
+        PRESETS.iter().find(|preset| preset.rule() == rule)
+    }
+
+    pub fn is_running(&self) -> bool {
+        !self.paused || self.step > 0 || self.step_back
+    }
+
     pub fn update(
         &mut self,
         ctx: &WgpuContext,
         command_encoder: &mut CommandEncoder,
         chunk_manager: &mut ChunkManager,
     ) {
+        self.run_ticks(ctx, command_encoder, chunk_manager);
+        // Also on frames without ticks, so the flags of the last ones before a pause or a step
+        // back are read back once the previous copy came back
+        self.activity.encode_readback(
+            ctx,
+            command_encoder,
+            &self.res.feedback_buffer,
+            chunk_manager,
+            self.tick,
+        );
+    }
+
+    fn run_ticks(
+        &mut self,
+        ctx: &WgpuContext,
+        command_encoder: &mut CommandEncoder,
+        chunk_manager: &mut ChunkManager,
+    ) {
+        self.determinism.update();
+        self.lifetimes.update();
+        self.heat.update();
+        self.probe.update();
+        self.activity.update(chunk_manager);
+        chunk_manager.set_tick(self.tick);
+        self.agents.update(ctx, command_encoder, self.tick);
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(change) = self.shader_reload.poll() {
+            let result = self.reload_pipelines(ctx, &change);
+            self.shader_reload.finish(&change, result);
+        }
+        if self.step_back {
+            self.step_back = false;
+            self.step_backward(ctx, command_encoder, chunk_manager);
+            chunk_manager.set_tick(self.tick);
+            return;
+        }
         if self.paused && self.step == 0 {
+            self.schedule.pause();
+            return;
+        }
+        // Single steps run the iterations right away
+        let scheduled = if self.paused {
+            self.n_iter
+        } else {
+            self.schedule.ticks_this_frame(self.n_iter)
+        };
+        if scheduled == 0 {
             return;
         }
+        // Runs only as many iterations as are left, so the pause lands on the exact tick
+        let n_iter = match self.pause_at {
+            Some(pause_at) if pause_at <= self.tick => {
+                self.paused = true;
+                self.step = 0;
+                self.pause_at = None;
+                return;
+            }
+            Some(pause_at) => (pause_at - self.tick).min(scheduled as u64) as u32,
+            None => scheduled,
+        };
         if self.step > 0 {
             self.step -= 1;
         }
+        self.prepare(ctx, command_encoder, chunk_manager);
+        self.probe
+            .prepare(command_encoder, &self.res.feedback_buffer);
+        let record = self
+            .lifetimes
+            .prepare(ctx, command_encoder, chunk_manager.num_offsets());
+        self.snapshots
+            .capture_if_due(ctx, command_encoder, chunk_manager, self.tick);
+        let mut n_iter = n_iter;
+        if self.determinism.ready() {
+            self.verify_tick(ctx, command_encoder, chunk_manager, record);
+            n_iter -= 1;
+        } else if self.compare_kernels && !self.uses_packed_kernel() {
+            self.compare_kernel_tick(ctx, command_encoder, chunk_manager, record);
+            n_iter -= 1;
+        }
+        if n_iter > 0 {
+            self.dispatch(command_encoder, chunk_manager, n_iter, record);
+            chunk_manager.advance_which(n_iter);
+            self.tick += n_iter as u64;
+        }
+        chunk_manager.set_tick(self.tick);
+        // Neither the packed kernel nor the agents report the chunks they change
+        if self.uses_packed_kernel() || self.agents.active() {
+            chunk_manager.mark_all_modified();
+        }
+        if record {
+            self.lifetimes.encode_readback(command_encoder);
+        }
+        self.probe
+            .encode_readback(command_encoder, &self.res.feedback_buffer);
+        self.heat
+            .encode_readback(ctx, command_encoder, chunk_manager, self.tick);
+        if self.pause_at == Some(self.tick) {
+            self.paused = true;
+            self.step = 0;
+            self.pause_at = None;
+        }
+    }
+
+    /// Uploads everything the dispatch reads besides the chunks themselves
+    fn prepare(
+        &mut self,
+        ctx: &WgpuContext,
+        command_encoder: &mut CommandEncoder,
+        chunk_manager: &ChunkManager,
+    ) {
+        if self.block_rule_dirty {
+            ctx.queue.write_buffer(
+                &self.res.block_rule_buffer,
+                0,
+                bytemuck::cast_slice(&self.block_rule_preset.table()),
+            );
+            self.block_rule_dirty = false;
+        }
+        if let Some(offsets) = self.neighborhood.take_upload() {
+            ctx.staging.write(
+                &ctx.device,
+                &ctx.queue,
+                command_encoder,
+                "simulate neighborhood",
+                &self.res.neighborhood_buffer,
+                0,
+                bytemuck::cast_slice(&offsets),
+            );
+        }
+        let region = self.region_params();
+        if self.uploaded_region != Some(region) {
+            ctx.staging.write(
+                &ctx.device,
+                &ctx.queue,
+                command_encoder,
+                "simulate region",
+                &self.res.region_buffer,
+                0,
+                bytemuck::bytes_of(&region),
+            );
+            self.uploaded_region = Some(region);
+        }
+        let rule = self.rule_config();
+        if self.uploaded_rule != Some(rule) {
+            ctx.staging.write(
+                &ctx.device,
+                &ctx.queue,
+                command_encoder,
+                "simulate rule",
+                &self.res.rule_buffer,
+                0,
+                bytemuck::bytes_of(&rule),
+            );
+            self.uploaded_rule = Some(rule);
+        }
+        self.upload_chunk_info(ctx, command_encoder, chunk_manager);
+        self.heat
+            .prepare(ctx, command_encoder, chunk_manager, self.tick);
+        if self.uses_packed_kernel() {
+            self.ensure_packed_buffers(ctx, chunk_manager.num_offsets());
+        }
+        if self.portals.enabled {
+            // Offsets move around as chunks come and go, so the table follows the chunk info
+            if let Some(table) = self.portals.build_table(chunk_manager) {
+                ctx.queue
+                    .write_buffer(&self.res.portal_buffer, 0, bytemuck::cast_slice(table));
+            }
+        }
+    }
+
+    /// Restores the newest snapshot before the previous tick and simulates forward up to it.
+    /// Rule changes since the snapshot apply to the replayed ticks as well.
+    fn step_backward(
+        &mut self,
+        ctx: &WgpuContext,
+        command_encoder: &mut CommandEncoder,
+        chunk_manager: &mut ChunkManager,
+    ) {
+        let Some(target) = self.tick.checked_sub(1) else {
+            return;
+        };
+        let Some((snapshot_tick, snapshot)) = self.snapshots.latest_before(chunk_manager, target)
+        else {
+            log::warn!("No snapshot at or before tick {} to step back to", target);
+            return;
+        };
+        let snapshot_tick = *snapshot_tick;
+        chunk_manager.restore_snapshot(command_encoder, snapshot);
+        self.tick = snapshot_tick;
+        self.paused = true;
+        self.step = 0;
+
+        let n_iter = (target - snapshot_tick) as u32;
+        if n_iter > 0 {
+            self.prepare(ctx, command_encoder, chunk_manager);
+            // Replayed ticks were recorded the first time around
+            self.dispatch(command_encoder, chunk_manager, n_iter, false);
+            chunk_manager.advance_which(n_iter);
+            self.tick += n_iter as u64;
+        }
+    }
+
+    /// Runs the current tick twice from the same input and has the results compared on the GPU.
+    /// Only the second run records lifetimes.
+    fn verify_tick(
+        &mut self,
+        ctx: &WgpuContext,
+        command_encoder: &mut CommandEncoder,
+        chunk_manager: &mut ChunkManager,
+        record: bool,
+    ) {
+        self.dispatch(command_encoder, chunk_manager, 1, false);
+        chunk_manager.advance_which(1);
+        self.determinism
+            .capture_reference(ctx, command_encoder, chunk_manager, self.tick);
+        // The input is still untouched in the other buffer, so run the same tick again from it
+        chunk_manager.advance_which(1);
+        self.dispatch(command_encoder, chunk_manager, 1, record);
+        chunk_manager.advance_which(1);
+        self.determinism
+            .compare(ctx, command_encoder, chunk_manager);
+        self.tick += 1;
+    }
+
+    /// Runs the current tick with the other kernel first and throws the result away, then runs
+    /// it for real with the selected one, each in its own profiler scope
+    fn compare_kernel_tick(
+        &mut self,
+        ctx: &WgpuContext,
+        command_encoder: &mut CommandEncoder,
+        chunk_manager: &mut ChunkManager,
+        record: bool,
+    ) {
+        for kernel in [self.kernel.other(), self.kernel] {
+            let record = record && kernel == self.kernel;
+            ctx.profiler
+                .profile(command_encoder, kernel.name(), |encoder| {
+                    self.dispatch_kernel(encoder, chunk_manager, 1, kernel, record);
+                });
+        }
+        chunk_manager.advance_which(1);
+        self.tick += 1;
+    }
+
+    pub fn after_submit(&mut self) {
+        self.determinism.after_submit();
+        self.lifetimes.after_submit();
+        self.heat.after_submit();
+        self.probe.after_submit();
+        self.activity.after_submit();
+        self.agents.after_submit();
+    }
+
+    fn ensure_packed_buffers(&mut self, ctx: &WgpuContext, chunks: u32) {
+        if self
+            .packed_buffers
+            .as_ref()
+            .is_some_and(|b| b.chunks >= chunks)
+        {
+            return;
+        }
+        let chunks = chunks.next_power_of_two();
+        let size = chunks as u64 * PACKED_WORDS_PER_CHUNK * size_of::<u32>() as u64;
+        if size > ctx.device.limits().max_storage_buffer_binding_size as u64 {
+            log::warn!(
+                "{} chunks don't fit into a packed buffer, using the unpacked kernel",
+                chunks
+            );
+            self.packed = false;
+            self.packed_buffers = None;
+            return;
+        }
+        self.packed_buffers = Some(PackedBuffers::new(
+            ctx,
+            &self.res.packed.bind_group_layout,
+            chunks,
+        ));
+    }
+
+    // Only rebuilds the chunk info when the chunks or the throttle moved, and only uploads the
+    // entries that changed
+    fn upload_chunk_info(
+        &mut self,
+        ctx: &WgpuContext,
+        command_encoder: &mut CommandEncoder,
+        chunk_manager: &ChunkManager,
+    ) {
+        self.throttle.suspended = self.mode.uses_blocks();
+        let key = (chunk_manager.layout_version(), self.throttle.shift_inputs());
+        if self.chunk_info_key == Some(key) {
+            return;
+        }
+        self.chunk_info_key = Some(key);
+
+        if self
+            .res
+            .ensure_chunk_capacity(ctx, chunk_manager.num_offsets())
+        {
+            self.chunk_info.clear();
+            self.portals.invalidate_table();
+            self.activity.invalidate();
+        }
+
+        // Reuses the allocation from previous frames
+        self.next_chunk_info.clear();
+        let throttle = &self.throttle;
+        self.next_chunk_info
+            .par_extend(
+                chunk_manager
+                    .offset_positions()
+                    .par_iter()
+                    .map(|pos| ChunkInfoEntry {
+                        pos: *pos,
+                        tick_shift: throttle.tick_shift(pos),
+                    }),
+            );
+        let throttled_chunks = self
+            .next_chunk_info
+            .iter()
+            .filter(|c| c.tick_shift > 0)
+            .count();
+        self.throttle.set_throttled_chunks(throttled_chunks);
+
+        // Entries past the end of the old info are all new, entries past the end of the new one
+        // are never read
+        let (old, new) = (&self.chunk_info, &self.next_chunk_info);
+        let dirty = |i: usize| old.get(i) != Some(&new[i]);
+        let mut i = 0;
+        while i < new.len() {
+            if !dirty(i) {
+                i += 1;
+                continue;
+            }
+            let start = i;
+            let mut last_dirty = i;
+            while i < new.len() && i - last_dirty <= CHUNK_INFO_MERGE_GAP {
+                if dirty(i) {
+                    last_dirty = i;
+                }
+                i += 1;
+            }
+            ctx.staging.write(
+                &ctx.device,
+                &ctx.queue,
+                command_encoder,
+                "simulate chunk_info",
+                &self.res.chunk_info_buffer,
+                (start * size_of::<ChunkInfoEntry>()) as u64,
+                bytemuck::cast_slice(&new[start..=last_dirty]),
+            );
+        }
+        std::mem::swap(&mut self.chunk_info, &mut self.next_chunk_info);
+    }
+
+    fn push_constants(&self, chunk_manager: &ChunkManager, i: u32) -> PushConstants {
+        let (probe_voxel, probe_enabled) = self.probe.target();
+        PushConstants {
+            // Only depends on the seed and the tick, so replaying from a snapshot gives the same
+            // result
+            rng: rng::stream_u64(self.seed, self.tick + i as u64),
+            chunks_per_buffer_shift: chunk_manager.chunks_per_group().ilog2(),
+            starting_which: chunk_manager.which() ^ (i & 1),
+            num_chunks: chunk_manager.num_offsets(),
+            mode: self.mode,
+            block_offset: ((self.tick + i as u64) & 1) as u32,
+            portals_enabled: self.portals.enabled as u32,
+            target_which: chunk_manager.which(),
+            tick: (self.tick + i as u64) as u32,
+            record_lifetimes: 0,
+            containment_shape: self.containment.shape as u32,
+            probe_voxel,
+            probe_enabled,
+            containment_center: self.containment.center,
+            containment_radius: self.containment.radius,
+            containment_half_extents: self.containment.half_extents,
+            reaction_diffusion: self.reaction_diffusion,
+            ..Default::default()
+        }
+    }
+
+    /// `record` has the unpacked kernels record lifetimes, the packed one never does
+    fn dispatch(
+        &self,
+        command_encoder: &mut CommandEncoder,
+        chunk_manager: &ChunkManager,
+        n_iter: u32,
+        record: bool,
+    ) {
+        if let Some(packed_buffers) = self
+            .packed_buffers
+            .as_ref()
+            .filter(|b| self.uses_packed_kernel() && b.chunks >= chunk_manager.num_offsets())
+        {
+            self.dispatch_packed(command_encoder, chunk_manager, packed_buffers, n_iter);
+            return;
+        }
+        self.dispatch_kernel(command_encoder, chunk_manager, n_iter, self.kernel, record);
+    }
+
+    fn dispatch_kernel(
+        &self,
+        command_encoder: &mut CommandEncoder,
+        chunk_manager: &ChunkManager,
+        n_iter: u32,
+        kernel: SimulateKernel,
+        record: bool,
+    ) {
         let mut compute_pass = command_encoder.begin_compute_pass(&ComputePassDescriptor {
             label: Some("simulate compute_pass"),
             timestamp_writes: None,
         });
-        compute_pass.set_pipeline(&self.res.pipeline);
 
-        let mut chunk_info = vec![ChunkInfoEntry::default(); chunk_manager.num_offsets() as usize];
-
-        for chunk in chunk_manager.chunks().values() {
-            chunk_info[chunk.offset() as usize] = ChunkInfoEntry {
-                pos: chunk.pos,
-                ..Default::default()
-            };
+        for i in 0..n_iter {
+            let tick = self.tick + i as u64;
+            if self.heat.enabled {
+                self.heat.encode_diffuse(
+                    &mut compute_pass,
+                    chunk_manager,
+                    tick,
+                    chunk_manager.which() ^ (i & 1),
+                );
+            }
+            // Reducing the deaths, diffusing the heat or stepping the agents of the previous tick
+            // switched pipelines
+            if i == 0 || record || self.heat.enabled || self.agents.active() {
+                compute_pass.set_pipeline(&self.res.pipelines[kernel as usize]);
+                compute_pass.set_bind_group(0, &self.res.data_bind_group, &[]);
+                compute_pass.set_bind_group(1, chunk_manager.bind_group(true), &[]);
+                compute_pass.set_bind_group(2, self.lifetimes.bind_group(), &[]);
+            }
+            compute_pass.set_bind_group(3, self.heat.bind_group(tick), &[]);
+            compute_pass.set_push_constants(
+                0,
+                bytemuck::bytes_of(&PushConstants {
+                    record_lifetimes: record as u32,
+                    ..self.push_constants(chunk_manager, i)
+                }),
+            );
+            compute_pass.dispatch_workgroups(
+                chunk_manager.num_offsets(),
+                self.res.workgroups_per_chunk(),
+                1,
+            );
+            if record {
+                self.lifetimes.encode_reduce(&mut compute_pass);
+            }
+            if self.agents.active() {
+                self.agents.encode_step(
+                    &mut compute_pass,
+                    chunk_manager,
+                    tick,
+                    chunk_manager.which() ^ (i & 1) ^ 1,
+                );
+            }
         }
+    }
 
-        ctx.queue.write_buffer(
-            &self.res.chunk_info_buffer,
-            0,
-            bytemuck::cast_slice(&chunk_info),
-        );
-
+    /// Packs the chunks into buffer 0, runs the ticks between the packed buffers and unpacks the
+    /// result into the texture `dispatch` would have left it in, so the rest of the frame doesn't
+    /// see a difference
+    fn dispatch_packed(
+        &self,
+        command_encoder: &mut CommandEncoder,
+        chunk_manager: &ChunkManager,
+        packed_buffers: &PackedBuffers,
+        n_iter: u32,
+    ) {
+        let mut compute_pass = command_encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("simulate packed_compute_pass"),
+            timestamp_writes: None,
+        });
         compute_pass.set_bind_group(0, &self.res.data_bind_group, &[]);
         compute_pass.set_bind_group(1, chunk_manager.bind_group(true), &[]);
+        let workgroups_per_chunk = (CHUNK_SIZE / 2 / PACKED_WORKGROUP_SIZE).pow(3);
+        let consts = self.push_constants(chunk_manager, 0);
 
-        for i in 0..self.n_iter {
+        // The second bind group writes buffer 0
+        compute_pass.set_pipeline(&self.res.packed.pack);
+        compute_pass.set_bind_group(2, &packed_buffers.bind_groups[1], &[]);
+        compute_pass.set_push_constants(0, bytemuck::bytes_of(&consts));
+        compute_pass.dispatch_workgroups(chunk_manager.num_offsets(), workgroups_per_chunk, 1);
+
+        compute_pass.set_pipeline(&self.res.packed.step);
+        for i in 0..n_iter {
+            compute_pass.set_bind_group(2, &packed_buffers.bind_groups[(i & 1) as usize], &[]);
             compute_pass.set_push_constants(
                 0,
-                bytemuck::bytes_of(&PushConstants {
-                    rng: rand::random(),
-                    chunks_per_buffer_shift: chunk_manager.chunks_per_group().ilog2(),
-                    starting_which: chunk_manager.which() ^ (i & 1),
-                    num_chunks: chunk_manager.num_offsets(),
-                }),
+                bytemuck::bytes_of(&self.push_constants(chunk_manager, i)),
             );
-            compute_pass.dispatch_workgroups(chunk_manager.num_offsets(), 512, 1);
+            compute_pass.dispatch_workgroups(chunk_manager.num_offsets(), workgroups_per_chunk, 1);
         }
 
-        drop(compute_pass);
-        chunk_manager.advance_which(self.n_iter);
+        // Colors of cells that stay filled come from the textures as they were before the ticks
+        compute_pass.set_pipeline(&self.res.packed.unpack);
+        compute_pass.set_bind_group(2, &packed_buffers.bind_groups[(n_iter & 1) as usize], &[]);
+        compute_pass.set_push_constants(
+            0,
+            bytemuck::bytes_of(&PushConstants {
+                target_which: chunk_manager.which() ^ (n_iter & 1),
+                ..consts
+            }),
+        );
+        compute_pass.dispatch_workgroups(chunk_manager.num_offsets(), workgroups_per_chunk, 1);
     }
 
-    pub fn ui(&mut self, ui: &mut egui::Ui, _elp: &EventLoopProxy<UserEvent>) {
+    pub fn set_workgroup_size(
+        &mut self,
+        ctx: &WgpuContext,
+        chunk_manager: &ChunkManager,
+        workgroup_size: u32,
+    ) {
+        if workgroup_size != self.res.workgroup_size {
+            self.res = Resources::new(
+                ctx,
+                chunk_manager,
+                &self.lifetimes,
+                &self.heat,
+                workgroup_size,
+            );
+            self.packed_buffers = None;
+            self.block_rule_dirty = true;
+            self.activity.invalidate();
+            #[cfg(not(target_arch = "wasm32"))]
+            self.shader_reload.invalidate();
+            self.neighborhood.invalidate();
+            self.chunk_info_key = None;
+            self.chunk_info.clear();
+            self.portals.invalidate_table();
+        }
+    }
+
+    /// Swaps the simulation pipelines for ones built from the hot reloaded shader, keeping the
+    /// current ones if it doesn't compile
+    #[cfg(not(target_arch = "wasm32"))]
+    fn reload_pipelines(&mut self, ctx: &WgpuContext, change: &ShaderChange) -> Result<(), String> {
+        let source = match change {
+            ShaderChange::File(source) => source.as_str(),
+            ShaderChange::Builtin => include_str!("simulate.wgsl"),
+        };
+        ctx.device.push_error_scope(ErrorFilter::Validation);
+        let pipelines = Resources::create_pipelines(
+            ctx,
+            &self.res.pipeline_layout,
+            self.res.workgroup_size,
+            source,
+        );
+        if let Some(error) = pollster::block_on(ctx.device.pop_error_scope()) {
+            return Err(error.to_string());
+        }
+        self.res.pipelines = pipelines?;
+        Ok(())
+    }
+
+    /// Records a single tick that writes into the inactive buffer without advancing the world
+    pub fn encode_benchmark(
+        &mut self,
+        ctx: &WgpuContext,
+        command_encoder: &mut CommandEncoder,
+        chunk_manager: &ChunkManager,
+    ) {
+        self.upload_chunk_info(ctx, command_encoder, chunk_manager);
+        self.dispatch(command_encoder, chunk_manager, 1, false);
+    }
+
+    pub fn submission_in_flight(&self) -> bool {
+        self.in_flight.load(Ordering::Acquire)
+    }
+
+    pub fn submit(&self, ctx: &WgpuContext, command_encoder: CommandEncoder) {
+        self.in_flight.store(true, Ordering::Release);
+        ctx.submit([command_encoder.finish()]);
+        let in_flight = self.in_flight.clone();
+        ctx.queue.on_submitted_work_done(move || {
+            in_flight.store(false, Ordering::Release);
+        });
+    }
+
+    /// What meshing should treat as the surface of the concentration field, 0 when cells are
+    /// colors
+    pub fn iso_level(&self) -> f32 {
+        if self.mode == SimulationMode::ReactionDiffusion {
+            self.iso_level.max(f32::EPSILON)
+        } else {
+            0.0
+        }
+    }
+
+    /// States of the generations rule the cells follow, 0 when they aren't decaying cells
+    pub fn generation_states(&self) -> u32 {
+        match self.mode {
+            SimulationMode::Life2d | SimulationMode::LargerThanLife => self.states,
+            _ => 0,
+        }
+    }
+
+    /// Whether chunks without live cells stay empty until cells from a neighbor get there, which
+    /// is what allows dropping them
+    pub fn empty_stays_empty(&self) -> bool {
+        let births_from_nothing = match self.mode {
+            SimulationMode::ReactionDiffusion => true,
+            SimulationMode::LargerThanLife if self.count_sets => self.birth_mask & 1 != 0,
+            SimulationMode::LargerThanLife => self.birth_range.0 == 0,
+            _ => self.birth_mask & 1 != 0,
+        };
+        // Portals and agents reach chunks that aren't neighbors
+        !births_from_nothing && !self.portals.enabled && !self.agents.active()
+    }
+
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    pub fn seed(&self) -> u32 {
+        self.seed
+    }
+
+    /// Ticks only depend on the seed and the tick number, so a fixed seed replays the same run
+    pub fn set_seed(&mut self, seed: u32) {
+        self.seed = seed;
+    }
+
+    pub fn set_tick(&mut self, tick: u64) {
+        self.tick = tick;
+        self.pause_at = None;
+    }
+
+    /// Runs `ticks` more ticks from the current one, then pauses
+    pub fn run_for(&mut self, ticks: u64) {
+        self.pause_at_tick(self.tick.saturating_add(ticks));
+    }
+
+    /// Runs until `tick` and pauses there, does nothing if it has already passed
+    pub fn pause_at_tick(&mut self, tick: u64) {
+        if tick <= self.tick {
+            log::warn!("Tick {} has already passed", tick);
+            return;
+        }
+        self.pause_at = Some(tick);
+        self.paused = false;
+    }
+
+    pub fn pause_at(&self) -> Option<u64> {
+        self.pause_at
+    }
+
+    /// Writes the rule settings, as stored in presets and saved worlds
+    pub fn write_meta(&self, meta: &mut BTreeMap<String, String>) {
+        let mut set = |key: &str, value: String| {
+            meta.insert(format!("simulate.{}", key), value);
+        };
+        set("mode", self.mode.key().to_owned());
+        set("iterations", self.n_iter.to_string());
+        set("birth_mask", self.birth_mask.to_string());
+        set("survival_mask", self.survival_mask.to_string());
+        set("birth_probability", self.birth_probability.to_string());
+        set(
+            "survival_probability",
+            self.survival_probability.to_string(),
+        );
+        set("neighborhood", self.neighborhood.key());
+        let rd = &self.reaction_diffusion;
+        set(
+            "gray_scott",
+            format!(
+                "{} {} {} {}",
+                rd.feed, rd.kill, rd.diffusion_u, rd.diffusion_v
+            ),
+        );
+        set("iso_level", self.iso_level.to_string());
+        set("neighborhood_weights", self.neighborhood.weights_key());
+        set(
+            "birth_range",
+            format!("{}-{}", self.birth_range.0, self.birth_range.1),
+        );
+        set(
+            "survival_range",
+            format!("{}-{}", self.survival_range.0, self.survival_range.1),
+        );
+        set("count_sets", self.count_sets.to_string());
+        set("states", self.states.to_string());
+        set("spread_neighborhood", self.spread_neighborhood.to_string());
+        set("block_rule", format!("{:?}", self.block_rule_preset));
+    }
+
+    /// Applies the settings written by `write_meta`, missing or invalid entries are left as is
+    pub fn read_meta(&mut self, meta: &BTreeMap<String, String>) {
+        let get = |key: &str| meta.get(&format!("simulate.{}", key));
+        if let Some(mode) = get("mode") {
+            match SimulationMode::from_key(mode) {
+                Some(mode) => self.mode = mode,
+                None => log::warn!("Unknown simulation mode {:?}", mode),
+            }
+        }
+        if let Some(n_iter) = get("iterations").and_then(|v| v.parse::<u32>().ok()) {
+            self.n_iter = n_iter.clamp(1, MAX_ITERATIONS);
+        }
+        if let Some(mask) = get("birth_mask").and_then(|v| v.parse().ok()) {
+            self.birth_mask = mask;
+        }
+        if let Some(mask) = get("survival_mask").and_then(|v| v.parse().ok()) {
+            self.survival_mask = mask;
+        }
+        if let Some(p) = get("birth_probability").and_then(|v| v.parse::<f32>().ok()) {
+            self.birth_probability = p.clamp(0.0, 1.0);
+        }
+        if let Some(p) = get("survival_probability").and_then(|v| v.parse::<f32>().ok()) {
+            self.survival_probability = p.clamp(0.0, 1.0);
+        }
+        if let Some(params) = get("gray_scott") {
+            let params = params
+                .split_whitespace()
+                .map(|v| v.parse::<f32>())
+                .collect::<Result<Vec<_>, _>>();
+            match params.as_deref() {
+                Ok(&[feed, kill, diffusion_u, diffusion_v]) => {
+                    self.reaction_diffusion = GrayScott {
+                        feed,
+                        kill,
+                        diffusion_u,
+                        diffusion_v,
+                    };
+                }
+                _ => log::warn!("Invalid Gray-Scott parameters {:?}", params),
+            }
+        }
+        if let Some(level) = get("iso_level").and_then(|v| v.parse::<f32>().ok()) {
+            self.iso_level = level.clamp(0.0, 1.0);
+        }
+        if let Some(neighborhood) = get("neighborhood") {
+            if let Err(e) = self.neighborhood.set_from_key(neighborhood) {
+                log::warn!("{}", e);
+            }
+        }
+        if let Some(weights) = get("neighborhood_weights") {
+            if let Err(e) = self.neighborhood.set_weights_from_key(weights) {
+                log::warn!("{}", e);
+            }
+        }
+        let parse_range = |v: &String| {
+            let (min, max) = v.split_once('-')?;
+            Some((min.parse().ok()?, max.parse().ok()?))
+        };
+        if let Some(range) = get("birth_range").and_then(parse_range) {
+            self.birth_range = range;
+        }
+        if let Some(range) = get("survival_range").and_then(parse_range) {
+            self.survival_range = range;
+        }
+        if let Some(count_sets) = get("count_sets").and_then(|v| v.parse().ok()) {
+            self.count_sets = count_sets;
+        }
+        if let Some(states) = get("states").and_then(|v| v.parse::<u32>().ok()) {
+            self.states = states.clamp(2, MAX_STATES);
+        }
+        if let Some(spread) = get("spread_neighborhood").and_then(|v| v.parse().ok()) {
+            self.spread_neighborhood = spread;
+        }
+        if let Some(preset) = get("block_rule") {
+            if let Some(preset) = BlockRulePreset::ALL
+                .into_iter()
+                .find(|p| format!("{:?}", p) == *preset)
+            {
+                self.block_rule_preset = preset;
+                self.block_rule_dirty = true;
+            }
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui, elp: &EventLoopProxy<UserEvent>) {
         ui.collapsing("Simulate", |ui| {
-            ui.add(egui::Slider::new(&mut self.n_iter, 1..=1024).text("Iterations"));
+            ui.add(egui::Slider::new(&mut self.n_iter, 1..=MAX_ITERATIONS).text("Iterations"));
+            self.schedule.ui(ui, &mut self.n_iter);
             ui.add(egui::Checkbox::new(&mut self.paused, "Pause"));
+            ui.add_enabled(
+                self.selection.active,
+                egui::Checkbox::new(&mut self.only_in_selection, "Only inside the selection"),
+            )
+            .on_hover_text(
+                "Cells outside of the selection are frozen, they still count as neighbors. Cells \
+                 moving across its edge can be duplicated or lost.",
+            );
+            ui.horizontal(|ui| {
+                if ui
+                    .button("Step back")
+                    .on_hover_text("Comma, replays from the last snapshot")
+                    .clicked()
+                {
+                    self.step_back = true;
+                }
+                if ui.button("Step").on_hover_text("I").clicked() {
+                    self.step = 1;
+                }
+                ui.label(format!("Tick {}", self.tick));
+                if let Some(pause_at) = self.pause_at {
+                    ui.label(format!("(pausing at {})", pause_at));
+                    if ui.small_button("Cancel").clicked() {
+                        self.pause_at = None;
+                    }
+                }
+            });
+            ui.horizontal(|ui| {
+                if ui.button("Run for").clicked() {
+                    self.run_for(self.run_ticks_input);
+                }
+                ui.add(
+                    egui::DragValue::new(&mut self.run_ticks_input)
+                        .clamp_range(1..=u64::MAX)
+                        .suffix(" ticks"),
+                );
+            });
+            ui.horizontal(|ui| {
+                if ui.button("Pause at tick").clicked() {
+                    self.pause_at_tick(self.pause_at_input);
+                }
+                ui.add(egui::DragValue::new(&mut self.pause_at_input));
+            });
+            self.snapshots.ui(ui);
+            self.determinism.ui(ui);
+            self.lifetimes.ui(ui);
+            self.throttle.ui(ui);
+            self.heat.ui(ui);
+            self.agents.ui(ui);
+            #[cfg(not(target_arch = "wasm32"))]
+            self.shader_reload.ui(ui);
+            ui.horizontal(|ui| {
+                ui.label("Kernel");
+                for kernel in SimulateKernel::ALL {
+                    ui.radio_value(&mut self.kernel, kernel, kernel.name());
+                }
+            });
+            ui.checkbox(&mut self.compare_kernels, "Compare kernels")
+                .on_hover_text(
+                    "Runs the first tick of every frame with both kernels and keeps the selected \
+                     one's result, see the profiler",
+                );
+            ui.add(egui::Checkbox::new(
+                &mut self.separate_submission,
+                "Separate submission",
+            ));
+            self.rule_preset_ui(ui, elp);
+            ui.horizontal(|ui| {
+                let prev_mode = self.mode;
+                ui.radio_value(&mut self.mode, SimulationMode::Spread3d, "3D spread");
+                ui.radio_value(&mut self.mode, SimulationMode::Life2d, "2D life");
+                ui.radio_value(&mut self.mode, SimulationMode::Margolus, "Margolus");
+                ui.radio_value(
+                    &mut self.mode,
+                    SimulationMode::LargerThanLife,
+                    "Larger than life",
+                );
+                ui.radio_value(&mut self.mode, SimulationMode::Powder, "Powder");
+                ui.radio_value(
+                    &mut self.mode,
+                    SimulationMode::ReactionDiffusion,
+                    "Reaction-diffusion",
+                )
+                .on_hover_text(
+                    "Gray-Scott on two concentrations per cell. Cells from other modes are read \
+                     as concentrations, which seeds the reaction.",
+                );
+                let plane = self.mode == SimulationMode::Life2d;
+                if (prev_mode == SimulationMode::Life2d) != plane {
+                    let _ = elp.send_event(UserEvent::RequestPlaneMode(plane));
+                }
+            });
+            if self.mode == SimulationMode::Spread3d {
+                ui.checkbox(&mut self.spread_neighborhood, "Spread through neighborhood")
+                    .on_hover_text("Instead of only the 6 cells sharing a face");
+                if self.spread_neighborhood {
+                    self.neighborhood.ui(ui);
+                }
+            } else if self.mode == SimulationMode::Life2d {
+                Self::neighbor_mask_ui(ui, "Birth", &mut self.birth_mask, 8);
+                Self::neighbor_mask_ui(ui, "Survival", &mut self.survival_mask, 8);
+                self.states_ui(ui);
+                self.probability_ui(ui);
+            } else if self.mode == SimulationMode::LargerThanLife {
+                self.neighborhood.ui(ui);
+                let size = self.neighborhood.max_count();
+                ui.checkbox(&mut self.count_sets, "Count sets")
+                    .on_hover_text("Pick every count that leads to a birth or survival");
+                if self.count_sets {
+                    // The masks only have room for counts up to 31
+                    let size = size.min(u32::BITS - 1);
+                    Self::neighbor_mask_ui(ui, "Birth", &mut self.birth_mask, size);
+                    Self::neighbor_mask_ui(ui, "Survival", &mut self.survival_mask, size);
+                } else {
+                    Self::neighbor_range_ui(ui, "Birth", &mut self.birth_range, size);
+                    Self::neighbor_range_ui(ui, "Survival", &mut self.survival_range, size);
+                }
+                self.states_ui(ui);
+                self.probability_ui(ui);
+            } else if self.mode == SimulationMode::ReactionDiffusion {
+                self.reaction_diffusion_ui(ui);
+            } else if self.mode == SimulationMode::Powder {
+                ui.label(
+                    "Paint materials with the brush. Sand and water fall, fire and smoke rise and \
+                     burn out, other colors stay where they are.",
+                );
+            } else if self.mode == SimulationMode::Margolus {
+                egui::ComboBox::from_label("Block rule")
+                    .selected_text(self.block_rule_preset.name())
+                    .show_ui(ui, |ui| {
+                        for preset in BlockRulePreset::ALL {
+                            if ui
+                                .selectable_value(
+                                    &mut self.block_rule_preset,
+                                    preset,
+                                    preset.name(),
+                                )
+                                .changed()
+                            {
+                                self.block_rule_dirty = true;
+                            }
+                        }
+                    });
+                ui.add_enabled(
+                    !self.portals.enabled,
+                    egui::Checkbox::new(&mut self.packed, "Packed kernel"),
+                )
+                .on_hover_text(
+                    "Simulates 2x2x2 blocks of binary cells per word. Moving cells lose their \
+                     color and portals aren't supported.",
+                );
+            }
+        });
+    }
+
+    fn states_ui(&mut self, ui: &mut egui::Ui) {
+        ui.add(egui::Slider::new(&mut self.states, 2..=MAX_STATES).text("States"))
+            .on_hover_text(
+                "Cells that stop surviving fade through the states past alive before they die, \
+                 and only live cells count as neighbors",
+            );
+    }
+
+    fn rule_preset_ui(&mut self, ui: &mut egui::Ui, elp: &EventLoopProxy<UserEvent>) {
+        let mut picked = None;
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_label("Rule preset")
+                .selected_text(self.current_preset().map_or("Custom", |preset| preset.name))
+                .show_ui(ui, |ui| {
+                    for preset in &PRESETS {
+                        if ui
+                            .selectable_label(false, preset.name)
+                            .on_hover_text(preset.notation)
+                            .clicked()
+                        {
+                            picked = Some(preset.rule());
+                        }
+                    }
+                });
+            ui.checkbox(&mut self.reseed_on_preset, "Reseed");
+        });
+        ui.horizontal(|ui| {
+            // The hint shows the current rule in the rule language
+            let hint = self
+                .current_rule()
+                .map_or_else(|| "B6-8/S5-7/C10/M".to_owned(), |rule| rule.to_string());
+            let response = ui
+                .add(egui::TextEdit::singleline(&mut self.rule_input).hint_text(hint))
+                .on_hover_text(
+                    "Counts a cell is born and survives with after B and S, like 4,6-8, the \
+                     number of states after C, then M or N for the 26 or 6 cells around a cell, \
+                     followed by a radius for larger ones. Survival/birth/states/neighborhood \
+                     works too.",
+                );
+            if response.changed() {
+                self.rule_error = None;
+            }
+            let entered = response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+            if ui.button("Apply rule").clicked() || entered {
+                match rule_parser::parse_rule(&self.rule_input) {
+                    Ok(rule) => picked = Some(rule),
+                    Err(e) => self.rule_error = Some(e),
+                }
+            }
+        });
+        if let Some(error) = &self.rule_error {
+            ui.colored_label(ui.visuals().error_fg_color, error);
+        }
+        let Some(rule) = picked else {
+            return;
+        };
+        let was_plane = self.mode == SimulationMode::Life2d;
+        self.apply_rule(&rule);
+        // Leaving the plane seeds a new world anyway
+        if was_plane {
+            let _ = elp.send_event(UserEvent::RequestPlaneMode(false));
+        } else if self.reseed_on_preset {
+            let _ = elp.send_event(UserEvent::RequestReseed);
+        }
+    }
+
+    fn neighbor_mask_ui(ui: &mut egui::Ui, label: &str, mask: &mut u32, max: u32) {
+        ui.horizontal_wrapped(|ui| {
+            ui.label(label);
+            for n in 0..=max {
+                let mut set = *mask & (1 << n) != 0;
+                if ui.toggle_value(&mut set, n.to_string()).changed() {
+                    *mask ^= 1 << n;
+                }
+            }
+        });
+    }
+
+    fn reaction_diffusion_ui(&mut self, ui: &mut egui::Ui) {
+        let rd = &mut self.reaction_diffusion;
+        let current = GrayScottPreset::ALL
+            .into_iter()
+            .find(|p| p.params() == *rd)
+            .map_or("Custom", |p| p.name());
+        egui::ComboBox::from_label("Preset")
+            .selected_text(current)
+            .show_ui(ui, |ui| {
+                for preset in GrayScottPreset::ALL {
+                    if ui.selectable_label(false, preset.name()).clicked() {
+                        *rd = preset.params();
+                    }
+                }
+            });
+        ui.add(
+            egui::Slider::new(&mut rd.feed, 0.0..=0.1)
+                .max_decimals(4)
+                .text("Feed"),
+        );
+        ui.add(
+            egui::Slider::new(&mut rd.kill, 0.0..=0.1)
+                .max_decimals(4)
+                .text("Kill"),
+        );
+        ui.add(egui::Slider::new(&mut rd.diffusion_u, 0.0..=1.0 / 6.0).text("Diffusion u"));
+        ui.add(egui::Slider::new(&mut rd.diffusion_v, 0.0..=1.0 / 6.0).text("Diffusion v"));
+        ui.add(egui::Slider::new(&mut self.iso_level, 0.01..=1.0).text("Iso level"))
+            .on_hover_text("Cells with at least this much v are drawn");
+    }
+
+    fn probability_ui(&mut self, ui: &mut egui::Ui) {
+        ui.add(egui::Slider::new(&mut self.birth_probability, 0.0..=1.0).text("Birth probability"));
+        ui.add(
+            egui::Slider::new(&mut self.survival_probability, 0.0..=1.0)
+                .text("Survival probability"),
+        )
+        .on_hover_text("Cells that fail the roll die even though the rule lets them survive");
+    }
+
+    fn neighbor_range_ui(ui: &mut egui::Ui, label: &str, range: &mut (u32, u32), size: u32) {
+        ui.horizontal(|ui| {
+            ui.label(label);
+            ui.add(egui::DragValue::new(&mut range.0).clamp_range(0..=size));
+            ui.label("to");
+            ui.add(egui::DragValue::new(&mut range.1).clamp_range(range.0..=size));
+            ui.label("neighbors");
         });
     }
 }
@@ -1,35 +1,195 @@
+#include "common.wgsl"
+#include "cell_state.wgsl"
+#include "rng.wgsl"
+
 struct PushConstants {
     @size(4) rng: u32,
     @size(4) chunks_per_buffer_shift: u32,
     @size(4) starting_which: u32,
     @size(4) num_chunks: u32,
+    @size(4) mode: u32,
+    @size(4) block_offset: u32,
+    @size(4) portals_enabled: u32,
+    // Buffer the packed kernel unpacks into
+    @size(4) target_which: u32,
+    // Low bits of the tick, for chunks that only run some ticks
+    @size(4) tick: u32,
+    // Nonzero to keep cell ages and append deaths for the lifetime histogram
+    @size(4) record_lifetimes: u32,
+    // Cells outside of the boundary are killed, in world voxel coordinates
+    @size(8) containment_shape: u32,
+    // Cell that writes its rule evaluation to the probe buffer when probe_enabled is nonzero
+    probe_voxel: vec3<i32>,
+    probe_enabled: u32,
+    containment_center: vec3<f32>,
+    containment_radius: f32,
+    containment_half_extents: vec3<f32>,
+    // Gray-Scott rates per tick
+    rd_feed: f32,
+    rd_kill: f32,
+    rd_diffusion_u: f32,
+    rd_diffusion_v: f32,
 }
 
+// The tiled kernel loads the cells around the workgroup into shared memory once, the direct
+// kernel reads every neighbor from the chunk textures
+const TILED: bool = {{TILED}};
+const WG_SIZE: u32 = {{WG_SIZE}}u;
+const WG_PER_AXIS: u32 = CHUNK_SIZE_U / WG_SIZE;
+const WG_PER_CHUNK: u32 = WG_PER_AXIS * WG_PER_AXIS * WG_PER_AXIS;
+const TILE_SIZE: u32 = WG_SIZE + 2u;
+const TILE_VOLUME: u32 = TILE_SIZE * TILE_SIZE * TILE_SIZE;
+const TILE_STRIDE: vec3<u32> = vec3<u32>(1u, TILE_SIZE, TILE_SIZE * TILE_SIZE);
+
+const MODE_SPREAD_3D: u32 = 0u;
+const MODE_LIFE_2D: u32 = 1u;
+const MODE_MARGOLUS: u32 = 2u;
+const MODE_LARGER_THAN_LIFE: u32 = 3u;
+const MODE_REACTION_DIFFUSION: u32 = 4u;
+const MODE_POWDER: u32 = 5u;
+
+// Chance per tick of fire turning into smoke and of smoke clearing
+const FIRE_BURN_OUT: u32 = 0x18000000u;
+const SMOKE_CLEAR: u32 = 0x04000000u;
+
+const CONTAIN_OFF: u32 = 0u;
+const CONTAIN_SPHERE: u32 = 1u;
+const CONTAIN_BOX: u32 = 2u;
+
 struct ChunkInfoEntry {
-    @size(16) chunk_pos: vec3<i32>,
+    chunk_pos: vec3<i32>,
+    // The chunk only runs ticks that are a multiple of 1 << tick_shift and keeps its cells in
+    // the others
+    tick_shift: u32,
+}
+
+const PORTAL_FLIP_U: u32 = 1u;
+const PORTAL_FLIP_V: u32 = 2u;
+const PORTAL_SWAP_UV: u32 = 4u;
+
+struct PortalEntry {
+    // Offset + 1 of the linked chunk, 0 when the face isn't linked
+    @size(4) target_chunk: u32,
+    @size(4) face: u32,
+    @size(8) flags: u32,
 }
 
 var<push_constant> consts: PushConstants;
 
 @group(0) @binding(0)
-var<storage, read_write> chunks: array<ChunkInfoEntry>;
+var<storage, read> chunks: array<ChunkInfoEntry>;
+
+@group(0) @binding(1)
+var<storage, read> block_rules: array<u32, 256>;
+
+@group(0) @binding(2)
+var<storage, read> portals: array<PortalEntry>;
+
+// Offsets of the cells the larger than life rule counts, at most two cells away on every axis,
+// with the weight a live cell there adds to the count in w
+@group(0) @binding(3)
+var<storage, read> neighborhood: array<vec4<i32>>;
+
+struct Region {
+    min: vec3<i32>,
+    enabled: u32,
+    // Inclusive
+    max: vec3<i32>,
+}
+
+// Cells outside of the box keep their state when enabled, in world voxel coordinates
+@group(0) @binding(4)
+var<uniform> region: Region;
+
+struct RuleConfig {
+    birth_mask: u32,
+    survival_mask: u32,
+    // Offsets in the neighborhood buffer, read by the larger than life rule
+    neighborhood_size: u32,
+    // Inclusive weighted neighbor count ranges, min in the low 16 bits
+    birth_range: u32,
+    survival_range: u32,
+    // Chance of a birth or survival the rule decided on happening, 1 to always follow the rule
+    birth_probability: f32,
+    survival_probability: f32,
+    // Nonzero to check the larger than life count against the masks instead of the ranges
+    count_sets: u32,
+    // Nonzero for the spread rule to read the neighborhood buffer instead of the 6 face neighbors
+    spread_neighborhood: u32,
+    // Generations state count, 2 when cells die at once
+    @size(12) states: u32,
+}
+
+@group(0) @binding(5)
+var<uniform> rule: RuleConfig;
+
+// Flags of the probe result, the outcome is only written once the cell was simulated
+const PROBE_SIMULATED: u32 = 1u;
+const PROBE_COUNTED: u32 = 2u;
+const PROBE_BORN: u32 = 4u;
+const PROBE_SURVIVES: u32 = 8u;
+
+struct ProbeResult {
+    flags: u32,
+    tick: u32,
+    before: u32,
+    after: u32,
+    // Live neighbors, or the sum of their weights for larger than life
+    count: i32,
+    newest: u32,
+}
+
+// What the simulation reports back, in one buffer to stay within the storage buffer limit
+struct Feedback {
+    probe: ProbeResult,
+    // Nonzero for every chunk offset a cell changed in since the flags were last cleared
+    chunk_changed: array<atomic<u32>>,
+}
+
+@group(0) @binding(6)
+var<storage, read_write> feedback: Feedback;
 
 @group(1) @binding(0)
-var atlas: texture_storage_3d<r32uint, read>;
+var atlas: texture_storage_3d<{{CHUNK_FORMAT}}, read>;
 
 @group(1) @binding(1)
-var grids: binding_array<texture_storage_3d<r32uint, read_write>, 8>;
+var grids: binding_array<texture_storage_3d<{{CHUNK_FORMAT}}, read_write>, 8>;
+
+const DEATH_CAPACITY: u32 = {{DEATH_CAPACITY}}u;
+const MAX_AGE: u32 = 0xFFFFu;
+
+struct Deaths {
+    count: atomic<u32>,
+    ages: array<u32>,
+}
+
+// Ticks every cell has been alive for, two 16 bit ages per word indexed like the chunk info
+@group(2) @binding(0)
+var<storage, read_write> ages: array<atomic<u32>>;
+
+@group(2) @binding(1)
+var<storage, read_write> deaths: Deaths;
 
-fn hash(in: u32) -> u32 {
-    var x = in;
-    x += x << 10u;
-    x ^= x >>  6u;
-    x += x <<  3u;
-    x ^= x >> 11u;
-    x += x << 15u;
-    return x;
+struct HeatParams {
+    emission: f32,
+    cooling: f32,
+    diffusion: f32,
+    enabled: u32,
+    // Cells aren't born or don't survive where it is hotter than this
+    birth_max: f32,
+    survival_max: f32,
 }
 
+const HEAT_CELL_SIZE: u32 = {{HEAT_CELL_SIZE}}u;
+const HEAT_PER_AXIS: u32 = CHUNK_SIZE_U / HEAT_CELL_SIZE;
+
+// Temperature per heat cell, diffused right before this tick, indexed like the chunk info
+@group(3) @binding(0)
+var<storage, read> temperature: array<f32>;
+
+@group(3) @binding(1)
+var<uniform> heat: HeatParams;
+
 var<private> dirs: array<vec3<i32>, 6> = array<vec3<i32>, 6>(
     vec3<i32>(1, 0, 0),
     vec3<i32>(-1, 0, 0),
@@ -39,15 +199,405 @@ var<private> dirs: array<vec3<i32>, 6> = array<vec3<i32>, 6>(
     vec3<i32>(0, 0, -1)
 );
 
+// Maps a position just outside `face` of the current chunk to the matching position inside the
+// chunk behind the linked face, at the same depth
+fn through_portal(pos: vec3<i32>, face: u32, portal: PortalEntry) -> vec3<i32> {
+    let axis = face / 2u;
+    var depth = -1 - pos[axis];
+    if((face & 1u) == 0u) {
+        depth = pos[axis] - CHUNK_SIZE;
+    }
+    var u = pos[(axis + 1u) % 3u];
+    var v = pos[(axis + 2u) % 3u];
+    if((portal.flags & PORTAL_SWAP_UV) != 0u) {
+        let t = u;
+        u = v;
+        v = t;
+    }
+    if((portal.flags & PORTAL_FLIP_U) != 0u) {
+        u = CHUNK_SIZE - 1 - u;
+    }
+    if((portal.flags & PORTAL_FLIP_V) != 0u) {
+        v = CHUNK_SIZE - 1 - v;
+    }
+
+    let target_axis = portal.face / 2u;
+    var mapped = vec3<i32>(0);
+    mapped[target_axis] = depth;
+    if((portal.face & 1u) == 0u) {
+        mapped[target_axis] = CHUNK_SIZE - 1 - depth;
+    }
+    mapped[(target_axis + 1u) % 3u] = u;
+    mapped[(target_axis + 2u) % 3u] = v;
+    return mapped;
+}
+
 struct Shared {
-    loaded: array<u32, 1000>,
+    loaded: array<u32, {{TILE_VOLUME}}>,
     neighbor: array<u32, 27>,
 }
 
 var<workgroup> workgroup_shared: Shared;
+// Set by the invocations whose cell changed, one of them reports it for the whole workgroup
+var<workgroup> workgroup_changed: atomic<u32>;
+
+// Chunk and workgroup position of the invocation, for loads outside of the shared tile
+var<private> current_chunk_idx: u32;
+var<private> current_wg_pos: vec3<u32>;
+// Heat where the cell is, 0 without the heat field
+var<private> current_temperature: f32;
+// Whether this invocation is the probed cell
+var<private> probing: bool;
+
+// Offset + 1 of the chunk at `outside`, -1 to 1 on every axis from the current chunk
+fn neighbor_chunk(outside: vec3<i32>) -> u32 {
+    if(TILED) {
+        return workgroup_shared.neighbor[dot(vec3<i32>(1, 3, 9), outside + vec3<i32>(1, 1, 1))];
+    }
+    return textureLoad(atlas, chunks[current_chunk_idx].chunk_pos + outside + vec3<i32>(ATLAS_OFFSET)).r;
+}
+
+// Cell at `chunk_pos` in the current chunk's coordinates, up to a chunk outside of it on every
+// axis
+fn load_cell(chunk_pos: vec3<i32>) -> u32 {
+    var pos = chunk_pos;
+    let outside = extractBits(pos, CHUNK_SHIFT, 32u - CHUNK_SHIFT);
+    var neighbor = neighbor_chunk(outside);
+    // Only positions across a single face go through portals, edges and corners keep the
+    // regular neighbor
+    if(consts.portals_enabled != 0u && dot(abs(outside), vec3<i32>(1)) == 1) {
+        let axis = select(select(2u, 1u, outside.y != 0), 0u, outside.x != 0);
+        let face = axis * 2u + select(0u, 1u, outside[axis] < 0);
+        let portal = portals[current_chunk_idx * 6u + face];
+        if(portal.target_chunk != 0u) {
+            neighbor = portal.target_chunk;
+            pos = through_portal(pos, face, portal);
+        }
+    }
+    if(neighbor == 0u) {
+        return 0u;
+    }
+    let chunk_idx = neighbor - 1u;
+    let buffer_idx = chunk_idx >> consts.chunks_per_buffer_shift;
+    let offset_x = chunk_idx & ((1u << consts.chunks_per_buffer_shift) - 1u);
+    return textureLoad(grids[buffer_idx], vec3<u32>(pos & vec3(CHUNK_SIZE - 1)) + vec3<u32>(offset_x, 0u, consts.starting_which) * CHUNK_SIZE_U).r;
+}
+
+// Cell at `tile_pos` in the tile around the workgroup, which starts one cell before it
+fn cell(tile_pos: vec3<u32>) -> u32 {
+    if(TILED) {
+        return workgroup_shared.loaded[dot(tile_pos, TILE_STRIDE)];
+    }
+    return load_cell(vec3<i32>(current_wg_pos + tile_pos) - vec3<i32>(1, 1, 1));
+}
+
+// Ages the cell, and appends its age when it dies. Only this invocation touches its half of the
+// word, the atomics keep the other half intact.
+fn record_lifetime(chunk_idx: u32, local: vec3<u32>, before: u32, after: u32) {
+    let index = chunk_idx * CHUNK_SIZE_U * CHUNK_SIZE_U * CHUNK_SIZE_U + dot(local, vec3<u32>(1u, CHUNK_SIZE_U, CHUNK_SIZE_U * CHUNK_SIZE_U));
+    let word = index >> 1u;
+    let shift = (index & 1u) * 16u;
+    let age = (atomicLoad(&ages[word]) >> shift) & MAX_AGE;
+    if(after == 0u) {
+        if(before != 0u) {
+            atomicAnd(&ages[word], ~(MAX_AGE << shift));
+            let slot = atomicAdd(&deaths.count, 1u);
+            if(slot < DEATH_CAPACITY) {
+                deaths.ages[slot] = age;
+            }
+        }
+    } else if(before == 0u) {
+        atomicAnd(&ages[word], ~(MAX_AGE << shift));
+        atomicAdd(&ages[word], 1u << shift);
+    } else if(age < MAX_AGE) {
+        atomicAdd(&ages[word], 1u << shift);
+    }
+}
+
+fn contained(world_pos: vec3<i32>) -> bool {
+    let d = vec3<f32>(world_pos) + vec3<f32>(0.5) - consts.containment_center;
+    if(consts.containment_shape == CONTAIN_SPHERE) {
+        return dot(d, d) <= consts.containment_radius * consts.containment_radius;
+    } else if(consts.containment_shape == CONTAIN_BOX) {
+        return all(abs(d) <= consts.containment_half_extents);
+    }
+    return true;
+}
+
+// Births and survivals only happen with their probability. `roll` comes from the tick's random
+// number, so a replayed tick rolls the same.
+fn by_chance(allowed: bool, probability: f32, roll: u32) -> bool {
+    return allowed && (probability >= 1.0 || f32(roll) / 4294967295.0 < probability);
+}
+
+// Generations rules keep cells that stopped surviving around for a few more states. The alpha byte
+// of a decaying cell counts the steps it took, live cells stay fully opaque.
+fn decaying(value: u32) -> bool {
+    return rule.states > 2u && value != 0u && (value >> 24u) != 0xFFu;
+}
+
+fn alive(value: u32) -> bool {
+    return value != 0u && !decaying(value);
+}
+
+fn apply_rule(cur: u32, newest: u32, born: bool, survives: bool, roll: u32) -> u32 {
+    if(probing) {
+        feedback.probe.flags = PROBE_COUNTED | select(0u, PROBE_BORN, born) | select(0u, PROBE_SURVIVES, survives);
+        feedback.probe.newest = newest;
+    }
+    let heated = heat.enabled != 0u;
+    if(decaying(cur)) {
+        // A step further every tick whatever the neighbors are, dead after the last state
+        let step = (cur >> 24u) + 1u;
+        return select(0u, (cur & 0xFFFFFFu) | (step << 24u), step < rule.states - 1u);
+    }
+    if(cur != 0u) {
+        let cool_enough = !heated || current_temperature <= heat.survival_max;
+        if(by_chance(survives && cool_enough, rule.survival_probability, roll)) {
+            return cur;
+        }
+        return select(0u, (cur & 0xFFFFFFu) | (1u << 24u), rule.states > 2u);
+    }
+    let cool_enough = !heated || current_temperature <= heat.birth_max;
+    return select(0u, newest, by_chance(born && cool_enough, rule.birth_probability, roll));
+}
+
+// Conway-style rule on the y == 0 plane, everything off the plane is cleared
+fn simulate_life_2d(lid: vec3<u32>, cur: u32, world_y: i32, roll: u32) -> u32 {
+    if(world_y != 0) {
+        return 0u;
+    }
+    var count = 0u;
+    var newest = 0u;
+    for(var dz = -1; dz <= 1; dz += 1) {
+        for(var dx = -1; dx <= 1; dx += 1) {
+            if(dx == 0 && dz == 0) {
+                continue;
+            }
+            let neighbor = cell(vec3<u32>(vec3<i32>(lid) + vec3<i32>(1 + dx, 1, 1 + dz)));
+            if(alive(neighbor)) {
+                count += 1u;
+                newest = max(newest, neighbor);
+            }
+        }
+    }
+    let born = (rule.birth_mask & (1u << count)) != 0u;
+    let survives = (rule.survival_mask & (1u << count)) != 0u;
+    if(probing) {
+        feedback.probe.count = i32(count);
+    }
+    return apply_rule(cur, newest, born, survives, roll);
+}
+
+fn in_range(count: u32, range: u32) -> bool {
+    return count >= (range & 0xFFFFu) && count <= (range >> 16u);
+}
+
+// Cell at the `i`th offset of the neighborhood buffer. Offsets past the tile are read through
+// `load_cell`, which finds the chunks two cells over in the atlas like any other neighbor.
+fn neighborhood_cell(lid: vec3<u32>, i: u32) -> u32 {
+    let offset = neighborhood[i].xyz;
+    if(all(abs(offset) <= vec3<i32>(1))) {
+        return cell(vec3<u32>(vec3<i32>(lid) + vec3<i32>(1) + offset));
+    }
+    return load_cell(vec3<i32>(current_wg_pos + lid) + offset);
+}
+
+// Weighted count rule over the offsets in the neighborhood buffer, totalistic when all weights
+// are 1
+fn simulate_larger_than_life(lid: vec3<u32>, cur: u32, roll: u32) -> u32 {
+    var sum = 0;
+    var newest = 0u;
+    for(var i = 0u; i < rule.neighborhood_size; i += 1u) {
+        let neighbor = neighborhood_cell(lid, i);
+        if(alive(neighbor)) {
+            sum += neighborhood[i].w;
+            newest = max(newest, neighbor);
+        }
+    }
+    if(probing) {
+        feedback.probe.count = sum;
+    }
+    // Negative weights can't push the count below zero
+    let count = u32(max(sum, 0));
+    var born = in_range(count, rule.birth_range);
+    var survives = in_range(count, rule.survival_range);
+    if(rule.count_sets != 0u) {
+        // Counts past what the masks hold are never in the set
+        let bit = select(0u, 1u << count, count < 32u);
+        born = (rule.birth_mask & bit) != 0u;
+        survives = (rule.survival_mask & bit) != 0u;
+    }
+    return apply_rule(cur, newest, born, survives, roll);
+}
+
+// Concentrations u and v of a reaction-diffusion cell. The value keeps 1 - u and v as 16 bit
+// fractions, so the resting state u = 1, v = 0 is an empty cell.
+fn rd_unpack(value: u32) -> vec2<f32> {
+    let p = unpack2x16unorm(value);
+    return vec2<f32>(1.0 - p.x, p.y);
+}
+
+// Rounds with dither from `roll`, so changes smaller than a 16 bit step still add up over ticks
+fn rd_pack(uv: vec2<f32>, roll: u32) -> u32 {
+    let stored = clamp(vec2<f32>(1.0 - uv.x, uv.y), vec2<f32>(0.0), vec2<f32>(1.0));
+    let dither = vec2<f32>(f32(roll & 0xFFFFu), f32(roll >> 16u)) / 65536.0;
+    let q = vec2<u32>(min(floor(stored * 65535.0 + dither), vec2<f32>(65535.0)));
+    return q.x | (q.y << 16u);
+}
+
+// Gray-Scott step on the 6 neighbor laplacian. Missing chunks read as the resting state.
+fn simulate_reaction_diffusion(lid: vec3<u32>, cur: u32, roll: u32) -> u32 {
+    let c = rd_unpack(cur);
+    var laplacian = -6.0 * c;
+    for(var i = 0u; i < 6u; i += 1u) {
+        laplacian += rd_unpack(cell(vec3<u32>(vec3<i32>(lid) + vec3<i32>(1) + dirs[i])));
+    }
+    let reaction = c.x * c.y * c.y;
+    let u = c.x + consts.rd_diffusion_u * laplacian.x - reaction + consts.rd_feed * (1.0 - c.x);
+    let v = c.y + consts.rd_diffusion_v * laplacian.y + reaction - (consts.rd_feed + consts.rd_kill) * c.y;
+    return rd_pack(vec2<f32>(u, v), roll);
+}
+
+// Block partitioning update. Blocks straddling chunk borders read their other half through the
+// atlas.
+fn simulate_margolus(lid: vec3<u32>, gpos: vec3<u32>) -> u32 {
+    let in_block = (gpos + vec3<u32>(consts.block_offset)) & vec3<u32>(1u);
+    let block_origin = vec3<i32>(lid) + vec3<i32>(1) - vec3<i32>(in_block);
+
+    var values: array<u32, 8>;
+    var pattern = 0u;
+    for(var k = 0u; k < 8u; k += 1u) {
+        let d = vec3<i32>(vec3<u32>(k & 1u, (k >> 1u) & 1u, k >> 2u));
+        values[k] = cell(vec3<u32>(block_origin + d));
+        if(values[k] != 0u) {
+            pattern |= 1u << k;
+        }
+    }
+
+    let new_pattern = block_rules[pattern];
+    let self_bit = dot(in_block, vec3<u32>(1u, 2u, 4u));
+    if((new_pattern & (1u << self_bit)) == 0u) {
+        return 0u;
+    }
+
+    // Carry colors along by matching the n-th live cell after the update with the n-th before
+    let rank = countOneBits(new_pattern & ((1u << self_bit) - 1u));
+    var seen = 0u;
+    var newest = 0u;
+    for(var k = 0u; k < 8u; k += 1u) {
+        if(values[k] != 0u) {
+            if(seen == rank) {
+                return values[k];
+            }
+            seen += 1u;
+            newest = max(newest, values[k]);
+        }
+    }
+    return select(newest, 0xFFFFFFFFu, newest == 0u);
+}
+
+// Heavier cells sink below lighter ones, fire and smoke are lighter than air. Colors that aren't
+// materials are walls.
+const WALL: i32 = 100;
+
+fn density(value: u32) -> i32 {
+    if(value == 0u) {
+        return 0;
+    } else if(value == MATERIAL_SAND) {
+        return 3;
+    } else if(value == MATERIAL_WATER) {
+        return 2;
+    } else if(value == MATERIAL_FIRE) {
+        return -1;
+    } else if(value == MATERIAL_SMOKE) {
+        return -2;
+    }
+    return WALL;
+}
+
+fn is_fluid(value: u32) -> bool {
+    return value == MATERIAL_WATER || value == MATERIAL_FIRE || value == MATERIAL_SMOKE;
+}
+
+// Swaps the cells at block bits `upper` and `lower` when the upper one is heavier
+fn settle(block: ptr<function, array<u32, 8>>, upper: u32, lower: u32) -> bool {
+    let a = (*block)[upper];
+    let b = (*block)[lower];
+    let da = density(a);
+    let db = density(b);
+    if(da == WALL || db == WALL || da <= db) {
+        return false;
+    }
+    (*block)[upper] = b;
+    (*block)[lower] = a;
+    return true;
+}
+
+// Falling sand on the Margolus blocks. Every cell of a block computes the whole block from the
+// same block seed and keeps its own cell, so the block stays consistent across chunk borders.
+fn simulate_powder(lid: vec3<u32>, gpos: vec3<u32>) -> u32 {
+    let in_block = (gpos + vec3<u32>(consts.block_offset)) & vec3<u32>(1u);
+    let block_origin = vec3<i32>(lid) + vec3<i32>(1) - vec3<i32>(in_block);
+
+    var block: array<u32, 8>;
+    for(var k = 0u; k < 8u; k += 1u) {
+        let d = vec3<i32>(vec3<u32>(k & 1u, (k >> 1u) & 1u, k >> 2u));
+        block[k] = cell(vec3<u32>(block_origin + d));
+    }
+
+    let world = bitcast<vec3<u32>>(chunks[current_chunk_idx].chunk_pos * CHUNK_SIZE + vec3<i32>(gpos) - vec3<i32>(in_block));
+    var seed = hash(consts.rng ^ hash(world.x ^ hash(world.y ^ hash(world.z))));
+
+    // Fire burns out into smoke, smoke clears
+    for(var k = 0u; k < 8u; k += 1u) {
+        seed = hash(seed);
+        if(block[k] == MATERIAL_FIRE && seed < FIRE_BURN_OUT) {
+            block[k] = MATERIAL_SMOKE;
+        } else if(block[k] == MATERIAL_SMOKE && seed < SMOKE_CLEAR) {
+            block[k] = 0u;
+        }
+    }
+
+    // Bits 0, 1, 4 and 5 are the bottom of the four columns, +2 the top
+    var moved = 0u;
+    for(var c = 0u; c < 4u; c += 1u) {
+        let bottom = (c & 1u) | ((c & 2u) << 1u);
+        if(settle(&block, bottom + 2u, bottom)) {
+            moved |= 1u << c;
+        }
+    }
+
+    // Sand that couldn't fall slides down a diagonal, along x or z picked by the seed
+    seed = hash(seed);
+    let axis_bit = select(1u, 4u, (seed & 1u) != 0u);
+    for(var c = 0u; c < 4u; c += 1u) {
+        let bottom = (c & 1u) | ((c & 2u) << 1u);
+        let other = bottom ^ axis_bit;
+        if((moved & (1u << c)) == 0u && block[bottom + 2u] == MATERIAL_SAND) {
+            settle(&block, bottom + 2u, other);
+        }
+    }
+
+    // Fluids spread sideways into empty cells on both layers
+    seed = hash(seed);
+    for(var k = 0u; k < 8u; k += 1u) {
+        let other = k ^ axis_bit;
+        if(other > k && (seed & (1u << k)) != 0u) {
+            let a = block[k];
+            let b = block[other];
+            if((is_fluid(a) && b == 0u) || (is_fluid(b) && a == 0u)) {
+                block[k] = b;
+                block[other] = a;
+            }
+        }
+    }
+
+    return block[dot(in_block, vec3<u32>(1u, 2u, 4u))];
+}
 
 @compute
-@workgroup_size(8, 8, 8)
+@workgroup_size({{WG_SIZE}}, {{WG_SIZE}}, {{WG_SIZE}})
 fn cs_simulate(
     @builtin(local_invocation_id) lid: vec3<u32>,
     @builtin(local_invocation_index) lidx: u32,
@@ -55,54 +605,122 @@ fn cs_simulate(
     @builtin(num_workgroups) num_wg: vec3<u32>,
     ) {
     let wg = (wid.z * num_wg.y + wid.y) * num_wg.x + wid.x;
-    let chunk_idx = wg / 512u;
+    let chunk_idx = wg / WG_PER_CHUNK;
     if(chunk_idx >= consts.num_chunks) {
         return;
     }
     let current_chunk = chunks[chunk_idx];
-    let current_wg = wg % 512;
-    let wg_pos = vec3<u32>(current_wg % 8, (current_wg / 8) % 8, current_wg / 64) * 8u;
+    let current_wg = wg % WG_PER_CHUNK;
+    let wg_pos = vec3<u32>(
+        current_wg % WG_PER_AXIS,
+        (current_wg / WG_PER_AXIS) % WG_PER_AXIS,
+        current_wg / (WG_PER_AXIS * WG_PER_AXIS)
+    ) * WG_SIZE;
 
-    if(all(lid <= vec3<u32>(2u))) {
-        workgroup_shared.neighbor[dot(vec3<u32>(1u, 3u, 9u), lid)] =
-            textureLoad(atlas, current_chunk.chunk_pos + vec3<i32>(lid) - vec3<i32>(1) + vec3<i32>(32)).r;
+    current_chunk_idx = chunk_idx;
+    current_wg_pos = wg_pos;
+
+    let buffer_idx = chunk_idx >> consts.chunks_per_buffer_shift;
+    let offset_x = chunk_idx & ((1u << consts.chunks_per_buffer_shift) - 1u);
+    let chunk_origin = vec3<u32>(offset_x, 0u, 0u) * CHUNK_SIZE_U;
+    if((consts.tick & ((1u << current_chunk.tick_shift) - 1u)) != 0u) {
+        let cur = textureLoad(grids[buffer_idx], chunk_origin + wg_pos + lid + vec3<u32>(0u, 0u, consts.starting_which * CHUNK_SIZE_U)).r;
+        textureStore(grids[buffer_idx], chunk_origin + wg_pos + lid + vec3<u32>(0u, 0u, (consts.starting_which ^ 1u) * CHUNK_SIZE_U), vec4<u32>(cur, 0u, 0u, 0u));
+        return;
     }
 
-    workgroupBarrier();
+    if(TILED) {
+        if(all(lid <= vec3<u32>(2u))) {
+            workgroup_shared.neighbor[dot(vec3<u32>(1u, 3u, 9u), lid)] =
+                textureLoad(atlas, current_chunk.chunk_pos + vec3<i32>(lid) - vec3<i32>(1) + vec3<i32>(ATLAS_OFFSET)).r;
+        }
+
+        workgroupBarrier();
+
+        for(var i = lidx; i < TILE_VOLUME; i += WG_SIZE * WG_SIZE * WG_SIZE) {
+            let tile_pos = vec3<u32>(i % TILE_SIZE, (i / TILE_SIZE) % TILE_SIZE, i / (TILE_SIZE * TILE_SIZE));
+            workgroup_shared.loaded[i] = load_cell(vec3<i32>(tile_pos + wg_pos) - vec3<i32>(1, 1, 1));
+        }
+
+        workgroupBarrier();
+    }
+
+    let rng = hash(consts.rng + chunk_idx * CHUNK_SIZE_U * CHUNK_SIZE_U * CHUNK_SIZE_U + dot(wg_pos + lid, vec3<u32>(1u, CHUNK_SIZE_U, CHUNK_SIZE_U * CHUNK_SIZE_U)));
+    var cur = cell(lid + vec3<u32>(1));
+    let before = cur;
+    if(heat.enabled != 0u) {
+        let heat_pos = (wg_pos + lid) / HEAT_CELL_SIZE;
+        current_temperature = temperature[chunk_idx * HEAT_PER_AXIS * HEAT_PER_AXIS * HEAT_PER_AXIS + dot(heat_pos, vec3<u32>(1u, HEAT_PER_AXIS, HEAT_PER_AXIS * HEAT_PER_AXIS))];
+    }
+    // Separate from the spread mode's use of `rng`
+    let roll = hash(rng ^ 0x9E3779B9u);
+    let world_pos = current_chunk.chunk_pos * CHUNK_SIZE + vec3<i32>(wg_pos + lid);
+    probing = consts.probe_enabled != 0u && all(world_pos == consts.probe_voxel);
+    if(probing) {
+        // Modes without a rule leave the inputs of earlier ticks out
+        feedback.probe.flags = 0u;
+    }
 
-    if(lidx < 500u) {
-        for(var i = 0u; i < 2u; i += 1u) {
-            let pos = vec3<i32>(vec3<u32>((lidx % 5u) * 2u + i, (lidx / 5u) % 10u, lidx / 50u) + wg_pos) - vec3<i32>(1, 1, 1);
-            let neighbor = workgroup_shared.neighbor[
-                dot(vec3<i32>(1, 3, 9), extractBits(pos, 6u, 26u) + vec3<i32>(1, 1, 1))
-            ];
-            var loaded = 0u;
+    if(consts.mode == MODE_LIFE_2D) {
+        let world_y = current_chunk.chunk_pos.y * CHUNK_SIZE + i32(wg_pos.y + lid.y);
+        cur = simulate_life_2d(lid, cur, world_y, roll);
+    } else if(consts.mode == MODE_MARGOLUS) {
+        cur = simulate_margolus(lid, wg_pos + lid);
+    } else if(consts.mode == MODE_LARGER_THAN_LIFE) {
+        cur = simulate_larger_than_life(lid, cur, roll);
+    } else if(consts.mode == MODE_POWDER) {
+        cur = simulate_powder(lid, wg_pos + lid);
+    } else if(consts.mode == MODE_REACTION_DIFFUSION) {
+        cur = simulate_reaction_diffusion(lid, cur, roll);
+    } else {
+        let spread_neighborhood = rule.spread_neighborhood != 0u;
+        let count = select(6u, rule.neighborhood_size, spread_neighborhood);
+        for(var i = 0u; i < count; i += 1u) {
+            var neighbor = 0u;
+            if(spread_neighborhood) {
+                neighbor = neighborhood_cell(lid, i);
+            } else {
+                neighbor = cell(vec3<u32>(vec3<i32>(lid) + vec3<i32>(1) + dirs[i]));
+            }
             if(neighbor != 0u) {
-                let chunk_idx = neighbor - 1u;
-                let buffer_idx = chunk_idx >> consts.chunks_per_buffer_shift;
-                let offset_x = chunk_idx & ((1u << consts.chunks_per_buffer_shift) - 1u);
-                loaded = textureLoad(grids[buffer_idx], vec3<u32>(pos & vec3(63)) + vec3<u32>(offset_x, 0u, consts.starting_which) * 64u).r;
+                cur = max(cur, neighbor);
+                if (f32(rng) / 4294967295.0 < 0.01) {
+                    cur = hash(rng);
+                }
             }
-            workgroup_shared.loaded[lidx * 2 + i] = loaded;
         }
     }
 
-    workgroupBarrier();
-
-    let rng = hash(consts.rng + chunk_idx * 262144u + dot(wg_pos + lid, vec3<u32>(1u, 64u, 4096u)));
-    var cur = workgroup_shared.loaded[dot(lid + vec3<u32>(1), vec3<u32>(1u, 10u, 100u))];
+    if(consts.containment_shape != CONTAIN_OFF && cur != 0u) {
+        if(!contained(world_pos)) {
+            cur = 0u;
+        }
+    }
 
-    for(var i = 0u; i < 6u; i += 1u) {
-        let neighbor = workgroup_shared.loaded[dot(vec3<u32>(vec3<i32>(lid) + vec3<i32>(1) + dirs[i]), vec3<u32>(1u, 10u, 100u))];
-        if(neighbor != 0u) {
-            cur = max(cur, neighbor);
-            if (f32(rng) / 4294967295.0 < 0.01) {
-                cur = hash(rng);
-            }
+    if(region.enabled != 0u) {
+        if(any(world_pos < region.min) || any(world_pos > region.max)) {
+            cur = before;
         }
     }
 
-    let buffer_idx = chunk_idx >> consts.chunks_per_buffer_shift;
-    let offset_x = chunk_idx & ((1u << consts.chunks_per_buffer_shift) - 1u);
-    textureStore(grids[buffer_idx], wg_pos + lid + vec3<u32>(offset_x, 0u, consts.starting_which ^ 1u) * 64u, vec4<u32>(cur, 0u, 0u, 0u));
+    if(probing) {
+        feedback.probe.flags |= PROBE_SIMULATED;
+        feedback.probe.tick = consts.tick;
+        feedback.probe.before = before;
+        feedback.probe.after = cur;
+    }
+
+    if(consts.record_lifetimes != 0u) {
+        record_lifetime(chunk_idx, wg_pos + lid, before, cur);
+    }
+
+    textureStore(grids[buffer_idx], chunk_origin + wg_pos + lid + vec3<u32>(0u, 0u, (consts.starting_which ^ 1u) * CHUNK_SIZE_U), vec4<u32>(cur, 0u, 0u, 0u));
+
+    if(cur != before) {
+        atomicStore(&workgroup_changed, 1u);
+    }
+    workgroupBarrier();
+    if(lidx == 0u && atomicLoad(&workgroup_changed) != 0u) {
+        atomicStore(&feedback.chunk_changed[chunk_idx], 1u);
+    }
 }
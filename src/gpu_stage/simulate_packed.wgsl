@@ -0,0 +1,210 @@
+#include "common.wgsl"
+
+// Margolus kernel on packed cells: every word holds the 2x2x2 block of binary cells at twice its
+// position, bit (x + 2y + 4z) for the cell at (x, y, z) within the block. Chunks are packed into
+// their slot of the buffer, simulated there, and unpacked back into the chunk textures.
+
+struct PushConstants {
+    @size(4) rng: u32,
+    @size(4) chunks_per_buffer_shift: u32,
+    @size(4) starting_which: u32,
+    @size(4) num_chunks: u32,
+    @size(4) mode: u32,
+    @size(4) block_offset: u32,
+    @size(4) portals_enabled: u32,
+    @size(4) target_which: u32,
+    // Low bits of the tick, for chunks that only run some ticks
+    @size(4) tick: u32,
+    // Nonzero to keep cell ages and append deaths for the lifetime histogram
+    @size(4) record_lifetimes: u32,
+}
+
+const PACKED_SIZE: u32 = CHUNK_SIZE_U / 2u;
+const PACKED_VOLUME: u32 = PACKED_SIZE * PACKED_SIZE * PACKED_SIZE;
+const PACKED_STRIDE: vec3<u32> = vec3<u32>(1u, PACKED_SIZE, PACKED_SIZE * PACKED_SIZE);
+const WG_SIZE: u32 = 4u;
+const WG_PER_AXIS: u32 = PACKED_SIZE / WG_SIZE;
+const WG_PER_CHUNK: u32 = WG_PER_AXIS * WG_PER_AXIS * WG_PER_AXIS;
+const TILE_SIZE: u32 = WG_SIZE + 2u;
+const TILE_VOLUME: u32 = TILE_SIZE * TILE_SIZE * TILE_SIZE;
+const TILE_STRIDE: vec3<u32> = vec3<u32>(1u, TILE_SIZE, TILE_SIZE * TILE_SIZE);
+// Color of cells that were empty before the packed ticks and are filled after them
+const FILLED_COLOR: u32 = 0xFFFFFFFFu;
+
+struct ChunkInfoEntry {
+    chunk_pos: vec3<i32>,
+    // The chunk only runs ticks that are a multiple of 1 << tick_shift and keeps its cells in
+    // the others
+    tick_shift: u32,
+}
+
+var<push_constant> consts: PushConstants;
+
+@group(0) @binding(0)
+var<storage, read> chunks: array<ChunkInfoEntry>;
+
+@group(0) @binding(1)
+var<storage, read> block_rules: array<u32, 256>;
+
+@group(1) @binding(0)
+var atlas: texture_storage_3d<{{CHUNK_FORMAT}}, read>;
+
+@group(1) @binding(1)
+var grids: binding_array<texture_storage_3d<{{CHUNK_FORMAT}}, read_write>, 8>;
+
+@group(2) @binding(0)
+var<storage, read> packed_src: array<u32>;
+
+@group(2) @binding(1)
+var<storage, read_write> packed_dst: array<u32>;
+
+struct Shared {
+    loaded: array<u32, {{TILE_VOLUME}}>,
+    neighbor: array<u32, 27>,
+}
+
+var<workgroup> workgroup_shared: Shared;
+
+fn block_corner(k: u32) -> vec3<u32> {
+    return vec3<u32>(k & 1u, (k >> 1u) & 1u, k >> 2u);
+}
+
+// Chunk index and packed position within the chunk of the invocation, the same split of the
+// dispatch as the unpacked kernel
+fn packed_position(wid: vec3<u32>, num_wg: vec3<u32>, lid: vec3<u32>) -> vec4<u32> {
+    let wg = (wid.z * num_wg.y + wid.y) * num_wg.x + wid.x;
+    let current_wg = wg % WG_PER_CHUNK;
+    let wg_pos = vec3<u32>(
+        current_wg % WG_PER_AXIS,
+        (current_wg / WG_PER_AXIS) % WG_PER_AXIS,
+        current_wg / (WG_PER_AXIS * WG_PER_AXIS)
+    ) * WG_SIZE;
+    return vec4<u32>(wg_pos + lid, wg / WG_PER_CHUNK);
+}
+
+fn grid_pos(chunk_idx: u32, pos: vec3<u32>, which: u32) -> vec3<u32> {
+    let offset_x = chunk_idx & ((1u << consts.chunks_per_buffer_shift) - 1u);
+    return pos + vec3<u32>(offset_x, 0u, which) * CHUNK_SIZE_U;
+}
+
+@compute
+@workgroup_size(4, 4, 4)
+fn cs_pack(
+    @builtin(local_invocation_id) lid: vec3<u32>,
+    @builtin(workgroup_id) wid: vec3<u32>,
+    @builtin(num_workgroups) num_wg: vec3<u32>,
+    ) {
+    let p = packed_position(wid, num_wg, lid);
+    let chunk_idx = p.w;
+    if(chunk_idx >= consts.num_chunks) {
+        return;
+    }
+    let buffer_idx = chunk_idx >> consts.chunks_per_buffer_shift;
+    var block = 0u;
+    for(var k = 0u; k < 8u; k += 1u) {
+        let cell = textureLoad(grids[buffer_idx], grid_pos(chunk_idx, p.xyz * 2u + block_corner(k), consts.starting_which)).r;
+        if(cell != 0u) {
+            block |= 1u << k;
+        }
+    }
+    packed_dst[chunk_idx * PACKED_VOLUME + dot(p.xyz, PACKED_STRIDE)] = block;
+}
+
+// Cell at `cell` in the tile, in cell coordinates
+fn tile_cell(cell: vec3<u32>) -> u32 {
+    let block = workgroup_shared.loaded[dot(cell >> vec3<u32>(1u), TILE_STRIDE)];
+    return (block >> dot(cell & vec3<u32>(1u), vec3<u32>(1u, 2u, 4u))) & 1u;
+}
+
+@compute
+@workgroup_size(4, 4, 4)
+fn cs_step(
+    @builtin(local_invocation_id) lid: vec3<u32>,
+    @builtin(local_invocation_index) lidx: u32,
+    @builtin(workgroup_id) wid: vec3<u32>,
+    @builtin(num_workgroups) num_wg: vec3<u32>,
+    ) {
+    let p = packed_position(wid, num_wg, lid);
+    let chunk_idx = p.w;
+    if(chunk_idx >= consts.num_chunks) {
+        return;
+    }
+    let current_chunk = chunks[chunk_idx];
+    let wg_pos = p.xyz - lid;
+    let index = chunk_idx * PACKED_VOLUME + dot(p.xyz, PACKED_STRIDE);
+    if((consts.tick & ((1u << current_chunk.tick_shift) - 1u)) != 0u) {
+        packed_dst[index] = packed_src[index];
+        return;
+    }
+
+    if(all(lid <= vec3<u32>(2u))) {
+        workgroup_shared.neighbor[dot(vec3<u32>(1u, 3u, 9u), lid)] =
+            textureLoad(atlas, current_chunk.chunk_pos + vec3<i32>(lid) - vec3<i32>(1) + vec3<i32>(ATLAS_OFFSET)).r;
+    }
+
+    workgroupBarrier();
+
+    // Every block is read by up to 8 invocations, so the tile with a one block border is loaded once
+    for(var i = lidx; i < TILE_VOLUME; i += WG_SIZE * WG_SIZE * WG_SIZE) {
+        let tile_pos = vec3<u32>(i % TILE_SIZE, (i / TILE_SIZE) % TILE_SIZE, i / (TILE_SIZE * TILE_SIZE));
+        let pos = vec3<i32>(tile_pos + wg_pos) - vec3<i32>(1);
+        let outside = extractBits(pos, CHUNK_SHIFT - 1u, 33u - CHUNK_SHIFT);
+        let neighbor = workgroup_shared.neighbor[dot(vec3<i32>(1, 3, 9), outside + vec3<i32>(1))];
+        var loaded = 0u;
+        if(neighbor != 0u) {
+            let local = vec3<u32>(pos & vec3<i32>(i32(PACKED_SIZE) - 1));
+            loaded = packed_src[(neighbor - 1u) * PACKED_VOLUME + dot(local, PACKED_STRIDE)];
+        }
+        workgroup_shared.loaded[i] = loaded;
+    }
+
+    workgroupBarrier();
+
+    let center = lid + vec3<u32>(1u);
+    var block = 0u;
+    if(consts.block_offset == 0u) {
+        // The Margolus blocks are exactly the packed blocks
+        block = block_rules[workgroup_shared.loaded[dot(center, TILE_STRIDE)]];
+    } else {
+        // Every cell is in a different block, which straddles up to 8 packed blocks
+        for(var k = 0u; k < 8u; k += 1u) {
+            let cell = center * 2u + block_corner(k);
+            let in_block = (cell + vec3<u32>(consts.block_offset)) & vec3<u32>(1u);
+            let block_origin = cell - in_block;
+            var pattern = 0u;
+            for(var j = 0u; j < 8u; j += 1u) {
+                pattern |= tile_cell(block_origin + block_corner(j)) << j;
+            }
+            let self_bit = dot(in_block, vec3<u32>(1u, 2u, 4u));
+            block |= ((block_rules[pattern] >> self_bit) & 1u) << k;
+        }
+    }
+    packed_dst[index] = block;
+}
+
+// Writes the packed cells back into the chunk textures. Cells that stay filled keep their color,
+// the packed kernel doesn't carry colors along with moving cells.
+@compute
+@workgroup_size(4, 4, 4)
+fn cs_unpack(
+    @builtin(local_invocation_id) lid: vec3<u32>,
+    @builtin(workgroup_id) wid: vec3<u32>,
+    @builtin(num_workgroups) num_wg: vec3<u32>,
+    ) {
+    let p = packed_position(wid, num_wg, lid);
+    let chunk_idx = p.w;
+    if(chunk_idx >= consts.num_chunks) {
+        return;
+    }
+    let buffer_idx = chunk_idx >> consts.chunks_per_buffer_shift;
+    let block = packed_src[chunk_idx * PACKED_VOLUME + dot(p.xyz, PACKED_STRIDE)];
+    for(var k = 0u; k < 8u; k += 1u) {
+        let pos = p.xyz * 2u + block_corner(k);
+        let before = textureLoad(grids[buffer_idx], grid_pos(chunk_idx, pos, consts.starting_which)).r;
+        var cell = 0u;
+        if(((block >> k) & 1u) != 0u) {
+            cell = select(FILLED_COLOR, before, before != 0u);
+        }
+        textureStore(grids[buffer_idx], grid_pos(chunk_idx, pos, consts.target_which), vec4<u32>(cell, 0u, 0u, 0u));
+    }
+}
@@ -0,0 +1,498 @@
+use std::mem::size_of;
+use std::rc::Rc;
+
+use bytemuck::{offset_of, Pod, Zeroable};
+use nalgebra_glm as glm;
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::*;
+
+use crate::camera::Camera;
+use crate::chunk_manager::ChunkManager;
+use crate::clipboard::Clipboard;
+use crate::gpu_stage::pick::PickHit;
+use crate::shader_prep::ShaderPrep;
+use crate::util::RenderTarget;
+use crate::wgpu_context::WgpuContext;
+
+const WORKGROUP_SIZE: u32 = 64;
+// How far in front of the camera the pattern goes while nothing is picked
+const FREE_DISTANCE: f32 = 24.0;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable, Default)]
+struct GhostPushConstants {
+    view_proj: glm::Mat4x4,
+    offset: glm::IVec3,
+    opacity: f32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable, Default)]
+struct GhostFace {
+    cell: glm::IVec3,
+    face: u32,
+    value: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable, Default)]
+struct StampPushConstants {
+    group: u32,
+    origin_x: u32,
+    which: u32,
+    count: u32,
+    chunk_pos: [i32; 4],
+    offset: [i32; 4],
+}
+
+struct Resources {
+    ghost_shader: ShaderModule,
+    ghost_pipeline_layout: PipelineLayout,
+    stamp_pipeline: ComputePipeline,
+    cells_bind_group_layout: BindGroupLayout,
+}
+
+struct DynamicResources {
+    output_target: Rc<RenderTarget>,
+    ghost_pipeline: RenderPipeline,
+}
+
+// Built from the clipboard as it's rotated right now
+struct PatternBuffers {
+    face_count: u32,
+    face_buffer: Buffer,
+    cell_count: u32,
+    cells_bind_group: BindGroup,
+}
+
+/// Places a pattern from the clipboard against the picked face. Until it's stamped with a click
+/// the pattern is drawn as a translucent ghost where it would land, stamping writes its live
+/// cells into the world and leaves the cells around them as they were.
+pub struct Stamp {
+    res: Resources,
+    dynamic: DynamicResources,
+    clipboard: Option<Clipboard>,
+    buffers: Option<PatternBuffers>,
+    pub opacity: f32,
+    // Where the corner of the pattern goes this frame
+    offset: glm::IVec3,
+    requested: bool,
+}
+
+impl Resources {
+    fn new(ctx: &WgpuContext, chunk_manager: &ChunkManager) -> Self {
+        let ghost_shader = ctx.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("stamp ghost_shader"),
+            source: ShaderSource::Wgsl(include_str!("./ghost.wgsl").into()),
+        });
+        let ghost_pipeline_layout = ctx
+            .device
+            .create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some("stamp ghost_pipeline_layout"),
+                bind_group_layouts: &[],
+                push_constant_ranges: &[PushConstantRange {
+                    stages: ShaderStages::VERTEX,
+                    range: 0..size_of::<GhostPushConstants>() as u32,
+                }],
+            });
+
+        let source = ShaderPrep::new()
+            .define("WORKGROUP_SIZE", WORKGROUP_SIZE)
+            .process(include_str!("./stamp.wgsl"));
+        let stamp_shader = ctx.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("stamp shader"),
+            source: ShaderSource::Wgsl(source.into()),
+        });
+        let cells_bind_group_layout =
+            ctx.device
+                .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                    label: Some("stamp cells_bind_group_layout"),
+                    entries: &[BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: BufferSize::new(size_of::<glm::IVec4>() as u64),
+                        },
+                        count: None,
+                    }],
+                });
+        let stamp_pipeline_layout = ctx
+            .device
+            .create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some("stamp pipeline_layout"),
+                bind_group_layouts: &[
+                    &cells_bind_group_layout,
+                    chunk_manager.bind_group_layout(true),
+                ],
+                push_constant_ranges: &[PushConstantRange {
+                    stages: ShaderStages::COMPUTE,
+                    range: 0..size_of::<StampPushConstants>() as u32,
+                }],
+            });
+        let stamp_pipeline = ctx
+            .device
+            .create_compute_pipeline(&ComputePipelineDescriptor {
+                label: Some("stamp pipeline"),
+                layout: Some(&stamp_pipeline_layout),
+                module: &stamp_shader,
+                entry_point: "cs_stamp",
+            });
+
+        Self {
+            ghost_shader,
+            ghost_pipeline_layout,
+            stamp_pipeline,
+            cells_bind_group_layout,
+        }
+    }
+}
+
+impl DynamicResources {
+    fn new(ctx: &WgpuContext, res: &Resources, output_target: Rc<RenderTarget>) -> Self {
+        let ghost_pipeline = ctx
+            .device
+            .create_render_pipeline(&RenderPipelineDescriptor {
+                label: Some("stamp ghost_pipeline"),
+                layout: Some(&res.ghost_pipeline_layout),
+                vertex: VertexState {
+                    module: &res.ghost_shader,
+                    entry_point: "vs_ghost",
+                    buffers: &[VertexBufferLayout {
+                        array_stride: size_of::<GhostFace>() as u64,
+                        step_mode: VertexStepMode::Instance,
+                        attributes: &[
+                            VertexAttribute {
+                                format: VertexFormat::Sint32x3,
+                                offset: offset_of!(GhostFace, cell) as u64,
+                                shader_location: 0,
+                            },
+                            VertexAttribute {
+                                format: VertexFormat::Uint32,
+                                offset: offset_of!(GhostFace, face) as u64,
+                                shader_location: 1,
+                            },
+                            VertexAttribute {
+                                format: VertexFormat::Uint32,
+                                offset: offset_of!(GhostFace, value) as u64,
+                                shader_location: 2,
+                            },
+                        ],
+                    }],
+                },
+                fragment: Some(FragmentState {
+                    module: &res.ghost_shader,
+                    entry_point: "fs_ghost",
+                    targets: &[Some(ColorTargetState {
+                        format: output_target.info.format,
+                        blend: Some(BlendState::ALPHA_BLENDING),
+                        write_mask: ColorWrites::ALL,
+                    })],
+                }),
+                primitive: PrimitiveState {
+                    topology: PrimitiveTopology::TriangleList,
+                    strip_index_format: None,
+                    front_face: FrontFace::Ccw,
+                    cull_mode: Some(Face::Back),
+                    unclipped_depth: false,
+                    polygon_mode: PolygonMode::Fill,
+                    conservative: false,
+                },
+                // Hidden behind voxels, without hiding them in turn
+                depth_stencil: Some(DepthStencilState {
+                    format: TextureFormat::Depth32Float,
+                    depth_write_enabled: false,
+                    depth_compare: CompareFunction::Greater,
+                    stencil: Default::default(),
+                    bias: Default::default(),
+                }),
+                multisample: MultisampleState::default(),
+                multiview: None,
+            });
+
+        Self {
+            output_target,
+            ghost_pipeline,
+        }
+    }
+}
+
+impl PatternBuffers {
+    fn new(ctx: &WgpuContext, res: &Resources, clipboard: &Clipboard) -> Self {
+        let faces = clipboard
+            .exposed_faces()
+            .into_iter()
+            .map(|(cell, face, value)| GhostFace { cell, face, value })
+            .collect::<Vec<_>>();
+        let face_buffer = ctx.device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("stamp face_buffer"),
+            contents: bytemuck::cast_slice(&faces),
+            usage: BufferUsages::VERTEX,
+        });
+        let cells = clipboard
+            .cells()
+            .iter()
+            .map(|(pos, value)| glm::vec4(pos.x, pos.y, pos.z, *value as i32))
+            .collect::<Vec<_>>();
+        let cell_buffer = ctx.device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("stamp cell_buffer"),
+            contents: bytemuck::cast_slice(&cells),
+            usage: BufferUsages::STORAGE,
+        });
+        let cells_bind_group = ctx.device.create_bind_group(&BindGroupDescriptor {
+            label: Some("stamp cells_bind_group"),
+            layout: &res.cells_bind_group_layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: cell_buffer.as_entire_binding(),
+            }],
+        });
+        Self {
+            face_count: faces.len() as u32,
+            face_buffer,
+            cell_count: cells.len() as u32,
+            cells_bind_group,
+        }
+    }
+}
+
+impl Stamp {
+    pub fn new(
+        ctx: &WgpuContext,
+        chunk_manager: &ChunkManager,
+        output_target: Rc<RenderTarget>,
+    ) -> Self {
+        let res = Resources::new(ctx, chunk_manager);
+        let dynamic = DynamicResources::new(ctx, &res, output_target);
+        Self {
+            res,
+            dynamic,
+            clipboard: None,
+            buffers: None,
+            opacity: 0.4,
+            offset: glm::vec3(0, 0, 0),
+            requested: false,
+        }
+    }
+
+    pub fn resize(&mut self, ctx: &WgpuContext, output_target: Rc<RenderTarget>) {
+        self.dynamic = DynamicResources::new(ctx, &self.res, output_target);
+    }
+
+    /// Starts placing `clipboard`, replacing what was being placed
+    pub fn hold(&mut self, clipboard: Clipboard) {
+        self.clipboard = Some(clipboard);
+        self.buffers = None;
+    }
+
+    pub fn cancel(&mut self) {
+        self.clipboard = None;
+        self.buffers = None;
+        self.requested = false;
+    }
+
+    pub fn active(&self) -> bool {
+        self.clipboard.is_some()
+    }
+
+    /// Quarter turn of the held pattern about `axis`
+    pub fn rotate(&mut self, axis: usize) {
+        if let Some(clipboard) = &mut self.clipboard {
+            clipboard.rotate(axis);
+            self.buffers = None;
+        }
+    }
+
+    /// Stamps the held pattern where it's shown on the next update, returns whether a pattern
+    /// is held
+    pub fn request(&mut self) -> bool {
+        self.requested = self.active();
+        self.requested
+    }
+
+    /// Moves the pattern onto the face under the crosshair, centered across it and growing out
+    /// along its normal, or in front of the camera when nothing is picked
+    pub fn prepare(&mut self, camera: &Camera, pick: Option<PickHit>) {
+        let Some(clipboard) = &self.clipboard else {
+            return;
+        };
+        let size = clipboard.size();
+        self.offset = match pick {
+            Some(hit) => {
+                let mut offset = hit.adjacent() - size / 2;
+                for axis in 0..3 {
+                    match hit.normal[axis] {
+                        1 => offset[axis] = hit.adjacent()[axis],
+                        -1 => offset[axis] = hit.adjacent()[axis] - size[axis] + 1,
+                        _ => {}
+                    }
+                }
+                offset
+            }
+            None => {
+                let center = camera.position + camera.forward() * FREE_DISTANCE;
+                center.map(|c| c.floor() as i32) - size / 2
+            }
+        };
+    }
+
+    /// World space box the pattern covers while a stamp is pending, its chunks have to exist
+    /// before it's written
+    pub fn pending_bounds(&self) -> Option<(glm::Vec3, glm::Vec3)> {
+        let clipboard = self.clipboard.as_ref().filter(|_| self.requested)?;
+        let min = self.offset.cast::<f32>();
+        Some((
+            min,
+            min + (clipboard.size() - glm::vec3(1, 1, 1)).cast::<f32>(),
+        ))
+    }
+
+    fn buffers(&mut self, ctx: &WgpuContext) -> Option<&PatternBuffers> {
+        let clipboard = self.clipboard.as_ref()?;
+        if self.buffers.is_none() {
+            self.buffers = Some(PatternBuffers::new(ctx, &self.res, clipboard));
+        }
+        self.buffers.as_ref()
+    }
+
+    /// Writes the pattern into the chunks it overlaps if a stamp was requested. Returns the
+    /// name of the pattern and where its corner went.
+    pub fn update(
+        &mut self,
+        ctx: &WgpuContext,
+        encoder: &mut CommandEncoder,
+        chunk_manager: &mut ChunkManager,
+    ) -> Option<(String, glm::IVec3)> {
+        let (min, max) = self.pending_bounds()?;
+        self.requested = false;
+        let offset = self.offset;
+        self.buffers(ctx)?;
+        let buffers = self.buffers.as_ref().unwrap();
+        let touched = chunk_manager
+            .chunks_in_aabb(&min, &max)
+            .map(|chunk| chunk.pos)
+            .collect::<Vec<_>>();
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+                label: Some("stamp compute_pass"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_pipeline(&self.res.stamp_pipeline);
+            compute_pass.set_bind_group(0, &buffers.cells_bind_group, &[]);
+            compute_pass.set_bind_group(1, chunk_manager.bind_group(true), &[]);
+            for pos in &touched {
+                let chunk = &chunk_manager.chunks()[pos];
+                let (group, origin_x) = chunk_manager.offset_to_group_and_origin_x(chunk.offset());
+                compute_pass.set_push_constants(
+                    0,
+                    bytemuck::bytes_of(&StampPushConstants {
+                        group,
+                        origin_x,
+                        which: chunk_manager.which(),
+                        count: buffers.cell_count,
+                        chunk_pos: [pos.x, pos.y, pos.z, 0],
+                        offset: [offset.x, offset.y, offset.z, 0],
+                    }),
+                );
+                compute_pass.dispatch_workgroups(buffers.cell_count.div_ceil(WORKGROUP_SIZE), 1, 1);
+            }
+        }
+        for pos in &touched {
+            chunk_manager.mark_chunk_written(pos);
+        }
+        let name = self.clipboard.as_ref()?.name.clone();
+        Some((name, offset))
+    }
+
+    /// Draws the ghost of the held pattern into the scene, after the voxels
+    pub fn draw(
+        &mut self,
+        ctx: &WgpuContext,
+        encoder: &mut CommandEncoder,
+        view_proj: &glm::Mat4x4,
+    ) {
+        let offset = self.offset;
+        let opacity = self.opacity;
+        if self.buffers(ctx).is_none() {
+            return;
+        }
+        let buffers = self.buffers.as_ref().unwrap();
+        let output_target = &self.dynamic.output_target;
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("stamp ghost_render_pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: &output_target.render_target,
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Load,
+                    store: StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                view: output_target
+                    .depth_target
+                    .as_ref()
+                    .expect("no depth target"),
+                depth_ops: Some(Operations {
+                    load: LoadOp::Load,
+                    store: StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        render_pass.set_pipeline(&self.dynamic.ghost_pipeline);
+        render_pass.set_push_constants(
+            ShaderStages::VERTEX,
+            0,
+            bytemuck::bytes_of(&GhostPushConstants {
+                view_proj: *view_proj,
+                offset,
+                opacity,
+            }),
+        );
+        render_pass.set_vertex_buffer(0, buffers.face_buffer.slice(..));
+        render_pass.draw(0..6, 0..buffers.face_count);
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.collapsing("Stamp", |ui| {
+            let Some(clipboard) = &self.clipboard else {
+                ui.label("Pick a pattern to stamp in the asset browser");
+                return;
+            };
+            let size = clipboard.size();
+            ui.label(format!(
+                "{}: {} x {} x {}, {} cells",
+                clipboard.name,
+                size.x,
+                size.y,
+                size.z,
+                clipboard.cells().len()
+            ));
+            ui.label("Click to stamp it where the ghost is");
+            ui.horizontal(|ui| {
+                ui.label("Rotate");
+                for (axis, (name, key)) in [("X", "T"), ("Y", "R"), ("Z", "G")].iter().enumerate() {
+                    if ui
+                        .button(*name)
+                        .on_hover_text(format!("Quarter turn about {}, {}", name, key))
+                        .clicked()
+                    {
+                        self.rotate(axis);
+                    }
+                }
+            });
+            ui.add(egui::Slider::new(&mut self.opacity, 0.05..=1.0).text("Ghost opacity"));
+            if ui
+                .button("Stop stamping")
+                .on_hover_text("Backspace")
+                .clicked()
+            {
+                self.cancel();
+            }
+        });
+    }
+}
@@ -0,0 +1,39 @@
+#include "common.wgsl"
+
+struct PushConstants {
+    @size(4) group: u32,
+    @size(4) origin_x: u32,
+    @size(4) which: u32,
+    @size(4) count: u32,
+    @size(16) chunk_pos: vec3<i32>,
+    // World position of the corner of the pattern
+    @size(16) offset: vec3<i32>,
+};
+
+var<push_constant> consts: PushConstants;
+
+// Position relative to the corner of the pattern, and the cell's value in w
+@group(0) @binding(0)
+var<storage, read> cells: array<vec4<i32>>;
+
+@group(1) @binding(0)
+var atlas: texture_storage_3d<{{CHUNK_FORMAT}}, read>;
+
+@group(1) @binding(1)
+var grids: binding_array<texture_storage_3d<{{CHUNK_FORMAT}}, read_write>, 8>;
+
+// One invocation per pattern cell, the ones outside of the chunk are skipped
+@compute
+@workgroup_size({{WORKGROUP_SIZE}})
+fn cs_stamp(@builtin(global_invocation_id) gid: vec3<u32>) {
+    if(gid.x >= consts.count) {
+        return;
+    }
+    let cell = cells[gid.x];
+    let local = consts.offset + cell.xyz - consts.chunk_pos * CHUNK_SIZE;
+    if(any(local < vec3<i32>(0)) || any(local >= vec3<i32>(CHUNK_SIZE))) {
+        return;
+    }
+    let origin = vec3<u32>(consts.origin_x * CHUNK_SIZE_U, 0u, consts.which * CHUNK_SIZE_U);
+    textureStore(grids[consts.group], origin + vec3<u32>(local), vec4<u32>(bitcast<u32>(cell.w), 0u, 0u, 0u));
+}
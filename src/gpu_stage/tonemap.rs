@@ -1,3 +1,6 @@
+use crate::resource_tracker;
+use crate::rng;
+use crate::shader_prep::ShaderPrep;
 use crate::user_event::UserEvent;
 use crate::util::*;
 use crate::wgpu_context::WgpuContext;
@@ -5,9 +8,11 @@ use crate::FinalDrawResources;
 use bytemuck::{Pod, Zeroable};
 use nalgebra_glm as glm;
 use pod_enum::pod_enum;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use std::mem::size_of;
 use std::rc::Rc;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 use wgpu::*;
 use winit::event_loop::EventLoopProxy;
 
@@ -38,6 +43,106 @@ impl Default for TargetColorSpace {
     }
 }
 
+#[repr(u32)]
+#[pod_enum]
+enum DitherMode {
+    Off = 0,
+    Ordered = 1,
+    BlueNoise = 2,
+}
+
+impl Default for DitherMode {
+    fn default() -> Self {
+        DitherMode::Off
+    }
+}
+
+const BLUE_NOISE_SIZE: usize = 64;
+
+/// Blue noise thresholds for a tile of BLUE_NOISE_SIZE squared pixels, made with the
+/// void-and-cluster method. Generated once and shared by every tonemap stage.
+fn blue_noise() -> &'static [u8] {
+    static NOISE: OnceLock<Vec<u8>> = OnceLock::new();
+    NOISE.get_or_init(|| {
+        let size = BLUE_NOISE_SIZE;
+        let count = size * size;
+        // Energy a point adds at every offset from it, the tile wraps around
+        let sigma = 1.5f32;
+        let kernel = (0..count)
+            .map(|i| {
+                let wrap = |d: usize| d.min(size - d) as f32;
+                let (dx, dy) = (wrap(i % size), wrap(i / size));
+                (-(dx * dx + dy * dy) / (2.0 * sigma * sigma)).exp()
+            })
+            .collect::<Vec<_>>();
+        let splat = |energy: &mut [f32], point: usize, sign: f32| {
+            let (px, py) = (point % size, point / size);
+            for (i, e) in energy.iter_mut().enumerate() {
+                let dx = (i % size + size - px) % size;
+                let dy = (i / size + size - py) % size;
+                *e += sign * kernel[dx + dy * size];
+            }
+        };
+        // Densest point of the pattern, or the emptiest spot without one
+        let extreme = |energy: &[f32], pattern: &[bool], set: bool| {
+            let mut candidates = (0..count).filter(|&i| pattern[i] == set);
+            let first = candidates.next().unwrap();
+            candidates.fold(first, |best, i| {
+                let denser = energy[i] > energy[best];
+                if denser == set && energy[i] != energy[best] {
+                    i
+                } else {
+                    best
+                }
+            })
+        };
+
+        // A sparse random pattern, evened out by moving points from clusters into voids
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut pattern = vec![false; count];
+        let mut energy = vec![0.0; count];
+        let initial = count / 10;
+        while pattern.iter().filter(|&&p| p).count() < initial {
+            let point = rng.gen_range(0..count);
+            if !pattern[point] {
+                pattern[point] = true;
+                splat(&mut energy, point, 1.0);
+            }
+        }
+        loop {
+            let cluster = extreme(&energy, &pattern, true);
+            pattern[cluster] = false;
+            splat(&mut energy, cluster, -1.0);
+            let void = extreme(&energy, &pattern, false);
+            pattern[void] = true;
+            splat(&mut energy, void, 1.0);
+            if void == cluster {
+                break;
+            }
+        }
+
+        // Points of the even pattern are ranked by taking out the densest first, the rest by
+        // filling the emptiest spot first
+        let mut rank = vec![0; count];
+        {
+            let (mut pattern, mut energy) = (pattern.clone(), energy.clone());
+            for r in (0..initial).rev() {
+                let cluster = extreme(&energy, &pattern, true);
+                pattern[cluster] = false;
+                splat(&mut energy, cluster, -1.0);
+                rank[cluster] = r;
+            }
+        }
+        for r in initial..count {
+            let void = extreme(&energy, &pattern, false);
+            pattern[void] = true;
+            splat(&mut energy, void, 1.0);
+            rank[void] = r;
+        }
+        rank.iter().map(|&r| (r * 256 / count) as u8).collect()
+    })
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, Pod, Zeroable, Default)]
 struct Uniforms {
@@ -46,7 +151,13 @@ struct Uniforms {
     target_color_space: TargetColorSpace,
     _pad0: [f32; 2],
     output_scale: f32,
-    _pad1: [f32; 3],
+    vignette: f32,
+    grain: f32,
+    aberration: f32,
+    grain_seed: u32,
+    dither: f32,
+    dither_mode: DitherMode,
+    _pad1: u32,
 }
 
 struct Resources {
@@ -56,6 +167,7 @@ struct Resources {
     uniform_buffer: Buffer,
     bind_group_layout: BindGroupLayout,
     linear_buffer_sampler: Sampler,
+    blue_noise_view: TextureView,
 }
 
 struct DynamicResources {
@@ -71,13 +183,30 @@ pub struct Tonemap {
     bleed: f32,
     tonemapping: TonemapType,
     output_scale: f32,
+    vignette_enabled: bool,
+    vignette: f32,
+    grain_enabled: bool,
+    grain: f32,
+    aberration_enabled: bool,
+    // Offset of the red and blue channels at the corners, in pixels
+    aberration: f32,
+    // Reseeds the grain every frame, from the visual effects stream
+    frame: u32,
+    seed: u32,
+    dither_mode: DitherMode,
+    // Amplitude in steps of the 8 bit output
+    dither: f32,
 }
 
 impl Resources {
     fn new(ctx: &WgpuContext) -> Self {
         let shader = ctx.device.create_shader_module(ShaderModuleDescriptor {
             label: Some("tonemap shader"),
-            source: ShaderSource::Wgsl(include_str!("./tonemap.wgsl").into()),
+            source: ShaderSource::Wgsl(
+                ShaderPrep::new()
+                    .process(include_str!("./tonemap.wgsl"))
+                    .into(),
+            ),
         });
 
         let renderbuffer_desc = TextureDescriptor {
@@ -128,6 +257,16 @@ impl Resources {
                         },
                         count: None,
                     },
+                    BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Float { filterable: false },
+                            view_dimension: TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
                 ],
             });
 
@@ -151,6 +290,38 @@ impl Resources {
             ..Default::default()
         });
 
+        let blue_noise_size = Extent3d {
+            width: BLUE_NOISE_SIZE as u32,
+            height: BLUE_NOISE_SIZE as u32,
+            depth_or_array_layers: 1,
+        };
+        let blue_noise_texture = ctx.device.create_texture(&TextureDescriptor {
+            label: Some("tonemap blue_noise_texture"),
+            size: blue_noise_size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::R8Unorm,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        ctx.queue.write_texture(
+            ImageCopyTexture {
+                texture: &blue_noise_texture,
+                mip_level: 0,
+                origin: Origin3d::ZERO,
+                aspect: TextureAspect::All,
+            },
+            blue_noise(),
+            ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(BLUE_NOISE_SIZE as u32),
+                rows_per_image: None,
+            },
+            blue_noise_size,
+        );
+        let blue_noise_view = blue_noise_texture.create_view(&TextureViewDescriptor::default());
+
         Self {
             renderbuffer_desc,
             pipeline_layout,
@@ -158,6 +329,7 @@ impl Resources {
             uniform_buffer,
             bind_group_layout,
             linear_buffer_sampler,
+            blue_noise_view,
         }
     }
 }
@@ -171,7 +343,13 @@ impl DynamicResources {
         res.renderbuffer_desc.size.width = output_target_info.width;
         res.renderbuffer_desc.size.height = output_target_info.height;
         let renderbuffer = ctx.device.create_texture(&res.renderbuffer_desc);
-        let renderbuffer_view = renderbuffer.create_view(&TextureViewDescriptor::default());
+        let renderbuffer_view = resource_tracker::view(
+            &renderbuffer,
+            &TextureViewDescriptor {
+                label: Some("tonemap renderbuffer_view"),
+                ..Default::default()
+            },
+        );
         let pipeline = ctx
             .device
             .create_render_pipeline(&RenderPipelineDescriptor {
@@ -193,24 +371,31 @@ impl DynamicResources {
                 multiview: None,
             });
 
-        let bind_group = ctx.device.create_bind_group(&BindGroupDescriptor {
-            label: Some("tonemap bind_group"),
-            layout: &res.bind_group_layout,
-            entries: &[
-                BindGroupEntry {
-                    binding: 0,
-                    resource: BindingResource::Sampler(&res.linear_buffer_sampler),
-                },
-                BindGroupEntry {
-                    binding: 1,
-                    resource: BindingResource::TextureView(&renderbuffer_view),
-                },
-                BindGroupEntry {
-                    binding: 2,
-                    resource: res.uniform_buffer.as_entire_binding(),
-                },
-            ],
-        });
+        let bind_group = resource_tracker::bind_group(
+            ctx,
+            &BindGroupDescriptor {
+                label: Some("tonemap bind_group"),
+                layout: &res.bind_group_layout,
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::Sampler(&res.linear_buffer_sampler),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: BindingResource::TextureView(&renderbuffer_view),
+                    },
+                    BindGroupEntry {
+                        binding: 2,
+                        resource: res.uniform_buffer.as_entire_binding(),
+                    },
+                    BindGroupEntry {
+                        binding: 3,
+                        resource: BindingResource::TextureView(&res.blue_noise_view),
+                    },
+                ],
+            },
+        );
 
         let input_target = Rc::new(RenderTarget {
             render_target: renderbuffer_view.into(),
@@ -244,33 +429,100 @@ impl Tonemap {
             bleed: 0.0,
             tonemapping: TonemapType::AcesFull,
             output_scale: 1.0,
+            vignette_enabled: false,
+            vignette: 0.4,
+            grain_enabled: false,
+            grain: 0.04,
+            aberration_enabled: false,
+            aberration: 2.0,
+            frame: 0,
+            seed: 0,
+            dither_mode: DitherMode::BlueNoise,
+            dither: 1.0,
         }
     }
     pub fn resize(&mut self, ctx: &WgpuContext, output_target_info: Rc<RenderTargetInfo>) {
         self.dynamic = DynamicResources::new(ctx, &mut self.res, output_target_info);
     }
 
-    pub fn update(&mut self, ctx: &WgpuContext) {
+    pub fn set_seed(&mut self, seed: u32) {
+        self.seed = seed;
+    }
+
+    /// Takes over the user facing settings of another tonemap stage
+    pub fn copy_settings(&mut self, other: &Tonemap) {
+        self.exposure = other.exposure;
+        self.bleed = other.bleed;
+        self.tonemapping = other.tonemapping;
+        self.output_scale = other.output_scale;
+        self.vignette_enabled = other.vignette_enabled;
+        self.vignette = other.vignette;
+        self.grain_enabled = other.grain_enabled;
+        self.grain = other.grain;
+        self.seed = other.seed;
+        self.aberration_enabled = other.aberration_enabled;
+        self.aberration = other.aberration;
+        self.dither_mode = other.dither_mode;
+        self.dither = other.dither;
+    }
+
+    /// With `bypass` set only the color space conversion is applied
+    pub fn update(
+        &mut self,
+        ctx: &WgpuContext,
+        command_encoder: &mut CommandEncoder,
+        bypass: bool,
+    ) {
+        self.frame = self.frame.wrapping_add(1);
         let output_linear = self.dynamic.output_target_info.format.is_srgb();
-        let exposure = self.exposure;
-        let bleed = exposure * self.bleed;
+        let effect = |enabled: bool, amount: f32| {
+            if enabled && !bypass {
+                amount
+            } else {
+                0.0
+            }
+        };
+        let (exposure, bleed, tonemapping, output_scale) = if bypass {
+            (1.0, 0.0, TonemapType::None, 1.0)
+        } else {
+            (
+                self.exposure,
+                self.exposure * self.bleed,
+                self.tonemapping,
+                self.output_scale,
+            )
+        };
         let transform = glm::mat3(
             exposure, bleed, bleed, bleed, exposure, bleed, bleed, bleed, exposure,
         );
 
         let uniforms = Uniforms {
             linear_transform: glm::mat3_to_mat4(&transform),
-            tonemapping: self.tonemapping,
+            tonemapping,
             target_color_space: if output_linear {
                 TargetColorSpace::Linear
             } else {
                 TargetColorSpace::Srgb
             },
-            output_scale: self.output_scale,
+            output_scale,
+            vignette: effect(self.vignette_enabled, self.vignette),
+            grain: effect(self.grain_enabled, self.grain),
+            aberration: effect(self.aberration_enabled, self.aberration),
+            grain_seed: rng::stream(self.seed, self.frame),
+            // Applies in bypass too, it only hides the quantization
+            dither: self.dither,
+            dither_mode: self.dither_mode,
             ..Default::default()
         };
-        ctx.queue
-            .write_buffer(&self.res.uniform_buffer, 0, bytemuck::bytes_of(&uniforms));
+        ctx.staging.write(
+            &ctx.device,
+            &ctx.queue,
+            command_encoder,
+            "tonemap uniforms",
+            &self.res.uniform_buffer,
+            0,
+            bytemuck::bytes_of(&uniforms),
+        );
     }
 
     pub fn final_draw_resources(&self) -> Arc<FinalDrawResources> {
@@ -295,6 +547,44 @@ impl Tonemap {
                 ui.radio_value(&mut self.tonemapping, TonemapType::AcesFull, "AcesFull");
             });
             ui.add(egui::Slider::new(&mut self.output_scale, 0.0..=10.0).text("Output scale"));
+
+            ui.label("Effects");
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut self.vignette_enabled, "Vignette");
+                ui.add_enabled(
+                    self.vignette_enabled,
+                    egui::Slider::new(&mut self.vignette, 0.0..=1.0),
+                );
+            });
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut self.grain_enabled, "Film grain");
+                ui.add_enabled(
+                    self.grain_enabled,
+                    egui::Slider::new(&mut self.grain, 0.0..=0.3),
+                );
+            });
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut self.aberration_enabled, "Chromatic aberration");
+                ui.add_enabled(
+                    self.aberration_enabled,
+                    egui::Slider::new(&mut self.aberration, 0.0..=16.0).suffix(" px"),
+                );
+            });
+
+            ui.label("Dithering").on_hover_text(
+                "Noise of about one output step, so smooth gradients don't band on 8 bit displays",
+            );
+            ui.horizontal(|ui| {
+                ui.radio_value(&mut self.dither_mode, DitherMode::Off, "Off");
+                ui.radio_value(&mut self.dither_mode, DitherMode::Ordered, "Ordered");
+                ui.radio_value(&mut self.dither_mode, DitherMode::BlueNoise, "Blue noise");
+            });
+            ui.add_enabled(
+                self.dither_mode != DitherMode::Off,
+                egui::Slider::new(&mut self.dither, 0.0..=4.0)
+                    .text("Strength")
+                    .suffix(" steps"),
+            );
         });
     }
 }
@@ -1,3 +1,6 @@
+#include "rng.wgsl"
+#include "dither.wgsl"
+
 struct VertexOut {
     @builtin(position) position: vec4<f32>,
 };
@@ -5,7 +8,16 @@ struct VertexOut {
 struct Uniforms {
     @size(64) linear_transform: mat4x4<f32>,
     @size(16) tonemapping_target_color_space: vec4<u32>,
-    @size(16) output_scale: f32,
+    output_scale: f32,
+    vignette: f32,
+    grain: f32,
+    aberration: f32,
+    // Differs every frame
+    grain_seed: u32,
+    // Noise amplitude in steps of the 8 bit output
+    dither: f32,
+    // 0 is off, 1 ordered and 2 blue noise
+    @size(8) dither_mode: u32,
 };
 
 @group(0) @binding(0)
@@ -17,6 +29,10 @@ var linear_buffer_texture: texture_2d<f32>;
 @group(0) @binding(2)
 var<uniform> uniforms: Uniforms;
 
+// Tiled blue noise thresholds from 0 to 1
+@group(0) @binding(3)
+var blue_noise: texture_2d<f32>;
+
 var<private> v_positions: array<vec2<f32>, 3> = array<vec2<f32>, 3>(
     vec2<f32>(-1.0, -1.0),
     vec2<f32>(3.0, -1.0),
@@ -64,17 +80,36 @@ fn linear_to_srgb(x: vec3<f32>) -> vec3<f32> {
     return pow(clamp(x, vec3<f32>(0.0), vec3<f32>(1.0)), vec3<f32>(1.0 / 2.2));
 }
 
+// Threshold from 0 to 1 that the dither pattern has at the pixel
+fn dither_threshold(pixel: vec2<u32>) -> f32 {
+    if(uniforms.dither_mode == 1u) {
+        return bayer_threshold(pixel, 3u);
+    }
+    let size = textureDimensions(blue_noise);
+    return textureLoad(blue_noise, pixel % size, 0).r;
+}
+
+fn sample_linear(uv: vec2<f32>) -> vec3<f32> {
+    return textureSample(linear_buffer_texture, linear_buffer_sampler, uv).xyz;
+}
+
 @fragment
 fn fs_main(in: VertexOut) -> @location(0) vec4<f32> {
     let tonemapping = uniforms.tonemapping_target_color_space.x;
     let target_color_space = uniforms.tonemapping_target_color_space.y;
     let output_scale = uniforms.output_scale;
 
-    var color: vec3<f32> = textureSample(
-        linear_buffer_texture,
-        linear_buffer_sampler,
-        in.position.xy / vec2<f32>(textureDimensions(linear_buffer_texture))
-    ).xyz;
+    let dimensions = vec2<f32>(textureDimensions(linear_buffer_texture));
+    let uv = in.position.xy / dimensions;
+    // -1 to 1 from the center to the edges
+    let centered = uv * 2.0 - 1.0;
+
+    var color: vec3<f32> = sample_linear(uv);
+    if(uniforms.aberration > 0.0) {
+        let offset = centered * uniforms.aberration / dimensions;
+        color.x = sample_linear(uv + offset).x;
+        color.z = sample_linear(uv - offset).z;
+    }
 
     color = (vec4<f32>(color, 1.0) * uniforms.linear_transform).xyz;
     if(tonemapping == 1u) {
@@ -83,9 +118,39 @@ fn fs_main(in: VertexOut) -> @location(0) vec4<f32> {
         color = aces_full(color.xyz);
     }
 
+    if(uniforms.vignette > 0.0) {
+        let falloff = smoothstep(0.4, 1.4, length(centered));
+        color *= 1.0 - uniforms.vignette * falloff;
+    }
+
     if(target_color_space == 1u) {
         color = linear_to_srgb(color);
     }
+
+    if(uniforms.grain > 0.0) {
+        // Added after the color space conversion so it's equally visible in shadows and highlights
+        let pixel = vec2<u32>(in.position.xy);
+        let seed = rng_stream(uniforms.grain_seed, pixel.x | (pixel.y << 16u));
+        let noise = f32(seed) / 4294967295.0 - 0.5;
+        var grain = vec3<f32>(noise * uniforms.grain);
+        if(target_color_space == 0u) {
+            // Roughly perceptual steps for a linear target, the surface converts to sRGB after
+            grain *= 2.2 * pow(max(color, vec3<f32>(0.001)), vec3<f32>(1.0 - 1.0 / 2.2));
+        }
+        color = max(color + grain, vec3<f32>(0.0));
+    }
     color *= output_scale;
+
+    if(uniforms.dither_mode != 0u && uniforms.dither > 0.0) {
+        // Last so the banding of the final quantization is what gets broken up, a step's worth of
+        // noise centered on zero
+        let noise = (dither_threshold(vec2<u32>(in.position.xy)) - 0.5) * uniforms.dither / 255.0;
+        var dither = vec3<f32>(noise);
+        if(target_color_space == 0u) {
+            // The surface quantizes in sRGB, so the step is sized for that
+            dither *= 2.2 * pow(max(color, vec3<f32>(0.001)), vec3<f32>(1.0 - 1.0 / 2.2));
+        }
+        color = max(color + dither, vec3<f32>(0.0));
+    }
     return vec4<f32>(color, 1.0);
 }
\ No newline at end of file
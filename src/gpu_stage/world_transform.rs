@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+use std::mem::size_of;
+
+use bytemuck::{Pod, Zeroable};
+use nalgebra_glm as glm;
+use wgpu::*;
+
+use crate::chunk::{CHUNK_SIZE, CHUNK_VOLUME};
+use crate::chunk_manager::ChunkManager;
+use crate::profiler::log_duration;
+use crate::shader_prep::ShaderPrep;
+use crate::wgpu_context::WgpuContext;
+
+const WORKGROUP_SIZE: u32 = 4;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+impl Axis {
+    pub const ALL: [Axis; 3] = [Axis::X, Axis::Y, Axis::Z];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    pub fn name(self) -> &'static str {
+        ["X", "Y", "Z"][self.index()]
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Transform {
+    Mirror(Axis),
+    // 90 degrees counterclockwise when looking down the axis
+    Rotate(Axis),
+}
+
+impl Transform {
+    // Signed permutation applied to voxel coordinates
+    fn matrix(self) -> glm::TMat3<i32> {
+        match self {
+            Transform::Mirror(axis) => {
+                let mut matrix = glm::TMat3::<i32>::identity();
+                matrix[(axis.index(), axis.index())] = -1;
+                matrix
+            }
+            Transform::Rotate(axis) => {
+                let (a, u, v) = (axis.index(), (axis.index() + 1) % 3, (axis.index() + 2) % 3);
+                let mut matrix = glm::TMat3::<i32>::zeros();
+                matrix[(a, a)] = 1;
+                matrix[(u, v)] = -1;
+                matrix[(v, u)] = 1;
+                matrix
+            }
+        }
+    }
+}
+
+/// Where a transform sends each world voxel, chosen so the transformed chunk bounds keep their
+/// minimum corner and the world stays roughly in place
+#[derive(Copy, Clone, Debug)]
+pub struct VoxelMap {
+    matrix: glm::TMat3<i32>,
+    translation: glm::IVec3,
+}
+
+impl VoxelMap {
+    fn new(transform: Transform, min: &glm::IVec3, max: &glm::IVec3) -> Self {
+        let matrix = transform.matrix();
+        let mut translation = *min;
+        for i in 0..3 {
+            for j in 0..3 {
+                let corner = if matrix[(i, j)] > 0 { min[j] } else { max[j] };
+                translation[i] -= matrix[(i, j)] * corner;
+            }
+        }
+        Self {
+            matrix,
+            translation,
+        }
+    }
+
+    pub fn apply(&self, voxel: &glm::IVec3) -> glm::IVec3 {
+        self.matrix * voxel + self.translation
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable, Default)]
+struct TransformPushConstants {
+    group: u32,
+    origin_x: u32,
+    which: u32,
+    _pad0: u32,
+    rows: [[i32; 4]; 3],
+    offset: [i32; 4],
+}
+
+/// Mirrors or rotates the whole world within its chunk bounds. Chunks map one to one, so every
+/// destination chunk is filled from a single snapshotted source chunk with its voxel indices
+/// remapped.
+pub struct WorldTransform {
+    // Created the first time the world is transformed
+    pipeline: Option<TransformPipeline>,
+}
+
+struct TransformPipeline {
+    pipeline: ComputePipeline,
+    source_buffer: Buffer,
+    bind_group: BindGroup,
+}
+
+impl TransformPipeline {
+    fn new(ctx: &WgpuContext, chunk_manager: &ChunkManager) -> Self {
+        log_duration("TransformPipeline::new", || {
+            Self::create(ctx, chunk_manager)
+        })
+    }
+
+    fn create(ctx: &WgpuContext, chunk_manager: &ChunkManager) -> Self {
+        let source = ShaderPrep::new().process(include_str!("./world_transform.wgsl"));
+        let shader = ctx.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("world_transform shader"),
+            source: ShaderSource::Wgsl(source.into()),
+        });
+
+        let bind_group_layout = ctx
+            .device
+            .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("world_transform bind_group_layout"),
+                entries: &[BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+            });
+
+        let pipeline_layout = ctx
+            .device
+            .create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some("world_transform pipeline_layout"),
+                bind_group_layouts: &[&bind_group_layout, chunk_manager.bind_group_layout(true)],
+                push_constant_ranges: &[PushConstantRange {
+                    stages: ShaderStages::COMPUTE,
+                    range: 0..size_of::<TransformPushConstants>() as u32,
+                }],
+            });
+
+        let pipeline = ctx
+            .device
+            .create_compute_pipeline(&ComputePipelineDescriptor {
+                label: Some("world_transform pipeline"),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point: "cs_transform",
+            });
+
+        let source_buffer = ctx.device.create_buffer(&BufferDescriptor {
+            label: Some("world_transform source_buffer"),
+            size: (CHUNK_VOLUME * size_of::<u32>()) as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = ctx.device.create_bind_group(&BindGroupDescriptor {
+            label: Some("world_transform bind_group"),
+            layout: &bind_group_layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: source_buffer.as_entire_binding(),
+            }],
+        });
+
+        Self {
+            pipeline,
+            source_buffer,
+            bind_group,
+        }
+    }
+}
+
+impl WorldTransform {
+    pub fn new() -> Self {
+        Self { pipeline: None }
+    }
+
+    /// Transforms every chunk, returns where the voxels went or an error when the transformed
+    /// world wouldn't fit
+    pub fn apply(
+        &mut self,
+        ctx: &WgpuContext,
+        chunk_manager: &mut ChunkManager,
+        transform: Transform,
+    ) -> Result<VoxelMap, String> {
+        chunk_manager.finalize_changes_and_start_frame(ctx);
+        let mut min = glm::vec3(i32::MAX, i32::MAX, i32::MAX);
+        let mut max = glm::vec3(i32::MIN, i32::MIN, i32::MIN);
+        for pos in chunk_manager.chunks().keys() {
+            min = glm::min2(&min, pos);
+            max = glm::max2(&max, pos);
+        }
+        if min.x > max.x {
+            return Err("the world is empty".to_owned());
+        }
+        let size = CHUNK_SIZE as i32;
+        let map = VoxelMap::new(
+            transform,
+            &(min * size),
+            &((max + glm::vec3(1, 1, 1)) * size - glm::vec3(1, 1, 1)),
+        );
+
+        // The center of a chunk lands inside the chunk it is moved to
+        let center = glm::vec3(size / 2, size / 2, size / 2);
+        let sources = chunk_manager
+            .chunks()
+            .keys()
+            .map(|pos| {
+                let dest = map
+                    .apply(&(pos * size + center))
+                    .map(|c| c.div_euclid(size));
+                (dest, *pos)
+            })
+            .collect::<HashMap<_, _>>();
+        chunk_manager.check_chunk_positions(ctx, &sources.keys().copied().collect())?;
+        let pipeline = self
+            .pipeline
+            .get_or_insert_with(|| TransformPipeline::new(ctx, chunk_manager));
+
+        let mut encoder = ctx
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("world_transform snapshot"),
+            });
+        let snapshot = chunk_manager.snapshot_chunks(ctx, &mut encoder);
+        ctx.queue.submit([encoder.finish()]);
+        let snapshot_index = snapshot
+            .positions()
+            .iter()
+            .enumerate()
+            .map(|(i, pos)| (*pos, i))
+            .collect::<HashMap<_, _>>();
+
+        chunk_manager.set_chunk_positions(&sources.keys().copied().collect());
+        chunk_manager.finalize_changes_and_start_frame(ctx);
+
+        // Local source voxel of a local destination voxel, inverting the signed permutation
+        let inverse = map.matrix.transpose();
+        let rows = [0, 1, 2].map(|i| {
+            let row = inverse.row(i);
+            [row[0], row[1], row[2], 0]
+        });
+        let mut encoder = ctx
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("world_transform"),
+            });
+        for (dest, source) in &sources {
+            snapshot.copy_chunk_to_buffer(
+                &mut encoder,
+                snapshot_index[source],
+                &pipeline.source_buffer,
+                0,
+            );
+            let offset = inverse * (dest * size - map.translation) - source * size;
+
+            let chunk = &chunk_manager.chunks()[dest];
+            let (group, origin_x) = chunk_manager.offset_to_group_and_origin_x(chunk.offset());
+            let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+                label: Some("world_transform compute_pass"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_pipeline(&pipeline.pipeline);
+            compute_pass.set_bind_group(0, &pipeline.bind_group, &[]);
+            compute_pass.set_bind_group(1, chunk_manager.bind_group(true), &[]);
+            compute_pass.set_push_constants(
+                0,
+                bytemuck::bytes_of(&TransformPushConstants {
+                    group,
+                    origin_x,
+                    which: chunk_manager.which(),
+                    _pad0: 0,
+                    rows,
+                    offset: [offset.x, offset.y, offset.z, 0],
+                }),
+            );
+            let workgroups = CHUNK_SIZE / WORKGROUP_SIZE;
+            compute_pass.dispatch_workgroups(workgroups, workgroups, workgroups);
+        }
+        ctx.queue.submit([encoder.finish()]);
+        chunk_manager.mark_all_written();
+        Ok(map)
+    }
+}
@@ -0,0 +1,35 @@
+#include "common.wgsl"
+
+struct PushConstants {
+    @size(4) group: u32,
+    @size(4) origin_x: u32,
+    @size(4) which: u32,
+    @size(4) _pad0: u32,
+    // Source voxel of a destination voxel, both local to their chunks: source = matrix * dest + offset
+    @size(16) row_x: vec3<i32>,
+    @size(16) row_y: vec3<i32>,
+    @size(16) row_z: vec3<i32>,
+    @size(16) offset: vec3<i32>,
+};
+
+var<push_constant> consts: PushConstants;
+
+// The source chunk tightly packed in x, y, z order
+@group(0) @binding(0)
+var<storage, read> source: array<u32>;
+
+@group(1) @binding(0)
+var atlas: texture_storage_3d<{{CHUNK_FORMAT}}, read>;
+
+@group(1) @binding(1)
+var grids: binding_array<texture_storage_3d<{{CHUNK_FORMAT}}, read_write>, 8>;
+
+@compute
+@workgroup_size(4, 4, 4)
+fn cs_transform(@builtin(global_invocation_id) gid: vec3<u32>) {
+    let dest = vec3<i32>(gid);
+    let src = vec3<u32>(vec3<i32>(dot(consts.row_x, dest), dot(consts.row_y, dest), dot(consts.row_z, dest)) + consts.offset);
+    let value = source[(src.z * CHUNK_SIZE_U + src.y) * CHUNK_SIZE_U + src.x];
+    let origin = vec3<u32>(consts.origin_x * CHUNK_SIZE_U, 0u, consts.which * CHUNK_SIZE_U);
+    textureStore(grids[consts.group], origin + gid, vec4<u32>(value, 0u, 0u, 0u));
+}
@@ -0,0 +1,159 @@
+// Runs the simulation without a window or event loop, on a canvas that's never attached to the
+// page. Only used by the browser test suite in tests/web.rs, which is why it's exported.
+
+use nalgebra_glm as glm;
+use wasm_bindgen::JsCast;
+
+use crate::chunk::{Chunk, CHUNK_VOLUME};
+use crate::chunk_manager::ChunkManager;
+use crate::gpu_stage::simulate::{Simulate, SimulationMode};
+use crate::profiler::Profiler;
+use crate::staging::StagingRing;
+use crate::wgpu_context::WgpuContext;
+
+pub use crate::chunk::CHUNK_SIZE;
+
+// Event loop turns to wait for a readback before giving up
+const MAX_READBACK_WAITS: u32 = 1000;
+
+/// Lets the browser run its event loop once, which is where buffer mappings resolve
+async fn yield_to_browser() {
+    let promise = js_sys::Promise::new(&mut |resolve, _| {
+        let _ = web_sys::window()
+            .expect("No window")
+            .set_timeout_with_callback_and_timeout_and_arguments_0(&resolve, 0);
+    });
+    let _ = wasm_bindgen_futures::JsFuture::from(promise).await;
+}
+
+/// A single chunk at the origin with the simulation around it, set up like the app does
+pub struct Headless {
+    ctx: WgpuContext<'static>,
+    chunk_manager: ChunkManager,
+    simulate: Simulate,
+}
+
+impl Headless {
+    /// Fails when the browser has no WebGPU adapter or the device misses something the engine
+    /// requests
+    pub async fn new(seed: u32) -> Result<Self, String> {
+        let canvas = web_sys::window()
+            .and_then(|window| window.document())
+            .ok_or("No document")?
+            .create_element("canvas")
+            .map_err(|e| format!("{:?}", e))?
+            .dyn_into::<web_sys::HtmlCanvasElement>()
+            .map_err(|e| format!("{:?}", e))?;
+        canvas.set_width(CHUNK_SIZE);
+        canvas.set_height(CHUNK_SIZE);
+
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::all(),
+            ..wgpu::InstanceDescriptor::default()
+        });
+        let surface = instance
+            .create_surface(wgpu::SurfaceTarget::Canvas(canvas))
+            .map_err(|e| e.to_string())?;
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::HighPerformance,
+                force_fallback_adapter: false,
+                compatible_surface: Some(&surface),
+            })
+            .await
+            .ok_or("No adapter, is WebGPU available?")?;
+        let (device, queue) = crate::request_device(&adapter)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let surface_caps = surface.get_capabilities(&adapter);
+        let surface_format = WgpuContext::preferred_surface_format(&surface_caps);
+        let surface_config = surface
+            .get_default_config(&adapter, CHUNK_SIZE, CHUNK_SIZE)
+            .ok_or("The surface isn't supported by the adapter")?;
+        surface.configure(&device, &surface_config);
+        let profiler = Profiler::new(&device, &queue, true);
+        let ctx = WgpuContext {
+            surface,
+            adapter,
+            device,
+            queue,
+            surface_caps,
+            surface_format,
+            surface_config,
+            profiler,
+            staging: StagingRing::new(),
+        };
+
+        let mut chunk_manager = ChunkManager::new(&ctx);
+        chunk_manager.add_chunk(Chunk::new(glm::vec3(0, 0, 0)));
+        chunk_manager.finalize_changes_and_start_frame(&ctx);
+        let mut simulate = Simulate::new(&ctx, &chunk_manager);
+        simulate.set_seed(seed);
+
+        Ok(Self {
+            ctx,
+            chunk_manager,
+            simulate,
+        })
+    }
+
+    /// Replaces the chunk with `voxels`, given in local coordinates, everything else is empty
+    pub fn upload(&mut self, voxels: &[([usize; 3], u32)]) {
+        let size = CHUNK_SIZE as usize;
+        let mut data = vec![0u32; CHUNK_VOLUME];
+        for ([x, y, z], value) in voxels {
+            data[(z * size + y) * size + x] = *value;
+        }
+        self.chunk_manager
+            .upload_chunk_data(&self.ctx, glm::vec3(0, 0, 0), &data);
+    }
+
+    /// Switches to the 2D rule on the y = 0 plane, with bit n of the masks for n live neighbors
+    pub fn set_life_2d(&mut self, birth_mask: u32, survival_mask: u32) {
+        self.simulate.mode = SimulationMode::Life2d;
+        self.simulate.birth_mask = birth_mask;
+        self.simulate.survival_mask = survival_mask;
+    }
+
+    /// Runs `ticks` ticks, one submission each like separate frames would
+    pub fn run(&mut self, ticks: u32) {
+        for _ in 0..ticks {
+            self.chunk_manager
+                .finalize_changes_and_start_frame(&self.ctx);
+            self.simulate.step = 1;
+            self.ctx.staging.begin_frame();
+            let mut encoder =
+                self.ctx
+                    .device
+                    .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                        label: Some("encoder headless"),
+                    });
+            self.simulate
+                .update(&self.ctx, &mut encoder, &mut self.chunk_manager);
+            self.ctx.submit([encoder.finish()]);
+            self.simulate.after_submit();
+        }
+    }
+
+    pub fn tick(&self) -> u64 {
+        self.simulate.tick()
+    }
+
+    /// The voxels of the chunk, x fastest, then y, then z
+    pub async fn download(&mut self) -> Result<Vec<u32>, String> {
+        self.chunk_manager
+            .finalize_changes_and_start_frame(&self.ctx);
+        let download = self.chunk_manager.download_chunks(&self.ctx);
+        for _ in 0..MAX_READBACK_WAITS {
+            if let Some(chunks) = download.try_take() {
+                return chunks?
+                    .pop()
+                    .map(|(_, data)| data)
+                    .ok_or_else(|| "No chunk was downloaded".to_owned());
+            }
+            yield_to_browser().await;
+        }
+        Err("Timed out waiting for the readback".to_owned())
+    }
+}
@@ -0,0 +1,550 @@
+// Turns images and volume files into chunks. 2D images (binary or ASCII netpbm, or 2D NRRD) are
+// heightmaps extruded up from y = 0, 3D volumes (NRRD with a raw attached body, or headerless raw
+// files with the size given by hand) become a voxel wherever a sample passes the threshold.
+
+use std::collections::HashMap;
+
+use nalgebra_glm as glm;
+
+use crate::chunk::{CHUNK_SIZE, CHUNK_VOLUME};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum SampleType {
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+    F32,
+    F64,
+}
+
+impl SampleType {
+    // Types offered for headerless raw files
+    const RAW: [SampleType; 3] = [SampleType::U8, SampleType::U16, SampleType::F32];
+
+    fn bytes(self) -> usize {
+        match self {
+            SampleType::U8 | SampleType::I8 => 1,
+            SampleType::U16 | SampleType::I16 => 2,
+            SampleType::U32 | SampleType::I32 | SampleType::F32 => 4,
+            SampleType::F64 => 8,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            SampleType::U8 => "8 bit",
+            SampleType::I8 => "8 bit signed",
+            SampleType::U16 => "16 bit",
+            SampleType::I16 => "16 bit signed",
+            SampleType::U32 => "32 bit",
+            SampleType::I32 => "32 bit signed",
+            SampleType::F32 => "32 bit float",
+            SampleType::F64 => "64 bit float",
+        }
+    }
+
+    fn from_nrrd(name: &str) -> Option<Self> {
+        Some(match name {
+            "uchar" | "unsigned char" | "uint8" | "uint8_t" => SampleType::U8,
+            "signed char" | "int8" | "int8_t" => SampleType::I8,
+            "ushort" | "unsigned short" | "unsigned short int" | "uint16" | "uint16_t" => {
+                SampleType::U16
+            }
+            "short" | "short int" | "signed short" | "signed short int" | "int16" | "int16_t" => {
+                SampleType::I16
+            }
+            "uint" | "unsigned int" | "uint32" | "uint32_t" => SampleType::U32,
+            "int" | "signed int" | "int32" | "int32_t" => SampleType::I32,
+            "float" => SampleType::F32,
+            "double" => SampleType::F64,
+            _ => return None,
+        })
+    }
+
+    fn decode(self, bytes: &[u8], little_endian: bool) -> f64 {
+        let mut b = [0u8; 8];
+        b[..bytes.len()].copy_from_slice(bytes);
+        if !little_endian {
+            b[..bytes.len()].reverse();
+        }
+        match self {
+            SampleType::U8 => b[0] as f64,
+            SampleType::I8 => b[0] as i8 as f64,
+            SampleType::U16 => u16::from_le_bytes([b[0], b[1]]) as f64,
+            SampleType::I16 => i16::from_le_bytes([b[0], b[1]]) as f64,
+            SampleType::U32 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            SampleType::I32 => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            SampleType::F32 => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            SampleType::F64 => f64::from_le_bytes(b),
+        }
+    }
+}
+
+/// Product of header dimensions, or an error when a malformed header would overflow it
+fn checked_product(dims: &[usize]) -> Result<usize, String> {
+    dims.iter()
+        .try_fold(1usize, |acc, d| acc.checked_mul(*d))
+        .ok_or_else(|| format!("the dimensions {:?} are too large", dims))
+}
+
+/// Samples normalized to 0..1, x fastest. Images have a depth of 1.
+struct Samples {
+    size: [usize; 3],
+    values: Vec<f32>,
+}
+
+impl Samples {
+    fn new(size: [usize; 3], raw: Vec<f64>) -> Result<Self, String> {
+        if size.iter().any(|s| *s == 0) {
+            return Err("the file has no samples".to_owned());
+        }
+        let (min, max) = raw
+            .iter()
+            .filter(|v| v.is_finite())
+            .fold((f64::MAX, f64::MIN), |(min, max), v| {
+                (min.min(*v), max.max(*v))
+            });
+        let range = if max > min { max - min } else { 1.0 };
+        let values = raw
+            .iter()
+            .map(|v| {
+                if v.is_finite() {
+                    ((v - min) / range) as f32
+                } else {
+                    0.0
+                }
+            })
+            .collect();
+        Ok(Self { size, values })
+    }
+
+    fn from_bytes(
+        size: [usize; 3],
+        ty: SampleType,
+        little_endian: bool,
+        data: &[u8],
+    ) -> Result<Self, String> {
+        let count = checked_product(&size)?;
+        let bytes = checked_product(&[count, ty.bytes()])?;
+        if data.len() < bytes {
+            return Err(format!(
+                "expected {} bytes of samples, the file has {}",
+                bytes,
+                data.len()
+            ));
+        }
+        let raw = data
+            .chunks_exact(ty.bytes())
+            .take(count)
+            .map(|bytes| ty.decode(bytes, little_endian))
+            .collect();
+        Self::new(size, raw)
+    }
+
+    fn get(&self, x: usize, y: usize, z: usize) -> f32 {
+        self.values[(z * self.size[1] + y) * self.size[0] + x]
+    }
+
+    fn is_image(&self) -> bool {
+        self.size[2] == 1
+    }
+}
+
+// Whitespace separated header tokens of netpbm files, skipping comments
+struct Tokens<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Tokens<'a> {
+    fn next(&mut self) -> Result<&'a str, String> {
+        let data = self.data;
+        loop {
+            while self.pos < data.len() && data[self.pos].is_ascii_whitespace() {
+                self.pos += 1;
+            }
+            if self.pos < data.len() && data[self.pos] == b'#' {
+                while self.pos < data.len() && data[self.pos] != b'\n' {
+                    self.pos += 1;
+                }
+                continue;
+            }
+            break;
+        }
+        let start = self.pos;
+        while self.pos < data.len() && !data[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            return Err("unexpected end of the file".to_owned());
+        }
+        std::str::from_utf8(&data[start..self.pos]).map_err(|e| e.to_string())
+    }
+
+    fn number(&mut self) -> Result<usize, String> {
+        let token = self.next()?;
+        token.parse().map_err(|_| format!("bad number {:?}", token))
+    }
+}
+
+/// Binary (P5, P6) and ASCII (P2, P3) graymaps and pixmaps, colors are converted to luminance
+fn parse_netpbm(data: &[u8]) -> Result<Samples, String> {
+    let mut tokens = Tokens { data, pos: 0 };
+    let magic = tokens.next()?;
+    let (channels, binary) = match magic {
+        "P2" => (1, false),
+        "P3" => (3, false),
+        "P5" => (1, true),
+        "P6" => (3, true),
+        _ => {
+            return Err(format!(
+                "unsupported image type {:?}, expected P2, P3, P5 or P6",
+                magic
+            ))
+        }
+    };
+    let (width, height, max_value) = (tokens.number()?, tokens.number()?, tokens.number()?);
+    if max_value == 0 || max_value > u16::MAX as usize {
+        return Err(format!("bad maximum value {}", max_value));
+    }
+    let count = checked_product(&[width, height, channels])?;
+
+    let raw = if binary {
+        // A single whitespace byte separates the header from the pixels
+        let body = &data[(tokens.pos + 1).min(data.len())..];
+        let ty = if max_value > 255 {
+            SampleType::U16
+        } else {
+            SampleType::U8
+        };
+        if body.len() < checked_product(&[count, ty.bytes()])? {
+            return Err("the image is truncated".to_owned());
+        }
+        body.chunks_exact(ty.bytes())
+            .take(count)
+            .map(|bytes| ty.decode(bytes, false))
+            .collect::<Vec<_>>()
+    } else {
+        (0..count)
+            .map(|_| tokens.number().map(|v| v as f64))
+            .collect::<Result<Vec<_>, _>>()?
+    };
+    let luminance = raw
+        .chunks_exact(channels)
+        .map(|c| match c {
+            [r, g, b] => 0.2126 * r + 0.7152 * g + 0.0722 * b,
+            _ => c[0],
+        })
+        .collect();
+    Samples::new([width, height, 1], luminance)
+}
+
+/// NRRD files with the raw data attached after the header
+fn parse_nrrd(data: &[u8]) -> Result<Samples, String> {
+    if !data.starts_with(b"NRRD") {
+        return Err("missing the NRRD magic".to_owned());
+    }
+    let mut ty = None;
+    let mut sizes = Vec::new();
+    let mut little_endian = true;
+    let mut pos = 0;
+    loop {
+        let end = data[pos..]
+            .iter()
+            .position(|b| *b == b'\n')
+            .map(|i| pos + i)
+            .ok_or("the header doesn't end")?;
+        let line = std::str::from_utf8(&data[pos..end])
+            .map_err(|e| e.to_string())?
+            .trim_end_matches('\r');
+        pos = end + 1;
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with('#') || line.starts_with("NRRD") {
+            continue;
+        }
+        let Some((field, value)) = line.split_once(": ") else {
+            // Key/value pairs use := and don't matter here
+            continue;
+        };
+        let value = value.trim();
+        match field {
+            "type" => {
+                ty = Some(
+                    SampleType::from_nrrd(value)
+                        .ok_or_else(|| format!("unsupported sample type {:?}", value))?,
+                )
+            }
+            "sizes" => {
+                sizes = value
+                    .split_whitespace()
+                    .map(|s| s.parse::<usize>().map_err(|e| e.to_string()))
+                    .collect::<Result<_, _>>()?
+            }
+            "endian" => little_endian = value == "little",
+            "encoding" if value != "raw" => {
+                return Err(format!("unsupported encoding {:?}, only raw is", value))
+            }
+            "data file" | "datafile" => {
+                return Err("detached data files aren't supported".to_owned())
+            }
+            _ => {}
+        }
+    }
+    let ty = ty.ok_or("the header has no type")?;
+    let size = match sizes[..] {
+        [x, y] => [x, y, 1],
+        [x, y, z] => [x, y, z],
+        _ => return Err(format!("expected 2 or 3 sizes, got {:?}", sizes)),
+    };
+    Samples::from_bytes(size, ty, little_endian, &data[pos..])
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct ImportOptions {
+    // Normalized samples below this stay empty
+    pub threshold: f32,
+    // Voxels per sample along every axis
+    pub scale: f32,
+    // Voxels a white heightmap pixel rises to
+    pub height: u32,
+    pub color: [f32; 3],
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        Self {
+            threshold: 0.5,
+            scale: 1.0,
+            height: 64,
+            color: [0.9, 0.9, 0.9],
+        }
+    }
+}
+
+impl ImportOptions {
+    // Brighter samples give brighter voxels
+    fn voxel(&self, value: f32) -> u32 {
+        let [r, g, b] = self
+            .color
+            .map(|c| ((c * (0.35 + 0.65 * value)).clamp(0.0, 1.0) * 255.0).round() as u32);
+        0xFF000000 | b << 16 | g << 8 | r
+    }
+
+    /// Size of the imported world in voxels
+    fn output_size(&self, samples: &Samples) -> [usize; 3] {
+        let scaled = |size: usize| ((size as f32 * self.scale).ceil() as usize).max(1);
+        if samples.is_image() {
+            [
+                scaled(samples.size[0]),
+                self.height.max(1) as usize,
+                scaled(samples.size[1]),
+            ]
+        } else {
+            samples.size.map(scaled)
+        }
+    }
+
+    fn sample(&self, samples: &Samples, voxel: [usize; 3]) -> f32 {
+        let at = |axis: usize, coord: usize| {
+            ((coord as f32 / self.scale) as usize).min(samples.size[axis] - 1)
+        };
+        if samples.is_image() {
+            samples.get(at(0, voxel[0]), at(1, voxel[2]), 0)
+        } else {
+            samples.get(at(0, voxel[0]), at(1, voxel[1]), at(2, voxel[2]))
+        }
+    }
+
+    /// Every chunk of the box the samples cover, starting at the origin, or an error when more
+    /// than `max_chunks` would be needed
+    fn voxelize(
+        &self,
+        samples: &Samples,
+        max_chunks: usize,
+    ) -> Result<Vec<(glm::IVec3, Vec<u32>)>, String> {
+        let size = self.output_size(samples);
+        let chunk_size = CHUNK_SIZE as usize;
+        let chunk_counts = size.map(|s| s.div_ceil(chunk_size));
+        let total = checked_product(&chunk_counts)?;
+        if total > max_chunks {
+            return Err(format!(
+                "{}x{}x{} voxels need {} chunks, at most {} fit",
+                size[0], size[1], size[2], total, max_chunks
+            ));
+        }
+
+        let mut chunks = HashMap::new();
+        for cx in 0..chunk_counts[0] {
+            for cy in 0..chunk_counts[1] {
+                for cz in 0..chunk_counts[2] {
+                    chunks.insert(
+                        glm::vec3(cx as i32, cy as i32, cz as i32),
+                        vec![0u32; CHUNK_VOLUME],
+                    );
+                }
+            }
+        }
+        let mut set = |voxel: [usize; 3], value: u32| {
+            let pos = voxel.map(|c| (c / chunk_size) as i32);
+            let local = voxel.map(|c| c % chunk_size);
+            let data = chunks.get_mut(&glm::vec3(pos[0], pos[1], pos[2])).unwrap();
+            data[(local[2] * chunk_size + local[1]) * chunk_size + local[0]] = value;
+        };
+
+        for x in 0..size[0] {
+            for z in 0..size[2] {
+                if samples.is_image() {
+                    let value = self.sample(samples, [x, 0, z]);
+                    if value < self.threshold {
+                        continue;
+                    }
+                    let height = ((value * size[1] as f32).round() as usize).clamp(1, size[1]);
+                    for y in 0..height {
+                        set([x, y, z], self.voxel(value));
+                    }
+                } else {
+                    for y in 0..size[1] {
+                        let value = self.sample(samples, [x, y, z]);
+                        if value >= self.threshold {
+                            set([x, y, z], self.voxel(value));
+                        }
+                    }
+                }
+            }
+        }
+        Ok(chunks.into_iter().collect())
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read_file(path: &str) -> Result<Vec<u8>, String> {
+    std::fs::read(path).map_err(|e| e.to_string())
+}
+
+#[cfg(target_arch = "wasm32")]
+fn read_file(_path: &str) -> Result<Vec<u8>, String> {
+    Err("the web build can't read files from disk".to_owned())
+}
+
+/// Import settings and the file they apply to
+pub struct Importer {
+    path: String,
+    // Size and type of headerless raw files
+    raw_size: [usize; 3],
+    raw_type: SampleType,
+    raw_little_endian: bool,
+    pub options: ImportOptions,
+    last_error: Option<String>,
+}
+
+impl Importer {
+    pub fn new() -> Self {
+        Self {
+            path: String::new(),
+            raw_size: [64, 64, 64],
+            raw_type: SampleType::U8,
+            raw_little_endian: true,
+            options: ImportOptions::default(),
+            last_error: None,
+        }
+    }
+
+    fn is_raw(&self) -> bool {
+        !["pgm", "ppm", "pnm", "nrrd"]
+            .iter()
+            .any(|ext| self.path.to_lowercase().ends_with(&format!(".{}", ext)))
+    }
+
+    fn load_samples(&self) -> Result<Samples, String> {
+        let data = read_file(&self.path)?;
+        let lower = self.path.to_lowercase();
+        if lower.ends_with(".nrrd") {
+            parse_nrrd(&data)
+        } else if self.is_raw() {
+            Samples::from_bytes(self.raw_size, self.raw_type, self.raw_little_endian, &data)
+        } else {
+            parse_netpbm(&data)
+        }
+    }
+
+    /// Reads and voxelizes the file, errors are logged and shown until the next import
+    pub fn import(&mut self, max_chunks: usize) -> Option<Vec<(glm::IVec3, Vec<u32>)>> {
+        let result = self
+            .load_samples()
+            .and_then(|samples| self.options.voxelize(&samples, max_chunks));
+        match result {
+            Ok(chunks) => {
+                log::info!("Imported {} into {} chunks", self.path, chunks.len());
+                self.last_error = None;
+                Some(chunks)
+            }
+            Err(e) => {
+                log::warn!("Failed to import {}: {}", self.path, e);
+                self.last_error = Some(e);
+                None
+            }
+        }
+    }
+
+    /// Returns the chunks to replace the world with once an import succeeds
+    pub fn ui(
+        &mut self,
+        ui: &mut egui::Ui,
+        max_chunks: usize,
+    ) -> Option<Vec<(glm::IVec3, Vec<u32>)>> {
+        let mut imported = None;
+        ui.collapsing("Import", |ui| {
+            if cfg!(target_arch = "wasm32") {
+                ui.label("Importing reads files from disk, which the web build can't");
+                return;
+            }
+            ui.horizontal(|ui| {
+                ui.label("File");
+                ui.text_edit_singleline(&mut self.path).on_hover_text(
+                    ".pgm/.ppm images become heightmaps, .nrrd and raw files volumes",
+                );
+            });
+            if self.is_raw() {
+                ui.horizontal(|ui| {
+                    ui.label("Raw size");
+                    for size in &mut self.raw_size {
+                        ui.add(egui::DragValue::new(size).clamp_range(1..=4096));
+                    }
+                });
+                ui.horizontal(|ui| {
+                    for ty in SampleType::RAW {
+                        ui.radio_value(&mut self.raw_type, ty, ty.name());
+                    }
+                    ui.checkbox(&mut self.raw_little_endian, "Little endian");
+                });
+            }
+            let options = &mut self.options;
+            ui.add(egui::Slider::new(&mut options.threshold, 0.0..=1.0).text("Threshold"));
+            ui.add(
+                egui::Slider::new(&mut options.scale, 0.125..=8.0)
+                    .logarithmic(true)
+                    .text("Voxels per sample"),
+            );
+            ui.add(egui::Slider::new(&mut options.height, 1..=1024).text("Heightmap height"));
+            ui.horizontal(|ui| {
+                ui.label("Color");
+                ui.color_edit_button_rgb(&mut options.color);
+            });
+            if ui
+                .add_enabled(!self.path.is_empty(), egui::Button::new("Replace world"))
+                .clicked()
+            {
+                imported = self.import(max_chunks);
+            }
+            if let Some(e) = &self.last_error {
+                ui.colored_label(ui.visuals().warn_fg_color, e);
+            }
+        });
+        imported
+    }
+}
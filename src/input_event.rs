@@ -0,0 +1,58 @@
+use winit::event::{ElementState, MouseScrollDelta, WindowEvent};
+use winit::keyboard::{KeyCode, PhysicalKey};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle,
+    Other(u16),
+}
+
+/// Input as seen by the game, independent of where it came from. The winit layer translates into
+/// this, and recorded replays or scripts can construct it directly. Keys reuse winit's `KeyCode`,
+/// which is plain data and needs no window.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum InputEvent {
+    Key { key: KeyCode, pressed: bool },
+    MouseButton { button: MouseButton, pressed: bool },
+    // Scroll amount in lines
+    Wheel { x: f32, y: f32 },
+    // Mouse motion, only delivered while the cursor is locked or while dragging to look
+    MouseMotion { dx: f64, dy: f64 },
+}
+
+impl InputEvent {
+    pub fn from_window_event(event: &WindowEvent) -> Option<Self> {
+        match event {
+            WindowEvent::KeyboardInput {
+                event:
+                    winit::event::KeyEvent {
+                        physical_key: PhysicalKey::Code(key),
+                        state,
+                        ..
+                    },
+                ..
+            } => Some(Self::Key {
+                key: *key,
+                pressed: *state == ElementState::Pressed,
+            }),
+            WindowEvent::MouseInput { state, button, .. } => Some(Self::MouseButton {
+                button: match button {
+                    winit::event::MouseButton::Left => MouseButton::Left,
+                    winit::event::MouseButton::Right => MouseButton::Right,
+                    winit::event::MouseButton::Middle => MouseButton::Middle,
+                    winit::event::MouseButton::Back => MouseButton::Other(3),
+                    winit::event::MouseButton::Forward => MouseButton::Other(4),
+                    winit::event::MouseButton::Other(other) => MouseButton::Other(*other),
+                },
+                pressed: *state == ElementState::Pressed,
+            }),
+            WindowEvent::MouseWheel {
+                delta: MouseScrollDelta::LineDelta(x, y),
+                ..
+            } => Some(Self::Wheel { x: *x, y: *y }),
+            _ => None,
+        }
+    }
+}
@@ -24,6 +24,10 @@ impl KeyTracker {
         self.keys_pressed.contains(&key)
     }
 
+    pub fn any_pressed(&self) -> bool {
+        !self.keys_pressed.is_empty()
+    }
+
     pub fn reset(&mut self) {
         self.keys_pressed.clear();
     }
@@ -1,31 +1,87 @@
+mod about;
+mod annotations;
+mod asset_browser;
+mod assets;
+mod autotune;
+mod bookmarks;
+mod camera;
+mod camera_path;
 mod chunk;
 mod chunk_datastore;
 mod chunk_manager;
+mod chunk_priority;
+mod clipboard;
+mod containment;
+mod demo_mode;
+mod distance_throttle;
+mod engine_config;
+mod event_log;
+#[cfg(feature = "recording")]
+mod frame_capture;
 mod game;
 mod gpu_stage;
+#[cfg(target_arch = "wasm32")]
+#[doc(hidden)]
+pub mod headless;
+mod importer;
+mod input_event;
 mod key_tracker;
+mod materials;
+mod neighborhood;
+mod portals;
 mod profiler;
 mod resource_size_helper;
+mod resource_tracker;
+mod rng;
+mod rule_parser;
+mod rules;
+mod selection;
+mod settings;
+mod shader_prep;
+#[cfg(not(target_arch = "wasm32"))]
+mod shader_reload;
+mod snapshots;
+mod staging;
+mod startup_screen;
+#[cfg(all(feature = "stats-server", not(target_arch = "wasm32")))]
+mod stats_server;
+mod storage;
+mod theme;
+mod thumbnail;
+mod tick_schedule;
+mod tool_window;
 mod user_event;
 mod util;
+mod validation;
 mod wgpu_context;
+mod workspace;
+mod world_file;
+mod worldgen;
 
+pub use crate::engine_config::{EngineConfig, EngineConfigBuilder, RenderConfig, RuleConfig};
 use crate::game::Game;
+pub use crate::game::StageToggles;
+pub use crate::gpu_stage::meshing_render::Translucency;
+pub use crate::gpu_stage::simulate::SimulationMode;
+use crate::input_event::InputEvent;
+use crate::tool_window::DetachedWindow;
 use crate::user_event::UserEvent;
 use crate::wgpu_context::WgpuContext;
 use egui::ViewportId;
 
 use std::sync::Arc;
-use winit::dpi::PhysicalSize;
-use winit::event_loop::EventLoopBuilder;
+use std::time::Duration;
+use winit::dpi::{PhysicalPosition, PhysicalSize};
+use winit::event::StartCause;
+use winit::event_loop::{ControlFlow, EventLoopBuilder};
 use winit::window::CursorGrabMode;
 use winit::{
-    event::{Event, WindowEvent},
+    event::{ElementState, Event, MouseButton, WindowEvent},
     window::WindowBuilder,
 };
 
 pub struct FinalDrawResources {
-    pub bind_group: wgpu::BindGroup,
+    pub bind_group: resource_tracker::Tracked<wgpu::BindGroup>,
     pub pipeline: wgpu::RenderPipeline,
 }
 
@@ -45,46 +101,84 @@ impl egui_wgpu::CallbackTrait for GamePaintCallback {
     }
 }
 
-pub async fn start() {
-    let event_loop = EventLoopBuilder::<UserEvent>::with_user_event()
-        .build()
-        .unwrap();
-    let event_loop_proxy = event_loop.create_proxy();
-
-    let window = WindowBuilder::new()
-        .with_title("CellularAutomata3d")
-        .build(&event_loop)
-        .unwrap();
-
+/// Control flow while idle in power saving mode, waking up for egui's delayed repaints
+fn idle_control_flow(repaint_delay: Duration) -> ControlFlow {
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(deadline) = std::time::Instant::now().checked_add(repaint_delay) {
+        return ControlFlow::WaitUntil(deadline);
+    }
+    // Timed wakeups aren't used on the web, the browser drives redraws
     #[cfg(target_arch = "wasm32")]
-    add_canvas_to_body(&window, event_loop_proxy.clone());
-
-    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
-        backends: wgpu::Backends::all(),
-        ..wgpu::InstanceDescriptor::default()
-    });
+    let _ = repaint_delay;
+    ControlFlow::Wait
+}
 
-    let surface = instance
-        .create_surface(&window)
-        .expect("Could not create surface");
+/// Startup options, from the command line on native
+#[derive(Default)]
+pub struct StartOptions {
+    // Only run the core stages, for drivers that have trouble with the others
+    pub safe_mode: bool,
+    // Refuse to start when the config doesn't fit the device limits instead of working around it
+    pub strict: bool,
+    // Address to serve engine stats on, with the stats-server feature
+    pub stats_addr: Option<String>,
+    // Applied over the config saved in the settings
+    pub config: EngineConfigBuilder,
+    // Run seed to start with instead of a random one
+    pub seed: Option<u64>,
+}
 
-    let adapter = instance
-        .request_adapter(&wgpu::RequestAdapterOptions {
-            power_preference: wgpu::PowerPreference::HighPerformance,
-            force_fallback_adapter: false,
-            compatible_surface: Some(&surface),
-        })
-        .await
-        .expect("Could not create adapter");
+impl StartOptions {
+    pub fn from_args(args: impl Iterator<Item = String>) -> Self {
+        let mut options = Self::default();
+        for arg in args {
+            match arg.as_str() {
+                "--safe-mode" => options.safe_mode = true,
+                "--strict" => options.strict = true,
+                _ if arg.starts_with("--stats-addr=") => {
+                    options.stats_addr = Some(arg["--stats-addr=".len()..].to_owned());
+                }
+                _ if arg.starts_with("--world-size=") => {
+                    match arg["--world-size=".len()..].parse() {
+                        Ok(size) => options.config = options.config.world_size(size),
+                        Err(_) => log::warn!("Ignoring invalid {:?}", arg),
+                    }
+                }
+                _ if arg.starts_with("--seed=") => match arg["--seed=".len()..].parse() {
+                    Ok(seed) => options.seed = Some(seed),
+                    Err(_) => log::warn!("Ignoring invalid {:?}", arg),
+                },
+                _ if arg.starts_with("--chunks-per-group=") => {
+                    match arg["--chunks-per-group=".len()..].parse() {
+                        Ok(n) => options.config = options.config.chunks_per_group(n),
+                        Err(_) => log::warn!("Ignoring invalid {:?}", arg),
+                    }
+                }
+                _ => log::warn!("Ignoring unknown argument {:?}", arg),
+            }
+        }
+        options
+    }
+}
 
-    let (device, queue) = adapter
+/// The device with the features and limits the engine relies on, shared with the headless
+/// harness so the tests run under the same limits as the app
+async fn request_device(
+    adapter: &wgpu::Adapter,
+) -> Result<(wgpu::Device, wgpu::Queue), wgpu::RequestDeviceError> {
+    adapter
         .request_device(
             &wgpu::DeviceDescriptor {
                 label: Some("device"),
                 required_features: if cfg!(target_arch = "wasm32") {
                     wgpu::Features::default()
                 } else {
-                    wgpu::Features::TIMESTAMP_QUERY
+                    // Timestamps are only for the GPU timings in the profiler
+                    (if cfg!(feature = "profiler-gpu") {
+                        wgpu::Features::TIMESTAMP_QUERY
+                    } else {
+                        wgpu::Features::empty()
+                    })
                         | wgpu::Features::STORAGE_RESOURCE_BINDING_ARRAY
                         | wgpu::Features::TEXTURE_BINDING_ARRAY
                     | wgpu::Features::SAMPLED_TEXTURE_AND_STORAGE_BUFFER_ARRAY_NON_UNIFORM_INDEXING
@@ -115,15 +209,46 @@ pub async fn start() {
             None,
         )
         .await
+}
+
+pub async fn start(options: StartOptions) {
+    let event_loop = EventLoopBuilder::<UserEvent>::with_user_event()
+        .build()
+        .unwrap();
+    let event_loop_proxy = event_loop.create_proxy();
+
+    let window = WindowBuilder::new()
+        .with_title("CellularAutomata3d")
+        .build(&event_loop)
+        .unwrap();
+
+    #[cfg(target_arch = "wasm32")]
+    add_canvas_to_body(&window, event_loop_proxy.clone());
+
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+        backends: wgpu::Backends::all(),
+        ..wgpu::InstanceDescriptor::default()
+    });
+
+    let surface = instance
+        .create_surface(&window)
+        .expect("Could not create surface");
+
+    let adapter = instance
+        .request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            force_fallback_adapter: false,
+            compatible_surface: Some(&surface),
+        })
+        .await
+        .expect("Could not create adapter");
+
+    let (device, queue) = request_device(&adapter)
+        .await
         .expect("Could not create device");
 
     let surface_caps = surface.get_capabilities(&adapter);
-    let surface_format = surface_caps
-        .formats
-        .iter()
-        .copied()
-        .find(|format| format.is_srgb())
-        .unwrap_or(surface_caps.formats[0]);
+    let surface_format = WgpuContext::preferred_surface_format(&surface_caps);
 
     log::info!("Surface format: {:?}", surface_format);
 
@@ -138,7 +263,9 @@ pub async fn start() {
     .collect::<Vec<_>>();
 
     let surface_config = wgpu::SurfaceConfiguration {
-        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        // Copying from the surface captures screenshots with the GUI, where it's supported
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+            | (surface_caps.usages & wgpu::TextureUsages::COPY_SRC),
         format: surface_format,
         width: window.inner_size().width,
         height: window.inner_size().height,
@@ -150,8 +277,13 @@ pub async fn start() {
     surface.configure(&device, &surface_config);
 
     let mut requested_surface_size: Option<PhysicalSize<u32>> = None;
+    let mut surface_caps_stale = false;
 
-    let profiler = profiler::Profiler::new(&device, &queue, cfg!(target_arch = "wasm32"));
+    let profiler = profiler::Profiler::new(
+        &device,
+        &queue,
+        cfg!(target_arch = "wasm32") || !cfg!(feature = "profiler-gpu"),
+    );
     let mut ctx = WgpuContext {
         surface,
         adapter,
@@ -161,6 +293,7 @@ pub async fn start() {
         surface_format,
         surface_config,
         profiler,
+        staging: staging::StagingRing::new(),
     };
 
     let mut egui_state = egui_winit::State::new(
@@ -172,19 +305,83 @@ pub async fn start() {
     );
     let mut egui_renderer = egui_wgpu::Renderer::new(&ctx.device, surface_format, None, 1);
     let mut cursor_locked = false;
+    // Right drag to look, the cursor stays visible and egui doesn't see the drag
+    let mut drag_looking = false;
+    let mut last_cursor_position: Option<PhysicalPosition<f64>> = None;
+    let mut redraw_pending = true;
+    let mut repaint_delay = Duration::MAX;
+
+    let mut game = {
+        let mut startup =
+            startup_screen::StartupScreen::new(&window, &mut egui_state, &mut egui_renderer);
+        profiler::log_duration("Game::new", || {
+            Game::new(
+                &ctx,
+                &mut startup,
+                options.safe_mode,
+                options.strict,
+                &options.config,
+                options.seed,
+            )
+        })
+    };
+    game.set_refresh_rate(
+        window
+            .current_monitor()
+            .and_then(|monitor| monitor.refresh_rate_millihertz()),
+    );
+    let mut detached_windows: Vec<DetachedWindow> = Vec::new();
 
-    let mut game = Game::new(&ctx);
+    #[cfg(all(feature = "stats-server", not(target_arch = "wasm32")))]
+    let mut stats_server = options.stats_addr.as_deref().and_then(|addr| {
+        stats_server::StatsServer::start(addr)
+            .map_err(|e| log::error!("Could not serve stats on {}: {}", addr, e))
+            .ok()
+    });
+    #[cfg(not(all(feature = "stats-server", not(target_arch = "wasm32"))))]
+    if options.stats_addr.is_some() {
+        log::warn!("Built without the stats-server feature, --stats-addr is ignored");
+    }
 
     event_loop
         .run(|event, elwt| {
+            if let Event::UserEvent(_) = event {
+                redraw_pending = true;
+            }
             match event {
-                Event::WindowEvent { window_id, event } if window_id == window.id() => {
+                Event::WindowEvent { window_id, event } if window_id != window.id() => {
+                    let Some(index) = detached_windows.iter().position(|w| w.id() == window_id)
+                    else {
+                        return;
+                    };
+                    let detached = &mut detached_windows[index];
+                    let tool = detached.tool;
+                    if let WindowEvent::RedrawRequested = event {
+                        // Redrawing the main window redraws this one again
+                        redraw_pending |= detached.redraw(&ctx, |ui| {
+                            game.tool_window_ui(tool, ui, &ctx, &event_loop_proxy);
+                        });
+                        return;
+                    }
+                    if !detached.window_event(&ctx, &event) {
+                        game.set_detached(tool, false);
+                        detached_windows.remove(index);
+                    }
+                    // Changes made in a tool window show up in the main window
+                    redraw_pending = true;
+                }
+                Event::WindowEvent { event, .. } => {
+                    if !matches!(event, WindowEvent::RedrawRequested) {
+                        redraw_pending = true;
+                    }
                     if cursor_locked {
                         use WindowEvent::*;
                         match event {
                             KeyboardInput { .. } | MouseInput { .. } | MouseWheel { .. } => {
                                 // Send these inputs to the game
-                                game.input(&event, &event_loop_proxy);
+                                if let Some(input) = InputEvent::from_window_event(&event) {
+                                    game.input(&input, &event_loop_proxy);
+                                }
                                 return;
                             }
                             CursorMoved { .. } | AxisMotion { .. } => {
@@ -198,12 +395,77 @@ pub async fn start() {
                             _ => (),
                         }
                     }
+                    if let WindowEvent::CursorMoved { position, .. } = event {
+                        let last = last_cursor_position.replace(position);
+                        game.pointer_update(Some((position.x, position.y)));
+                        if drag_looking {
+                            if let Some(last) = last {
+                                game.input(
+                                    &InputEvent::MouseMotion {
+                                        dx: position.x - last.x,
+                                        dy: position.y - last.y,
+                                    },
+                                    &event_loop_proxy,
+                                );
+                            }
+                            return;
+                        }
+                    }
+                    if let WindowEvent::MouseInput {
+                        state: ElementState::Pressed,
+                        button: MouseButton::Right,
+                        ..
+                    } = event
+                    {
+                        if !cursor_locked
+                            && game.look_mode().drags()
+                            && !egui_state.egui_ctx().is_pointer_over_area()
+                        {
+                            drag_looking = true;
+                            return;
+                        }
+                    }
+                    if drag_looking {
+                        use WindowEvent::*;
+                        match event {
+                            MouseInput {
+                                state: ElementState::Released,
+                                button: MouseButton::Right,
+                                ..
+                            } => {
+                                drag_looking = false;
+                                game.drag_look_update(false);
+                                return;
+                            }
+                            KeyboardInput { .. } | MouseInput { .. } | MouseWheel { .. } => {
+                                if let Some(input) = InputEvent::from_window_event(&event) {
+                                    game.input(&input, &event_loop_proxy);
+                                }
+                                return;
+                            }
+                            CursorLeft { .. } | Focused(false) => {
+                                drag_looking = false;
+                                last_cursor_position = None;
+                                game.drag_look_update(false);
+                            }
+                            _ => (),
+                        }
+                    }
                     let response = egui_state.on_window_event(&window, &event);
                     if !response.consumed {
                         match event {
                             WindowEvent::Resized(size) => {
                                 requested_surface_size = Some(size);
                             }
+                            WindowEvent::Moved(_) | WindowEvent::ScaleFactorChanged { .. } => {
+                                // Possibly on another monitor now
+                                surface_caps_stale = true;
+                                game.set_refresh_rate(
+                                    window
+                                        .current_monitor()
+                                        .and_then(|monitor| monitor.refresh_rate_millihertz()),
+                                );
+                            }
                             WindowEvent::CloseRequested => {
                                 elwt.exit();
                             }
@@ -211,12 +473,25 @@ pub async fn start() {
                         }
                     }
                     if let WindowEvent::RedrawRequested = event {
+                        let format_changed = surface_caps_stale && ctx.update_surface_caps();
+                        surface_caps_stale = false;
+                        if format_changed {
+                            // The egui pipeline bakes in the format, and its textures go with it
+                            egui_renderer =
+                                egui_wgpu::Renderer::new(&ctx.device, ctx.surface_format, None, 1);
+                            egui_state
+                                .egui_ctx()
+                                .set_fonts(egui::FontDefinitions::default());
+                            game.reload_gui_textures();
+                        }
+                        let resized = requested_surface_size.is_some();
                         if let Some(size) = requested_surface_size.take() {
                             ctx.surface_config.width = size.width;
                             ctx.surface_config.height = size.height;
-                            ctx.surface.configure(&ctx.device, &ctx.surface_config);
+                        }
+                        if resized || format_changed {
+                            ctx.configure_surface();
                             game.resize(&ctx);
-                            requested_surface_size = None;
                         }
                         let output = ctx.surface.get_current_texture();
                         let mut encoder =
@@ -227,6 +502,7 @@ pub async fn start() {
                         match output {
                             Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
                                 requested_surface_size = Some(window.inner_size());
+                                surface_caps_stale = true;
                                 log::warn!("get_current_texture() Lost/Outdated");
                             }
                             Err(wgpu::SurfaceError::OutOfMemory) => {
@@ -237,7 +513,14 @@ pub async fn start() {
                                 log::warn!("get_current_texture() Timeout")
                             }
                             Ok(surface_texture) => {
-                                ctx.device.poll(wgpu::Maintain::Wait);
+                                // Waiting would finish the separately submitted simulation
+                                // before every frame, which keeps it from overlapping the
+                                // rendering, so only its callbacks are run then
+                                ctx.device.poll(if game.simulate.separate_submission {
+                                    wgpu::Maintain::Poll
+                                } else {
+                                    wgpu::Maintain::Wait
+                                });
 
                                 let surface_view = surface_texture
                                     .texture
@@ -246,9 +529,15 @@ pub async fn start() {
                                 ctx.profiler.gather_prev_frame_info(&ctx.device);
 
                                 ctx.profiler.begin_frame(&mut encoder);
+                                ctx.staging.begin_frame();
 
                                 game.update(&ctx, &mut encoder);
 
+                                #[cfg(all(feature = "stats-server", not(target_arch = "wasm32")))]
+                                if let Some(stats_server) = &mut stats_server {
+                                    stats_server.publish(game.engine_stats(&ctx));
+                                }
+
                                 egui_renderer
                                     .callback_resources
                                     .insert(game.final_draw_resources());
@@ -287,13 +576,17 @@ pub async fn start() {
                                         .response
                                         .interact(egui::Sense::click());
 
-                                    if response.clicked() {
+                                    if response.clicked() && game.look_mode().locks_on_click() {
                                         let _ = event_loop_proxy
                                             .send_event(UserEvent::RequestCursorLock(true));
                                     }
                                 });
                                 egui_state
                                     .handle_platform_output(&window, full_output.platform_output);
+                                repaint_delay = full_output
+                                    .viewport_output
+                                    .get(&ViewportId::ROOT)
+                                    .map_or(Duration::MAX, |output| output.repaint_delay);
 
                                 let pixels_per_point = egui_state.egui_ctx().pixels_per_point();
 
@@ -324,6 +617,7 @@ pub async fn start() {
                                     &clipped_primitives,
                                     &screen_descriptor,
                                 );
+                                ctx.profiler.marker(&mut encoder, "gui");
                                 {
                                     let mut rpass =
                                         encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
@@ -351,15 +645,20 @@ pub async fn start() {
                                         &screen_descriptor,
                                     );
                                 }
+                                #[cfg(feature = "recording")]
+                                game.capture_surface(&ctx, &mut encoder, &surface_texture.texture);
                                 for t in &full_output.textures_delta.free {
                                     egui_renderer.free_texture(t);
                                 }
 
                                 ctx.profiler.end_frame(&mut encoder);
-                                ctx.queue.submit(Some(encoder.finish()));
+                                ctx.submit(Some(encoder.finish()));
                                 ctx.profiler.after_submit();
                                 game.after_submit();
                                 surface_texture.present();
+                                for detached in &detached_windows {
+                                    detached.request_redraw();
+                                }
                             }
                         }
                     }
@@ -369,7 +668,14 @@ pub async fn start() {
                     ..
                 } => {
                     if cursor_locked {
-                        game.mouse_motion(delta.0, delta.1);
+                        game.input(
+                            &InputEvent::MouseMotion {
+                                dx: delta.0,
+                                dy: delta.1,
+                            },
+                            &event_loop_proxy,
+                        );
+                        redraw_pending = true;
                     }
                 }
                 Event::UserEvent(UserEvent::RequestCursorLock(locked)) => {
@@ -398,8 +704,31 @@ pub async fn start() {
                 Event::UserEvent(UserEvent::RequestResize) => {
                     game.resize(&ctx);
                 }
+                Event::UserEvent(UserEvent::RequestPlaneMode(plane)) => {
+                    game.set_plane_mode(&ctx, plane);
+                }
+                Event::UserEvent(UserEvent::RequestReseed) => {
+                    game.reseed_world(&ctx);
+                }
+                Event::UserEvent(UserEvent::DetachToolWindow(tool)) => {
+                    if detached_windows.iter().all(|w| w.tool != tool) {
+                        if let Some(detached) = DetachedWindow::new(elwt, &instance, &ctx, tool) {
+                            detached_windows.push(detached);
+                            game.set_detached(tool, true);
+                        }
+                    }
+                }
+                Event::NewEvents(StartCause::ResumeTimeReached { .. }) => {
+                    redraw_pending = true;
+                }
                 Event::AboutToWait => {
-                    window.request_redraw();
+                    if redraw_pending || repaint_delay.is_zero() || game.wants_continuous_redraw() {
+                        redraw_pending = false;
+                        elwt.set_control_flow(ControlFlow::Poll);
+                        window.request_redraw();
+                    } else {
+                        elwt.set_control_flow(idle_control_flow(repaint_delay));
+                    }
                 }
                 _ => (),
             }
@@ -412,7 +741,7 @@ pub async fn start() {
 pub async fn wasm_start() {
     std::panic::set_hook(Box::new(console_error_panic_hook::hook));
     console_log::init_with_level(log::Level::Trace).expect("Failed to initialize logger");
-    start().await;
+    start(StartOptions::default()).await;
 }
 
 #[cfg(target_arch = "wasm32")]
@@ -1,4 +1,4 @@
-use ca3d::start;
+use ca3d::{start, StartOptions};
 use std::env;
 
 #[cfg(not(target_arch = "wasm32"))]
@@ -7,5 +7,5 @@ fn main() {
         env::set_var("RUST_LOG", "info")
     }
     env_logger::init();
-    pollster::block_on(start());
+    pollster::block_on(start(StartOptions::from_args(env::args().skip(1))));
 }
@@ -0,0 +1,53 @@
+use crate::shader_prep::ShaderPrep;
+
+/// Cells of the powder mode. A material is a cell holding exactly its color, so painting it with
+/// the brush is all it takes and any other color is a wall that never moves.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Material {
+    Sand,
+    Water,
+    Fire,
+    Smoke,
+}
+
+impl Material {
+    pub const ALL: [Material; 4] = [
+        Material::Sand,
+        Material::Water,
+        Material::Fire,
+        Material::Smoke,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Material::Sand => "Sand",
+            Material::Water => "Water",
+            Material::Fire => "Fire",
+            Material::Smoke => "Smoke",
+        }
+    }
+
+    /// Packed RGBA like every other cell, water and smoke are see-through
+    pub fn value(&self) -> u32 {
+        match self {
+            Material::Sand => 0xFF4FB8E0,
+            Material::Water => 0xB0E07830,
+            Material::Fire => 0xFF1060FF,
+            Material::Smoke => 0x60505050,
+        }
+    }
+
+    pub fn from_value(value: u32) -> Option<Self> {
+        Self::ALL.into_iter().find(|m| m.value() == value)
+    }
+
+    /// Defines MATERIAL_SAND and friends
+    pub fn define_all(prep: ShaderPrep) -> ShaderPrep {
+        Self::ALL.into_iter().fold(prep, |prep, material| {
+            prep.define(
+                &format!("MATERIAL_{}", material.name().to_uppercase()),
+                material.value(),
+            )
+        })
+    }
+}
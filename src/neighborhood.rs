@@ -0,0 +1,348 @@
+use std::collections::HashMap;
+
+use nalgebra_glm as glm;
+
+/// Furthest a neighbor can be from the cell on any axis
+pub const MAX_RADIUS: i32 = 2;
+/// Every cell of a radius 2 cube besides the center
+pub const MAX_OFFSETS: usize = ((2 * MAX_RADIUS + 1).pow(3) - 1) as usize;
+/// Largest weight a single neighbor can have either way
+pub const MAX_WEIGHT: i32 = 8;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum NeighborhoodShape {
+    // Every cell within `radius` on all axes
+    Moore,
+    // Every cell at most `radius` steps away along the axes, the 6 face neighbors at radius 1
+    VonNeumann,
+    // Offsets typed in by hand
+    Custom,
+}
+
+/// The cells a larger than life rule counts, uploaded as a list of offsets so the shader doesn't
+/// care about the shape. Every offset carries a weight that a live neighbor there adds to the
+/// count, which lets rules favor some directions over others.
+pub struct Neighborhood {
+    pub shape: NeighborhoodShape,
+    pub radius: i32,
+    custom: Vec<glm::IVec3>,
+    // Offsets that don't count as 1, kept when they drop out of the neighborhood
+    weights: HashMap<glm::IVec3, i32>,
+    custom_input: String,
+    custom_error: Option<String>,
+    // Set when the offsets changed since they were last uploaded
+    dirty: bool,
+}
+
+/// Parses offsets written as "x,y,z" separated by semicolons or new lines
+pub fn parse_offsets(input: &str) -> Result<Vec<glm::IVec3>, String> {
+    let mut offsets = Vec::new();
+    for entry in input.split([';', '\n']).map(str::trim) {
+        if entry.is_empty() {
+            continue;
+        }
+        let parts = entry
+            .split(',')
+            .map(|v| v.trim().parse::<i32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("{:?}: {}", entry, e))?;
+        let [x, y, z] = parts[..] else {
+            return Err(format!("{:?} isn't three numbers", entry));
+        };
+        let offset = glm::vec3(x, y, z);
+        if offset == glm::IVec3::zeros() {
+            return Err("The cell itself can't be its own neighbor".to_owned());
+        }
+        if offset.abs().max() > MAX_RADIUS {
+            return Err(format!(
+                "{:?} is further than {} cells away",
+                entry, MAX_RADIUS
+            ));
+        }
+        if !offsets.contains(&offset) {
+            offsets.push(offset);
+        }
+    }
+    Ok(offsets)
+}
+
+impl Neighborhood {
+    pub fn new() -> Self {
+        let custom = vec![
+            glm::vec3(2, 0, 0),
+            glm::vec3(-2, 0, 0),
+            glm::vec3(0, 2, 0),
+            glm::vec3(0, -2, 0),
+            glm::vec3(0, 0, 2),
+            glm::vec3(0, 0, -2),
+        ];
+        Self {
+            shape: NeighborhoodShape::Moore,
+            radius: MAX_RADIUS,
+            custom_input: Self::format_offsets(&custom),
+            custom,
+            weights: HashMap::new(),
+            custom_error: None,
+            dirty: true,
+        }
+    }
+
+    fn format_offsets(offsets: &[glm::IVec3]) -> String {
+        offsets
+            .iter()
+            .map(|o| format!("{},{},{}", o.x, o.y, o.z))
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+
+    pub fn offsets(&self) -> Vec<glm::IVec3> {
+        match self.shape {
+            NeighborhoodShape::Moore => {
+                let r = self.radius.clamp(1, MAX_RADIUS);
+                let mut offsets = Vec::new();
+                for z in -r..=r {
+                    for y in -r..=r {
+                        for x in -r..=r {
+                            if (x, y, z) != (0, 0, 0) {
+                                offsets.push(glm::vec3(x, y, z));
+                            }
+                        }
+                    }
+                }
+                offsets
+            }
+            NeighborhoodShape::VonNeumann => {
+                let r = self.radius.clamp(1, MAX_RADIUS);
+                let mut offsets = Vec::new();
+                for z in -r..=r {
+                    for y in -r..=r {
+                        for x in -r..=r {
+                            let steps = x.abs() + y.abs() + z.abs();
+                            if steps != 0 && steps <= r {
+                                offsets.push(glm::vec3(x, y, z));
+                            }
+                        }
+                    }
+                }
+                offsets
+            }
+            NeighborhoodShape::Custom => self.custom.clone(),
+        }
+    }
+
+    pub fn weight(&self, offset: &glm::IVec3) -> i32 {
+        self.weights.get(offset).copied().unwrap_or(1)
+    }
+
+    fn set_weight(&mut self, offset: glm::IVec3, weight: i32) {
+        if weight == 1 {
+            self.weights.remove(&offset);
+        } else {
+            self.weights.insert(offset, weight);
+        }
+        self.dirty = true;
+    }
+
+    /// Highest count a cell can see, when every positively weighted neighbor is alive
+    pub fn max_count(&self) -> u32 {
+        self.offsets()
+            .iter()
+            .map(|o| self.weight(o).max(0) as u32)
+            .sum()
+    }
+
+    pub fn size(&self) -> u32 {
+        match self.shape {
+            NeighborhoodShape::Moore => {
+                let side = 2 * self.radius.clamp(1, MAX_RADIUS) as u32 + 1;
+                side.pow(3) - 1
+            }
+            NeighborhoodShape::VonNeumann => {
+                // Octahedral numbers, without the center
+                let r = self.radius.clamp(1, MAX_RADIUS) as u32;
+                (2 * r + 1) * (2 * r * r + 2 * r + 3) / 3 - 1
+            }
+            NeighborhoodShape::Custom => self.custom.len() as u32,
+        }
+    }
+
+    /// Returns the offsets with their weight in w if they changed since the last call, as the
+    /// shader reads them
+    pub fn take_upload(&mut self) -> Option<Vec<glm::IVec4>> {
+        if !std::mem::take(&mut self.dirty) {
+            return None;
+        }
+        Some(
+            self.offsets()
+                .iter()
+                .map(|o| glm::vec4(o.x, o.y, o.z, self.weight(o)))
+                .collect(),
+        )
+    }
+
+    pub fn invalidate(&mut self) {
+        self.dirty = true;
+    }
+
+    // Stable name used in saved files, custom offsets follow the colon
+    pub fn key(&self) -> String {
+        match self.shape {
+            NeighborhoodShape::Moore => format!("moore{}", self.radius),
+            NeighborhoodShape::VonNeumann => format!("vonneumann{}", self.radius),
+            NeighborhoodShape::Custom => format!("custom:{}", Self::format_offsets(&self.custom)),
+        }
+    }
+
+    // Only the weights that aren't 1, as "x,y,z=w" separated by semicolons
+    pub fn weights_key(&self) -> String {
+        let mut weights = self.weights.iter().collect::<Vec<_>>();
+        weights.sort_by_key(|(o, _)| (o.z, o.y, o.x));
+        weights
+            .iter()
+            .map(|(o, w)| format!("{},{},{}={}", o.x, o.y, o.z, w))
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+
+    pub fn set_weights_from_key(&mut self, key: &str) -> Result<(), String> {
+        let mut weights = HashMap::new();
+        for entry in key.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+            let (offset, weight) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("{:?} has no weight", entry))?;
+            let offset = *parse_offsets(offset)?
+                .first()
+                .ok_or_else(|| format!("{:?} has no offset", entry))?;
+            let weight = weight
+                .trim()
+                .parse::<i32>()
+                .map_err(|e| format!("{:?}: {}", entry, e))?;
+            weights.insert(offset, weight.clamp(-MAX_WEIGHT, MAX_WEIGHT));
+        }
+        self.weights = weights;
+        self.dirty = true;
+        Ok(())
+    }
+
+    pub fn set_from_key(&mut self, key: &str) -> Result<(), String> {
+        if let Some(offsets) = key.strip_prefix("custom:") {
+            self.custom = parse_offsets(offsets)?;
+            self.custom_input = Self::format_offsets(&self.custom);
+            self.custom_error = None;
+            self.shape = NeighborhoodShape::Custom;
+        } else if let Some(radius) = key.strip_prefix("moore") {
+            self.radius = Self::parse_radius(radius)?;
+            self.shape = NeighborhoodShape::Moore;
+        } else if let Some(radius) = key.strip_prefix("vonneumann") {
+            self.radius = Self::parse_radius(radius)?;
+            self.shape = NeighborhoodShape::VonNeumann;
+        } else {
+            return Err(format!("Unknown neighborhood {:?}", key));
+        }
+        self.dirty = true;
+        Ok(())
+    }
+
+    fn parse_radius(radius: &str) -> Result<i32, String> {
+        radius
+            .parse::<i32>()
+            .ok()
+            .filter(|r| (1..=MAX_RADIUS).contains(r))
+            .ok_or_else(|| format!("Invalid neighborhood radius {:?}", radius))
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        let prev = (self.shape, self.radius);
+        ui.horizontal(|ui| {
+            ui.label("Neighborhood");
+            ui.radio_value(&mut self.shape, NeighborhoodShape::Moore, "Moore");
+            ui.radio_value(
+                &mut self.shape,
+                NeighborhoodShape::VonNeumann,
+                "von Neumann",
+            );
+            ui.radio_value(&mut self.shape, NeighborhoodShape::Custom, "Custom");
+        });
+        match self.shape {
+            NeighborhoodShape::Moore | NeighborhoodShape::VonNeumann => {
+                ui.add(egui::Slider::new(&mut self.radius, 1..=MAX_RADIUS).text("Radius"));
+            }
+            NeighborhoodShape::Custom => {
+                let response = ui
+                    .add(
+                        egui::TextEdit::multiline(&mut self.custom_input)
+                            .desired_rows(2)
+                            .hint_text("x,y,z; x,y,z"),
+                    )
+                    .on_hover_text(format!(
+                        "Offsets from the cell, at most {} cells away on every axis",
+                        MAX_RADIUS
+                    ));
+                if response.changed() {
+                    match parse_offsets(&self.custom_input) {
+                        Ok(offsets) => {
+                            self.custom = offsets;
+                            self.custom_error = None;
+                            self.dirty = true;
+                        }
+                        Err(e) => self.custom_error = Some(e),
+                    }
+                }
+                if let Some(error) = &self.custom_error {
+                    ui.colored_label(ui.visuals().error_fg_color, error);
+                }
+            }
+        }
+        if prev != (self.shape, self.radius) {
+            self.dirty = true;
+        }
+        ui.label(format!("{} neighbors", self.size()));
+        self.weights_ui(ui);
+    }
+
+    fn weights_ui(&mut self, ui: &mut egui::Ui) {
+        ui.collapsing("Weights", |ui| {
+            ui.horizontal(|ui| {
+                if ui.button("Uniform").clicked() {
+                    self.weights.clear();
+                    self.dirty = true;
+                }
+                if ui
+                    .button("Gravity")
+                    .on_hover_text("Neighbors above count double and neighbors below not at all")
+                    .clicked()
+                {
+                    for offset in self.offsets() {
+                        self.set_weight(offset, 1 + offset.y.signum());
+                    }
+                }
+            });
+            let offsets = self.offsets();
+            egui::ScrollArea::vertical()
+                .max_height(200.0)
+                .show(ui, |ui| {
+                    egui::Grid::new("neighborhood_weights")
+                        .num_columns(4)
+                        .striped(true)
+                        .show(ui, |ui| {
+                            for (i, offset) in offsets.iter().enumerate() {
+                                let mut weight = self.weight(offset);
+                                ui.label(format!("{}, {}, {}", offset.x, offset.y, offset.z));
+                                if ui
+                                    .add(
+                                        egui::DragValue::new(&mut weight)
+                                            .clamp_range(-MAX_WEIGHT..=MAX_WEIGHT),
+                                    )
+                                    .changed()
+                                {
+                                    self.set_weight(*offset, weight);
+                                }
+                                if i % 2 == 1 {
+                                    ui.end_row();
+                                }
+                            }
+                        });
+                });
+        });
+    }
+}
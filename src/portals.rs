@@ -0,0 +1,271 @@
+use bytemuck::{Pod, Zeroable};
+use nalgebra_glm as glm;
+
+use crate::chunk_manager::ChunkManager;
+
+// Faces in the same order as `dirs` in simulate.wgsl: +x, -x, +y, -y, +z, -z
+const FACE_NAMES: [&str; 6] = ["+X", "-X", "+Y", "-Y", "+Z", "-Z"];
+
+const FLAG_FLIP_U: u32 = 1 << 0;
+const FLAG_FLIP_V: u32 = 1 << 1;
+const FLAG_SWAP_UV: u32 = 1 << 2;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ChunkFace {
+    pub chunk: glm::IVec3,
+    pub face: u32,
+}
+
+/// Links two chunk faces so cells leaving through one enter through the other. The in-face
+/// coordinates run along axes `(axis + 1) % 3` and `(axis + 2) % 3` and are optionally swapped,
+/// then flipped, on the way from `a` to `b`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PortalLink {
+    pub a: ChunkFace,
+    pub b: ChunkFace,
+    pub flip_u: bool,
+    pub flip_v: bool,
+    pub swap_uv: bool,
+}
+
+impl PortalLink {
+    fn flags(&self) -> u32 {
+        let mut flags = 0;
+        if self.flip_u {
+            flags |= FLAG_FLIP_U;
+        }
+        if self.flip_v {
+            flags |= FLAG_FLIP_V;
+        }
+        if self.swap_uv {
+            flags |= FLAG_SWAP_UV;
+        }
+        flags
+    }
+
+    // The same link seen from `b`, flips applied after a swap act on the other axis in reverse
+    fn reversed(&self) -> Self {
+        let (flip_u, flip_v) = if self.swap_uv {
+            (self.flip_v, self.flip_u)
+        } else {
+            (self.flip_u, self.flip_v)
+        };
+        Self {
+            a: self.b,
+            b: self.a,
+            flip_u,
+            flip_v,
+            swap_uv: self.swap_uv,
+        }
+    }
+}
+
+/// One entry per face of every chunk, indexed by `offset * 6 + face`
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable, Default, PartialEq)]
+pub struct PortalEntry {
+    // Offset + 1 of the linked chunk, 0 when the face isn't linked
+    target_chunk: u32,
+    face: u32,
+    flags: u32,
+    _pad0: u32,
+}
+
+pub struct Portals {
+    pub enabled: bool,
+    links: Vec<PortalLink>,
+    table: Vec<PortalEntry>,
+    new_link: PortalLink,
+}
+
+impl Portals {
+    pub fn new() -> Self {
+        let face = ChunkFace {
+            chunk: glm::IVec3::zeros(),
+            face: 0,
+        };
+        Self {
+            enabled: false,
+            links: Vec::new(),
+            table: Vec::new(),
+            new_link: PortalLink {
+                a: face,
+                b: ChunkFace { face: 1, ..face },
+                flip_u: false,
+                flip_v: false,
+                swap_uv: false,
+            },
+        }
+    }
+
+    pub fn add(&mut self, link: PortalLink) {
+        // A face leads to at most one other face
+        self.links.retain(|l| {
+            let faces = [l.a, l.b];
+            !faces.contains(&link.a) && !faces.contains(&link.b)
+        });
+        self.links.push(link);
+    }
+
+    pub fn clear(&mut self) {
+        self.links.clear();
+    }
+
+    /// Links the two outermost layers of chunks along `axis`, flipping the first in-face axis
+    /// when `flip_u` is set. Wrapping x and z gives a torus, flipping one of them a Klein bottle.
+    pub fn wrap(&mut self, chunk_manager: &ChunkManager, axis: usize, flip_u: bool) {
+        let chunks = chunk_manager.chunks();
+        if chunks.is_empty() {
+            return;
+        }
+        let u_axis = (axis + 1) % 3;
+        let min = chunks
+            .keys()
+            .fold(glm::vec3(i32::MAX, i32::MAX, i32::MAX), |a, p| {
+                glm::min2(&a, p)
+            });
+        let max = chunks
+            .keys()
+            .fold(glm::vec3(i32::MIN, i32::MIN, i32::MIN), |a, p| {
+                glm::max2(&a, p)
+            });
+
+        let mut positions = chunks
+            .keys()
+            .filter(|p| p[axis] == max[axis])
+            .copied()
+            .collect::<Vec<_>>();
+        positions.sort_by_key(|p| (p.x, p.y, p.z));
+        for pos in positions {
+            let mut target = pos;
+            target[axis] = min[axis];
+            if flip_u {
+                target[u_axis] = max[u_axis] - (pos[u_axis] - min[u_axis]);
+            }
+            if !chunks.contains_key(&target) {
+                continue;
+            }
+            self.add(PortalLink {
+                a: ChunkFace {
+                    chunk: pos,
+                    face: axis as u32 * 2,
+                },
+                b: ChunkFace {
+                    chunk: target,
+                    face: axis as u32 * 2 + 1,
+                },
+                flip_u,
+                flip_v: false,
+                swap_uv: false,
+            });
+        }
+    }
+
+    /// Forces the next `build_table` to return the table, for a freshly created buffer
+    pub fn invalidate_table(&mut self) {
+        self.table.clear();
+    }
+
+    /// Rebuilds the per face table for the current chunk offsets, returns it only if it changed
+    pub fn build_table(&mut self, chunk_manager: &ChunkManager) -> Option<&[PortalEntry]> {
+        let mut table = vec![PortalEntry::default(); chunk_manager.num_offsets() as usize * 6];
+        let chunks = chunk_manager.chunks();
+        for link in &self.links {
+            for link in [*link, link.reversed()] {
+                let (Some(from), Some(to)) = (chunks.get(&link.a.chunk), chunks.get(&link.b.chunk))
+                else {
+                    continue;
+                };
+                table[from.offset() as usize * 6 + link.a.face as usize] = PortalEntry {
+                    target_chunk: to.offset() + 1,
+                    face: link.b.face,
+                    flags: link.flags(),
+                    ..Default::default()
+                };
+            }
+        }
+        if table == self.table {
+            return None;
+        }
+        self.table = table;
+        Some(&self.table)
+    }
+
+    fn chunk_face_ui(ui: &mut egui::Ui, id: &str, face: &mut ChunkFace) {
+        ui.horizontal(|ui| {
+            ui.add(egui::DragValue::new(&mut face.chunk.x).prefix("x "));
+            ui.add(egui::DragValue::new(&mut face.chunk.y).prefix("y "));
+            ui.add(egui::DragValue::new(&mut face.chunk.z).prefix("z "));
+            egui::ComboBox::from_id_source(id)
+                .selected_text(FACE_NAMES[face.face as usize])
+                .width(48.0)
+                .show_ui(ui, |ui| {
+                    for (i, name) in FACE_NAMES.iter().enumerate() {
+                        ui.selectable_value(&mut face.face, i as u32, *name);
+                    }
+                });
+        });
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui, chunk_manager: &ChunkManager) {
+        ui.collapsing("Portals", |ui| {
+            ui.checkbox(&mut self.enabled, "Enabled")
+                .on_hover_text("Cells leaving a linked chunk face enter through the other face");
+            ui.horizontal(|ui| {
+                if ui.button("Torus").clicked() {
+                    self.clear();
+                    self.wrap(chunk_manager, 0, false);
+                    self.wrap(chunk_manager, 2, false);
+                    self.enabled = true;
+                }
+                if ui.button("Klein bottle").clicked() {
+                    self.clear();
+                    self.wrap(chunk_manager, 0, false);
+                    self.wrap(chunk_manager, 2, true);
+                    self.enabled = true;
+                }
+                if ui.button("Clear").clicked() {
+                    self.clear();
+                }
+            });
+
+            let mut remove = None;
+            egui::ScrollArea::vertical()
+                .max_height(120.0)
+                .show(ui, |ui| {
+                    for (i, link) in self.links.iter().enumerate() {
+                        ui.horizontal(|ui| {
+                            ui.label(format!(
+                                "{:?} {} <-> {:?} {}{}{}{}",
+                                link.a.chunk.as_slice(),
+                                FACE_NAMES[link.a.face as usize],
+                                link.b.chunk.as_slice(),
+                                FACE_NAMES[link.b.face as usize],
+                                if link.swap_uv { " swap" } else { "" },
+                                if link.flip_u { " flip u" } else { "" },
+                                if link.flip_v { " flip v" } else { "" },
+                            ));
+                            if ui.small_button("Remove").clicked() {
+                                remove = Some(i);
+                            }
+                        });
+                    }
+                });
+            if let Some(i) = remove {
+                self.links.remove(i);
+            }
+
+            ui.separator();
+            Self::chunk_face_ui(ui, "portal face a", &mut self.new_link.a);
+            Self::chunk_face_ui(ui, "portal face b", &mut self.new_link.b);
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut self.new_link.swap_uv, "Swap");
+                ui.checkbox(&mut self.new_link.flip_u, "Flip u");
+                ui.checkbox(&mut self.new_link.flip_v, "Flip v");
+                if ui.button("Link").clicked() {
+                    self.add(self.new_link);
+                }
+            });
+        });
+    }
+}
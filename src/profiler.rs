@@ -1,11 +1,20 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
 use std::time::Duration;
 
 use egui::Ui;
 use egui_extras::{Column, TableBuilder};
+use egui_plot::{Line, Plot, PlotPoint, PlotPoints, Points, Text};
 use indexmap::IndexMap;
 use wgpu::*;
 
+// Frames kept for the frame time graph
+const FRAME_HISTORY: usize = 300;
+// A frame this many times slower than the median is a spike
+const SPIKE_FACTOR: f64 = 2.0;
+// Frames faster than this are never spikes, however uneven
+const MIN_SPIKE_MS: f64 = 8.0;
+
 struct CpuTimer {
     #[cfg(target_arch = "wasm32")]
     performance: web_sys::Performance,
@@ -54,6 +63,19 @@ impl CpuTimestamp {
     }
 }
 
+/// Runs `f` and logs how long it took, for one-off work such as startup
+pub fn log_duration<T>(name: &str, f: impl FnOnce() -> T) -> T {
+    let cpu_timer = CpuTimer::new();
+    let start = cpu_timer.now();
+    let ret = f();
+    log::info!(
+        "{} took {:.3} ms",
+        name,
+        cpu_timer.now().elapsed(&start).as_secs_f64() * 1000.0
+    );
+    ret
+}
+
 struct PendingQueryInfo {
     cpu_start: CpuTimestamp,
     cpu_end: Option<CpuTimestamp>,
@@ -67,6 +89,12 @@ pub struct QueryInfo {
     pub gpu: Option<(Duration, Duration)>,
 }
 
+// Time between two frames, and what took longest in the first of them
+struct FrameSample {
+    ms: f64,
+    dominant: Option<(String, f64)>,
+}
+
 struct GpuResources {
     query_set: QuerySet,
     query_buffer: Buffer,
@@ -102,6 +130,8 @@ impl GpuResources {
 
 struct Mutables {
     name_stack: Vec<String>,
+    // Whether each open scope pushed a debug group, the toggle may change in the middle of a frame
+    debug_group_stack: Vec<bool>,
     queries: IndexMap<Vec<String>, PendingQueryInfo>,
     query_index: u32,
 }
@@ -110,6 +140,7 @@ impl Mutables {
     fn new() -> Self {
         Self {
             name_stack: Vec::new(),
+            debug_group_stack: Vec::new(),
             queries: IndexMap::new(),
             query_index: 0,
         }
@@ -123,6 +154,10 @@ pub struct Profiler {
     mutables: RefCell<Mutables>,
     timestamp_period: f32,
     prev_frame_info: IndexMap<String, QueryInfo>,
+    // Wraps every scope in a debug group named after it, for RenderDoc, Xcode or PIX captures
+    debug_markers: Cell<bool>,
+    frame_history: VecDeque<FrameSample>,
+    last_frame: Option<CpuTimestamp>,
 }
 
 impl Profiler {
@@ -145,6 +180,9 @@ impl Profiler {
             max_queries,
             timestamp_period,
             prev_frame_info: IndexMap::new(),
+            debug_markers: Cell::new(cfg!(debug_assertions)),
+            frame_history: VecDeque::with_capacity(FRAME_HISTORY),
+            last_frame: None,
         }
     }
 
@@ -157,6 +195,11 @@ impl Profiler {
             }
         }
         mutables.name_stack.push(name.to_owned());
+        let debug_markers = self.debug_markers.get();
+        if debug_markers {
+            encoder.push_debug_group(name);
+        }
+        mutables.debug_group_stack.push(debug_markers);
         let query_info = PendingQueryInfo {
             cpu_start: self.cpu_timer.now(),
             cpu_end: None,
@@ -185,6 +228,9 @@ impl Profiler {
             .name_stack
             .pop()
             .expect("Profiler end called without begin");
+        if mutables.debug_group_stack.pop() == Some(true) {
+            encoder.pop_debug_group();
+        }
         query_info.cpu_end = Some(self.cpu_timer.now());
         query_info.gpu_end_query_index = Some(mutables.query_index);
         mutables.query_index += 1;
@@ -273,6 +319,16 @@ impl Profiler {
                 .unwrap_or_default();
         }
 
+        let now = self.cpu_timer.now();
+        if let Some(last_frame) = self.last_frame.replace(now) {
+            let ms = now.elapsed(&last_frame).as_secs_f64() * 1000.0;
+            let dominant = self.dominant_stage(ms);
+            if self.frame_history.len() == FRAME_HISTORY {
+                self.frame_history.pop_front();
+            }
+            self.frame_history.push_back(FrameSample { ms, dominant });
+        }
+
         if mutables.query_index > self.max_queries {
             while mutables.query_index > self.max_queries {
                 self.max_queries *= 2;
@@ -284,7 +340,152 @@ impl Profiler {
         }
     }
 
+    /// The top level stage that took longest in the previous frame, by its slower of CPU and GPU
+    /// time. Time in the frame outside any stage and time between frames, such as pipeline
+    /// creation in the UI or waiting on the surface, compete as stages of their own.
+    fn dominant_stage(&self, frame_ms: f64) -> Option<(String, f64)> {
+        let ms = |duration: Duration| duration.as_secs_f64() * 1000.0;
+        let (_, main) = self.prev_frame_info.first()?;
+        let stages = self
+            .prev_frame_info
+            .iter()
+            .filter_map(|(name, info)| {
+                let (_, stage) = name.split_once('.')?;
+                let gpu = info.gpu.map_or(Duration::ZERO, |gpu| gpu.1);
+                (!stage.contains('.')).then(|| (stage, ms(info.cpu.1), ms(info.cpu.1.max(gpu))))
+            })
+            .collect::<Vec<_>>();
+        let untracked = ms(main.cpu.1) - stages.iter().map(|(_, cpu, _)| cpu).sum::<f64>();
+        stages
+            .iter()
+            .map(|(stage, _, slowest)| (stage.to_string(), *slowest))
+            .chain([
+                ("outside stages".to_owned(), untracked),
+                ("between frames".to_owned(), frame_ms - ms(main.cpu.1)),
+            ])
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+    }
+
+    // Indices into the frame history of the frames that took much longer than usual
+    fn spikes(&self) -> Vec<usize> {
+        let mut sorted = self.frame_history.iter().map(|f| f.ms).collect::<Vec<_>>();
+        sorted.sort_by(f64::total_cmp);
+        let Some(median) = sorted.get(sorted.len() / 2) else {
+            return Vec::new();
+        };
+        let threshold = (median * SPIKE_FACTOR).max(MIN_SPIKE_MS);
+        (0..self.frame_history.len())
+            .filter(|i| self.frame_history[*i].ms > threshold)
+            .collect()
+    }
+
+    /// Frame times of the last few seconds, with spikes labelled by the stage that dominated them.
+    /// `compact` leaves out the axes and labels, for the status bar.
+    pub fn frame_times_ui(&self, ui: &mut Ui, compact: bool) {
+        let line = Line::new(
+            self.frame_history
+                .iter()
+                .enumerate()
+                .map(|(i, frame)| [i as f64, frame.ms])
+                .collect::<PlotPoints>(),
+        );
+        let spikes = self.spikes();
+        let spike_color = ui.visuals().warn_fg_color;
+        let points = Points::new(
+            spikes
+                .iter()
+                .map(|i| [*i as f64, self.frame_history[*i].ms])
+                .collect::<PlotPoints>(),
+        )
+        .color(spike_color)
+        .radius(if compact { 1.5 } else { 3.0 });
+
+        let plot = Plot::new(("frame_times", compact))
+            .include_x(0.0)
+            .include_x(FRAME_HISTORY as f64)
+            .include_y(0.0)
+            .allow_drag(false)
+            .allow_zoom(false)
+            .allow_scroll(false)
+            .allow_boxed_zoom(false)
+            .allow_double_click_reset(false);
+        let plot = if compact {
+            plot.width(160.0)
+                .height(18.0)
+                .show_axes(false)
+                .show_grid(false)
+                .show_x(false)
+                .show_y(false)
+        } else {
+            plot.height(120.0)
+                .y_axis_label("ms")
+                .label_formatter(|_, point| format!("{:.2} ms", point.y))
+        };
+        let response = plot
+            .show(ui, |plot_ui| {
+                plot_ui.line(line);
+                plot_ui.points(points);
+                if compact {
+                    return;
+                }
+                for i in &spikes {
+                    let frame = &self.frame_history[*i];
+                    if let Some((stage, _)) = &frame.dominant {
+                        plot_ui.text(
+                            Text::new(PlotPoint::new(*i as f64, frame.ms), stage.as_str())
+                                .anchor(egui::Align2::CENTER_BOTTOM)
+                                .color(spike_color),
+                        );
+                    }
+                }
+            })
+            .response;
+
+        if compact {
+            let latest = self.frame_history.back().map_or(0.0, |frame| frame.ms);
+            response.on_hover_ui(|ui| {
+                ui.label(format!(
+                    "{:.2} ms ({:.0} FPS)",
+                    latest,
+                    1000.0 / latest.max(0.001)
+                ));
+                for i in spikes.iter().rev().take(5) {
+                    let frame = &self.frame_history[*i];
+                    if let Some((stage, ms)) = &frame.dominant {
+                        ui.label(format!(
+                            "{:.1} ms spike, {} took {:.1} ms",
+                            frame.ms, stage, ms
+                        ));
+                    }
+                }
+            });
+        }
+    }
+
+    /// Timings of every scope in the previous frame, the first one is the whole frame
+    pub fn prev_frame_info(&self) -> &IndexMap<String, QueryInfo> {
+        &self.prev_frame_info
+    }
+
+    /// Leaves a single named marker in the command stream when debug markers are on
+    pub fn marker(&self, encoder: &mut CommandEncoder, name: &str) {
+        if self.debug_markers.get() {
+            encoder.insert_debug_marker(name);
+        }
+    }
+
     pub fn ui(&self, ui: &mut Ui) {
+        let mut debug_markers = self.debug_markers.get();
+        if ui
+            .checkbox(&mut debug_markers, "Debug markers")
+            .on_hover_text(
+                "Names every stage in graphics debugger captures, costs a little on some drivers",
+            )
+            .changed()
+        {
+            self.debug_markers.set(debug_markers);
+        }
+        self.frame_times_ui(ui, false);
         TableBuilder::new(ui)
             .column(Column::auto().resizable(true))
             .column(Column::auto().resizable(true))
@@ -0,0 +1,176 @@
+// Debug build registry of live GPU resource handles per stage, to spot resources that survive a
+// resize. Release builds keep the wrappers but skip all of the bookkeeping.
+
+use std::collections::BTreeMap;
+use std::ops::Deref;
+
+use wgpu::*;
+
+use crate::wgpu_context::WgpuContext;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ResourceKind {
+    Texture,
+    TextureView,
+    Buffer,
+    BindGroup,
+}
+
+type Key = (String, ResourceKind);
+
+#[cfg(debug_assertions)]
+static REGISTRY: std::sync::Mutex<BTreeMap<Key, u64>> = std::sync::Mutex::new(BTreeMap::new());
+
+struct Token {
+    #[cfg(debug_assertions)]
+    key: Key,
+}
+
+impl Token {
+    fn new(kind: ResourceKind, label: Option<&str>) -> Self {
+        #[cfg(debug_assertions)]
+        {
+            // The stage is the first word of the label, as in "bloom uniform_buffer"
+            let stage = label
+                .and_then(|l| l.split_whitespace().next())
+                .unwrap_or("unlabeled");
+            let key = (stage.to_owned(), kind);
+            *REGISTRY.lock().unwrap().entry(key.clone()).or_default() += 1;
+            Self { key }
+        }
+
+        #[cfg(not(debug_assertions))]
+        {
+            let _ = (kind, label);
+            Self {}
+        }
+    }
+}
+
+#[cfg(debug_assertions)]
+impl Drop for Token {
+    fn drop(&mut self) {
+        if let Some(count) = REGISTRY.lock().unwrap().get_mut(&self.key) {
+            *count -= 1;
+        }
+    }
+}
+
+/// A resource handle that's counted in the registry while it's alive
+pub struct Tracked<T> {
+    inner: T,
+    _token: Token,
+}
+
+impl<T> Deref for Tracked<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner
+    }
+}
+
+pub fn texture(ctx: &WgpuContext, desc: &TextureDescriptor) -> Tracked<Texture> {
+    Tracked {
+        inner: ctx.device.create_texture(desc),
+        _token: Token::new(ResourceKind::Texture, desc.label),
+    }
+}
+
+pub fn view(texture: &Texture, desc: &TextureViewDescriptor) -> Tracked<TextureView> {
+    Tracked {
+        inner: texture.create_view(desc),
+        _token: Token::new(ResourceKind::TextureView, desc.label),
+    }
+}
+
+pub fn buffer(ctx: &WgpuContext, desc: &BufferDescriptor) -> Tracked<Buffer> {
+    Tracked {
+        inner: ctx.device.create_buffer(desc),
+        _token: Token::new(ResourceKind::Buffer, desc.label),
+    }
+}
+
+pub fn bind_group(ctx: &WgpuContext, desc: &BindGroupDescriptor) -> Tracked<BindGroup> {
+    Tracked {
+        inner: ctx.device.create_bind_group(desc),
+        _token: Token::new(ResourceKind::BindGroup, desc.label),
+    }
+}
+
+/// Live handles per stage and kind, empty in release builds
+pub fn counts() -> BTreeMap<Key, u64> {
+    #[cfg(debug_assertions)]
+    {
+        REGISTRY.lock().unwrap().clone()
+    }
+
+    #[cfg(not(debug_assertions))]
+    {
+        BTreeMap::new()
+    }
+}
+
+// Resizes in a row a count has to grow for before it's reported
+const GROWTH_REPORT_THRESHOLD: u32 = 3;
+
+/// Compares the counts after every resize and warns about the ones that keep growing
+#[derive(Default)]
+pub struct LeakCheck {
+    previous: BTreeMap<Key, u64>,
+    growth_streaks: BTreeMap<Key, u32>,
+}
+
+impl LeakCheck {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn after_resize(&mut self) {
+        let counts = counts();
+        for (key, count) in &counts {
+            let grew = self
+                .previous
+                .get(key)
+                .is_some_and(|previous| count > previous);
+            let streak = self.growth_streaks.entry(key.clone()).or_default();
+            *streak = if grew { *streak + 1 } else { 0 };
+            if *streak == GROWTH_REPORT_THRESHOLD {
+                log::warn!(
+                    "{} {:?} count grew on {} resizes in a row, now {}",
+                    key.0,
+                    key.1,
+                    GROWTH_REPORT_THRESHOLD,
+                    count
+                );
+            }
+        }
+        self.previous = counts;
+    }
+
+    pub fn ui(&self, ui: &mut egui::Ui) {
+        if !cfg!(debug_assertions) {
+            ui.label("Resources are only tracked in debug builds");
+            return;
+        }
+        egui::Grid::new("resource counts")
+            .striped(true)
+            .show(ui, |ui| {
+                for ((stage, kind), count) in counts() {
+                    ui.label(stage.as_str());
+                    ui.label(format!("{:?}", kind));
+                    let streak = self
+                        .growth_streaks
+                        .get(&(stage, kind))
+                        .copied()
+                        .unwrap_or(0);
+                    if streak >= GROWTH_REPORT_THRESHOLD {
+                        ui.colored_label(ui.visuals().warn_fg_color, count.to_string());
+                    } else {
+                        ui.label(count.to_string());
+                    }
+                    ui.end_row();
+                }
+            });
+    }
+}
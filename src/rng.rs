@@ -0,0 +1,226 @@
+use std::collections::BTreeMap;
+
+use rand::Rng;
+
+const META_KEY_PREFIX: &str = "rng.";
+
+/// Parts of the app that draw random numbers, each from a seed of its own so rerolling one never
+/// changes what another produces
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Stream {
+    Worldgen = 0,
+    Simulation = 1,
+    // Film grain and other effects that only change the picture
+    Visual = 2,
+}
+
+impl Stream {
+    pub const ALL: [Stream; 3] = [Stream::Worldgen, Stream::Simulation, Stream::Visual];
+
+    fn key(self) -> &'static str {
+        match self {
+            Stream::Worldgen => "worldgen",
+            Stream::Simulation => "simulation",
+            Stream::Visual => "visual",
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Stream::Worldgen => "World generation",
+            Stream::Simulation => "Simulation",
+            Stream::Visual => "Visual effects",
+        }
+    }
+}
+
+/// Seed of every stream, saved with worlds so a run can be reproduced. The streams start out
+/// derived from a single run seed, so that one number is enough to get the same world and the
+/// same ticks again.
+pub struct RngStreams {
+    run_seed: u64,
+    seeds: [u32; 3],
+    // Run seed typed into the ui, applied with the button next to it
+    run_seed_input: String,
+}
+
+impl RngStreams {
+    /// Streams derived from `run_seed`, or from a random one
+    pub fn new(run_seed: Option<u64>) -> Self {
+        let mut streams = Self {
+            run_seed: 0,
+            seeds: [0; 3],
+            run_seed_input: String::new(),
+        };
+        streams.set_run_seed(run_seed.unwrap_or_else(|| rand::thread_rng().gen()));
+        streams
+    }
+
+    pub fn run_seed(&self) -> u64 {
+        self.run_seed
+    }
+
+    /// Derives the seed of every stream from `run_seed`
+    pub fn set_run_seed(&mut self, run_seed: u64) {
+        self.run_seed = run_seed;
+        self.seeds = Stream::ALL.map(|stream| stream_u64(stream as u32, run_seed));
+        self.run_seed_input = run_seed.to_string();
+    }
+
+    /// Picks a new random run seed and returns it
+    pub fn reroll_run(&mut self) -> u64 {
+        self.set_run_seed(rand::thread_rng().gen());
+        self.run_seed
+    }
+
+    /// Whether the streams still have the seeds derived from the run seed
+    fn follows_run_seed(&self) -> bool {
+        Stream::ALL
+            .into_iter()
+            .all(|stream| self.seed(stream) == stream_u64(stream as u32, self.run_seed))
+    }
+
+    pub fn seed(&self, stream: Stream) -> u32 {
+        self.seeds[stream as usize]
+    }
+
+    pub fn set_seed(&mut self, stream: Stream, seed: u32) {
+        self.seeds[stream as usize] = seed;
+    }
+
+    /// Picks a new random seed for `stream` and returns it
+    pub fn reroll(&mut self, stream: Stream) -> u32 {
+        self.set_seed(stream, rand::thread_rng().gen());
+        self.seed(stream)
+    }
+
+    pub fn write_meta(&self, meta: &mut BTreeMap<String, String>) {
+        meta.insert(format!("{}run", META_KEY_PREFIX), self.run_seed.to_string());
+        for stream in Stream::ALL {
+            meta.insert(
+                format!("{}{}", META_KEY_PREFIX, stream.key()),
+                self.seed(stream).to_string(),
+            );
+        }
+    }
+
+    /// Reads the seeds written by `write_meta` and returns the streams that were in `meta`, worlds
+    /// saved before the streams existed keep the current seeds
+    pub fn read_meta(&mut self, meta: &BTreeMap<String, String>) -> Vec<Stream> {
+        match meta
+            .get(&format!("{}run", META_KEY_PREFIX))
+            .map(|seed| seed.parse::<u64>())
+        {
+            Some(Ok(run_seed)) => {
+                self.run_seed = run_seed;
+                self.run_seed_input = run_seed.to_string();
+            }
+            Some(Err(e)) => log::warn!("Invalid run seed: {}", e),
+            None => {}
+        }
+        Stream::ALL
+            .into_iter()
+            .filter(|stream| {
+                let key = format!("{}{}", META_KEY_PREFIX, stream.key());
+                match meta.get(&key).map(|seed| seed.parse::<u32>()) {
+                    Some(Ok(seed)) => {
+                        self.set_seed(*stream, seed);
+                        true
+                    }
+                    Some(Err(e)) => {
+                        log::warn!("Invalid seed {:?}: {}", key, e);
+                        false
+                    }
+                    None => false,
+                }
+            })
+            .collect()
+    }
+
+    /// Returns the streams whose seed was changed, and whether the world should be seeded again
+    /// because the run seed changed
+    pub fn ui(&mut self, ui: &mut egui::Ui) -> (Vec<Stream>, bool) {
+        let mut changed = Vec::new();
+        let mut reseed = false;
+        ui.collapsing("Random streams", |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Run seed");
+                let input = ui
+                    .add(egui::TextEdit::singleline(&mut self.run_seed_input).desired_width(160.0));
+                let parsed = self.run_seed_input.trim().parse::<u64>();
+                let submitted = input.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                if ui
+                    .add_enabled(parsed.is_ok(), egui::Button::new("Use"))
+                    .on_hover_text(
+                        "Seeds the world and the simulation from this number, the same number \
+                         starts the same run again",
+                    )
+                    .clicked()
+                    || (submitted && parsed.is_ok())
+                {
+                    if let Ok(seed) = parsed {
+                        self.set_run_seed(seed);
+                        changed.extend(Stream::ALL);
+                        reseed = true;
+                    }
+                }
+                if ui
+                    .button("Reseed")
+                    .on_hover_text("A new random run seed, and a new world from it")
+                    .clicked()
+                {
+                    self.reroll_run();
+                    changed.extend(Stream::ALL);
+                    reseed = true;
+                }
+            });
+            if self.run_seed_input.trim().parse::<u64>().is_err() {
+                ui.colored_label(ui.visuals().warn_fg_color, "Not a seed, expected a number");
+            }
+            if !self.follows_run_seed() {
+                ui.weak(
+                    "Streams were changed on their own, the run seed alone won't reproduce them",
+                );
+            }
+            egui::Grid::new("rng_streams").show(ui, |ui| {
+                for stream in Stream::ALL {
+                    ui.label(stream.label());
+                    let seed = &mut self.seeds[stream as usize];
+                    if ui.add(egui::DragValue::new(seed)).changed() {
+                        changed.push(stream);
+                    }
+                    if ui.button("Reroll").clicked() {
+                        self.reroll(stream);
+                        changed.push(stream);
+                    }
+                    ui.end_row();
+                }
+            });
+            ui.weak("A new world generation seed applies the next time the world is seeded");
+        });
+        (changed, reseed)
+    }
+}
+
+/// PCG hash, the same as `rng_hash` in rng.wgsl
+pub fn hash(x: u32) -> u32 {
+    // https://www.pcg-random.org/
+    let state = x.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    (word >> 22) ^ word
+}
+
+/// Value number `counter` of the stream seeded with `seed`, the same as `rng_stream` in rng.wgsl.
+/// Counter based, so any value can be drawn without drawing the ones before it.
+pub fn stream(seed: u32, counter: u32) -> u32 {
+    hash(seed ^ hash(counter))
+}
+
+/// Like `stream` with a 64 bit counter, for the simulation ticks
+pub fn stream_u64(seed: u32, counter: u64) -> u32 {
+    // splitmix64 finalizer
+    let mut x = counter ^ ((seed as u64) << 32);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    (x ^ (x >> 31)) as u32
+}
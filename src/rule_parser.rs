@@ -0,0 +1,234 @@
+use std::fmt;
+
+use crate::gpu_stage::simulate::MAX_STATES;
+use crate::neighborhood::{Neighborhood, NeighborhoodShape, MAX_RADIUS};
+
+// The birth and survival masks have a bit per count
+const MAX_COUNT: u32 = u32::BITS - 1;
+
+/// A counting rule written as text, either with a letter in front of every part like
+/// "B6-8/S5-7/C10/M", or as survival/birth/states/neighborhood like the presets, "5-7/6-8/10/M".
+/// The states and the neighborhood can be left out, for 2 states and the 26 cells around a cell.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rule {
+    pub birth_mask: u32,
+    pub survival_mask: u32,
+    pub states: u32,
+    // Moore or von Neumann
+    pub shape: NeighborhoodShape,
+    pub radius: i32,
+}
+
+impl Rule {
+    /// Key of the neighborhood, see `Neighborhood::key`
+    pub fn neighborhood_key(&self) -> String {
+        let mut neighborhood = Neighborhood::new();
+        neighborhood.shape = self.shape;
+        neighborhood.radius = self.radius;
+        neighborhood.key()
+    }
+
+    fn neighbor_count(&self) -> u32 {
+        let mut neighborhood = Neighborhood::new();
+        neighborhood.shape = self.shape;
+        neighborhood.radius = self.radius;
+        neighborhood.size()
+    }
+}
+
+/// Parses a comma separated set of counts and ranges like "2,6,9-12" into a mask
+fn parse_counts(part: &str, name: &str, max: u32) -> Result<u32, String> {
+    if part.trim().is_empty() {
+        return Ok(0);
+    }
+    let mut mask = 0;
+    for item in part.split(',').map(str::trim) {
+        let parse = |count: &str| {
+            let count = count.trim();
+            count
+                .parse::<u32>()
+                .map_err(|_| format!("{} has {:?}, which isn't a count", name, count))
+        };
+        let (from, to) = match item.split_once('-') {
+            Some((from, to)) => (parse(from)?, parse(to)?),
+            None => (parse(item)?, parse(item)?),
+        };
+        if from > to {
+            return Err(format!("{} range {} runs backwards", name, item));
+        }
+        if to > max {
+            return Err(format!(
+                "{} goes up to {}, but only counts up to {} are possible here",
+                name, to, max
+            ));
+        }
+        mask |= (u32::MAX >> (MAX_COUNT - to)) & (u32::MAX << from);
+    }
+    Ok(mask)
+}
+
+fn parse_states(part: &str) -> Result<u32, String> {
+    part.parse::<u32>()
+        .ok()
+        .filter(|states| (2..=MAX_STATES).contains(states))
+        .ok_or_else(|| {
+            format!(
+                "States should be a number from 2 to {}, not {:?}",
+                MAX_STATES, part
+            )
+        })
+}
+
+/// "M" and "N" for the Moore and von Neumann neighborhoods, optionally followed by a radius
+fn parse_neighborhood(part: &str) -> Result<(NeighborhoodShape, i32), String> {
+    let shape = match part.chars().next().map(|c| c.to_ascii_uppercase()) {
+        Some('M') => NeighborhoodShape::Moore,
+        Some('N') => NeighborhoodShape::VonNeumann,
+        _ => {
+            return Err(format!(
+                "Unknown neighborhood {:?}, use M for Moore or N for von Neumann",
+                part
+            ))
+        }
+    };
+    let radius = match part[1..].trim() {
+        "" => 1,
+        radius => radius
+            .parse::<i32>()
+            .ok()
+            .filter(|r| (1..=MAX_RADIUS).contains(r))
+            .ok_or_else(|| {
+                format!(
+                    "Neighborhood radius should be from 1 to {}, not {:?}",
+                    MAX_RADIUS, radius
+                )
+            })?,
+    };
+    Ok((shape, radius))
+}
+
+fn starts_with_letter(part: &str, letters: &str) -> bool {
+    part.chars()
+        .next()
+        .is_some_and(|c| letters.contains(c.to_ascii_uppercase()))
+}
+
+pub fn parse_rule(input: &str) -> Result<Rule, String> {
+    if input.trim().is_empty() {
+        return Err("The rule is empty".to_owned());
+    }
+    let parts = input.split('/').map(str::trim).collect::<Vec<_>>();
+
+    let mut birth = None;
+    let mut survival = None;
+    let mut states = None;
+    let mut neighborhood = None;
+    if parts.iter().any(|part| starts_with_letter(part, "BSC")) {
+        for &part in &parts {
+            let (slot, name) = match part.chars().next().map(|c| c.to_ascii_uppercase()) {
+                Some('B') => (&mut birth, "Birth"),
+                Some('S') => (&mut survival, "Survival"),
+                Some('C') => (&mut states, "States"),
+                Some('M' | 'N') => (&mut neighborhood, "Neighborhood"),
+                _ => {
+                    return Err(format!(
+                        "Every part needs a B, S, C, M or N in front, {:?} doesn't have one",
+                        part
+                    ))
+                }
+            };
+            if slot.replace(part).is_some() {
+                return Err(format!("{} is given twice", name));
+            }
+        }
+        // The neighborhood keeps its letter, it says which shape
+        birth = Some(birth.ok_or("Births are missing, add B followed by the counts")?);
+        survival = Some(survival.ok_or("Survivals are missing, add S followed by the counts")?);
+        for part in [&mut birth, &mut survival, &mut states]
+            .into_iter()
+            .flatten()
+        {
+            let letter_and_value: &str = *part;
+            *part = letter_and_value[1..].trim();
+        }
+    } else {
+        let [s, b, rest @ ..] = &parts[..] else {
+            return Err("Expected survival/birth, or parts starting with B and S".to_owned());
+        };
+        survival = Some(*s);
+        birth = Some(*b);
+        match rest {
+            [] => {}
+            [n] if starts_with_letter(n, "MN") => neighborhood = Some(*n),
+            [c] => states = Some(*c),
+            [c, n] => {
+                states = Some(*c);
+                neighborhood = Some(*n);
+            }
+            _ => {
+                return Err(
+                    "Too many parts, expected survival/birth/states/neighborhood".to_owned(),
+                )
+            }
+        }
+    }
+
+    let (shape, radius) =
+        neighborhood.map_or(Ok((NeighborhoodShape::Moore, 1)), parse_neighborhood)?;
+    let mut rule = Rule {
+        birth_mask: 0,
+        survival_mask: 0,
+        states: states.map_or(Ok(2), parse_states)?,
+        shape,
+        radius,
+    };
+    let max = rule.neighbor_count().min(MAX_COUNT);
+    rule.birth_mask = parse_counts(birth.unwrap_or_default(), "Birth", max)?;
+    rule.survival_mask = parse_counts(survival.unwrap_or_default(), "Survival", max)?;
+    Ok(rule)
+}
+
+/// Writes the counts of a mask as a set, with runs of counts as ranges
+fn format_counts(mask: u32) -> String {
+    let mut items = Vec::new();
+    let mut count = 0;
+    while count <= MAX_COUNT {
+        if mask & (1 << count) == 0 {
+            count += 1;
+            continue;
+        }
+        let from = count;
+        while count < MAX_COUNT && mask & (1 << (count + 1)) != 0 {
+            count += 1;
+        }
+        items.push(if from == count {
+            from.to_string()
+        } else {
+            format!("{}-{}", from, count)
+        });
+        count += 1;
+    }
+    items.join(",")
+}
+
+impl fmt::Display for Rule {
+    /// The form with letters, which `parse_rule` reads back into the same rule
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "B{}/S{}/C{}/{}",
+            format_counts(self.birth_mask),
+            format_counts(self.survival_mask),
+            self.states,
+            if self.shape == NeighborhoodShape::VonNeumann {
+                "N"
+            } else {
+                "M"
+            }
+        )?;
+        if self.radius != 1 {
+            write!(f, "{}", self.radius)?;
+        }
+        Ok(())
+    }
+}
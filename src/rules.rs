@@ -0,0 +1,88 @@
+use crate::neighborhood::NeighborhoodShape;
+use crate::rule_parser::Rule;
+
+/// A well known 3D rule over the 26 cells around a cell, given as the neighbor counts a live
+/// cell survives with and a dead one is born with
+pub struct RulePreset {
+    pub name: &'static str,
+    // The rule as usually written: survival/birth/states/neighborhood
+    pub notation: &'static str,
+    survival: &'static [u32],
+    birth: &'static [u32],
+    // Dead and alive plus the decaying states in between
+    pub states: u32,
+}
+
+/// The counts of these rules go past what the larger than life ranges can express, they're
+/// matched as sets
+pub const PRESETS: [RulePreset; 6] = [
+    RulePreset {
+        name: "445",
+        notation: "4/4/5/M",
+        survival: &[4],
+        birth: &[4],
+        states: 5,
+    },
+    RulePreset {
+        name: "Clouds",
+        notation: "13-26/13-14,17-19/2/M",
+        survival: &[13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26],
+        birth: &[13, 14, 17, 18, 19],
+        states: 2,
+    },
+    RulePreset {
+        name: "Builder",
+        notation: "2,6,9/4,6,8-9/10/M",
+        survival: &[2, 6, 9],
+        birth: &[4, 6, 8, 9],
+        states: 10,
+    },
+    RulePreset {
+        name: "Amoeba",
+        notation: "9-26/5-7,12-13,15/5/M",
+        survival: &[
+            9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26,
+        ],
+        birth: &[5, 6, 7, 12, 13, 15],
+        states: 5,
+    },
+    RulePreset {
+        name: "Pyroclastic",
+        notation: "4-7/6-8/10/M",
+        survival: &[4, 5, 6, 7],
+        birth: &[6, 7, 8],
+        states: 10,
+    },
+    RulePreset {
+        name: "Spiky growth",
+        notation: "0-3,7-9,11-13,18-21,23-24,26/13,17,20-26/4/M",
+        survival: &[0, 1, 2, 3, 7, 8, 9, 11, 12, 13, 18, 19, 20, 21, 23, 24, 26],
+        birth: &[13, 17, 20, 21, 22, 23, 24, 25, 26],
+        states: 4,
+    },
+];
+
+fn mask(counts: &[u32]) -> u32 {
+    counts.iter().fold(0, |mask, count| mask | (1 << count))
+}
+
+impl RulePreset {
+    pub fn birth_mask(&self) -> u32 {
+        mask(self.birth)
+    }
+
+    pub fn survival_mask(&self) -> u32 {
+        mask(self.survival)
+    }
+
+    /// Every preset counts the 26 cells around a cell
+    pub fn rule(&self) -> Rule {
+        Rule {
+            birth_mask: self.birth_mask(),
+            survival_mask: self.survival_mask(),
+            states: self.states,
+            shape: NeighborhoodShape::Moore,
+            radius: 1,
+        }
+    }
+}
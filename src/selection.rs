@@ -0,0 +1,107 @@
+use nalgebra_glm as glm;
+
+use crate::chunk::CHUNK_SIZE;
+use crate::gpu_stage::live_bounds::LiveBounds;
+use crate::gpu_stage::overlay::Overlay;
+use crate::gpu_stage::pick::PickHit;
+use crate::theme;
+
+/// Box of cells picked out of the world, in world voxel coordinates with `max` inclusive like
+/// `LiveBounds`
+pub struct Selection {
+    pub active: bool,
+    pub min: glm::IVec3,
+    pub max: glm::IVec3,
+    pub visible: bool,
+}
+
+impl Selection {
+    pub fn new() -> Self {
+        Self {
+            active: false,
+            min: glm::vec3(0, 0, 0),
+            max: glm::vec3(1, 1, 1) * (CHUNK_SIZE as i32 - 1),
+            visible: true,
+        }
+    }
+
+    fn set(&mut self, min: glm::IVec3, max: glm::IVec3) {
+        self.min = min;
+        self.max = max;
+        self.active = true;
+    }
+
+    pub fn size(&self) -> glm::IVec3 {
+        self.max - self.min + glm::vec3(1, 1, 1)
+    }
+
+    pub fn draw(&self, overlay: &Overlay) {
+        if !self.active || !self.visible {
+            return;
+        }
+        overlay.cuboid(
+            theme::rgba(overlay.theme.selection),
+            self.min.cast::<f32>(),
+            (self.max + glm::vec3(1, 1, 1)).cast::<f32>(),
+        );
+    }
+
+    /// `hit` is the last pick, the selection can be moved onto the voxel under it
+    pub fn ui(
+        &mut self,
+        ui: &mut egui::Ui,
+        live_bounds: Option<&LiveBounds>,
+        hit: Option<PickHit>,
+    ) {
+        ui.collapsing("Selection", |ui| {
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut self.active, "Box selection");
+                ui.checkbox(&mut self.visible, "Show");
+            });
+            ui.add_enabled_ui(self.active, |ui| {
+                egui::Grid::new("selection_bounds").show(ui, |ui| {
+                    for (label, corner) in [("Min", &mut self.min), ("Max", &mut self.max)] {
+                        ui.label(label);
+                        for axis in 0..3 {
+                            ui.add(egui::DragValue::new(&mut corner[axis]).speed(0.25));
+                        }
+                        ui.end_row();
+                    }
+                });
+                // Dragging a corner past the other one drags that one along
+                for axis in 0..3 {
+                    if self.max[axis] < self.min[axis] {
+                        self.max[axis] = self.min[axis];
+                    }
+                }
+                let size = self.size();
+                ui.label(format!(
+                    "{} x {} x {}, {} cells",
+                    size.x,
+                    size.y,
+                    size.z,
+                    size.x as i64 * size.y as i64 * size.z as i64
+                ));
+            });
+            ui.horizontal(|ui| {
+                if ui
+                    .add_enabled(live_bounds.is_some(), egui::Button::new("Live cells"))
+                    .on_hover_text("Select the box around every live cell")
+                    .clicked()
+                {
+                    let bounds = live_bounds.unwrap();
+                    self.set(bounds.min, bounds.max);
+                }
+                if ui
+                    .add_enabled(hit.is_some(), egui::Button::new("Picked chunk"))
+                    .on_hover_text("Select the chunk of the voxel under the pick position")
+                    .clicked()
+                {
+                    let voxel = hit.unwrap().voxel;
+                    let min = voxel.map(|v| v.div_euclid(CHUNK_SIZE as i32) * CHUNK_SIZE as i32);
+                    self.set(min, min + glm::vec3(1, 1, 1) * (CHUNK_SIZE as i32 - 1));
+                }
+            });
+        });
+    }
+}
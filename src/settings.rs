@@ -0,0 +1,46 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use crate::storage;
+
+const SETTINGS_KEY: &str = "settings.txt";
+
+/// Persistent `key=value` settings, loaded once at startup
+#[derive(Default)]
+pub struct Settings {
+    values: BTreeMap<String, String>,
+}
+
+impl Settings {
+    pub fn load() -> Self {
+        let values = storage::read_string(SETTINGS_KEY)
+            .map(|contents| {
+                contents
+                    .lines()
+                    .filter_map(|line| line.split_once('='))
+                    .map(|(key, value)| (key.trim().to_owned(), value.trim().to_owned()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self { values }
+    }
+
+    pub fn save(&self) {
+        let contents = self
+            .values
+            .iter()
+            .map(|(key, value)| format!("{}={}\n", key, value))
+            .collect::<String>();
+        if let Err(e) = storage::write_string(SETTINGS_KEY, &contents) {
+            log::warn!("Failed to save settings: {}", e);
+        }
+    }
+
+    pub fn get<T: FromStr>(&self, key: &str) -> Option<T> {
+        self.values.get(key).and_then(|value| value.parse().ok())
+    }
+
+    pub fn set(&mut self, key: &str, value: impl ToString) {
+        self.values.insert(key.to_owned(), value.to_string());
+    }
+}
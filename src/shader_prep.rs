@@ -0,0 +1,93 @@
+use std::collections::HashSet;
+
+use wgpu::TextureFormat;
+
+use crate::chunk::CHUNK_SIZE;
+use crate::chunk_datastore::{ATLAS_OFFSET, CHUNK_FORMAT};
+
+// Sources that can be pulled into a shader with `#include "name"`
+const INCLUDES: &[(&str, &str)] = &[
+    ("common.wgsl", include_str!("gpu_stage/common.wgsl")),
+    ("cell_state.wgsl", include_str!("gpu_stage/cell_state.wgsl")),
+    ("rng.wgsl", include_str!("gpu_stage/rng.wgsl")),
+    ("dither.wgsl", include_str!("gpu_stage/dither.wgsl")),
+];
+
+fn wgsl_storage_format(format: TextureFormat) -> &'static str {
+    match format {
+        TextureFormat::R32Uint => "r32uint",
+        TextureFormat::R32Float => "r32float",
+        TextureFormat::Rgba8Uint => "rgba8uint",
+        TextureFormat::Rgba16Float => "rgba16float",
+        _ => panic!("no wgsl storage format for {:?}", format),
+    }
+}
+
+/// Expands `#include` lines and substitutes `{{NAME}}` placeholders in WGSL sources, so values
+/// shared between Rust and the shaders are only defined once on the Rust side
+pub struct ShaderPrep {
+    constants: Vec<(String, String)>,
+}
+
+impl ShaderPrep {
+    pub fn new() -> Self {
+        Self {
+            constants: Vec::new(),
+        }
+        .define("CHUNK_SIZE", CHUNK_SIZE)
+        .define("CHUNK_SHIFT", CHUNK_SIZE.ilog2())
+        .define("ATLAS_OFFSET", ATLAS_OFFSET)
+        .define("CHUNK_FORMAT", wgsl_storage_format(CHUNK_FORMAT))
+    }
+
+    pub fn define(mut self, name: &str, value: impl ToString) -> Self {
+        self.constants.push((name.to_owned(), value.to_string()));
+        self
+    }
+
+    pub fn process(&self, source: &str) -> String {
+        self.try_process(source).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Like `process`, but for sources that don't ship with the binary, which can name includes
+    /// or constants that don't exist
+    pub fn try_process(&self, source: &str) -> Result<String, String> {
+        let mut included = HashSet::new();
+        let source = Self::expand_includes(source, &mut included)?;
+        let source = self.constants.iter().fold(source, |source, (name, value)| {
+            source.replace(&format!("{{{{{}}}}}", name), value)
+        });
+        if let Some(start) = source.find("{{") {
+            let end = source[start..]
+                .find("}}")
+                .map_or(source.len(), |end| start + end + 2);
+            return Err(format!("undefined shader constant {}", &source[start..end]));
+        }
+        Ok(source)
+    }
+
+    fn expand_includes(
+        source: &str,
+        included: &mut HashSet<&'static str>,
+    ) -> Result<String, String> {
+        source
+            .lines()
+            .map(|line| match line.trim().strip_prefix("#include") {
+                Some(name) => {
+                    let name = name.trim().trim_matches('"');
+                    let (name, contents) = INCLUDES
+                        .iter()
+                        .find(|(include_name, _)| *include_name == name)
+                        .ok_or_else(|| format!("unknown shader include {}", name))?;
+                    if included.insert(name) {
+                        Self::expand_includes(contents, included)
+                    } else {
+                        Ok(String::new())
+                    }
+                }
+                None => Ok(line.to_owned()),
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(|lines| lines.join("\n"))
+    }
+}
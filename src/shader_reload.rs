@@ -0,0 +1,179 @@
+use std::time::{Duration, Instant, SystemTime};
+
+// How often the file's modification time is checked
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// What the simulation should build its pipelines from after a poll
+pub enum ShaderChange {
+    // Contents of the watched file, a full replacement for `simulate.wgsl`
+    File(String),
+    // Watching stopped, back to the shader that ships with the binary
+    Builtin,
+}
+
+/// Development mode that swaps the simulation shader for a WGSL file on disk and rebuilds the
+/// pipelines every time the file is saved, so custom rules can be iterated on without restarting.
+/// A file that doesn't compile leaves the last working pipelines in place.
+pub struct ShaderReload {
+    pub enabled: bool,
+    path: String,
+    // Modification time of the file when it was last read
+    modified: Option<SystemTime>,
+    last_poll: Option<Instant>,
+    // Set while the pipelines are built from the file
+    active: bool,
+    // Why the last version of the file couldn't be used
+    error: Option<String>,
+    show_error: bool,
+}
+
+impl ShaderReload {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            path: String::new(),
+            modified: None,
+            last_poll: None,
+            active: false,
+            error: None,
+            show_error: false,
+        }
+    }
+
+    /// Reads the file again on the next poll, for when the pipelines were rebuilt from the
+    /// built-in shader
+    pub fn invalidate(&mut self) {
+        self.modified = None;
+        self.last_poll = None;
+        self.active = false;
+    }
+
+    /// Returns the source to rebuild the pipelines from when the file changed since it was last
+    /// read, or when watching was turned off
+    pub fn poll(&mut self) -> Option<ShaderChange> {
+        if !self.enabled {
+            self.modified = None;
+            self.last_poll = None;
+            self.error = None;
+            return std::mem::take(&mut self.active).then_some(ShaderChange::Builtin);
+        }
+        if self
+            .last_poll
+            .is_some_and(|last_poll| last_poll.elapsed() < POLL_INTERVAL)
+        {
+            return None;
+        }
+        self.last_poll = Some(Instant::now());
+        let modified = match std::fs::metadata(&self.path).and_then(|m| m.modified()) {
+            Ok(modified) => modified,
+            Err(e) => {
+                self.set_error(format!("{}: {}", self.path, e));
+                self.modified = None;
+                return None;
+            }
+        };
+        if self.modified == Some(modified) {
+            return None;
+        }
+        self.modified = Some(modified);
+        match std::fs::read_to_string(&self.path) {
+            Ok(source) => Some(ShaderChange::File(source)),
+            Err(e) => {
+                self.set_error(format!("{}: {}", self.path, e));
+                None
+            }
+        }
+    }
+
+    /// Result of rebuilding the pipelines from what `poll` returned
+    pub fn finish(&mut self, change: &ShaderChange, result: Result<(), String>) {
+        match result {
+            Ok(()) => {
+                self.active = matches!(change, ShaderChange::File(_));
+                self.error = None;
+                self.show_error = false;
+                if self.active {
+                    log::info!("Reloaded simulation shader from {}", self.path);
+                }
+            }
+            Err(e) => self.set_error(e),
+        }
+    }
+
+    fn set_error(&mut self, error: String) {
+        // The same error is reported every poll while a file is missing, only show it once
+        if self.error.as_ref() != Some(&error) {
+            log::error!("Simulation shader reload failed: {}", error);
+            self.show_error = true;
+        }
+        self.error = Some(error);
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.collapsing("Shader hot reload", |ui| {
+            ui.horizontal(|ui| {
+                ui.label("File");
+                if ui
+                    .add_enabled(
+                        !self.enabled,
+                        egui::TextEdit::singleline(&mut self.path).hint_text("rule.wgsl"),
+                    )
+                    .changed()
+                {
+                    self.modified = None;
+                }
+            });
+            ui.add_enabled(
+                !self.path.is_empty(),
+                egui::Checkbox::new(&mut self.enabled, "Watch file"),
+            )
+            .on_hover_text(
+                "Builds the simulation from this file instead of simulate.wgsl, and again every \
+                 time it's saved",
+            );
+            if !self.enabled {
+                return;
+            }
+            match &self.error {
+                Some(_) => {
+                    ui.colored_label(ui.visuals().warn_fg_color, "Compile failed");
+                    if ui.small_button("Show error").clicked() {
+                        self.show_error = true;
+                    }
+                }
+                None if self.active => {
+                    ui.label("Simulating with the file");
+                }
+                None => {
+                    ui.label("Waiting for the file");
+                }
+            }
+        });
+    }
+
+    /// Window with the last compile error, shown until it's closed or the file compiles
+    pub fn error_window(&mut self, ctx: &egui::Context) {
+        let Some(error) = &self.error else {
+            return;
+        };
+        egui::Window::new("Shader error")
+            .open(&mut self.show_error)
+            .default_width(600.0)
+            .show(ctx, |ui| {
+                ui.label(if self.active {
+                    "The simulation keeps using the last version that compiled."
+                } else {
+                    "The simulation keeps using the built-in shader."
+                });
+                egui::ScrollArea::vertical()
+                    .max_height(400.0)
+                    .show(ui, |ui| {
+                        ui.add(
+                            egui::TextEdit::multiline(&mut error.as_str())
+                                .code_editor()
+                                .desired_width(f32::INFINITY),
+                        );
+                    });
+            });
+    }
+}
@@ -0,0 +1,99 @@
+use std::collections::VecDeque;
+
+use crate::chunk_manager::{ChunkManager, ChunkSnapshot};
+use crate::wgpu_context::WgpuContext;
+
+/// The last few world states, taken every `interval` ticks. Stepping backward restores the newest
+/// snapshot before the target tick and simulates forward from there.
+pub struct SnapshotRing {
+    pub interval: u64,
+    pub capacity: usize,
+    snapshots: VecDeque<(u64, ChunkSnapshot)>,
+    // Sum of the chunk versions when the snapshots were taken, edits can't be replayed
+    version_sum: u64,
+}
+
+impl SnapshotRing {
+    pub fn new() -> Self {
+        Self {
+            interval: 64,
+            capacity: 8,
+            snapshots: VecDeque::new(),
+            version_sum: 0,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+    }
+
+    /// Drops every snapshot if chunks were edited or replaced since they were taken
+    fn check_edits(&mut self, chunk_manager: &ChunkManager) {
+        let version_sum = chunk_manager.chunks().values().map(|c| c.version).sum();
+        if version_sum != self.version_sum {
+            self.version_sum = version_sum;
+            self.clear();
+        }
+    }
+
+    /// Snapshots the world at `tick` unless a recent enough one exists
+    pub fn capture_if_due(
+        &mut self,
+        ctx: &WgpuContext,
+        encoder: &mut wgpu::CommandEncoder,
+        chunk_manager: &ChunkManager,
+        tick: u64,
+    ) {
+        self.check_edits(chunk_manager);
+        // Anything after `tick` belongs to a timeline that was stepped back from
+        while self.snapshots.back().is_some_and(|(t, _)| *t > tick) {
+            self.snapshots.pop_back();
+        }
+        if let Some((last_tick, _)) = self.snapshots.back() {
+            if tick < last_tick + self.interval.max(1) {
+                return;
+            }
+        }
+        self.snapshots
+            .push_back((tick, chunk_manager.snapshot_chunks(ctx, encoder)));
+        while self.snapshots.len() > self.capacity.max(1) {
+            self.snapshots.pop_front();
+        }
+    }
+
+    /// Newest snapshot at or before `tick`
+    pub fn latest_before(
+        &mut self,
+        chunk_manager: &ChunkManager,
+        tick: u64,
+    ) -> Option<&(u64, ChunkSnapshot)> {
+        self.check_edits(chunk_manager);
+        self.snapshots.iter().rev().find(|(t, _)| *t <= tick)
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.collapsing("Snapshots", |ui| {
+            ui.add(
+                egui::Slider::new(&mut self.interval, 1..=1024)
+                    .logarithmic(true)
+                    .text("Every n ticks"),
+            );
+            ui.add(egui::Slider::new(&mut self.capacity, 1..=64).text("Keep"));
+            let bytes = self
+                .snapshots
+                .iter()
+                .map(|(_, s)| s.size_bytes())
+                .sum::<u64>();
+            ui.label(format!(
+                "{} snapshots, {:.1} MiB",
+                self.snapshots.len(),
+                bytes as f64 / (1024.0 * 1024.0)
+            ));
+            if let (Some((first, _)), Some((last, _))) =
+                (self.snapshots.front(), self.snapshots.back())
+            {
+                ui.label(format!("Ticks {} to {}", first, last));
+            }
+        });
+    }
+}
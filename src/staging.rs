@@ -0,0 +1,155 @@
+use std::cell::RefCell;
+
+use egui_extras::{Column, TableBuilder};
+use indexmap::IndexMap;
+use wgpu::*;
+
+// Smallest ring buffer allocated, it doubles from there whenever a frame needs more
+const MIN_CAPACITY: u64 = 1 << 16;
+
+#[derive(Default)]
+struct Ring {
+    buffer: Option<Buffer>,
+    capacity: u64,
+    // Next free byte, and where the bytes not yet handed to the queue start
+    offset: u64,
+    flushed: u64,
+    pending: Vec<u8>,
+    // Bytes written per label in this frame and in the last complete one
+    frame_bytes: IndexMap<&'static str, u64>,
+    prev_frame_bytes: IndexMap<&'static str, u64>,
+    grown: u32,
+}
+
+/// Per-frame writes into GPU buffers, such as uniforms and instance data. Writes are packed into
+/// one ring buffer and copied to their targets in command order, and the ring is filled with a
+/// single queue write before the commands are submitted.
+///
+/// Nothing in the ring is overwritten within a frame, so copies in an encoder that's submitted
+/// late still see their data. Queue writes are ordered after earlier submissions, which is what
+/// makes starting over at the next frame safe.
+#[derive(Default)]
+pub struct StagingRing {
+    ring: RefCell<Ring>,
+}
+
+impl StagingRing {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Copies `data` into `target` at `target_offset` at this point in `encoder`. `data` has to be
+    /// a multiple of 4 bytes long, like any buffer copy.
+    pub fn write(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        encoder: &mut CommandEncoder,
+        label: &'static str,
+        target: &Buffer,
+        target_offset: u64,
+        data: &[u8],
+    ) {
+        let size = data.len() as u64;
+        if size == 0 {
+            return;
+        }
+        assert!(
+            size % COPY_BUFFER_ALIGNMENT == 0,
+            "{} writes {} bytes, not a multiple of {}",
+            label,
+            size,
+            COPY_BUFFER_ALIGNMENT
+        );
+
+        let ring = &mut *self.ring.borrow_mut();
+        if ring.buffer.is_none() || ring.offset + size > ring.capacity {
+            // Copies already recorded keep the old buffer alive, it just needs its data first
+            Self::flush_ring(ring, queue);
+            ring.capacity = (ring.capacity * 2)
+                .max(size.next_power_of_two())
+                .max(MIN_CAPACITY);
+            if ring.buffer.is_some() {
+                ring.grown += 1;
+                log::info!("Growing the staging ring to {} KiB", ring.capacity / 1024);
+            }
+            ring.buffer = Some(device.create_buffer(&BufferDescriptor {
+                label: Some("staging ring_buffer"),
+                size: ring.capacity,
+                usage: BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }));
+            ring.offset = 0;
+            ring.flushed = 0;
+        }
+
+        ring.pending.extend_from_slice(data);
+        encoder.copy_buffer_to_buffer(
+            ring.buffer.as_ref().unwrap(),
+            ring.offset,
+            target,
+            target_offset,
+            size,
+        );
+        ring.offset += size;
+        *ring.frame_bytes.entry(label).or_default() += size;
+    }
+
+    fn flush_ring(ring: &mut Ring, queue: &Queue) {
+        if let Some(buffer) = ring.buffer.as_ref().filter(|_| !ring.pending.is_empty()) {
+            queue.write_buffer(buffer, ring.flushed, &ring.pending);
+        }
+        ring.pending.clear();
+        ring.flushed = ring.offset;
+    }
+
+    /// Hands the data written so far to the queue, has to happen before any encoder that was
+    /// written with is submitted
+    pub fn flush(&self, queue: &Queue) {
+        Self::flush_ring(&mut self.ring.borrow_mut(), queue);
+    }
+
+    /// Starts filling the ring from the front again, once the last frame's encoders are submitted
+    pub fn begin_frame(&self) {
+        let ring = &mut *self.ring.borrow_mut();
+        // Anything still pending went into an encoder that was never submitted
+        ring.pending.clear();
+        ring.offset = 0;
+        ring.flushed = 0;
+        ring.prev_frame_bytes = std::mem::take(&mut ring.frame_bytes);
+    }
+
+    pub fn ui(&self, ui: &mut egui::Ui) {
+        let ring = self.ring.borrow();
+        let total = ring.prev_frame_bytes.values().sum::<u64>();
+        ui.label(format!(
+            "Staged uploads: {:.1} KiB last frame, ring of {} KiB, grown {} times",
+            total as f64 / 1024.0,
+            ring.capacity / 1024,
+            ring.grown
+        ));
+        TableBuilder::new(ui)
+            .column(Column::auto().resizable(true))
+            .column(Column::auto().resizable(true))
+            .header(20.0, |mut header| {
+                header.col(|ui| {
+                    ui.heading("Upload");
+                });
+                header.col(|ui| {
+                    ui.heading("Bytes");
+                });
+            })
+            .body(|mut body| {
+                for (label, bytes) in &ring.prev_frame_bytes {
+                    body.row(20.0, |mut row| {
+                        row.col(|ui| {
+                            ui.label(*label);
+                        });
+                        row.col(|ui| {
+                            ui.label(bytes.to_string());
+                        });
+                    });
+                }
+            });
+    }
+}
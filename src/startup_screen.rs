@@ -0,0 +1,150 @@
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{Duration, Instant};
+
+use winit::window::Window;
+
+use crate::profiler::log_duration;
+use crate::wgpu_context::WgpuContext;
+
+// Every present waits for vsync, so stages that are done quickly don't get a frame each
+#[cfg(not(target_arch = "wasm32"))]
+const PRESENT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Shown while the stages are created, before the main loop starts. wgpu creates pipelines
+/// synchronously and some drivers take seconds compiling them on the first run, so frames listing
+/// the stages created so far are presented in between rather than leaving the window black. On
+/// the web the canvas only updates once control goes back to the browser, there the stages are
+/// only logged.
+#[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+pub struct StartupScreen<'a> {
+    window: &'a Window,
+    egui_state: &'a mut egui_winit::State,
+    egui_renderer: &'a mut egui_wgpu::Renderer,
+    done: Vec<String>,
+    #[cfg(not(target_arch = "wasm32"))]
+    last_present: Option<Instant>,
+}
+
+impl<'a> StartupScreen<'a> {
+    pub fn new(
+        window: &'a Window,
+        egui_state: &'a mut egui_winit::State,
+        egui_renderer: &'a mut egui_wgpu::Renderer,
+    ) -> Self {
+        Self {
+            window,
+            egui_state,
+            egui_renderer,
+            done: Vec::new(),
+            #[cfg(not(target_arch = "wasm32"))]
+            last_present: None,
+        }
+    }
+
+    /// Runs `create` and logs how long it took, showing `name` as the stage being created
+    pub fn stage<T>(&mut self, ctx: &WgpuContext, name: &str, create: impl FnOnce() -> T) -> T {
+        self.present_if_due(ctx, name);
+        self.run(name, create)
+    }
+
+    /// Presents a frame showing `current` as the stage being created, unless one was presented
+    /// only just now
+    pub fn present_if_due(&mut self, ctx: &WgpuContext, current: &str) {
+        #[cfg(not(target_arch = "wasm32"))]
+        if self
+            .last_present
+            .map_or(true, |last| last.elapsed() >= PRESENT_INTERVAL)
+        {
+            self.present(ctx, current);
+            self.last_present = Some(Instant::now());
+        }
+        #[cfg(target_arch = "wasm32")]
+        let _ = (ctx, current);
+    }
+
+    /// Runs `create` and logs how long it took without presenting, for stages whose GPU errors
+    /// are caught and mustn't include the ones of the startup screen itself
+    pub fn run<T>(&mut self, name: &str, create: impl FnOnce() -> T) -> T {
+        let stage = log_duration(name, create);
+        self.done.push(name.to_owned());
+        stage
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn present(&mut self, ctx: &WgpuContext, current: &str) {
+        let Ok(output) = ctx.surface.get_current_texture() else {
+            return;
+        };
+        let view = output
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+
+        let raw_input = self.egui_state.take_egui_input(self.window);
+        let done = &self.done;
+        let full_output = self.egui_state.egui_ctx().run(raw_input, |ui_ctx| {
+            egui::CentralPanel::default().show(ui_ctx, |ui| {
+                ui.vertical_centered(|ui| {
+                    ui.add_space(ui.available_height() * 0.25);
+                    ui.heading("CellularAutomata3d");
+                    ui.label("Creating pipelines, the first start can take a while");
+                    ui.add_space(8.0);
+                    for name in done {
+                        ui.weak(format!("{} done", name));
+                    }
+                    ui.add(egui::Spinner::new());
+                    ui.strong(current);
+                });
+            });
+        });
+
+        let pixels_per_point = full_output.pixels_per_point;
+        let clipped_primitives = self
+            .egui_state
+            .egui_ctx()
+            .tessellate(full_output.shapes, pixels_per_point);
+        for (id, image_delta) in &full_output.textures_delta.set {
+            self.egui_renderer
+                .update_texture(&ctx.device, &ctx.queue, *id, image_delta);
+        }
+        let screen_descriptor = egui_wgpu::ScreenDescriptor {
+            size_in_pixels: [ctx.surface_config.width, ctx.surface_config.height],
+            pixels_per_point,
+        };
+
+        let mut encoder = ctx
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("encoder startup"),
+            });
+        self.egui_renderer.update_buffers(
+            &ctx.device,
+            &ctx.queue,
+            &mut encoder,
+            &clipped_primitives,
+            &screen_descriptor,
+        );
+        {
+            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("renderpass startup"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            self.egui_renderer
+                .render(&mut rpass, &clipped_primitives, &screen_descriptor);
+        }
+        for id in &full_output.textures_delta.free {
+            self.egui_renderer.free_texture(id);
+        }
+        ctx.submit(Some(encoder.finish()));
+        output.present();
+    }
+}
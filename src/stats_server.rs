@@ -0,0 +1,252 @@
+// Tiny HTTP server for watching a running simulation from outside, e.g. from Prometheus or a
+// Grafana JSON data source. Only built with the `stats-server` feature on native targets.
+
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Debug, Default)]
+pub struct StageTiming {
+    pub name: String,
+    pub cpu: Duration,
+    pub gpu: Option<Duration>,
+}
+
+/// What the endpoints report, updated once per frame
+#[derive(Clone, Debug, Default)]
+pub struct EngineStats {
+    // Profiler scopes of the previous frame, the first one is the whole frame
+    pub stages: Vec<StageTiming>,
+    pub tick: u64,
+    pub ticks_per_second: f64,
+    pub paused: bool,
+    // Live cells from the last bounds reduction, if there was one
+    pub population: Option<u64>,
+    pub chunks: u32,
+    pub faces: u64,
+    pub truncated_chunks: u32,
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn json_millis(duration: Option<Duration>) -> String {
+    duration.map_or("null".to_owned(), |d| {
+        format!("{:.4}", d.as_secs_f64() * 1000.0)
+    })
+}
+
+impl EngineStats {
+    pub fn to_json(&self) -> String {
+        let stages = self
+            .stages
+            .iter()
+            .map(|stage| {
+                format!(
+                    "{{\"name\":{},\"cpu_ms\":{},\"gpu_ms\":{}}}",
+                    json_string(&stage.name),
+                    json_millis(Some(stage.cpu)),
+                    json_millis(stage.gpu)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        let frame = self.stages.first();
+        format!(
+            "{{\"frame_cpu_ms\":{},\"frame_gpu_ms\":{},\"stages\":[{}],\"tick\":{},\
+             \"ticks_per_second\":{:.3},\"paused\":{},\"population\":{},\"chunks\":{},\
+             \"faces\":{},\"truncated_chunks\":{}}}",
+            json_millis(frame.map(|f| f.cpu)),
+            json_millis(frame.and_then(|f| f.gpu)),
+            stages,
+            self.tick,
+            self.ticks_per_second,
+            self.paused,
+            self.population.map_or("null".to_owned(), |p| p.to_string()),
+            self.chunks,
+            self.faces,
+            self.truncated_chunks
+        )
+    }
+
+    /// Prometheus text exposition format
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        let mut gauge = |name: &str, help: &str, value: String| {
+            let _ = writeln!(out, "# HELP ca3d_{} {}", name, help);
+            let _ = writeln!(out, "# TYPE ca3d_{} gauge", name);
+            let _ = writeln!(out, "ca3d_{} {}", name, value);
+        };
+        gauge("tick", "Current simulation tick", self.tick.to_string());
+        gauge(
+            "ticks_per_second",
+            "Simulation ticks per second over the last second",
+            format!("{:.3}", self.ticks_per_second),
+        );
+        gauge(
+            "paused",
+            "1 while the simulation is paused",
+            (self.paused as u32).to_string(),
+        );
+        if let Some(population) = self.population {
+            gauge("population", "Live cells", population.to_string());
+        }
+        gauge("chunks", "Loaded chunks", self.chunks.to_string());
+        gauge("faces", "Faces rendered", self.faces.to_string());
+        gauge(
+            "truncated_chunks",
+            "Chunks whose faces didn't fit their buffer",
+            self.truncated_chunks.to_string(),
+        );
+
+        let _ = writeln!(
+            out,
+            "# HELP ca3d_stage_cpu_seconds CPU time of a profiler scope in the last frame"
+        );
+        let _ = writeln!(out, "# TYPE ca3d_stage_cpu_seconds gauge");
+        for stage in &self.stages {
+            let _ = writeln!(
+                out,
+                "ca3d_stage_cpu_seconds{{stage={}}} {:.9}",
+                json_string(&stage.name),
+                stage.cpu.as_secs_f64()
+            );
+        }
+        let _ = writeln!(
+            out,
+            "# HELP ca3d_stage_gpu_seconds GPU time of a profiler scope in the last frame"
+        );
+        let _ = writeln!(out, "# TYPE ca3d_stage_gpu_seconds gauge");
+        for stage in &self.stages {
+            if let Some(gpu) = stage.gpu {
+                let _ = writeln!(
+                    out,
+                    "ca3d_stage_gpu_seconds{{stage={}}} {:.9}",
+                    json_string(&stage.name),
+                    gpu.as_secs_f64()
+                );
+            }
+        }
+        out
+    }
+}
+
+/// Serves the latest `EngineStats` as JSON on `/stats` and in the Prometheus format on `/metrics`,
+/// from a thread of its own so a slow client never stalls a frame
+pub struct StatsServer {
+    stats: Arc<Mutex<EngineStats>>,
+    // Tick and time the tick rate was last measured from, it's averaged over RATE_WINDOW
+    last_tick: Option<(u64, Instant)>,
+    ticks_per_second: f64,
+}
+
+impl StatsServer {
+    pub fn start(addr: &str) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        log::info!("Serving stats on http://{}/stats", listener.local_addr()?);
+        let stats = Arc::new(Mutex::new(EngineStats::default()));
+        let thread_stats = stats.clone();
+        thread::Builder::new()
+            .name("stats-server".to_owned())
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    match stream {
+                        Ok(stream) => {
+                            if let Err(e) = Self::respond(stream, &thread_stats) {
+                                log::warn!("Stats request failed: {}", e);
+                            }
+                        }
+                        Err(e) => log::warn!("Stats connection failed: {}", e),
+                    }
+                }
+            })?;
+        Ok(Self {
+            stats,
+            last_tick: None,
+            ticks_per_second: 0.0,
+        })
+    }
+
+    pub fn publish(&mut self, mut stats: EngineStats) {
+        let now = Instant::now();
+        match self.last_tick {
+            Some((last_tick, last_time)) if now - last_time < RATE_WINDOW => {
+                // Loading a world or stepping back moves the tick backwards
+                if stats.tick < last_tick {
+                    self.last_tick = Some((stats.tick, now));
+                }
+            }
+            Some((last_tick, last_time)) => {
+                let elapsed = (now - last_time).as_secs_f64();
+                self.ticks_per_second = stats.tick.saturating_sub(last_tick) as f64 / elapsed;
+                self.last_tick = Some((stats.tick, now));
+            }
+            None => self.last_tick = Some((stats.tick, now)),
+        }
+        stats.ticks_per_second = self.ticks_per_second;
+        *self.stats.lock().unwrap() = stats;
+    }
+
+    fn respond(stream: TcpStream, stats: &Mutex<EngineStats>) -> std::io::Result<()> {
+        stream.set_read_timeout(Some(Duration::from_secs(2)))?;
+        let mut reader = BufReader::new(&stream);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        // The headers aren't needed, but are read so the client doesn't see a reset
+        let mut header = String::new();
+        while reader.read_line(&mut header)? > 2 {
+            header.clear();
+        }
+
+        let mut parts = request_line.split_whitespace();
+        let (method, path) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+        let path = path.split('?').next().unwrap_or("");
+        let (status, content_type, body) = match (method, path) {
+            ("GET", "/" | "/stats") => (
+                "200 OK",
+                "application/json",
+                stats.lock().unwrap().to_json(),
+            ),
+            ("GET", "/metrics") => (
+                "200 OK",
+                "text/plain; version=0.0.4",
+                stats.lock().unwrap().to_prometheus(),
+            ),
+            ("GET", _) => ("404 Not Found", "text/plain", "Not found\n".to_owned()),
+            _ => (
+                "405 Method Not Allowed",
+                "text/plain",
+                "Only GET is supported\n".to_owned(),
+            ),
+        };
+        let mut stream = &stream;
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            content_type,
+            body.len(),
+            body
+        )?;
+        stream.flush()
+    }
+}
@@ -0,0 +1,115 @@
+// Small key-value persistence layer: files on native, localStorage on the web
+
+#[cfg(not(target_arch = "wasm32"))]
+const DATA_DIR: &str = "ca3d_data";
+
+#[cfg(target_arch = "wasm32")]
+const KEY_PREFIX: &str = "ca3d.";
+
+#[cfg(not(target_arch = "wasm32"))]
+fn path(key: &str) -> std::path::PathBuf {
+    std::path::Path::new(DATA_DIR).join(key)
+}
+
+#[cfg(target_arch = "wasm32")]
+fn local_storage() -> Option<web_sys::Storage> {
+    web_sys::window()?.local_storage().ok()?
+}
+
+pub fn read_string(key: &str) -> Option<String> {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        std::fs::read_to_string(path(key)).ok()
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    {
+        local_storage()?
+            .get_item(&format!("{}{}", KEY_PREFIX, key))
+            .ok()?
+    }
+}
+
+pub fn write_string(key: &str, contents: &str) -> Result<(), String> {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let path = path(key);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        std::fs::write(path, contents).map_err(|e| e.to_string())
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    {
+        local_storage()
+            .ok_or_else(|| "localStorage unavailable".to_owned())?
+            .set_item(&format!("{}{}", KEY_PREFIX, key), contents)
+            .map_err(|e| format!("{:?}", e))
+    }
+}
+
+/// Binary files only exist on native, localStorage can only hold strings
+pub fn write_bytes(key: &str, contents: &[u8]) -> Result<(), String> {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let path = path(key);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        std::fs::write(path, contents).map_err(|e| e.to_string())
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    {
+        let _ = (key, contents);
+        Err("binary files are not supported on the web".to_owned())
+    }
+}
+
+pub fn remove(key: &str) -> Result<(), String> {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        std::fs::remove_file(path(key)).map_err(|e| e.to_string())
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    {
+        local_storage()
+            .ok_or_else(|| "localStorage unavailable".to_owned())?
+            .remove_item(&format!("{}{}", KEY_PREFIX, key))
+            .map_err(|e| format!("{:?}", e))
+    }
+}
+
+/// Names of the keys directly inside `dir`, sorted
+pub fn list(dir: &str) -> Vec<String> {
+    #[cfg(not(target_arch = "wasm32"))]
+    let mut names = std::fs::read_dir(path(dir))
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.file_type().is_ok_and(|t| t.is_file()))
+                .filter_map(|entry| entry.file_name().into_string().ok())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    #[cfg(target_arch = "wasm32")]
+    let mut names = {
+        let prefix = format!("{}{}/", KEY_PREFIX, dir);
+        local_storage()
+            .map(|storage| {
+                let len = storage.length().unwrap_or(0);
+                (0..len)
+                    .filter_map(|i| storage.key(i).ok().flatten())
+                    .filter_map(|key| key.strip_prefix(&prefix).map(|name| name.to_owned()))
+                    .filter(|name| !name.contains('/'))
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default()
+    };
+
+    names.sort();
+    names
+}
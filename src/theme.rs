@@ -0,0 +1,184 @@
+use nalgebra_glm as glm;
+
+use crate::settings::Settings;
+
+const THEME_KEY_PREFIX: &str = "theme.";
+
+/// Colors the overlay draws with, so they can be matched to the voxel palette or to what a
+/// recording needs. Colors the user picks per item, like annotations or the paint color, aren't
+/// part of it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Theme {
+    // The line from the origin the overlay always draws
+    pub origin: [f32; 3],
+    pub erase_brush: [f32; 3],
+    pub containment: [f32; 3],
+    pub camera_path: [f32; 3],
+    // View direction of every camera path keyframe
+    pub keyframe: [f32; 3],
+    // Seam check voxels that are missing on one side, and that differ between the sides
+    pub seam_missing: [f32; 3],
+    pub seam_mismatch: [f32; 3],
+    pub selection: [f32; 3],
+    // Newest end of the pick trail, it fades out from there
+    pub pick_trail: [f32; 3],
+    // Chunks that changed just now, they darken as the change gets older
+    pub chunk_activity: [f32; 3],
+}
+
+const PRESETS: [(&str, Theme); 4] = [
+    (
+        "Default",
+        Theme {
+            origin: [1.0, 0.0, 1.0],
+            erase_brush: [1.0, 1.0, 1.0],
+            containment: [0.2, 0.8, 1.0],
+            camera_path: [1.0, 0.8, 0.2],
+            keyframe: [0.2, 0.8, 1.0],
+            seam_missing: [1.0, 0.9, 0.1],
+            seam_mismatch: [1.0, 0.1, 0.1],
+            selection: [0.4, 1.0, 0.4],
+            pick_trail: [1.0, 0.5, 0.1],
+            chunk_activity: [1.0, 0.6, 0.1],
+        },
+    ),
+    (
+        "High contrast",
+        Theme {
+            origin: [1.0, 1.0, 1.0],
+            erase_brush: [1.0, 1.0, 0.0],
+            containment: [0.0, 1.0, 0.0],
+            camera_path: [1.0, 0.0, 1.0],
+            keyframe: [0.0, 1.0, 1.0],
+            seam_missing: [1.0, 1.0, 0.0],
+            seam_mismatch: [1.0, 0.0, 0.0],
+            selection: [0.0, 0.5, 1.0],
+            pick_trail: [1.0, 0.0, 0.5],
+            chunk_activity: [1.0, 0.5, 0.0],
+        },
+    ),
+    (
+        "Muted",
+        Theme {
+            origin: [0.6, 0.45, 0.6],
+            erase_brush: [0.75, 0.75, 0.75],
+            containment: [0.45, 0.6, 0.7],
+            camera_path: [0.75, 0.65, 0.45],
+            keyframe: [0.45, 0.6, 0.7],
+            seam_missing: [0.75, 0.7, 0.4],
+            seam_mismatch: [0.75, 0.4, 0.4],
+            selection: [0.5, 0.7, 0.5],
+            pick_trail: [0.75, 0.55, 0.4],
+            chunk_activity: [0.75, 0.6, 0.35],
+        },
+    ),
+    // For bright worlds and light backgrounds
+    (
+        "Dark",
+        Theme {
+            origin: [0.4, 0.0, 0.4],
+            erase_brush: [0.05, 0.05, 0.05],
+            containment: [0.0, 0.2, 0.5],
+            camera_path: [0.5, 0.25, 0.0],
+            keyframe: [0.0, 0.3, 0.4],
+            seam_missing: [0.45, 0.35, 0.0],
+            seam_mismatch: [0.5, 0.0, 0.0],
+            selection: [0.0, 0.35, 0.1],
+            pick_trail: [0.45, 0.15, 0.0],
+            chunk_activity: [0.5, 0.3, 0.0],
+        },
+    ),
+];
+
+pub fn rgba([r, g, b]: [f32; 3]) -> glm::Vec4 {
+    glm::vec4(r, g, b, 1.0)
+}
+
+fn to_hex(color: [f32; 3]) -> String {
+    let [r, g, b] = color.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+fn from_hex(hex: &str) -> Option<[f32; 3]> {
+    let hex = hex.strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?].map(|c| c as f32 / 255.0))
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        PRESETS[0].1
+    }
+}
+
+impl Theme {
+    /// Settings key, label and value of every color
+    fn colors_mut(&mut self) -> [(&'static str, &'static str, &mut [f32; 3]); 10] {
+        [
+            ("origin", "Origin line", &mut self.origin),
+            ("erase_brush", "Eraser", &mut self.erase_brush),
+            ("containment", "Containment", &mut self.containment),
+            ("camera_path", "Camera path", &mut self.camera_path),
+            ("keyframe", "Keyframe direction", &mut self.keyframe),
+            ("seam_missing", "Seam missing", &mut self.seam_missing),
+            ("seam_mismatch", "Seam mismatch", &mut self.seam_mismatch),
+            ("selection", "Selection", &mut self.selection),
+            ("pick_trail", "Pick trail", &mut self.pick_trail),
+            ("chunk_activity", "Chunk activity", &mut self.chunk_activity),
+        ]
+    }
+
+    /// The saved colors, colors that weren't saved come from the default preset
+    pub fn load(settings: &Settings) -> Self {
+        let mut theme = Self::default();
+        for (key, _, color) in theme.colors_mut() {
+            if let Some(saved) = settings
+                .get::<String>(&format!("{}{}", THEME_KEY_PREFIX, key))
+                .and_then(|hex| from_hex(&hex))
+            {
+                *color = saved;
+            }
+        }
+        theme
+    }
+
+    fn save(&mut self, settings: &mut Settings) {
+        for (key, _, color) in self.colors_mut() {
+            settings.set(&format!("{}{}", THEME_KEY_PREFIX, key), to_hex(*color));
+        }
+        settings.save();
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui, settings: &mut Settings) {
+        ui.collapsing("Overlay colors", |ui| {
+            let mut changed = false;
+            let current = PRESETS
+                .iter()
+                .find(|(_, preset)| *preset == *self)
+                .map_or("Custom", |(name, _)| *name);
+            egui::ComboBox::from_label("Preset")
+                .selected_text(current)
+                .show_ui(ui, |ui| {
+                    for (name, preset) in PRESETS {
+                        if ui.selectable_label(current == name, name).clicked() {
+                            *self = preset;
+                            changed = true;
+                        }
+                    }
+                });
+            egui::Grid::new("theme_colors").show(ui, |ui| {
+                for (_, label, color) in self.colors_mut() {
+                    ui.label(label);
+                    changed |= ui.color_edit_button_rgb(color).changed();
+                    ui.end_row();
+                }
+            });
+            if changed {
+                self.save(settings);
+            }
+        });
+    }
+}
@@ -0,0 +1,259 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use nalgebra_glm as glm;
+use wgpu::*;
+
+use crate::chunk_manager::ChunkManager;
+use crate::gpu_stage::meshing_render::{PerChunkResource, Render};
+use crate::gpu_stage::tonemap::Tonemap;
+use crate::resource_tracker;
+use crate::util::{RenderTarget, RenderTargetInfo, TextureAndView};
+use crate::wgpu_context::WgpuContext;
+
+pub const THUMBNAIL_SIZE: u32 = 64;
+const THUMBNAIL_FORMAT: TextureFormat = TextureFormat::Rgba8UnormSrgb;
+
+/// Rgba8 pixels of a thumbnail, ready once the readback buffer is mapped
+pub struct ThumbnailCapture {
+    buffer: Buffer,
+    mapped: Arc<AtomicBool>,
+    failed: Arc<AtomicBool>,
+    width: u32,
+    padded_bytes_per_row: u32,
+}
+
+impl ThumbnailCapture {
+    /// Records a copy of a 4 bytes per pixel `texture` into a new readback buffer. Reading it
+    /// back only starts with `start_map`, once the copy has been submitted.
+    pub fn copy_from(ctx: &WgpuContext, encoder: &mut CommandEncoder, texture: &Texture) -> Self {
+        let (width, height) = (texture.width(), texture.height());
+        let padded_bytes_per_row = (width * 4).next_multiple_of(COPY_BYTES_PER_ROW_ALIGNMENT);
+        let buffer = ctx.device.create_buffer(&BufferDescriptor {
+            label: Some("thumbnail readback_buffer"),
+            size: (padded_bytes_per_row * height) as u64,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        encoder.copy_texture_to_buffer(
+            ImageCopyTexture {
+                texture,
+                mip_level: 0,
+                origin: Origin3d::ZERO,
+                aspect: TextureAspect::All,
+            },
+            ImageCopyBuffer {
+                buffer: &buffer,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+        Self {
+            buffer,
+            mapped: Arc::new(AtomicBool::new(false)),
+            failed: Arc::new(AtomicBool::new(false)),
+            width,
+            padded_bytes_per_row,
+        }
+    }
+
+    pub fn start_map(&self) {
+        let mapped = self.mapped.clone();
+        let failed = self.failed.clone();
+        self.buffer
+            .slice(..)
+            .map_async(MapMode::Read, move |result| match result {
+                Ok(_) => mapped.store(true, Ordering::Release),
+                Err(e) => {
+                    log::error!("Failed to map thumbnail buffer: {:?}", e);
+                    failed.store(true, Ordering::Release);
+                }
+            });
+    }
+
+    /// Tightly packed rows, without the copy alignment padding. `None` until the buffer is
+    /// mapped, an error if mapping it failed.
+    pub fn try_take(&self) -> Option<Result<Vec<u8>, String>> {
+        if self.failed.load(Ordering::Acquire) {
+            return Some(Err("Failed to read the image back from the GPU".to_owned()));
+        }
+        if !self.mapped.load(Ordering::Acquire) {
+            return None;
+        }
+        let pixels = self
+            .buffer
+            .slice(..)
+            .get_mapped_range()
+            .chunks(self.padded_bytes_per_row as usize)
+            .flat_map(|row| &row[..self.width as usize * 4])
+            .copied()
+            .collect();
+        self.buffer.unmap();
+        self.mapped.store(false, Ordering::Release);
+        Some(Ok(pixels))
+    }
+}
+
+/// A second render and tonemap chain at its own resolution, used for asset thumbnails and for
+/// recording camera paths
+pub struct ThumbnailRenderer {
+    render: Render,
+    tonemap: Tonemap,
+    width: u32,
+    height: u32,
+    output: TextureAndView,
+    // Keeps the depth target alive, the render stage only holds a view
+    _depth: TextureAndView,
+}
+
+impl ThumbnailRenderer {
+    fn new_texture(
+        ctx: &WgpuContext,
+        label: &str,
+        (width, height): (u32, u32),
+        format: TextureFormat,
+        usage: TextureUsages,
+    ) -> TextureAndView {
+        let texture = ctx.device.create_texture(&TextureDescriptor {
+            label: Some(label),
+            size: Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format,
+            usage,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&TextureViewDescriptor::default());
+        TextureAndView { texture, view }
+    }
+
+    pub fn new(ctx: &WgpuContext) -> Self {
+        Self::with_size(ctx, THUMBNAIL_SIZE, THUMBNAIL_SIZE)
+    }
+
+    pub fn with_size(ctx: &WgpuContext, width: u32, height: u32) -> Self {
+        let tonemap = Tonemap::new(
+            ctx,
+            Rc::new(RenderTargetInfo {
+                format: THUMBNAIL_FORMAT,
+                width,
+                height,
+            }),
+        );
+        let depth = Self::new_texture(
+            ctx,
+            "thumbnail depth",
+            (width, height),
+            TextureFormat::Depth32Float,
+            TextureUsages::RENDER_ATTACHMENT,
+        );
+        let output = Self::new_texture(
+            ctx,
+            "thumbnail output",
+            (width, height),
+            THUMBNAIL_FORMAT,
+            TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_SRC,
+        );
+
+        let tonemap_input = tonemap.input_target();
+        let mut render = Render::new(
+            ctx,
+            Rc::new(RenderTarget {
+                render_target: tonemap_input.render_target.clone(),
+                depth_target: Some(Rc::new(resource_tracker::view(
+                    &depth.texture,
+                    &TextureViewDescriptor {
+                        label: Some("thumbnail depth_view"),
+                        ..Default::default()
+                    },
+                ))),
+                info: RenderTargetInfo {
+                    format: tonemap_input.info.format,
+                    width,
+                    height,
+                },
+            }),
+        );
+        // Thumbnails show every chunk whole, even the ones still fading in on screen
+        render.fade_frames = 0;
+
+        Self {
+            render,
+            tonemap,
+            width,
+            height,
+            output,
+            _depth: depth,
+        }
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// Renders the current meshes from `view_proj` with the main tonemap settings and starts
+    /// reading the result back
+    pub fn capture(
+        &mut self,
+        ctx: &WgpuContext,
+        tonemap_settings: &Tonemap,
+        chunk_manager: &ChunkManager,
+        per_chunk_resources: &HashMap<glm::IVec3, PerChunkResource>,
+        view_proj: &glm::Mat4,
+    ) -> ThumbnailCapture {
+        let mut encoder = ctx
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("encoder thumbnail"),
+            });
+        self.render.update(
+            ctx,
+            &mut encoder,
+            chunk_manager,
+            per_chunk_resources,
+            view_proj,
+            0,
+        );
+        self.tonemap.copy_settings(tonemap_settings);
+        self.tonemap.update(ctx, &mut encoder, false);
+        {
+            let final_draw_resources = self.tonemap.final_draw_resources();
+            let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+                label: Some("thumbnail render_pass"),
+                color_attachments: &[Some(RenderPassColorAttachment {
+                    view: &self.output.view,
+                    resolve_target: None,
+                    ops: Operations {
+                        load: LoadOp::Clear(Color::BLACK),
+                        store: StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            render_pass.set_pipeline(&final_draw_resources.pipeline);
+            render_pass.set_bind_group(0, &final_draw_resources.bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+        let capture = ThumbnailCapture::copy_from(ctx, &mut encoder, &self.output.texture);
+        ctx.submit([encoder.finish()]);
+        capture.start_map();
+        capture
+    }
+}
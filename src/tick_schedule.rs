@@ -0,0 +1,187 @@
+// Longest gap between frames that absolute rates catch up on, so a stall doesn't end in a burst
+const MAX_CATCH_UP: f64 = 0.25;
+// Absolute rates run this many ticks in a frame at most
+const MAX_TICKS_PER_FRAME: u32 = 1024;
+
+/// When the simulation ticks, relative to the frames presented
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TickRate {
+    // The simulation's iterations every frame
+    EveryFrame,
+    // The iterations every this many frames
+    EveryNFrames(u32),
+    // Ticks per second of wall clock time, whatever the frame rate
+    PerSecond(f32),
+}
+
+/// Spreads ticks over frames. Rates tied to the frames tick the same amount on every frame the
+/// display shows, while an absolute rate that isn't a multiple or fraction of the refresh rate
+/// ticks unevenly from frame to frame, which shows as stutter.
+pub struct TickSchedule {
+    pub rate: TickRate,
+    // Of the monitor the window is on, in hertz, when winit knows it
+    refresh_rate: Option<f32>,
+    frame: u64,
+    // Fraction of a tick carried over to the next frame under an absolute rate
+    owed: f64,
+    now: f64,
+    last_now: Option<f64>,
+}
+
+impl TickSchedule {
+    pub fn new() -> Self {
+        Self {
+            rate: TickRate::EveryFrame,
+            refresh_rate: None,
+            frame: 0,
+            owed: 0.0,
+            now: 0.0,
+            last_now: None,
+        }
+    }
+
+    pub fn set_refresh_rate(&mut self, refresh_rate: Option<f32>) {
+        if refresh_rate != self.refresh_rate {
+            if let Some(hz) = refresh_rate {
+                log::info!("Display refresh rate is {:.2} Hz", hz);
+            }
+        }
+        self.refresh_rate = refresh_rate;
+    }
+
+    /// Current time in seconds, set once per frame
+    pub fn set_now(&mut self, now: f64) {
+        self.now = now;
+    }
+
+    /// Ticks to run this frame, `iterations` is how many the frame locked rates run at once
+    pub fn ticks_this_frame(&mut self, iterations: u32) -> u32 {
+        let dt = self
+            .last_now
+            .map_or(0.0, |last| (self.now - last).clamp(0.0, MAX_CATCH_UP));
+        self.last_now = Some(self.now);
+        self.frame += 1;
+        match self.rate {
+            TickRate::EveryFrame => iterations,
+            TickRate::EveryNFrames(n) => {
+                if self.frame % n.max(1) as u64 == 0 {
+                    iterations
+                } else {
+                    0
+                }
+            }
+            TickRate::PerSecond(tps) => {
+                self.owed += dt * tps.max(0.0) as f64;
+                let ticks = (self.owed.floor() as u32).min(MAX_TICKS_PER_FRAME);
+                self.owed -= ticks as f64;
+                // Whatever couldn't be caught up on is dropped
+                self.owed = self.owed.min(1.0);
+                ticks
+            }
+        }
+    }
+
+    /// Forgets the time of the last frame, after frames that didn't tick at all
+    pub fn pause(&mut self) {
+        self.last_now = None;
+        self.owed = 0.0;
+    }
+
+    /// Ticks per second the rate works out to, frame locked rates need the refresh rate
+    pub fn ticks_per_second(&self, iterations: u32) -> Option<f32> {
+        match self.rate {
+            TickRate::EveryFrame => self.refresh_rate.map(|hz| hz * iterations as f32),
+            TickRate::EveryNFrames(n) => self
+                .refresh_rate
+                .map(|hz| hz * iterations as f32 / n.max(1) as f32),
+            TickRate::PerSecond(tps) => Some(tps),
+        }
+    }
+
+    /// The frame locked rate closest to `tps`, as frames per tick and ticks per frame
+    fn synced(&self, tps: f32) -> Option<(u32, u32)> {
+        let hz = self.refresh_rate?;
+        Some(if tps >= hz {
+            (
+                1,
+                (tps / hz).round().clamp(1.0, MAX_TICKS_PER_FRAME as f32) as u32,
+            )
+        } else {
+            (
+                (hz / tps.max(f32::EPSILON)).round().clamp(1.0, 240.0) as u32,
+                1,
+            )
+        })
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui, iterations: &mut u32) {
+        ui.horizontal(|ui| {
+            ui.label("Tick");
+            if ui
+                .radio(self.rate == TickRate::EveryFrame, "Every frame")
+                .clicked()
+            {
+                self.rate = TickRate::EveryFrame;
+            }
+            let every_n = matches!(self.rate, TickRate::EveryNFrames(_));
+            if ui.radio(every_n, "Every n frames").clicked() && !every_n {
+                self.rate = TickRate::EveryNFrames(2);
+            }
+            let per_second = matches!(self.rate, TickRate::PerSecond(_));
+            if ui.radio(per_second, "Per second").clicked() && !per_second {
+                let tps = self.ticks_per_second(*iterations).unwrap_or(60.0);
+                self.rate = TickRate::PerSecond(tps);
+            }
+        });
+        match &mut self.rate {
+            TickRate::EveryFrame => {}
+            TickRate::EveryNFrames(n) => {
+                ui.add(egui::Slider::new(n, 1..=16).text("Frames per tick"));
+            }
+            TickRate::PerSecond(tps) => {
+                ui.add(
+                    egui::Slider::new(tps, 0.1..=10000.0)
+                        .logarithmic(true)
+                        .text("Ticks per second"),
+                );
+            }
+        }
+
+        match self.refresh_rate {
+            Some(hz) => {
+                let tps = self.ticks_per_second(*iterations).unwrap_or(0.0);
+                ui.label(format!("{:.1} ticks per second at {:.0} Hz", tps, hz));
+            }
+            None => {
+                ui.label("Display refresh rate unknown");
+            }
+        }
+        if let TickRate::PerSecond(tps) = self.rate {
+            if let Some((frames, ticks)) = self.synced(tps) {
+                let hz = self.refresh_rate.unwrap_or(0.0);
+                let exact = hz * ticks as f32 / frames as f32;
+                if (tps - exact).abs() > exact * 0.01 {
+                    ui.horizontal(|ui| {
+                        ui.colored_label(
+                            ui.visuals().warn_fg_color,
+                            "Uneven ticks per frame, may stutter",
+                        );
+                        if ui
+                            .button(format!("Sync to {:.1}", exact))
+                            .on_hover_text("Closest rate that ticks the same on every frame")
+                            .clicked()
+                        {
+                            if frames == 1 {
+                                self.rate = TickRate::EveryFrame;
+                                *iterations = ticks;
+                            } else {
+                                self.rate = TickRate::EveryNFrames(frames);
+                                *iterations = 1;
+                            }
+                        }
+                    });
+                }
+            }
+        }
+    }
+}
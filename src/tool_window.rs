@@ -0,0 +1,240 @@
+use std::sync::Arc;
+
+use egui::ViewportId;
+use winit::event::WindowEvent;
+use winit::event_loop::EventLoopWindowTarget;
+use winit::window::{Window, WindowBuilder, WindowId};
+
+use crate::user_event::UserEvent;
+use crate::wgpu_context::WgpuContext;
+
+/// Tool windows that can be moved out of the main window into their own OS window
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ToolWindow {
+    RenderOptions,
+    Stats,
+    Profiler,
+    Assets,
+    Timeline,
+    Legend,
+}
+
+impl ToolWindow {
+    pub const ALL: [ToolWindow; 6] = [
+        ToolWindow::RenderOptions,
+        ToolWindow::Stats,
+        ToolWindow::Profiler,
+        ToolWindow::Assets,
+        ToolWindow::Timeline,
+        ToolWindow::Legend,
+    ];
+
+    pub fn title(self) -> &'static str {
+        match self {
+            ToolWindow::RenderOptions => "Render options",
+            ToolWindow::Stats => "Stats",
+            ToolWindow::Profiler => "Profiler",
+            ToolWindow::Assets => "Assets",
+            ToolWindow::Timeline => "Timeline",
+            ToolWindow::Legend => "Legend",
+        }
+    }
+
+    // Stable name used in the settings
+    pub fn key(self) -> &'static str {
+        match self {
+            ToolWindow::RenderOptions => "render_options",
+            ToolWindow::Stats => "stats",
+            ToolWindow::Profiler => "profiler",
+            ToolWindow::Assets => "assets",
+            ToolWindow::Timeline => "timeline",
+            ToolWindow::Legend => "legend",
+        }
+    }
+}
+
+/// An OS window showing one tool window, with its own surface and egui context. The main window
+/// keeps the 3D view.
+pub struct DetachedWindow {
+    pub tool: ToolWindow,
+    window: Arc<Window>,
+    surface: wgpu::Surface<'static>,
+    surface_config: wgpu::SurfaceConfiguration,
+    egui_state: egui_winit::State,
+    egui_renderer: egui_wgpu::Renderer,
+}
+
+impl DetachedWindow {
+    pub fn new(
+        elwt: &EventLoopWindowTarget<UserEvent>,
+        instance: &wgpu::Instance,
+        ctx: &WgpuContext,
+        tool: ToolWindow,
+    ) -> Option<Self> {
+        let window = match WindowBuilder::new()
+            .with_title(format!("CellularAutomata3d - {}", tool.title()))
+            .with_inner_size(winit::dpi::LogicalSize::new(420.0, 640.0))
+            .build(elwt)
+        {
+            Ok(window) => Arc::new(window),
+            Err(e) => {
+                log::error!("Could not create a window for {}: {}", tool.title(), e);
+                return None;
+            }
+        };
+        let surface = match instance.create_surface(window.clone()) {
+            Ok(surface) => surface,
+            Err(e) => {
+                log::error!("Could not create a surface for {}: {}", tool.title(), e);
+                return None;
+            }
+        };
+
+        let surface_caps = surface.get_capabilities(&ctx.adapter);
+        if surface_caps.formats.is_empty() {
+            log::error!("The adapter can't present to the {} window", tool.title());
+            return None;
+        }
+        let format = WgpuContext::preferred_surface_format(&surface_caps);
+        let surface_config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format,
+            width: window.inner_size().width.max(1),
+            height: window.inner_size().height.max(1),
+            present_mode: wgpu::PresentMode::Fifo,
+            desired_maximum_frame_latency: 2,
+            alpha_mode: surface_caps.alpha_modes[0],
+            view_formats: vec![],
+        };
+        surface.configure(&ctx.device, &surface_config);
+
+        let egui_state = egui_winit::State::new(
+            egui::Context::default(),
+            ViewportId::default(),
+            &window,
+            None,
+            Some(4096),
+        );
+        let egui_renderer = egui_wgpu::Renderer::new(&ctx.device, format, None, 1);
+
+        Some(Self {
+            tool,
+            window,
+            surface,
+            surface_config,
+            egui_state,
+            egui_renderer,
+        })
+    }
+
+    pub fn id(&self) -> WindowId {
+        self.window.id()
+    }
+
+    pub fn request_redraw(&self) {
+        self.window.request_redraw();
+    }
+
+    /// Handles an event for this window, returns false once the window was closed
+    pub fn window_event(&mut self, ctx: &WgpuContext, event: &WindowEvent) -> bool {
+        let response = self.egui_state.on_window_event(&self.window, event);
+        if response.repaint {
+            self.window.request_redraw();
+        }
+        match event {
+            WindowEvent::CloseRequested => return false,
+            WindowEvent::Resized(size) => {
+                self.surface_config.width = size.width.max(1);
+                self.surface_config.height = size.height.max(1);
+                self.surface.configure(&ctx.device, &self.surface_config);
+            }
+            _ => (),
+        }
+        true
+    }
+
+    /// Draws `add_contents` over the whole window, returns whether egui wants to repaint soon,
+    /// which is when the contents were interacted with
+    pub fn redraw(&mut self, ctx: &WgpuContext, add_contents: impl FnOnce(&mut egui::Ui)) -> bool {
+        let surface_texture = match self.surface.get_current_texture() {
+            Ok(surface_texture) => surface_texture,
+            Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
+                self.surface.configure(&ctx.device, &self.surface_config);
+                self.window.request_redraw();
+                return false;
+            }
+            Err(e) => {
+                log::warn!("get_current_texture() for {}: {}", self.tool.title(), e);
+                return false;
+            }
+        };
+        let surface_view = surface_texture
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+
+        let raw_input = self.egui_state.take_egui_input(&self.window);
+        let full_output = self.egui_state.egui_ctx().run(raw_input, |egui_ctx| {
+            egui::CentralPanel::default().show(egui_ctx, |ui| {
+                egui::ScrollArea::vertical().show(ui, add_contents);
+            });
+        });
+        self.egui_state
+            .handle_platform_output(&self.window, full_output.platform_output);
+        let repaint = full_output
+            .viewport_output
+            .get(&ViewportId::ROOT)
+            .is_some_and(|output| output.repaint_delay.is_zero());
+
+        let pixels_per_point = self.egui_state.egui_ctx().pixels_per_point();
+        let clipped_primitives = self
+            .egui_state
+            .egui_ctx()
+            .tessellate(full_output.shapes, pixels_per_point);
+        for (id, image_delta) in &full_output.textures_delta.set {
+            self.egui_renderer
+                .update_texture(&ctx.device, &ctx.queue, *id, image_delta);
+        }
+        let screen_descriptor = egui_wgpu::ScreenDescriptor {
+            size_in_pixels: [self.surface_config.width, self.surface_config.height],
+            pixels_per_point,
+        };
+
+        let mut encoder = ctx
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("encoder tool_window"),
+            });
+        self.egui_renderer.update_buffers(
+            &ctx.device,
+            &ctx.queue,
+            &mut encoder,
+            &clipped_primitives,
+            &screen_descriptor,
+        );
+        {
+            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("renderpass tool_window"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &surface_view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            self.egui_renderer
+                .render(&mut rpass, &clipped_primitives, &screen_descriptor);
+        }
+        for id in &full_output.textures_delta.free {
+            self.egui_renderer.free_texture(id);
+        }
+
+        ctx.queue.submit(Some(encoder.finish()));
+        surface_texture.present();
+        repaint
+    }
+}
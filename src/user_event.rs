@@ -1,5 +1,10 @@
+use crate::tool_window::ToolWindow;
+
 pub enum UserEvent {
     RequestCursorLock(bool),
     NotifyCursorLockStatus(bool),
     RequestResize,
+    RequestPlaneMode(bool),
+    RequestReseed,
+    DetachToolWindow(ToolWindow),
 }
@@ -2,6 +2,7 @@ use bytemuck::{Pod, Zeroable};
 use std::rc::Rc;
 use wgpu::{Texture, TextureFormat, TextureView};
 
+use crate::resource_tracker::Tracked;
 use crate::wgpu_context::WgpuContext;
 
 pub struct RenderTargetInfo {
@@ -21,8 +22,8 @@ impl From<&WgpuContext<'_>> for RenderTargetInfo {
 }
 
 pub struct RenderTarget {
-    pub render_target: Rc<TextureView>,
-    pub depth_target: Option<Rc<TextureView>>,
+    pub render_target: Rc<Tracked<TextureView>>,
+    pub depth_target: Option<Rc<Tracked<TextureView>>>,
     pub info: RenderTargetInfo,
 }
 
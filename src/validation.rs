@@ -0,0 +1,130 @@
+// Checks the engine config against the device limits before anything is created, so a config
+// that can't work is reported with what to change instead of failing inside wgpu validation.
+
+use crate::chunk::CHUNK_SIZE;
+use crate::chunk_datastore::{ATLAS_OFFSET, ATLAS_SIZE, MAX_GRID_GROUPS};
+use crate::engine_config::EngineConfig;
+use crate::gpu_stage::{meshing_render, simulate};
+
+fn prev_power_of_two(n: u32) -> u32 {
+    1 << n.max(1).ilog2()
+}
+
+/// Everything about `config` that `limits` don't allow, each with the change that fixes it
+pub fn check_limits(limits: &wgpu::Limits, config: &EngineConfig) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    let max_3d = limits.max_texture_dimension_3d;
+    // A grid group holds both buffers of its chunks stacked along z
+    let grid_depth = CHUNK_SIZE * 2;
+    if grid_depth > max_3d || ATLAS_SIZE > max_3d {
+        problems.push(format!(
+            "3d textures can be at most {} texels on a side, the chunk grids need {} and the atlas \
+             {}, so this device can't run the engine",
+            max_3d, grid_depth, ATLAS_SIZE
+        ));
+        return problems;
+    }
+
+    let max_chunks_per_group = prev_power_of_two(max_3d / CHUNK_SIZE);
+    let chunks_per_group = match config.chunks_per_group {
+        Some(n) if !n.is_power_of_two() => {
+            problems.push(format!(
+                "chunks_per_group is {} but has to be a power of two, set it to {}",
+                n,
+                prev_power_of_two(n).min(max_chunks_per_group)
+            ));
+            max_chunks_per_group
+        }
+        Some(n) if n > max_chunks_per_group => {
+            problems.push(format!(
+                "chunks_per_group {} makes grid textures {} texels wide but the device allows {}, \
+                 reduce chunks_per_group to {}",
+                n,
+                n * CHUNK_SIZE,
+                max_3d,
+                max_chunks_per_group
+            ));
+            max_chunks_per_group
+        }
+        Some(n) => n,
+        None => max_chunks_per_group,
+    };
+
+    let world_size = config.world_size.max(1) as u32;
+    let capacity = MAX_GRID_GROUPS * chunks_per_group;
+    let chunks = (world_size as u64).pow(3);
+    if chunks > capacity as u64 {
+        problems.push(format!(
+            "world_size {} needs {} chunks but {} grid groups of {} chunks hold {}, reduce \
+             world_size to {}",
+            world_size,
+            chunks,
+            MAX_GRID_GROUPS,
+            chunks_per_group,
+            capacity,
+            (capacity as f64).cbrt().floor()
+        ));
+    }
+    let atlas_extent = (ATLAS_SIZE as i32 - ATLAS_OFFSET) as u32;
+    if world_size > atlas_extent {
+        problems.push(format!(
+            "world_size {} reaches past the chunk atlas, reduce world_size to {}",
+            world_size, atlas_extent
+        ));
+    }
+
+    // The atlas and every grid group are bound together
+    let storage_textures = MAX_GRID_GROUPS + 1;
+    if storage_textures > limits.max_storage_textures_per_shader_stage {
+        problems.push(format!(
+            "the chunk bind group has {} storage textures but the device allows {} per stage, \
+             lower MAX_GRID_GROUPS to {}",
+            storage_textures,
+            limits.max_storage_textures_per_shader_stage,
+            limits
+                .max_storage_textures_per_shader_stage
+                .saturating_sub(1)
+        ));
+    }
+
+    for (stage, size) in [
+        ("simulate", simulate::PUSH_CONSTANTS_SIZE),
+        ("meshing", meshing_render::MESHING_PUSH_CONSTANTS_SIZE),
+        ("render", meshing_render::RENDER_PUSH_CONSTANTS_SIZE),
+    ] {
+        if size > limits.max_push_constant_size {
+            problems.push(format!(
+                "{} uses {} bytes of push constants but the device allows {}",
+                stage, size, limits.max_push_constant_size
+            ));
+        }
+    }
+
+    // The simulation starts with the largest workgroup size
+    let max_invocations = limits.max_compute_invocations_per_workgroup;
+    let default_size = *simulate::WORKGROUP_SIZE_CANDIDATES.last().unwrap();
+    if default_size.pow(3) > max_invocations {
+        let fitting = simulate::WORKGROUP_SIZE_CANDIDATES
+            .iter()
+            .rev()
+            .find(|size| size.pow(3) <= max_invocations);
+        problems.push(match fitting {
+            Some(size) => format!(
+                "simulation workgroups of {}^3 need {} invocations but the device allows {}, \
+                 reduce the workgroup size to {}",
+                default_size,
+                default_size.pow(3),
+                max_invocations,
+                size
+            ),
+            None => format!(
+                "the device allows {} invocations per workgroup, too few for any simulation \
+                 workgroup size",
+                max_invocations
+            ),
+        });
+    }
+
+    problems
+}
@@ -1,4 +1,5 @@
 use crate::profiler::Profiler;
+use crate::staging::StagingRing;
 
 use wgpu::*;
 
@@ -11,4 +12,69 @@ pub struct WgpuContext<'window> {
     pub surface_format: TextureFormat,
     pub surface_config: SurfaceConfiguration,
     pub profiler: Profiler,
+    pub staging: StagingRing,
+}
+
+impl WgpuContext<'_> {
+    /// The first sRGB format the surface supports, or its preferred format if there's none
+    pub fn preferred_surface_format(caps: &SurfaceCapabilities) -> TextureFormat {
+        caps.formats
+            .iter()
+            .copied()
+            .find(|format| format.is_srgb())
+            .unwrap_or(caps.formats[0])
+    }
+
+    /// Queries the surface capabilities again, since they can change when the window moves to
+    /// another monitor, and updates the configuration to match. Returns whether the format
+    /// changed, in which case everything that bakes in the format has to be recreated. The
+    /// surface still has to be configured afterwards.
+    pub fn update_surface_caps(&mut self) -> bool {
+        let caps = self.surface.get_capabilities(&self.adapter);
+        if caps.formats.is_empty() {
+            log::warn!("Surface reports no supported formats, keeping the current one");
+            return false;
+        }
+
+        let format = if caps.formats.contains(&self.surface_format) {
+            // Don't switch formats just because the preferred order changed
+            self.surface_format
+        } else {
+            Self::preferred_surface_format(&caps)
+        };
+        if !caps
+            .present_modes
+            .contains(&self.surface_config.present_mode)
+        {
+            self.surface_config.present_mode = PresentMode::Fifo;
+        }
+        if !caps.alpha_modes.contains(&self.surface_config.alpha_mode) {
+            self.surface_config.alpha_mode = caps.alpha_modes[0];
+        }
+        self.surface_config.usage =
+            TextureUsages::RENDER_ATTACHMENT | (caps.usages & TextureUsages::COPY_SRC);
+        self.surface_caps = caps;
+
+        let changed = format != self.surface_format;
+        if changed {
+            log::info!(
+                "Surface format changed from {:?} to {:?}",
+                self.surface_format,
+                format
+            );
+            self.surface_format = format;
+            self.surface_config.format = format;
+        }
+        changed
+    }
+
+    /// Submits command buffers that may copy from the staging ring, after handing it its data
+    pub fn submit<I: IntoIterator<Item = CommandBuffer>>(&self, command_buffers: I) {
+        self.staging.flush(&self.queue);
+        self.queue.submit(command_buffers);
+    }
+
+    pub fn configure_surface(&self) {
+        self.surface.configure(&self.device, &self.surface_config);
+    }
 }
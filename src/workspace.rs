@@ -0,0 +1,128 @@
+use std::collections::BTreeMap;
+
+use crate::settings::Settings;
+use crate::tool_window::ToolWindow;
+
+const KEY_PREFIX: &str = "workspace.";
+const DETACHED_KEY: &str = "detached";
+const LAST_WORLD_KEY: &str = "last_world";
+
+/// Which egui windows are open and where, which tool windows are detached and the world opened
+/// last, kept with the settings so the next start looks the same. That's a file on native and
+/// localStorage on the web.
+pub struct Workspace {
+    values: BTreeMap<String, String>,
+    // Set when `values` changed since they were last saved
+    dirty: bool,
+    // Detached on the last run and not yet reopened
+    pending_detach: Vec<ToolWindow>,
+}
+
+impl Workspace {
+    pub fn load(settings: &Settings) -> Self {
+        let keys = ToolWindow::ALL
+            .iter()
+            .map(|tool| tool.key())
+            .chain(["debug"])
+            .flat_map(|window| [format!("open.{}", window), format!("rect.{}", window)])
+            .chain([DETACHED_KEY.to_owned(), LAST_WORLD_KEY.to_owned()]);
+        let values = keys
+            .filter_map(|key| {
+                let value = settings.get::<String>(&format!("{}{}", KEY_PREFIX, key))?;
+                Some((key, value))
+            })
+            .collect::<BTreeMap<_, _>>();
+        let pending_detach = values
+            .get(DETACHED_KEY)
+            .map(|tools| {
+                tools
+                    .split(',')
+                    .filter_map(|key| ToolWindow::ALL.into_iter().find(|t| t.key() == key))
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self {
+            values,
+            dirty: false,
+            pending_detach,
+        }
+    }
+
+    fn set(&mut self, key: String, value: String) {
+        if self.values.get(&key) != Some(&value) {
+            self.values.insert(key, value);
+            self.dirty = true;
+        }
+    }
+
+    /// Whether the window named `window` was open, windows start closed
+    pub fn is_open(&self, window: &str) -> bool {
+        self.values
+            .get(&format!("open.{}", window))
+            .is_some_and(|open| open == "true")
+    }
+
+    pub fn set_open(&mut self, window: &str, open: bool) {
+        self.set(format!("open.{}", window), open.to_string());
+    }
+
+    /// Where the window named `window` was last shown, as `x y width height` in points
+    pub fn rect(&self, window: &str) -> Option<egui::Rect> {
+        let value = self.values.get(&format!("rect.{}", window))?;
+        let parts = value
+            .split_whitespace()
+            .map(|v| v.parse::<f32>().ok())
+            .collect::<Option<Vec<_>>>()?;
+        let [x, y, width, height] = parts[..] else {
+            return None;
+        };
+        Some(egui::Rect::from_min_size(
+            egui::pos2(x, y),
+            egui::vec2(width, height),
+        ))
+    }
+
+    pub fn set_rect(&mut self, window: &str, rect: egui::Rect) {
+        self.set(
+            format!("rect.{}", window),
+            format!(
+                "{:.0} {:.0} {:.0} {:.0}",
+                rect.min.x,
+                rect.min.y,
+                rect.width(),
+                rect.height()
+            ),
+        );
+    }
+
+    /// Tool windows to detach again, once
+    pub fn take_pending_detach(&mut self) -> Vec<ToolWindow> {
+        std::mem::take(&mut self.pending_detach)
+    }
+
+    pub fn set_detached(&mut self, detached: impl Iterator<Item = ToolWindow>) {
+        let mut keys = detached.map(|tool| tool.key()).collect::<Vec<_>>();
+        keys.sort();
+        self.set(DETACHED_KEY.to_owned(), keys.join(","));
+    }
+
+    pub fn last_world(&self) -> Option<&str> {
+        self.values.get(LAST_WORLD_KEY).map(String::as_str)
+    }
+
+    pub fn set_last_world(&mut self, name: &str) {
+        self.set(LAST_WORLD_KEY.to_owned(), name.to_owned());
+    }
+
+    /// Writes the changes to the settings, but not in the middle of dragging a window around
+    pub fn save(&mut self, settings: &mut Settings, dragging: bool) {
+        if !self.dirty || dragging {
+            return;
+        }
+        for (key, value) in &self.values {
+            settings.set(&format!("{}{}", KEY_PREFIX, key), value);
+        }
+        settings.save();
+        self.dirty = false;
+    }
+}
@@ -0,0 +1,71 @@
+// Text encoding of chunk voxels for world and pattern assets. Every chunk is a `chunk x y z` line
+// followed by a line of run-length encoded voxels, `count*value` or a lone `value`, in hex.
+
+use std::fmt::Write;
+
+use nalgebra_glm as glm;
+
+use crate::chunk::CHUNK_VOLUME;
+
+pub fn encode_chunks(chunks: &[(glm::IVec3, Vec<u32>)]) -> String {
+    let mut out = String::new();
+    for (pos, data) in chunks {
+        let _ = writeln!(out, "chunk {} {} {}", pos.x, pos.y, pos.z);
+        let mut i = 0;
+        while i < data.len() {
+            let value = data[i];
+            let run = data[i..].iter().take_while(|v| **v == value).count();
+            if run == 1 {
+                let _ = write!(out, "{:x} ", value);
+            } else {
+                let _ = write!(out, "{:x}*{:x} ", run, value);
+            }
+            i += run;
+        }
+        out.push('\n');
+    }
+    out
+}
+
+pub fn decode_chunks(body: &str) -> Result<Vec<(glm::IVec3, Vec<u32>)>, String> {
+    let parse_hex =
+        |s: &str| u32::from_str_radix(s, 16).map_err(|e| format!("bad value {:?}: {}", s, e));
+
+    let mut chunks = Vec::new();
+    let mut lines = body.lines().filter(|line| !line.trim().is_empty());
+    while let Some(header) = lines.next() {
+        let coords = header
+            .strip_prefix("chunk ")
+            .ok_or_else(|| format!("expected chunk header, got {:?}", header))?
+            .split_whitespace()
+            .map(|c| c.parse::<i32>().map_err(|e| e.to_string()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let [x, y, z] = coords[..] else {
+            return Err(format!("bad chunk header {:?}", header));
+        };
+
+        let mut data = Vec::with_capacity(CHUNK_VOLUME);
+        for token in lines.next().unwrap_or("").split_whitespace() {
+            let (run, value) = match token.split_once('*') {
+                Some((run, value)) => (parse_hex(run)? as usize, parse_hex(value)?),
+                None => (1, parse_hex(token)?),
+            };
+            if data.len() + run > CHUNK_VOLUME {
+                return Err(format!("chunk {} {} {} has too many voxels", x, y, z));
+            }
+            data.extend(std::iter::repeat(value).take(run));
+        }
+        if data.len() != CHUNK_VOLUME {
+            return Err(format!(
+                "chunk {} {} {} has {} voxels, expected {}",
+                x,
+                y,
+                z,
+                data.len(),
+                CHUNK_VOLUME
+            ));
+        }
+        chunks.push((glm::vec3(x, y, z), data));
+    }
+    Ok(chunks)
+}
@@ -0,0 +1,192 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::sync::{Arc, Mutex};
+
+use nalgebra_glm as glm;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::chunk::{CHUNK_SIZE, CHUNK_VOLUME};
+use crate::chunk_manager::ChunkManager;
+
+// Generated chunks waiting in the upload queue at most, so a large world doesn't sit in memory
+// all at once when generating outpaces uploading
+const MAX_QUEUED_CHUNKS: usize = 64;
+// Without threads the chunks are generated on the main thread, this many per frame
+#[cfg(target_arch = "wasm32")]
+const CHUNKS_PER_FRAME: usize = 4;
+
+/// Random cells for the chunk at `pos`. Every chunk gets its own cells, the same for a given
+/// seed. A plane world only has cells in the y = 0 layer of the bottom chunks.
+pub fn generate_chunk(seed: u64, pos: glm::IVec3, plane: bool) -> Vec<u32> {
+    let mut blocks = vec![0u32; CHUNK_VOLUME];
+    if plane && pos.y != 0 {
+        return blocks;
+    }
+    let chunk_seed = [pos.x, pos.y, pos.z].iter().fold(seed, |hash, &c| {
+        (hash ^ c as u32 as u64).wrapping_mul(0x100000001B3)
+    });
+    let mut rng = StdRng::seed_from_u64(chunk_seed);
+
+    let size = CHUNK_SIZE as usize;
+    for x in 0..size {
+        for z in 0..size {
+            if plane {
+                if rng.gen_range(0..100) < 35 {
+                    blocks[x + z * size * size] = rng.gen::<u32>() | 0xFF000000;
+                }
+                continue;
+            }
+            for y in 0..size {
+                if rng.gen_range(0..10000) == 0 {
+                    blocks[x + y * size + z * size * size] = rng.gen();
+                }
+            }
+        }
+    }
+    blocks
+}
+
+struct Job {
+    // Chunks nobody has started on yet
+    remaining: Arc<Mutex<Vec<glm::IVec3>>>,
+    cancelled: Arc<AtomicBool>,
+    #[cfg(not(target_arch = "wasm32"))]
+    receiver: Receiver<(glm::IVec3, Vec<u32>)>,
+    #[cfg(target_arch = "wasm32")]
+    seed: u64,
+    #[cfg(target_arch = "wasm32")]
+    plane: bool,
+    total: usize,
+    done: usize,
+}
+
+impl Drop for Job {
+    fn drop(&mut self) {
+        // Workers blocked on a full channel are woken up by the receiver going away, the rest
+        // stop before their next chunk
+        self.cancelled.store(true, Ordering::Relaxed);
+        self.remaining.lock().unwrap().clear();
+    }
+}
+
+/// Generates the cells of a new world on worker threads and hands the chunks to the chunk
+/// manager's upload queue as they finish, so the UI keeps running while a large world is made.
+/// On the web there are no threads and a few chunks are generated every frame instead.
+pub struct WorldGen {
+    job: Option<Job>,
+}
+
+impl WorldGen {
+    pub fn new() -> Self {
+        Self { job: None }
+    }
+
+    /// Starts generating `positions` in order, dropping whatever was being generated before
+    pub fn start(&mut self, mut positions: Vec<glm::IVec3>, seed: u64, plane: bool) {
+        self.cancel();
+        // Taken from the back
+        positions.reverse();
+        let total = positions.len();
+        let remaining = Arc::new(Mutex::new(positions));
+        let cancelled = Arc::new(AtomicBool::new(false));
+
+        #[cfg(not(target_arch = "wasm32"))]
+        let receiver = {
+            // One thread is left for the main loop
+            let workers = std::thread::available_parallelism()
+                .map_or(1, |n| n.get().saturating_sub(1))
+                .clamp(1, total.max(1));
+            let (sender, receiver) = std::sync::mpsc::sync_channel(workers * 2);
+            for i in 0..workers {
+                let remaining = remaining.clone();
+                let cancelled = cancelled.clone();
+                let sender = sender.clone();
+                let spawned = std::thread::Builder::new()
+                    .name(format!("worldgen {}", i))
+                    .spawn(move || {
+                        while !cancelled.load(Ordering::Relaxed) {
+                            let Some(pos) = remaining.lock().unwrap().pop() else {
+                                break;
+                            };
+                            let data = generate_chunk(seed, pos, plane);
+                            if sender.send((pos, data)).is_err() {
+                                break;
+                            }
+                        }
+                    });
+                if let Err(e) = spawned {
+                    log::error!("Failed to start a worldgen thread: {}", e);
+                }
+            }
+            receiver
+        };
+
+        self.job = Some(Job {
+            remaining,
+            cancelled,
+            #[cfg(not(target_arch = "wasm32"))]
+            receiver,
+            #[cfg(target_arch = "wasm32")]
+            seed,
+            #[cfg(target_arch = "wasm32")]
+            plane,
+            total,
+            done: 0,
+        });
+    }
+
+    pub fn cancel(&mut self) {
+        self.job = None;
+    }
+
+    /// Chunks generated and to generate in total, `None` when nothing is being generated
+    pub fn progress(&self) -> Option<(usize, usize)> {
+        self.job.as_ref().map(|job| (job.done, job.total))
+    }
+
+    /// Queues the chunks that finished since the last call for upload
+    pub fn poll(&mut self, chunk_manager: &mut ChunkManager) {
+        let Some(job) = &mut self.job else {
+            return;
+        };
+        #[cfg(target_arch = "wasm32")]
+        let mut generated = 0;
+        while chunk_manager.queued_uploads() < MAX_QUEUED_CHUNKS {
+            #[cfg(not(target_arch = "wasm32"))]
+            let chunk = match job.receiver.try_recv() {
+                Ok(chunk) => chunk,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    if job.done < job.total {
+                        log::error!(
+                            "Worldgen stopped after {} of {} chunks",
+                            job.done,
+                            job.total
+                        );
+                    }
+                    job.done = job.total;
+                    break;
+                }
+            };
+            #[cfg(target_arch = "wasm32")]
+            let chunk = {
+                if generated == CHUNKS_PER_FRAME {
+                    break;
+                }
+                let Some(pos) = job.remaining.lock().unwrap().pop() else {
+                    break;
+                };
+                generated += 1;
+                (pos, generate_chunk(job.seed, pos, job.plane))
+            };
+            let (pos, data) = chunk;
+            chunk_manager.queue_chunk_upload(pos, data);
+            job.done += 1;
+        }
+        if job.done >= job.total {
+            self.job = None;
+        }
+    }
+}
@@ -0,0 +1,147 @@
+// Browser tests for the web build. They run the engine headlessly under the same device limits
+// and features as the app, so web-only breakage shows up here. See the README for how to run them.
+#![cfg(target_arch = "wasm32")]
+
+use ca3d::headless::{Headless, CHUNK_SIZE};
+use wasm_bindgen_test::*;
+
+wasm_bindgen_test_configure!(run_in_browser);
+
+const SEED: u32 = 1234;
+const LIVE: u32 = 0xFF3080F0;
+const BIRTH_3: u32 = 1 << 3;
+const SURVIVAL_2_3: u32 = (1 << 2) | (1 << 3);
+// Live cells and the wrapping sum of every voxel after the spread test's 6 ticks with `SEED`.
+// Worked out on the CPU from the spread rule, a change to the rule or to how ticks are seeded
+// changes them.
+const SPREAD_LIVE_CELLS: usize = 1068;
+const SPREAD_CHECKSUM: u32 = 2615631284;
+
+async fn headless() -> Headless {
+    Headless::new(SEED)
+        .await
+        .unwrap_or_else(|e| panic!("Could not start the engine: {}", e))
+}
+
+async fn download(headless: &mut Headless) -> Vec<u32> {
+    headless
+        .download()
+        .await
+        .unwrap_or_else(|e| panic!("Could not read the chunk back: {}", e))
+}
+
+/// Local positions of the non-empty voxels, sorted
+fn live_cells(data: &[u32]) -> Vec<[usize; 3]> {
+    let size = CHUNK_SIZE as usize;
+    let mut cells = data
+        .iter()
+        .enumerate()
+        .filter(|(_, value)| **value != 0)
+        .map(|(i, _)| [i % size, i / size % size, i / (size * size)])
+        .collect::<Vec<_>>();
+    cells.sort();
+    cells
+}
+
+/// Cells on the y = 0 plane from (x, z) pairs, moved by `offset`
+fn plane(cells: &[(usize, usize)], offset: (usize, usize)) -> Vec<[usize; 3]> {
+    let mut cells = cells
+        .iter()
+        .map(|(x, z)| [x + offset.0, 0, z + offset.1])
+        .collect::<Vec<_>>();
+    cells.sort();
+    cells
+}
+
+fn upload_plane(headless: &mut Headless, cells: &[[usize; 3]]) {
+    let voxels = cells.iter().map(|c| (*c, LIVE)).collect::<Vec<_>>();
+    headless.upload(&voxels);
+}
+
+#[wasm_bindgen_test]
+async fn chunk_round_trips() {
+    let mut headless = headless().await;
+    let last = CHUNK_SIZE as usize - 1;
+    let voxels = [
+        ([0, 0, 0], 1),
+        ([last, 0, 0], 2),
+        ([0, last, 0], 3),
+        ([0, 0, last], 4),
+        ([last, last, last], 0xFFFFFFFF),
+        ([5, 17, 33], LIVE),
+    ];
+    headless.upload(&voxels);
+
+    let data = download(&mut headless).await;
+    let size = CHUNK_SIZE as usize;
+    for ([x, y, z], value) in voxels {
+        assert_eq!(
+            data[(z * size + y) * size + x],
+            value,
+            "voxel {:?}",
+            [x, y, z]
+        );
+    }
+    assert_eq!(live_cells(&data).len(), voxels.len());
+}
+
+#[wasm_bindgen_test]
+async fn blinker_oscillates() {
+    let mut headless = headless().await;
+    headless.set_life_2d(BIRTH_3, SURVIVAL_2_3);
+    let horizontal = plane(&[(0, 1), (1, 1), (2, 1)], (30, 30));
+    let vertical = plane(&[(1, 0), (1, 1), (1, 2)], (30, 30));
+    upload_plane(&mut headless, &horizontal);
+
+    headless.run(1);
+    assert_eq!(live_cells(&download(&mut headless).await), vertical);
+    headless.run(1);
+    assert_eq!(live_cells(&download(&mut headless).await), horizontal);
+    assert_eq!(headless.tick(), 2);
+}
+
+#[wasm_bindgen_test]
+async fn glider_moves_diagonally() {
+    let mut headless = headless().await;
+    headless.set_life_2d(BIRTH_3, SURVIVAL_2_3);
+    let glider = [(1, 0), (2, 1), (0, 2), (1, 2), (2, 2)];
+    upload_plane(&mut headless, &plane(&glider, (20, 20)));
+
+    // Every 4 ticks the glider is back in shape one cell further along x and z
+    headless.run(8);
+    let data = download(&mut headless).await;
+    assert_eq!(live_cells(&data), plane(&glider, (22, 22)));
+    // Births take the color of their neighbors
+    assert!(data.iter().all(|value| *value == 0 || *value == LIVE));
+}
+
+#[wasm_bindgen_test]
+async fn spread_replays_with_the_same_seed() {
+    let seeds = [[10, 10, 10], [40, 12, 50], [31, 60, 2]];
+    let mut results = Vec::new();
+    for _ in 0..2 {
+        let mut headless = headless().await;
+        let voxels = seeds.iter().map(|c| (*c, LIVE)).collect::<Vec<_>>();
+        headless.upload(&voxels);
+        headless.run(6);
+        results.push(download(&mut headless).await);
+    }
+
+    // Every seed grows into the 6 step octahedron around it
+    let cells = live_cells(&results[0]);
+    let reach = |c: &[usize; 3]| {
+        seeds
+            .iter()
+            .any(|s| (0..3).map(|axis| s[axis].abs_diff(c[axis])).sum::<usize>() <= 6)
+    };
+    assert!(cells.iter().all(reach));
+    assert_eq!(cells.len(), SPREAD_LIVE_CELLS);
+    let checksum = results[0]
+        .iter()
+        .fold(0u32, |sum, value| sum.wrapping_add(*value));
+    assert_eq!(checksum, SPREAD_CHECKSUM);
+    assert!(
+        results[0] == results[1],
+        "two runs with the same seed differ"
+    );
+}